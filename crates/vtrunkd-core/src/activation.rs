@@ -0,0 +1,155 @@
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::OnceLock;
+
+#[cfg(unix)]
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+#[cfg(unix)]
+use nix::sys::socket::{getsockopt, sockopt, SockType};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::error::VtrunkdResult;
+
+/// First descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// UDP sockets handed over by systemd socket activation, kept for the life of the process.
+static SOCKETS: OnceLock<Vec<UdpSocket>> = OnceLock::new();
+/// Link sockets taken over from the previous process during an upgrade, by link name.
+static INHERITED: OnceLock<Vec<(String, UdpSocket)>> = OnceLock::new();
+/// TUN queues taken over during an upgrade, the first one first.
+#[cfg(unix)]
+static INHERITED_QUEUES: OnceLock<Vec<RawFd>> = OnceLock::new();
+
+/// Takes the sockets passed through `LISTEN_FDS`, if they are meant for this process.
+///
+/// Must run before daemonizing: `LISTEN_PID` names the process systemd started.
+pub fn init(config: &Config) -> VtrunkdResult<()> {
+    let sockets = take_listen_fds(std::process::id())?;
+    for socket in &sockets {
+        let local = socket.local_addr()?;
+        let used = config
+            .wireguard
+            .links
+            .iter()
+            .any(|link| link.bind.as_deref().and_then(|b| b.parse().ok()) == Some(local));
+        if used {
+            info!("Using socket-activated UDP socket {}", local);
+        } else {
+            warn!(
+                "Socket-activated UDP socket {} matches no link bind address",
+                local
+            );
+        }
+    }
+    let _ = SOCKETS.set(sockets);
+    Ok(())
+}
+
+/// Keeps link sockets taken over from the previous process for [`socket_for`].
+pub fn adopt(links: Vec<(String, UdpSocket)>) {
+    let _ = INHERITED.set(links);
+}
+
+/// Keeps the TUN queues taken over from the previous process for [`tun_queues`]. The
+/// first also goes in `network.tun_fd`, which is how the tunnel finds the device.
+#[cfg(unix)]
+pub fn adopt_tun_queues(fds: Vec<RawFd>) {
+    let _ = INHERITED_QUEUES.set(fds);
+}
+
+/// Every queue of the TUN device whose first queue is `fd`: those taken over with it in
+/// an upgrade, or else just `fd`.
+#[cfg(unix)]
+pub(crate) fn tun_queues(fd: RawFd) -> Vec<RawFd> {
+    match INHERITED_QUEUES.get() {
+        Some(queues) if queues.first() == Some(&fd) => queues.clone(),
+        _ => vec![fd],
+    }
+}
+
+/// A fresh handle to the inherited socket for link `name`, or else the activated socket
+/// bound to `bind`, if there is one.
+///
+/// The original stays open so a restarted tunnel can claim it again.
+pub fn socket_for(name: &str, bind: SocketAddr) -> VtrunkdResult<Option<UdpSocket>> {
+    let inherited = INHERITED.get().and_then(|links| {
+        links
+            .iter()
+            .find(|(link, _)| link == name)
+            .map(|(_, socket)| socket)
+    });
+    let activated = || {
+        SOCKETS.get().and_then(|sockets| {
+            sockets
+                .iter()
+                .find(|socket| socket.local_addr().ok() == Some(bind))
+        })
+    };
+    let Some(socket) = inherited.or_else(activated) else {
+        return Ok(None);
+    };
+    let socket = socket.try_clone()?;
+    socket.set_nonblocking(true)?;
+    Ok(Some(socket))
+}
+
+#[cfg(unix)]
+fn take_listen_fds(pid: u32) -> VtrunkdResult<Vec<UdpSocket>> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    // Hooks and other children must not think the sockets are theirs.
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let count = match parse_listen_env(listen_pid.as_deref(), listen_fds.as_deref(), pid) {
+        Some(count) => count,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut sockets = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        if getsockopt(fd, sockopt::SockType)? != SockType::Datagram {
+            warn!("Ignoring socket-activated fd {}: not a UDP socket", fd);
+            continue;
+        }
+        // SAFETY: systemd hands these descriptors to this process, and they are
+        // adopted exactly once because the environment is cleared above.
+        sockets.push(unsafe { UdpSocket::from_raw_fd(fd) });
+    }
+    Ok(sockets)
+}
+
+/// Socket activation is systemd's; there is nothing to take elsewhere.
+#[cfg(not(unix))]
+fn take_listen_fds(_pid: u32) -> VtrunkdResult<Vec<UdpSocket>> {
+    Ok(Vec::new())
+}
+
+/// Number of passed descriptors, or `None` when they were meant for another process.
+#[cfg(unix)]
+fn parse_listen_env(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Option<RawFd> {
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    listen_fds?.parse::<RawFd>().ok().filter(|count| *count > 0)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_env_requires_matching_pid() {
+        assert_eq!(parse_listen_env(Some("42"), Some("2"), 42), Some(2));
+        assert_eq!(parse_listen_env(Some("41"), Some("2"), 42), None);
+        assert_eq!(parse_listen_env(None, Some("2"), 42), None);
+        assert_eq!(parse_listen_env(Some("42"), Some("0"), 42), None);
+        assert_eq!(parse_listen_env(Some("42"), Some("x"), 42), None);
+    }
+}
@@ -0,0 +1,83 @@
+//! Per-link `allowed_sources`: datagrams from other networks are dropped as they are
+//! received, before they can cost a handshake or move the link's peer address.
+
+use std::net::IpAddr;
+
+use crate::config::parse_cidr;
+use crate::error::VtrunkdResult;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedSources(Vec<(IpAddr, u8)>);
+
+impl AllowedSources {
+    /// Parses `address/prefix` entries; a bare address allows just that host.
+    pub fn parse(entries: &[String]) -> VtrunkdResult<Self> {
+        let mut networks = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let network = match entry.parse::<IpAddr>() {
+                Ok(address) => (address, if address.is_ipv4() { 32 } else { 128 }),
+                Err(_) => parse_cidr(entry)?,
+            };
+            networks.push(network);
+        }
+        Ok(AllowedSources(networks))
+    }
+
+    pub fn permits(&self, source: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 senders as `::ffff:a.b.c.d`.
+        let source = match source {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(source, IpAddr::V4),
+            v4 => v4,
+        };
+        self.0
+            .iter()
+            .any(|&(network, prefix)| contains(network, prefix, source))
+    }
+}
+
+fn contains(network: IpAddr, prefix: u8, address: IpAddr) -> bool {
+    match (network, address) {
+        (IpAddr::V4(network), IpAddr::V4(address)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(network) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(address)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(network) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_match_by_network_and_family() {
+        let entries = ["198.51.100.0/24", "203.0.113.7", "2001:db8::/32"].map(String::from);
+        let allowed = AllowedSources::parse(&entries).unwrap();
+        for permitted in [
+            "198.51.100.200",
+            "203.0.113.7",
+            "::ffff:198.51.100.1",
+            "2001:db8::1",
+        ] {
+            assert!(allowed.permits(permitted.parse().unwrap()), "{}", permitted);
+        }
+        for refused in [
+            "198.51.101.1",
+            "203.0.113.8",
+            "2001:db9::1",
+            "::198.51.100.1",
+        ] {
+            assert!(!allowed.permits(refused.parse().unwrap()), "{}", refused);
+        }
+
+        let everyone = AllowedSources::parse(&["0.0.0.0/0".to_string()]).unwrap();
+        assert!(everyone.permits("192.0.2.1".parse().unwrap()));
+        assert!(!everyone.permits("2001:db8::1".parse().unwrap()));
+        assert!(AllowedSources::parse(&["198.51.100.0/33".to_string()]).is_err());
+        assert!(AllowedSources::parse(&["lte".to_string()]).is_err());
+    }
+}
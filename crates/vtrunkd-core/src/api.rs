@@ -0,0 +1,403 @@
+//! Authenticated HTTP management API mirroring the control socket, for headless servers
+//! managed by web dashboards or Ansible. Plain HTTP/1.1 with one request per connection;
+//! put a TLS proxy in front when it leaves the host.
+
+use std::io::Write;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::audit;
+use crate::config::{self, HttpApiConfig};
+use crate::control::{self, ControlCommand, ControlRequest, LinkAdminState};
+use crate::error::VtrunkdResult;
+
+/// Largest accepted request body; a config file is a few kilobytes.
+const MAX_BODY: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+/// Most read of the request line and headers together, so a line that never ends is cut off.
+const MAX_HEAD: u64 = 64 * 1024;
+/// A client that has not sent its whole request by then is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Self {
+        Response { status, body }
+    }
+
+    fn message(status: u16, key: &str, message: &str) -> Self {
+        let body = serde_json::json!({ key: message }).to_string();
+        Response { status, body }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            _ => "Service Unavailable",
+        }
+    }
+}
+
+/// Binds `settings.bind` and serves requests against the tunnel loop. `PUT /config`
+/// rewrites `config_path`, or is refused without one. Returns the bound address.
+pub fn spawn_server(
+    settings: &HttpApiConfig,
+    tx: mpsc::Sender<ControlRequest>,
+    config_path: Option<PathBuf>,
+) -> VtrunkdResult<SocketAddr> {
+    let listener = std::net::TcpListener::bind(&settings.bind)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let address = listener.local_addr()?;
+    if !address.ip().is_loopback() {
+        warn!(
+            "HTTP API on {} is not loopback; the token travels in clear text without a TLS proxy",
+            address
+        );
+    }
+    info!("HTTP API listening on {}", address);
+
+    let token = settings.token.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let tx = tx.clone();
+                    let token = token.clone();
+                    let config_path = config_path.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) =
+                            handle_connection(stream, peer, &token, tx, config_path.as_deref())
+                                .await
+                        {
+                            debug!("HTTP API connection from {} failed: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    warn!("HTTP API accept error: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(address)
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    token: &str,
+    tx: mpsc::Sender<ControlRequest>,
+    config_path: Option<&Path>,
+) -> VtrunkdResult<()> {
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(Ok(request))) => {
+            let source = format!("http {}", peer);
+            if authorized(request.token.as_deref(), token) {
+                route(request, &tx, config_path, &source).await
+            } else {
+                audit::record(
+                    &source,
+                    "unauthorized",
+                    serde_json::json!({ "method": request.method, "path": request.path }),
+                );
+                Response::message(401, "error", "missing or wrong bearer token")
+            }
+        }
+        Ok(Ok(Err(response))) => response,
+        Ok(Err(err)) => return Err(err.into()),
+        Err(_) => return Ok(()),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads one request; a malformed one yields the error response to send instead.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Result<Request, Response>> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD + MAX_BODY as u64));
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(Err(Response::message(
+            400,
+            "error",
+            "malformed request line",
+        )));
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        token: None,
+        body: Vec::new(),
+    };
+
+    let mut content_length = 0;
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            if content_length > MAX_BODY {
+                return Ok(Err(Response::message(413, "error", "body too large")));
+            }
+            request.body.resize(content_length, 0);
+            reader.read_exact(&mut request.body).await?;
+            return Ok(Ok(request));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Ok(Err(Response::message(400, "error", "malformed header")));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            let Ok(length) = value.parse() else {
+                return Ok(Err(Response::message(400, "error", "bad Content-Length")));
+            };
+            content_length = length;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.token = value.strip_prefix("Bearer ").map(str::to_string);
+        }
+    }
+    Ok(Err(Response::message(400, "error", "incomplete request")))
+}
+
+/// Compares in constant time so the token cannot be guessed byte by byte.
+fn authorized(given: Option<&str>, token: &str) -> bool {
+    let Some(given) = given else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn route(
+    request: Request,
+    tx: &mpsc::Sender<ControlRequest>,
+    config_path: Option<&Path>,
+    source: &str,
+) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["status"]) => command(tx, ControlCommand::Stats).await,
+        ("GET", ["links"]) => {
+            let stats = command(tx, ControlCommand::Stats).await;
+            if stats.status != 200 {
+                return stats;
+            }
+            match serde_json::from_str::<serde_json::Value>(&stats.body) {
+                Ok(stats) => Response::json(200, stats["links"].to_string()),
+                Err(e) => Response::message(500, "error", &e.to_string()),
+            }
+        }
+        ("POST", ["links", name, state]) => match state.parse::<LinkAdminState>() {
+            Ok(state) => {
+                let set_state = ControlCommand::SetLinkState {
+                    name: name.to_string(),
+                    state,
+                };
+                match control::dispatch(tx, set_state, source).await {
+                    Ok(result) => Response::message(200, "result", &result),
+                    Err(message) => Response::message(400, "error", &message),
+                }
+            }
+            Err(message) => Response::message(404, "error", &message),
+        },
+        ("PUT", ["config"]) => match config_path {
+            Some(path) => put_config(path, &request.body, source),
+            None => Response::message(501, "error", "no configuration file to write"),
+        },
+        (_, ["status"] | ["links"] | ["links", _, _] | ["config"]) => {
+            Response::message(405, "error", "method not allowed")
+        }
+        _ => Response::message(404, "error", "not found"),
+    }
+}
+
+async fn command(tx: &mpsc::Sender<ControlRequest>, command: ControlCommand) -> Response {
+    match control::dispatch(tx, command, "http").await {
+        Ok(body) => Response::json(200, body),
+        Err(message) => Response::message(503, "error", &message),
+    }
+}
+
+/// Validates the new YAML and replaces the config file; the daemon applies it on restart.
+fn put_config(path: &Path, body: &[u8], source: &str) -> Response {
+    let Ok(yaml) = std::str::from_utf8(body) else {
+        return Response::message(400, "error", "configuration must be UTF-8");
+    };
+    if let Err(e) = config::parse_config(yaml) {
+        return Response::message(400, "error", &e.to_string());
+    }
+    let written = write_config(path, yaml);
+    audit::record(
+        source,
+        "config_written",
+        serde_json::json!({
+            "path": path,
+            "result": if written.is_ok() { "ok" } else { "error" },
+        }),
+    );
+    match written {
+        Ok(()) => {
+            info!("Configuration {:?} replaced over the HTTP API", path);
+            Response::message(200, "result", "saved; restart vtrunkd to apply")
+        }
+        Err(e) => Response::message(500, "error", &e.to_string()),
+    }
+}
+
+/// Writes beside the file and renames, so a crash never leaves half a config.
+fn write_config(path: &Path, yaml: &str) -> std::io::Result<()> {
+    let staging = path.with_extension("yaml.new");
+    // One left by a crash, or planted to be written through, is replaced rather than opened.
+    match std::fs::remove_file(&staging) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // The file holds the private key, so it is never readable by others, not even briefly.
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&staging)?;
+    file.write_all(yaml.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&staging, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    async fn request(address: SocketAddr, raw: String) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn get(path: &str, token: &str) -> String {
+        format!(
+            "GET {} HTTP/1.1\r\nHost: test\r\nAuthorization: Bearer {}\r\n\r\n",
+            path, token
+        )
+    }
+
+    #[tokio::test]
+    async fn serves_the_control_commands_behind_the_token() {
+        let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let reply = match request.command {
+                    ControlCommand::Stats => r#"{"links":[{"name":"wifi"}]}"#.to_string(),
+                    other => other.to_line(),
+                };
+                let _ = request.reply.send(Ok(reply));
+            }
+        });
+        let dir = std::env::temp_dir().join(format!("vtrunkd-api-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("vtrunkd.yaml");
+        let settings = HttpApiConfig {
+            bind: "127.0.0.1:0".to_string(),
+            token: "s3cret".to_string(),
+        };
+        let address = spawn_server(&settings, tx, Some(config_path.clone())).unwrap();
+
+        assert_eq!(request(address, get("/status", "wrong")).await.0, 401);
+        let (status, body) = request(address, get("/links", "s3cret")).await;
+        assert_eq!((status, body.as_str()), (200, r#"[{"name":"wifi"}]"#));
+        let post = "POST /links/wifi/down HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n";
+        let (status, body) = request(address, post.to_string()).await;
+        assert_eq!(
+            (status, body.as_str()),
+            (200, r#"{"result":"link down wifi"}"#)
+        );
+        assert_eq!(
+            request(address, get("/links/wifi/down", "s3cret")).await.0,
+            405
+        );
+        assert_eq!(request(address, get("/nope", "s3cret")).await.0, 404);
+
+        let put = |yaml: &str| {
+            format!(
+                "PUT /config HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{}",
+                yaml.len(),
+                yaml
+            )
+        };
+        assert_eq!(request(address, put("network: {}")).await.0, 400);
+        assert!(!config_path.exists());
+        // A staging file planted as a symlink is replaced, not written through.
+        #[cfg(unix)]
+        let target = {
+            let target = dir.join("elsewhere");
+            std::os::unix::fs::symlink(&target, config_path.with_extension("yaml.new")).unwrap();
+            target
+        };
+        let yaml = serde_yaml::to_string(&config::Config::default()).unwrap();
+        assert_eq!(request(address, put(&yaml)).await.0, 200);
+        assert_eq!(std::fs::read_to_string(&config_path).unwrap(), yaml);
+        #[cfg(unix)]
+        {
+            assert!(!target.exists());
+            let mode = std::fs::metadata(&config_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A request line that never ends is cut off at the limit.
+        let endless = "A".repeat((MAX_HEAD + MAX_BODY as u64) as usize);
+        assert_eq!(request(address, endless).await.0, 400);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
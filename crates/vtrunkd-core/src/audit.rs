@@ -0,0 +1,293 @@
+//! Append-only audit log (`logging.audit`) of security-relevant events, kept apart from
+//! the daemon log for deployments with compliance requirements.
+//!
+//! Each record is one JSON object with a UTC `time`, the `source` it came from and an
+//! `event`: handshakes, endpoint changes, key rotations and handovers from the tunnel,
+//! configuration loads and writes, and control commands that change state, with who sent
+//! them. Records go to a file that is only ever appended to, or to journald with
+//! `VTRUNKD_AUDIT=1` so `journalctl VTRUNKD_AUDIT=1` lists them alone.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::config::AuditConfig;
+use crate::control::ControlCommand;
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::TunnelEvent;
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// LOG_NOTICE.
+#[cfg(unix)]
+const JOURNALD_PRIORITY: u8 = 5;
+
+static AUDIT: OnceLock<Mutex<AuditSink>> = OnceLock::new();
+
+/// Where audit records are written. `file` is implied when `logging.audit.file` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutput {
+    File,
+    Journald,
+}
+
+enum AuditSink {
+    File(File),
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+}
+
+impl AuditSink {
+    fn open(config: &AuditConfig) -> io::Result<Self> {
+        match (config.output, &config.file) {
+            (Some(AuditOutput::Journald), _) => Self::journald(),
+            (_, Some(path)) => {
+                // The daemon changes directory to / after forking, so pin relative paths now.
+                let path = Path::new(path);
+                let path = if path.is_absolute() {
+                    path.to_path_buf()
+                } else {
+                    std::env::current_dir()?.join(path)
+                };
+                let mut options = OpenOptions::new();
+                options.create(true).append(true);
+                #[cfg(unix)]
+                options.mode(0o600);
+                Ok(AuditSink::File(options.open(path)?))
+            }
+            (_, None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "logging.audit needs a file or output journald",
+            )),
+        }
+    }
+
+    #[cfg(unix)]
+    fn journald() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(AuditSink::Journald(socket))
+    }
+
+    #[cfg(not(unix))]
+    fn journald() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "journald is not available on this platform",
+        ))
+    }
+
+    fn write(&mut self, record: &Value) -> io::Result<()> {
+        // serde_json escapes control characters, so a record never spans lines.
+        let line = record.to_string();
+        match self {
+            AuditSink::File(file) => {
+                file.write_all(format!("{}\n", line).as_bytes())?;
+                file.sync_data()
+            }
+            #[cfg(unix)]
+            AuditSink::Journald(socket) => {
+                let event = record["event"].as_str().unwrap_or_default();
+                let datagram = format!(
+                    "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER=vtrunkd\nVTRUNKD_AUDIT=1\nVTRUNKD_AUDIT_EVENT={}\n",
+                    line, JOURNALD_PRIORITY, event
+                );
+                socket.send(datagram.as_bytes()).map(|_| ())
+            }
+        }
+    }
+}
+
+/// Opens the audit log for [`record`]; without `logging.audit` records are discarded.
+pub fn init(config: Option<&AuditConfig>) -> VtrunkdResult<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    let sink = AuditSink::open(config)
+        .map_err(|e| VtrunkdError::Config(format!("Failed to open the audit log: {}", e)))?;
+    let _ = AUDIT.set(Mutex::new(sink));
+    Ok(())
+}
+
+/// Appends a record with `source`, stamped with the current time.
+pub fn record(source: &str, event: &str, details: Value) {
+    let Some(sink) = AUDIT.get() else {
+        return;
+    };
+    let record = build_record(SystemTime::now(), source, event, details);
+    let mut sink = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Err(e) = sink.write(&record) {
+        warn!("Failed to write the audit log: {}", e);
+    }
+}
+
+/// Records a control command that changes state, and how it was answered.
+pub fn command(source: &str, command: &ControlCommand, result: &Result<String, String>) {
+    if !command.changes_state() {
+        return;
+    }
+    let (outcome, message) = match result {
+        Ok(message) => ("ok", message),
+        Err(message) => ("error", message),
+    };
+    record(
+        source,
+        "control_command",
+        json!({ "command": command.to_line(), "result": outcome, "message": message }),
+    );
+}
+
+/// Records the configuration the daemon starts with.
+pub fn config_loaded(path: &Path) {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    record(
+        "daemon",
+        "config_loaded",
+        json!({ "path": path, "version": env!("CARGO_PKG_VERSION") }),
+    );
+}
+
+/// Records a SIGHUP; the running configuration is kept, as reloading is not supported.
+pub fn reload_requested() {
+    record("signal", "config_reload", json!({ "applied": false }));
+}
+
+/// Records the tunnel events worth auditing; link state flaps stay in the daemon log.
+pub fn spawn(mut events: broadcast::Receiver<TunnelEvent>) {
+    if AUDIT.get().is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Some((name, details)) = audited(&event) {
+                        record("tunnel", &name, details);
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    record("audit", "events_missed", json!({ "count": skipped }));
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+fn audited(event: &TunnelEvent) -> Option<(String, Value)> {
+    match event {
+        TunnelEvent::LinkUp { .. }
+        | TunnelEvent::LinkDown { .. }
+        | TunnelEvent::LinkDegraded { .. }
+        | TunnelEvent::Failover { .. } => None,
+        _ => {
+            let Value::Object(mut fields) = serde_json::to_value(event).ok()? else {
+                return None;
+            };
+            let name = fields.remove("event")?.as_str()?.to_string();
+            Some((name, Value::Object(fields)))
+        }
+    }
+}
+
+fn build_record(time: SystemTime, source: &str, event: &str, details: Value) -> Value {
+    let mut record = json!({ "time": rfc3339(time), "source": source, "event": event });
+    if let (Value::Object(record), Value::Object(details)) = (&mut record, details) {
+        record.extend(details);
+    }
+    record
+}
+
+/// `2026-01-02T03:04:05.678Z`, without pulling in a date crate.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Howard Hinnant's civil_from_days.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn records_are_timestamped_json_lines() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(rfc3339(leap_day), "2024-02-29T12:34:56.789Z");
+
+        let event = TunnelEvent::EndpointChanged {
+            link: "lte".to_string(),
+            endpoint: "198.51.100.7:51820".to_string(),
+        };
+        let (name, details) = audited(&event).unwrap();
+        let record = build_record(leap_day, "tunnel", &name, details);
+        assert_eq!(
+            record.to_string(),
+            r#"{"endpoint":"198.51.100.7:51820","event":"endpoint_changed","link":"lte","source":"tunnel","time":"2024-02-29T12:34:56.789Z"}"#
+        );
+        let flap = TunnelEvent::LinkUp {
+            link: "lte".to_string(),
+        };
+        assert!(audited(&flap).is_none());
+
+        let dir = std::env::temp_dir().join(format!("vtrunkd-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+        std::fs::write(&path, "earlier\n").unwrap();
+        let config = AuditConfig {
+            output: None,
+            file: Some(path.to_string_lossy().into_owned()),
+        };
+        let mut sink = AuditSink::open(&config).unwrap();
+        sink.write(&record).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, format!("earlier\n{}\n", record));
+        let _ = std::fs::remove_dir_all(dir);
+
+        let (journal, socket) = UnixDatagram::pair().unwrap();
+        AuditSink::Journald(socket).write(&record).unwrap();
+        let mut buf = [0u8; 512];
+        let size = journal.recv(&mut buf).unwrap();
+        let datagram = std::str::from_utf8(&buf[..size]).unwrap();
+        assert!(datagram.starts_with(&format!("MESSAGE={}\n", record)));
+        assert!(datagram.contains("\nVTRUNKD_AUDIT=1\nVTRUNKD_AUDIT_EVENT=endpoint_changed\n"));
+    }
+}
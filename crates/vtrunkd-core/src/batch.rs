@@ -0,0 +1,297 @@
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+/// Receive buffers for up to `batch` datagrams per wakeup.
+///
+/// On Linux one `recvmmsg` fills as many buffers as the socket has datagrams queued;
+/// elsewhere each call reads a single datagram.
+pub struct RecvBatch {
+    bufs: Vec<Vec<u8>>,
+    /// Buffer index, datagram length, source and TOS byte of each datagram read.
+    received: Vec<(usize, usize, SocketAddr, u8)>,
+}
+
+impl RecvBatch {
+    pub fn new(batch: usize, buffer_size: usize) -> Self {
+        let batch = batch.max(1);
+        RecvBatch {
+            bufs: vec![vec![0u8; buffer_size]; batch],
+            received: Vec::with_capacity(batch),
+        }
+    }
+
+    /// Waits for at least one datagram and returns how many were read.
+    pub async fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.received.clear();
+        // Also for a single buffer, so the TOS byte comes along.
+        #[cfg(target_os = "linux")]
+        {
+            let bufs = &mut self.bufs;
+            let received = &mut self.received;
+            socket
+                .async_io(tokio::io::Interest::READABLE, || {
+                    linux::recvmmsg(socket, bufs, received)
+                })
+                .await?;
+            Ok(self.received.len())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let (size, src) = socket.recv_from(&mut self.bufs[0]).await?;
+            self.received.push((0, size, src, 0));
+            Ok(1)
+        }
+    }
+
+    /// The datagrams read by the last [`RecvBatch::recv`], with the TOS byte they arrived
+    /// with when the socket has `IP_RECVTOS`/`IPV6_RECVTCLASS` on (0 otherwise).
+    pub fn packets(&self) -> impl Iterator<Item = (&[u8], SocketAddr, u8)> {
+        self.received
+            .iter()
+            .map(|(index, size, src, tos)| (&self.bufs[*index][..*size], *src, *tos))
+    }
+}
+
+/// Sends `packets` to `remote`, using one `sendmmsg` per batch on Linux.
+///
+/// Returns how many leading packets were sent; an error is returned only when none were.
+pub async fn send_batch(
+    socket: &UdpSocket,
+    remote: SocketAddr,
+    packets: &[&[u8]],
+) -> io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    if packets.len() > 1 {
+        return socket
+            .async_io(tokio::io::Interest::WRITABLE, || {
+                linux::sendmmsg(socket, remote, packets)
+            })
+            .await;
+    }
+    let mut sent = 0;
+    for packet in packets {
+        match socket.send_to(packet, remote).await {
+            Ok(_) => sent += 1,
+            Err(err) if sent == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use linux::{received_tos, to_socket_addr, to_storage, CONTROL_LEN};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+
+    use nix::libc;
+    use tokio::net::UdpSocket;
+
+    fn check(result: libc::c_int) -> io::Result<usize> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    pub fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: the kernel wrote a sockaddr_in for AF_INET.
+                let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)),
+                    u16::from_be(sin.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: the kernel wrote a sockaddr_in6 for AF_INET6.
+                let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_storage(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: sockaddr_storage is plain old data; all zeroes is a valid value.
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: sockaddr_storage is large and aligned enough for sockaddr_in.
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: sockaddr_storage is large and aligned enough for sockaddr_in6.
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as libc::socklen_t)
+    }
+
+    /// Room for the `IP_TOS` and `IPV6_TCLASS` control messages, in `u64`s for alignment.
+    pub const CONTROL_LEN: usize = 8;
+
+    /// The TOS byte or IPv6 traffic class a datagram arrived with, if the kernel added it.
+    pub fn received_tos(msg: &libc::msghdr) -> u8 {
+        // SAFETY: `msg` was filled by the kernel, so its control messages are well formed
+        // and the CMSG macros stay within `msg_controllen`.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_TOS) => return *data,
+                    (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                        return std::ptr::read_unaligned(data.cast::<libc::c_int>()) as u8
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+            }
+        }
+        0
+    }
+
+    pub fn recvmmsg(
+        socket: &UdpSocket,
+        bufs: &mut [Vec<u8>],
+        received: &mut Vec<(usize, usize, SocketAddr, u8)>,
+    ) -> io::Result<()> {
+        // SAFETY: sockaddr_storage is plain old data; all zeroes is a valid value.
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; bufs.len()];
+        let mut controls = vec![[0u64; CONTROL_LEN]; bufs.len()];
+        let mut iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .zip(controls.iter_mut())
+            .map(|((iov, addr), control)| {
+                // SAFETY: mmsghdr is plain old data; all zeroes is a valid value.
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (addr as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                header.msg_hdr.msg_iov = iov;
+                header.msg_hdr.msg_iovlen = 1;
+                header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+                header.msg_hdr.msg_controllen = mem::size_of_val(control) as _;
+                header
+            })
+            .collect();
+
+        // SAFETY: every header points at a live iovec, buffer, address and control slot.
+        let count = check(unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as _,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        })?;
+        for (index, (header, addr)) in headers.iter().zip(&addrs).take(count).enumerate() {
+            if let Some(src) = to_socket_addr(addr) {
+                let tos = received_tos(&header.msg_hdr);
+                received.push((index, header.msg_len as usize, src, tos));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sendmmsg(
+        socket: &UdpSocket,
+        remote: SocketAddr,
+        packets: &[&[u8]],
+    ) -> io::Result<usize> {
+        let (mut addr, addr_len) = to_storage(remote);
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iov| {
+                // SAFETY: mmsghdr is plain old data; all zeroes is a valid value.
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = (&mut addr as *mut libc::sockaddr_storage).cast();
+                header.msg_hdr.msg_namelen = addr_len;
+                header.msg_hdr.msg_iov = iov;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        // SAFETY: every header points at a live iovec and the shared destination address;
+        // the kernel only reads the packet data.
+        check(unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                headers.len() as _,
+                libc::MSG_DONTWAIT,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn batches_round_trip() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote = receiver.local_addr().unwrap();
+
+        let packets: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let sent = send_batch(&sender, remote, &packets).await.unwrap();
+        assert_eq!(sent, 3);
+
+        let mut batch = RecvBatch::new(8, 64);
+        let mut seen = Vec::new();
+        while seen.len() < 3 {
+            batch.recv(&receiver).await.unwrap();
+            for (data, src, _) in batch.packets() {
+                assert_eq!(src, sender.local_addr().unwrap());
+                seen.push(data.to_vec());
+            }
+        }
+        assert_eq!(
+            seen,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+}
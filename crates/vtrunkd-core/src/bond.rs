@@ -0,0 +1,51 @@
+//! Bonding control packets exchanged on each link alongside WireGuard traffic.
+//!
+//! They are not encrypted, so everything here parses untrusted input.
+
+const BOND_MAGIC: [u8; 4] = *b"VTBD";
+pub const BOND_PING: u8 = 1;
+pub const BOND_PONG: u8 = 2;
+/// Sent on every link during a graceful shutdown so the peer fails over at once.
+pub const BOND_BYE: u8 = 3;
+pub const BOND_PACKET_LEN: usize = 13;
+
+pub fn build_control_packet(message_type: u8, token: u64) -> [u8; BOND_PACKET_LEN] {
+    let mut buf = [0u8; BOND_PACKET_LEN];
+    buf[..4].copy_from_slice(&BOND_MAGIC);
+    buf[4] = message_type;
+    buf[5..].copy_from_slice(&token.to_be_bytes());
+    buf
+}
+
+/// The message type and token of a control packet, or `None` for anything else.
+pub fn parse_control_packet(data: &[u8]) -> Option<(u8, u64)> {
+    if data.len() != BOND_PACKET_LEN {
+        return None;
+    }
+    if data[..4] != BOND_MAGIC {
+        return None;
+    }
+    let message_type = data[4];
+    let token = u64::from_be_bytes(data[5..13].try_into().ok()?);
+    Some((message_type, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_packet_round_trip() {
+        let token = 42u64;
+        let packet = build_control_packet(BOND_PING, token);
+        let parsed = parse_control_packet(&packet).expect("parse control packet");
+        assert_eq!(parsed, (BOND_PING, token));
+    }
+
+    #[test]
+    fn control_packet_rejects_bad_magic() {
+        let mut packet = build_control_packet(BOND_PING, 1);
+        packet[0] = b'X';
+        assert!(parse_control_packet(&packet).is_none());
+    }
+}
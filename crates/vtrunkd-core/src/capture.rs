@@ -0,0 +1,424 @@
+use std::fmt;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use nix::libc;
+use tracing::info;
+
+pub const DEFAULT_CAPTURE_SECS: u64 = 60;
+pub const DEFAULT_CAPTURE_MAX_MB: u64 = 100;
+/// Kept by the daemon, rather than a temp directory where other users can plant files.
+#[cfg(target_os = "linux")]
+pub const DEFAULT_CAPTURE_DIR: &str = "/var/lib/vtrunkd/captures";
+#[cfg(not(target_os = "linux"))]
+pub const DEFAULT_CAPTURE_DIR: &str = "/var/db/vtrunkd/captures";
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_SNAPLEN: u32 = 65535;
+/// Raw IPv4/IPv6 packets without a link-layer header.
+const LINKTYPE_RAW: u32 = 101;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+const IPPROTO_UDP: u8 = 17;
+
+/// Which traffic a capture records.
+///
+/// `tun` writes the cleartext inner packets; `links` writes the encrypted UDP
+/// datagrams of every link with synthesized IP/UDP headers so Wireshark can
+/// tell links apart by address and decode them as WireGuard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTarget {
+    Tun,
+    Links,
+    All,
+}
+
+impl CaptureTarget {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CaptureTarget::Tun => "tun",
+            CaptureTarget::Links => "links",
+            CaptureTarget::All => "all",
+        }
+    }
+}
+
+impl fmt::Display for CaptureTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CaptureTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tun" => Ok(CaptureTarget::Tun),
+            "links" => Ok(CaptureTarget::Links),
+            "all" => Ok(CaptureTarget::All),
+            other => Err(format!("Unknown capture target: {}", other)),
+        }
+    }
+}
+
+struct PcapFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl PcapFile {
+    /// Creates `path` afresh, readable by its owner only: captures hold the tunnel's
+    /// cleartext, and an existing file or symlink there is refused rather than written.
+    fn create(path: PathBuf) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+        let mut writer = BufWriter::new(options.open(&path)?);
+        writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapFile { path, writer })
+    }
+
+    fn write_record(&mut self, packet: &[u8]) -> io::Result<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let len = packet.len() as u32;
+        self.writer
+            .write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(packet)?;
+        Ok(PCAP_RECORD_HEADER_LEN + packet.len() as u64)
+    }
+}
+
+/// A running capture with its time and size budget.
+pub struct Capture {
+    tun: Option<PcapFile>,
+    links: Option<PcapFile>,
+    started: Instant,
+    max_duration: Duration,
+    max_bytes: u64,
+    written: u64,
+}
+
+impl Capture {
+    pub fn start(
+        dir: &Path,
+        target: CaptureTarget,
+        max_duration: Duration,
+        max_bytes: u64,
+    ) -> io::Result<Self> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // Created if missing, for the daemon's user only.
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(dir)?;
+        let file = |kind: &str| {
+            // A capture started again within the same second gets the next free name.
+            let mut attempt = 0;
+            loop {
+                let name = match attempt {
+                    0 => format!("vtrunkd-{}-{}.pcap", kind, stamp),
+                    n => format!("vtrunkd-{}-{}-{}.pcap", kind, stamp, n),
+                };
+                match PcapFile::create(dir.join(name)) {
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        };
+        let tun = match target {
+            CaptureTarget::Tun | CaptureTarget::All => Some(file("tun")?),
+            CaptureTarget::Links => None,
+        };
+        let links = match target {
+            CaptureTarget::Links | CaptureTarget::All => Some(file("links")?),
+            CaptureTarget::Tun => None,
+        };
+        let capture = Capture {
+            tun,
+            links,
+            started: Instant::now(),
+            max_duration,
+            max_bytes,
+            written: 0,
+        };
+        info!(
+            "Packet capture started: {} (limit {}s, {} bytes)",
+            capture.paths(),
+            max_duration.as_secs(),
+            max_bytes
+        );
+        Ok(capture)
+    }
+
+    pub fn paths(&self) -> String {
+        [&self.tun, &self.links]
+            .into_iter()
+            .flatten()
+            .map(|file| file.path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.written >= self.max_bytes || self.started.elapsed() >= self.max_duration
+    }
+
+    /// Records an inner packet read from or written to the TUN device.
+    pub fn record_tun(&mut self, packet: &[u8]) -> io::Result<()> {
+        if let Some(file) = &mut self.tun {
+            self.written += file.write_record(packet)?;
+        }
+        Ok(())
+    }
+
+    /// Records a link datagram, wrapped in IP/UDP headers for `src` -> `dst`.
+    pub fn record_link(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        if let Some(file) = &mut self.links {
+            let packet = udp_packet(src, dst, payload);
+            self.written += file.write_record(&packet)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<String> {
+        for file in [&mut self.tun, &mut self.links].into_iter().flatten() {
+            file.writer.flush()?;
+        }
+        let paths = self.paths();
+        info!(
+            "Packet capture finished: {} ({} bytes)",
+            paths, self.written
+        );
+        Ok(paths)
+    }
+}
+
+/// Internet checksum (RFC 1071) over `chunks`; all but the last must be of even length.
+pub(crate) fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = match pair {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+                [hi] => u16::from_be_bytes([*hi, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A computed UDP checksum of zero is sent as all ones; zero means "none".
+fn udp_checksum(pseudo: &[u8], udp: &[u8]) -> u16 {
+    match checksum(&[pseudo, udp]) {
+        0 => 0xffff,
+        sum => sum,
+    }
+}
+
+/// Builds an IPv4 or IPv6 UDP packet carrying `payload`. Mixed families are
+/// written as IPv6 with the IPv4 side mapped.
+fn udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    let mut packet = Vec::with_capacity(40 + udp.len());
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let pseudo = [
+                &src_ip.octets()[..],
+                &dst_ip.octets()[..],
+                &[0, IPPROTO_UDP],
+                &udp_len.to_be_bytes(),
+            ]
+            .concat();
+            let udp_sum = udp_checksum(&pseudo, &udp);
+            udp[6..8].copy_from_slice(&udp_sum.to_be_bytes());
+
+            let total_len = (20 + udp.len()) as u16;
+            let mut header = [0u8; 20];
+            header[0] = 0x45;
+            header[2..4].copy_from_slice(&total_len.to_be_bytes());
+            header[6] = 0x40;
+            header[8] = 64;
+            header[9] = IPPROTO_UDP;
+            header[12..16].copy_from_slice(&src_ip.octets());
+            header[16..20].copy_from_slice(&dst_ip.octets());
+            let header_sum = checksum(&[&header]);
+            header[10..12].copy_from_slice(&header_sum.to_be_bytes());
+            packet.extend_from_slice(&header);
+        }
+        (src_ip, dst_ip) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (src_ip, dst_ip) = (to_v6(src_ip), to_v6(dst_ip));
+            let pseudo = [
+                &src_ip.octets()[..],
+                &dst_ip.octets()[..],
+                &(udp.len() as u32).to_be_bytes(),
+                &[0, 0, 0, IPPROTO_UDP],
+            ]
+            .concat();
+            let udp_sum = udp_checksum(&pseudo, &udp);
+            udp[6..8].copy_from_slice(&udp_sum.to_be_bytes());
+
+            let mut header = [0u8; 40];
+            header[0] = 0x60;
+            header[4..6].copy_from_slice(&udp_len.to_be_bytes());
+            header[6] = IPPROTO_UDP;
+            header[7] = 64;
+            header[8..24].copy_from_slice(&src_ip.octets());
+            header[24..40].copy_from_slice(&dst_ip.octets());
+            packet.extend_from_slice(&header);
+        }
+    }
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn udp_packet_has_valid_ipv4_checksums() {
+        let src: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+        let dst: SocketAddr = "198.51.100.7:51820".parse().unwrap();
+        let packet = udp_packet(src, dst, b"wireguard");
+
+        assert_eq!(packet.len(), 20 + 8 + 9);
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(checksum(&[&packet[..20]]), 0);
+        assert_eq!(&packet[20..22], &40000u16.to_be_bytes());
+        assert_eq!(&packet[22..24], &51820u16.to_be_bytes());
+
+        let pseudo = [
+            &packet[12..20],
+            &[0, IPPROTO_UDP][..],
+            &(17u16).to_be_bytes()[..],
+        ]
+        .concat();
+        assert_eq!(checksum(&[&pseudo, &packet[20..]]), 0);
+    }
+
+    #[test]
+    fn capture_writes_pcap_records() {
+        let dir = std::env::temp_dir().join(format!("vtrunkd-capture-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut capture =
+            Capture::start(&dir, CaptureTarget::Tun, Duration::from_secs(60), 1024).unwrap();
+        capture.record_tun(&[0x45; 20]).unwrap();
+        capture
+            .record_link(
+                "127.0.0.1:1".parse().unwrap(),
+                "127.0.0.1:2".parse().unwrap(),
+                b"x",
+            )
+            .unwrap();
+        assert!(!capture.is_expired());
+        let path = capture.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[..4], &PCAP_MAGIC.to_le_bytes());
+        assert_eq!(&data[20..24], &LINKTYPE_RAW.to_le_bytes());
+        assert_eq!(data.len(), 24 + 16 + 20);
+        #[cfg(unix)]
+        {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn capture_files_are_created_afresh() {
+        let parent =
+            std::env::temp_dir().join(format!("vtrunkd-capture-new-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&parent);
+        let dir = parent.join("captures");
+
+        let capture =
+            Capture::start(&dir, CaptureTarget::Tun, Duration::from_secs(60), 1024).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // The same second again, and a name planted as a symlink: neither is written over.
+        let first = PathBuf::from(capture.finish().unwrap());
+        let stamp = first.file_name().unwrap().to_str().unwrap()[12..]
+            .trim_end_matches(".pcap")
+            .to_string();
+        let target = parent.join("elsewhere");
+        std::os::unix::fs::symlink(&target, dir.join(format!("vtrunkd-tun-{}-1.pcap", stamp)))
+            .unwrap();
+        assert!(PcapFile::create(first.clone()).is_err());
+        let second =
+            Capture::start(&dir, CaptureTarget::Tun, Duration::from_secs(60), 1024).unwrap();
+        assert_ne!(PathBuf::from(second.paths()), first);
+        assert!(!target.exists());
+
+        let _ = std::fs::remove_dir_all(&parent);
+    }
+
+    #[test]
+    fn capture_expires_at_size_limit() {
+        let dir =
+            std::env::temp_dir().join(format!("vtrunkd-capture-limit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut capture =
+            Capture::start(&dir, CaptureTarget::All, Duration::from_secs(60), 64).unwrap();
+        capture.record_tun(&[0u8; 60]).unwrap();
+        assert!(capture.is_expired());
+        let paths = capture.finish().unwrap();
+        assert_eq!(paths.split(' ').count(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
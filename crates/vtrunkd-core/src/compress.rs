@@ -0,0 +1,114 @@
+//! LZ4 compression of tunnelled packets, for slow DSL and satellite links carrying
+//! text-heavy traffic.
+//!
+//! boringtun only passes IP packets through a session, so a compressed packet travels in
+//! an envelope that looks like one: a 20-byte IPv4 header with the experimental protocol
+//! number 253 and [`ENVELOPE_ID`] as its identification, the original length, then the
+//! LZ4 block. Both peers only use it after announcing it inside the session.
+
+use lz4_flex::block;
+
+const HEADER_LEN: usize = 20;
+const ENVELOPE_LEN: usize = HEADER_LEN + 2;
+/// RFC 3692 experimentation protocol number.
+const ENVELOPE_PROTOCOL: u8 = 253;
+const ENVELOPE_ID: u16 = 0x5654;
+/// Smaller packets (ACKs, DNS, voice) rarely shrink by more than the envelope costs.
+const MIN_LEN: usize = 128;
+
+/// The envelope for `packet`, or `None` when compression would not save anything.
+pub fn compress(packet: &[u8]) -> Option<Vec<u8>> {
+    if packet.len() < MIN_LEN || packet.len() > usize::from(u16::MAX) {
+        return None;
+    }
+    let mut envelope = vec![0u8; ENVELOPE_LEN + block::get_maximum_output_size(packet.len())];
+    let compressed = block::compress_into(packet, &mut envelope[ENVELOPE_LEN..]).ok()?;
+    let total = ENVELOPE_LEN + compressed;
+    // Already-compressed payloads (TLS, video, archives) go out as they are.
+    if total >= packet.len() {
+        return None;
+    }
+    envelope.truncate(total);
+    envelope[0] = 0x45;
+    envelope[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+    envelope[4..6].copy_from_slice(&ENVELOPE_ID.to_be_bytes());
+    envelope[8] = 64;
+    envelope[9] = ENVELOPE_PROTOCOL;
+    envelope[HEADER_LEN..ENVELOPE_LEN].copy_from_slice(&(packet.len() as u16).to_be_bytes());
+    Some(envelope)
+}
+
+pub fn is_compressed(packet: &[u8]) -> bool {
+    packet.len() > ENVELOPE_LEN
+        && packet[0] == 0x45
+        && packet[4..6] == ENVELOPE_ID.to_be_bytes()
+        && packet[9] == ENVELOPE_PROTOCOL
+}
+
+/// The original packet inside an envelope, or `None` when it does not decompress to the
+/// length it claims or would not fit in `max_len`.
+pub fn decompress(envelope: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let len = usize::from(u16::from_be_bytes([
+        envelope[HEADER_LEN],
+        envelope[HEADER_LEN + 1],
+    ]));
+    if len > max_len {
+        return None;
+    }
+    let mut packet = vec![0u8; len];
+    match block::decompress_into(&envelope[ENVELOPE_LEN..], &mut packet) {
+        Ok(written) if written == len => Some(packet),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_response() -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0x02, 0x1c, 0, 1, 0x40, 0, 64, 6, 0, 0];
+        packet.extend_from_slice(&[10, 88, 0, 1, 10, 88, 0, 2]);
+        packet.extend_from_slice(&[0; 20]);
+        while packet.len() < 540 {
+            packet.extend_from_slice(b"<tr><td>link</td><td>up</td></tr>\n");
+        }
+        packet.truncate(540);
+        packet
+    }
+
+    #[test]
+    fn text_round_trips_through_the_envelope() {
+        let packet = http_response();
+        let envelope = compress(&packet).expect("text compresses");
+        assert!(envelope.len() < packet.len() / 2);
+        assert!(is_compressed(&envelope));
+        assert!(!is_compressed(&packet));
+        // boringtun trims decrypted packets to the IPv4 total length.
+        assert_eq!(
+            usize::from(u16::from_be_bytes([envelope[2], envelope[3]])),
+            envelope.len()
+        );
+        assert_eq!(decompress(&envelope, 1420), Some(packet));
+        assert_eq!(decompress(&envelope, 500), None);
+    }
+
+    #[test]
+    fn incompressible_and_small_packets_are_left_alone() {
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..1400)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(compress(&noise), None);
+        assert_eq!(compress(&http_response()[..100]), None);
+
+        let mut corrupt = compress(&http_response()).unwrap();
+        corrupt.truncate(corrupt.len() - 8);
+        assert_eq!(decompress(&corrupt, 1420), None);
+    }
+}
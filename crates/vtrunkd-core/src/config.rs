@@ -0,0 +1,1685 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+pub const DEFAULT_HEALTH_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_ERROR_BACKOFF_SECS: u64 = 5;
+pub const DEFAULT_MAX_RESTARTS: u32 = 10;
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+pub const DEFAULT_CHANNEL_DEPTH: usize = 1024;
+pub const DEFAULT_SEND_QUEUE_DEPTH: usize = 256;
+pub const DEFAULT_LINKS_DOWN_BUFFER_PACKETS: usize = 256;
+pub const DEFAULT_LINKS_DOWN_BUFFER_SECS: u64 = 5;
+/// boringtun's own limit, kept as the default.
+pub const DEFAULT_HANDSHAKE_RATE_LIMIT: u64 = 10;
+pub const DEFAULT_HANDSHAKE_SOURCE_LIMIT: u32 = 5;
+pub const DEFAULT_MQTT_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_INTERFACE: &str = "tun0";
+/// Kernel limit on messages per `sendmmsg`/`recvmmsg` call (`UIO_MAXIOV`).
+const MAX_BATCH_SIZE: usize = 1024;
+/// Kernel limit on queues per multi-queue TUN device (`MAX_TAP_QUEUES`).
+const MAX_TUN_QUEUES: usize = 256;
+/// Crypto lanes are numbered in one byte of the session index.
+pub const MAX_CRYPTO_WORKERS: usize = 64;
+
+/// Replaces private and preshared keys in `vtrunkd showconf` output.
+const REDACTED: &str = "<redacted>";
+
+use crate::allowlist::AllowedSources;
+use crate::audit::AuditOutput;
+use crate::capture::DEFAULT_CAPTURE_DIR;
+use crate::control::{LinkAdminState, DEFAULT_CONTROL_SOCKET};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::logging::{LogFormat, LogOutput, DEFAULT_LOG_KEEP_FILES, DEFAULT_LOG_MAX_SIZE_MB};
+use crate::policy::Schedule;
+use crate::sandbox::SandboxMode;
+use crate::uci;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub wireguard: WireGuardConfig,
+    pub control: Option<ControlConfig>,
+    pub hooks: Option<HooksConfig>,
+    pub logging: Option<LoggingConfig>,
+    pub sandbox: Option<SandboxMode>,
+    pub runtime: Option<RuntimeConfig>,
+    pub mqtt: Option<MqttConfig>,
+    /// Time-of-day rules setting link admin states, e.g. an LTE link down overnight.
+    pub policies: Option<Vec<LinkPolicy>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub mtu: u32,
+    pub buffer_size: usize,
+    pub interface: Option<String>,
+    pub address: Option<String>,
+    pub netmask: Option<String>,
+    pub destination: Option<String>,
+    /// Further IPv4 and IPv6 addresses in CIDR notation (`fd00:10::2/64`), added next to
+    /// `address` (Linux only).
+    pub addresses: Option<Vec<String>>,
+    /// Already-open TUN descriptor inherited from a privileged helper; the interface
+    /// settings above are then left to whoever opened it.
+    pub tun_fd: Option<i32>,
+    /// Number of `IFF_MULTI_QUEUE` queues to open (Linux only); each gets its own reader,
+    /// and with a vtrunkd peer a crypto lane of its own unless `wireguard.crypto_workers`
+    /// says otherwise.
+    pub tun_queues: Option<usize>,
+    /// Datagrams per `sendmmsg`/`recvmmsg` call on the link sockets.
+    pub batch_size: Option<usize>,
+    /// How TUN queues and link sockets are read and written; `io_uring` needs the `io-uring`
+    /// feature.
+    pub io_backend: Option<IoBackend>,
+    /// Packets queued from the TUN and link readers to the tunnel loop before new ones
+    /// are dropped (and counted as overflow).
+    pub channel_depth: Option<usize>,
+    /// Which packet read from the TUN device is dropped when that queue is full.
+    pub tun_drop_policy: Option<DropPolicy>,
+    /// Datagrams waiting for each link's socket before the scheduler passes the link over
+    /// for the next one (256 by default).
+    pub send_queue_depth: Option<usize>,
+    /// `attach` opens the existing TUN device named `interface` as it is configured,
+    /// instead of creating one (Linux only).
+    pub interface_mode: Option<InterfaceMode>,
+    /// User (name or uid) allowed to open the device without `CAP_NET_ADMIN` (Linux only).
+    pub tun_owner: Option<String>,
+    /// Group (name or gid) allowed to open the device without `CAP_NET_ADMIN` (Linux only).
+    pub tun_group: Option<String>,
+    /// Keeps the device, with its addresses and routes, after vtrunkd exits or restarts
+    /// (Linux only).
+    pub persistent: Option<bool>,
+    /// Lowers the MSS of TCP SYNs crossing the TUN device to fit `mtu`, in both directions.
+    pub clamp_mss: Option<bool>,
+    /// Answers packets read from the TUN device that cannot be sent, too big for `mtu`
+    /// with fragmentation forbidden or with no link to take them, with an ICMP error
+    /// (on by default).
+    pub icmp_errors: Option<bool>,
+    /// What becomes of packets read from the TUN device that are not IPv4 or IPv6.
+    pub unsupported_packets: Option<UnsupportedPolicy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireGuardConfig {
+    /// Base64 private key; leave it out when `private_key_credential` names one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_key: String,
+    /// systemd credential (`LoadCredential=`, `LoadCredentialEncrypted=`) holding the
+    /// private key, read from `$CREDENTIALS_DIRECTORY` at each tunnel start.
+    pub private_key_credential: Option<String>,
+    pub peer_public_key: String,
+    pub preshared_key: Option<String>,
+    /// systemd credential holding the preshared key, as for `private_key_credential`.
+    pub preshared_key_credential: Option<String>,
+    pub persistent_keepalive: Option<u16>,
+    pub bonding_mode: Option<BondingMode>,
+    /// `wireguard` for a stock WireGuard peer: one link, no bonding control packets.
+    pub peer_kind: Option<PeerKind>,
+    pub error_backoff_secs: Option<u64>,
+    pub health_check_interval_ms: Option<u64>,
+    pub health_check_timeout_ms: Option<u64>,
+    pub stats_interval_secs: Option<u64>,
+    /// Consecutive tunnel failures to restart from before the daemon exits; 0 never restarts.
+    pub max_restarts: Option<u32>,
+    /// Copies each inner packet's DSCP, when it has one, to the outer UDP header in place
+    /// of the link's `dscp`, so upstream QoS sees the tunnelled voice traffic.
+    pub copy_dscp: Option<bool>,
+    /// RFC 6040 ECN: inner ECN marks are copied to the outer header, and congestion marked
+    /// on the path is passed on to the inner packet.
+    pub ecn: Option<bool>,
+    /// LZ4-compresses inner packets that shrink, once the peer announces it can too.
+    pub compression: Option<bool>,
+    /// WireGuard sessions with the peer, each encrypting and decrypting on a thread of its
+    /// own; flows are spread over them. Both peers use the lower of their two counts (one
+    /// per TUN queue by default).
+    pub crypto_workers: Option<usize>,
+    /// Handshake messages per second, across all sources, answered before initiators must
+    /// prove their address with a cookie reply first (10 by default; 0 always asks).
+    pub handshake_rate_limit: Option<u64>,
+    /// Handshake messages per second one source address may send on a link; more are
+    /// dropped unread (5 by default).
+    pub handshake_source_limit: Option<u32>,
+    /// File the link endpoints, RTTs and traffic counters are saved to on shutdown and
+    /// restored from at startup.
+    pub state_file: Option<String>,
+    /// Rebinds links with a `bind_device` or a specific `bind` address when rtnetlink
+    /// reports their device recreated or readdressed (Linux only; on by default).
+    pub follow_devices: Option<bool>,
+    /// Holds data packets that overtook one sent before them, so the TUN device sees them
+    /// in the order the peer sent them.
+    pub reorder: Option<bool>,
+    /// Longest a missing packet is waited for; tuned from the spread of the links' round
+    /// trips when unset.
+    pub reorder_hold_ms: Option<u64>,
+    /// In `failover` mode, sends on the link with the best quality score, weight breaking
+    /// ties, instead of the one with the highest weight.
+    pub failover_by_quality: Option<bool>,
+    /// What becomes of TUN packets while no link is available.
+    pub links_down: Option<LinksDownPolicy>,
+    /// Packets `links_down: buffer` holds, the oldest dropped first (256 by default).
+    pub links_down_buffer_packets: Option<usize>,
+    /// Longest `links_down: buffer` holds a packet (5 by default).
+    pub links_down_buffer_secs: Option<u64>,
+    /// Caps the traffic read from the TUN device and sent to the peer, in kbit/s of inner
+    /// packets; packets over it are dropped. No limit when unset.
+    pub send_limit_kbit: Option<u64>,
+    /// Caps the traffic received from the peer and written to the TUN device, likewise.
+    pub receive_limit_kbit: Option<u64>,
+    pub links: Vec<WireGuardLinkConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireGuardLinkConfig {
+    pub name: Option<String>,
+    pub bind: Option<String>,
+    /// Network device the link sends and receives on (`SO_BINDTODEVICE`, Linux only),
+    /// whatever address it has; the link is rebound when the device is recreated.
+    pub bind_device: Option<String>,
+    pub endpoint: Option<String>,
+    pub weight: Option<u32>,
+    /// `SO_RCVBUF` for the link socket in bytes; the kernel default when unset.
+    pub socket_recv_buffer: Option<usize>,
+    /// `SO_SNDBUF` for the link socket in bytes; the kernel default when unset.
+    pub socket_send_buffer: Option<usize>,
+    /// Sockets sharing the link's address with `SO_REUSEPORT`, each read by its own task,
+    /// so the kernel spreads the datagrams of different sources across cores (Linux only;
+    /// 1 by default).
+    pub receive_sockets: Option<usize>,
+    /// DSCP (0-63) marked on the link's outer UDP packets.
+    pub dscp: Option<u8>,
+    /// TTL (IPv4) and hop limit (IPv6) of the link's outer UDP packets, e.g. 64 or 128 to
+    /// look like the host's own traffic to carriers that count hops; the kernel default
+    /// when unset.
+    pub ttl: Option<u8>,
+    /// Source networks (`198.51.100.0/24`, `2001:db8::/32`, or single addresses) the link
+    /// accepts datagrams from; everything else is dropped unread. Any source when unset.
+    pub allowed_sources: Option<Vec<String>>,
+    /// Drops datagrams from anywhere but the link's peer, its `endpoint` or the address
+    /// it last authenticated from, before they are decrypted. Handshakes still get
+    /// through so a peer that moved can come back. Off by default.
+    pub strict_source: Option<bool>,
+}
+
+/// MQTT telemetry publisher (`mqtt` feature).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `host[:port]` of the broker; port 1883 when omitted. Plain MQTT, no TLS.
+    pub broker: String,
+    /// Defaults to `vtrunkd-<hostname>`.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topics are published under it; defaults to `vtrunkd/<client_id>`.
+    pub topic_prefix: Option<String>,
+    pub interval_secs: Option<u64>,
+}
+
+/// Sets `link`'s admin state from `from` to `to` (`HH:MM`, local time) on `days`, or every
+/// day when unset. A window that ends before it starts runs past midnight, and its day is
+/// the one it starts on; the link is brought back up when no rule for it applies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkPolicy {
+    pub link: String,
+    pub from: String,
+    pub to: String,
+    pub days: Option<Vec<Weekday>>,
+    pub state: LinkAdminState,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+/// Async runtime tuning; read once at startup, so changes need a restart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Tokio worker threads; one per CPU when unset.
+    pub workers: Option<usize>,
+    /// CPUs the runtime's threads are pinned to (Linux only), e.g. the big cores on big.LITTLE.
+    pub pin_cpus: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControlConfig {
+    pub socket: Option<String>,
+    /// Where `capture start` writes pcap files; defaults to a directory of the daemon's own,
+    /// `/var/lib/vtrunkd/captures` on Linux, created `0700`.
+    pub capture_dir: Option<String>,
+    /// Also serve the read-only WireGuard UAPI at `/var/run/wireguard/<interface>.sock`
+    /// so `wg show` and WireGuard exporters can read the tunnel.
+    pub uapi: Option<bool>,
+    /// Also own `org.vtrunkd.Manager` on the system bus (`dbus` feature).
+    pub dbus: Option<bool>,
+    /// Also serve the HTTP management API.
+    pub http: Option<HttpApiConfig>,
+    /// `address:port` for unauthenticated `GET /healthz` and `GET /readyz`, e.g.
+    /// `0.0.0.0:8081` for Kubernetes probes.
+    pub health: Option<String>,
+    /// Users (name or uid) allowed to change the tunnel over the socket (`link`, `capture`,
+    /// `rotate-key`, `accept-key`, `handover`); root and the daemon's own user always are.
+    /// Once this or `write_groups` is set, the socket is opened to everyone for reading.
+    pub write_users: Option<Vec<String>>,
+    /// Groups (name or gid), primary or supplementary, allowed the same.
+    pub write_groups: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpApiConfig {
+    /// `address:port` to listen on, e.g. `127.0.0.1:8080`.
+    pub bind: String,
+    /// Bearer token every request must carry.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: Option<LogFormat>,
+    /// `stdout`, `file`, `journald` or `syslog`; defaults to `file` when `file` is set.
+    pub output: Option<LogOutput>,
+    /// Append logs to this file instead of stdout; needed to keep logs when daemonized.
+    pub file: Option<String>,
+    pub max_size_mb: Option<u64>,
+    /// Also rotate once the current file is this many hours old.
+    pub rotate_hours: Option<u64>,
+    /// Rotated files to keep next to `file`.
+    pub keep_files: Option<usize>,
+    /// Separate audit log of handshakes, endpoint changes, key rotations, configuration
+    /// changes and control commands.
+    pub audit: Option<AuditConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// `file` or `journald`; defaults to `file`.
+    pub output: Option<AuditOutput>,
+    /// File records are appended to, one JSON object per line. It is never rotated or
+    /// truncated by vtrunkd.
+    pub file: Option<String>,
+}
+
+/// Actions run when link events fire. Each action sets either `command` or `url`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    pub on_link_down: Option<Vec<HookAction>>,
+    pub on_link_up: Option<Vec<HookAction>>,
+    pub on_all_links_down: Option<Vec<HookAction>>,
+    /// Shell commands run in order before the TUN interface is created, as in wg-quick;
+    /// `%i` expands to the interface name. A failing command aborts the start.
+    pub pre_up: Option<Vec<String>>,
+    /// Run once the interface is up and the links are bound, before the handshake.
+    pub post_up: Option<Vec<String>>,
+    /// Run on shutdown while the interface still exists.
+    pub pre_down: Option<Vec<String>>,
+    /// Run after the interface is gone, including after a failed start.
+    pub post_down: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookAction {
+    /// Shell command run with `sh -c`; event details are passed as `VTRUNKD_*` env vars.
+    pub command: Option<String>,
+    /// Plain `http://` URL that receives the event as a JSON POST body.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BondingMode {
+    #[default]
+    #[serde(alias = "bonding", alias = "bonded")]
+    Aggregate,
+    Redundant,
+    Failover,
+}
+
+/// What runs on the other end of the links.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerKind {
+    #[default]
+    Vtrunkd,
+    /// Kernel WireGuard, wireguard-go or any other peer that only speaks WireGuard.
+    Wireguard,
+}
+
+/// Whether vtrunkd creates the TUN device or uses one made by NetworkManager,
+/// systemd-networkd or an orchestrator.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceMode {
+    #[default]
+    Create,
+    Attach,
+}
+
+/// I/O path for the TUN queues and link sockets.
+///
+/// `io_uring` keeps up to `batch_size` reads and writes in flight per queue and socket on
+/// dedicated threads, with the TUN buffers registered with the kernel.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IoBackend {
+    #[default]
+    Epoll,
+    IoUring,
+}
+
+/// What goes when the queue from the TUN device to the tunnel loop is full.
+///
+/// `tail` drops the packet just read, `oldest` the one that has waited longest, and
+/// `fair` the oldest packet of the flow (addresses, protocol and ports) with the most
+/// packets queued, so one bulk transfer cannot starve interactive traffic.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    #[default]
+    Tail,
+    Oldest,
+    Fair,
+}
+
+/// What happens to packets read from the TUN device while no link is available (each is
+/// down, in its error backoff, drained or without a peer address).
+///
+/// `best_effort` sends them on the links anyway, in case one still gets through, and
+/// drops them only when no link has a peer address. `drop` drops them at once, and
+/// `buffer` holds them until a link comes back. Packets dropped count in
+/// `dropped_no_link` and, unless `network.icmp_errors` is off, are answered with an ICMP
+/// unreachable error.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LinksDownPolicy {
+    #[default]
+    BestEffort,
+    Drop,
+    Buffer,
+}
+
+/// What happens to a packet read from the TUN device that is not well-formed IPv4 or IPv6.
+///
+/// `drop` drops it, `log` drops it and logs what it looked like (once a second at most),
+/// and `forward` tunnels it as it is. Each is counted either way.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsupportedPolicy {
+    #[default]
+    Drop,
+    Log,
+    Forward,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: NetworkConfig {
+                mtu: 1420,
+                buffer_size: 65536,
+                interface: None,
+                address: None,
+                netmask: None,
+                destination: None,
+                addresses: None,
+                tun_fd: None,
+                tun_queues: None,
+                batch_size: None,
+                io_backend: None,
+                channel_depth: None,
+                tun_drop_policy: None,
+                send_queue_depth: None,
+                interface_mode: None,
+                tun_owner: None,
+                tun_group: None,
+                persistent: None,
+                clamp_mss: None,
+                icmp_errors: None,
+                unsupported_packets: None,
+            },
+            wireguard: WireGuardConfig {
+                private_key: "REPLACE_ME".to_string(),
+                private_key_credential: None,
+                peer_public_key: "REPLACE_ME".to_string(),
+                preshared_key: None,
+                preshared_key_credential: None,
+                persistent_keepalive: Some(25),
+                bonding_mode: Some(BondingMode::Aggregate),
+                peer_kind: None,
+                error_backoff_secs: Some(DEFAULT_ERROR_BACKOFF_SECS),
+                health_check_interval_ms: Some(DEFAULT_HEALTH_INTERVAL_MS),
+                health_check_timeout_ms: Some(5000),
+                stats_interval_secs: None,
+                max_restarts: None,
+                copy_dscp: None,
+                ecn: None,
+                compression: None,
+                crypto_workers: None,
+                handshake_rate_limit: None,
+                handshake_source_limit: None,
+                state_file: None,
+                follow_devices: None,
+                reorder: None,
+                reorder_hold_ms: None,
+                failover_by_quality: None,
+                links_down: None,
+                links_down_buffer_packets: None,
+                links_down_buffer_secs: None,
+                send_limit_kbit: None,
+                receive_limit_kbit: None,
+                links: vec![WireGuardLinkConfig {
+                    name: Some("link-0".to_string()),
+                    bind: Some("0.0.0.0:0".to_string()),
+                    bind_device: None,
+                    endpoint: Some("example.com:51820".to_string()),
+                    weight: Some(1),
+                    socket_recv_buffer: None,
+                    socket_send_buffer: None,
+                    receive_sockets: None,
+                    dscp: None,
+                    ttl: None,
+                    allowed_sources: None,
+                    strict_source: None,
+                }],
+            },
+            control: Some(ControlConfig {
+                socket: Some(DEFAULT_CONTROL_SOCKET.to_string()),
+                capture_dir: None,
+                uapi: None,
+                dbus: None,
+                http: None,
+                health: None,
+                write_users: None,
+                write_groups: None,
+            }),
+            hooks: None,
+            logging: None,
+            sandbox: None,
+            runtime: None,
+            mqtt: None,
+            policies: None,
+        }
+    }
+}
+
+impl Config {
+    /// Runs the checks `load_config` applies, for configs built in code.
+    pub fn validate(&self) -> VtrunkdResult<()> {
+        validate_config(self)
+    }
+
+    pub fn control_socket(&self) -> Option<&str> {
+        self.control
+            .as_ref()
+            .and_then(|control| control.socket.as_deref())
+    }
+
+    pub fn capture_dir(&self) -> Option<&str> {
+        self.control
+            .as_ref()
+            .and_then(|control| control.capture_dir.as_deref())
+    }
+
+    pub fn uapi_enabled(&self) -> bool {
+        self.control
+            .as_ref()
+            .and_then(|control| control.uapi)
+            .unwrap_or(false)
+    }
+
+    pub fn dbus_enabled(&self) -> bool {
+        self.control
+            .as_ref()
+            .and_then(|control| control.dbus)
+            .unwrap_or(false)
+    }
+
+    pub fn http_api(&self) -> Option<&HttpApiConfig> {
+        self.control
+            .as_ref()
+            .and_then(|control| control.http.as_ref())
+    }
+
+    pub fn health_bind(&self) -> Option<&str> {
+        self.control
+            .as_ref()
+            .and_then(|control| control.health.as_deref())
+    }
+
+    pub fn log_format(&self) -> Option<LogFormat> {
+        self.logging.as_ref().and_then(|logging| logging.format)
+    }
+
+    /// The configuration as the daemon applies it, with every default spelled out.
+    pub fn effective(&self) -> Config {
+        let mut config = self.clone();
+
+        config.network.batch_size.get_or_insert(DEFAULT_BATCH_SIZE);
+        config
+            .network
+            .io_backend
+            .get_or_insert_with(IoBackend::default);
+
+        let wg = &mut config.wireguard;
+        wg.bonding_mode.get_or_insert_with(BondingMode::default);
+        wg.peer_kind.get_or_insert_with(PeerKind::default);
+        wg.error_backoff_secs
+            .get_or_insert(DEFAULT_ERROR_BACKOFF_SECS);
+        wg.health_check_interval_ms
+            .get_or_insert(DEFAULT_HEALTH_INTERVAL_MS);
+        wg.max_restarts.get_or_insert(DEFAULT_MAX_RESTARTS);
+        for (index, link) in wg.links.iter_mut().enumerate() {
+            link.name = Some(link_name(link, index));
+            link.weight.get_or_insert(1);
+        }
+
+        let control = config.control.get_or_insert_with(ControlConfig::default);
+        control
+            .socket
+            .get_or_insert_with(|| DEFAULT_CONTROL_SOCKET.to_string());
+        control
+            .capture_dir
+            .get_or_insert_with(|| DEFAULT_CAPTURE_DIR.to_string());
+        control.uapi.get_or_insert(false);
+        control.dbus.get_or_insert(false);
+
+        if let Some(mqtt) = &mut config.mqtt {
+            mqtt.interval_secs.get_or_insert(DEFAULT_MQTT_INTERVAL_SECS);
+        }
+
+        let logging = config.logging.get_or_insert_with(LoggingConfig::default);
+        logging.format.get_or_insert_with(LogFormat::default);
+        if logging.file.is_some() {
+            logging.output.get_or_insert(LogOutput::File);
+            logging.max_size_mb.get_or_insert(DEFAULT_LOG_MAX_SIZE_MB);
+            logging.keep_files.get_or_insert(DEFAULT_LOG_KEEP_FILES);
+        } else {
+            logging.output.get_or_insert(LogOutput::Stdout);
+        }
+        if let Some(audit) = &mut logging.audit {
+            audit.output.get_or_insert(AuditOutput::File);
+        }
+
+        config.sandbox.get_or_insert_with(SandboxMode::default);
+        let runtime = config.runtime.get_or_insert_with(RuntimeConfig::default);
+        runtime.workers.get_or_insert_with(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+        config
+    }
+
+    /// Masks the private and preshared keys, the API token and the MQTT password so the
+    /// config can be shared.
+    pub fn redact_secrets(&mut self) {
+        if !self.wireguard.private_key.is_empty() {
+            self.wireguard.private_key = REDACTED.to_string();
+        }
+        if let Some(preshared_key) = &mut self.wireguard.preshared_key {
+            *preshared_key = REDACTED.to_string();
+        }
+        if let Some(http) = self.control.as_mut().and_then(|c| c.http.as_mut()) {
+            http.token = REDACTED.to_string();
+        }
+        if let Some(password) = self.mqtt.as_mut().and_then(|m| m.password.as_mut()) {
+            *password = REDACTED.to_string();
+        }
+    }
+}
+
+pub fn load_config(path: &Path) -> VtrunkdResult<Config> {
+    if !path.exists() {
+        return Err(VtrunkdError::NotFound(format!(
+            "Configuration file not found: {:?}",
+            path
+        )));
+    }
+
+    let text = std::fs::read_to_string(path)?;
+    parse_document(&text)
+}
+
+/// Parses and validates a configuration in either format `load_config` reads.
+pub fn parse_document(text: &str) -> VtrunkdResult<Config> {
+    // OpenWrt keeps its configuration as UCI under /etc/config.
+    if uci::is_uci(text) {
+        let config = uci::parse(text)?;
+        validate_config(&config)?;
+        return Ok(config);
+    }
+    parse_config(text)
+}
+
+/// Parses and validates a configuration document.
+pub fn parse_config(yaml: &str) -> VtrunkdResult<Config> {
+    let config: Config = serde_yaml::from_str(yaml)?;
+    validate_config(&config)?;
+    Ok(config)
+}
+
+pub fn generate_default_config(path: &Path) -> VtrunkdResult<()> {
+    let config = Config::default();
+    let yaml = serde_yaml::to_string(&config)?;
+    std::fs::write(path, yaml)?;
+    // The file holds the private key; see `check_key_file_permissions`.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// Refuses a file holding private or preshared keys that group or other users can access,
+/// as ssh does for private keys.
+#[cfg(unix)]
+pub fn check_key_file_permissions(path: &Path) -> VtrunkdResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "{:?} holds private keys but has mode {:03o}; run `chmod 600` on it",
+            path, mode
+        )));
+    }
+    Ok(())
+}
+
+/// Windows ACLs are left to the installer.
+#[cfg(not(unix))]
+pub fn check_key_file_permissions(_path: &Path) -> VtrunkdResult<()> {
+    Ok(())
+}
+
+/// Reads a credential systemd passed to the service (`LoadCredential=`,
+/// `LoadCredentialEncrypted=`, `SetCredential=`), without surrounding whitespace.
+pub fn read_credential(name: &str) -> VtrunkdResult<Zeroizing<String>> {
+    let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") else {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Credential {} needs $CREDENTIALS_DIRECTORY; start vtrunkd from a systemd unit with LoadCredential={}:<file>",
+            name, name
+        )));
+    };
+    let path = Path::new(&dir).join(name);
+    let value = Zeroizing::new(std::fs::read_to_string(&path).map_err(|e| {
+        VtrunkdError::InvalidConfig(format!("Failed to read credential {:?}: {}", path, e))
+    })?);
+    Ok(Zeroizing::new(value.trim().to_string()))
+}
+
+impl WireGuardConfig {
+    /// The base64 private key, from its credential when one is named.
+    pub fn load_private_key(&self) -> VtrunkdResult<Zeroizing<String>> {
+        match &self.private_key_credential {
+            Some(name) => read_credential(name),
+            None => Ok(Zeroizing::new(self.private_key.clone())),
+        }
+    }
+
+    /// The base64 preshared key, if any, from its credential when one is named.
+    pub fn load_preshared_key(&self) -> VtrunkdResult<Option<Zeroizing<String>>> {
+        match (&self.preshared_key_credential, &self.preshared_key) {
+            (Some(name), _) => read_credential(name).map(Some),
+            (None, Some(key)) => Ok(Some(Zeroizing::new(key.clone()))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether the config file itself holds key material.
+    pub fn has_inline_keys(&self) -> bool {
+        !self.private_key.is_empty() || self.preshared_key.is_some()
+    }
+}
+
+/// Splits `address/prefix`, checking the prefix length against the address family.
+pub fn parse_cidr(value: &str) -> VtrunkdResult<(IpAddr, u8)> {
+    let invalid = || VtrunkdError::InvalidConfig(format!("Invalid network address {}", value));
+    let (address, prefix) = value.split_once('/').ok_or_else(invalid)?;
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    if prefix > max {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Prefix of network address {} must be at most {}",
+            value, max
+        )));
+    }
+    Ok((address, prefix))
+}
+
+/// Name used for a link in logs and control commands.
+pub fn link_name(link: &WireGuardLinkConfig, index: usize) -> String {
+    link.name
+        .clone()
+        .unwrap_or_else(|| format!("link-{}", index))
+}
+
+pub(crate) fn validate_config(config: &Config) -> VtrunkdResult<()> {
+    if config.network.mtu == 0 {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network MTU cannot be 0".to_string(),
+        ));
+    }
+
+    if config.network.mtu > u16::MAX as u32 {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network MTU exceeds u16::MAX".to_string(),
+        ));
+    }
+
+    if config.network.buffer_size == 0 {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network buffer_size cannot be 0".to_string(),
+        ));
+    }
+
+    if config.network.buffer_size < config.network.mtu as usize {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network buffer_size must be at least MTU size".to_string(),
+        ));
+    }
+
+    if config.network.tun_fd.is_some_and(|fd| fd < 0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network tun_fd cannot be negative".to_string(),
+        ));
+    }
+
+    if config.network.interface_mode == Some(InterfaceMode::Attach) {
+        let network = &config.network;
+        if network.interface.is_none() || network.tun_fd.is_some() {
+            return Err(VtrunkdError::InvalidConfig(
+                "Network interface_mode attach needs an interface name and no tun_fd".to_string(),
+            ));
+        }
+        if network.address.is_some()
+            || network.netmask.is_some()
+            || network.destination.is_some()
+            || network.addresses.is_some()
+        {
+            return Err(VtrunkdError::InvalidConfig(
+                "Network interface_mode attach uses the device as configured; remove address, netmask, destination and addresses".to_string(),
+            ));
+        }
+    }
+
+    let network = &config.network;
+    if (network.tun_owner.is_some() || network.tun_group.is_some() || network.persistent.is_some())
+        && (network.tun_fd.is_some() || network.interface_mode == Some(InterfaceMode::Attach))
+    {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network tun_owner, tun_group and persistent apply only to a device vtrunkd creates"
+                .to_string(),
+        ));
+    }
+
+    if let Some(queues) = config.network.tun_queues {
+        if queues == 0 || queues > MAX_TUN_QUEUES {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "Network tun_queues must be between 1 and {}",
+                MAX_TUN_QUEUES
+            )));
+        }
+        if queues > 1 && config.network.tun_fd.is_some() {
+            return Err(VtrunkdError::InvalidConfig(
+                "Network tun_queues cannot be combined with tun_fd".to_string(),
+            ));
+        }
+    }
+
+    if config
+        .network
+        .batch_size
+        .is_some_and(|size| size == 0 || size > MAX_BATCH_SIZE)
+    {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Network batch_size must be between 1 and {}",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let primary = [
+        ("address", &config.network.address),
+        ("netmask", &config.network.netmask),
+        ("destination", &config.network.destination),
+    ];
+    for (field, value) in primary {
+        if let Some(value) = value {
+            match value.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => {}
+                Ok(IpAddr::V6(_)) => {
+                    return Err(VtrunkdError::InvalidConfig(format!(
+                        "Network {} must be IPv4; list IPv6 addresses under addresses",
+                        field
+                    )));
+                }
+                Err(_) => {
+                    return Err(VtrunkdError::InvalidConfig(format!(
+                        "Invalid network {}: {}",
+                        field, value
+                    )));
+                }
+            }
+        }
+    }
+    for address in config.network.addresses.iter().flatten() {
+        parse_cidr(address)?;
+    }
+
+    if config.network.channel_depth == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network channel_depth must be greater than 0".to_string(),
+        ));
+    }
+    if config.network.send_queue_depth == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network send_queue_depth must be greater than 0".to_string(),
+        ));
+    }
+
+    if config.network.io_backend == Some(IoBackend::IoUring)
+        && !cfg!(all(target_os = "linux", feature = "io-uring"))
+    {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network io_backend io_uring needs a Linux build with the io-uring feature".to_string(),
+        ));
+    }
+
+    if let Some(http) = config.http_api() {
+        if http.bind.parse::<std::net::SocketAddr>().is_err() {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "control.http.bind must be address:port, got {}",
+                http.bind
+            )));
+        }
+        if http.token.trim().is_empty() {
+            return Err(VtrunkdError::InvalidConfig(
+                "control.http.token cannot be empty".to_string(),
+            ));
+        }
+    }
+
+    if let Some(mqtt) = &config.mqtt {
+        if !cfg!(feature = "mqtt") {
+            return Err(VtrunkdError::InvalidConfig(
+                "mqtt needs a build with the mqtt feature".to_string(),
+            ));
+        }
+        if mqtt.broker.is_empty() {
+            return Err(VtrunkdError::InvalidConfig(
+                "mqtt.broker cannot be empty".to_string(),
+            ));
+        }
+        if mqtt.interval_secs == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "mqtt.interval_secs must be greater than 0".to_string(),
+            ));
+        }
+    }
+
+    if config.dbus_enabled() && !cfg!(feature = "dbus") {
+        return Err(VtrunkdError::InvalidConfig(
+            "control.dbus needs a build with the dbus feature".to_string(),
+        ));
+    }
+
+    let wg = &config.wireguard;
+    match (wg.private_key.is_empty(), &wg.private_key_credential) {
+        (true, None) => {
+            return Err(VtrunkdError::InvalidConfig(
+                "WireGuard private_key or private_key_credential is required".to_string(),
+            ));
+        }
+        (false, Some(_)) => {
+            return Err(VtrunkdError::InvalidConfig(
+                "Set either private_key or private_key_credential, not both".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if wg.preshared_key.is_some() && wg.preshared_key_credential.is_some() {
+        return Err(VtrunkdError::InvalidConfig(
+            "Set either preshared_key or preshared_key_credential, not both".to_string(),
+        ));
+    }
+    for name in [&wg.private_key_credential, &wg.preshared_key_credential]
+        .into_iter()
+        .flatten()
+    {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "Invalid credential name {:?}",
+                name
+            )));
+        }
+    }
+
+    if config.wireguard.peer_public_key.is_empty() {
+        return Err(VtrunkdError::InvalidConfig(
+            "WireGuard peer_public_key is required".to_string(),
+        ));
+    }
+
+    if config.wireguard.links.is_empty() {
+        return Err(VtrunkdError::InvalidConfig(
+            "WireGuard links cannot be empty".to_string(),
+        ));
+    }
+
+    if let Some(backoff) = config.wireguard.error_backoff_secs {
+        if backoff == 0 {
+            return Err(VtrunkdError::InvalidConfig(
+                "error_backoff_secs must be greater than 0".to_string(),
+            ));
+        }
+    }
+
+    if let Some(interval) = config.wireguard.health_check_interval_ms {
+        if interval == 0 {
+            return Err(VtrunkdError::InvalidConfig(
+                "health_check_interval_ms must be greater than 0".to_string(),
+            ));
+        }
+    }
+
+    if let Some(timeout) = config.wireguard.health_check_timeout_ms {
+        if timeout == 0 {
+            return Err(VtrunkdError::InvalidConfig(
+                "health_check_timeout_ms must be greater than 0".to_string(),
+            ));
+        }
+    }
+
+    if let Some(interval) = config.wireguard.stats_interval_secs {
+        if interval == 0 {
+            return Err(VtrunkdError::InvalidConfig(
+                "stats_interval_secs must be greater than 0".to_string(),
+            ));
+        }
+    }
+
+    if let Some(timeout) = config.wireguard.health_check_timeout_ms {
+        let interval = config
+            .wireguard
+            .health_check_interval_ms
+            .unwrap_or(DEFAULT_HEALTH_INTERVAL_MS);
+        if timeout <= interval {
+            return Err(VtrunkdError::InvalidConfig(
+                "health_check_timeout_ms must be greater than health_check_interval_ms".to_string(),
+            ));
+        }
+    }
+
+    if config.wireguard.peer_kind == Some(PeerKind::Wireguard) {
+        if config.wireguard.links.len() != 1 {
+            return Err(VtrunkdError::InvalidConfig(
+                "peer_kind wireguard supports exactly one link".to_string(),
+            ));
+        }
+        if config.wireguard.health_check_timeout_ms.is_some() {
+            return Err(VtrunkdError::InvalidConfig(
+                "health_check_timeout_ms needs a vtrunkd peer; remove it for peer_kind wireguard"
+                    .to_string(),
+            ));
+        }
+        if config.wireguard.compression == Some(true) {
+            return Err(VtrunkdError::InvalidConfig(
+                "compression needs a vtrunkd peer; remove it for peer_kind wireguard".to_string(),
+            ));
+        }
+        if config
+            .wireguard
+            .crypto_workers
+            .is_some_and(|workers| workers > 1)
+        {
+            return Err(VtrunkdError::InvalidConfig(
+                "crypto_workers needs a vtrunkd peer; remove it for peer_kind wireguard"
+                    .to_string(),
+            ));
+        }
+    }
+    if config
+        .wireguard
+        .crypto_workers
+        .is_some_and(|workers| workers == 0 || workers > MAX_CRYPTO_WORKERS)
+    {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "wireguard.crypto_workers must be between 1 and {}",
+            MAX_CRYPTO_WORKERS
+        )));
+    }
+
+    if config.wireguard.links_down_buffer_packets == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.links_down_buffer_packets must be greater than 0".to_string(),
+        ));
+    }
+    if config.wireguard.links_down_buffer_secs == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.links_down_buffer_secs must be greater than 0".to_string(),
+        ));
+    }
+    if config.wireguard.send_limit_kbit == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.send_limit_kbit must be greater than 0".to_string(),
+        ));
+    }
+    if config.wireguard.receive_limit_kbit == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.receive_limit_kbit must be greater than 0".to_string(),
+        ));
+    }
+    if config.wireguard.reorder_hold_ms == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.reorder_hold_ms must be greater than 0".to_string(),
+        ));
+    }
+
+    if config.wireguard.handshake_source_limit == Some(0) {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.handshake_source_limit must be greater than 0".to_string(),
+        ));
+    }
+
+    if config.wireguard.state_file.as_deref() == Some("") {
+        return Err(VtrunkdError::InvalidConfig(
+            "wireguard.state_file cannot be empty".to_string(),
+        ));
+    }
+
+    if let Some(socket) = config.control_socket() {
+        if socket.is_empty() {
+            return Err(VtrunkdError::InvalidConfig(
+                "control.socket cannot be empty".to_string(),
+            ));
+        }
+    }
+
+    if let Some(logging) = &config.logging {
+        if logging.file.as_deref() == Some("") {
+            return Err(VtrunkdError::InvalidConfig(
+                "logging.file cannot be empty".to_string(),
+            ));
+        }
+        if logging.max_size_mb == Some(0) || logging.rotate_hours == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "logging.max_size_mb and logging.rotate_hours must be greater than 0".to_string(),
+            ));
+        }
+        if let Some(audit) = &logging.audit {
+            let has_file = audit.file.as_deref().is_some_and(|file| !file.is_empty());
+            match audit.output.unwrap_or(AuditOutput::File) {
+                AuditOutput::File if !has_file => {
+                    return Err(VtrunkdError::InvalidConfig(
+                        "logging.audit needs a file, or output journald".to_string(),
+                    ));
+                }
+                AuditOutput::Journald if audit.file.is_some() => {
+                    return Err(VtrunkdError::InvalidConfig(
+                        "logging.audit file is only used with output file".to_string(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(hooks) = &config.hooks {
+        let actions = [
+            &hooks.on_link_down,
+            &hooks.on_link_up,
+            &hooks.on_all_links_down,
+        ];
+        for action in actions.into_iter().flatten().flatten() {
+            match (&action.command, &action.url) {
+                (Some(command), None) if !command.trim().is_empty() => {}
+                (None, Some(url)) => {
+                    crate::hooks::parse_http_url(url)?;
+                }
+                _ => {
+                    return Err(VtrunkdError::InvalidConfig(
+                        "Each hook must set exactly one of command or url".to_string(),
+                    ));
+                }
+            }
+        }
+        let commands = [
+            &hooks.pre_up,
+            &hooks.post_up,
+            &hooks.pre_down,
+            &hooks.post_down,
+        ];
+        if commands
+            .into_iter()
+            .flatten()
+            .flatten()
+            .any(|command| command.trim().is_empty())
+        {
+            return Err(VtrunkdError::InvalidConfig(
+                "pre_up, post_up, pre_down and post_down commands must not be empty".to_string(),
+            ));
+        }
+    }
+
+    let mut names = std::collections::HashSet::new();
+    for (index, link) in config.wireguard.links.iter().enumerate() {
+        // Control commands, the HTTP API path and D-Bus all address a link by this name.
+        let name = link_name(link, index);
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '/') {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "WireGuard link name {:?} must be non-empty, without spaces or '/'",
+                name
+            )));
+        }
+        if !names.insert(name) {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "Duplicate WireGuard link name: {}",
+                link_name(link, index)
+            )));
+        }
+    }
+
+    if let Some(runtime) = &config.runtime {
+        if runtime.workers == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "Runtime workers must be greater than 0".to_string(),
+            ));
+        }
+        if runtime.pin_cpus.as_ref().is_some_and(Vec::is_empty) {
+            return Err(VtrunkdError::InvalidConfig(
+                "Runtime pin_cpus must list at least one CPU".to_string(),
+            ));
+        }
+    }
+
+    for link in &config.wireguard.links {
+        if let Some(weight) = link.weight {
+            if weight == 0 {
+                return Err(VtrunkdError::InvalidConfig(
+                    "WireGuard link weight must be greater than 0".to_string(),
+                ));
+            }
+        }
+        if link.socket_recv_buffer == Some(0) || link.socket_send_buffer == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "WireGuard link socket buffer sizes must be greater than 0".to_string(),
+            ));
+        }
+        if link.receive_sockets == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "WireGuard link receive_sockets must be greater than 0".to_string(),
+            ));
+        }
+        if link.dscp.is_some_and(|dscp| dscp > 63) {
+            return Err(VtrunkdError::InvalidConfig(
+                "WireGuard link dscp must be between 0 and 63".to_string(),
+            ));
+        }
+        if link.ttl == Some(0) {
+            return Err(VtrunkdError::InvalidConfig(
+                "WireGuard link ttl must be between 1 and 255".to_string(),
+            ));
+        }
+        if let Some(sources) = &link.allowed_sources {
+            if sources.is_empty() {
+                return Err(VtrunkdError::InvalidConfig(
+                    "WireGuard link allowed_sources must not be empty; omit it to allow any source"
+                        .to_string(),
+                ));
+            }
+            AllowedSources::parse(sources)?;
+        }
+    }
+
+    if let Some(policies) = &config.policies {
+        for policy in policies {
+            let known = config
+                .wireguard
+                .links
+                .iter()
+                .enumerate()
+                .any(|(index, link)| link_name(link, index) == policy.link);
+            if !known {
+                return Err(VtrunkdError::InvalidConfig(format!(
+                    "Policy for unknown link {}",
+                    policy.link
+                )));
+            }
+        }
+        Schedule::parse(policies)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bonding_mode_aliases_parse() {
+        let aggregate: BondingMode = serde_yaml::from_str("bonding").unwrap();
+        assert_eq!(aggregate, BondingMode::Aggregate);
+
+        let aggregate2: BondingMode = serde_yaml::from_str("bonded").unwrap();
+        assert_eq!(aggregate2, BondingMode::Aggregate);
+
+        let redundant: BondingMode = serde_yaml::from_str("redundant").unwrap();
+        assert_eq!(redundant, BondingMode::Redundant);
+    }
+
+    #[test]
+    fn effective_config_fills_defaults_and_redacts_keys() {
+        let yaml = r#"
+network:
+  mtu: 1420
+  buffer_size: 65536
+wireguard:
+  private_key: "c2VjcmV0"
+  peer_public_key: "cGVlcg=="
+  preshared_key: "cHNr"
+  links:
+    - endpoint: "example.com:51820"
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let mut effective = config.effective();
+        effective.redact_secrets();
+
+        assert_eq!(effective.network.batch_size, Some(DEFAULT_BATCH_SIZE));
+        let wg = &effective.wireguard;
+        assert_eq!(wg.bonding_mode, Some(BondingMode::Aggregate));
+        assert_eq!(wg.error_backoff_secs, Some(DEFAULT_ERROR_BACKOFF_SECS));
+        assert_eq!(wg.links[0].name.as_deref(), Some("link-0"));
+        assert_eq!(effective.control_socket(), Some(DEFAULT_CONTROL_SOCKET));
+        assert_eq!(effective.sandbox, Some(SandboxMode::Off));
+
+        let output = serde_yaml::to_string(&effective).unwrap();
+        assert!(!output.contains("c2VjcmV0"));
+        assert!(!output.contains("cHNr"));
+        assert!(output.contains("cGVlcg=="));
+    }
+
+    #[test]
+    fn config_rejects_unknown_fields() {
+        let yaml = r#"
+network:
+  mtu: 1420
+  buffer_size: 65536
+  extra: 123
+wireguard:
+  private_key: "key"
+  peer_public_key: "peer"
+  links:
+    - endpoint: "example.com:51820"
+"#;
+        let parsed: Result<Config, _> = serde_yaml::from_str(yaml);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn validate_config_rejects_timeout_le_interval() {
+        let mut config = Config::default();
+        config.wireguard.health_check_interval_ms = Some(1000);
+        config.wireguard.health_check_timeout_ms = Some(1000);
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_rejects_timeout_le_default_interval() {
+        let mut config = Config::default();
+        config.wireguard.health_check_interval_ms = None;
+        config.wireguard.health_check_timeout_ms = Some(DEFAULT_HEALTH_INTERVAL_MS);
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_rejects_buffer_smaller_than_mtu() {
+        let mut config = Config::default();
+        config.network.mtu = 1500;
+        config.network.buffer_size = 1000;
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_checks_allowed_sources() {
+        let mut config = Config::default();
+        config.wireguard.links[0].allowed_sources = Some(vec!["198.51.100.0/24".to_string()]);
+        assert!(validate_config(&config).is_ok());
+        for bad in [vec![], vec!["198.51.100.0/40".to_string()]] {
+            config.wireguard.links[0].allowed_sources = Some(bad);
+            assert!(validate_config(&config).is_err());
+        }
+    }
+
+    #[test]
+    fn validate_config_checks_the_audit_log() {
+        let mut config = Config::default();
+        let logging = config.logging.get_or_insert_with(LoggingConfig::default);
+        logging.audit = Some(AuditConfig {
+            output: None,
+            file: Some("/var/log/vtrunkd/audit.log".to_string()),
+        });
+        assert!(validate_config(&config).is_ok());
+        let audit = |output, file: Option<&str>| AuditConfig {
+            output,
+            file: file.map(str::to_string),
+        };
+        for (audit, valid) in [
+            (audit(Some(AuditOutput::Journald), None), true),
+            (audit(None, None), false),
+            (audit(Some(AuditOutput::File), Some("")), false),
+            (audit(Some(AuditOutput::Journald), Some("audit.log")), false),
+        ] {
+            config.logging.as_mut().unwrap().audit = Some(audit);
+            assert_eq!(validate_config(&config).is_ok(), valid);
+        }
+    }
+
+    #[test]
+    fn validate_config_rejects_zero_handshake_source_limit() {
+        let mut config = Config::default();
+        config.wireguard.handshake_rate_limit = Some(0);
+        config.wireguard.handshake_source_limit = Some(1);
+        assert!(validate_config(&config).is_ok());
+        config.wireguard.handshake_source_limit = Some(0);
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn validate_config_rejects_an_empty_links_down_buffer() {
+        let mut config = Config::default();
+        config.wireguard.links_down = Some(LinksDownPolicy::Buffer);
+        config.wireguard.links_down_buffer_packets = Some(64);
+        assert!(validate_config(&config).is_ok());
+        config.wireguard.links_down_buffer_secs = Some(0);
+        assert!(validate_config(&config).is_err());
+        config.wireguard.links_down_buffer_secs = None;
+        config.wireguard.links_down_buffer_packets = Some(0);
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn validate_config_rejects_zero_bandwidth_limits() {
+        let mut config = Config::default();
+        config.wireguard.send_limit_kbit = Some(50_000);
+        config.wireguard.receive_limit_kbit = Some(10_000);
+        assert!(validate_config(&config).is_ok());
+        config.wireguard.receive_limit_kbit = Some(0);
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn validate_config_rejects_bad_tun_queues() {
+        let mut config = Config::default();
+        config.network.tun_queues = Some(0);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+        config.network.tun_queues = Some(4);
+        config.network.tun_fd = Some(3);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn validate_config_rejects_bad_crypto_workers() {
+        let mut config = Config::default();
+        config.wireguard.crypto_workers = Some(4);
+        assert!(validate_config(&config).is_ok());
+        for workers in [0, MAX_CRYPTO_WORKERS + 1] {
+            config.wireguard.crypto_workers = Some(workers);
+            assert!(matches!(
+                validate_config(&config),
+                Err(VtrunkdError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_config_rejects_negative_tun_fd() {
+        let mut config = Config::default();
+        config.network.tun_fd = Some(-1);
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_rejects_duplicate_link_names() {
+        let mut config = Config::default();
+        let link = config.wireguard.links[0].clone();
+        config.wireguard.links.push(link);
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_rejects_unaddressable_link_names() {
+        let mut config = Config::default();
+        for bad in ["", "lte 1", "wan\t2", "usb/0"] {
+            config.wireguard.links[0].name = Some(bad.to_string());
+            assert!(
+                matches!(
+                    validate_config(&config),
+                    Err(VtrunkdError::InvalidConfig(_))
+                ),
+                "accepted {:?}",
+                bad
+            );
+        }
+        config.wireguard.links[0].name = Some("lte-1".to_string());
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn validate_config_checks_tunnel_addresses() {
+        let mut config = Config::default();
+        config.network.addresses = Some(vec![
+            "10.20.0.2/24".to_string(),
+            "fd00:10::2/64".to_string(),
+        ]);
+        assert!(validate_config(&config).is_ok());
+        assert_eq!(
+            parse_cidr("fd00:10::2/64").unwrap(),
+            ("fd00:10::2".parse().unwrap(), 64)
+        );
+
+        for bad in ["fd00:10::2", "10.20.0.2/33", "fd00::1/129", "lan/24"] {
+            config.network.addresses = Some(vec![bad.to_string()]);
+            assert!(validate_config(&config).is_err(), "accepted {}", bad);
+        }
+        config.network.addresses = None;
+        config.network.address = Some("fd00:10::2".to_string());
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn validate_config_requires_a_name_to_attach() {
+        let mut config = Config::default();
+        config.network.interface_mode = Some(InterfaceMode::Attach);
+        assert!(validate_config(&config).is_err());
+        config.network.interface = Some("vtrunk0".to_string());
+        assert!(validate_config(&config).is_ok());
+        config.network.address = Some("10.10.0.2".to_string());
+        assert!(validate_config(&config).is_err());
+        config.network.address = None;
+        config.network.persistent = Some(true);
+        assert!(validate_config(&config).is_err());
+        config.network.interface_mode = None;
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn validate_config_rejects_ambiguous_hooks() {
+        let config = Config {
+            hooks: Some(HooksConfig {
+                on_link_down: Some(vec![HookAction {
+                    command: Some("logger link down".to_string()),
+                    url: Some("http://alerts.local/hook".to_string()),
+                }]),
+                ..HooksConfig::default()
+            }),
+            ..Config::default()
+        };
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_config_limits_stock_wireguard_peers() {
+        let mut config = Config::default();
+        config.wireguard.peer_kind = Some(PeerKind::Wireguard);
+        config.wireguard.health_check_timeout_ms = None;
+        assert!(validate_config(&config).is_ok());
+
+        config.wireguard.health_check_timeout_ms = Some(5000);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+
+        config.wireguard.health_check_timeout_ms = None;
+        config.wireguard.compression = Some(true);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+
+        config.wireguard.compression = None;
+        config.wireguard.crypto_workers = Some(4);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+
+        config.wireguard.crypto_workers = None;
+        let mut link = config.wireguard.links[0].clone();
+        link.name = Some("second".to_string());
+        config.wireguard.links.push(link);
+        assert!(matches!(
+            validate_config(&config),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn validate_config_checks_policies() {
+        let mut config = Config {
+            policies: Some(vec![LinkPolicy {
+                link: "link-0".to_string(),
+                from: "00:00".to_string(),
+                to: "06:00".to_string(),
+                days: None,
+                state: LinkAdminState::Down,
+            }]),
+            ..Config::default()
+        };
+        assert!(validate_config(&config).is_ok());
+        config.policies.as_mut().unwrap()[0].to = "6am".to_string();
+        assert!(validate_config(&config).is_err());
+        config.policies.as_mut().unwrap()[0].to = "06:00".to_string();
+        config.policies.as_mut().unwrap()[0].link = "lte".to_string();
+        assert!(validate_config(&config).is_err());
+
+        let yaml = "link: link-0\nfrom: \"09:00\"\nto: \"17:00\"\ndays: [mon, fri]\nstate: drain\n";
+        let policy: LinkPolicy = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(policy.days, Some(vec![Weekday::Mon, Weekday::Fri]));
+        assert_eq!(policy.state, LinkAdminState::Drain);
+    }
+
+    #[test]
+    fn validate_config_rejects_mtu_too_large() {
+        let mut config = Config::default();
+        config.network.mtu = (u16::MAX as u32) + 1;
+        let result = validate_config(&config);
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keys_come_inline_or_from_one_credential() {
+        let mut config = Config::default();
+        config.wireguard.private_key.clear();
+        assert!(validate_config(&config).is_err());
+        config.wireguard.private_key_credential = Some("vtrunkd.private_key".to_string());
+        assert!(validate_config(&config).is_ok());
+        assert!(!config.wireguard.has_inline_keys());
+        let output = serde_yaml::to_string(&config).unwrap();
+        assert!(!output.contains("private_key:"));
+
+        config.wireguard.preshared_key = Some("cHNr".to_string());
+        config.wireguard.preshared_key_credential = Some("vtrunkd.psk".to_string());
+        assert!(validate_config(&config).is_err());
+        config.wireguard.preshared_key = None;
+        for bad in ["", "../etc/shadow", ".."] {
+            config.wireguard.preshared_key_credential = Some(bad.to_string());
+            assert!(validate_config(&config).is_err(), "accepted {:?}", bad);
+        }
+
+        config.wireguard.private_key = "c2VjcmV0".to_string();
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn credentials_are_read_from_the_credentials_directory() {
+        let dir = std::env::temp_dir().join(format!("vtrunkd-creds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("vtrunkd.private_key"), "c2VjcmV0\n").unwrap();
+        // No other test touches this variable.
+        std::env::set_var("CREDENTIALS_DIRECTORY", &dir);
+
+        let mut wg = Config::default().wireguard;
+        wg.private_key.clear();
+        wg.private_key_credential = Some("vtrunkd.private_key".to_string());
+        wg.preshared_key_credential = Some("vtrunkd.psk".to_string());
+        assert_eq!(wg.load_private_key().unwrap().as_str(), "c2VjcmV0");
+        assert!(wg.load_preshared_key().is_err());
+
+        std::env::remove_var("CREDENTIALS_DIRECTORY");
+        assert!(matches!(
+            wg.load_private_key(),
+            Err(VtrunkdError::InvalidConfig(msg)) if msg.contains("LoadCredential=")
+        ));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_config_passes_the_key_permission_check() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("vtrunkd-keys-{}.yaml", std::process::id()));
+        generate_default_config(&path).unwrap();
+        assert!(check_key_file_permissions(&path).is_ok());
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(
+            check_key_file_permissions(&path),
+            Err(VtrunkdError::InvalidConfig(msg)) if msg.contains("644")
+        ));
+        let _ = std::fs::remove_file(path);
+    }
+}
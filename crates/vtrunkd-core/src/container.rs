@@ -0,0 +1,221 @@
+//! `vtrunkd --container`: the daemon as a container entrypoint. The configuration comes
+//! from the environment rather than a file: either whole, base64-encoded YAML (or UCI) in
+//! `VTRUNKD_CONFIG_B64`, or built from the individual variables below over the defaults
+//! of `vtrunkd config`, enough for a server provisioned as a small OCI image:
+//!
+//! | Variable | Sets |
+//! | --- | --- |
+//! | `VTRUNKD_PRIVATE_KEY` | `wireguard.private_key` (required) |
+//! | `VTRUNKD_PEER_PUBLIC_KEY` | `wireguard.peer_public_key` (required) |
+//! | `VTRUNKD_PRESHARED_KEY` | `wireguard.preshared_key` |
+//! | `VTRUNKD_LINKS` | Comma-separated link `bind` addresses, one link each (required) |
+//! | `VTRUNKD_ENDPOINTS` | Comma-separated `endpoint`s for those links, in order |
+//! | `VTRUNKD_BONDING_MODE` | `wireguard.bonding_mode` |
+//! | `VTRUNKD_INTERFACE`, `VTRUNKD_ADDRESS`, `VTRUNKD_NETMASK`, `VTRUNKD_MTU` | The same `network` keys |
+//! | `VTRUNKD_HEALTH` | `control.health`, e.g. `0.0.0.0:8081` |
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::config::{self, BondingMode, Config, ControlConfig, NetworkConfig, WireGuardLinkConfig};
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+pub const CONFIG_B64_VAR: &str = "VTRUNKD_CONFIG_B64";
+
+/// The configuration from the environment, read through `var` (`std::env::var` outside
+/// tests), and validated as a file would be.
+pub fn config_from_env(var: impl Fn(&str) -> Option<String>) -> VtrunkdResult<Config> {
+    if let Some(encoded) = var(CONFIG_B64_VAR) {
+        let decoded = general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| invalid(format!("{} is not base64: {}", CONFIG_B64_VAR, e)))?;
+        let text = String::from_utf8(decoded)
+            .map_err(|_| invalid(format!("{} does not decode to UTF-8", CONFIG_B64_VAR)))?;
+        return config::parse_document(&text);
+    }
+
+    let required = |name: &str| {
+        var(name).ok_or_else(|| {
+            invalid(format!(
+                "{} is not set; set it, or the whole configuration in {}",
+                name, CONFIG_B64_VAR
+            ))
+        })
+    };
+    let mut config = Config::default();
+    let wireguard = &mut config.wireguard;
+    wireguard.private_key = required("VTRUNKD_PRIVATE_KEY")?;
+    wireguard.peer_public_key = required("VTRUNKD_PEER_PUBLIC_KEY")?;
+    wireguard.preshared_key = var("VTRUNKD_PRESHARED_KEY");
+    if let Some(mode) = var("VTRUNKD_BONDING_MODE") {
+        let mode = serde_yaml::from_str::<BondingMode>(&mode)
+            .map_err(|_| invalid(format!("VTRUNKD_BONDING_MODE {:?} is not a mode", mode)))?;
+        wireguard.bonding_mode = Some(mode);
+    }
+
+    let binds = list(&required("VTRUNKD_LINKS")?);
+    let endpoints = var("VTRUNKD_ENDPOINTS").map(|endpoints| list(&endpoints));
+    if endpoints
+        .as_ref()
+        .is_some_and(|endpoints| endpoints.len() != binds.len())
+    {
+        return Err(invalid(
+            "VTRUNKD_ENDPOINTS must list one endpoint per VTRUNKD_LINKS entry".to_string(),
+        ));
+    }
+    let template = wireguard.links[0].clone();
+    wireguard.links = binds
+        .into_iter()
+        .enumerate()
+        .map(|(index, bind)| WireGuardLinkConfig {
+            name: None,
+            bind: Some(bind),
+            endpoint: endpoints.as_ref().map(|endpoints| endpoints[index].clone()),
+            ..template.clone()
+        })
+        .collect();
+
+    let network = &mut config.network;
+    if let Some(interface) = var("VTRUNKD_INTERFACE") {
+        network.interface = Some(interface);
+    }
+    if let Some(address) = var("VTRUNKD_ADDRESS") {
+        network.address = Some(address);
+    }
+    if let Some(netmask) = var("VTRUNKD_NETMASK") {
+        network.netmask = Some(netmask);
+    }
+    if let Some(mtu) = var("VTRUNKD_MTU") {
+        network.mtu = mtu
+            .parse()
+            .map_err(|_| invalid(format!("VTRUNKD_MTU {:?} is not a number", mtu)))?;
+    }
+    if let Some(health) = var("VTRUNKD_HEALTH") {
+        config
+            .control
+            .get_or_insert_with(ControlConfig::default)
+            .health = Some(health);
+    }
+
+    config::validate_config(&config)?;
+    Ok(config)
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn invalid(message: String) -> VtrunkdError {
+    VtrunkdError::InvalidConfig(message)
+}
+
+/// Fails early, saying what the container lacks, when the TUN device cannot be created:
+/// `/dev/net/tun` must be passed in and `CAP_NET_ADMIN` granted, the one capability the
+/// daemon needs. A TUN fd handed over or a device attached to need neither.
+#[cfg(target_os = "linux")]
+pub fn check_tun_access(network: &NetworkConfig) -> VtrunkdResult<()> {
+    use crate::config::InterfaceMode;
+
+    if network.tun_fd.is_some() || network.interface_mode == Some(InterfaceMode::Attach) {
+        return Ok(());
+    }
+    if !std::path::Path::new("/dev/net/tun").exists() {
+        return Err(VtrunkdError::PermissionDenied(
+            "/dev/net/tun is missing; run the container with --device /dev/net/tun".to_string(),
+        ));
+    }
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    if !has_net_admin(&status) {
+        return Err(VtrunkdError::PermissionDenied(
+            "creating the TUN device needs CAP_NET_ADMIN; run the container with \
+             --cap-add NET_ADMIN (in Kubernetes, securityContext.capabilities.add: [NET_ADMIN])"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_tun_access(_network: &NetworkConfig) -> VtrunkdResult<()> {
+    Ok(())
+}
+
+/// Whether the effective capabilities in `/proc/self/status` include `CAP_NET_ADMIN`.
+#[cfg(target_os = "linux")]
+fn has_net_admin(status: &str) -> bool {
+    const CAP_NET_ADMIN: u32 = 12;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .is_some_and(|mask| mask & (1 << CAP_NET_ADMIN) != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const KEY: &str = "YNqHbfBQKaGvzefSSEufkdzOPR1AQsGcPfeTkg5Y4lU=";
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn builds_the_config_from_variables_or_takes_it_whole() {
+        let config = config_from_env(env(&[
+            ("VTRUNKD_PRIVATE_KEY", KEY),
+            ("VTRUNKD_PEER_PUBLIC_KEY", KEY),
+            ("VTRUNKD_LINKS", "0.0.0.0:51820, 0.0.0.0:51821"),
+            ("VTRUNKD_BONDING_MODE", "redundant"),
+            ("VTRUNKD_MTU", "1380"),
+            ("VTRUNKD_HEALTH", "0.0.0.0:8081"),
+        ]))
+        .unwrap();
+        assert_eq!(config.wireguard.links.len(), 2);
+        assert_eq!(
+            config.wireguard.links[1].bind.as_deref(),
+            Some("0.0.0.0:51821")
+        );
+        assert!(config.wireguard.links[1].endpoint.is_none());
+        assert_eq!(config.wireguard.bonding_mode, Some(BondingMode::Redundant));
+        assert_eq!(config.network.mtu, 1380);
+        assert_eq!(config.health_bind(), Some("0.0.0.0:8081"));
+
+        let missing = config_from_env(env(&[("VTRUNKD_PRIVATE_KEY", KEY)])).unwrap_err();
+        assert!(missing.to_string().contains("VTRUNKD_PEER_PUBLIC_KEY"));
+        assert!(config_from_env(env(&[
+            ("VTRUNKD_PRIVATE_KEY", KEY),
+            ("VTRUNKD_PEER_PUBLIC_KEY", KEY),
+            ("VTRUNKD_LINKS", "0.0.0.0:51820"),
+            ("VTRUNKD_ENDPOINTS", "a.example:1,b.example:2"),
+        ]))
+        .is_err());
+
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        let encoded = general_purpose::STANDARD.encode(yaml);
+        let whole = config_from_env(env(&[(CONFIG_B64_VAR, &encoded)])).unwrap();
+        assert_eq!(whole.wireguard.links.len(), 2);
+        assert!(config_from_env(env(&[(CONFIG_B64_VAR, "not base64!")])).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_cap_net_admin_from_the_effective_set() {
+        assert!(has_net_admin("Name:\tvtrunkd\nCapEff:\t0000000000001000\n"));
+        assert!(has_net_admin("CapEff:\t000001ffffffffff\n"));
+        assert!(!has_net_admin(
+            "CapInh:\t0000000000001000\nCapEff:\t0000000000000400\n"
+        ));
+        assert!(!has_net_admin("Name:\tvtrunkd\n"));
+    }
+}
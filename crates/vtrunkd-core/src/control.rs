@@ -0,0 +1,826 @@
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::fmt;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::unistd::{geteuid, Group, User};
+#[cfg(target_os = "linux")]
+use nix::unistd::{getgrouplist, Gid, Uid};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+#[cfg(unix)]
+use tracing::{debug, info, warn};
+
+use crate::audit;
+use crate::capture::{CaptureTarget, DEFAULT_CAPTURE_MAX_MB, DEFAULT_CAPTURE_SECS};
+use crate::config::ControlConfig;
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::EventSender;
+
+#[cfg(target_os = "linux")]
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/vtrunkd.sock";
+#[cfg(not(target_os = "linux"))]
+pub const DEFAULT_CONTROL_SOCKET: &str = "/var/run/vtrunkd.sock";
+
+/// Longest command line the daemon reads; a capture path or handover path fits easily.
+#[cfg(unix)]
+const MAX_LINE: u64 = 8 * 1024;
+/// Longest reply line a client reads; stats for many links stay well below it.
+#[cfg(unix)]
+const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+/// A daemon that has not answered a command by then is treated as hung.
+#[cfg(unix)]
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Administrative state of a link, set at runtime over the control socket.
+///
+/// `Drain` stops scheduling data on the link but keeps handshakes, keepalives
+/// and health probes flowing so it can be returned to service immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkAdminState {
+    #[default]
+    Up,
+    Down,
+    Drain,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    SetLinkState {
+        name: String,
+        state: LinkAdminState,
+    },
+    /// Opens a fresh socket for the link, after its device or address came back.
+    RebindLink {
+        name: String,
+    },
+    Stats,
+    /// Keeps the connection open and streams events as JSON lines.
+    Events,
+    CaptureStart {
+        target: CaptureTarget,
+        seconds: u64,
+        max_mb: u64,
+    },
+    CaptureStop,
+    /// Sends the TUN device and link sockets to the Unix socket at `path`, then shuts down.
+    Handover {
+        path: String,
+    },
+    /// The WireGuard UAPI `get` body; only the UAPI socket sends it, as the reply spans lines.
+    Uapi,
+    /// Replaces this side's static key pair and hands the new public key to the peer.
+    RotateKey,
+    /// Accepts the new public key the peer offered, in base64.
+    AcceptKey {
+        public_key: String,
+    },
+}
+
+/// Who may send commands that change the tunnel; anyone who can connect may read.
+/// Unrestricted unless `control.write_users` or `control.write_groups` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteAccess {
+    restricted: bool,
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<Result<String, String>>,
+}
+
+impl LinkAdminState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkAdminState::Up => "up",
+            LinkAdminState::Down => "down",
+            LinkAdminState::Drain => "drain",
+        }
+    }
+
+    pub fn carries_data(self) -> bool {
+        self == LinkAdminState::Up
+    }
+
+    pub fn carries_control(self) -> bool {
+        self != LinkAdminState::Down
+    }
+}
+
+impl fmt::Display for LinkAdminState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LinkAdminState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "up" => Ok(LinkAdminState::Up),
+            "down" => Ok(LinkAdminState::Down),
+            "drain" => Ok(LinkAdminState::Drain),
+            other => Err(format!("Unknown link state: {}", other)),
+        }
+    }
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["link", "rebind", name] => Ok(ControlCommand::RebindLink {
+                name: name.to_string(),
+            }),
+            ["link", state, name] => Ok(ControlCommand::SetLinkState {
+                name: name.to_string(),
+                state: state.parse()?,
+            }),
+            ["stats"] => Ok(ControlCommand::Stats),
+            ["events"] => Ok(ControlCommand::Events),
+            ["capture", "start", target, limits @ ..] if limits.len() <= 2 => {
+                let limit = |index: usize, default: u64| match limits.get(index) {
+                    Some(value) => value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|value| *value > 0)
+                        .ok_or_else(|| format!("Invalid capture limit: {}", value)),
+                    None => Ok(default),
+                };
+                Ok(ControlCommand::CaptureStart {
+                    target: target.parse()?,
+                    seconds: limit(0, DEFAULT_CAPTURE_SECS)?,
+                    max_mb: limit(1, DEFAULT_CAPTURE_MAX_MB)?,
+                })
+            }
+            ["capture", "stop"] => Ok(ControlCommand::CaptureStop),
+            ["rotate-key"] => Ok(ControlCommand::RotateKey),
+            ["accept-key", public_key] => Ok(ControlCommand::AcceptKey {
+                public_key: public_key.to_string(),
+            }),
+            ["handover", path] => Ok(ControlCommand::Handover {
+                path: path.to_string(),
+            }),
+            [] => Err("Empty command".to_string()),
+            _ => Err(format!("Unknown command: {}", line.trim())),
+        }
+    }
+
+    /// Whether the command changes the tunnel rather than only reading it.
+    pub fn changes_state(&self) -> bool {
+        !matches!(
+            self,
+            ControlCommand::Stats | ControlCommand::Events | ControlCommand::Uapi
+        )
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            ControlCommand::SetLinkState { name, state } => format!("link {} {}", state, name),
+            ControlCommand::RebindLink { name } => format!("link rebind {}", name),
+            ControlCommand::Stats => "stats".to_string(),
+            ControlCommand::Events => "events".to_string(),
+            ControlCommand::CaptureStart {
+                target,
+                seconds,
+                max_mb,
+            } => format!("capture start {} {} {}", target, seconds, max_mb),
+            ControlCommand::CaptureStop => "capture stop".to_string(),
+            ControlCommand::Handover { path } => format!("handover {}", path),
+            ControlCommand::Uapi => "uapi".to_string(),
+            ControlCommand::RotateKey => "rotate-key".to_string(),
+            ControlCommand::AcceptKey { public_key } => format!("accept-key {}", public_key),
+        }
+    }
+}
+
+impl WriteAccess {
+    /// Resolves the configured user and group names.
+    pub fn from_config(control: Option<&ControlConfig>) -> VtrunkdResult<Self> {
+        let Some(control) = control else {
+            return Ok(WriteAccess::default());
+        };
+        if control.write_users.is_none() && control.write_groups.is_none() {
+            return Ok(WriteAccess::default());
+        }
+        let uids = control
+            .write_users
+            .iter()
+            .flatten()
+            .map(|user| match user.parse::<u32>() {
+                Ok(uid) => Ok(uid),
+                Err(_) => user_id(user)?.ok_or_else(|| {
+                    VtrunkdError::InvalidConfig(format!(
+                        "Unknown control write_users user {}",
+                        user
+                    ))
+                }),
+            })
+            .collect::<VtrunkdResult<_>>()?;
+        let gids = control
+            .write_groups
+            .iter()
+            .flatten()
+            .map(|group| match group.parse::<u32>() {
+                Ok(gid) => Ok(gid),
+                Err(_) => group_id(group)?.ok_or_else(|| {
+                    VtrunkdError::InvalidConfig(format!(
+                        "Unknown control write_groups group {}",
+                        group
+                    ))
+                }),
+            })
+            .collect::<VtrunkdResult<_>>()?;
+        Ok(WriteAccess {
+            restricted: true,
+            uids,
+            gids,
+        })
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.restricted
+    }
+
+    /// Whether a peer with this uid and primary gid, if known, may change the tunnel.
+    /// Supplementary groups are only looked up when neither of those is listed.
+    #[cfg(unix)]
+    fn permits(&self, peer: Option<(u32, u32)>) -> bool {
+        if !self.restricted {
+            return true;
+        }
+        let Some((uid, gid)) = peer else {
+            return false;
+        };
+        if uid == 0 || uid == geteuid().as_raw() || self.uids.contains(&uid) {
+            return true;
+        }
+        if self.gids.contains(&gid) {
+            return true;
+        }
+        supplementary_groups(uid, gid)
+            .iter()
+            .any(|gid| self.gids.contains(gid))
+    }
+}
+
+#[cfg(unix)]
+fn user_id(name: &str) -> VtrunkdResult<Option<u32>> {
+    Ok(User::from_name(name)?.map(|user| user.uid.as_raw()))
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> VtrunkdResult<Option<u32>> {
+    Ok(Group::from_name(name)?.map(|group| group.gid.as_raw()))
+}
+
+/// Without a user database only numeric ids can be listed.
+#[cfg(not(unix))]
+fn user_id(_name: &str) -> VtrunkdResult<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+fn group_id(_name: &str) -> VtrunkdResult<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn supplementary_groups(uid: u32, gid: u32) -> Vec<u32> {
+    let Ok(Some(user)) = User::from_uid(Uid::from_raw(uid)) else {
+        return Vec::new();
+    };
+    let Ok(name) = CString::new(user.name) else {
+        return Vec::new();
+    };
+    getgrouplist(&name, Gid::from_raw(gid))
+        .map(|groups| groups.into_iter().map(Gid::as_raw).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn supplementary_groups(_uid: u32, _gid: u32) -> Vec<u32> {
+    Vec::new()
+}
+
+/// Binds the control socket and forwards parsed commands to the tunnel loop. With
+/// restricted `access` the socket is opened to all users, who may then only read.
+#[cfg(unix)]
+pub fn spawn_server(
+    path: &Path,
+    tx: mpsc::Sender<ControlRequest>,
+    events: EventSender,
+    access: WriteAccess,
+) -> VtrunkdResult<()> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(VtrunkdError::AlreadyRunning(format!(
+                "control socket {:?} is in use",
+                path
+            )));
+        }
+        // A leftover socket from an unclean exit would make bind fail.
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if access.is_restricted() {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+    }
+    info!("Control socket listening on {:?}", path);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let tx = tx.clone();
+                    let events = events.clone();
+                    let access = access.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream, tx, events, &access).await {
+                            debug!("Control connection error: {}", err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    warn!("Control socket accept error: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn handle_connection(
+    stream: UnixStream,
+    tx: mpsc::Sender<ControlRequest>,
+    events: EventSender,
+    access: &WriteAccess,
+) -> VtrunkdResult<()> {
+    let cred = stream.peer_cred().ok();
+    let peer = cred.map(|cred| (cred.uid(), cred.gid()));
+    let source = match cred {
+        Some(cred) => match cred.pid() {
+            Some(pid) => format!("control uid={} pid={}", cred.uid(), pid),
+            None => format!("control uid={}", cred.uid()),
+        },
+        None => "control".to_string(),
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let line = match read_line(&mut reader, MAX_LINE).await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                writer
+                    .write_all(format!("error {}\n", err).as_bytes())
+                    .await?;
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let result = match ControlCommand::parse(&line) {
+            Ok(ControlCommand::Events) => {
+                writer.write_all(b"ok subscribed\n").await?;
+                return stream_events(&mut writer, events).await;
+            }
+            Ok(command) if command.changes_state() && !access.permits(peer) => {
+                audit::record(
+                    &source,
+                    "control_denied",
+                    serde_json::json!({ "command": command.to_line() }),
+                );
+                Err("Permission denied: this user may only read the tunnel state".to_string())
+            }
+            Ok(command) => dispatch(&tx, command, &source).await,
+            Err(err) => Err(err),
+        };
+        let response = match result {
+            Ok(message) => format!("ok {}\n", message),
+            Err(message) => format!("error {}\n", message),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Reads one line of at most `limit` bytes without its line ending, or `None` at the end
+/// of the stream. A longer line is an `InvalidData` error, so it is never buffered whole.
+#[cfg(unix)]
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limit: u64,
+) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = (&mut *reader).take(limit).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    } else if read as u64 == limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Line longer than {} bytes", limit),
+        ));
+    }
+    Ok(Some(line))
+}
+
+#[cfg(unix)]
+async fn stream_events(
+    writer: &mut tokio::net::unix::OwnedWriteHalf,
+    events: EventSender,
+) -> VtrunkdResult<()> {
+    let mut rx = events.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => {
+                let line = serde_json::to_string(&event)
+                    .map_err(|e| VtrunkdError::Control(e.to_string()))?;
+                writer.write_all(format!("{}\n", line).as_bytes()).await?;
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                debug!("Control event subscriber lagged by {} events", skipped);
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Hands `command` to the tunnel loop and waits for its answer; commands that change
+/// state are audited with `source`.
+pub(crate) async fn dispatch(
+    tx: &mpsc::Sender<ControlRequest>,
+    command: ControlCommand,
+    source: &str,
+) -> Result<String, String> {
+    let audited = command.changes_state().then(|| command.clone());
+    let (reply, response) = oneshot::channel();
+    let result = match tx.send(ControlRequest { command, reply }).await {
+        Ok(()) => response
+            .await
+            .unwrap_or_else(|_| Err("Tunnel dropped the request".to_string())),
+        Err(_) => Err("Tunnel is not running".to_string()),
+    };
+    if let Some(command) = audited {
+        audit::command(source, &command, &result);
+    }
+    result
+}
+
+/// Sends a single command to a running daemon and returns its reply.
+#[cfg(unix)]
+pub async fn send_command(path: &Path, command: &ControlCommand) -> VtrunkdResult<String> {
+    tokio::time::timeout(RESPONSE_TIMEOUT, exchange(path, command))
+        .await
+        .map_err(|_| no_reply(path))?
+}
+
+#[cfg(unix)]
+async fn exchange(path: &Path, command: &ControlCommand) -> VtrunkdResult<String> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| VtrunkdError::Control(format!("Failed to connect to {:?}: {}", path, e)))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", command.to_line()).as_bytes())
+        .await?;
+    writer.shutdown().await?;
+
+    let response = read_line(&mut BufReader::new(reader), MAX_RESPONSE)
+        .await?
+        .unwrap_or_default();
+    let response = response.trim_end();
+
+    if let Some(message) = response.strip_prefix("ok") {
+        Ok(message.trim_start().to_string())
+    } else if let Some(message) = response.strip_prefix("error") {
+        Err(VtrunkdError::Control(message.trim_start().to_string()))
+    } else {
+        Err(VtrunkdError::Control(format!(
+            "Unexpected control response: {:?}",
+            response
+        )))
+    }
+}
+
+#[cfg(unix)]
+fn no_reply(path: &Path) -> VtrunkdError {
+    VtrunkdError::Control(format!(
+        "No reply from {:?} within {:?}",
+        path, RESPONSE_TIMEOUT
+    ))
+}
+
+/// Subscribes to the daemon's event stream, calling `on_event` with each JSON line.
+#[cfg(unix)]
+pub async fn watch_events<F>(path: &Path, mut on_event: F) -> VtrunkdResult<()>
+where
+    F: FnMut(&str),
+{
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| VtrunkdError::Control(format!("Failed to connect to {:?}: {}", path, e)))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", ControlCommand::Events.to_line()).as_bytes())
+        .await?;
+
+    let mut reader = BufReader::new(reader);
+    let subscribed = tokio::time::timeout(RESPONSE_TIMEOUT, read_line(&mut reader, MAX_RESPONSE))
+        .await
+        .map_err(|_| no_reply(path))?;
+    match subscribed? {
+        Some(line) if line.starts_with("ok") => {}
+        Some(line) => return Err(VtrunkdError::Control(line)),
+        None => return Err(VtrunkdError::Control("Connection closed".to_string())),
+    }
+    while let Some(line) = read_line(&mut reader, MAX_RESPONSE).await? {
+        on_event(&line);
+    }
+    Ok(())
+}
+
+/// The control socket is a Unix domain socket; other platforms have none to offer.
+#[cfg(not(unix))]
+pub fn spawn_server(
+    path: &Path,
+    _tx: mpsc::Sender<ControlRequest>,
+    _events: EventSender,
+    _access: WriteAccess,
+) -> VtrunkdResult<()> {
+    Err(unsupported(path))
+}
+
+#[cfg(not(unix))]
+pub async fn send_command(path: &Path, _command: &ControlCommand) -> VtrunkdResult<String> {
+    Err(unsupported(path))
+}
+
+#[cfg(not(unix))]
+pub async fn watch_events<F>(path: &Path, _on_event: F) -> VtrunkdResult<()>
+where
+    F: FnMut(&str),
+{
+    Err(unsupported(path))
+}
+
+#[cfg(not(unix))]
+fn unsupported(path: &Path) -> VtrunkdError {
+    VtrunkdError::Control(format!(
+        "Control socket {:?} needs Unix domain sockets, which this platform lacks",
+        path
+    ))
+}
+
+pub fn socket_path(configured: Option<&str>) -> PathBuf {
+    PathBuf::from(configured.unwrap_or(DEFAULT_CONTROL_SOCKET))
+}
+
+/// Directory for capture files started over the control socket.
+pub fn capture_dir(configured: Option<&str>) -> PathBuf {
+    PathBuf::from(configured.unwrap_or(crate::capture::DEFAULT_CAPTURE_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_link_command() {
+        let command = ControlCommand::parse("link drain lte").expect("parse");
+        assert_eq!(
+            command,
+            ControlCommand::SetLinkState {
+                name: "lte".to_string(),
+                state: LinkAdminState::Drain,
+            }
+        );
+        assert_eq!(ControlCommand::parse(&command.to_line()), Ok(command));
+
+        let rebind = ControlCommand::parse("link rebind wan").expect("parse");
+        assert_eq!(
+            rebind,
+            ControlCommand::RebindLink {
+                name: "wan".to_string(),
+            }
+        );
+        assert!(rebind.changes_state());
+        assert_eq!(ControlCommand::parse(&rebind.to_line()), Ok(rebind));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_state() {
+        assert!(ControlCommand::parse("link sideways lte").is_err());
+        assert!(ControlCommand::parse("link up").is_err());
+        assert!(ControlCommand::parse("").is_err());
+        assert!(ControlCommand::parse("stats extra").is_err());
+        assert_eq!(ControlCommand::parse("stats"), Ok(ControlCommand::Stats));
+        assert_eq!(
+            ControlCommand::parse("rotate-key"),
+            Ok(ControlCommand::RotateKey)
+        );
+        let accept = ControlCommand::parse("accept-key a2V5").expect("parse");
+        assert!(accept.changes_state());
+        assert_eq!(ControlCommand::parse(&accept.to_line()), Ok(accept));
+        assert!(ControlCommand::parse("accept-key").is_err());
+    }
+
+    #[test]
+    fn parse_capture_command_fills_defaults() {
+        assert_eq!(
+            ControlCommand::parse("capture start links 30"),
+            Ok(ControlCommand::CaptureStart {
+                target: CaptureTarget::Links,
+                seconds: 30,
+                max_mb: DEFAULT_CAPTURE_MAX_MB,
+            })
+        );
+        assert!(ControlCommand::parse("capture start tun 0").is_err());
+        assert!(ControlCommand::parse("capture start wifi").is_err());
+        assert_eq!(
+            ControlCommand::parse("capture stop"),
+            Ok(ControlCommand::CaptureStop)
+        );
+    }
+
+    #[test]
+    fn write_access_limits_changes_to_listed_users_and_groups() {
+        assert!(WriteAccess::default().permits(None));
+        let control = ControlConfig {
+            write_users: Some(vec!["1234".to_string()]),
+            write_groups: Some(vec!["root".to_string(), "5678".to_string()]),
+            ..ControlConfig::default()
+        };
+        let access = WriteAccess::from_config(Some(&control)).unwrap();
+        assert!(access.is_restricted());
+        assert!(access.permits(Some((0, 100))));
+        assert!(access.permits(Some((1234, 100))));
+        assert!(access.permits(Some((4321, 0))));
+        assert!(access.permits(Some((4321, 5678))));
+        assert!(!access.permits(Some((4321, 100))));
+        assert!(!access.permits(None));
+
+        let readers_only = ControlConfig {
+            write_users: Some(Vec::new()),
+            ..ControlConfig::default()
+        };
+        let access = WriteAccess::from_config(Some(&readers_only)).unwrap();
+        assert!(!access.permits(Some((4321, 100))));
+        let unknown = ControlConfig {
+            write_groups: Some(vec!["no-such-vtrunkd-group".to_string()]),
+            ..ControlConfig::default()
+        };
+        assert!(WriteAccess::from_config(Some(&unknown)).is_err());
+        assert!(!ControlCommand::Stats.changes_state());
+        assert!(ControlCommand::RotateKey.changes_state());
+    }
+
+    #[tokio::test]
+    async fn server_round_trip() {
+        let path = std::env::temp_dir().join(format!("vtrunkd-test-{}.sock", std::process::id()));
+        let (tx, mut rx) = mpsc::channel(4);
+        spawn_server(&path, tx, crate::events::channel(), WriteAccess::default())
+            .expect("bind control socket");
+
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let reply = match request.command {
+                    ControlCommand::SetLinkState { name, state } if name == "wifi" => {
+                        Ok(format!("{} {}", name, state))
+                    }
+                    ControlCommand::SetLinkState { name, .. } => {
+                        Err(format!("Unknown link: {}", name))
+                    }
+                    _ => Ok("{}".to_string()),
+                };
+                let _ = request.reply.send(reply);
+            }
+        });
+
+        let ok = send_command(
+            &path,
+            &ControlCommand::SetLinkState {
+                name: "wifi".to_string(),
+                state: LinkAdminState::Down,
+            },
+        )
+        .await
+        .expect("command succeeds");
+        assert_eq!(ok, "wifi down");
+
+        let err = send_command(
+            &path,
+            &ControlCommand::SetLinkState {
+                name: "lte".to_string(),
+                state: LinkAdminState::Up,
+            },
+        )
+        .await;
+        assert!(matches!(err, Err(VtrunkdError::Control(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn oversized_request_is_rejected() {
+        let path =
+            std::env::temp_dir().join(format!("vtrunkd-test-long-{}.sock", std::process::id()));
+        let (tx, _rx) = mpsc::channel(4);
+        spawn_server(&path, tx, crate::events::channel(), WriteAccess::default())
+            .expect("bind control socket");
+
+        let mut stream = UnixStream::connect(&path).await.expect("connect");
+        let endless = "A".repeat(4 * MAX_LINE as usize);
+        stream.write_all(endless.as_bytes()).await.expect("write");
+
+        let mut reply = String::new();
+        BufReader::new(&mut stream)
+            .read_line(&mut reply)
+            .await
+            .expect("reply");
+        assert!(reply.starts_with("error Line longer than"), "{}", reply);
+        // The connection is closed; unread bytes may turn that into a reset.
+        let mut rest = Vec::new();
+        assert!(!matches!(stream.read_to_end(&mut rest).await, Ok(n) if n > 0));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn live_socket_is_not_replaced() {
+        let path =
+            std::env::temp_dir().join(format!("vtrunkd-test-live-{}.sock", std::process::id()));
+        let (tx, _rx) = mpsc::channel(4);
+        spawn_server(
+            &path,
+            tx.clone(),
+            crate::events::channel(),
+            WriteAccess::default(),
+        )
+        .expect("bind control socket");
+
+        let second = spawn_server(&path, tx, crate::events::channel(), WriteAccess::default());
+        assert!(matches!(second, Err(VtrunkdError::AlreadyRunning(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn events_are_streamed_to_subscribers() {
+        use crate::events::{self, TunnelEvent};
+
+        let path =
+            std::env::temp_dir().join(format!("vtrunkd-test-events-{}.sock", std::process::id()));
+        let (tx, _rx) = mpsc::channel(4);
+        let events = events::channel();
+        spawn_server(&path, tx, events.clone(), WriteAccess::default())
+            .expect("bind control socket");
+
+        let (line_tx, mut line_rx) = mpsc::unbounded_channel();
+        let watch_path = path.clone();
+        tokio::spawn(async move {
+            let _ = watch_events(&watch_path, |line| {
+                let _ = line_tx.send(line.to_string());
+            })
+            .await;
+        });
+
+        // Wait until the subscription is registered before publishing.
+        while events.receiver_count() == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        events::emit(&events, TunnelEvent::HandshakeComplete);
+
+        let line = line_rx.recv().await.expect("event line");
+        assert_eq!(line, r#"{"event":"handshake_complete"}"#);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
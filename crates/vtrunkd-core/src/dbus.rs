@@ -0,0 +1,181 @@
+//! `org.vtrunkd.Manager` on the system bus, so NetworkManager dispatcher scripts, desktop
+//! extensions and the GUI can drive the daemon without its control socket or a child
+//! process. Access is governed by the bus policy in `dist/dbus/`.
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, info};
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::{fdo, interface, Connection};
+
+use crate::audit;
+use crate::control::{self, ControlCommand, ControlRequest, LinkAdminState};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::{EventSender, TunnelEvent};
+
+pub const BUS_NAME: &str = "org.vtrunkd.Manager";
+pub const OBJECT_PATH: &str = "/org/vtrunkd/Manager";
+
+struct Manager {
+    tx: mpsc::Sender<ControlRequest>,
+    stop: Arc<Notify>,
+}
+
+impl Manager {
+    async fn dispatch(&self, command: ControlCommand, source: &str) -> fdo::Result<String> {
+        control::dispatch(&self.tx, command, source)
+            .await
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "org.vtrunkd.Manager")]
+impl Manager {
+    /// Succeeds once the daemon runs; with bus activation the call itself starts it.
+    async fn start(&self) {}
+
+    /// Says goodbye to the peer and shuts the daemon down.
+    async fn stop(&self, #[zbus(header)] header: Header<'_>) {
+        info!("Stop requested over D-Bus");
+        audit::record(&source(&header), "stop", serde_json::json!({}));
+        self.stop.notify_one();
+    }
+
+    /// Link and tunnel counters as JSON, as `vtrunkd stats` prints them.
+    async fn status(&self) -> fdo::Result<String> {
+        self.dispatch(ControlCommand::Stats, "dbus").await
+    }
+
+    /// Sets a link to "up", "down" or "drain".
+    async fn set_link_state(
+        &self,
+        name: String,
+        state: String,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<String> {
+        let state: LinkAdminState = state.parse().map_err(fdo::Error::InvalidArgs)?;
+        self.dispatch(
+            ControlCommand::SetLinkState { name, state },
+            &source(&header),
+        )
+        .await
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
+    /// Each link and tunnel event as the JSON object `vtrunkd events` prints.
+    #[zbus(signal)]
+    async fn event(emitter: &SignalEmitter<'_>, event: &str) -> zbus::Result<()>;
+}
+
+/// The caller's unique bus name, for the audit log.
+fn source(header: &Header<'_>) -> String {
+    match header.sender() {
+        Some(sender) => format!("dbus {}", sender),
+        None => "dbus".to_string(),
+    }
+}
+
+fn bus_error(err: zbus::Error) -> VtrunkdError {
+    VtrunkdError::Control(format!("D-Bus: {}", err))
+}
+
+/// Claims [`BUS_NAME`] on the system bus and forwards tunnel events as `Event` signals.
+/// `stop` is notified when a client calls `Stop`.
+pub async fn spawn_server(
+    tx: mpsc::Sender<ControlRequest>,
+    events: EventSender,
+    stop: Arc<Notify>,
+) -> VtrunkdResult<()> {
+    let connection = zbus::connection::Builder::system()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Manager { tx, stop }))
+        .map_err(bus_error)?
+        .build()
+        .await
+        .map_err(bus_error)?;
+    info!("D-Bus service {} registered", BUS_NAME);
+
+    // Subscribe before returning so no event after startup is missed.
+    let rx = events.subscribe();
+    tokio::spawn(forward_events(connection, rx));
+    Ok(())
+}
+
+async fn forward_events(connection: Connection, mut rx: broadcast::Receiver<TunnelEvent>) {
+    let Ok(emitter) = SignalEmitter::new(&connection, OBJECT_PATH) else {
+        return;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(event) => {
+                let Ok(json) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if let Err(err) = Manager::event(&emitter, &json).await {
+                    debug!("D-Bus event signal failed: {}", err);
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                debug!("D-Bus event forwarder lagged by {} events", skipped);
+            }
+            // The connection, and with it the bus name, goes away with the tunnel.
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+    use zbus::connection::Builder;
+    use zbus::Guid;
+
+    #[tokio::test]
+    async fn methods_reach_the_tunnel_loop() {
+        let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+        let stop = Arc::new(Notify::new());
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let _ = request.reply.send(Ok(request.command.to_line()));
+            }
+        });
+
+        // A peer-to-peer connection stands in for the system bus.
+        let (server, client) = UnixStream::pair().unwrap();
+        let manager = Manager {
+            tx,
+            stop: stop.clone(),
+        };
+        let server = Builder::unix_stream(server)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at(OBJECT_PATH, manager)
+            .unwrap()
+            .build();
+        let client = Builder::unix_stream(client).p2p().build();
+        let (_server, client) = tokio::try_join!(server, client).unwrap();
+        let proxy = zbus::Proxy::new(&client, BUS_NAME, OBJECT_PATH, BUS_NAME)
+            .await
+            .unwrap();
+
+        let status: String = proxy.call("Status", &()).await.unwrap();
+        assert_eq!(status, "stats");
+        let reply: String = proxy.call("SetLinkState", &("lte", "drain")).await.unwrap();
+        assert_eq!(reply, "link drain lte");
+        let refused: zbus::Result<String> = proxy.call("SetLinkState", &("lte", "sideways")).await;
+        assert!(refused.is_err());
+
+        let stopped = stop.notified();
+        proxy.call::<_, _, ()>("Stop", &()).await.unwrap();
+        stopped.await;
+    }
+}
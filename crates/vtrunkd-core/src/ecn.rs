@@ -0,0 +1,116 @@
+//! RFC 6040 ECN handling between the tunnelled packets and the outer UDP datagrams, as the
+//! kernel's tunnels do it: the inner marking is copied out on the way in, and congestion
+//! the path signalled on the outer header is folded back in on the way out.
+
+pub const NOT_ECT: u8 = 0b00;
+pub const ECT_1: u8 = 0b01;
+pub const ECT_0: u8 = 0b10;
+pub const CE: u8 = 0b11;
+
+/// ECN field of a plaintext IPv4 or IPv6 packet.
+pub fn inner(packet: &[u8]) -> u8 {
+    match packet {
+        [first, second, ..] if first >> 4 == 4 => second & 0b11,
+        [first, second, ..] if first >> 4 == 6 => (second >> 4) & 0b11,
+        _ => NOT_ECT,
+    }
+}
+
+/// Outer ECN for an inner one. CE becomes ECT(0) so a later hop can still mark it.
+pub fn encapsulate(inner: u8) -> u8 {
+    if inner == CE {
+        ECT_0
+    } else {
+        inner
+    }
+}
+
+/// Applies the outer marking to a decrypted packet. Returns `false` when the packet must
+/// be dropped: the path marked CE on a flow that cannot be told about congestion.
+pub fn decapsulate(packet: &mut [u8], outer: u8) -> bool {
+    let current = inner(packet);
+    let updated = match (outer, current) {
+        (CE, NOT_ECT) => return false,
+        (CE, _) => CE,
+        (ECT_1, ECT_0) => ECT_1,
+        _ => return true,
+    };
+    if updated != current {
+        set_inner(packet, updated);
+    }
+    true
+}
+
+fn set_inner(packet: &mut [u8], ecn: u8) {
+    match packet[0] >> 4 {
+        4 if packet.len() >= 20 => {
+            let old = u16::from_be_bytes([packet[0], packet[1]]);
+            packet[1] = (packet[1] & !0b11) | ecn;
+            let new = u16::from_be_bytes([packet[0], packet[1]]);
+            // Incremental header checksum update (RFC 1624, eqn. 3).
+            let checksum = u16::from_be_bytes([packet[10], packet[11]]);
+            let mut sum = u32::from(!checksum) + u32::from(!old) + u32::from(new);
+            while sum > 0xffff {
+                sum = (sum & 0xffff) + (sum >> 16);
+            }
+            packet[10..12].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+        }
+        6 => packet[1] = (packet[1] & !0b0011_0000) | (ecn << 4),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_checksum(header: &[u8]) -> u16 {
+        let mut sum: u32 = header
+            .chunks(2)
+            .enumerate()
+            .filter(|(index, _)| *index != 5)
+            .map(|(_, word)| u32::from(u16::from_be_bytes([word[0], word[1]])))
+            .sum();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    fn ipv4(ecn: u8) -> Vec<u8> {
+        let mut packet = vec![0x45, 0xb8 | ecn, 0, 20, 0, 0, 0x40, 0, 64, 17, 0, 0];
+        packet.extend_from_slice(&[10, 10, 0, 1, 10, 10, 0, 2]);
+        let checksum = ipv4_checksum(&packet);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn congestion_on_the_path_reaches_the_inner_packet() {
+        let mut packet = ipv4(ECT_0);
+        assert!(decapsulate(&mut packet, CE));
+        assert_eq!(inner(&packet), CE);
+        // DSCP is kept and the header checksum still holds.
+        assert_eq!(packet[1] >> 2, 46);
+        assert_eq!(
+            u16::from_be_bytes([packet[10], packet[11]]),
+            ipv4_checksum(&packet)
+        );
+
+        assert!(!decapsulate(&mut ipv4(NOT_ECT), CE));
+        let mut untouched = ipv4(ECT_0);
+        assert!(decapsulate(&mut untouched, ECT_0));
+        assert_eq!(untouched, ipv4(ECT_0));
+
+        let mut ipv6 = vec![0x6b, 0x80 | (ECT_1 << 4), 0, 0];
+        assert!(decapsulate(&mut ipv6, CE));
+        assert_eq!((inner(&ipv6), ipv6[1] & 0x0f, ipv6[0]), (CE, 0, 0x6b));
+    }
+
+    #[test]
+    fn encapsulation_copies_all_but_ce() {
+        assert_eq!(encapsulate(inner(&ipv4(ECT_1))), ECT_1);
+        assert_eq!(encapsulate(inner(&ipv4(CE))), ECT_0);
+        assert_eq!(encapsulate(inner(&[])), NOT_ECT);
+    }
+}
@@ -0,0 +1,125 @@
+use std::io;
+use thiserror::Error;
+
+pub type VtrunkdResult<T> = Result<T, VtrunkdError>;
+
+#[derive(Error, Debug)]
+pub enum VtrunkdError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Network error: {0}")]
+    Network(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("System call failed: {0}")]
+    SystemCall(String),
+
+    #[error("Resource not found: {0}")]
+    NotFound(String),
+
+    #[error("Control error: {0}")]
+    Control(String),
+
+    #[error("Another instance is already running: {0}")]
+    AlreadyRunning(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+}
+
+/// Process exit codes from sysexits(3), grouped so supervisors can tell an
+/// unrecoverable setup problem from a failure worth restarting.
+pub mod exit {
+    /// Configuration file missing.
+    pub const NO_INPUT: u8 = 66;
+    /// Control socket or other local service unusable.
+    pub const UNAVAILABLE: u8 = 69;
+    /// A system call failed.
+    pub const OS_ERROR: u8 = 71;
+    /// Another instance holds the PID file or control socket.
+    pub const CANT_CREATE: u8 = 73;
+    pub const IO_ERROR: u8 = 74;
+    /// Transient network failure; restarting may help.
+    pub const TEMP_FAIL: u8 = 75;
+    /// Missing privileges (TUN creation, privileged ports, PID file directory).
+    pub const NO_PERMISSION: u8 = 77;
+    /// Invalid configuration; restarting will not help.
+    pub const CONFIG: u8 = 78;
+}
+
+impl VtrunkdError {
+    /// Stable numeric code for logs and scripts. Codes are never reused or renumbered.
+    pub fn code(&self) -> u16 {
+        match self {
+            VtrunkdError::Io(err) if err.kind() == io::ErrorKind::PermissionDenied => 9,
+            VtrunkdError::Io(_) => 1,
+            VtrunkdError::Config(_) => 2,
+            VtrunkdError::Network(_) => 3,
+            VtrunkdError::InvalidConfig(_) => 4,
+            VtrunkdError::SystemCall(_) => 5,
+            VtrunkdError::NotFound(_) => 6,
+            VtrunkdError::Control(_) => 7,
+            VtrunkdError::AlreadyRunning(_) => 8,
+            VtrunkdError::PermissionDenied(_) => 9,
+        }
+    }
+
+    /// Process exit code for this error; see [`exit`].
+    pub fn exit_code(&self) -> u8 {
+        match self.code() {
+            1 => exit::IO_ERROR,
+            2 | 4 => exit::CONFIG,
+            3 => exit::TEMP_FAIL,
+            5 => exit::OS_ERROR,
+            6 => exit::NO_INPUT,
+            7 => exit::UNAVAILABLE,
+            8 => exit::CANT_CREATE,
+            9 => exit::NO_PERMISSION,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<nix::Error> for VtrunkdError {
+    fn from(err: nix::Error) -> Self {
+        match err {
+            nix::Error::EPERM | nix::Error::EACCES => {
+                VtrunkdError::PermissionDenied(err.to_string())
+            }
+            _ => VtrunkdError::SystemCall(err.to_string()),
+        }
+    }
+}
+
+impl From<serde_yaml::Error> for VtrunkdError {
+    fn from(err: serde_yaml::Error) -> Self {
+        VtrunkdError::Config(format!("YAML parsing error: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_and_permission_errors_get_distinct_exit_codes() {
+        let config = VtrunkdError::InvalidConfig("mtu must be greater than 0".to_string());
+        let network = VtrunkdError::Network("link receivers stopped".to_string());
+        let denied = VtrunkdError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(config.exit_code(), exit::CONFIG);
+        assert_eq!(network.exit_code(), exit::TEMP_FAIL);
+        assert_eq!(denied.code(), 9);
+        assert_eq!(denied.exit_code(), exit::NO_PERMISSION);
+        assert_eq!(
+            VtrunkdError::from(nix::Error::EACCES).exit_code(),
+            exit::NO_PERMISSION
+        );
+    }
+}
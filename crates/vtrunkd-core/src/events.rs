@@ -0,0 +1,96 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Capacity of the event broadcast; slow subscribers skip ahead when they lag.
+const EVENT_CHANNEL_DEPTH: usize = 256;
+
+pub type EventSender = broadcast::Sender<TunnelEvent>;
+
+/// Link and tunnel state changes published to control-socket subscribers.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TunnelEvent {
+    LinkUp {
+        link: String,
+    },
+    LinkDown {
+        link: String,
+        reason: String,
+    },
+    LinkDegraded {
+        link: String,
+        silent_ms: u64,
+    },
+    LinkAdmin {
+        link: String,
+        state: String,
+    },
+    EndpointChanged {
+        link: String,
+        endpoint: String,
+    },
+    HandshakeComplete,
+    Failover {
+        from: String,
+        to: String,
+    },
+    TunnelRestart {
+        attempt: u32,
+        error: String,
+    },
+    /// The TUN device and link sockets were passed to an upgraded process.
+    HandedOver,
+    /// The peer offered a new static key, which `vtrunkd accept-key` accepts.
+    KeyOffered {
+        peer_public_key: String,
+    },
+    /// A static key was rotated; the base64 public keys now in use on each side.
+    KeyRotated {
+        public_key: String,
+        peer_public_key: String,
+    },
+}
+
+pub fn channel() -> EventSender {
+    broadcast::channel(EVENT_CHANNEL_DEPTH).0
+}
+
+/// Publishes an event; having no subscribers is not an error.
+pub fn emit(events: &EventSender, event: TunnelEvent) {
+    let _ = events.send(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_serialize_with_tag() {
+        let event = TunnelEvent::Failover {
+            from: "wifi".to_string(),
+            to: "lte".to_string(),
+        };
+        let json = serde_json::to_string(&event).expect("serialize");
+        assert_eq!(json, r#"{"event":"failover","from":"wifi","to":"lte"}"#);
+    }
+
+    #[tokio::test]
+    async fn emit_reaches_subscribers() {
+        let events = channel();
+        emit(&events, TunnelEvent::HandshakeComplete);
+
+        let mut rx = events.subscribe();
+        emit(
+            &events,
+            TunnelEvent::LinkUp {
+                link: "wifi".to_string(),
+            },
+        );
+        assert_eq!(
+            rx.recv().await.expect("event"),
+            TunnelEvent::LinkUp {
+                link: "wifi".to_string()
+            }
+        );
+    }
+}
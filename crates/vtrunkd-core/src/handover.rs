@@ -0,0 +1,241 @@
+use std::io::{IoSlice, IoSliceMut};
+use std::net::UdpSocket;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use serde::{Deserialize, Serialize};
+use tokio::net::UnixListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::info;
+
+use crate::control::{self, ControlCommand};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::TunnelEvent;
+
+/// Upper bound on descriptors in one handover: the TUN queues plus one per link.
+const MAX_HANDOVER_FDS: usize = 128;
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sent alongside the descriptors; the first `tun_queues` descriptors are the TUN
+/// device's queues and the rest are the link sockets in this order.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// Missing from older daemons, which hand over a single queue.
+    #[serde(default = "one_queue")]
+    tun_queues: usize,
+    links: Vec<String>,
+}
+
+fn one_queue() -> usize {
+    1
+}
+
+/// What a new process takes over from the running one.
+pub struct Inherited {
+    /// Every queue of the TUN device, the first one first.
+    pub tun_fds: Vec<RawFd>,
+    pub links: Vec<(String, UdpSocket)>,
+}
+
+/// Where the upgrading process waits for the descriptors.
+fn handover_path(control_path: &Path) -> PathBuf {
+    let mut path = control_path.as_os_str().to_owned();
+    path.push(".handover");
+    PathBuf::from(path)
+}
+
+/// Sends the TUN queues and link sockets to the process listening at `path`.
+///
+/// The descriptors stay open here too; the caller shuts down once this succeeds.
+pub fn send(path: &Path, tun_fds: &[RawFd], links: &[(String, RawFd)]) -> VtrunkdResult<()> {
+    if tun_fds.len() + links.len() > MAX_HANDOVER_FDS {
+        return Err(VtrunkdError::Control(format!(
+            "Too many descriptors to hand over: {} TUN queues and {} links",
+            tun_fds.len(),
+            links.len()
+        )));
+    }
+    let manifest = Manifest {
+        tun_queues: tun_fds.len(),
+        links: links.iter().map(|(name, _)| name.clone()).collect(),
+    };
+    let manifest =
+        serde_json::to_vec(&manifest).map_err(|e| VtrunkdError::Control(e.to_string()))?;
+    let fds: Vec<RawFd> = tun_fds
+        .iter()
+        .copied()
+        .chain(links.iter().map(|(_, fd)| *fd))
+        .collect();
+
+    let stream = UnixStream::connect(path)?;
+    sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(&manifest)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Asks the daemon on `control_path` to hand over its TUN queues and link sockets.
+///
+/// The old daemon starts shutting down once the descriptors are sent, without
+/// saying goodbye to the peer, so traffic keeps flowing through this process.
+pub async fn receive(control_path: &Path) -> VtrunkdResult<Inherited> {
+    let path = handover_path(control_path);
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    let command = ControlCommand::Handover {
+        path: path.display().to_string(),
+    };
+    let (reply, accepted) = tokio::join!(
+        control::send_command(control_path, &command),
+        tokio::time::timeout(HANDOVER_TIMEOUT, listener.accept())
+    );
+    let _ = std::fs::remove_file(&path);
+    reply?;
+    let (stream, _) = accepted.map_err(|_| {
+        VtrunkdError::Control("Running daemon did not hand over its sockets".to_string())
+    })??;
+
+    // The old daemon replied only after sending, so the message is already queued.
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+    let inherited = read_handover(&stream)?;
+    info!(
+        "Took over TUN descriptors {:?} and {} link sockets",
+        inherited.tun_fds,
+        inherited.links.len()
+    );
+    Ok(inherited)
+}
+
+fn read_handover(stream: &UnixStream) -> VtrunkdResult<Inherited> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; MAX_HANDOVER_FDS]);
+    let (size, fds) = {
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let message = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        let mut fds = Vec::new();
+        for cmsg in message.cmsgs() {
+            if let ControlMessageOwned::ScmRights(received) = cmsg {
+                fds.extend(received);
+            }
+        }
+        (message.bytes, fds)
+    };
+
+    let manifest = match serde_json::from_slice::<Manifest>(&buf[..size]) {
+        Ok(manifest)
+            if manifest.tun_queues > 0
+                && manifest.tun_queues + manifest.links.len() == fds.len() =>
+        {
+            manifest
+        }
+        _ => {
+            for fd in fds {
+                let _ = nix::unistd::close(fd);
+            }
+            return Err(VtrunkdError::Control(
+                "Malformed handover from the running daemon".to_string(),
+            ));
+        }
+    };
+
+    // SAFETY: SCM_RIGHTS installed these descriptors in this process just now and
+    // nothing else refers to them.
+    let (tun_fds, link_fds) = fds.split_at(manifest.tun_queues);
+    let links = manifest
+        .links
+        .into_iter()
+        .zip(link_fds)
+        .map(|(name, fd)| (name, unsafe { UdpSocket::from_raw_fd(*fd) }))
+        .collect();
+    Ok(Inherited {
+        tun_fds: tun_fds.to_vec(),
+        links,
+    })
+}
+
+/// Resolves once this process has handed its sockets to an upgraded one.
+pub async fn handed_over(mut events: broadcast::Receiver<TunnelEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(TunnelEvent::HandedOver) => return,
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+/// Waits until the old daemon has released its control socket, or the grace period ends.
+pub async fn wait_for_exit(control_path: &Path, grace: Duration) {
+    let deadline = tokio::time::Instant::now() + grace;
+    while tokio::time::Instant::now() < deadline
+        && std::os::unix::net::UnixStream::connect(control_path).is_ok()
+    {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors_survive_the_trip() {
+        let path =
+            std::env::temp_dir().join(format!("vtrunkd-test-handover-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let queues = [
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        ];
+        let link = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        send(
+            &path,
+            &[queues[0].as_raw_fd(), queues[1].as_raw_fd()],
+            &[("wifi".to_string(), link.as_raw_fd())],
+        )
+        .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let inherited = read_handover(&stream).unwrap();
+
+        assert_eq!(inherited.tun_fds.len(), 2);
+        assert_ne!(inherited.tun_fds[0], queues[0].as_raw_fd());
+        assert_eq!(inherited.links.len(), 1);
+        let (name, socket) = &inherited.links[0];
+        assert_eq!(name, "wifi");
+        assert_eq!(socket.local_addr().unwrap(), link.local_addr().unwrap());
+
+        for fd in inherited.tun_fds {
+            let _ = nix::unistd::close(fd);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn older_manifests_carry_one_queue() {
+        let manifest: Manifest = serde_json::from_str(r#"{"links":["wifi"]}"#).unwrap();
+        assert_eq!(manifest.tun_queues, 1);
+    }
+
+    #[test]
+    fn handover_path_sits_next_to_control_socket() {
+        assert_eq!(
+            handover_path(Path::new("/run/vtrunkd.sock")),
+            PathBuf::from("/run/vtrunkd.sock.handover")
+        );
+    }
+}
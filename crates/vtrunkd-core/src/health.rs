@@ -0,0 +1,202 @@
+//! Unauthenticated `GET /healthz` and `GET /readyz` for orchestrators and load balancers
+//! (`control.health`), apart from the token-protected HTTP API. Both answer from the
+//! tunnel loop's `stats`, with 200 or 503 and a one-line JSON reason:
+//!
+//! - `/healthz`: the tunnel is running, so its TUN device is open and configured. A tunnel
+//!   waiting out a restart backoff, or a loop that does not answer in time, fails it.
+//! - `/readyz`: as `/healthz`, and at least one link is up, or idle waiting for its peer
+//!   to connect. Links down, drained or set down by an operator do not count.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::control::{self, ControlCommand, ControlRequest};
+use crate::error::VtrunkdResult;
+use crate::stats::StatsSnapshot;
+
+/// A client that has not sent its request line by then is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most read of a request: enough for the request line, which is all that is used.
+const MAX_REQUEST: u64 = 1024;
+/// How long the tunnel loop gets to answer before the check fails.
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Binds `bind` and answers health checks from the tunnel loop. Returns the bound address.
+pub fn spawn_server(bind: &str, tx: mpsc::Sender<ControlRequest>) -> VtrunkdResult<SocketAddr> {
+    let listener = std::net::TcpListener::bind(bind)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    let address = listener.local_addr()?;
+    info!("Health checks listening on {}", address);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream, &tx).await {
+                            debug!("Health check from {} failed: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    warn!("Health check accept error: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(address)
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    tx: &mpsc::Sender<ControlRequest>,
+) -> VtrunkdResult<()> {
+    let mut line = String::new();
+    let mut reader = BufReader::new((&mut stream).take(MAX_REQUEST));
+    match tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut line)).await {
+        Ok(read) => read?,
+        Err(_) => return Ok(()),
+    };
+    let mut parts = line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET" | "HEAD"), Some("/healthz")) => verdict(check(tx, false).await),
+        (Some("GET" | "HEAD"), Some("/readyz")) => verdict(check(tx, true).await),
+        (Some(_), Some("/healthz" | "/readyz")) => (405, reason("method not allowed")),
+        _ => (404, reason("not found")),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        match status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Service Unavailable",
+        },
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if !line.starts_with("HEAD ") {
+        stream.write_all(body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Why the tunnel is not healthy (or ready), if it is not.
+async fn check(tx: &mpsc::Sender<ControlRequest>, ready: bool) -> Result<(), String> {
+    let stats = tokio::time::timeout(
+        TUNNEL_TIMEOUT,
+        control::dispatch(tx, ControlCommand::Stats, "health"),
+    )
+    .await
+    .map_err(|_| "tunnel did not answer".to_string())??;
+    let stats: StatsSnapshot =
+        serde_json::from_str(&stats).map_err(|e| format!("unreadable stats: {}", e))?;
+    // Only the running loop reports link states; the supervisor's stand-in does not.
+    if stats.links.iter().all(|link| link.state.is_none()) {
+        return Err("tunnel is not running".to_string());
+    }
+    if ready
+        && !stats
+            .links
+            .iter()
+            .any(|link| matches!(link.state.as_deref(), Some("up" | "idle")))
+    {
+        return Err("no link is up".to_string());
+    }
+    Ok(())
+}
+
+fn verdict(result: Result<(), String>) -> (u16, String) {
+    match result {
+        Ok(()) => (200, serde_json::json!({ "status": "ok" }).to_string()),
+        Err(message) => (503, reason(&message)),
+    }
+}
+
+fn reason(message: &str) -> String {
+    serde_json::json!({ "status": "unavailable", "reason": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(address: SocketAddr, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn follows_the_tunnel_and_its_links() {
+        let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+        let (states_tx, mut states) = mpsc::channel::<Vec<Option<&str>>>(4);
+        tokio::spawn(async move {
+            let mut current = Vec::new();
+            while let Some(request) = rx.recv().await {
+                while let Ok(next) = states.try_recv() {
+                    current = next;
+                }
+                let links: Vec<_> = current
+                    .iter()
+                    .map(|state: &Option<&str>| serde_json::json!({ "state": state }))
+                    .collect();
+                let reply = serde_json::json!({ "links": links }).to_string();
+                assert!(matches!(request.command, ControlCommand::Stats));
+                let _ = request.reply.send(Ok(reply));
+            }
+        });
+        let address = spawn_server("127.0.0.1:0", tx).unwrap();
+
+        // Restarting: the supervisor answers without link states.
+        states_tx.send(vec![None, None]).await.unwrap();
+        let (status, body) = get(address, "/healthz").await;
+        assert_eq!(status, 503);
+        assert!(body.contains("not running"), "{}", body);
+
+        states_tx
+            .send(vec![Some("down"), Some("admin-down")])
+            .await
+            .unwrap();
+        assert_eq!(get(address, "/healthz").await.0, 200);
+        let (status, body) = get(address, "/readyz").await;
+        assert_eq!(status, 503);
+        assert!(body.contains("no link is up"), "{}", body);
+
+        states_tx
+            .send(vec![Some("down"), Some("idle")])
+            .await
+            .unwrap();
+        assert_eq!(
+            get(address, "/readyz").await,
+            (200, r#"{"status":"ok"}"#.to_string())
+        );
+        assert_eq!(get(address, "/metrics").await.0, 404);
+
+        // A request line that runs past the limit is answered at the limit, not waited on.
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(&[b'A'; MAX_REQUEST as usize])
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    }
+}
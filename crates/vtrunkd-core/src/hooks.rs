@@ -0,0 +1,405 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::config::{Config, HookAction, HooksConfig};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::TunnelEvent;
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HookKind {
+    LinkDown,
+    LinkUp,
+    AllLinksDown,
+}
+
+/// Tracks which links can carry data so "all links down" fires once per outage.
+struct LinkTracker {
+    total: usize,
+    unavailable: HashSet<String>,
+    all_down: bool,
+}
+
+impl HookKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            HookKind::LinkDown => "on_link_down",
+            HookKind::LinkUp => "on_link_up",
+            HookKind::AllLinksDown => "on_all_links_down",
+        }
+    }
+}
+
+impl LinkTracker {
+    fn new(total: usize) -> Self {
+        LinkTracker {
+            total,
+            unavailable: HashSet::new(),
+            all_down: false,
+        }
+    }
+
+    /// Applies an event and returns the hooks it triggers.
+    fn apply(&mut self, event: &TunnelEvent) -> Vec<HookKind> {
+        let mut triggered = Vec::new();
+        match event {
+            TunnelEvent::LinkDown { link, .. } => {
+                self.unavailable.insert(link.clone());
+                triggered.push(HookKind::LinkDown);
+            }
+            TunnelEvent::LinkUp { link } => {
+                self.unavailable.remove(link);
+                triggered.push(HookKind::LinkUp);
+            }
+            TunnelEvent::LinkAdmin { link, state } => {
+                if state == "up" {
+                    self.unavailable.remove(link);
+                } else {
+                    self.unavailable.insert(link.clone());
+                }
+            }
+            _ => return triggered,
+        }
+
+        let all_down = self.total > 0 && self.unavailable.len() >= self.total;
+        if all_down && !self.all_down {
+            triggered.push(HookKind::AllLinksDown);
+        }
+        self.all_down = all_down;
+        triggered
+    }
+}
+
+/// Runs configured hooks for link events published on `events`.
+pub fn spawn(config: &Config, mut events: broadcast::Receiver<TunnelEvent>) {
+    let Some(hooks) = config.hooks.clone() else {
+        return;
+    };
+    let mut tracker = LinkTracker::new(config.wireguard.links.len());
+
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Hook runner missed {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            for kind in tracker.apply(&event) {
+                let payload = match kind {
+                    HookKind::AllLinksDown => serde_json::json!({ "event": "all_links_down" }),
+                    _ => serde_json::to_value(&event).unwrap_or_default(),
+                };
+                for action in actions_for(&hooks, kind) {
+                    let action = action.clone();
+                    let payload = payload.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = run_action(kind, &action, &payload).await {
+                            warn!("Hook {} failed: {}", kind.as_str(), e);
+                        }
+                    });
+                }
+            }
+        }
+    });
+}
+
+fn actions_for(hooks: &HooksConfig, kind: HookKind) -> &[HookAction] {
+    let actions = match kind {
+        HookKind::LinkDown => &hooks.on_link_down,
+        HookKind::LinkUp => &hooks.on_link_up,
+        HookKind::AllLinksDown => &hooks.on_all_links_down,
+    };
+    actions.as_deref().unwrap_or_default()
+}
+
+async fn run_action(
+    kind: HookKind,
+    action: &HookAction,
+    payload: &serde_json::Value,
+) -> VtrunkdResult<()> {
+    let result = if let Some(command) = &action.command {
+        tokio::time::timeout(HOOK_TIMEOUT, run_command(kind, command, payload)).await
+    } else if let Some(url) = &action.url {
+        tokio::time::timeout(HOOK_TIMEOUT, post_json(url, payload)).await
+    } else {
+        return Ok(());
+    };
+    result.map_err(|_| VtrunkdError::Network("hook timed out".to_string()))?
+}
+
+async fn run_command(
+    kind: HookKind,
+    command: &str,
+    payload: &serde_json::Value,
+) -> VtrunkdResult<()> {
+    let field = |name: &str| payload.get(name).and_then(|v| v.as_str()).unwrap_or("");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("VTRUNKD_HOOK", kind.as_str())
+        .env("VTRUNKD_EVENT", field("event"))
+        .env("VTRUNKD_LINK", field("link"))
+        .env("VTRUNKD_REASON", field("reason"))
+        .env("VTRUNKD_EVENT_JSON", payload.to_string())
+        .kill_on_drop(true)
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(VtrunkdError::SystemCall(format!(
+            "hook command exited with {}",
+            status
+        )));
+    }
+    debug!("Hook {} ran {:?}", kind.as_str(), command);
+    Ok(())
+}
+
+/// Splits `http://host[:port][/path]` into the connect address, Host header and path.
+pub fn parse_http_url(url: &str) -> VtrunkdResult<(String, String, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        VtrunkdError::InvalidConfig(format!("Hook URL must start with http://: {}", url))
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Hook URL is missing a host: {}",
+            url
+        )));
+    }
+    let has_port = match authority.rfind(':') {
+        Some(index) => !authority[index..].contains(']'),
+        None => false,
+    };
+    let address = if has_port {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((address, authority.to_string(), path.to_string()))
+}
+
+async fn post_json(url: &str, payload: &serde_json::Value) -> VtrunkdResult<()> {
+    let (address, host, path) = parse_http_url(url)?;
+    let body = payload.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: vtrunkd/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+        body
+    );
+
+    let mut stream = TcpStream::connect(&address).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(0);
+    if !(200..300).contains(&status) {
+        return Err(VtrunkdError::Network(format!(
+            "hook POST to {} returned status {}",
+            url, status
+        )));
+    }
+    info!("Hook POST to {} delivered", url);
+    Ok(())
+}
+
+/// Points in the tunnel's life where the wg-quick-style commands run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    PreUp,
+    PostUp,
+    PreDown,
+    PostDown,
+}
+
+impl Lifecycle {
+    fn as_str(self) -> &'static str {
+        match self {
+            Lifecycle::PreUp => "pre_up",
+            Lifecycle::PostUp => "post_up",
+            Lifecycle::PreDown => "pre_down",
+            Lifecycle::PostDown => "post_down",
+        }
+    }
+
+    fn commands(self, hooks: &HooksConfig) -> &[String] {
+        let commands = match self {
+            Lifecycle::PreUp => &hooks.pre_up,
+            Lifecycle::PostUp => &hooks.post_up,
+            Lifecycle::PreDown => &hooks.pre_down,
+            Lifecycle::PostDown => &hooks.post_down,
+        };
+        commands.as_deref().unwrap_or_default()
+    }
+}
+
+/// Runs the `stage` commands one after another and stops at the first failure.
+/// `links` holds each link's name and state, as `vtrunkd links` shows them.
+pub async fn run_lifecycle(
+    config: &Config,
+    stage: Lifecycle,
+    interface: &str,
+    links: &[(String, &str)],
+) -> VtrunkdResult<()> {
+    let Some(hooks) = &config.hooks else {
+        return Ok(());
+    };
+    let network = &config.network;
+    let link_states = links
+        .iter()
+        .map(|(name, state)| format!("{}={}", name, state))
+        .collect::<Vec<_>>()
+        .join(" ");
+    for command in stage.commands(hooks) {
+        let command = command.replace("%i", interface);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("VTRUNKD_HOOK", stage.as_str())
+            .env("VTRUNKD_INTERFACE", interface)
+            .env("VTRUNKD_ADDRESS", network.address.as_deref().unwrap_or(""))
+            .env("VTRUNKD_NETMASK", network.netmask.as_deref().unwrap_or(""))
+            .env(
+                "VTRUNKD_DESTINATION",
+                network.destination.as_deref().unwrap_or(""),
+            )
+            .env("VTRUNKD_MTU", network.mtu.to_string())
+            .env("VTRUNKD_LINKS", &link_states)
+            .kill_on_drop(true)
+            .status();
+        let status = tokio::time::timeout(HOOK_TIMEOUT, status)
+            .await
+            .map_err(|_| VtrunkdError::Network(format!("{} hook timed out", stage.as_str())))??;
+        if !status.success() {
+            return Err(VtrunkdError::SystemCall(format!(
+                "{} command {:?} exited with {}",
+                stage.as_str(),
+                command,
+                status
+            )));
+        }
+        info!("Hook {} ran {:?}", stage.as_str(), command);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn down(link: &str) -> TunnelEvent {
+        TunnelEvent::LinkDown {
+            link: link.to_string(),
+            reason: "no rx".to_string(),
+        }
+    }
+
+    #[test]
+    fn all_links_down_fires_once_per_outage() {
+        let mut tracker = LinkTracker::new(2);
+        assert_eq!(tracker.apply(&down("wifi")), vec![HookKind::LinkDown]);
+        assert_eq!(
+            tracker.apply(&down("lte")),
+            vec![HookKind::LinkDown, HookKind::AllLinksDown]
+        );
+        assert_eq!(tracker.apply(&down("lte")), vec![HookKind::LinkDown]);
+        assert_eq!(
+            tracker.apply(&TunnelEvent::LinkUp {
+                link: "wifi".to_string()
+            }),
+            vec![HookKind::LinkUp]
+        );
+        assert_eq!(
+            tracker.apply(&down("wifi")),
+            vec![HookKind::LinkDown, HookKind::AllLinksDown]
+        );
+    }
+
+    #[tokio::test]
+    async fn lifecycle_commands_see_the_tunnel_and_stop_at_a_failure() {
+        let out = std::env::temp_dir().join(format!("vtrunkd-lifecycle-{}", std::process::id()));
+        let mut config = Config::default();
+        config.network.address = Some("10.10.0.2".to_string());
+        config.hooks = Some(HooksConfig {
+            post_up: Some(vec![
+                format!(
+                    "echo \"%i $VTRUNKD_HOOK $VTRUNKD_ADDRESS $VTRUNKD_LINKS\" > {}",
+                    out.display()
+                ),
+                "false".to_string(),
+                format!("echo never >> {}", out.display()),
+            ]),
+            ..HooksConfig::default()
+        });
+        let links = [("wifi".to_string(), "up"), ("lte".to_string(), "drain")];
+
+        let result = run_lifecycle(&config, Lifecycle::PostUp, "vtrunk0", &links).await;
+        assert!(matches!(result, Err(VtrunkdError::SystemCall(_))));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "vtrunk0 post_up 10.10.0.2 wifi=up lte=drain\n"
+        );
+        run_lifecycle(&config, Lifecycle::PreDown, "vtrunk0", &links)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(out);
+    }
+
+    #[test]
+    fn parse_http_url_defaults_port_and_path() {
+        let (address, host, path) = parse_http_url("http://alerts.local").unwrap();
+        assert_eq!(address, "alerts.local:80");
+        assert_eq!(host, "alerts.local");
+        assert_eq!(path, "/");
+
+        let (address, _, path) = parse_http_url("http://[::1]:8080/hooks/vtrunkd").unwrap();
+        assert_eq!(address, "[::1]:8080");
+        assert_eq!(path, "/hooks/vtrunkd");
+
+        assert!(parse_http_url("https://alerts.local").is_err());
+    }
+
+    #[tokio::test]
+    async fn post_json_delivers_payload() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let size = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..size]).to_string()
+        });
+
+        post_json(&url, &serde_json::json!({ "event": "all_links_down" }))
+            .await
+            .expect("hook delivered");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1"));
+        assert!(request.ends_with(r#"{"event":"all_links_down"}"#));
+    }
+}
@@ -0,0 +1,302 @@
+//! Follows the network devices links ride, so a modem that re-attaches or a DHCP lease
+//! with a new address does not leave a link on a dead socket. rtnetlink reports which
+//! device changed (Linux only); the tunnel loop decides which links to rebind.
+
+use std::net::IpAddr;
+#[cfg(unix)]
+use std::net::{SocketAddrV4, SocketAddrV6};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// Changes to devices within this long of each other are reported together, once the
+/// addresses have settled.
+#[cfg(target_os = "linux")]
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// The addresses `device` has now.
+pub(crate) fn device_addresses(device: &str) -> Vec<IpAddr> {
+    interface_addresses()
+        .into_iter()
+        .filter(|(name, _)| name == device)
+        .map(|(_, address)| address)
+        .collect()
+}
+
+/// The device that has `address`.
+pub(crate) fn device_of(address: IpAddr) -> Option<String> {
+    interface_addresses()
+        .into_iter()
+        .find(|(_, candidate)| *candidate == address)
+        .map(|(name, _)| name)
+}
+
+/// The device's interface index, which changes when the device is recreated.
+#[cfg(unix)]
+pub(crate) fn device_index(device: &str) -> Option<u32> {
+    nix::net::if_::if_nametoindex(device).ok()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device_index(_device: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn interface_addresses() -> Vec<(String, IpAddr)> {
+    let Ok(interfaces) = nix::ifaddrs::getifaddrs() else {
+        return Vec::new();
+    };
+    interfaces
+        .filter_map(|interface| {
+            let address = interface.address?;
+            let ip = if let Some(v4) = address.as_sockaddr_in() {
+                IpAddr::V4(*SocketAddrV4::from(*v4).ip())
+            } else {
+                IpAddr::V6(*SocketAddrV6::from(*address.as_sockaddr_in6()?).ip())
+            };
+            Some((interface.interface_name, ip))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn interface_addresses() -> Vec<(String, IpAddr)> {
+    Vec::new()
+}
+
+/// Sends the name of each device that came up, changed or got an address to `tx`, from a
+/// task in `tasks`.
+#[cfg(target_os = "linux")]
+pub(crate) fn spawn(tasks: &mut JoinSet<()>, tx: mpsc::Sender<String>) -> std::io::Result<()> {
+    let socket = linux::subscribe()?;
+    tasks.spawn(linux::watch(socket, tx));
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn spawn(_tasks: &mut JoinSet<()>, _tx: mpsc::Sender<String>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// A device a message is about, by name when the message carries it.
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+enum DeviceRef {
+    Name(String),
+    Index(u32),
+}
+
+/// The devices in a batch of rtnetlink messages that came up or got an address. Removals
+/// are left out: there is nothing to rebind to until something comes back.
+#[cfg(target_os = "linux")]
+fn changed_devices(buffer: &[u8]) -> Vec<DeviceRef> {
+    const HEADER: usize = 16;
+    const IFINFOMSG: usize = 16;
+    const IFADDRMSG: usize = 8;
+    const IFLA_IFNAME: u16 = 3;
+    const RTM_NEWLINK: u16 = 16;
+    const RTM_NEWADDR: u16 = 20;
+
+    let mut devices = Vec::new();
+    let mut rest = buffer;
+    while rest.len() >= HEADER {
+        let length = u32::from_ne_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if length < HEADER || length > rest.len() {
+            break;
+        }
+        let kind = u16::from_ne_bytes([rest[4], rest[5]]);
+        let body = &rest[HEADER..length];
+        match kind {
+            RTM_NEWLINK if body.len() >= IFINFOMSG => {
+                let name = attributes(&body[IFINFOMSG..])
+                    .find(|(kind, _)| *kind == IFLA_IFNAME)
+                    .map(|(_, value)| {
+                        let value = value.split(|byte| *byte == 0).next().unwrap_or(value);
+                        String::from_utf8_lossy(value).into_owned()
+                    });
+                let index = u32::from_ne_bytes([body[4], body[5], body[6], body[7]]);
+                devices.push(name.map_or(DeviceRef::Index(index), DeviceRef::Name));
+            }
+            RTM_NEWADDR if body.len() >= IFADDRMSG => {
+                devices.push(DeviceRef::Index(u32::from_ne_bytes([
+                    body[4], body[5], body[6], body[7],
+                ])));
+            }
+            _ => {}
+        }
+        rest = &rest[length.next_multiple_of(4).min(rest.len())..];
+    }
+    devices
+}
+
+/// The `rtattr`s of a message body: type and payload.
+#[cfg(target_os = "linux")]
+fn attributes(mut rest: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if rest.len() < 4 {
+            return None;
+        }
+        let length = u16::from_ne_bytes([rest[0], rest[1]]) as usize;
+        if length < 4 || length > rest.len() {
+            return None;
+        }
+        let kind = u16::from_ne_bytes([rest[2], rest[3]]);
+        let value = &rest[4..length];
+        rest = &rest[length.next_multiple_of(4).min(rest.len())..];
+        Some((kind, value))
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::BTreeSet;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use nix::libc;
+    use nix::sys::socket::{
+        bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+    };
+    use tokio::io::unix::AsyncFd;
+    use tokio::sync::mpsc;
+    use tracing::{debug, warn};
+
+    use super::{changed_devices, interface_addresses, DeviceRef, SETTLE};
+
+    pub(super) fn subscribe() -> io::Result<AsyncFd<OwnedFd>> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            SockProtocol::NetlinkRoute,
+        )?;
+        // SAFETY: `socket` just returned this descriptor and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let groups = libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, groups as u32))?;
+        AsyncFd::new(fd)
+    }
+
+    pub(super) async fn watch(socket: AsyncFd<OwnedFd>, tx: mpsc::Sender<String>) {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut pending = BTreeSet::new();
+        loop {
+            let received = if pending.is_empty() {
+                read(&socket, &mut buffer).await
+            } else {
+                match tokio::time::timeout(SETTLE, read(&socket, &mut buffer)).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        for device in std::mem::take(&mut pending) {
+                            debug!("Network device {} changed", device);
+                            if tx.send(device).await.is_err() {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                }
+            };
+            match received {
+                Ok(size) => {
+                    for device in changed_devices(&buffer[..size]) {
+                        let name = match device {
+                            DeviceRef::Name(name) => Some(name),
+                            DeviceRef::Index(index) => name_of(index),
+                        };
+                        pending.extend(name);
+                    }
+                }
+                // The kernel dropped messages; any device may have changed.
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    pending.extend(interface_addresses().into_iter().map(|(name, _)| name));
+                }
+                Err(e) => {
+                    warn!("Stopped following network devices: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn read(socket: &AsyncFd<OwnedFd>, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = socket.readable().await?;
+            match guard.try_io(|fd| {
+                recv(fd.as_raw_fd(), buffer, MsgFlags::empty()).map_err(io::Error::from)
+            }) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn name_of(index: u32) -> Option<String> {
+        let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+        // SAFETY: `name` has the IF_NAMESIZE bytes `if_indextoname` may write.
+        let result = unsafe { libc::if_indextoname(index, name.as_mut_ptr()) };
+        if result.is_null() {
+            return None;
+        }
+        // SAFETY: on success `name` holds a NUL-terminated string.
+        let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    fn message(kind: u16, body: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&((16 + body.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(body);
+        message.resize(message.len().next_multiple_of(4), 0);
+        message
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reports_devices_that_came_up_or_got_an_address() {
+        // ifinfomsg for index 7, then IFLA_IFNAME "wwan0".
+        let mut link = vec![0u8; 16];
+        link[4..8].copy_from_slice(&7u32.to_ne_bytes());
+        link.extend_from_slice(&10u16.to_ne_bytes());
+        link.extend_from_slice(&3u16.to_ne_bytes());
+        link.extend_from_slice(b"wwan0\0");
+        let mut address = vec![2u8, 24, 0, 0];
+        address.extend_from_slice(&9u32.to_ne_bytes());
+
+        let mut batch = message(16, &link);
+        batch.extend(message(21, &address));
+        batch.extend(message(20, &address));
+        assert_eq!(
+            changed_devices(&batch),
+            vec![DeviceRef::Name("wwan0".to_string()), DeviceRef::Index(9)]
+        );
+        // Truncated messages end the batch without panicking.
+        assert_eq!(changed_devices(&batch[..20]), Vec::new());
+        assert_eq!(
+            changed_devices(&batch[..batch.len() - 2]),
+            vec![DeviceRef::Name("wwan0".to_string())]
+        );
+    }
+
+    #[test]
+    fn loopback_has_its_address() {
+        let loopback = IpAddr::from([127, 0, 0, 1]);
+        let Some(device) = device_of(loopback) else {
+            // No loopback device in this sandbox.
+            return;
+        };
+        assert!(device_addresses(&device).contains(&loopback));
+        assert!(device_index(&device).is_some());
+    }
+}
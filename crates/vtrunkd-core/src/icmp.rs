@@ -0,0 +1,297 @@
+//! ICMP errors written back into the TUN device for packets the tunnel cannot carry: too
+//! big for the tunnel MTU with fragmentation forbidden, or with no link to send them on.
+//! The sending host's stack then lowers its path MTU or gives up at once, instead of
+//! retransmitting into a black hole.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use crate::capture::checksum;
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_ICMPV6: u8 = 58;
+const ICMP_UNREACHABLE: u8 = 3;
+const ICMP_NET_UNREACHABLE: u8 = 0;
+const ICMP_FRAG_NEEDED: u8 = 4;
+const ICMPV6_UNREACHABLE: u8 = 1;
+const ICMPV6_NO_ROUTE: u8 = 0;
+const ICMPV6_TOO_BIG: u8 = 2;
+/// IPv4 errors fit in 576 bytes (RFC 1812), IPv6 ones in 1280 (RFC 4443).
+const IPV4_MAX_ERROR: usize = 576;
+const IPV6_MAX_ERROR: usize = 1280;
+/// Errors written per second at most, as RFC 1812 and RFC 4443 ask of routers.
+const RATE_LIMIT: u32 = 100;
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Why a packet is refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Larger than the tunnel MTU, which is reported back.
+    TooBig,
+    /// No link to send it on.
+    Unreachable,
+}
+
+/// Writes the errors, rate limited, from the tunnel's own addresses.
+pub struct IcmpErrors {
+    mtu: u32,
+    v4: Option<Ipv4Addr>,
+    v6: Option<Ipv6Addr>,
+    window_start: Instant,
+    sent: u32,
+}
+
+impl IcmpErrors {
+    /// Errors come from the first address of each family in `addresses`; where there is
+    /// none, from the destination of the refused packet.
+    pub fn new(mtu: u32, addresses: impl IntoIterator<Item = IpAddr>, now: Instant) -> Self {
+        let (mut v4, mut v6) = (None, None);
+        for address in addresses {
+            match address {
+                IpAddr::V4(address) => v4 = v4.or(Some(address)),
+                IpAddr::V6(address) => v6 = v6.or(Some(address)),
+            }
+        }
+        IcmpErrors {
+            mtu,
+            v4,
+            v6,
+            window_start: now,
+            sent: 0,
+        }
+    }
+
+    /// Whether `packet` is over the tunnel MTU and may not be fragmented: IPv4 with DF
+    /// set, or IPv6, which routers never fragment.
+    pub fn too_big(&self, packet: &[u8]) -> bool {
+        if packet.len() <= self.mtu as usize {
+            return false;
+        }
+        match packet.first().map(|byte| byte >> 4) {
+            Some(4) => packet.len() >= 20 && packet[6] & 0x40 != 0,
+            Some(6) => true,
+            _ => false,
+        }
+    }
+
+    /// The ICMP or ICMPv6 error refusing `packet`, or `None` when it must not get one (an
+    /// ICMP error itself, a later fragment, a multicast or broadcast, one from no
+    /// address) or the rate limit is reached.
+    pub fn refuse(&mut self, packet: &[u8], refusal: Refusal, now: Instant) -> Option<Vec<u8>> {
+        let error = match packet.first().map(|byte| byte >> 4) {
+            Some(4) => self.ipv4(packet, refusal)?,
+            Some(6) => self.ipv6(packet, refusal)?,
+            _ => return None,
+        };
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.sent = 0;
+        }
+        if self.sent >= RATE_LIMIT {
+            return None;
+        }
+        self.sent += 1;
+        Some(error)
+    }
+
+    fn ipv4(&self, packet: &[u8], refusal: Refusal) -> Option<Vec<u8>> {
+        if packet.len() < 20 {
+            return None;
+        }
+        let header = usize::from(packet[0] & 0x0f) * 4;
+        let fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+        let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+        let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+        let is_error = packet[9] == PROTO_ICMP
+            && packet
+                .get(header)
+                .is_some_and(|kind| matches!(kind, 3 | 4 | 5 | 11 | 12));
+        if header < 20
+            || fragment != 0
+            || is_error
+            || source.is_unspecified()
+            || source.is_multicast()
+            || source.is_broadcast()
+            || destination.is_multicast()
+            || destination.is_broadcast()
+        {
+            return None;
+        }
+        let (code, mtu) = match refusal {
+            Refusal::TooBig => (
+                ICMP_FRAG_NEEDED,
+                u16::try_from(self.mtu).unwrap_or(u16::MAX),
+            ),
+            Refusal::Unreachable => (ICMP_NET_UNREACHABLE, 0),
+        };
+        let quoted = &packet[..packet.len().min(IPV4_MAX_ERROR - 28)];
+        let mut icmp = vec![ICMP_UNREACHABLE, code, 0, 0, 0, 0];
+        icmp.extend_from_slice(&mtu.to_be_bytes());
+        icmp.extend_from_slice(quoted);
+        let sum = checksum(&[&icmp]);
+        icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+        let from = self.v4.unwrap_or(destination);
+        let mut error = vec![0x45, 0];
+        error.extend_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+        error.extend_from_slice(&[0, 0, 0, 0, 64, PROTO_ICMP, 0, 0]);
+        error.extend_from_slice(&from.octets());
+        error.extend_from_slice(&source.octets());
+        let sum = checksum(&[&error]);
+        error[10..12].copy_from_slice(&sum.to_be_bytes());
+        error.extend_from_slice(&icmp);
+        Some(error)
+    }
+
+    fn ipv6(&self, packet: &[u8], refusal: Refusal) -> Option<Vec<u8>> {
+        if packet.len() < 40 {
+            return None;
+        }
+        let address = |at: usize| {
+            let octets: [u8; 16] = packet[at..at + 16].try_into().ok()?;
+            Some(Ipv6Addr::from(octets))
+        };
+        let (source, destination) = (address(8)?, address(24)?);
+        // Extension headers before an ICMPv6 error are not followed; such packets are
+        // refused like any other.
+        let is_error = packet[6] == PROTO_ICMPV6 && packet.get(40).is_some_and(|kind| *kind < 128);
+        if is_error
+            || source.is_unspecified()
+            || source.is_multicast()
+            || destination.is_multicast()
+        {
+            return None;
+        }
+        let (kind, code, value) = match refusal {
+            Refusal::TooBig => (ICMPV6_TOO_BIG, 0, self.mtu),
+            Refusal::Unreachable => (ICMPV6_UNREACHABLE, ICMPV6_NO_ROUTE, 0),
+        };
+        let quoted = &packet[..packet.len().min(IPV6_MAX_ERROR - 48)];
+        let mut icmp = vec![kind, code, 0, 0];
+        icmp.extend_from_slice(&value.to_be_bytes());
+        icmp.extend_from_slice(quoted);
+
+        let from = self.v6.unwrap_or(destination);
+        let mut pseudo = Vec::with_capacity(40);
+        pseudo.extend_from_slice(&from.octets());
+        pseudo.extend_from_slice(&source.octets());
+        pseudo.extend_from_slice(&(icmp.len() as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, PROTO_ICMPV6]);
+        let sum = checksum(&[&pseudo, &icmp]);
+        icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+        let mut error = vec![0x60, 0, 0, 0];
+        error.extend_from_slice(&(icmp.len() as u16).to_be_bytes());
+        error.extend_from_slice(&[PROTO_ICMPV6, 64]);
+        error.extend_from_slice(&from.octets());
+        error.extend_from_slice(&source.octets());
+        error.extend_from_slice(&icmp);
+        Some(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4(size: usize, dont_fragment: bool) -> Vec<u8> {
+        let mut packet = vec![0u8; size];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&(size as u16).to_be_bytes());
+        if dont_fragment {
+            packet[6] = 0x40;
+        }
+        packet[8] = 64;
+        packet[9] = 6;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        packet[16..20].copy_from_slice(&[192, 0, 2, 1]);
+        packet
+    }
+
+    fn ipv6(size: usize) -> Vec<u8> {
+        let mut packet = vec![0u8; size];
+        packet[0] = 0x60;
+        packet[4..6].copy_from_slice(&((size - 40) as u16).to_be_bytes());
+        packet[6] = 17;
+        packet[7] = 64;
+        packet[8..24].copy_from_slice(&"fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+        packet[24..40].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        packet
+    }
+
+    #[test]
+    fn only_unfragmentable_packets_over_the_mtu_are_too_big() {
+        let errors = IcmpErrors::new(1420, [], Instant::now());
+        assert!(errors.too_big(&ipv4(1500, true)));
+        assert!(!errors.too_big(&ipv4(1500, false)));
+        assert!(!errors.too_big(&ipv4(1420, true)));
+        assert!(errors.too_big(&ipv6(1500)));
+    }
+
+    #[test]
+    fn frag_needed_carries_the_mtu_back_to_the_sender() {
+        let mut errors = IcmpErrors::new(1420, [IpAddr::from([10, 0, 0, 1])], Instant::now());
+        let packet = ipv4(1500, true);
+        let error = errors
+            .refuse(&packet, Refusal::TooBig, Instant::now())
+            .unwrap();
+        assert_eq!(error.len(), IPV4_MAX_ERROR);
+        assert_eq!(checksum(&[&error[..20]]), 0);
+        assert_eq!(error[9], PROTO_ICMP);
+        assert_eq!(error[12..16], [10, 0, 0, 1]);
+        assert_eq!(error[16..20], [10, 0, 0, 2]);
+        let icmp = &error[20..];
+        assert_eq!(checksum(&[icmp]), 0);
+        assert_eq!(icmp[..2], [ICMP_UNREACHABLE, ICMP_FRAG_NEEDED]);
+        assert_eq!(u16::from_be_bytes([icmp[6], icmp[7]]), 1420);
+        assert_eq!(icmp[8..28], packet[..20]);
+
+        // Never an error about an error.
+        let mut bounced = ipv4(56, true);
+        bounced[9] = PROTO_ICMP;
+        bounced[20] = ICMP_UNREACHABLE;
+        assert_eq!(
+            errors.refuse(&bounced, Refusal::Unreachable, Instant::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn ipv6_errors_come_from_the_destination_without_an_address() {
+        let mut errors = IcmpErrors::new(1420, [], Instant::now());
+        let packet = ipv6(1500);
+        let error = errors
+            .refuse(&packet, Refusal::TooBig, Instant::now())
+            .unwrap();
+        assert_eq!(error.len(), IPV6_MAX_ERROR);
+        assert_eq!(error[6], PROTO_ICMPV6);
+        assert_eq!(error[8..24], packet[24..40]);
+        assert_eq!(error[24..40], packet[8..24]);
+        let mut pseudo = error[8..40].to_vec();
+        pseudo.extend_from_slice(&((error.len() - 40) as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, PROTO_ICMPV6]);
+        assert_eq!(checksum(&[&pseudo, &error[40..]]), 0);
+        assert_eq!(error[40..42], [ICMPV6_TOO_BIG, 0]);
+        assert_eq!(u32::from_be_bytes(error[44..48].try_into().unwrap()), 1420);
+
+        let unreachable = errors
+            .refuse(&ipv6(80), Refusal::Unreachable, Instant::now())
+            .unwrap();
+        assert_eq!(unreachable[40..42], [ICMPV6_UNREACHABLE, ICMPV6_NO_ROUTE]);
+        assert_eq!(unreachable.len(), 48 + 80);
+    }
+
+    #[test]
+    fn errors_are_rate_limited() {
+        let now = Instant::now();
+        let mut errors = IcmpErrors::new(1420, [], now);
+        let packet = ipv4(60, false);
+        for _ in 0..RATE_LIMIT {
+            assert!(errors.refuse(&packet, Refusal::Unreachable, now).is_some());
+        }
+        assert!(errors.refuse(&packet, Refusal::Unreachable, now).is_none());
+        assert!(errors
+            .refuse(&packet, Refusal::Unreachable, now + WINDOW)
+            .is_some());
+    }
+}
@@ -0,0 +1,71 @@
+//! Tells apart what the TUN device hands over before it is tunnelled. The peer only ever
+//! writes IPv4 and IPv6 back out, so anything else would be carried across for nothing.
+
+/// What a packet read from the TUN device turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+    /// IPv4 or IPv6 by its version, but cut short of its own header or length.
+    Malformed,
+    /// Another version, or nothing at all.
+    NonIp,
+}
+
+pub fn family(packet: &[u8]) -> Family {
+    match packet.first().map(|byte| byte >> 4) {
+        Some(4) => {
+            if packet.len() < 20 {
+                return Family::Malformed;
+            }
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            let length = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+            if header < 20 || length < header || length > packet.len() {
+                Family::Malformed
+            } else {
+                Family::Ipv4
+            }
+        }
+        Some(6) => {
+            if packet.len() < 40 {
+                return Family::Malformed;
+            }
+            let payload = usize::from(u16::from_be_bytes([packet[4], packet[5]]));
+            if 40 + payload > packet.len() {
+                Family::Malformed
+            } else {
+                Family::Ipv6
+            }
+        }
+        _ => Family::NonIp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_ip_versions_from_everything_else() {
+        let mut ipv4 = vec![0u8; 28];
+        ipv4[0] = 0x45;
+        ipv4[2..4].copy_from_slice(&28u16.to_be_bytes());
+        assert_eq!(family(&ipv4), Family::Ipv4);
+        assert_eq!(family(&ipv4[..24]), Family::Malformed);
+        ipv4[0] = 0x44;
+        assert_eq!(family(&ipv4), Family::Malformed);
+
+        let mut ipv6 = vec![0u8; 48];
+        ipv6[0] = 0x60;
+        ipv6[4..6].copy_from_slice(&8u16.to_be_bytes());
+        assert_eq!(family(&ipv6), Family::Ipv6);
+        assert_eq!(family(&ipv6[..44]), Family::Malformed);
+
+        // An Ethernet frame (ARP) from a TAP-style sender, and an empty read.
+        assert_eq!(
+            family(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x08, 0x06]),
+            Family::NonIp
+        );
+        assert_eq!(family(&[]), Family::NonIp);
+    }
+}
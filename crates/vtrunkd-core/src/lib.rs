@@ -0,0 +1,58 @@
+//! The vtrunkd bonding engine. The `vtrunkd` daemon and other Rust applications drive it
+//! through [`Tunnel`]; the GUI uses the config and control helpers, and the fuzz targets in
+//! `fuzz/` call the parsers directly.
+
+pub mod activation;
+mod allowlist;
+pub mod api;
+pub mod audit;
+mod batch;
+pub mod bond;
+pub mod capture;
+mod compress;
+pub mod config;
+pub mod container;
+pub mod control;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+mod ecn;
+pub mod error;
+pub mod events;
+#[cfg(unix)]
+pub mod handover;
+pub mod health;
+pub mod hooks;
+mod hotplug;
+mod icmp;
+mod inner;
+pub mod logging;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+mod mss;
+#[cfg(all(test, feature = "netsim"))]
+mod netsim;
+mod network;
+mod outage;
+pub mod pidfile;
+pub mod policy;
+pub mod protect;
+mod quality;
+mod queue;
+mod ratelimit;
+mod reorder;
+mod rotate;
+pub mod runtime;
+pub mod sandbox;
+pub mod state;
+pub mod stats;
+pub mod supervisor;
+mod throttle;
+pub mod tunnel;
+pub mod uapi;
+pub mod uci;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+pub mod wgquick;
+pub mod wireguard;
+
+pub use tunnel::{Tunnel, TunnelBuilder};
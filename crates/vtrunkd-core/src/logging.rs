@@ -0,0 +1,469 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+#[cfg(unix)]
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LoggingConfig;
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+pub const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+pub const DEFAULT_LOG_KEEP_FILES: usize = 5;
+
+#[cfg(target_os = "macos")]
+const SYSLOG_SOCKET: &str = "/var/run/syslog";
+#[cfg(all(unix, not(target_os = "macos")))]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// `LOG_DAEMON` facility from syslog(3).
+#[cfg(unix)]
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+/// Output format for daemon logs.
+///
+/// `json` emits one object per line with the message and structured fields
+/// (`link`, `event`, `rtt_ms`, ...) for log shippers such as Loki or ELK.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Where daemon logs are written. `file` is implied when `logging.file` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    Stdout,
+    File,
+    Journald,
+    Syslog,
+}
+
+pub enum LogSink {
+    Stdout,
+    File(RotatingFile),
+    Journald,
+    Syslog,
+}
+
+/// Log file that rotates to `<file>.1`, `<file>.2`, ... by size and optionally by age.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    max_size: u64,
+    max_age: Option<Duration>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &Path,
+        max_size: u64,
+        max_age: Option<Duration>,
+        keep: usize,
+    ) -> io::Result<Self> {
+        // The daemon changes directory to / after forking, so pin relative paths now.
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened: Instant::now(),
+            max_size,
+            max_age,
+            keep,
+        })
+    }
+
+    pub fn from_config(logging: &LoggingConfig) -> io::Result<Option<Self>> {
+        let Some(path) = &logging.file else {
+            return Ok(None);
+        };
+        let max_size = logging.max_size_mb.unwrap_or(DEFAULT_LOG_MAX_SIZE_MB) * 1024 * 1024;
+        let max_age = logging
+            .rotate_hours
+            .map(|hours| Duration::from_secs(hours * 3600));
+        let keep = logging.keep_files.unwrap_or(DEFAULT_LOG_KEEP_FILES);
+        RotatingFile::open(Path::new(path), max_size, max_age, keep).map(Some)
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self.size + incoming as u64 > self.max_size;
+        let too_old = self.max_age.is_some_and(|age| self.opened.elapsed() >= age);
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.keep));
+            for index in (1..self.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Sends each log event as one RFC 3164 datagram to the local syslog socket.
+#[derive(Clone)]
+#[cfg(unix)]
+pub struct Syslog {
+    connection: Arc<RwLock<SyslogConnection>>,
+}
+
+#[cfg(unix)]
+struct SyslogConnection {
+    socket: UnixDatagram,
+    header: String,
+}
+
+#[cfg(unix)]
+pub struct SyslogLine {
+    connection: Arc<RwLock<SyslogConnection>>,
+    severity: u8,
+    buf: Vec<u8>,
+}
+
+/// The syslog writer in use, for [`reopen`].
+#[cfg(unix)]
+static SYSLOG: OnceLock<Syslog> = OnceLock::new();
+
+#[cfg(unix)]
+impl SyslogConnection {
+    fn open(path: &Path) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SyslogConnection {
+            socket,
+            header: format!("vtrunkd[{}]: ", std::process::id()),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Syslog {
+    pub fn connect() -> io::Result<Self> {
+        Ok(Syslog {
+            connection: Arc::new(RwLock::new(SyslogConnection::open(Path::new(
+                SYSLOG_SOCKET,
+            ))?)),
+        })
+    }
+
+    /// Opens a new socket, with the current pid in the header, for the lines after this.
+    fn reconnect(&self, path: &Path) -> io::Result<()> {
+        let connection = SyslogConnection::open(path)?;
+        *self
+            .connection
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = connection;
+        Ok(())
+    }
+
+    fn line(&self, severity: u8) -> SyslogLine {
+        SyslogLine {
+            connection: Arc::clone(&self.connection),
+            severity,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Re-opens the log output in a daemonized child. Logging starts before the fork, so
+/// socket activation and an upgrade's handover are logged, and syslog would otherwise
+/// keep the parent's socket and pid. journald reads the pid off every message it gets.
+#[cfg(unix)]
+pub fn reopen() -> VtrunkdResult<()> {
+    if let Some(syslog) = SYSLOG.get() {
+        syslog.reconnect(Path::new(SYSLOG_SOCKET)).map_err(|e| {
+            VtrunkdError::Config(format!("Failed to reopen {}: {}", SYSLOG_SOCKET, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reopen() -> VtrunkdResult<()> {
+    Ok(())
+}
+
+/// Maps a tracing level to a syslog(3) severity.
+#[cfg(unix)]
+fn syslog_severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+#[cfg(unix)]
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.line(syslog_severity(&Level::INFO))
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.line(syslog_severity(meta.level()))
+    }
+}
+
+#[cfg(unix)]
+impl Write for SyslogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SyslogLine {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buf);
+        let message = message.trim_end();
+        if message.is_empty() {
+            return;
+        }
+        let priority = SYSLOG_FACILITY_DAEMON * 8 + self.severity;
+        let connection = self
+            .connection
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let datagram = format!("<{}>{}{}", priority, connection.header, message);
+        let _ = connection.socket.send(datagram.as_bytes());
+    }
+}
+
+impl LogSink {
+    /// Picks the sink from `--log-output`, then `logging.output`, then `logging.file`.
+    pub fn from_config(
+        output: Option<LogOutput>,
+        logging: Option<&LoggingConfig>,
+    ) -> VtrunkdResult<Self> {
+        let output = output
+            .or(logging.and_then(|logging| logging.output))
+            .unwrap_or(match logging.and_then(|logging| logging.file.as_ref()) {
+                Some(_) => LogOutput::File,
+                None => LogOutput::Stdout,
+            });
+        match output {
+            LogOutput::Stdout => Ok(LogSink::Stdout),
+            LogOutput::Journald => Ok(LogSink::Journald),
+            LogOutput::Syslog => Ok(LogSink::Syslog),
+            LogOutput::File => logging
+                .map(RotatingFile::from_config)
+                .transpose()?
+                .flatten()
+                .map(LogSink::File)
+                .ok_or_else(|| {
+                    VtrunkdError::InvalidConfig(
+                        "File logging requires logging.file in the config".to_string(),
+                    )
+                }),
+        }
+    }
+}
+
+pub fn init(format: LogFormat, debug: bool, sink: LogSink) -> VtrunkdResult<()> {
+    let filter = if debug {
+        "vtrunkd=debug"
+    } else {
+        "vtrunkd=info"
+    };
+    let filter = EnvFilter::new(filter);
+
+    let (writer, ansi, timestamps) = match sink {
+        LogSink::Stdout => (BoxMakeWriter::new(io::stdout), true, true),
+        LogSink::File(file) => (BoxMakeWriter::new(Mutex::new(file)), false, true),
+        #[cfg(unix)]
+        LogSink::Syslog => {
+            let syslog = Syslog::connect().map_err(|e| {
+                VtrunkdError::Config(format!("Failed to open {}: {}", SYSLOG_SOCKET, e))
+            })?;
+            let _ = SYSLOG.set(syslog.clone());
+            (BoxMakeWriter::new(syslog), false, false)
+        }
+        #[cfg(not(unix))]
+        LogSink::Syslog => {
+            return Err(VtrunkdError::Config(
+                "syslog is not available on this platform".to_string(),
+            ))
+        }
+        LogSink::Journald => {
+            // journald stores level, fields and timestamps natively; the format is ignored.
+            let journald = tracing_journald::layer()
+                .map_err(|e| VtrunkdError::Config(format!("Failed to connect to journald: {}", e)))?
+                .with_syslog_identifier("vtrunkd".to_string())
+                .with_field_prefix(Some("VTRUNKD".to_string()));
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(journald)
+                .init();
+            return Ok(());
+        }
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    // syslog stamps messages itself.
+    match (format, timestamps) {
+        (LogFormat::Text, true) => tracing_subscriber::registry()
+            .with(filter)
+            .with(layer)
+            .init(),
+        (LogFormat::Text, false) => tracing_subscriber::registry()
+            .with(filter)
+            .with(layer.without_time())
+            .init(),
+        (LogFormat::Json, _) => tracing_subscriber::registry()
+            .with(filter)
+            .with(layer.json().flatten_event(true).with_current_span(false))
+            .init(),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_format_parses_lowercase() {
+        let format: LogFormat = serde_yaml::from_str("json").unwrap();
+        assert_eq!(format, LogFormat::Json);
+        assert!(serde_yaml::from_str::<LogFormat>("xml").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syslog_lines_carry_priority() {
+        let (daemon, syslog_socket) = UnixDatagram::pair().unwrap();
+        let syslog = Syslog {
+            connection: Arc::new(RwLock::new(SyslogConnection {
+                socket: syslog_socket,
+                header: "vtrunkd[1]: ".to_string(),
+            })),
+        };
+        let mut line = syslog.line(syslog_severity(&Level::WARN));
+        line.write_all(b"WireGuard lte marked down\n").unwrap();
+        drop(line);
+
+        let mut buf = [0u8; 128];
+        let size = daemon.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"<28>vtrunkd[1]: WireGuard lte marked down");
+
+        // As after daemonizing: a new socket, and this process's pid.
+        let path = std::env::temp_dir().join(format!("vtrunkd-syslog-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        syslog.reconnect(&path).unwrap();
+        let mut line = syslog.line(syslog_severity(&Level::INFO));
+        line.write_all(b"started").unwrap();
+        drop(line);
+        let size = daemon.recv(&mut buf).unwrap();
+        let expected = format!("<30>vtrunkd[{}]: started", std::process::id());
+        assert_eq!(&buf[..size], expected.as_bytes());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rotating_file_keeps_limited_history() {
+        let dir = std::env::temp_dir().join(format!("vtrunkd-log-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vtrunkd.log");
+
+        let mut file = RotatingFile::open(&path, 16, None, 2).unwrap();
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("vtrunkd.log.1")).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("vtrunkd.log.2")).unwrap(),
+            "second line\n"
+        );
+        assert!(!dir.join("vtrunkd.log.3").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,305 @@
+//! MQTT telemetry for fleets of bonded routers: link state and throughput are published
+//! under `topic_prefix` so existing MQTT dashboards and alerting can watch them.
+//!
+//! Topics (all JSON except `online`):
+//! - `<prefix>/online`: `true`, or `false` from the broker's last will; retained.
+//! - `<prefix>/tunnel`: TUN counters and rates every interval; retained.
+//! - `<prefix>/links/<name>`: state, counters and rates every interval and on state
+//!   changes; retained.
+//! - `<prefix>/events`: each tunnel event as `vtrunkd events` prints it.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rumqttc::{AsyncClient, LastWill, MqttOptions, QoS};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::config::{MqttConfig, DEFAULT_MQTT_INTERVAL_SECS};
+use crate::events::TunnelEvent;
+use crate::stats::{LinkStats, StatsSnapshot};
+
+const DEFAULT_PORT: u16 = 1883;
+/// Messages queued for the broker before new telemetry is dropped.
+const QUEUE_DEPTH: usize = 64;
+/// Pause before the client reconnects to an unreachable broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How often the tunnel should hand over a stats snapshot.
+pub fn interval(config: &MqttConfig) -> Duration {
+    Duration::from_secs(config.interval_secs.unwrap_or(DEFAULT_MQTT_INTERVAL_SECS))
+}
+
+fn client_id(config: &MqttConfig) -> String {
+    config.client_id.clone().unwrap_or_else(|| {
+        let host = nix::unistd::gethostname()
+            .ok()
+            .and_then(|name| name.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        format!("vtrunkd-{}", host)
+    })
+}
+
+/// Splits `host[:port]`, including bracketed IPv6 literals.
+fn parse_broker(broker: &str) -> (String, u16) {
+    if let Some(rest) = broker.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once(']') {
+            let port = port.strip_prefix(':').and_then(|p| p.parse().ok());
+            return (host.to_string(), port.unwrap_or(DEFAULT_PORT));
+        }
+    }
+    match broker.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host.to_string(), port),
+            Err(_) => (broker.to_string(), DEFAULT_PORT),
+        },
+        _ => (broker.to_string(), DEFAULT_PORT),
+    }
+}
+
+/// Link states as the events describe them; links start `up`, as the tunnel does.
+#[derive(Debug, Default)]
+struct LinkStates(HashMap<String, &'static str>);
+
+impl LinkStates {
+    fn get(&self, link: &str) -> &'static str {
+        self.0.get(link).copied().unwrap_or("up")
+    }
+
+    /// Returns the link whose state changed, if any.
+    fn apply(&mut self, event: &TunnelEvent) -> Option<String> {
+        let (link, state) = match event {
+            TunnelEvent::LinkUp { link } => (link, "up"),
+            TunnelEvent::LinkDown { link, .. } => (link, "down"),
+            TunnelEvent::LinkDegraded { link, .. } => (link, "degraded"),
+            TunnelEvent::LinkAdmin { link, state } => match state.as_str() {
+                "down" => (link, "admin_down"),
+                "drain" => (link, "drain"),
+                _ => (link, "up"),
+            },
+            _ => return None,
+        };
+        (self.0.insert(link.clone(), state) != Some(state)).then(|| link.clone())
+    }
+}
+
+fn rate(current: u64, previous: Option<u64>, elapsed: Duration) -> u64 {
+    match previous {
+        Some(previous) if !elapsed.is_zero() => {
+            (current.saturating_sub(previous) as f64 * 8.0 / elapsed.as_secs_f64()) as u64
+        }
+        _ => 0,
+    }
+}
+
+fn link_payload(
+    link: &LinkStats,
+    state: &str,
+    previous: Option<&LinkStats>,
+    elapsed: Duration,
+) -> String {
+    json!({
+        "state": state,
+        "tx_bytes": link.tx_bytes,
+        "rx_bytes": link.rx_bytes,
+        "tx_packets": link.tx_packets,
+        "rx_packets": link.rx_packets,
+        "tx_errors": link.tx_errors,
+        "tx_bps": rate(link.tx_bytes, previous.map(|p| p.tx_bytes), elapsed),
+        "rx_bps": rate(link.rx_bytes, previous.map(|p| p.rx_bytes), elapsed),
+    })
+    .to_string()
+}
+
+/// Topic and payload for every retained telemetry message.
+fn telemetry(
+    prefix: &str,
+    current: &StatsSnapshot,
+    previous: Option<&StatsSnapshot>,
+    elapsed: Duration,
+    states: &LinkStates,
+) -> Vec<(String, String)> {
+    let tunnel = json!({
+        "tun_rx_bytes": current.tun_rx_bytes,
+        "tun_tx_bytes": current.tun_tx_bytes,
+        "tun_rx_bps": rate(current.tun_rx_bytes, previous.map(|p| p.tun_rx_bytes), elapsed),
+        "tun_tx_bps": rate(current.tun_tx_bytes, previous.map(|p| p.tun_tx_bytes), elapsed),
+        "dropped_no_link": current.dropped_no_link,
+        "failovers": current.failovers,
+    });
+    let mut messages = vec![(format!("{}/tunnel", prefix), tunnel.to_string())];
+    for link in &current.links {
+        let before = previous.and_then(|p| p.links.iter().find(|l| l.name == link.name));
+        messages.push((
+            format!("{}/links/{}", prefix, link.name),
+            link_payload(link, states.get(&link.name), before, elapsed),
+        ));
+    }
+    messages
+}
+
+/// Connects to the broker and publishes until the stats stream ends.
+pub fn spawn(
+    config: &MqttConfig,
+    mut stats: mpsc::Receiver<StatsSnapshot>,
+    mut events: broadcast::Receiver<TunnelEvent>,
+) {
+    let client_id = client_id(config);
+    let prefix = config
+        .topic_prefix
+        .clone()
+        .unwrap_or_else(|| format!("vtrunkd/{}", client_id))
+        .trim_end_matches('/')
+        .to_string();
+    let (host, port) = parse_broker(&config.broker);
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        format!("{}/online", prefix),
+        "false",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, QUEUE_DEPTH);
+    info!(
+        "Publishing MQTT telemetry to {} under {}",
+        config.broker, prefix
+    );
+
+    // Drives the connection; rumqttc reconnects on the next poll after an error.
+    let online_client = client.clone();
+    let online_topic = format!("{}/online", prefix);
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                    debug!("MQTT broker connected");
+                    let _ = online_client.try_publish(
+                        online_topic.as_str(),
+                        QoS::AtLeastOnce,
+                        true,
+                        "true",
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("MQTT connection error: {}", err);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut states = LinkStates::default();
+        let mut last: Option<(StatsSnapshot, Instant)> = None;
+        let publish = |topic: String, payload: String, retain: bool| {
+            if let Err(err) = client.try_publish(topic, QoS::AtMostOnce, retain, payload) {
+                debug!("MQTT message dropped: {}", err);
+            }
+        };
+        loop {
+            tokio::select! {
+                snapshot = stats.recv() => {
+                    let Some(snapshot) = snapshot else {
+                        return;
+                    };
+                    let now = Instant::now();
+                    let (previous, elapsed) = match &last {
+                        Some((previous, at)) => (Some(previous), now - *at),
+                        None => (None, Duration::ZERO),
+                    };
+                    for (topic, payload) in telemetry(&prefix, &snapshot, previous, elapsed, &states) {
+                        publish(topic, payload, true);
+                    }
+                    last = Some((snapshot, now));
+                }
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        if let Ok(json) = serde_json::to_string(&event) {
+                            publish(format!("{}/events", prefix), json, false);
+                        }
+                        let changed = states.apply(&event);
+                        let link = changed.as_deref().and_then(|name| {
+                            last.as_ref()?.0.links.iter().find(|l| l.name == name)
+                        });
+                        if let Some(link) = link {
+                            publish(
+                                format!("{}/links/{}", prefix, link.name),
+                                link_payload(link, states.get(&link.name), None, Duration::ZERO),
+                                true,
+                            );
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("MQTT publisher lagged by {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tx_bytes: u64) -> StatsSnapshot {
+        StatsSnapshot {
+            tun_rx_bytes: tx_bytes,
+            links: vec![LinkStats {
+                name: "lte".to_string(),
+                tx_bytes,
+                ..LinkStats::default()
+            }],
+            ..StatsSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn parse_broker_defaults_the_port() {
+        assert_eq!(parse_broker("mqtt.local"), ("mqtt.local".to_string(), 1883));
+        assert_eq!(
+            parse_broker("10.0.0.1:8883"),
+            ("10.0.0.1".to_string(), 8883)
+        );
+        assert_eq!(
+            parse_broker("[fd00::1]:1884"),
+            ("fd00::1".to_string(), 1884)
+        );
+        assert_eq!(parse_broker("fd00::1"), ("fd00::1".to_string(), 1883));
+    }
+
+    #[test]
+    fn telemetry_reports_rates_and_event_driven_state() {
+        let mut states = LinkStates::default();
+        let down = TunnelEvent::LinkDown {
+            link: "lte".to_string(),
+            reason: "health check timeout".to_string(),
+        };
+        assert_eq!(states.apply(&down).as_deref(), Some("lte"));
+        assert_eq!(states.apply(&down), None);
+        assert_eq!(states.apply(&TunnelEvent::HandshakeComplete), None);
+
+        let previous = snapshot(1_000);
+        let messages = telemetry(
+            "fleet/r1",
+            &snapshot(126_000),
+            Some(&previous),
+            Duration::from_secs(10),
+            &states,
+        );
+        assert_eq!(messages[0].0, "fleet/r1/tunnel");
+        assert_eq!(messages[1].0, "fleet/r1/links/lte");
+        let link: serde_json::Value = serde_json::from_str(&messages[1].1).unwrap();
+        assert_eq!(link["state"], "down");
+        assert_eq!(link["tx_bps"], 100_000);
+        assert_eq!(link["tx_bytes"], 126_000);
+    }
+}
@@ -0,0 +1,164 @@
+//! TCP MSS clamping for packets crossing the TUN device, so hosts behind the bonded router
+//! never negotiate segments that only fit their own, larger MTU (PMTU black holes).
+
+const IPV4_HEADER: u32 = 20;
+const IPV6_HEADER: u32 = 40;
+const TCP_HEADER: u32 = 20;
+const PROTO_TCP: u8 = 6;
+const TCP_SYN: u8 = 0x02;
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+/// Lowers the MSS option of a TCP SYN to what fits in `mtu`. Returns whether the packet
+/// changed; anything that is not an unfragmented TCP SYN is left alone.
+pub fn clamp(packet: &mut [u8], mtu: u32) -> bool {
+    let (offset, max_mss) = match packet.first().map(|b| b >> 4) {
+        Some(4) if packet.len() >= IPV4_HEADER as usize => {
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            let fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+            if packet[9] != PROTO_TCP || header < IPV4_HEADER as usize || fragment != 0 {
+                return false;
+            }
+            (header, mtu.saturating_sub(IPV4_HEADER + TCP_HEADER))
+        }
+        Some(6) if packet.len() >= IPV6_HEADER as usize => {
+            // Extension headers are rare on SYNs; those packets are passed through.
+            if packet[6] != PROTO_TCP {
+                return false;
+            }
+            (
+                IPV6_HEADER as usize,
+                mtu.saturating_sub(IPV6_HEADER + TCP_HEADER),
+            )
+        }
+        _ => return false,
+    };
+    let Some(tcp) = packet.get_mut(offset..) else {
+        return false;
+    };
+    let max_mss = u16::try_from(max_mss).unwrap_or(u16::MAX);
+    clamp_segment(tcp, max_mss)
+}
+
+fn clamp_segment(tcp: &mut [u8], max_mss: u16) -> bool {
+    if tcp.len() < TCP_HEADER as usize || tcp[13] & TCP_SYN == 0 {
+        return false;
+    }
+    let header = usize::from(tcp[12] >> 4) * 4;
+    if header > tcp.len() {
+        return false;
+    }
+    let mut i = TCP_HEADER as usize;
+    while i < header {
+        match tcp[i] {
+            OPT_END => return false,
+            OPT_NOP => i += 1,
+            kind => {
+                let Some(&len) = tcp.get(i + 1) else {
+                    return false;
+                };
+                let len = usize::from(len);
+                if len < 2 || i + len > header {
+                    return false;
+                }
+                if kind == OPT_MSS && len == 4 {
+                    let mss = u16::from_be_bytes([tcp[i + 2], tcp[i + 3]]);
+                    if mss <= max_mss {
+                        return false;
+                    }
+                    tcp[i + 2..i + 4].copy_from_slice(&max_mss.to_be_bytes());
+                    let checksum = u16::from_be_bytes([tcp[16], tcp[17]]);
+                    let checksum = adjust_checksum(checksum, mss, max_mss);
+                    tcp[16..18].copy_from_slice(&checksum.to_be_bytes());
+                    return true;
+                }
+                i += len;
+            }
+        }
+    }
+    false
+}
+
+/// Incremental update for one changed 16-bit word (RFC 1624, eqn. 3).
+fn adjust_checksum(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = u32::from(!checksum) + u32::from(!old) + u32::from(new);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ones_complement_sum(data: &[u8], mut sum: u32) -> u32 {
+        for chunk in data.chunks(2) {
+            let word = u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]);
+            sum += u32::from(word);
+        }
+        sum
+    }
+
+    fn fold(mut sum: u32) -> u16 {
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    /// An IPv4 SYN from 10.10.0.2 to 10.10.0.1 advertising `mss`, with a valid checksum.
+    fn ipv4_syn(mss: u16) -> Vec<u8> {
+        let mut packet = vec![
+            0x45, 0, 0, 48, 0, 0, 0x40, 0, 64, PROTO_TCP, 0, 0, 10, 10, 0, 2, 10, 10, 0, 1,
+        ];
+        let mut tcp = vec![
+            0xc3, 0x50, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x70, TCP_SYN, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        tcp.extend_from_slice(&[OPT_NOP, OPT_NOP, 4, 2, OPT_MSS, 4]);
+        tcp.extend_from_slice(&mss.to_be_bytes());
+        packet.extend_from_slice(&tcp);
+        let checksum = tcp_checksum(&packet);
+        packet[36..38].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    fn tcp_checksum(packet: &[u8]) -> u16 {
+        let mut tcp = packet[20..].to_vec();
+        tcp[16..18].copy_from_slice(&[0, 0]);
+        let mut pseudo = packet[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, PROTO_TCP]);
+        pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        fold(ones_complement_sum(&tcp, ones_complement_sum(&pseudo, 0)))
+    }
+
+    #[test]
+    fn clamps_syn_mss_and_keeps_the_checksum_valid() {
+        let mut packet = ipv4_syn(1460);
+        assert!(clamp(&mut packet, 1420));
+        assert_eq!(u16::from_be_bytes([packet[46], packet[47]]), 1380);
+        assert_eq!(
+            u16::from_be_bytes([packet[36], packet[37]]),
+            tcp_checksum(&packet)
+        );
+
+        // Already small enough, or not a SYN: untouched.
+        let mut small = ipv4_syn(1200);
+        assert!(!clamp(&mut small, 1420));
+        let mut ack = ipv4_syn(1460);
+        ack[33] = 0x10;
+        assert!(!clamp(&mut ack, 1420));
+        assert!(!clamp(&mut [0x45, 0, 0], 1420));
+    }
+
+    #[test]
+    fn clamps_ipv6_syns_to_the_larger_header() {
+        let ipv4 = ipv4_syn(1460);
+        let mut packet = vec![0x60, 0, 0, 0, 0, 28, PROTO_TCP, 64];
+        packet.extend_from_slice(&[0; 32]);
+        packet.extend_from_slice(&ipv4[20..]);
+        assert!(clamp(&mut packet, 1420));
+        assert_eq!(u16::from_be_bytes([packet[66], packet[67]]), 1360);
+    }
+}
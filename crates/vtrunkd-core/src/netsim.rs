@@ -0,0 +1,223 @@
+//! Impaired links for integration tests (`--features netsim`).
+//!
+//! A [`SimLink`] is a UDP relay placed between a link socket and its peer: point the link's
+//! `endpoint` at [`SimLink::addr`] and every datagram, in both directions, goes through the
+//! configured loss, latency, jitter, reordering and rate limit.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+
+/// Datagrams queued behind the rate limit for longer than this are dropped, like a full
+/// modem buffer.
+const MAX_QUEUE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default)]
+pub struct Impairment {
+    /// Fraction of datagrams dropped, from 0.0 to 1.0.
+    pub loss: f64,
+    pub latency: Duration,
+    /// Extra delay drawn uniformly from zero to this per datagram.
+    pub jitter: Duration,
+    /// Fraction of datagrams held back by another `latency + jitter`, so they arrive late.
+    pub reorder: f64,
+    /// Bits per second in each direction; unlimited when unset.
+    pub rate_bps: Option<u64>,
+}
+
+/// Per-relay counters.
+#[derive(Debug, Default)]
+pub struct SimCounters {
+    pub forwarded: AtomicU64,
+    pub dropped: AtomicU64,
+}
+
+/// A running relay; dropping it stops forwarding.
+pub struct SimLink {
+    addr: SocketAddr,
+    impairment: watch::Sender<Impairment>,
+    counters: Arc<SimCounters>,
+    task: JoinHandle<()>,
+}
+
+impl SimLink {
+    /// Relays between `upstream` and whichever address last sent from the other side.
+    pub async fn spawn(upstream: SocketAddr, impairment: Impairment) -> io::Result<Self> {
+        let bind: SocketAddr = if upstream.is_ipv6() {
+            "[::1]:0".parse().unwrap()
+        } else {
+            "127.0.0.1:0".parse().unwrap()
+        };
+        let socket = Arc::new(UdpSocket::bind(bind).await?);
+        let addr = socket.local_addr()?;
+        let (tx, rx) = watch::channel(impairment);
+        let counters = Arc::new(SimCounters::default());
+        let task = tokio::spawn(relay(socket, upstream, rx, Arc::clone(&counters)));
+        Ok(SimLink {
+            addr,
+            impairment: tx,
+            counters,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Applies to datagrams received from now on; ones already in flight keep their delay.
+    pub fn set(&self, impairment: Impairment) {
+        self.impairment.send_replace(impairment);
+    }
+
+    pub fn counters(&self) -> &SimCounters {
+        &self.counters
+    }
+}
+
+impl Drop for SimLink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn relay(
+    socket: Arc<UdpSocket>,
+    upstream: SocketAddr,
+    impairment: watch::Receiver<Impairment>,
+    counters: Arc<SimCounters>,
+) {
+    let mut buf = vec![0u8; 65536];
+    let mut downstream: Option<SocketAddr> = None;
+    // When each direction's link is next idle: index 0 towards upstream, 1 back.
+    let mut next_free = [Instant::now(); 2];
+    // Owns the delayed sends so aborting the relay cancels them too.
+    let mut in_flight = JoinSet::new();
+
+    loop {
+        let (len, src) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        while in_flight.try_join_next().is_some() {}
+
+        let (direction, dest) = if src == upstream {
+            match downstream {
+                Some(dest) => (1, dest),
+                None => continue,
+            }
+        } else {
+            downstream = Some(src);
+            (0, upstream)
+        };
+
+        let impairment = impairment.borrow().clone();
+        let now = Instant::now();
+        let Some(at) = schedule(&impairment, len, now, &mut next_free[direction]) else {
+            counters.dropped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+        counters.forwarded.fetch_add(1, Ordering::Relaxed);
+        let socket = Arc::clone(&socket);
+        let datagram = buf[..len].to_vec();
+        in_flight.spawn(async move {
+            tokio::time::sleep_until(at).await;
+            let _ = socket.send_to(&datagram, dest).await;
+        });
+    }
+}
+
+/// When a datagram of `len` bytes received at `now` should be delivered, or `None` to drop it.
+fn schedule(
+    impairment: &Impairment,
+    len: usize,
+    now: Instant,
+    next_free: &mut Instant,
+) -> Option<Instant> {
+    if impairment.loss > 0.0 && rand::random::<f64>() < impairment.loss {
+        return None;
+    }
+
+    let mut sent = now;
+    if let Some(rate) = impairment.rate_bps.filter(|rate| *rate > 0) {
+        let start = (*next_free).max(now);
+        if start.duration_since(now) > MAX_QUEUE_DELAY {
+            return None;
+        }
+        let serialization = Duration::from_nanos(len as u64 * 8 * 1_000_000_000 / rate);
+        *next_free = start + serialization;
+        sent = *next_free;
+    }
+
+    let spread = |max: Duration| max.mul_f64(rand::random::<f64>());
+    let mut delay = impairment.latency + spread(impairment.jitter);
+    if impairment.reorder > 0.0 && rand::random::<f64>() < impairment.reorder {
+        delay += impairment.latency + impairment.jitter;
+    }
+    Some(sent + delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_queues_then_drops() {
+        let impairment = Impairment {
+            // 8 kbit/s: one 1000-byte datagram per second.
+            rate_bps: Some(8_000),
+            ..Impairment::default()
+        };
+        let now = Instant::now();
+        let mut next_free = now;
+
+        let first = schedule(&impairment, 1000, now, &mut next_free).unwrap();
+        assert_eq!(first, now + Duration::from_secs(1));
+        assert!(schedule(&impairment, 1000, now, &mut next_free).is_none());
+    }
+
+    #[tokio::test]
+    async fn relays_both_ways_and_drops_everything_at_full_loss() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let link = SimLink::spawn(
+            server.local_addr().unwrap(),
+            Impairment {
+                latency: Duration::from_millis(20),
+                ..Impairment::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let started = Instant::now();
+        client.send_to(b"ping", link.addr()).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (len, src) = server.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(src, link.addr());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        server.send_to(b"pong", src).await.unwrap();
+        let (len, src) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        assert_eq!(src, link.addr());
+
+        link.set(Impairment {
+            loss: 1.0,
+            ..Impairment::default()
+        });
+        client.send_to(b"lost", link.addr()).await.unwrap();
+        let lost = tokio::time::timeout(Duration::from_millis(100), server.recv_from(&mut buf));
+        assert!(lost.await.is_err());
+        assert_eq!(link.counters().forwarded.load(Ordering::Relaxed), 2);
+        assert_eq!(link.counters().dropped.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,712 @@
+use crate::config::{InterfaceMode, NetworkConfig, DEFAULT_CHANNEL_DEPTH, DEFAULT_INTERFACE};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::config::{IoBackend, DEFAULT_BATCH_SIZE};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::queue::{self, TunReceiver};
+use crate::stats::StatsRegistry;
+
+#[cfg(unix)]
+use nix::fcntl::{fcntl, FcntlArg};
+use std::future::Future;
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::IntoRawFd;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tun::{Configuration, Layer};
+
+pub struct TunnelDevice {
+    name: String,
+    queues: Vec<Arc<tun::AsyncDevice>>,
+    next_queue: AtomicUsize,
+    /// One per queue with `io_backend: io_uring`; empty otherwise.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    writers: Vec<crate::uring::TunWriter>,
+}
+
+/// One TUN queue as the descriptor a ring thread works on, keeping the device open.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+struct QueueFd(Arc<tun::AsyncDevice>);
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl AsRawFd for QueueFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+fn parse_ip(field: &str, value: &str) -> VtrunkdResult<IpAddr> {
+    value
+        .parse()
+        .map_err(|_| VtrunkdError::InvalidConfig(format!("Invalid tun {}: {}", field, value)))
+}
+
+/// Applies `tun_owner`, `tun_group` and `persistent` through one queue of a new device.
+#[cfg(target_os = "linux")]
+fn set_tun_options(device: &tun::AsyncDevice, config: &NetworkConfig) -> VtrunkdResult<()> {
+    use nix::unistd::{Group, User};
+
+    let fd = device.as_raw_fd();
+    let tun_ioctl = |request: nix::libc::c_ulong, value: nix::libc::c_ulong, what: &str| {
+        // SAFETY: these TUN ioctls take their argument by value, not as a pointer.
+        if unsafe { nix::libc::ioctl(fd, request as _, value) } < 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(create_error(std::io::Error::new(
+                err.kind(),
+                format!("cannot set TUN {}: {}", what, err),
+            )));
+        }
+        Ok(())
+    };
+    if let Some(owner) = &config.tun_owner {
+        let uid = match owner.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => User::from_name(owner)?
+                .ok_or_else(|| {
+                    VtrunkdError::InvalidConfig(format!("Unknown tun_owner user {}", owner))
+                })?
+                .uid
+                .as_raw(),
+        };
+        tun_ioctl(nix::libc::TUNSETOWNER, uid.into(), "owner")?;
+    }
+    if let Some(group) = &config.tun_group {
+        let gid = match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => Group::from_name(group)?
+                .ok_or_else(|| {
+                    VtrunkdError::InvalidConfig(format!("Unknown tun_group group {}", group))
+                })?
+                .gid
+                .as_raw(),
+        };
+        tun_ioctl(nix::libc::TUNSETGROUP, gid.into(), "group")?;
+    }
+    if let Some(persistent) = config.persistent {
+        tun_ioctl(nix::libc::TUNSETPERSIST, persistent.into(), "persistence")?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_tun_options(_device: &tun::AsyncDevice, config: &NetworkConfig) -> VtrunkdResult<()> {
+    if config.tun_owner.is_some() || config.tun_group.is_some() || config.persistent.is_some() {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network tun_owner, tun_group and persistent are only supported on Linux".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Assigns `config.addresses` once the interface exists; `address` is set with the device.
+#[cfg(target_os = "linux")]
+fn add_addresses(name: &str, config: &NetworkConfig) -> VtrunkdResult<()> {
+    for cidr in config.addresses.iter().flatten() {
+        let (address, prefix) = crate::config::parse_cidr(cidr)?;
+        netlink::add_address(name, address, prefix).map_err(|e| {
+            let message = format!("Failed to add address {} to {}: {}", cidr, name, e);
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                VtrunkdError::PermissionDenied(message)
+            } else {
+                VtrunkdError::Network(message)
+            }
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn add_addresses(_name: &str, config: &NetworkConfig) -> VtrunkdResult<()> {
+    if config.addresses.as_ref().is_some_and(|a| !a.is_empty()) {
+        return Err(VtrunkdError::InvalidConfig(
+            "Network addresses are only supported on Linux".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn create_error(err: std::io::Error) -> VtrunkdError {
+    let message = format!("Failed to create TUN device: {}", err);
+    if err.kind() == std::io::ErrorKind::PermissionDenied {
+        VtrunkdError::PermissionDenied(message)
+    } else {
+        VtrunkdError::Network(message)
+    }
+}
+
+impl TunnelDevice {
+    pub fn new(config: &NetworkConfig) -> VtrunkdResult<Self> {
+        let device = TunnelDevice::open(config)?;
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if config.io_backend == Some(IoBackend::IoUring) {
+            return device.with_ring_writers(config);
+        }
+        Ok(device)
+    }
+
+    fn open(config: &NetworkConfig) -> VtrunkdResult<Self> {
+        if let Some(fd) = config.tun_fd {
+            return TunnelDevice::from_fd(fd, config.interface.as_deref());
+        }
+        let name = config
+            .interface
+            .clone()
+            .unwrap_or_else(|| DEFAULT_INTERFACE.to_string());
+        let queues = config.tun_queues.unwrap_or(1);
+        if config.interface_mode.unwrap_or_default() == InterfaceMode::Attach {
+            return TunnelDevice::attach(name, queues);
+        }
+        if queues > 1 {
+            return TunnelDevice::multiqueue(config, name, queues);
+        }
+
+        let mut configuration = Configuration::default();
+        configuration.tun_name(&name);
+        configuration.layer(Layer::L3);
+        configuration.mtu(config.mtu as u16);
+        configuration.up();
+
+        if let Some(address) = &config.address {
+            configuration.address(parse_ip("address", address)?);
+        }
+
+        if let Some(netmask) = &config.netmask {
+            configuration.netmask(parse_ip("netmask", netmask)?);
+        }
+
+        if let Some(destination) = &config.destination {
+            configuration.destination(parse_ip("destination", destination)?);
+        }
+
+        let device = tun::create_as_async(&configuration)
+            .map_err(|e| create_error(std::io::Error::from(e)))?;
+        set_tun_options(&device, config)?;
+        add_addresses(&name, config)?;
+
+        Ok(TunnelDevice::with_queues(name, vec![device]))
+    }
+
+    fn with_queues(name: String, queues: Vec<tun::AsyncDevice>) -> Self {
+        TunnelDevice {
+            name,
+            queues: queues.into_iter().map(Arc::new).collect(),
+            next_queue: AtomicUsize::new(0),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            writers: Vec::new(),
+        }
+    }
+
+    /// Writes every queue from a ring thread of its own, `batch_size` packets at a time.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn with_ring_writers(mut self, config: &NetworkConfig) -> VtrunkdResult<Self> {
+        let depth = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        let channel_depth = config.channel_depth.unwrap_or(DEFAULT_CHANNEL_DEPTH);
+        self.writers = self
+            .queues
+            .iter()
+            .map(|queue| {
+                crate::uring::spawn_tun_writer(
+                    Arc::new(QueueFd(Arc::clone(queue))),
+                    config.buffer_size,
+                    depth,
+                    channel_depth,
+                )
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(self)
+    }
+
+    /// Opens `queues` queues of one `IFF_MULTI_QUEUE` device so the kernel spreads flows
+    /// across them and each queue gets its own reader task.
+    #[cfg(target_os = "linux")]
+    fn multiqueue(config: &NetworkConfig, name: String, queues: usize) -> VtrunkdResult<Self> {
+        let fds = multiqueue::open_queues(&name, queues)?;
+        let device = TunnelDevice::from_queue_fds(name, fds)?;
+        set_tun_options(&device.queues[0], config)?;
+        multiqueue::configure(&device.name, config)?;
+        add_addresses(&device.name, config)?;
+        Ok(device)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn multiqueue(_config: &NetworkConfig, _name: String, _queues: usize) -> VtrunkdResult<Self> {
+        Err(VtrunkdError::InvalidConfig(
+            "Network tun_queues above 1 is only supported on Linux".to_string(),
+        ))
+    }
+
+    /// Opens an interface someone else created and configured, leaving its MTU, addresses
+    /// and link state alone. Persistent devices owned by our user need no privileges.
+    #[cfg(target_os = "linux")]
+    fn attach(name: String, queues: usize) -> VtrunkdResult<Self> {
+        let sysfs = std::path::Path::new("/sys/class/net").join(&name);
+        if !sysfs.exists() {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "TUN device {} does not exist; interface_mode attach needs it created first",
+                name
+            )));
+        }
+        if !sysfs.join("tun_flags").exists() {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "Interface {} is not a TUN device",
+                name
+            )));
+        }
+        let fds = multiqueue::open_queues(&name, queues)?;
+        TunnelDevice::from_queue_fds(name, fds)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn attach(_name: String, _queues: usize) -> VtrunkdResult<Self> {
+        Err(VtrunkdError::InvalidConfig(
+            "Network interface_mode attach is only supported on Linux".to_string(),
+        ))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn from_queue_fds(name: String, fds: Vec<std::os::fd::OwnedFd>) -> VtrunkdResult<Self> {
+        let devices = fds
+            .into_iter()
+            .map(|fd| {
+                let mut configuration = Configuration::default();
+                configuration.raw_fd(fd.into_raw_fd());
+                configuration.close_fd_on_drop(true);
+                tun::create_as_async(&configuration)
+                    .map_err(|e| create_error(std::io::Error::from(e)))
+            })
+            .collect::<VtrunkdResult<Vec<_>>>()?;
+        Ok(TunnelDevice::with_queues(name, devices))
+    }
+
+    /// Wraps a TUN descriptor opened and configured by someone else, with the other queues
+    /// taken over alongside it in an upgrade.
+    ///
+    /// The descriptors are not closed on drop, so the supervisor can wrap them again after a
+    /// restart.
+    #[cfg(unix)]
+    pub fn from_fd(fd: RawFd, interface: Option<&str>) -> VtrunkdResult<Self> {
+        let devices = crate::activation::tun_queues(fd)
+            .into_iter()
+            .map(|fd| {
+                fcntl(fd, FcntlArg::F_GETFD).map_err(|e| {
+                    VtrunkdError::InvalidConfig(format!(
+                        "TUN file descriptor {} is not open: {}",
+                        fd, e
+                    ))
+                })?;
+                let mut configuration = Configuration::default();
+                configuration.raw_fd(fd);
+                configuration.close_fd_on_drop(false);
+                tun::create_as_async(&configuration).map_err(|e| {
+                    VtrunkdError::Network(format!(
+                        "Failed to use TUN file descriptor {}: {}",
+                        fd, e
+                    ))
+                })
+            })
+            .collect::<VtrunkdResult<Vec<_>>>()?;
+        let name = interface
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("fd{}", fd));
+        Ok(TunnelDevice::with_queues(name, devices))
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_fd(_fd: i32, _interface: Option<&str>) -> VtrunkdResult<Self> {
+        Err(VtrunkdError::InvalidConfig(
+            "Network tun_fd is only supported on Unix".to_string(),
+        ))
+    }
+
+    /// Where the tunnel loop takes TUN packets from.
+    ///
+    /// A single queue without io_uring or a drop policy is read by the loop itself, in
+    /// place. Otherwise every queue is read on its own task into one bounded channel;
+    /// dropping the result stops those readers, and a read error ends that queue's reader
+    /// after it is forwarded.
+    pub fn packets(
+        &self,
+        network: &NetworkConfig,
+        stats: &Arc<StatsRegistry>,
+    ) -> VtrunkdResult<TunPackets> {
+        let buffer_size = network.buffer_size;
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        let uring = network.io_backend == Some(IoBackend::IoUring);
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let uring = false;
+        if self.queues.len() == 1 && !uring && network.tun_drop_policy.is_none() {
+            return Ok(TunPackets::Direct {
+                queue: Arc::clone(&self.queues[0]),
+                buffer: vec![0u8; buffer_size],
+                len: 0,
+            });
+        }
+        let (tx, rx) = queue::channel(
+            network.channel_depth.unwrap_or(DEFAULT_CHANNEL_DEPTH),
+            network.tun_drop_policy.unwrap_or_default(),
+            Arc::clone(stats),
+        );
+        let mut readers = JoinSet::new();
+        for queue in &self.queues {
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            if uring {
+                let depth = network.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+                crate::uring::spawn_tun_reader(
+                    &mut readers,
+                    Arc::new(QueueFd(Arc::clone(queue))),
+                    buffer_size,
+                    depth,
+                    tx.clone(),
+                )?;
+                continue;
+            }
+            let queue = Arc::clone(queue);
+            let tx = tx.clone();
+            readers.spawn(async move {
+                let mut buf = vec![0u8; buffer_size];
+                loop {
+                    match queue.recv(&mut buf).await {
+                        Ok(size) => {
+                            if !tx.push(buf[..size].to_vec()) {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            tx.push_error(err);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+        Ok(TunPackets::Queued {
+            _readers: readers,
+            rx,
+            packet: Vec::new(),
+        })
+    }
+
+    /// Writes to the queues in turn; any queue reaches the kernel's stack. Through io_uring
+    /// the write completes later, and a failure is returned by a later call.
+    pub async fn write_packet(&self, data: &[u8]) -> VtrunkdResult<()> {
+        let index = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(writer) = self.writers.get(index) {
+            writer.write(data).await?;
+            return Ok(());
+        }
+        self.queues[index].send(data).await?;
+        Ok(())
+    }
+
+    /// Writes a batch to one queue, which keeps each flow's packets in order. Through
+    /// io_uring the whole batch is queued before the ring thread submits it.
+    pub async fn write_packets(&self, packets: &[Vec<u8>]) -> VtrunkdResult<()> {
+        let index = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(writer) = self.writers.get(index) {
+            writer.write_batch(packets).await?;
+            return Ok(());
+        }
+        for packet in packets {
+            self.queues[index].send(packet).await?;
+        }
+        Ok(())
+    }
+
+    pub fn queue_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Every queue, for another process to take over.
+    #[cfg(unix)]
+    pub fn queue_fds(&self) -> Vec<RawFd> {
+        self.queues.iter().map(|queue| queue.as_raw_fd()).collect()
+    }
+}
+
+/// TUN packets for the tunnel loop, from [`TunnelDevice::packets`].
+pub enum TunPackets {
+    Direct {
+        queue: Arc<tun::AsyncDevice>,
+        buffer: Vec<u8>,
+        len: usize,
+    },
+    Queued {
+        _readers: JoinSet<()>,
+        rx: TunReceiver,
+        packet: Vec<u8>,
+    },
+}
+
+impl TunPackets {
+    /// Waits for the next packet, which [`packet`](Self::packet) then returns. Cancel-safe;
+    /// `None` once every reader has stopped.
+    pub async fn recv(&mut self) -> Option<std::io::Result<()>> {
+        match self {
+            TunPackets::Direct { queue, buffer, len } => {
+                Some(queue.recv(buffer).await.map(|size| *len = size))
+            }
+            TunPackets::Queued { rx, packet, .. } => {
+                rx.recv().await.map(|item| item.map(|next| *packet = next))
+            }
+        }
+    }
+
+    /// Takes the next packet if one is ready without waiting; `false` when none is.
+    pub fn try_recv(&mut self) -> std::io::Result<bool> {
+        match self {
+            TunPackets::Direct { queue, buffer, len } => {
+                let read = std::pin::pin!(queue.recv(buffer));
+                let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+                match read.poll(&mut context) {
+                    std::task::Poll::Ready(size) => {
+                        *len = size?;
+                        Ok(true)
+                    }
+                    std::task::Poll::Pending => Ok(false),
+                }
+            }
+            TunPackets::Queued { rx, packet, .. } => match rx.try_recv() {
+                Some(item) => {
+                    *packet = item?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+        }
+    }
+
+    /// The packet last received.
+    pub fn packet(&mut self) -> &mut [u8] {
+        match self {
+            TunPackets::Direct { buffer, len, .. } => &mut buffer[..*len],
+            TunPackets::Queued { packet, .. } => packet,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod multiqueue {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+    use nix::fcntl::{open, OFlag};
+    use nix::libc;
+    use nix::sys::socket::{socket, AddressFamily, SockFlag, SockType};
+    use nix::sys::stat::Mode;
+
+    use super::{create_error, parse_ip};
+    use crate::config::NetworkConfig;
+    use crate::error::{VtrunkdError, VtrunkdResult};
+
+    fn ifreq(name: &str) -> VtrunkdResult<libc::ifreq> {
+        if name.is_empty() || name.len() >= libc::IFNAMSIZ {
+            return Err(VtrunkdError::InvalidConfig(format!(
+                "Invalid tun interface name: {}",
+                name
+            )));
+        }
+        // SAFETY: ifreq is plain old data; all zeroes is a valid value.
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        Ok(req)
+    }
+
+    fn ioctl(fd: RawFd, request: libc::c_ulong, req: &mut libc::ifreq) -> io::Result<()> {
+        // SAFETY: `req` is a valid ifreq that outlives the call.
+        if unsafe { libc::ioctl(fd, request as _, req as *mut libc::ifreq) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Opens `queues` descriptors attached to the same TUN interface, multi-queue when
+    /// there is more than one.
+    pub fn open_queues(name: &str, queues: usize) -> VtrunkdResult<Vec<OwnedFd>> {
+        let mut flags = libc::IFF_TUN | libc::IFF_NO_PI;
+        if queues > 1 {
+            flags |= libc::IFF_MULTI_QUEUE;
+        }
+        let mut fds = Vec::with_capacity(queues);
+        for _ in 0..queues {
+            let fd = open(
+                "/dev/net/tun",
+                OFlag::O_RDWR | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )
+            .map_err(|e| create_error(e.into()))?;
+            // SAFETY: `open` just returned this descriptor and nothing else owns it.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let mut req = ifreq(name)?;
+            req.ifr_ifru.ifru_flags = flags as libc::c_short;
+            ioctl(fd.as_raw_fd(), libc::TUNSETIFF as _, &mut req).map_err(create_error)?;
+            fds.push(fd);
+        }
+        Ok(fds)
+    }
+
+    fn set_ipv4(
+        sock: RawFd,
+        name: &str,
+        request: libc::c_ulong,
+        field: &str,
+        value: &str,
+    ) -> VtrunkdResult<()> {
+        let addr: Ipv4Addr = match parse_ip(field, value)? {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => {
+                return Err(VtrunkdError::InvalidConfig(format!(
+                    "Network tun_queues above 1 only supports an IPv4 {}",
+                    field
+                )))
+            }
+        };
+        let sin = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr.octets()),
+            },
+            sin_zero: [0; 8],
+        };
+        let mut req = ifreq(name)?;
+        // SAFETY: sockaddr_in fits in the sockaddr slot of the ifreq union.
+        unsafe {
+            std::ptr::write(
+                std::ptr::addr_of_mut!(req.ifr_ifru.ifru_addr).cast::<libc::sockaddr_in>(),
+                sin,
+            );
+        }
+        ioctl(sock, request, &mut req).map_err(create_error)
+    }
+
+    /// Applies MTU, IPv4 addressing and brings the interface up, as `tun` does for one queue.
+    pub fn configure(name: &str, config: &NetworkConfig) -> VtrunkdResult<()> {
+        let sock = socket(
+            AddressFamily::Inet,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        // SAFETY: `socket` just returned this descriptor and nothing else owns it.
+        let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+        let fd = sock.as_raw_fd();
+
+        let mut req = ifreq(name)?;
+        req.ifr_ifru.ifru_mtu = config.mtu as libc::c_int;
+        ioctl(fd, libc::SIOCSIFMTU, &mut req).map_err(create_error)?;
+
+        if let Some(address) = &config.address {
+            set_ipv4(fd, name, libc::SIOCSIFADDR, "address", address)?;
+        }
+        if let Some(netmask) = &config.netmask {
+            set_ipv4(fd, name, libc::SIOCSIFNETMASK, "netmask", netmask)?;
+        }
+        if let Some(destination) = &config.destination {
+            set_ipv4(fd, name, libc::SIOCSIFDSTADDR, "destination", destination)?;
+        }
+
+        let mut req = ifreq(name)?;
+        ioctl(fd, libc::SIOCGIFFLAGS, &mut req).map_err(create_error)?;
+        // SAFETY: SIOCGIFFLAGS filled in the flags member.
+        let flags = unsafe { req.ifr_ifru.ifru_flags };
+        req.ifr_ifru.ifru_flags = flags | (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+        ioctl(fd, libc::SIOCSIFFLAGS, &mut req).map_err(create_error)
+    }
+}
+
+/// Just enough rtnetlink to add an address: `ioctl` only knows one IPv4 address per
+/// interface, and none for IPv6 on a TUN device.
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::net::IpAddr;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use nix::libc;
+    use nix::net::if_::if_nametoindex;
+    use nix::sys::socket::{
+        recv, sendto, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol,
+        SockType,
+    };
+
+    const NLMSG_HEADER: usize = 16;
+
+    fn attribute(message: &mut Vec<u8>, kind: u16, data: &[u8]) {
+        message.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(data);
+        message.resize((message.len() + 3) & !3, 0);
+    }
+
+    /// `RTM_NEWADDR` for `address/prefix` on interface `index`; replaces an existing entry
+    /// so a restart on a persistent device does not fail.
+    fn new_address_message(index: u32, address: IpAddr, prefix: u8) -> Vec<u8> {
+        let (family, octets) = match address {
+            IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+            IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+        };
+        let flags =
+            libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+        let mut message = Vec::with_capacity(64);
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&libc::RTM_NEWADDR.to_ne_bytes());
+        message.extend_from_slice(&(flags as u16).to_ne_bytes());
+        message.extend_from_slice(&1u32.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        // struct ifaddrmsg
+        message.extend_from_slice(&[family as u8, prefix, 0, libc::RT_SCOPE_UNIVERSE]);
+        message.extend_from_slice(&index.to_ne_bytes());
+        attribute(&mut message, libc::IFA_LOCAL, &octets);
+        attribute(&mut message, libc::IFA_ADDRESS, &octets);
+        let length = message.len() as u32;
+        message[..4].copy_from_slice(&length.to_ne_bytes());
+        message
+    }
+
+    pub fn add_address(name: &str, address: IpAddr, prefix: u8) -> io::Result<()> {
+        let index = if_nametoindex(name)?;
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkRoute,
+        )?;
+        // SAFETY: `socket` just returned this descriptor and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let message = new_address_message(index, address, prefix);
+        sendto(
+            fd.as_raw_fd(),
+            &message,
+            &NetlinkAddr::new(0, 0),
+            MsgFlags::empty(),
+        )?;
+
+        let mut reply = [0u8; 1024];
+        let size = recv(fd.as_raw_fd(), &mut reply, MsgFlags::empty())?;
+        if size < NLMSG_HEADER + 4
+            || u16::from_ne_bytes([reply[4], reply[5]]) != libc::NLMSG_ERROR as u16
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected netlink reply",
+            ));
+        }
+        let error = i32::from_ne_bytes([reply[16], reply[17], reply[18], reply[19]]);
+        if error < 0 {
+            return Err(io::Error::from_raw_os_error(-error));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Holds TUN packets while no link is available, for `wireguard.links_down: buffer`, so a
+//! short outage (a modem re-registering, a cable swapped) costs delay instead of loss.
+//! Packets are kept before encryption and sent in the order they were read once a link
+//! comes back; those held longer than the hold time, or pushed out by newer ones once
+//! the buffer is full, are dropped.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::stats::{self, TunnelCounters};
+
+pub struct Outage {
+    packets: VecDeque<(Instant, Vec<u8>)>,
+    limit: usize,
+    hold: Duration,
+}
+
+impl Outage {
+    pub fn new(limit: usize, hold: Duration) -> Self {
+        Outage {
+            packets: VecDeque::new(),
+            limit: limit.max(1),
+            hold,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn push(&mut self, packet: &[u8], now: Instant, stats: &TunnelCounters) {
+        self.expire(now, stats);
+        if self.packets.len() == self.limit {
+            self.packets.pop_front();
+            stats::add(&stats.outage_dropped, 1);
+        }
+        self.packets.push_back((now, packet.to_vec()));
+        self.record_depth(stats);
+    }
+
+    /// Drops the packets held longer than the hold time by `now`.
+    pub fn expire(&mut self, now: Instant, stats: &TunnelCounters) {
+        let before = self.packets.len();
+        while self
+            .packets
+            .front()
+            .is_some_and(|(held, _)| now.duration_since(*held) >= self.hold)
+        {
+            self.packets.pop_front();
+        }
+        stats::add(&stats.outage_dropped, (before - self.packets.len()) as u64);
+        self.record_depth(stats);
+    }
+
+    /// Hands back the packets still worth sending, oldest first.
+    pub fn take(&mut self, now: Instant, stats: &TunnelCounters) -> Vec<Vec<u8>> {
+        self.expire(now, stats);
+        let packets: Vec<Vec<u8>> = self.packets.drain(..).map(|(_, p)| p).collect();
+        stats::add(&stats.outage_replayed, packets.len() as u64);
+        self.record_depth(stats);
+        packets
+    }
+
+    fn record_depth(&self, stats: &TunnelCounters) {
+        stats
+            .outage_depth
+            .store(self.packets.len() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_packets_for_the_hold_time() {
+        let stats = TunnelCounters::default();
+        let mut outage = Outage::new(2, Duration::from_secs(5));
+        let now = Instant::now();
+        outage.push(b"one", now, &stats);
+        outage.push(b"two", now + Duration::from_secs(1), &stats);
+        outage.push(b"three", now + Duration::from_secs(2), &stats);
+        assert_eq!(stats.outage_depth.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.outage_dropped.load(Ordering::Relaxed), 1);
+
+        // "two" has been held for the whole hold time.
+        let replayed = outage.take(now + Duration::from_secs(6), &stats);
+        assert_eq!(replayed, [b"three".to_vec()]);
+        assert!(outage.is_empty());
+        assert_eq!(stats.outage_depth.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.outage_dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.outage_replayed.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+#[cfg(target_os = "linux")]
+const PID_DIR: &str = "/run";
+#[cfg(not(target_os = "linux"))]
+const PID_DIR: &str = "/var/run";
+
+/// An exclusively locked PID file, removed again on drop.
+///
+/// The lock is an `flock` on the open file (`LockFileEx` on Windows), so it
+/// is released by the kernel if the daemon dies without cleaning up, and a
+/// stale file never blocks a restart.
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+/// `/run/vtrunkd.pid`, or `/run/vtrunkd-<interface>.pid` when the TUN name is
+/// configured so instances driving different interfaces can coexist.
+pub fn default_path(config: &Config) -> PathBuf {
+    let name = match config.network.interface.as_deref() {
+        Some(interface) if !interface.is_empty() => format!("vtrunkd-{}.pid", interface),
+        _ => "vtrunkd.pid".to_string(),
+    };
+    Path::new(PID_DIR).join(name)
+}
+
+impl PidFile {
+    pub fn acquire(path: &Path) -> VtrunkdResult<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| {
+                let message = format!("Failed to open PID file {:?}: {}", path, e);
+                if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    VtrunkdError::PermissionDenied(message)
+                } else {
+                    VtrunkdError::Config(message)
+                }
+            })?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut contents = String::new();
+                let _ = file.read_to_string(&mut contents);
+                return Err(VtrunkdError::AlreadyRunning(format!(
+                    "{:?} is locked by PID {}",
+                    path,
+                    contents.trim()
+                )));
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        let mut pid_file = PidFile {
+            path: path.to_path_buf(),
+            file,
+        };
+        pid_file.write_pid()?;
+        Ok(pid_file)
+    }
+
+    /// Rewrites the file with the current PID; call again after forking.
+    pub fn write_pid(&mut self) -> VtrunkdResult<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_instance_is_refused() {
+        let path = std::env::temp_dir().join(format!("vtrunkd-test-{}.pid", std::process::id()));
+        let first = PidFile::acquire(&path).expect("first lock");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+
+        let second = PidFile::acquire(&path);
+        assert!(matches!(second, Err(VtrunkdError::AlreadyRunning(_))));
+
+        drop(first);
+        assert!(!path.exists());
+        let third = PidFile::acquire(&path).expect("lock after release");
+        drop(third);
+    }
+
+    #[test]
+    fn default_path_includes_interface() {
+        let mut config = Config::default();
+        assert_eq!(
+            default_path(&config),
+            Path::new(PID_DIR).join("vtrunkd.pid")
+        );
+        config.network.interface = Some("tun7".to_string());
+        assert_eq!(
+            default_path(&config),
+            Path::new(PID_DIR).join("vtrunkd-tun7.pid")
+        );
+    }
+}
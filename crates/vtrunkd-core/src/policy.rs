@@ -0,0 +1,302 @@
+//! Time-of-day link policies: a small task that sets links' admin states as the clock
+//! enters and leaves the windows in `policies`, for uplinks with off-peak data or links
+//! that should only carry traffic in business hours.
+//!
+//! A link's state is only set when the policies' answer for it changes, so a `vtrunkd link`
+//! command given in the meantime holds until the next window starts or ends. When the
+//! tunnel restarts, its links start up again and the policies are applied afresh.
+
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use nix::libc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::config::{Config, LinkPolicy};
+use crate::control::{self, ControlCommand, ControlRequest, LinkAdminState};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::TunnelEvent;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug)]
+struct Rule {
+    link: String,
+    /// Minutes since midnight; `to` is before `from` when the window runs past midnight.
+    /// `to` is 1440 for a window running to the end of the day.
+    from: u32,
+    to: u32,
+    /// Bit 0 for Monday through bit 6 for Sunday.
+    days: u8,
+    state: LinkAdminState,
+}
+
+/// The parsed `policies`.
+#[derive(Debug)]
+pub struct Schedule {
+    rules: Vec<Rule>,
+}
+
+impl Schedule {
+    pub fn parse(policies: &[LinkPolicy]) -> VtrunkdResult<Self> {
+        let rules = policies
+            .iter()
+            .map(|policy| {
+                let from = minute_of_day(&policy.from)
+                    .filter(|from| *from < MINUTES_PER_DAY)
+                    .ok_or_else(|| invalid_time(&policy.link, &policy.from))?;
+                let to = minute_of_day(&policy.to)
+                    .ok_or_else(|| invalid_time(&policy.link, &policy.to))?;
+                if from == to {
+                    return Err(VtrunkdError::InvalidConfig(format!(
+                        "Policy for {} must end at another time than it starts",
+                        policy.link
+                    )));
+                }
+                let days = match &policy.days {
+                    Some(days) if days.is_empty() => {
+                        return Err(VtrunkdError::InvalidConfig(format!(
+                            "Policy for {} lists no days; omit days for every day",
+                            policy.link
+                        )));
+                    }
+                    Some(days) => days.iter().fold(0, |mask, day| mask | 1 << *day as u8),
+                    None => 0x7f,
+                };
+                Ok(Rule {
+                    link: policy.link.clone(),
+                    from,
+                    to,
+                    days,
+                    state: policy.state,
+                })
+            })
+            .collect::<VtrunkdResult<_>>()?;
+        Ok(Schedule { rules })
+    }
+
+    /// The state each link named in a rule should be in at `minute` past midnight on
+    /// `weekday` (0 for Monday): that of the last rule applying to it, or up.
+    fn states(&self, weekday: u8, minute: u32) -> Vec<(&str, LinkAdminState)> {
+        let mut states: Vec<(&str, LinkAdminState)> = Vec::new();
+        for rule in &self.rules {
+            let index = match states.iter().position(|(link, _)| *link == rule.link) {
+                Some(index) => index,
+                None => {
+                    states.push((&rule.link, LinkAdminState::Up));
+                    states.len() - 1
+                }
+            };
+            if rule.applies(weekday, minute) {
+                states[index].1 = rule.state;
+            }
+        }
+        states
+    }
+}
+
+impl Rule {
+    fn applies(&self, weekday: u8, minute: u32) -> bool {
+        let on = |day: u8| self.days & (1 << day) != 0;
+        if self.from < self.to {
+            on(weekday) && (self.from..self.to).contains(&minute)
+        } else {
+            (on(weekday) && minute >= self.from) || (on((weekday + 6) % 7) && minute < self.to)
+        }
+    }
+}
+
+/// `HH:MM` as minutes since midnight; `24:00` stands for the end of the day.
+fn minute_of_day(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    let minute = hours * 60 + minutes;
+    (minutes < 60 && minute <= MINUTES_PER_DAY).then_some(minute)
+}
+
+fn invalid_time(link: &str, value: &str) -> VtrunkdError {
+    VtrunkdError::InvalidConfig(format!(
+        "Policy for {} has invalid time {:?} (expected HH:MM)",
+        link, value
+    ))
+}
+
+/// The weekday (0 for Monday) and minute of the day at `time` in the local time zone.
+fn local_time(time: SystemTime) -> (u8, u32) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    local_clock(secs).unwrap_or_else(|| {
+        // No time zone to go by; 1970-01-01 was a Thursday.
+        let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+        (((days + 3) % 7) as u8, (secs_of_day / 60) as u32)
+    })
+}
+
+#[cfg(unix)]
+fn local_clock(secs: u64) -> Option<(u8, u32)> {
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    let clock = secs as libc::time_t;
+    if unsafe { libc::localtime_r(&clock, &mut tm) }.is_null() {
+        return None;
+    }
+    Some((
+        ((tm.tm_wday + 6) % 7) as u8,
+        (tm.tm_hour * 60 + tm.tm_min) as u32,
+    ))
+}
+
+/// Policies follow UTC where there is no `localtime_r`.
+#[cfg(not(unix))]
+fn local_clock(_secs: u64) -> Option<(u8, u32)> {
+    None
+}
+
+fn until_next_minute(time: SystemTime) -> Duration {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let into_minute = since_epoch.as_millis() % 60_000;
+    Duration::from_millis((60_000 - into_minute) as u64)
+}
+
+/// Applies the configured policies through `control` until the tunnel is dropped.
+pub fn spawn(
+    config: &Config,
+    control: mpsc::Sender<ControlRequest>,
+    mut events: broadcast::Receiver<TunnelEvent>,
+) {
+    let Some(policies) = &config.policies else {
+        return;
+    };
+    let schedule = match Schedule::parse(policies) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            warn!("Link policies not applied: {}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        // The state the policies last set on each link; links start up.
+        let mut applied: HashMap<String, LinkAdminState> = HashMap::new();
+        loop {
+            let (weekday, minute) = local_time(SystemTime::now());
+            for (link, state) in schedule.states(weekday, minute) {
+                if applied.get(link).copied().unwrap_or_default() == state {
+                    continue;
+                }
+                let command = ControlCommand::SetLinkState {
+                    name: link.to_string(),
+                    state,
+                };
+                match control::dispatch(&control, command, "policy").await {
+                    Ok(_) => {
+                        info!(
+                            link,
+                            event = "link_policy",
+                            "Link policy sets {} {}",
+                            link,
+                            state
+                        );
+                        applied.insert(link.to_string(), state);
+                    }
+                    Err(e) => warn!("Link policy could not set {} {}: {}", link, state, e),
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(until_next_minute(SystemTime::now())) => {}
+                event = events.recv() => match event {
+                    Ok(TunnelEvent::TunnelRestart { .. }) => applied.clear(),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Weekday;
+
+    fn policy(link: &str, from: &str, to: &str, days: Option<Vec<Weekday>>) -> LinkPolicy {
+        LinkPolicy {
+            link: link.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            days,
+            state: LinkAdminState::Down,
+        }
+    }
+
+    fn state(schedule: &Schedule, weekday: u8, time: &str) -> Vec<LinkAdminState> {
+        let minute = minute_of_day(time).unwrap();
+        schedule
+            .states(weekday, minute)
+            .into_iter()
+            .map(|(_, state)| state)
+            .collect()
+    }
+
+    #[test]
+    fn windows_follow_the_clock_and_the_days() {
+        use LinkAdminState::{Down, Drain, Up};
+        let weekdays = vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ];
+        let mut business = policy("lte", "09:00", "17:00", Some(weekdays));
+        business.state = Drain;
+        let schedule = Schedule::parse(&[
+            policy("lte", "22:00", "06:00", Some(vec![Weekday::Fri])),
+            business,
+            policy("sat", "00:00", "24:00", Some(vec![Weekday::Sun])),
+        ])
+        .unwrap();
+
+        assert_eq!(state(&schedule, 0, "08:59"), [Up, Up]);
+        assert_eq!(state(&schedule, 0, "09:00"), [Drain, Up]);
+        assert_eq!(state(&schedule, 4, "16:59"), [Drain, Up]);
+        assert_eq!(state(&schedule, 4, "17:00"), [Up, Up]);
+        assert_eq!(state(&schedule, 4, "22:00"), [Down, Up]);
+        // Friday's night window runs into Saturday morning.
+        assert_eq!(state(&schedule, 5, "05:59"), [Down, Up]);
+        assert_eq!(state(&schedule, 5, "06:00"), [Up, Up]);
+        assert_eq!(state(&schedule, 3, "23:00"), [Up, Up]);
+        assert_eq!(state(&schedule, 6, "23:59"), [Up, Down]);
+        assert_eq!(state(&schedule, 0, "00:00"), [Up, Up]);
+    }
+
+    #[test]
+    fn parse_rejects_bad_windows() {
+        for (from, to) in [
+            ("9:00", "17:00"),
+            ("24:00", "06:00"),
+            ("06:60", "07:00"),
+            ("00:00", "24:00x"),
+        ] {
+            assert!(
+                Schedule::parse(&[policy("lte", from, to, None)]).is_err(),
+                "{} {}",
+                from,
+                to
+            );
+        }
+        assert!(Schedule::parse(&[policy("lte", "06:00", "06:00", None)]).is_err());
+        assert!(Schedule::parse(&[policy("lte", "00:00", "24:00", Some(vec![]))]).is_err());
+        assert!(Schedule::parse(&[policy("lte", "00:00", "24:00", None)]).is_ok());
+        assert!(until_next_minute(UNIX_EPOCH + Duration::from_secs(90)) == Duration::from_secs(30));
+    }
+}
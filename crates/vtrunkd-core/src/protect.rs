@@ -0,0 +1,65 @@
+//! Keeps link sockets out of a VPN that captures all traffic, such as Android's
+//! `VpnService`, whose `protect()` must see every socket before it sends.
+
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::sync::{Arc, RwLock};
+
+#[cfg(unix)]
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+/// Sockets elsewhere are not descriptors, and the callback is never run there.
+#[cfg(not(unix))]
+type RawFd = std::ffi::c_int;
+
+/// Returns false if the socket could not be excluded from the VPN.
+pub type Protector = Arc<dyn Fn(RawFd) -> bool + Send + Sync>;
+
+static PROTECTOR: RwLock<Option<Protector>> = RwLock::new(None);
+
+/// Installs the callback run on each link socket as it is opened; `None` removes it.
+///
+/// It applies to every tunnel in the process, including sockets opened on a restart.
+pub fn set(protector: Option<Protector>) {
+    *PROTECTOR.write().unwrap_or_else(|e| e.into_inner()) = protector;
+}
+
+/// Runs the installed callback, if any, on link `name`'s socket.
+#[cfg(unix)]
+pub(crate) fn socket(name: &str, fd: RawFd) -> VtrunkdResult<()> {
+    let protector = PROTECTOR.read().unwrap_or_else(|e| e.into_inner()).clone();
+    match protector {
+        Some(protect) if !protect(fd) => Err(VtrunkdError::Network(format!(
+            "Could not protect the {} socket from the VPN",
+            name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn protector_sees_the_socket_and_can_refuse() {
+        // Other tests open link sockets while this one runs, so only impossible
+        // descriptors are refused.
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        set(Some(Arc::new(move |fd| {
+            recorded.lock().unwrap().push(fd);
+            fd != -2
+        })));
+        assert!(socket("link-0", -1).is_ok());
+        assert!(seen.lock().unwrap().contains(&-1));
+        assert!(matches!(
+            socket("link-1", -2),
+            Err(VtrunkdError::Network(_))
+        ));
+
+        set(None);
+        assert!(socket("link-1", -2).is_ok());
+    }
+}
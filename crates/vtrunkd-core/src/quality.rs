@@ -0,0 +1,122 @@
+//! A link's quality as one score from 0 to 100, made of its health-check round trip, the
+//! jitter of that round trip, ping loss and how often it went down lately. The score
+//! follows the link continuously, so `failover_by_quality` can prefer the link that works
+//! best now over the one configured with the highest weight.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a link going down counts against it.
+const FAILURE_WINDOW: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+pub struct Quality {
+    /// Smoothed round trip and its mean deviation in ms, as RFC 6298 keeps them for TCP.
+    srtt: Option<f64>,
+    rttvar: f64,
+    /// Smoothed share of pings that went unanswered, 0 to 1.
+    loss: f64,
+    /// The link's ping and pong counters when loss was last taken in.
+    pings_sent: u64,
+    pongs_received: u64,
+    /// When the link went down within `FAILURE_WINDOW`.
+    failures: VecDeque<Instant>,
+}
+
+impl Quality {
+    pub fn record_rtt(&mut self, rtt_ms: u64) {
+        let rtt = rtt_ms as f64;
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2.0;
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 0.75 + (srtt - rtt).abs() * 0.25;
+                self.srtt = Some(srtt * 0.875 + rtt * 0.125);
+            }
+        }
+    }
+
+    /// Takes in the pings answered since the last call, from the link's running counters.
+    /// Called before each round of health pings, so the previous round has had its time.
+    pub fn record_loss(&mut self, pings_sent: u64, pongs_received: u64) {
+        let pings = pings_sent.saturating_sub(self.pings_sent);
+        let pongs = pongs_received.saturating_sub(self.pongs_received);
+        self.pings_sent = pings_sent;
+        self.pongs_received = pongs_received;
+        if pings == 0 {
+            return;
+        }
+        let lost = 1.0 - (pongs.min(pings) as f64 / pings as f64);
+        self.loss = self.loss * 0.875 + lost * 0.125;
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        while self
+            .failures
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > FAILURE_WINDOW)
+        {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+    }
+
+    /// 100 for a fast, steady link that answers every ping and has not gone down; `None`
+    /// until its first round trip is measured.
+    pub fn score(&self, now: Instant) -> Option<u64> {
+        let srtt = self.srtt?;
+        let failures = self
+            .failures
+            .iter()
+            .filter(|at| now.duration_since(**at) <= FAILURE_WINDOW)
+            .count();
+        // A point for each 10ms of round trip, 5ms of jitter and half a percent of loss,
+        // and ten for each recent failure, each capped so none alone empties the score.
+        let penalty = (srtt / 10.0).min(30.0)
+            + (self.rttvar / 5.0).min(20.0)
+            + (self.loss * 200.0).min(40.0)
+            + (failures as f64 * 10.0).min(30.0);
+        Some((100.0 - penalty).max(0.0).round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_falls_with_delay_jitter_loss_and_failures() {
+        let now = Instant::now();
+        let mut quality = Quality::default();
+        assert_eq!(quality.score(now), None);
+        for _ in 0..50 {
+            quality.record_rtt(20);
+        }
+        let steady = quality.score(now).unwrap();
+        assert_eq!(steady, 98);
+
+        let mut jittery = Quality::default();
+        for rtt in [20, 120].repeat(25) {
+            jittery.record_rtt(rtt);
+        }
+        assert!(jittery.score(now).unwrap() < steady - 10);
+
+        // Half the pings lost for a while.
+        let (mut pings, mut pongs) = (0, 0);
+        for round in 0..40 {
+            pings += 1;
+            pongs += round % 2;
+            quality.record_loss(pings, pongs);
+        }
+        let lossy = quality.score(now).unwrap();
+        assert!((58..=70).contains(&lossy), "score {}", lossy);
+
+        quality.record_failure(now);
+        quality.record_failure(now);
+        assert_eq!(quality.score(now), Some(lossy - 20));
+        // Failures stop counting once they are old enough.
+        assert_eq!(quality.score(now + FAILURE_WINDOW * 2), Some(lossy));
+    }
+}
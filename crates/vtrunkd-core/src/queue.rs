@@ -0,0 +1,335 @@
+//! Bounded queue from the TUN readers to the tunnel loop. When the links cannot keep up,
+//! the loop stops taking packets and the queue fills; `network.tun_drop_policy` then
+//! decides which packet is lost, and every loss is counted in `tun_rx_overflow`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::Notify;
+
+use crate::config::DropPolicy;
+use crate::stats::{self, TunnelCounters};
+
+/// A packet read from the TUN device, or the read error that stopped a queue.
+pub type TunItem = io::Result<Vec<u8>>;
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+    depth: usize,
+    policy: DropPolicy,
+    stats: Arc<stats::StatsRegistry>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Queued items with the flow each packet belongs to (0 unless the policy is `fair`).
+    items: VecDeque<(u64, TunItem)>,
+    /// Queued packets per flow, kept for the `fair` policy only.
+    flows: HashMap<u64, usize>,
+    senders: usize,
+    closed: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn counters(&self) -> &TunnelCounters {
+        &self.stats.tunnel
+    }
+}
+
+/// Queues at most `depth` packets, dropping by `policy` past that.
+pub fn channel(
+    depth: usize,
+    policy: DropPolicy,
+    stats: Arc<stats::StatsRegistry>,
+) -> (TunSender, TunReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            senders: 1,
+            ..State::default()
+        }),
+        notify: Notify::new(),
+        depth: depth.max(1),
+        policy,
+        stats,
+    });
+    (TunSender(Arc::clone(&shared)), TunReceiver(shared))
+}
+
+pub struct TunSender(Arc<Shared>);
+
+impl TunSender {
+    /// Queues a packet without waiting, making room by the drop policy when the queue is
+    /// full. Returns `false` once the tunnel loop is gone.
+    pub fn push(&self, packet: Vec<u8>) -> bool {
+        let shared = &*self.0;
+        let flow = match shared.policy {
+            DropPolicy::Fair => flow_key(&packet),
+            DropPolicy::Tail | DropPolicy::Oldest => 0,
+        };
+        let mut state = shared.lock();
+        if state.closed {
+            return false;
+        }
+        if state.items.len() >= shared.depth {
+            stats::add(&shared.counters().tun_rx_overflow, 1);
+            if !make_room(&mut state, shared.policy, flow) {
+                return true;
+            }
+        }
+        if shared.policy == DropPolicy::Fair {
+            *state.flows.entry(flow).or_default() += 1;
+        }
+        state.items.push_back((flow, Ok(packet)));
+        let depth = state.items.len() as u64;
+        drop(state);
+        record_depth(shared.counters(), depth);
+        shared.notify.notify_one();
+        true
+    }
+
+    /// Hands the read error that stopped a reader to the loop, past the depth limit.
+    pub fn push_error(&self, err: io::Error) {
+        let mut state = self.0.lock();
+        if !state.closed {
+            state.items.push_back((0, Err(err)));
+            drop(state);
+            self.0.notify.notify_one();
+        }
+    }
+}
+
+impl Clone for TunSender {
+    fn clone(&self) -> Self {
+        self.0.lock().senders += 1;
+        TunSender(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for TunSender {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.0.notify.notify_one();
+        }
+    }
+}
+
+/// Drops one queued packet to make room for one of `flow`, or returns `false` when the
+/// arriving packet is the one to go.
+fn make_room(state: &mut State, policy: DropPolicy, flow: u64) -> bool {
+    let victim = match policy {
+        DropPolicy::Tail => None,
+        DropPolicy::Oldest => state.items.iter().position(|(_, item)| item.is_ok()),
+        DropPolicy::Fair => {
+            // The flow with the longest backlog, counting the arriving packet, loses its
+            // oldest packet, so one bulk transfer cannot crowd out the rest.
+            let arriving = state.flows.get(&flow).copied().unwrap_or(0) + 1;
+            let (heaviest, queued) = state
+                .flows
+                .iter()
+                .max_by_key(|(_, queued)| **queued)
+                .map(|(flow, queued)| (*flow, *queued))
+                .unwrap_or((flow, 0));
+            let target = if arriving >= queued { flow } else { heaviest };
+            state
+                .items
+                .iter()
+                .position(|(key, item)| *key == target && item.is_ok())
+        }
+    };
+    let Some(index) = victim else {
+        return false;
+    };
+    if let Some((key, _)) = state.items.remove(index) {
+        if let Some(queued) = state.flows.get_mut(&key) {
+            *queued -= 1;
+            if *queued == 0 {
+                state.flows.remove(&key);
+            }
+        }
+    }
+    true
+}
+
+fn record_depth(counters: &TunnelCounters, depth: u64) {
+    counters.tun_queue_depth.store(depth, Ordering::Relaxed);
+    counters.tun_queue_peak.fetch_max(depth, Ordering::Relaxed);
+}
+
+pub struct TunReceiver(Arc<Shared>);
+
+impl TunReceiver {
+    /// The next item; `None` once every reader has stopped and the queue is drained.
+    pub async fn recv(&mut self) -> Option<TunItem> {
+        loop {
+            if let Some(item) = self.try_recv() {
+                return Some(item);
+            }
+            if self.0.lock().senders == 0 {
+                return None;
+            }
+            self.0.notify.notified().await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<TunItem> {
+        let shared = &*self.0;
+        let mut state = shared.lock();
+        let (flow, item) = state.items.pop_front()?;
+        if item.is_ok() && shared.policy == DropPolicy::Fair {
+            if let Some(queued) = state.flows.get_mut(&flow) {
+                *queued -= 1;
+                if *queued == 0 {
+                    state.flows.remove(&flow);
+                }
+            }
+        }
+        let depth = state.items.len() as u64;
+        drop(state);
+        shared
+            .counters()
+            .tun_queue_depth
+            .store(depth, Ordering::Relaxed);
+        Some(item)
+    }
+}
+
+impl Drop for TunReceiver {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.closed = true;
+        state.items.clear();
+        state.flows.clear();
+        drop(state);
+        self.0
+            .counters()
+            .tun_queue_depth
+            .store(0, Ordering::Relaxed);
+    }
+}
+
+/// Hashes the addresses, protocol and ports of an IPv4 or IPv6 packet; fragments and
+/// other protocols fall back to the addresses and protocol.
+pub(crate) fn flow_key(packet: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match packet.first().map(|byte| byte >> 4) {
+        Some(4) if packet.len() >= 20 => {
+            let protocol = packet[9];
+            packet[12..20].hash(&mut hasher);
+            protocol.hash(&mut hasher);
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            let fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff != 0;
+            if !fragment {
+                ports(protocol, packet.get(header..)).hash(&mut hasher);
+            }
+        }
+        Some(6) if packet.len() >= 40 => {
+            let protocol = packet[6];
+            packet[8..40].hash(&mut hasher);
+            protocol.hash(&mut hasher);
+            ports(protocol, packet.get(40..)).hash(&mut hasher);
+        }
+        _ => packet.len().hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+fn ports(protocol: u8, transport: Option<&[u8]>) -> Option<&[u8]> {
+    const TCP: u8 = 6;
+    const UDP: u8 = 17;
+    match protocol {
+        TCP | UDP => transport.and_then(|transport| transport.get(..4)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn udp(source_port: u16, id: u8) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = 17;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        packet[16..20].copy_from_slice(&[10, 0, 0, 1]);
+        packet[20..22].copy_from_slice(&source_port.to_be_bytes());
+        packet[22..24].copy_from_slice(&53u16.to_be_bytes());
+        packet[27] = id;
+        packet
+    }
+
+    fn drain(rx: &mut TunReceiver) -> Vec<u8> {
+        std::iter::from_fn(|| rx.try_recv())
+            .map(|item| item.unwrap()[27])
+            .collect()
+    }
+
+    fn queue(policy: DropPolicy) -> (TunSender, TunReceiver, Arc<stats::StatsRegistry>) {
+        let stats = Arc::new(stats::StatsRegistry::new(&Config::default()));
+        let (tx, rx) = channel(4, policy, Arc::clone(&stats));
+        (tx, rx, stats)
+    }
+
+    #[test]
+    fn policies_choose_what_a_full_queue_drops() {
+        let (tx, mut rx, stats) = queue(DropPolicy::Tail);
+        for id in 1..=6 {
+            assert!(tx.push(udp(1000, id)));
+        }
+        assert_eq!(drain(&mut rx), [1, 2, 3, 4]);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.tun_rx_overflow, 2);
+        assert_eq!(snapshot.tun_queue_peak, 4);
+        assert_eq!(snapshot.tun_queue_depth, 0);
+
+        let (tx, mut rx, _) = queue(DropPolicy::Oldest);
+        for id in 1..=6 {
+            tx.push(udp(1000, id));
+        }
+        assert_eq!(drain(&mut rx), [3, 4, 5, 6]);
+
+        // A bulk flow fills the queue; the other flow's packets still get in, at the
+        // bulk flow's expense.
+        let (tx, mut rx, stats) = queue(DropPolicy::Fair);
+        for id in 1..=4 {
+            tx.push(udp(1000, id));
+        }
+        tx.push(udp(2000, 10));
+        tx.push(udp(2000, 11));
+        tx.push(udp(1000, 5));
+        assert_eq!(drain(&mut rx), [4, 10, 11, 5]);
+        assert_eq!(stats.snapshot().tun_rx_overflow, 3);
+    }
+
+    #[tokio::test]
+    async fn receiver_ends_when_the_readers_stop() {
+        let (tx, mut rx, _) = queue(DropPolicy::Tail);
+        let reader = tx.clone();
+        reader.push(udp(1000, 1));
+        reader.push_error(io::Error::other("gone"));
+        drop(reader);
+        drop(tx);
+        assert!(rx.recv().await.unwrap().is_ok());
+        assert!(rx.recv().await.unwrap().is_err());
+        assert!(rx.recv().await.is_none());
+
+        let (tx, rx, _) = queue(DropPolicy::Tail);
+        drop(rx);
+        assert!(!tx.push(udp(1000, 1)));
+    }
+}
@@ -0,0 +1,69 @@
+//! `wireguard.send_limit_kbit` and `receive_limit_kbit`: token buckets that cap the
+//! tunnel's traffic each way, counted on the inner packets. On a server running one
+//! vtrunkd per client, they keep one client from taking the whole uplink of the VPS.
+//! Packets over the limit are dropped rather than queued, which TCP takes as congestion
+//! and backs off from.
+
+use std::time::Instant;
+
+/// How much traffic may pass at once after a quiet spell, in time at the full rate.
+const BURST_SECS: f64 = 0.1;
+/// Burst floor, so low limits still let a few full-sized packets through back to back.
+const MIN_BURST_BYTES: f64 = 64.0 * 1024.0;
+
+pub struct RateLimit {
+    bytes_per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    pub fn new(kbit_per_sec: u64, now: Instant) -> Self {
+        let bytes_per_sec = kbit_per_sec as f64 * 1000.0 / 8.0;
+        let burst = (bytes_per_sec * BURST_SECS).max(MIN_BURST_BYTES);
+        RateLimit {
+            bytes_per_sec,
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    /// Whether a packet of `bytes` fits the limit at `now`; one that does is taken off it.
+    pub fn allow(&mut self, bytes: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.burst);
+        if self.tokens < bytes as f64 {
+            return false;
+        }
+        self.tokens -= bytes as f64;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn passes_the_burst_then_the_rate() {
+        let now = Instant::now();
+        // 8 Mbit/s: 1 MB/s, with a 100 KB burst.
+        let mut limit = RateLimit::new(8_000, now);
+        let passed = (0..200).filter(|_| limit.allow(1000, now)).count();
+        assert_eq!(passed, 100);
+
+        // Another 10.5ms refills 10.5 KB.
+        let later = now + Duration::from_micros(10_500);
+        let passed = (0..200).filter(|_| limit.allow(1000, later)).count();
+        assert_eq!(passed, 10);
+
+        // A long pause refills no more than the burst.
+        let idle = later + Duration::from_secs(10);
+        let passed = (0..200).filter(|_| limit.allow(1000, idle)).count();
+        assert_eq!(passed, 100);
+    }
+}
@@ -0,0 +1,297 @@
+//! Puts data packets that took links of different delay back in the order the peer sent
+//! them, by the counter WireGuard stamps on each, before they reach the TUN device. A gap
+//! is held open for the hold time at most; the packets behind it then go out, and the
+//! missing packet is written whenever it turns up.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::stats::{self, TunnelCounters};
+
+/// Hold time until the links' round trips have been measured.
+const DEFAULT_HOLD: Duration = Duration::from_millis(50);
+/// Bounds of the tuned hold time.
+const MIN_HOLD: Duration = Duration::from_millis(5);
+const MAX_HOLD: Duration = Duration::from_millis(500);
+/// Packets held behind a gap before it is given up on, whatever the hold time.
+const MAX_HELD: usize = 1024;
+
+/// The receiver index (our end of the session) and counter of a WireGuard data message.
+pub fn sequence(packet: &[u8]) -> Option<(u32, u64)> {
+    if packet.len() < 32 || packet[..4] != [4, 0, 0, 0] {
+        return None;
+    }
+    let session = u32::from_le_bytes(packet[4..8].try_into().ok()?);
+    let counter = u64::from_le_bytes(packet[8..16].try_into().ok()?);
+    Some((session, counter))
+}
+
+pub struct Reorder {
+    /// `wireguard.reorder_hold_ms`; tuned from the links' round trips when unset.
+    fixed: Option<Duration>,
+    hold: Duration,
+    /// One per crypto lane, whose sessions number their packets each on their own.
+    lanes: Vec<Lane>,
+}
+
+#[derive(Default)]
+struct Lane {
+    session: Option<u32>,
+    /// The session `session` replaced; its stragglers are written as they come.
+    previous: Option<u32>,
+    next: u64,
+    /// Packets that arrived ahead of `next`, with when they did. `None` stands for one
+    /// with nothing to write (a keepalive, a control message, a dropped packet).
+    held: BTreeMap<u64, (Instant, Option<Vec<u8>>)>,
+}
+
+impl Reorder {
+    pub fn new(fixed: Option<Duration>, lanes: usize, stats: &TunnelCounters) -> Self {
+        let reorder = Reorder {
+            fixed,
+            hold: fixed.unwrap_or(DEFAULT_HOLD),
+            lanes: (0..lanes.max(1)).map(|_| Lane::default()).collect(),
+        };
+        reorder.record_hold(stats);
+        reorder
+    }
+
+    /// Takes in the packet numbered `counter` in `session` of `lane` and returns the
+    /// packets now in order, oldest first.
+    pub fn push(
+        &mut self,
+        lane: usize,
+        (session, counter): (u32, u64),
+        packet: Option<Vec<u8>>,
+        now: Instant,
+        stats: &TunnelCounters,
+    ) -> Vec<Vec<u8>> {
+        let mut ready = Vec::new();
+        let count = self.lanes.len();
+        let lane = &mut self.lanes[lane % count];
+        if lane.previous == Some(session) {
+            stats::add(&stats.reorder_late, 1);
+            ready.extend(packet);
+            return ready;
+        }
+        if lane.session != Some(session) {
+            // Counters start over with a new session; the old one's gaps will not fill.
+            ready.extend(
+                std::mem::take(&mut lane.held)
+                    .into_values()
+                    .flat_map(|(_, p)| p),
+            );
+            lane.previous = lane.session.replace(session);
+            lane.next = counter;
+        }
+        if counter < lane.next {
+            // Its gap was given up on, or it was overtaken by the first packet seen of
+            // its session.
+            stats::add(&stats.reorder_late, 1);
+            ready.extend(packet);
+        } else {
+            lane.held.insert(counter, (now, packet));
+            if lane.held.len() > MAX_HELD {
+                lane.skip_gap(stats);
+            }
+            lane.release(&mut ready);
+        }
+        self.record_depth(stats);
+        ready
+    }
+
+    /// When the hold time of the oldest gap runs out.
+    pub fn deadline(&self) -> Option<Instant> {
+        let since = self.lanes.iter().filter_map(Lane::oldest).min()?;
+        Some(since + self.hold)
+    }
+
+    /// Gives up on the gaps held past their hold time by `now` and returns the packets
+    /// that were waiting behind them.
+    pub fn expire(&mut self, now: Instant, stats: &TunnelCounters) -> Vec<Vec<u8>> {
+        let mut ready = Vec::new();
+        for lane in &mut self.lanes {
+            while lane.oldest().is_some_and(|since| since + self.hold <= now) {
+                lane.skip_gap(stats);
+                lane.release(&mut ready);
+            }
+        }
+        self.record_depth(stats);
+        ready
+    }
+
+    /// Sets the hold time from the round trips of the links carrying data, unless it is
+    /// fixed. A packet on the slowest link trails one sent on the fastest by about half
+    /// the difference of their round trips; as much again is left for jitter.
+    pub fn tune(&mut self, rtts: impl IntoIterator<Item = Duration>, stats: &TunnelCounters) {
+        if self.fixed.is_some() {
+            return;
+        }
+        let (mut fastest, mut slowest, mut links) = (Duration::MAX, Duration::ZERO, 0);
+        for rtt in rtts {
+            fastest = fastest.min(rtt);
+            slowest = slowest.max(rtt);
+            links += 1;
+        }
+        if links < 2 {
+            return;
+        }
+        let target = (slowest - fastest).clamp(MIN_HOLD, MAX_HOLD);
+        // Smoothed, so one slow pong does not swing it.
+        self.hold = (self.hold * 3 + target) / 4;
+        self.record_hold(stats);
+    }
+
+    fn record_depth(&self, stats: &TunnelCounters) {
+        let depth: usize = self.lanes.iter().map(|lane| lane.held.len()).sum();
+        stats.reorder_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    fn record_hold(&self, stats: &TunnelCounters) {
+        stats
+            .reorder_hold_ms
+            .store(self.hold.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Lane {
+    /// When the oldest packet held behind a gap arrived.
+    fn oldest(&self) -> Option<Instant> {
+        self.held.values().map(|(arrived, _)| *arrived).min()
+    }
+
+    fn skip_gap(&mut self, stats: &TunnelCounters) {
+        if let Some(first) = self.held.keys().next() {
+            self.next = *first;
+            stats::add(&stats.reorder_timeouts, 1);
+        }
+    }
+
+    fn release(&mut self, ready: &mut Vec<Vec<u8>>) {
+        while let Some(entry) = self.held.first_entry() {
+            if *entry.key() != self.next {
+                break;
+            }
+            let (_, packet) = entry.remove();
+            self.next += 1;
+            ready.extend(packet);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(session: u32, counter: u64) -> Vec<u8> {
+        let mut packet = vec![0u8; 32];
+        packet[0] = 4;
+        packet[4..8].copy_from_slice(&session.to_le_bytes());
+        packet[8..16].copy_from_slice(&counter.to_le_bytes());
+        packet
+    }
+
+    fn push(reorder: &mut Reorder, stats: &TunnelCounters, session: u32, counter: u64) -> Vec<u8> {
+        let packet = Some(vec![counter as u8]);
+        let sequence = sequence(&data(session, counter)).unwrap();
+        reorder
+            .push(0, sequence, packet, Instant::now(), stats)
+            .into_iter()
+            .map(|packet| packet[0])
+            .collect()
+    }
+
+    #[test]
+    fn holds_packets_until_the_gap_fills() {
+        let stats = TunnelCounters::default();
+        let mut reorder = Reorder::new(Some(Duration::from_millis(20)), 1, &stats);
+        assert_eq!(push(&mut reorder, &stats, 7, 0), [0]);
+        assert_eq!(push(&mut reorder, &stats, 7, 2), [0u8; 0]);
+        assert_eq!(push(&mut reorder, &stats, 7, 3), [0u8; 0]);
+        assert_eq!(stats.reorder_depth.load(Ordering::Relaxed), 2);
+        // A keepalive has nothing to write but still fills its place.
+        let keepalive = reorder.push(0, (7, 1), None, Instant::now(), &stats);
+        assert_eq!(keepalive, [vec![2], vec![3]]);
+        assert_eq!(stats.reorder_depth.load(Ordering::Relaxed), 0);
+        assert_eq!(sequence(&[1, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn gives_up_on_a_gap_after_the_hold_time() {
+        let stats = TunnelCounters::default();
+        let mut reorder = Reorder::new(Some(Duration::from_millis(20)), 1, &stats);
+        push(&mut reorder, &stats, 7, 0);
+        assert_eq!(push(&mut reorder, &stats, 7, 2), [0u8; 0]);
+        let deadline = reorder.deadline().unwrap();
+        assert!(reorder
+            .expire(deadline - Duration::from_millis(1), &stats)
+            .is_empty());
+        assert_eq!(reorder.expire(deadline, &stats), [vec![2]]);
+        assert_eq!(reorder.deadline(), None);
+        // The missing packet goes out as soon as it shows up.
+        assert_eq!(push(&mut reorder, &stats, 7, 1), [1]);
+        assert_eq!(stats.reorder_timeouts.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.reorder_late.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn new_session_flushes_the_old_one() {
+        let stats = TunnelCounters::default();
+        let mut reorder = Reorder::new(None, 1, &stats);
+        push(&mut reorder, &stats, 7, 0);
+        assert_eq!(push(&mut reorder, &stats, 7, 5), [0u8; 0]);
+        assert_eq!(push(&mut reorder, &stats, 9, 0), [5, 0]);
+        // Stragglers of the old session are not held.
+        assert_eq!(push(&mut reorder, &stats, 7, 3), [3]);
+        assert_eq!(push(&mut reorder, &stats, 9, 1), [1]);
+    }
+
+    #[test]
+    fn lanes_are_ordered_each_on_their_own() {
+        let stats = TunnelCounters::default();
+        let mut reorder = Reorder::new(Some(Duration::from_millis(20)), 2, &stats);
+        let mut push_on = |lane, session, counter: u64| {
+            reorder
+                .push(
+                    lane,
+                    (session, counter),
+                    Some(vec![counter as u8]),
+                    Instant::now(),
+                    &stats,
+                )
+                .concat()
+        };
+        assert_eq!(push_on(0, 7, 0), [0]);
+        assert_eq!(push_on(1, 9, 0), [0]);
+        assert_eq!(push_on(0, 7, 2), [0u8; 0]);
+        // The other lane's session neither flushes this gap nor waits behind it.
+        assert_eq!(push_on(1, 9, 1), [1]);
+        assert_eq!(push_on(0, 7, 1), [1, 2]);
+        assert_eq!(stats.reorder_late.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn hold_time_follows_the_round_trip_spread() {
+        let stats = TunnelCounters::default();
+        let mut reorder = Reorder::new(None, 1, &stats);
+        assert_eq!(stats.reorder_hold_ms.load(Ordering::Relaxed), 50);
+        reorder.tune([Duration::from_millis(30)], &stats);
+        assert_eq!(stats.reorder_hold_ms.load(Ordering::Relaxed), 50);
+        for _ in 0..20 {
+            reorder.tune(
+                [Duration::from_millis(30), Duration::from_millis(130)],
+                &stats,
+            );
+        }
+        let hold = stats.reorder_hold_ms.load(Ordering::Relaxed);
+        assert!((99..=100).contains(&hold), "hold {}ms", hold);
+
+        let mut fixed = Reorder::new(Some(Duration::from_millis(10)), 1, &stats);
+        fixed.tune(
+            [Duration::from_millis(30), Duration::from_millis(130)],
+            &stats,
+        );
+        assert_eq!(stats.reorder_hold_ms.load(Ordering::Relaxed), 10);
+    }
+}
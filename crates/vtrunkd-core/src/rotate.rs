@@ -0,0 +1,224 @@
+//! Static key rotation over the running session (`vtrunkd rotate-key`).
+//!
+//! The rotating side offers its new public key inside the tunnel, where WireGuard
+//! authenticates it, and keeps the old key until the peer accepts. The peer only accepts
+//! once its operator confirms the key (`vtrunkd accept-key`). The rotating side then starts
+//! a new session with the new key; the peer, which kept its old session meanwhile, switches
+//! when that session's handshake arrives. Traffic pauses for about one handshake.
+//!
+//! Like compressed packets, the messages travel as a fake IPv4 packet with protocol 253,
+//! here with [`ENVELOPE_ID`] as its identification: the message type, then the key. The
+//! optional features each side accepts, and its number of crypto lanes, travel the same
+//! way, padded to a key's length.
+//! Between vtrunkd peers, packets of that shape read from the TUN device are dropped, so
+//! only the peer can send them.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use zeroize::Zeroizing;
+
+const HEADER_LEN: usize = 20;
+const MESSAGE_LEN: usize = HEADER_LEN + 1 + 32;
+/// RFC 3692 experimentation protocol number.
+const ENVELOPE_PROTOCOL: u8 = 253;
+const ENVELOPE_ID: u16 = 0x564b;
+const MESSAGE_OFFER: u8 = 1;
+const MESSAGE_ACCEPT: u8 = 2;
+const MESSAGE_FEATURES: u8 = 3;
+const HANDSHAKE_INITIATION: u8 = 1;
+const HANDSHAKE_INITIATION_LEN: usize = 148;
+
+/// How often an unanswered offer is repeated, and how many times: ten minutes for the
+/// peer's operator to confirm it.
+pub const OFFER_INTERVAL: Duration = Duration::from_secs(5);
+pub const OFFER_ATTEMPTS: u32 = 120;
+
+/// Bit in [`Message::Features`]: LZ4-compressed packets are accepted.
+pub const FEATURE_LZ4: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// The sender will use this public key from its next handshake on.
+    Offer([u8; 32]),
+    /// The receiver of an offer will accept handshakes made with this key.
+    Accept([u8; 32]),
+    /// The optional features, a bit set of `FEATURE_*`, the sender accepts in this
+    /// session, and the crypto lanes it runs. Older peers never send one, so nothing
+    /// optional is used, and only lane 0, until it arrives.
+    Features { flags: u64, lanes: u8 },
+}
+
+impl Message {
+    pub fn encode(self) -> Vec<u8> {
+        let (message_type, key) = match self {
+            Message::Offer(key) => (MESSAGE_OFFER, key),
+            Message::Accept(key) => (MESSAGE_ACCEPT, key),
+            Message::Features { flags, lanes } => {
+                let mut padded = [0u8; 32];
+                padded[..8].copy_from_slice(&flags.to_be_bytes());
+                padded[8] = lanes;
+                (MESSAGE_FEATURES, padded)
+            }
+        };
+        let mut packet = vec![0u8; MESSAGE_LEN];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&(MESSAGE_LEN as u16).to_be_bytes());
+        packet[4..6].copy_from_slice(&ENVELOPE_ID.to_be_bytes());
+        packet[8] = 64;
+        packet[9] = ENVELOPE_PROTOCOL;
+        packet[HEADER_LEN] = message_type;
+        packet[HEADER_LEN + 1..].copy_from_slice(&key);
+        packet
+    }
+
+    /// The message in a decrypted packet, or `None` for ordinary traffic.
+    pub fn parse(packet: &[u8]) -> Option<Message> {
+        if packet.len() != MESSAGE_LEN
+            || packet[0] != 0x45
+            || packet[4..6] != ENVELOPE_ID.to_be_bytes()
+            || packet[9] != ENVELOPE_PROTOCOL
+        {
+            return None;
+        }
+        let key: [u8; 32] = packet[HEADER_LEN + 1..].try_into().ok()?;
+        match packet[HEADER_LEN] {
+            MESSAGE_OFFER => Some(Message::Offer(key)),
+            MESSAGE_ACCEPT => Some(Message::Accept(key)),
+            MESSAGE_FEATURES => Some(Message::Features {
+                flags: u64::from_be_bytes(key[..8].try_into().ok()?),
+                lanes: key[8],
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a packet is shaped like a rotation message, whatever its type or length.
+pub fn is_envelope(packet: &[u8]) -> bool {
+    packet.len() >= HEADER_LEN
+        && packet[0] == 0x45
+        && packet[4..6] == ENVELOPE_ID.to_be_bytes()
+        && packet[9] == ENVELOPE_PROTOCOL
+}
+
+/// Whether a datagram is a WireGuard handshake initiation, the only message a session
+/// with a rotated peer key can start from.
+pub fn is_handshake_initiation(datagram: &[u8]) -> bool {
+    datagram.len() == HANDSHAKE_INITIATION_LEN && datagram[..4] == [HANDSHAKE_INITIATION, 0, 0, 0]
+}
+
+/// What to do about an unanswered offer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OfferDue {
+    Wait,
+    Resend([u8; 32]),
+    /// The peer never accepted; the new key was discarded.
+    Expired,
+}
+
+/// A new key pair waiting for the peer to accept it.
+pub struct Pending {
+    pub private_key: Zeroizing<[u8; 32]>,
+    pub public_key: [u8; 32],
+    pub offered_at: Instant,
+    pub attempts: u32,
+}
+
+#[derive(Default)]
+pub struct KeyRotation {
+    /// Where rotated keys are kept; rotation is refused without it, as a restart would
+    /// fall back to the configured keys the peer no longer accepts.
+    pub state_file: Option<PathBuf>,
+    /// Base64 public keys of the configured pair, which the saved keys replace.
+    pub config_public_key: String,
+    pub config_peer_public_key: String,
+    /// The keys in use differ from the configured ones.
+    pub rotated: bool,
+    pub pending: Option<Pending>,
+    /// Offered by the peer and waiting for the operator to accept it.
+    pub peer_offered: Option<[u8; 32]>,
+    /// Accepted from the peer; used once a handshake with it arrives.
+    pub peer_next: Option<[u8; 32]>,
+    /// Initiations tried against `peer_next` in the current handshake count window; they
+    /// share `handshake_rate_limit` so failing traffic cannot double the DH work.
+    pub trials: u64,
+}
+
+impl KeyRotation {
+    /// Whether the pending offer is due to be sent again; gives up after
+    /// [`OFFER_ATTEMPTS`].
+    pub fn offer_due(&mut self, now: Instant) -> OfferDue {
+        let Some(pending) = &mut self.pending else {
+            return OfferDue::Wait;
+        };
+        if now.duration_since(pending.offered_at) < OFFER_INTERVAL {
+            return OfferDue::Wait;
+        }
+        if pending.attempts >= OFFER_ATTEMPTS {
+            self.pending = None;
+            return OfferDue::Expired;
+        }
+        pending.offered_at = now;
+        pending.attempts += 1;
+        OfferDue::Resend(pending.public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip_and_offers_give_up() {
+        let key = [7u8; 32];
+        for message in [
+            Message::Offer(key),
+            Message::Accept(key),
+            Message::Features {
+                flags: FEATURE_LZ4,
+                lanes: 4,
+            },
+        ] {
+            let packet = message.encode();
+            // boringtun trims decrypted packets to the IPv4 total length.
+            assert_eq!(
+                usize::from(u16::from_be_bytes([packet[2], packet[3]])),
+                packet.len()
+            );
+            assert_eq!(Message::parse(&packet), Some(message));
+        }
+        let mut unknown = Message::Offer(key).encode();
+        unknown[HEADER_LEN] = 9;
+        assert_eq!(Message::parse(&unknown), None);
+        assert_eq!(
+            Message::parse(&crate::compress::compress(&[b'a'; 400]).unwrap()),
+            None
+        );
+        assert!(is_envelope(&unknown));
+        assert!(is_envelope(&unknown[..HEADER_LEN]));
+        assert!(!is_envelope(
+            &crate::compress::compress(&[b'a'; 400]).unwrap()
+        ));
+        assert!(!is_envelope(&unknown[..HEADER_LEN - 1]));
+
+        let start = Instant::now();
+        let mut rotation = KeyRotation {
+            pending: Some(Pending {
+                private_key: Zeroizing::new([1; 32]),
+                public_key: key,
+                offered_at: start,
+                attempts: 1,
+            }),
+            ..KeyRotation::default()
+        };
+        assert_eq!(rotation.offer_due(start), OfferDue::Wait);
+        let mut now = start;
+        for _ in 1..OFFER_ATTEMPTS {
+            now += OFFER_INTERVAL;
+            assert_eq!(rotation.offer_due(now), OfferDue::Resend(key));
+        }
+        assert_eq!(rotation.offer_due(now + OFFER_INTERVAL), OfferDue::Expired);
+        assert!(rotation.pending.is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::RuntimeConfig;
+#[cfg(target_os = "linux")]
+use crate::error::VtrunkdError;
+use crate::error::VtrunkdResult;
+
+/// Builds the multi-threaded runtime. With `pin_cpus`, each of its threads pins itself as
+/// it starts, and the io_uring threads the tunnel starts from them inherit that; the main
+/// thread and the rest of the process keep every CPU.
+pub fn build(settings: &RuntimeConfig) -> VtrunkdResult<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(workers) = settings.workers {
+        builder.worker_threads(workers);
+    }
+    if let Some(cpus) = &settings.pin_cpus {
+        let pin = pinner(cpus)?;
+        builder.on_thread_start(pin);
+    }
+    Ok(builder.build()?)
+}
+
+/// A function pinning the calling thread to `cpus`, checked against the kernel on a
+/// throwaway thread first: a thread start hook has no way to report an error.
+#[cfg(target_os = "linux")]
+fn pinner(cpus: &[usize]) -> VtrunkdResult<impl Fn() + Send + Sync + 'static> {
+    use nix::sched::{sched_setaffinity, CpuSet};
+    use nix::unistd::Pid;
+
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu).map_err(|_| {
+            VtrunkdError::InvalidConfig(format!("Runtime pin_cpus has an invalid CPU: {}", cpu))
+        })?;
+    }
+    // Pid 0 is the calling thread.
+    let pin = move || sched_setaffinity(Pid::from_raw(0), &set);
+    std::thread::spawn(pin)
+        .join()
+        .map_err(|_| VtrunkdError::InvalidConfig("Cannot check pin_cpus".to_string()))?
+        .map_err(|e| {
+            VtrunkdError::InvalidConfig(format!("Cannot pin to CPUs {:?}: {}", cpus, e))
+        })?;
+    Ok(move || {
+        let _ = pin();
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pinner(cpus: &[usize]) -> VtrunkdResult<impl Fn() + Send + Sync + 'static> {
+    // Logging is not up yet; the daemon warns about this once it is.
+    let _ = cpus;
+    Ok(|| {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pin_rejects_cpus_out_of_range() {
+        assert!(matches!(
+            pinner(&[usize::MAX]),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+        // A CPU the kernel does not have is caught before the runtime starts.
+        assert!(matches!(
+            pinner(&[nix::sched::CpuSet::count() - 1]),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pins_the_runtime_threads_only() {
+        use nix::sched::sched_getaffinity;
+        use nix::unistd::Pid;
+
+        let before = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        let runtime = build(&RuntimeConfig {
+            workers: Some(1),
+            pin_cpus: Some(vec![0]),
+        })
+        .unwrap();
+        let pinned = runtime
+            .block_on(runtime.spawn(async {
+                let set = sched_getaffinity(Pid::from_raw(0)).unwrap();
+                (0..nix::sched::CpuSet::count())
+                    .filter(|&cpu| set.is_set(cpu).unwrap())
+                    .collect::<Vec<_>>()
+            }))
+            .unwrap();
+        assert_eq!(pinned, vec![0]);
+        let after = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn build_honours_worker_count() {
+        let runtime = build(&RuntimeConfig {
+            workers: Some(2),
+            pin_cpus: None,
+        })
+        .unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+    }
+}
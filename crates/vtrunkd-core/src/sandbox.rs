@@ -0,0 +1,347 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::VtrunkdResult;
+
+/// Syscall filtering applied once the TUN device and sockets are set up.
+///
+/// `basic` blocks syscalls a packet forwarder never needs (module loading,
+/// mounts, ptrace, kexec, bpf, ...). `strict` only allows the syscalls used by
+/// the runtime, packet loop, control socket, logging and hooks; anything else
+/// fails with `EPERM`. Hook commands inherit the filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMode {
+    #[default]
+    Off,
+    Basic,
+    Strict,
+}
+
+impl SandboxMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SandboxMode::Off => "off",
+            SandboxMode::Basic => "basic",
+            SandboxMode::Strict => "strict",
+        }
+    }
+}
+
+/// Set once a filter is installed; the supervisor calls `apply` again after every restart,
+/// and `strict` mode does not allow installing a second filter.
+#[cfg(target_os = "linux")]
+static APPLIED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+pub fn apply(mode: SandboxMode) -> VtrunkdResult<()> {
+    use std::sync::atomic::Ordering;
+
+    if mode == SandboxMode::Off || APPLIED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let program = linux::build(mode)?;
+    seccompiler::apply_filter_all_threads(&program).map_err(|e| {
+        crate::error::VtrunkdError::SystemCall(format!("Failed to install seccomp filter: {}", e))
+    })?;
+    APPLIED.store(true, Ordering::SeqCst);
+    tracing::info!("Sandbox enabled ({} seccomp filter)", mode.as_str());
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(mode: SandboxMode) -> VtrunkdResult<()> {
+    if mode != SandboxMode::Off {
+        tracing::warn!(
+            "Sandbox mode {} is only supported on Linux; continuing without it",
+            mode.as_str()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::BTreeMap;
+
+    use nix::libc;
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+    use super::SandboxMode;
+    use crate::error::{VtrunkdError, VtrunkdResult};
+
+    /// Syscalls no part of vtrunkd uses; denied in `basic` mode.
+    const DENIED: &[i64] = &[
+        libc::SYS_acct,
+        libc::SYS_add_key,
+        libc::SYS_adjtimex,
+        libc::SYS_bpf,
+        libc::SYS_chroot,
+        libc::SYS_clock_settime,
+        libc::SYS_delete_module,
+        libc::SYS_finit_module,
+        libc::SYS_init_module,
+        libc::SYS_kexec_file_load,
+        libc::SYS_kexec_load,
+        libc::SYS_keyctl,
+        libc::SYS_mount,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_perf_event_open,
+        libc::SYS_personality,
+        libc::SYS_pivot_root,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_ptrace,
+        libc::SYS_quotactl,
+        libc::SYS_reboot,
+        libc::SYS_request_key,
+        libc::SYS_setns,
+        libc::SYS_settimeofday,
+        libc::SYS_swapoff,
+        libc::SYS_swapon,
+        libc::SYS_syslog,
+        libc::SYS_umount2,
+        libc::SYS_unshare,
+        libc::SYS_userfaultfd,
+        libc::SYS_vhangup,
+    ];
+
+    /// Syscalls allowed in `strict` mode.
+    const ALLOWED: &[i64] = &[
+        // Memory and threads (tokio runtime, allocator).
+        libc::SYS_brk,
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_futex,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_sched_setaffinity,
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_gettid,
+        libc::SYS_getpid,
+        libc::SYS_getppid,
+        libc::SYS_tgkill,
+        libc::SYS_kill,
+        libc::SYS_prctl,
+        libc::SYS_prlimit64,
+        libc::SYS_getrandom,
+        libc::SYS_uname,
+        libc::SYS_getuid,
+        libc::SYS_geteuid,
+        libc::SYS_getgid,
+        libc::SYS_getegid,
+        libc::SYS_restart_syscall,
+        // Signals.
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        // Time.
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        libc::SYS_gettimeofday,
+        // Event loop.
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_eventfd2,
+        libc::SYS_ppoll,
+        libc::SYS_pipe2,
+        // File descriptors (TUN, logs, captures, PID file, socket cleanup).
+        libc::SYS_read,
+        libc::SYS_readv,
+        libc::SYS_write,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_close,
+        libc::SYS_close_range,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_fcntl,
+        libc::SYS_ioctl,
+        libc::SYS_lseek,
+        libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
+        libc::SYS_openat,
+        libc::SYS_faccessat,
+        libc::SYS_faccessat2,
+        libc::SYS_getdents64,
+        libc::SYS_getcwd,
+        libc::SYS_chdir,
+        libc::SYS_readlinkat,
+        libc::SYS_unlinkat,
+        libc::SYS_mkdirat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_ftruncate,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+        libc::SYS_flock,
+        libc::SYS_umask,
+        // Sockets (links, control socket, hook HTTP, syslog/journald).
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept4,
+        libc::SYS_connect,
+        libc::SYS_shutdown,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_recvmmsg,
+        // io_uring backend; ring operations themselves are not filtered by seccomp.
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_getsockopt,
+        libc::SYS_setsockopt,
+        // Hook commands.
+        libc::SYS_execve,
+        libc::SYS_wait4,
+        libc::SYS_waitid,
+        libc::SYS_setpgid,
+        libc::SYS_pidfd_open,
+        libc::SYS_pidfd_send_signal,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_arch_prctl,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_access,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_dup2,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_epoll_wait,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_fork,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_vfork,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_mkdir,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_pipe,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_poll,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_readlink,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rename,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_stat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_lstat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_unlink,
+    ];
+
+    pub fn build(mode: SandboxMode) -> VtrunkdResult<BpfProgram> {
+        let (syscalls, mismatch, on_match) = match mode {
+            SandboxMode::Off => return Ok(BpfProgram::new()),
+            SandboxMode::Basic => (
+                DENIED,
+                SeccompAction::Allow,
+                SeccompAction::Errno(libc::EPERM as u32),
+            ),
+            SandboxMode::Strict => (
+                ALLOWED,
+                SeccompAction::Errno(libc::EPERM as u32),
+                SeccompAction::Allow,
+            ),
+        };
+        // An empty rule list matches the syscall unconditionally.
+        let rules: BTreeMap<i64, Vec<seccompiler::SeccompRule>> = syscalls
+            .iter()
+            .map(|&syscall| (syscall, Vec::new()))
+            .collect();
+        let arch = std::env::consts::ARCH
+            .try_into()
+            .map_err(|e| VtrunkdError::SystemCall(format!("Unsupported seccomp arch: {}", e)))?;
+        let filter = SeccompFilter::new(rules, mismatch, on_match, arch)
+            .map_err(|e| VtrunkdError::SystemCall(format!("Invalid seccomp filter: {}", e)))?;
+        filter.try_into().map_err(|e| {
+            VtrunkdError::SystemCall(format!("Failed to compile seccomp filter: {}", e))
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn filters_compile_for_host_arch() {
+            assert!(!build(SandboxMode::Basic).unwrap().is_empty());
+            assert!(!build(SandboxMode::Strict).unwrap().is_empty());
+            assert!(build(SandboxMode::Off).unwrap().is_empty());
+        }
+
+        #[test]
+        fn allowed_syscalls_are_listed_once() {
+            let mut seen = std::collections::BTreeSet::new();
+            for syscall in ALLOWED {
+                assert!(seen.insert(syscall), "syscall {} allowed twice", syscall);
+            }
+        }
+
+        /// A capture into a directory that does not exist yet, and pinning a runtime
+        /// thread, both happen after the filter is in place.
+        #[test]
+        fn strict_filter_permits_capture_and_pinning() {
+            use std::time::Duration;
+
+            use crate::capture::{Capture, CaptureTarget};
+
+            let dir = std::env::temp_dir()
+                .join(format!("vtrunkd-sandbox-{}", std::process::id()))
+                .join("captures");
+            let _ = std::fs::remove_dir_all(dir.parent().unwrap());
+
+            let filtered_dir = dir.clone();
+            // The filter only covers this thread, not the rest of the test binary.
+            let result = std::thread::spawn(move || {
+                seccompiler::apply_filter(&build(SandboxMode::Strict).unwrap()).unwrap();
+                let affinity = nix::sched::sched_getaffinity(nix::unistd::Pid::from_raw(0))?;
+                nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), &affinity)?;
+                let mut capture = Capture::start(
+                    &filtered_dir,
+                    CaptureTarget::All,
+                    Duration::from_secs(60),
+                    1024,
+                )?;
+                capture.record_tun(&[0x45; 20])?;
+                capture.finish()
+            })
+            .join()
+            .unwrap();
+
+            assert!(result.is_ok(), "{:?}", result);
+            let _ = std::fs::remove_dir_all(dir.parent().unwrap());
+        }
+
+        #[test]
+        fn allow_and_deny_lists_are_disjoint() {
+            for syscall in DENIED {
+                assert!(
+                    !ALLOWED.contains(syscall),
+                    "syscall {} both allowed and denied",
+                    syscall
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Session state kept across restarts in `wireguard.state_file`: the peer's last-known
+//! address on each link, the link RTTs and the traffic counters. A restarted daemon then
+//! reaches its peer and schedules its links at once instead of relearning them. Keys
+//! replaced by `vtrunkd rotate-key` are kept here too.
+
+use std::io::Write;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use zeroize::Zeroize;
+
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::stats::StatsSnapshot;
+
+/// Endpoints and RTTs older than this describe another network; counters are kept.
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionState {
+    /// Unix time of the save.
+    pub saved_at: u64,
+    pub links: Vec<LinkState>,
+    pub stats: StatsSnapshot,
+    pub keys: Option<SessionKeys>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkState {
+    pub name: String,
+    pub remote: Option<SocketAddr>,
+    pub rtt_ms: Option<u64>,
+}
+
+/// Rotated keys (base64), used in place of the configured pair named by `config_*` for
+/// as long as the configuration still holds that pair. Wiped when dropped.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionKeys {
+    pub config_public_key: String,
+    pub config_peer_public_key: String,
+    pub private_key: String,
+    pub peer_public_key: String,
+}
+
+impl std::fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKeys")
+            .field("config_public_key", &self.config_public_key)
+            .field("config_peer_public_key", &self.config_peer_public_key)
+            .field("peer_public_key", &self.peer_public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
+}
+
+impl SessionState {
+    /// Whether the endpoints and RTTs are recent enough to use.
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        let saved = UNIX_EPOCH + Duration::from_secs(self.saved_at);
+        now.duration_since(saved).is_ok_and(|age| age <= MAX_AGE)
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// The saved state, or `None` on the first start. A damaged file is reported and
+/// ignored; it is replaced on the next shutdown.
+pub fn load(path: &Path) -> Option<SessionState> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Ignoring state file {:?}: {}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_str(&contents) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Ignoring state file {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes beside the file and renames, so a crash never leaves half a state file.
+pub fn save(path: &Path, state: &SessionState) -> VtrunkdResult<()> {
+    let json = serde_json::to_string(state)
+        .map_err(|e| VtrunkdError::Config(format!("Failed to encode session state: {}", e)))?;
+    let staging = path.with_extension("new");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Peer addresses are nobody else's business.
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&staging)?;
+    file.write_all(json.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&staging, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::LinkStats;
+
+    #[test]
+    fn state_survives_a_round_trip_and_ages_out() {
+        let dir = std::env::temp_dir().join(format!("vtrunkd-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vtrunkd.state");
+        assert_eq!(load(&path), None);
+
+        let state = SessionState {
+            saved_at: unix_now(),
+            links: vec![LinkState {
+                name: "lte".to_string(),
+                remote: Some("203.0.113.7:51820".parse().unwrap()),
+                rtt_ms: Some(48),
+            }],
+            stats: StatsSnapshot {
+                links: vec![LinkStats {
+                    name: "lte".to_string(),
+                    tx_bytes: 1 << 30,
+                    ..LinkStats::default()
+                }],
+                ..StatsSnapshot::default()
+            },
+            keys: None,
+        };
+        save(&path, &state).unwrap();
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.is_fresh(SystemTime::now()));
+        assert!(!loaded.is_fresh(SystemTime::now() + MAX_AGE * 2));
+
+        let rotated = SessionState {
+            keys: Some(SessionKeys {
+                config_public_key: "cHViMQ==".to_string(),
+                config_peer_public_key: "cHViMg==".to_string(),
+                private_key: "c2VjcmV0".to_string(),
+                peer_public_key: "cHViMw==".to_string(),
+            }),
+            ..state
+        };
+        save(&path, &rotated).unwrap();
+        assert_eq!(load(&path), Some(rotated.clone()));
+        assert!(!format!("{:?}", rotated).contains("c2VjcmV0"));
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert_eq!(load(&path), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,437 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::config::{link_name, Config};
+
+/// Counters for a single link. Updated with relaxed atomics on the hot path.
+///
+/// `tx_*`/`rx_*` count every datagram on the link socket; `control_*` count the
+/// bonding ping/pong subset of those. `rx_overflow` counts datagrams dropped
+/// because the tunnel loop's queue (`network.channel_depth`) was full, `tx_queue_full`
+/// datagrams the link's send queue (`network.send_queue_depth`) turned away, and
+/// `handshakes_throttled` handshakes dropped over `wireguard.handshake_source_limit`, and
+/// `rx_wrong_source` datagrams a `strict_source` link dropped for not coming from its peer.
+#[derive(Debug, Default)]
+pub struct LinkCounters {
+    pub tx_packets: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub tx_errors: AtomicU64,
+    pub tx_queue_full: AtomicU64,
+    pub rx_packets: AtomicU64,
+    pub rx_bytes: AtomicU64,
+    pub rx_dropped: AtomicU64,
+    pub rx_overflow: AtomicU64,
+    pub handshakes_throttled: AtomicU64,
+    pub rx_wrong_source: AtomicU64,
+    pub control_tx: AtomicU64,
+    pub control_rx: AtomicU64,
+    pub pings_sent: AtomicU64,
+    pub pongs_received: AtomicU64,
+}
+
+/// Counters for the TUN side and for scheduler decisions spanning links.
+///
+/// `tun_rx_overflow` counts TUN packets dropped because the tunnel loop's queue was full,
+/// whichever `network.tun_drop_policy` picked; `tun_queue_depth` is how many wait in it
+/// now and `tun_queue_peak` the most that ever did.
+/// `compressed_packets` counts TUN packets sent LZ4-compressed, and
+/// `compression_saved_bytes` what that took off their plaintext size. `cookie_replies`
+/// counts handshakes answered with a cookie because `wireguard.handshake_rate_limit` was hit.
+/// `tun_rx_non_ip` and `tun_rx_malformed` count packets read from the TUN device that were
+/// not IPv4 or IPv6, or were cut short, whatever `network.unsupported_packets` did with them.
+/// `dropped_too_big` counts TUN packets over the MTU that could not be fragmented and
+/// `icmp_errors` the ICMP errors written back for those and for `dropped_no_link` ones.
+/// With `wireguard.reorder`, `reorder_depth` is how many packets wait behind a gap now,
+/// `reorder_hold_ms` how long a gap is waited for, `reorder_timeouts` how many gaps were
+/// given up on and `reorder_late` how many packets arrived after that.
+/// With `wireguard.links_down: buffer`, `outage_depth` is how many TUN packets wait for a
+/// link now, `outage_replayed` how many were sent once one came back and `outage_dropped`
+/// how many were held too long or pushed out by newer ones.
+/// `dropped_send_limit` and `dropped_receive_limit` count packets over
+/// `wireguard.send_limit_kbit` and `receive_limit_kbit`.
+/// `tun_rx_reserved` counts TUN packets dropped for looking like one of the envelopes
+/// vtrunkd itself sends through the session, which the peer would unwrap.
+/// `crypto_dropped` counts packets dropped because a crypto lane's queue was full.
+#[derive(Debug, Default)]
+pub struct TunnelCounters {
+    pub tun_rx_packets: AtomicU64,
+    pub tun_rx_bytes: AtomicU64,
+    pub tun_rx_overflow: AtomicU64,
+    pub tun_rx_non_ip: AtomicU64,
+    pub tun_rx_malformed: AtomicU64,
+    pub tun_queue_depth: AtomicU64,
+    pub tun_queue_peak: AtomicU64,
+    pub tun_tx_packets: AtomicU64,
+    pub tun_tx_bytes: AtomicU64,
+    pub dropped_no_link: AtomicU64,
+    pub dropped_too_big: AtomicU64,
+    pub icmp_errors: AtomicU64,
+    pub failovers: AtomicU64,
+    pub compressed_packets: AtomicU64,
+    pub compression_saved_bytes: AtomicU64,
+    pub cookie_replies: AtomicU64,
+    pub reorder_depth: AtomicU64,
+    pub reorder_hold_ms: AtomicU64,
+    pub reorder_timeouts: AtomicU64,
+    pub reorder_late: AtomicU64,
+    pub outage_depth: AtomicU64,
+    pub outage_replayed: AtomicU64,
+    pub outage_dropped: AtomicU64,
+    pub dropped_send_limit: AtomicU64,
+    pub dropped_receive_limit: AtomicU64,
+    pub tun_rx_reserved: AtomicU64,
+    pub crypto_dropped: AtomicU64,
+}
+
+/// Shared stats registry. Outlives a single tunnel run so counters survive restarts.
+#[derive(Debug)]
+pub struct StatsRegistry {
+    pub tunnel: TunnelCounters,
+    links: Vec<(String, Arc<LinkCounters>)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LinkStats {
+    pub name: String,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+    pub tx_queue_full: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_dropped: u64,
+    pub rx_overflow: u64,
+    pub handshakes_throttled: u64,
+    pub rx_wrong_source: u64,
+    pub control_tx: u64,
+    pub control_rx: u64,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    /// Link state as logged (`up`, `down`, `idle`, `drain`, `admin-down`); set only in
+    /// replies to the `stats` command, as the registry does not track it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// Last health-check round trip; set only in replies to the `stats` command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u64>,
+    /// Quality score from 0 to 100 made of RTT, jitter, ping loss and recent failures;
+    /// set only in replies to the `stats` command, once an RTT has been measured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct StatsSnapshot {
+    pub tun_rx_packets: u64,
+    pub tun_rx_bytes: u64,
+    pub tun_rx_overflow: u64,
+    pub tun_rx_non_ip: u64,
+    pub tun_rx_malformed: u64,
+    /// Gauges of the current run; not restored from a saved snapshot.
+    pub tun_queue_depth: u64,
+    pub tun_queue_peak: u64,
+    pub tun_tx_packets: u64,
+    pub tun_tx_bytes: u64,
+    pub dropped_no_link: u64,
+    pub dropped_too_big: u64,
+    pub icmp_errors: u64,
+    pub failovers: u64,
+    pub compressed_packets: u64,
+    pub compression_saved_bytes: u64,
+    pub cookie_replies: u64,
+    /// Gauges of the current run; not restored from a saved snapshot.
+    pub reorder_depth: u64,
+    pub reorder_hold_ms: u64,
+    pub reorder_timeouts: u64,
+    pub reorder_late: u64,
+    /// Gauge of the current run; not restored from a saved snapshot.
+    pub outage_depth: u64,
+    pub outage_replayed: u64,
+    pub outage_dropped: u64,
+    pub dropped_send_limit: u64,
+    pub dropped_receive_limit: u64,
+    pub tun_rx_reserved: u64,
+    pub crypto_dropped: u64,
+    pub links: Vec<LinkStats>,
+}
+
+pub fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+/// Hands a packet to the tunnel loop without waiting; a full queue drops it and counts
+/// `overflow`. Returns `false` once the loop is gone.
+pub fn queue_packet<T>(tx: &mpsc::Sender<T>, packet: T, overflow: &AtomicU64) -> bool {
+    match tx.try_send(packet) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            add(overflow, 1);
+            true
+        }
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+impl LinkCounters {
+    pub fn record_tx(&self, bytes: usize) {
+        add(&self.tx_packets, 1);
+        add(&self.tx_bytes, bytes as u64);
+    }
+
+    pub fn record_rx(&self, bytes: usize) {
+        add(&self.rx_packets, 1);
+        add(&self.rx_bytes, bytes as u64);
+    }
+
+    fn restore(&self, saved: &LinkStats) {
+        add(&self.tx_packets, saved.tx_packets);
+        add(&self.tx_bytes, saved.tx_bytes);
+        add(&self.tx_errors, saved.tx_errors);
+        add(&self.tx_queue_full, saved.tx_queue_full);
+        add(&self.rx_packets, saved.rx_packets);
+        add(&self.rx_bytes, saved.rx_bytes);
+        add(&self.rx_dropped, saved.rx_dropped);
+        add(&self.rx_overflow, saved.rx_overflow);
+        add(&self.handshakes_throttled, saved.handshakes_throttled);
+        add(&self.rx_wrong_source, saved.rx_wrong_source);
+        add(&self.control_tx, saved.control_tx);
+        add(&self.control_rx, saved.control_rx);
+        add(&self.pings_sent, saved.pings_sent);
+        add(&self.pongs_received, saved.pongs_received);
+    }
+
+    fn snapshot(&self, name: &str) -> LinkStats {
+        LinkStats {
+            name: name.to_string(),
+            tx_packets: load(&self.tx_packets),
+            tx_bytes: load(&self.tx_bytes),
+            tx_errors: load(&self.tx_errors),
+            tx_queue_full: load(&self.tx_queue_full),
+            rx_packets: load(&self.rx_packets),
+            rx_bytes: load(&self.rx_bytes),
+            rx_dropped: load(&self.rx_dropped),
+            rx_overflow: load(&self.rx_overflow),
+            handshakes_throttled: load(&self.handshakes_throttled),
+            rx_wrong_source: load(&self.rx_wrong_source),
+            control_tx: load(&self.control_tx),
+            control_rx: load(&self.control_rx),
+            pings_sent: load(&self.pings_sent),
+            pongs_received: load(&self.pongs_received),
+            state: None,
+            rtt_ms: None,
+            quality: None,
+        }
+    }
+}
+
+impl TunnelCounters {
+    pub fn record_tun_rx(&self, bytes: usize) {
+        add(&self.tun_rx_packets, 1);
+        add(&self.tun_rx_bytes, bytes as u64);
+    }
+
+    pub fn record_tun_tx(&self, bytes: usize) {
+        add(&self.tun_tx_packets, 1);
+        add(&self.tun_tx_bytes, bytes as u64);
+    }
+}
+
+impl StatsRegistry {
+    pub fn new(config: &Config) -> Self {
+        let links = config
+            .wireguard
+            .links
+            .iter()
+            .enumerate()
+            .map(|(index, link)| (link_name(link, index), Arc::new(LinkCounters::default())))
+            .collect();
+        StatsRegistry {
+            tunnel: TunnelCounters::default(),
+            links,
+        }
+    }
+
+    /// Counters for the link at `index`; links are registered in config order.
+    pub fn link(&self, index: usize) -> Arc<LinkCounters> {
+        Arc::clone(&self.links[index].1)
+    }
+
+    /// Adds counters saved by an earlier run, matching links by name.
+    pub fn restore(&self, saved: &StatsSnapshot) {
+        let tunnel = &self.tunnel;
+        add(&tunnel.tun_rx_packets, saved.tun_rx_packets);
+        add(&tunnel.tun_rx_bytes, saved.tun_rx_bytes);
+        add(&tunnel.tun_rx_overflow, saved.tun_rx_overflow);
+        add(&tunnel.tun_rx_non_ip, saved.tun_rx_non_ip);
+        add(&tunnel.tun_rx_malformed, saved.tun_rx_malformed);
+        add(&tunnel.tun_tx_packets, saved.tun_tx_packets);
+        add(&tunnel.tun_tx_bytes, saved.tun_tx_bytes);
+        add(&tunnel.dropped_no_link, saved.dropped_no_link);
+        add(&tunnel.dropped_too_big, saved.dropped_too_big);
+        add(&tunnel.icmp_errors, saved.icmp_errors);
+        add(&tunnel.failovers, saved.failovers);
+        add(&tunnel.compressed_packets, saved.compressed_packets);
+        add(
+            &tunnel.compression_saved_bytes,
+            saved.compression_saved_bytes,
+        );
+        add(&tunnel.cookie_replies, saved.cookie_replies);
+        add(&tunnel.reorder_timeouts, saved.reorder_timeouts);
+        add(&tunnel.reorder_late, saved.reorder_late);
+        add(&tunnel.outage_replayed, saved.outage_replayed);
+        add(&tunnel.outage_dropped, saved.outage_dropped);
+        add(&tunnel.dropped_send_limit, saved.dropped_send_limit);
+        add(&tunnel.dropped_receive_limit, saved.dropped_receive_limit);
+        add(&tunnel.tun_rx_reserved, saved.tun_rx_reserved);
+        add(&tunnel.crypto_dropped, saved.crypto_dropped);
+        for (name, counters) in &self.links {
+            if let Some(link) = saved.links.iter().find(|link| &link.name == name) {
+                counters.restore(link);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let tunnel = &self.tunnel;
+        StatsSnapshot {
+            tun_rx_packets: load(&tunnel.tun_rx_packets),
+            tun_rx_bytes: load(&tunnel.tun_rx_bytes),
+            tun_rx_overflow: load(&tunnel.tun_rx_overflow),
+            tun_rx_non_ip: load(&tunnel.tun_rx_non_ip),
+            tun_rx_malformed: load(&tunnel.tun_rx_malformed),
+            tun_queue_depth: load(&tunnel.tun_queue_depth),
+            tun_queue_peak: load(&tunnel.tun_queue_peak),
+            tun_tx_packets: load(&tunnel.tun_tx_packets),
+            tun_tx_bytes: load(&tunnel.tun_tx_bytes),
+            dropped_no_link: load(&tunnel.dropped_no_link),
+            dropped_too_big: load(&tunnel.dropped_too_big),
+            icmp_errors: load(&tunnel.icmp_errors),
+            failovers: load(&tunnel.failovers),
+            compressed_packets: load(&tunnel.compressed_packets),
+            compression_saved_bytes: load(&tunnel.compression_saved_bytes),
+            cookie_replies: load(&tunnel.cookie_replies),
+            reorder_depth: load(&tunnel.reorder_depth),
+            reorder_hold_ms: load(&tunnel.reorder_hold_ms),
+            reorder_timeouts: load(&tunnel.reorder_timeouts),
+            reorder_late: load(&tunnel.reorder_late),
+            outage_depth: load(&tunnel.outage_depth),
+            outage_replayed: load(&tunnel.outage_replayed),
+            outage_dropped: load(&tunnel.outage_dropped),
+            dropped_send_limit: load(&tunnel.dropped_send_limit),
+            dropped_receive_limit: load(&tunnel.dropped_receive_limit),
+            tun_rx_reserved: load(&tunnel.tun_rx_reserved),
+            crypto_dropped: load(&tunnel.crypto_dropped),
+            links: self
+                .links
+                .iter()
+                .map(|(name, counters)| counters.snapshot(name))
+                .collect(),
+        }
+    }
+}
+
+/// Formats the byte delta over `elapsed` as a bit rate, e.g. `12.4Mbit/s`.
+pub fn format_rate(bytes: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let bits = if secs > 0.0 {
+        bytes as f64 * 8.0 / secs
+    } else {
+        0.0
+    };
+    if bits >= 1e9 {
+        format!("{:.1}Gbit/s", bits / 1e9)
+    } else if bits >= 1e6 {
+        format!("{:.1}Mbit/s", bits / 1e6)
+    } else if bits >= 1e3 {
+        format!("{:.1}kbit/s", bits / 1e3)
+    } else {
+        format!("{:.0}bit/s", bits)
+    }
+}
+
+/// Health ping loss over a window, or `None` when no pings were sent.
+pub fn loss_percent(pings_sent: u64, pongs_received: u64) -> Option<f64> {
+    if pings_sent == 0 {
+        return None;
+    }
+    let lost = pings_sent.saturating_sub(pongs_received);
+    Some(lost as f64 * 100.0 / pings_sent as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_reflects_counters() {
+        let registry = StatsRegistry::new(&Config::default());
+        let link = registry.link(0);
+        link.record_tx(100);
+        link.record_tx(50);
+        link.record_rx(20);
+        add(&registry.tunnel.failovers, 1);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.failovers, 1);
+        assert_eq!(snapshot.links.len(), 1);
+        assert_eq!(snapshot.links[0].name, "link-0");
+        assert_eq!(snapshot.links[0].tx_packets, 2);
+        assert_eq!(snapshot.links[0].tx_bytes, 150);
+        assert_eq!(snapshot.links[0].rx_bytes, 20);
+    }
+
+    #[test]
+    fn restore_adds_saved_counters_by_link_name() {
+        let registry = StatsRegistry::new(&Config::default());
+        registry.link(0).record_tx(10);
+        let mut saved = registry.snapshot();
+        saved.failovers = 3;
+        saved.links.push(LinkStats {
+            name: "removed".to_string(),
+            tx_bytes: 99,
+            ..LinkStats::default()
+        });
+
+        registry.restore(&saved);
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.failovers, 3);
+        assert_eq!(snapshot.links.len(), 1);
+        assert_eq!(snapshot.links[0].tx_bytes, 20);
+    }
+
+    #[test]
+    fn full_queue_counts_overflow() {
+        let (tx, rx) = mpsc::channel(1);
+        let overflow = AtomicU64::new(0);
+        assert!(queue_packet(&tx, 1, &overflow));
+        assert!(queue_packet(&tx, 2, &overflow));
+        assert_eq!(load(&overflow), 1);
+        drop(rx);
+        assert!(!queue_packet(&tx, 3, &overflow));
+    }
+
+    #[test]
+    fn format_rate_scales_units() {
+        let second = Duration::from_secs(1);
+        assert_eq!(format_rate(0, second), "0bit/s");
+        assert_eq!(format_rate(1_500, second), "12.0kbit/s");
+        assert_eq!(format_rate(2_500_000, Duration::from_secs(2)), "10.0Mbit/s");
+    }
+
+    #[test]
+    fn loss_percent_handles_empty_window() {
+        assert_eq!(loss_percent(0, 0), None);
+        assert_eq!(loss_percent(4, 3), Some(25.0));
+        assert_eq!(loss_percent(2, 3), Some(0.0));
+    }
+}
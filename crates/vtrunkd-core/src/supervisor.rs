@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+use crate::config::{Config, DEFAULT_MAX_RESTARTS};
+use crate::control::{ControlCommand, ControlRequest};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::{self, EventSender, TunnelEvent};
+use crate::stats::StatsRegistry;
+use crate::wireguard;
+
+const RESTART_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A run that stayed up this long was healthy; the next failure starts a fresh count.
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(300);
+
+/// Decides whether and when a failed tunnel is restarted.
+struct RestartPolicy {
+    max_restarts: u32,
+    restarts: u32,
+}
+
+impl RestartPolicy {
+    fn new(max_restarts: u32) -> Self {
+        RestartPolicy {
+            max_restarts,
+            restarts: 0,
+        }
+    }
+
+    /// Returns the delay before the next attempt, or `None` to give up.
+    fn on_failure(&mut self, error: &VtrunkdError, ran_for: Duration) -> Option<Duration> {
+        // A bad config or missing privileges fail the same way every time.
+        if matches!(
+            error,
+            VtrunkdError::InvalidConfig(_) | VtrunkdError::PermissionDenied(_)
+        ) {
+            return None;
+        }
+        if ran_for >= RESTART_RESET_AFTER {
+            self.restarts = 0;
+        }
+        if self.restarts >= self.max_restarts {
+            return None;
+        }
+        let delay = RESTART_BACKOFF_INITIAL
+            .saturating_mul(1 << self.restarts.min(16))
+            .min(RESTART_BACKOFF_MAX);
+        self.restarts += 1;
+        Some(delay)
+    }
+}
+
+/// Runs the tunnel and restarts it with exponential backoff when it fails.
+///
+/// Link counters live in `stats`, which outlives every attempt, so totals keep
+/// accumulating across restarts. The control socket stays up in between.
+pub async fn run(
+    config: Config,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+    mut control_rx: mpsc::Receiver<ControlRequest>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> VtrunkdResult<()> {
+    let mut policy = RestartPolicy::new(
+        config
+            .wireguard
+            .max_restarts
+            .unwrap_or(DEFAULT_MAX_RESTARTS),
+    );
+    loop {
+        let started = Instant::now();
+        let error = match wireguard::run(
+            &config,
+            Arc::clone(&stats),
+            events.clone(),
+            &mut control_rx,
+            &mut shutdown_rx,
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+        let Some(delay) = policy.on_failure(&error, started.elapsed()) else {
+            if policy.restarts > 0 {
+                error!(
+                    "WireGuard tunnel failed {} times in a row; giving up",
+                    policy.restarts + 1
+                );
+            }
+            return Err(error);
+        };
+        error!(
+            event = "tunnel_restart",
+            attempt = policy.restarts,
+            "WireGuard tunnel failed: {}; restarting in {}s (attempt {}/{})",
+            error,
+            delay.as_secs(),
+            policy.restarts,
+            policy.max_restarts
+        );
+        events::emit(
+            &events,
+            TunnelEvent::TunnelRestart {
+                attempt: policy.restarts,
+                error: error.to_string(),
+            },
+        );
+        if !wait_for_restart(delay, &stats, &mut control_rx, &mut shutdown_rx).await {
+            return Ok(());
+        }
+        info!("Restarting WireGuard tunnel");
+    }
+}
+
+/// Sleeps out the backoff while still answering the control socket.
+///
+/// Returns `false` if shutdown was requested in the meantime.
+async fn wait_for_restart(
+    delay: Duration,
+    stats: &StatsRegistry,
+    control_rx: &mut mpsc::Receiver<ControlRequest>,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+) -> bool {
+    let sleep = tokio::time::sleep(delay);
+    tokio::pin!(sleep);
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return true,
+            _ = &mut *shutdown_rx => return false,
+            Some(request) = control_rx.recv() => {
+                let result = match request.command {
+                    ControlCommand::Stats => serde_json::to_string(&stats.snapshot())
+                        .map_err(|e| format!("Failed to encode stats: {}", e)),
+                    _ => Err("Tunnel is restarting; try again shortly".to_string()),
+                };
+                let _ = request.reply.send(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient() -> VtrunkdError {
+        VtrunkdError::Network("tun read failed".to_string())
+    }
+
+    #[test]
+    fn backoff_doubles_and_gives_up() {
+        let mut policy = RestartPolicy::new(3);
+        let delays: Vec<_> = (0..4)
+            .map(|_| policy.on_failure(&transient(), Duration::from_secs(1)))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(4)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn long_healthy_run_resets_backoff() {
+        let mut policy = RestartPolicy::new(20);
+        for _ in 0..10 {
+            policy.on_failure(&transient(), Duration::ZERO);
+        }
+        assert_eq!(
+            policy.on_failure(&transient(), Duration::ZERO),
+            Some(RESTART_BACKOFF_MAX)
+        );
+        assert_eq!(
+            policy.on_failure(&transient(), RESTART_RESET_AFTER),
+            Some(RESTART_BACKOFF_INITIAL)
+        );
+    }
+
+    #[test]
+    fn config_errors_are_not_retried() {
+        let mut policy = RestartPolicy::new(DEFAULT_MAX_RESTARTS);
+        let error = VtrunkdError::InvalidConfig("bad key".to_string());
+        assert_eq!(policy.on_failure(&error, Duration::ZERO), None);
+        assert_eq!(
+            RestartPolicy::new(0).on_failure(&transient(), Duration::ZERO),
+            None
+        );
+    }
+}
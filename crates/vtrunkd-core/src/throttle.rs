@@ -0,0 +1,96 @@
+//! Per-source handshake throttling (`wireguard.handshake_source_limit`).
+//!
+//! Each handshake costs the tunnel loop a round of Diffie-Hellman. Past the global
+//! `handshake_rate_limit`, boringtun answers with cookie replies instead, which spoofed
+//! sources cannot use; this keeps a single source that can from taking the loop over.
+//! Handshakes beyond the limit are dropped as they are received, before the loop sees them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+const HANDSHAKE_INITIATION: u8 = 1;
+const HANDSHAKE_INITIATION_LEN: usize = 148;
+const HANDSHAKE_RESPONSE: u8 = 2;
+const HANDSHAKE_RESPONSE_LEN: usize = 92;
+const WINDOW: Duration = Duration::from_secs(1);
+/// Sources tracked per window. A flood from more addresses than this is left to the
+/// cookies; the map stays bounded.
+const MAX_SOURCES: usize = 4096;
+
+/// Whether a datagram is a WireGuard handshake initiation or response.
+pub fn is_handshake(datagram: &[u8]) -> bool {
+    match datagram {
+        [HANDSHAKE_INITIATION, 0, 0, 0, ..] => datagram.len() == HANDSHAKE_INITIATION_LEN,
+        [HANDSHAKE_RESPONSE, 0, 0, 0, ..] => datagram.len() == HANDSHAKE_RESPONSE_LEN,
+        _ => false,
+    }
+}
+
+/// Handshakes counted per source over one-second windows.
+pub struct HandshakeThrottle {
+    limit: u32,
+    window_start: Instant,
+    counts: HashMap<IpAddr, u32>,
+}
+
+impl HandshakeThrottle {
+    pub fn new(limit: u32, now: Instant) -> Self {
+        HandshakeThrottle {
+            limit,
+            window_start: now,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Counts a handshake from `source`; false once it is over the limit for this window.
+    pub fn allow(&mut self, source: IpAddr, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.counts.clear();
+        }
+        if self.counts.len() >= MAX_SOURCES && !self.counts.contains_key(&source) {
+            return true;
+        }
+        let count = self.counts.entry(source).or_insert(0);
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn sources_are_limited_separately_per_window() {
+        let start = Instant::now();
+        let mut throttle = HandshakeThrottle::new(2, start);
+        let flood: IpAddr = "198.51.100.1".parse().unwrap();
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(throttle.allow(flood, start));
+        assert!(throttle.allow(flood, start));
+        assert!(!throttle.allow(flood, start));
+        assert!(throttle.allow(peer, start));
+        assert!(throttle.allow(flood, start + WINDOW));
+
+        for host in 0..MAX_SOURCES as u32 {
+            throttle.allow(
+                IpAddr::from(Ipv4Addr::from((10 << 24) | host)),
+                start + WINDOW,
+            );
+        }
+        let untracked: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..3 {
+            assert!(throttle.allow(untracked, start + WINDOW));
+        }
+
+        let mut initiation = vec![0u8; HANDSHAKE_INITIATION_LEN];
+        initiation[0] = HANDSHAKE_INITIATION;
+        assert!(is_handshake(&initiation));
+        assert!(!is_handshake(&initiation[..HANDSHAKE_RESPONSE_LEN]));
+        initiation[0] = 4;
+        assert!(!is_handshake(&initiation));
+    }
+}
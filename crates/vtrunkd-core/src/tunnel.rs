@@ -0,0 +1,328 @@
+//! Embedding API: one bonded tunnel, started and stopped from the host application.
+//!
+//! ```no_run
+//! # async fn embed(config: vtrunkd_core::config::Config) -> vtrunkd_core::error::VtrunkdResult<()> {
+//! use vtrunkd_core::control::LinkAdminState;
+//! use vtrunkd_core::Tunnel;
+//!
+//! let mut tunnel = Tunnel::builder().config(config).build()?;
+//! let mut events = tunnel.events();
+//! tunnel.start()?;
+//! tunnel.set_link_state("lte", LinkAdminState::Drain).await?;
+//! while let Ok(event) = events.recv().await {
+//!     println!("{:?}", event);
+//! #   break;
+//! }
+//! tunnel.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::control::{self, ControlCommand, ControlRequest, LinkAdminState};
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::{self, EventSender, TunnelEvent};
+use crate::state;
+use crate::stats::{StatsRegistry, StatsSnapshot};
+use crate::supervisor;
+
+/// How long the tunnel gets to say goodbye to the peer before it is aborted.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+/// Control requests queued while the tunnel loop is busy.
+const CONTROL_CHANNEL_DEPTH: usize = 16;
+
+#[derive(Default)]
+pub struct TunnelBuilder {
+    config: Option<Config>,
+}
+
+impl TunnelBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Validates the configuration; nothing is opened until [`Tunnel::start`].
+    pub fn build(self) -> VtrunkdResult<Tunnel> {
+        let config = self
+            .config
+            .ok_or_else(|| VtrunkdError::Config("Tunnel needs a configuration".to_string()))?;
+        config.validate()?;
+        let (control_tx, control_rx) = mpsc::channel(CONTROL_CHANNEL_DEPTH);
+        let stats = StatsRegistry::new(&config);
+        // Once per tunnel: supervisor restarts keep counting in the same registry.
+        if let Some(saved) = config
+            .wireguard
+            .state_file
+            .as_deref()
+            .and_then(|path| state::load(Path::new(path)))
+        {
+            stats.restore(&saved.stats);
+        }
+        Ok(Tunnel {
+            stats: Arc::new(stats),
+            events: events::channel(),
+            config,
+            control_tx,
+            control_rx: Some(control_rx),
+            running: None,
+        })
+    }
+}
+
+struct Running {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<VtrunkdResult<()>>,
+}
+
+/// A bonded WireGuard tunnel with its link counters, event stream and link control.
+///
+/// It runs under the supervisor, so failures restart it with backoff as in the daemon.
+/// A tunnel runs once; build a new one to start again after [`Tunnel::stop`].
+pub struct Tunnel {
+    config: Config,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+    control_tx: mpsc::Sender<ControlRequest>,
+    control_rx: Option<mpsc::Receiver<ControlRequest>>,
+    running: Option<Running>,
+}
+
+impl Tunnel {
+    pub fn builder() -> TunnelBuilder {
+        TunnelBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Creates the TUN device and link sockets on the current tokio runtime.
+    pub fn start(&mut self) -> VtrunkdResult<()> {
+        let (run, stop) = self.prepare()?;
+        self.running = Some(Running {
+            stop,
+            task: tokio::spawn(run),
+        });
+        Ok(())
+    }
+
+    /// Says goodbye to the peer and waits up to [`SHUTDOWN_GRACE`] before aborting.
+    pub async fn stop(&mut self) -> VtrunkdResult<()> {
+        let Some(Running { stop, mut task }) = self.running.take() else {
+            return Ok(());
+        };
+        let _ = stop.send(());
+        match tokio::time::timeout(SHUTDOWN_GRACE, &mut task).await {
+            Ok(result) => result.map_err(join_error)?,
+            Err(_) => {
+                warn!(
+                    "WireGuard task did not stop within {:?}; aborting",
+                    SHUTDOWN_GRACE
+                );
+                task.abort();
+                let _ = task.await;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+            .as_ref()
+            .is_some_and(|running| !running.task.is_finished())
+    }
+
+    /// Runs in the current task until `shutdown` resolves, which is how the daemon uses it.
+    ///
+    /// Returns an error if the tunnel stops on its own, even cleanly.
+    pub async fn run_until<S>(&mut self, shutdown: S) -> VtrunkdResult<()>
+    where
+        S: Future<Output = std::io::Result<()>> + Send,
+    {
+        let (run, stop) = self.prepare()?;
+        run_until_shutdown(run, shutdown, stop).await
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// A snapshot every `every` until the receiver is dropped.
+    pub fn stats_stream(&self, every: Duration) -> mpsc::Receiver<StatsSnapshot> {
+        let (tx, rx) = mpsc::channel(1);
+        let stats = Arc::clone(&self.stats);
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(every);
+            loop {
+                timer.tick().await;
+                if tx.send(stats.snapshot()).await.is_err() {
+                    return;
+                }
+            }
+        });
+        rx
+    }
+
+    pub fn events(&self) -> broadcast::Receiver<TunnelEvent> {
+        self.events.subscribe()
+    }
+
+    /// The sender events are published on, for the control socket and hooks.
+    pub fn event_sender(&self) -> EventSender {
+        self.events.clone()
+    }
+
+    /// The channel the control socket forwards commands on.
+    pub fn control_sender(&self) -> mpsc::Sender<ControlRequest> {
+        self.control_tx.clone()
+    }
+
+    pub async fn set_link_state(&self, name: &str, state: LinkAdminState) -> VtrunkdResult<String> {
+        self.command(ControlCommand::SetLinkState {
+            name: name.to_string(),
+            state,
+        })
+        .await
+    }
+
+    /// Sends a control command as the control socket would; answered only while running.
+    pub async fn command(&self, command: ControlCommand) -> VtrunkdResult<String> {
+        if self.running.is_none() {
+            return Err(VtrunkdError::Control("Tunnel is not running".to_string()));
+        }
+        control::dispatch(&self.control_tx, command, "library")
+            .await
+            .map_err(VtrunkdError::Control)
+    }
+
+    fn prepare(
+        &mut self,
+    ) -> VtrunkdResult<(
+        impl Future<Output = VtrunkdResult<()>> + Send + 'static,
+        oneshot::Sender<()>,
+    )> {
+        let control_rx = self
+            .control_rx
+            .take()
+            .ok_or_else(|| VtrunkdError::Control("Tunnel already started".to_string()))?;
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let run = supervisor::run(
+            self.config.clone(),
+            Arc::clone(&self.stats),
+            self.events.clone(),
+            control_rx,
+            stop_rx,
+        );
+        Ok((run, stop_tx))
+    }
+}
+
+fn join_error(err: tokio::task::JoinError) -> VtrunkdError {
+    VtrunkdError::Network(format!("WireGuard task join error: {}", err))
+}
+
+async fn run_until_shutdown<R, S>(
+    run_fut: R,
+    shutdown: S,
+    stop: oneshot::Sender<()>,
+) -> VtrunkdResult<()>
+where
+    R: Future<Output = VtrunkdResult<()>> + Send + 'static,
+    S: Future<Output = std::io::Result<()>> + Send,
+{
+    let mut run_handle = tokio::spawn(run_fut);
+    tokio::select! {
+        result = &mut run_handle => {
+            match result {
+                Ok(Ok(())) => Err(VtrunkdError::Network(
+                    "WireGuard task exited unexpectedly".to_string(),
+                )),
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(join_error(e)),
+            }
+        }
+        shutdown_result = shutdown => {
+            shutdown_result?;
+            info!("Received shutdown signal");
+            let _ = stop.send(());
+            if tokio::time::timeout(SHUTDOWN_GRACE, &mut run_handle).await.is_err() {
+                warn!("WireGuard task did not stop within {:?}; aborting", SHUTDOWN_GRACE);
+                run_handle.abort();
+                let _ = run_handle.await;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_until_shutdown_errors_on_run_failure() {
+        let run_fut = async { Err(VtrunkdError::Network("boom".to_string())) };
+        let shutdown = std::future::pending::<std::io::Result<()>>();
+        let result = run_until_shutdown(run_fut, shutdown, oneshot::channel().0).await;
+        assert!(matches!(result, Err(VtrunkdError::Network(_))));
+    }
+
+    #[tokio::test]
+    async fn run_until_shutdown_errors_on_unexpected_exit() {
+        let run_fut = async { Ok(()) };
+        let shutdown = std::future::pending::<std::io::Result<()>>();
+        let result = run_until_shutdown(run_fut, shutdown, oneshot::channel().0).await;
+        assert!(matches!(result, Err(VtrunkdError::Network(_))));
+    }
+
+    #[tokio::test]
+    async fn run_until_shutdown_returns_ok_on_shutdown() {
+        let run_fut = std::future::pending::<VtrunkdResult<()>>();
+        let shutdown = async { Ok(()) };
+        let result = run_until_shutdown(run_fut, shutdown, oneshot::channel().0).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_until_shutdown_lets_run_finish_gracefully() {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let (done_tx, done_rx) = oneshot::channel();
+        let run_fut = async move {
+            let _ = stop_rx.await;
+            let _ = done_tx.send(());
+            Ok(())
+        };
+        let shutdown = async { Ok(()) };
+        let result = run_until_shutdown(run_fut, shutdown, stop_tx).await;
+        assert!(result.is_ok());
+        assert!(done_rx.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn builder_validates_and_commands_need_a_running_tunnel() {
+        let mut config = Config::default();
+        config.network.mtu = 0;
+        assert!(matches!(
+            Tunnel::builder().config(config).build(),
+            Err(VtrunkdError::InvalidConfig(_))
+        ));
+
+        let tunnel = Tunnel::builder().config(Config::default()).build().unwrap();
+        assert!(!tunnel.is_running());
+        assert!(matches!(
+            tunnel.set_link_state("link-0", LinkAdminState::Drain).await,
+            Err(VtrunkdError::Control(_))
+        ));
+        assert_eq!(tunnel.stats().links.len(), 1);
+    }
+}
@@ -0,0 +1,264 @@
+//! Read-only WireGuard cross-platform UAPI, as wireguard-go and boringtun serve it, so
+//! `wg show` and WireGuard exporters see the bonded tunnel as one peer.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+#[cfg(unix)]
+use tracing::{debug, info, warn};
+use zeroize::Zeroize;
+
+use crate::control::ControlRequest;
+#[cfg(unix)]
+use crate::control::{self, ControlCommand};
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+pub const SOCKET_DIR: &str = "/var/run/wireguard";
+
+/// What `get=1` reports; the bond shows up as a single peer on its active link. The keys
+/// are wiped when it is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Status {
+    pub private_key: [u8; 32],
+    pub listen_port: Option<u16>,
+    pub public_key: [u8; 32],
+    pub preshared_key: Option<[u8; 32]>,
+    pub endpoint: Option<SocketAddr>,
+    pub last_handshake: Option<SystemTime>,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl Drop for Status {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+        self.preshared_key.zeroize();
+    }
+}
+
+pub fn socket_path(interface: &str) -> PathBuf {
+    Path::new(SOCKET_DIR).join(format!("{}.sock", interface))
+}
+
+/// Binds the UAPI socket and answers `get` from the tunnel loop; `set` is refused because
+/// the vtrunkd config owns the keys and peers.
+#[cfg(unix)]
+pub fn spawn_server(path: &Path, tx: mpsc::Sender<ControlRequest>) -> VtrunkdResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if path.exists() {
+        // Another vtrunkd (or wireguard-go) serving this interface keeps its socket.
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(VtrunkdError::AlreadyRunning(format!(
+                "UAPI socket {:?} is in use",
+                path
+            )));
+        }
+        // The previous owner is gone (or is the instance being upgraded, which has exited).
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // The response carries the private key, like the kernel's `wg show private-key`.
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("WireGuard UAPI socket listening on {:?}", path);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream, tx).await {
+                            debug!("UAPI connection error: {}", err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    warn!("UAPI socket accept error: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// wireguard-go serves the UAPI over a named pipe on Windows, which is not done here.
+#[cfg(not(unix))]
+pub fn spawn_server(path: &Path, _tx: mpsc::Sender<ControlRequest>) -> VtrunkdResult<()> {
+    Err(VtrunkdError::Control(format!(
+        "UAPI socket {:?} needs Unix domain sockets, which this platform lacks",
+        path
+    )))
+}
+
+#[cfg(unix)]
+async fn handle_connection(
+    stream: UnixStream,
+    tx: mpsc::Sender<ControlRequest>,
+) -> VtrunkdResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(operation) = lines.next_line().await? {
+        // Every request, including its arguments, ends with an empty line.
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                break;
+            }
+        }
+        let response = match operation.as_str() {
+            "get=1" => match control::dispatch(&tx, ControlCommand::Uapi, "uapi").await {
+                Ok(body) => format!("{}errno=0\n\n", body),
+                Err(message) => {
+                    debug!("UAPI get failed: {}", message);
+                    format!("errno={}\n\n", nix::libc::EIO)
+                }
+            },
+            "set=1" => format!("errno={}\n\n", nix::libc::EOPNOTSUPP),
+            _ => return Ok(()),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+fn hex(key: &[u8; 32]) -> String {
+    key.iter().fold(String::with_capacity(64), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+
+/// The `get=1` body without the trailing `errno` line.
+pub(crate) fn render(status: &Status) -> String {
+    let mut out = format!("private_key={}\n", hex(&status.private_key));
+    if let Some(port) = status.listen_port {
+        let _ = writeln!(out, "listen_port={}", port);
+    }
+    let _ = writeln!(out, "public_key={}", hex(&status.public_key));
+    if let Some(key) = &status.preshared_key {
+        let _ = writeln!(out, "preshared_key={}", hex(key));
+    }
+    out.push_str("protocol_version=1\n");
+    if let Some(endpoint) = status.endpoint {
+        let _ = writeln!(out, "endpoint={}", endpoint);
+    }
+    let handshake = status
+        .last_handshake
+        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let _ = writeln!(out, "last_handshake_time_sec={}", handshake.as_secs());
+    let _ = writeln!(out, "last_handshake_time_nsec={}", handshake.subsec_nanos());
+    let _ = writeln!(out, "tx_bytes={}", status.tx_bytes);
+    let _ = writeln!(out, "rx_bytes={}", status.rx_bytes);
+    let _ = writeln!(
+        out,
+        "persistent_keepalive_interval={}",
+        status.persistent_keepalive.unwrap_or(0)
+    );
+    // vtrunkd leaves routing to the kernel, so the peer may send from any address.
+    out.push_str("allowed_ip=0.0.0.0/0\nallowed_ip=::/0\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn status() -> Status {
+        Status {
+            private_key: [0x11; 32],
+            listen_port: Some(51820),
+            public_key: [0xab; 32],
+            preshared_key: None,
+            endpoint: Some("192.0.2.1:51820".parse().unwrap()),
+            last_handshake: Some(UNIX_EPOCH + Duration::new(1_700_000_000, 5)),
+            tx_bytes: 100,
+            rx_bytes: 200,
+            persistent_keepalive: Some(25),
+        }
+    }
+
+    #[test]
+    fn render_matches_the_uapi_format() {
+        let body = render(&status());
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines[0], format!("private_key={}", "11".repeat(32)));
+        assert_eq!(lines[1], "listen_port=51820");
+        assert_eq!(lines[2], format!("public_key={}", "ab".repeat(32)));
+        for expected in [
+            "endpoint=192.0.2.1:51820",
+            "last_handshake_time_sec=1700000000",
+            "last_handshake_time_nsec=5",
+            "tx_bytes=100",
+            "rx_bytes=200",
+            "persistent_keepalive_interval=25",
+            "allowed_ip=::/0",
+        ] {
+            assert!(lines.contains(&expected), "missing {}", expected);
+        }
+        assert!(!body.contains("preshared_key"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn answers_get_from_the_tunnel_and_refuses_set() {
+        let dir = std::env::temp_dir().join(format!("vtrunkd-uapi-{}", std::process::id()));
+        let path = dir.join("tun-test.sock");
+        let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+        // A socket left by an instance that died is taken over; a live one is not.
+        std::fs::create_dir_all(&dir).unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        spawn_server(&path, tx.clone()).unwrap();
+        assert!(matches!(
+            spawn_server(&path, tx),
+            Err(VtrunkdError::AlreadyRunning(_))
+        ));
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                assert_eq!(request.command, ControlCommand::Uapi);
+                let _ = request.reply.send(Ok(render(&status())));
+            }
+        });
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        writer
+            .write_all(b"get=1\n\nset=1\nlisten_port=1\n\n")
+            .await
+            .unwrap();
+
+        let mut response = Vec::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).await.unwrap() > 0 {
+            response.push(line.trim_end().to_string());
+            line.clear();
+            if response.iter().filter(|l| l.starts_with("errno=")).count() == 2 {
+                break;
+            }
+        }
+        assert!(response.contains(&"listen_port=51820".to_string()));
+        assert!(response.contains(&"errno=0".to_string()));
+        assert_eq!(
+            response.last(),
+            Some(&format!("errno={}", nix::libc::EOPNOTSUPP))
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,462 @@
+//! OpenWrt UCI configuration (`/etc/config/vtrunkd`), read in place of the YAML file.
+//!
+//! Each section type stands for a part of the YAML layout and its options for that part's
+//! fields, so every setting has the same name in both:
+//!
+//! ```text
+//! config vtrunkd 'main'   # top-level options (sandbox); `enabled` is the init script's
+//! config network          # network
+//! config wireguard        # wireguard
+//! config link 'wan'       # one wireguard.links entry, named after the section
+//! config control          # control
+//! config http             # control.http
+//! config logging          # logging
+//! config audit            # logging.audit
+//! config runtime          # runtime
+//! config mqtt             # mqtt
+//! config hooks            # hooks (the pre_up/post_up/... command lists)
+//! config hook             # one hooks.on_<event> action: event, command or url
+//! ```
+//!
+//! `list` options become sequences. Values are typed by the field they fill, so `1`/`0`
+//! and `true`/`false` both work for switches.
+
+use std::collections::BTreeMap;
+
+use serde::de::value::{Error as ValueError, MapDeserializer, SeqDeserializer};
+use serde::de::{self, Error as _, IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize};
+
+use crate::config::Config;
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+/// Whether `text` reads as UCI: its first statement is `package` or `config`.
+pub fn is_uci(text: &str) -> bool {
+    text.lines()
+        .map(str::trim_start)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| line.split_whitespace().next())
+        .is_some_and(|word| word == "package" || word == "config")
+}
+
+/// Parses a UCI document into a configuration; `config::load_config` validates it.
+pub fn parse(text: &str) -> VtrunkdResult<Config> {
+    let tree = Tree::from_sections(&parse_sections(text)?)?;
+    Config::deserialize(tree.into_node())
+        .map_err(|e| VtrunkdError::InvalidConfig(format!("UCI configuration: {}", e)))
+}
+
+struct Section {
+    kind: String,
+    name: Option<String>,
+    line: usize,
+    options: Vec<(String, Node)>,
+}
+
+fn parse_sections(text: &str) -> VtrunkdResult<Vec<Section>> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let invalid = |message: &str| {
+            VtrunkdError::InvalidConfig(format!("UCI line {}: {}", line_no, message))
+        };
+        let words = split_words(line).map_err(|e| invalid(&e))?;
+        match words.as_slice() {
+            [] => {}
+            [keyword, ..] if keyword == "package" => {}
+            [keyword, kind] | [keyword, kind, _] if keyword == "config" => {
+                sections.push(Section {
+                    kind: kind.clone(),
+                    name: words.get(2).cloned(),
+                    line: line_no,
+                    options: Vec::new(),
+                });
+            }
+            [keyword, key, value] if keyword == "option" || keyword == "list" => {
+                let section = sections
+                    .last_mut()
+                    .ok_or_else(|| invalid("option outside a config section"))?;
+                let value = Node::Scalar(value.clone());
+                let existing = section.options.iter_mut().find(|(name, _)| name == key);
+                match (keyword.as_str(), existing) {
+                    ("option", Some(_)) => {
+                        return Err(invalid(&format!("option {} set twice", key)));
+                    }
+                    ("option", None) => section.options.push((key.clone(), value)),
+                    (_, Some((_, Node::Seq(items)))) => items.push(value),
+                    (_, Some(_)) => {
+                        return Err(invalid(&format!("{} is both an option and a list", key)));
+                    }
+                    (_, None) => section.options.push((key.clone(), Node::Seq(vec![value]))),
+                }
+            }
+            _ => return Err(invalid(&format!("cannot parse {:?}", line.trim()))),
+        }
+    }
+    Ok(sections)
+}
+
+/// Splits a line into words the way the shell-like UCI syntax does: single quotes are
+/// literal, double quotes and bare words take backslash escapes, and adjacent pieces join.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => return Ok(words),
+            Some(_) => {}
+        }
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.push(chars.next().unwrap_or('\\')),
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                },
+                '\\' => word.push(chars.next().unwrap_or('\\')),
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// The YAML layout being rebuilt from the sections.
+#[derive(Default)]
+struct Tree {
+    top: BTreeMap<String, Vec<(String, Node)>>,
+    links: Vec<Node>,
+    hooks: BTreeMap<String, Vec<Node>>,
+}
+
+impl Tree {
+    fn from_sections(sections: &[Section]) -> VtrunkdResult<Self> {
+        let mut tree = Tree::default();
+        for section in sections {
+            let invalid = |message: String| {
+                VtrunkdError::InvalidConfig(format!("UCI line {}: {}", section.line, message))
+            };
+            let mut options = section.options.clone();
+            match section.kind.as_str() {
+                "vtrunkd" => {
+                    options.retain(|(key, _)| key != "enabled");
+                    tree.top.entry(String::new()).or_default().extend(options);
+                }
+                "network" | "wireguard" | "control" | "logging" | "runtime" | "mqtt" | "hooks" => {
+                    tree.top
+                        .entry(section.kind.clone())
+                        .or_default()
+                        .extend(options);
+                }
+                "http" | "audit" => {
+                    let parent = if section.kind == "http" {
+                        "control"
+                    } else {
+                        "logging"
+                    };
+                    tree.top
+                        .entry(parent.to_string())
+                        .or_default()
+                        .push((section.kind.clone(), Node::Map(options)));
+                }
+                "link" => {
+                    if let Some(name) = &section.name {
+                        if !options.iter().any(|(key, _)| key == "name") {
+                            options.insert(0, ("name".to_string(), Node::Scalar(name.clone())));
+                        }
+                    }
+                    tree.links.push(Node::Map(options));
+                }
+                "hook" => {
+                    let index = options
+                        .iter()
+                        .position(|(key, _)| key == "event")
+                        .ok_or_else(|| invalid("hook sections need an event".to_string()))?;
+                    let event = match options.remove(index).1 {
+                        Node::Scalar(event) => event,
+                        _ => return Err(invalid("hook event must be an option".to_string())),
+                    };
+                    tree.hooks
+                        .entry(format!("on_{}", event))
+                        .or_default()
+                        .push(Node::Map(options));
+                }
+                other => return Err(invalid(format!("unknown section type {}", other))),
+            }
+        }
+        Ok(tree)
+    }
+
+    fn into_node(mut self) -> Node {
+        let mut top = self.top.remove("").unwrap_or_default();
+        if !self.links.is_empty() {
+            self.top
+                .entry("wireguard".to_string())
+                .or_default()
+                .push(("links".to_string(), Node::Seq(self.links)));
+        }
+        let hooks = self.top.entry("hooks".to_string()).or_default();
+        hooks.extend(
+            self.hooks
+                .into_iter()
+                .map(|(event, actions)| (event, Node::Seq(actions))),
+        );
+        if hooks.is_empty() {
+            self.top.remove("hooks");
+        }
+        top.extend(
+            self.top
+                .into_iter()
+                .map(|(kind, options)| (kind, Node::Map(options))),
+        );
+        Node::Map(top)
+    }
+}
+
+/// A value from the file, deserialized into whatever type the field it fills has.
+#[derive(Debug, Clone)]
+enum Node {
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn scalar(&self) -> Result<&str, ValueError> {
+        match self {
+            Node::Scalar(value) => Ok(value),
+            Node::Seq(_) => Err(ValueError::custom("expected an option, found a list")),
+            Node::Map(_) => Err(ValueError::custom("expected an option, found a section")),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, what: &str) -> Result<T, ValueError> {
+        let value = self.scalar()?;
+        value
+            .parse()
+            .map_err(|_| ValueError::custom(format!("invalid {} {:?}", what, value)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ValueError> for Node {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident => $visit:ident: $ty:ty,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+                visitor.$visit(self.parse::<$ty>("number")?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Node {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        match self {
+            Node::Scalar(value) => visitor.visit_string(value),
+            Node::Seq(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter())),
+            Node::Map(entries) => visitor.visit_map(MapDeserializer::new(entries.into_iter())),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        match self.scalar()? {
+            "1" | "true" | "yes" | "on" | "enabled" => visitor.visit_bool(true),
+            "0" | "false" | "no" | "off" | "disabled" => visitor.visit_bool(false),
+            other => Err(ValueError::custom(format!("invalid boolean {:?}", other))),
+        }
+    }
+
+    deserialize_number! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        visitor.visit_some(self)
+    }
+
+    /// An `option` where a list is expected is a list of one.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ValueError> {
+        match self {
+            Node::Scalar(_) => visitor.visit_seq(SeqDeserializer::new(std::iter::once(self))),
+            node => node.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        let value: de::value::StringDeserializer<ValueError> =
+            self.scalar()?.to_string().into_deserializer();
+        visitor.visit_enum(value)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BondingMode;
+    use crate::sandbox::SandboxMode;
+
+    const SAMPLE: &str = r#"
+package vtrunkd
+
+config vtrunkd 'main'
+	option enabled '1'
+	option sandbox 'basic'
+
+config network
+	option mtu '1380'
+	option buffer_size 65536
+	option address '10.10.0.2'
+	option netmask "255.255.255.0"
+	list addresses 'fd00:10::2/64'
+	option clamp_mss '1'
+
+config wireguard
+	option private_key 'aGVsbG8='
+	option peer_public_key 'd29ybGQ='
+	option bonding_mode 'redundant'
+	option compression 'true'
+
+# LTE first, named by its section
+config link 'lte'
+	option bind_device 'wwan0'
+	option endpoint 'vps.example.net:51820'
+	option weight '2'
+
+config link
+	option name 'wan'
+	option endpoint 'vps.example.net:51821'
+	option allowed_sources '198.51.100.0/24'
+
+config control
+	option socket '/var/run/vtrunkd.sock'
+
+config http
+	option bind '127.0.0.1:8080'
+	option token 'it'\''s secret'
+
+config hook
+	option event 'link_down'
+	option command 'logger -t vtrunkd down'
+"#;
+
+    #[test]
+    fn sections_fill_the_yaml_layout() {
+        assert!(is_uci(SAMPLE));
+        let config = parse(SAMPLE).unwrap();
+        assert_eq!(config.sandbox, Some(SandboxMode::Basic));
+        assert_eq!(config.network.mtu, 1380);
+        assert_eq!(config.network.buffer_size, 65536);
+        assert_eq!(config.network.netmask.as_deref(), Some("255.255.255.0"));
+        assert_eq!(
+            config.network.addresses,
+            Some(vec!["fd00:10::2/64".to_string()])
+        );
+        assert_eq!(config.network.clamp_mss, Some(true));
+        assert_eq!(config.wireguard.bonding_mode, Some(BondingMode::Redundant));
+        assert_eq!(config.wireguard.compression, Some(true));
+
+        let links = &config.wireguard.links;
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].name.as_deref(), Some("lte"));
+        assert_eq!(links[0].bind_device.as_deref(), Some("wwan0"));
+        assert_eq!(links[0].weight, Some(2));
+        assert_eq!(links[1].name.as_deref(), Some("wan"));
+        // A single `option` where a list goes is a list of one.
+        assert_eq!(
+            links[1].allowed_sources,
+            Some(vec!["198.51.100.0/24".to_string()])
+        );
+
+        let control = config.control.unwrap();
+        assert_eq!(control.socket.as_deref(), Some("/var/run/vtrunkd.sock"));
+        assert_eq!(control.http.unwrap().token, "it's secret");
+        let hooks = config.hooks.unwrap();
+        let down = hooks.on_link_down.unwrap();
+        assert_eq!(down[0].command.as_deref(), Some("logger -t vtrunkd down"));
+    }
+
+    #[test]
+    fn shipped_example_is_valid() {
+        let config = parse(include_str!("../../../dist/openwrt/vtrunkd.config")).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.wireguard.links[1].name.as_deref(), Some("wwan"));
+    }
+
+    #[test]
+    fn yaml_is_not_uci() {
+        assert!(!is_uci("# vtrunkd\nnetwork:\n  mtu: 1400\n"));
+        assert!(!is_uci(""));
+    }
+
+    #[test]
+    fn mistakes_name_the_line_or_the_value() {
+        let unknown = parse("config tunnel\n\toption mtu 1400\n").unwrap_err();
+        assert!(unknown.to_string().contains("line 1"), "{}", unknown);
+        assert!(unknown.to_string().contains("tunnel"), "{}", unknown);
+
+        let orphan = parse("\noption mtu 1400\n").unwrap_err();
+        assert!(orphan.to_string().contains("line 2"), "{}", orphan);
+
+        let twice = parse("config network\n\toption mtu 1\n\toption mtu 2\n").unwrap_err();
+        assert!(twice.to_string().contains("set twice"), "{}", twice);
+
+        let quote = parse("config network\n\toption mtu '1400\n").unwrap_err();
+        assert!(quote.to_string().contains("quote"), "{}", quote);
+
+        let number = SAMPLE.replace("'1380'", "'big'");
+        let number = parse(&number).unwrap_err();
+        assert!(number.to_string().contains("\"big\""), "{}", number);
+
+        let switch = SAMPLE.replace("option compression 'true'", "option compression 'maybe'");
+        let switch = parse(&switch).unwrap_err();
+        assert!(switch.to_string().contains("boolean"), "{}", switch);
+    }
+}
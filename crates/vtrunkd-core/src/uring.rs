@@ -0,0 +1,813 @@
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use io_uring::{opcode, squeue, types, IoUring};
+use nix::libc;
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinSet};
+use tracing::error;
+
+use crate::batch::{received_tos, to_socket_addr, to_storage, CONTROL_LEN};
+use crate::queue::TunSender;
+
+/// `user_data` of the read on the stop eventfd.
+const STOP: u64 = u64::MAX;
+/// Set in the `user_data` of the poll that guards each slot's read.
+const POLL: u64 = 1 << 62;
+/// `user_data` of the cancellations sent when a ring thread stops.
+const CANCEL: u64 = u64::MAX - 1;
+
+/// Wakes a ring thread so it exits. Owned by a task in the caller's `JoinSet`, so
+/// dropping or aborting the set stops the thread like it stops the epoll readers.
+struct StopOnDrop(OwnedFd);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        let one = 1u64.to_ne_bytes();
+        // SAFETY: writes 8 bytes from a live buffer to an eventfd this guard owns.
+        unsafe { libc::write(self.0.as_raw_fd(), one.as_ptr().cast(), one.len()) };
+    }
+}
+
+/// What a ring thread does with each completed read; `false` stops the thread.
+trait Sink: Send + 'static {
+    /// `src` and the TOS byte are only known for socket reads.
+    fn packet(&mut self, data: &[u8], src: Option<SocketAddr>, tos: u8) -> bool;
+    fn failed(&mut self, err: io::Error);
+}
+
+/// Forwards TUN packets to the tunnel loop, counting the ones a full queue drops.
+struct TunSink {
+    tx: TunSender,
+}
+
+impl Sink for TunSink {
+    fn packet(&mut self, data: &[u8], _src: Option<SocketAddr>, _tos: u8) -> bool {
+        self.tx.push(data.to_vec())
+    }
+
+    fn failed(&mut self, err: io::Error) {
+        self.tx.push_error(err);
+    }
+}
+
+/// One in-flight read; `iov` and `msg` point into the slot itself.
+struct Slot {
+    buf: Vec<u8>,
+    addr: libc::sockaddr_storage,
+    control: [u64; CONTROL_LEN],
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+// SAFETY: the raw pointers in `iov` and `msg` only refer to the slot's own heap data.
+unsafe impl Send for Slot {}
+
+/// Cancels every request a ring thread may have in flight, the stop read and each slot's
+/// poll and transfer, then waits until all `in_flight` of them have completed. Until then
+/// the kernel may still write into their buffers, so they must not be freed before this
+/// returns `Ok`.
+fn cancel_all(ring: &mut IoUring, slots: usize, in_flight: &mut usize) -> io::Result<()> {
+    let targets = (0..slots as u64).flat_map(|index| [POLL | index, index]);
+    for target in std::iter::once(STOP).chain(targets) {
+        let cancel = opcode::AsyncCancel::new(target).build().user_data(CANCEL);
+        // SAFETY: a cancellation refers to no memory.
+        while unsafe { ring.submission().push(&cancel) }.is_err() {
+            ring.submit()?;
+        }
+    }
+    while *in_flight > 0 {
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+        for entry in ring.completion() {
+            if entry.user_data() != CANCEL {
+                *in_flight -= 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The fixed state of a ring thread: which descriptor it reads and how.
+struct Reader {
+    fd: RawFd,
+    /// TUN queues use `READ_FIXED` on registered buffers; sockets use `RECVMSG` for the source.
+    socket: bool,
+    slots: Vec<Slot>,
+    /// Where the stop eventfd is read into; boxed so it can be leaked with the slots.
+    stop_buf: Box<[u8; 8]>,
+    /// Requests submitted and not yet completed.
+    in_flight: usize,
+}
+
+impl Reader {
+    fn new(fd: RawFd, socket: bool, depth: usize, buffer_size: usize) -> Self {
+        let mut slots: Vec<Slot> = (0..depth.max(1))
+            .map(|_| Slot {
+                buf: vec![0u8; buffer_size],
+                // SAFETY: sockaddr_storage, iovec and msghdr are plain old data; zeroes are valid.
+                addr: unsafe { mem::zeroed() },
+                control: [0; CONTROL_LEN],
+                iov: unsafe { mem::zeroed() },
+                msg: unsafe { mem::zeroed() },
+            })
+            .collect();
+        // Linked only once the slots have their final place; the vector never grows.
+        for slot in &mut slots {
+            slot.iov.iov_base = slot.buf.as_mut_ptr().cast();
+            slot.iov.iov_len = slot.buf.len();
+            slot.msg.msg_name = (&mut slot.addr as *mut libc::sockaddr_storage).cast();
+            slot.msg.msg_iov = &mut slot.iov;
+            slot.msg.msg_iovlen = 1;
+            slot.msg.msg_control = slot.control.as_mut_ptr().cast();
+        }
+        Reader {
+            fd,
+            socket,
+            slots,
+            stop_buf: Box::new([0; 8]),
+            in_flight: 0,
+        }
+    }
+
+    fn ring(&self) -> io::Result<IoUring> {
+        let entries = (2 * self.slots.len() + 1).next_power_of_two() as u32;
+        let ring = IoUring::new(entries)?;
+        if !self.socket {
+            let iovecs: Vec<libc::iovec> = self.slots.iter().map(|slot| slot.iov).collect();
+            // SAFETY: the buffers live in `self.slots`, which `run` frees only once nothing
+            // is in flight.
+            unsafe { ring.submitter().register_buffers(&iovecs)? };
+        }
+        Ok(ring)
+    }
+
+    /// Queues poll-then-read for `index`; the poll lets non-blocking descriptors wait.
+    fn queue(&mut self, ring: &mut IoUring, index: usize) -> io::Result<()> {
+        let fd = types::Fd(self.fd);
+        let slot = &mut self.slots[index];
+        let poll = opcode::PollAdd::new(fd, libc::POLLIN as u32)
+            .build()
+            .flags(squeue::Flags::IO_LINK)
+            .user_data(POLL | index as u64);
+        let read = if self.socket {
+            slot.msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            slot.msg.msg_controllen = mem::size_of_val(&slot.control) as _;
+            opcode::RecvMsg::new(fd, &mut slot.msg).build()
+        } else {
+            opcode::ReadFixed::new(
+                fd,
+                slot.buf.as_mut_ptr(),
+                slot.buf.len() as u32,
+                index as u16,
+            )
+            .offset(u64::MAX)
+            .build()
+        }
+        .user_data(index as u64);
+        // SAFETY: both entries point into `self.slots`, which `run` frees only once they
+        // have completed.
+        unsafe {
+            let mut submission = ring.submission();
+            submission
+                .push(&poll)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+            self.in_flight += 1;
+            submission
+                .push(&read)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+            self.in_flight += 1;
+        }
+        Ok(())
+    }
+
+    fn run(mut self, stop: OwnedFd, mut sink: impl Sink) {
+        let mut ring = match self.ring() {
+            Ok(ring) => ring,
+            Err(err) => return sink.failed(err),
+        };
+        if let Err(err) = self.serve(&mut ring, &stop, &mut sink) {
+            sink.failed(err);
+        }
+        if let Err(err) = cancel_all(&mut ring, self.slots.len(), &mut self.in_flight) {
+            // The kernel may still write into the buffers; leak them rather than free them
+            // under it.
+            error!("io_uring reader could not cancel its reads: {}", err);
+            mem::forget(ring);
+            mem::forget(self);
+        }
+    }
+
+    /// Reads until the stop eventfd fires, the sink refuses a packet or the ring fails,
+    /// possibly with requests still in flight.
+    fn serve(
+        &mut self,
+        ring: &mut IoUring,
+        stop: &OwnedFd,
+        sink: &mut impl Sink,
+    ) -> io::Result<()> {
+        let wait_stop = opcode::Read::new(
+            types::Fd(stop.as_raw_fd()),
+            self.stop_buf.as_mut_ptr(),
+            self.stop_buf.len() as u32,
+        )
+        .build()
+        .user_data(STOP);
+        // SAFETY: `stop_buf` is freed with the slots, only once nothing is in flight; the
+        // read holds its own reference to the eventfd.
+        unsafe {
+            ring.submission()
+                .push(&wait_stop)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        }
+        self.in_flight += 1;
+        for index in 0..self.slots.len() {
+            self.queue(ring, index)?;
+        }
+
+        loop {
+            ring.submit_and_wait(1)?;
+            let completed: Vec<(u64, i32)> = ring
+                .completion()
+                .map(|entry| (entry.user_data(), entry.result()))
+                .collect();
+            self.in_flight -= completed.len();
+            for (user_data, result) in completed {
+                if user_data == STOP {
+                    return Ok(());
+                }
+                if user_data & POLL != 0 {
+                    continue;
+                }
+                let index = user_data as usize;
+                if result >= 0 {
+                    let slot = &self.slots[index];
+                    let data = &slot.buf[..result as usize];
+                    let (src, tos) = if self.socket {
+                        (to_socket_addr(&slot.addr), received_tos(&slot.msg))
+                    } else {
+                        (None, 0)
+                    };
+                    if !sink.packet(data, src, tos) {
+                        return Ok(());
+                    }
+                } else if -result != libc::EAGAIN && -result != libc::ECANCELED {
+                    return Err(io::Error::from_raw_os_error(-result));
+                }
+                self.queue(ring, index)?;
+            }
+        }
+    }
+}
+
+/// Runs `reader` on its own thread until the task it adds to `tasks` is dropped or aborted.
+fn spawn(
+    tasks: &mut JoinSet<()>,
+    name: String,
+    reader: Reader,
+    sink: impl Sink,
+    keep_alive: impl Send + 'static,
+) -> io::Result<AbortHandle> {
+    // SAFETY: eventfd returns a new descriptor or -1.
+    let stop = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if stop < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `eventfd` just returned this descriptor and nothing else owns it.
+    let stop = unsafe { OwnedFd::from_raw_fd(stop) };
+    let thread_stop = stop.try_clone()?;
+    std::thread::Builder::new().name(name).spawn(move || {
+        let _keep_alive = keep_alive;
+        reader.run(thread_stop, sink);
+    })?;
+    Ok(tasks.spawn(async move {
+        let _stop = StopOnDrop(stop);
+        std::future::pending::<()>().await
+    }))
+}
+
+/// Reads one TUN queue through io_uring with `depth` registered buffers in flight.
+pub fn spawn_tun_reader<T: AsRawFd + Send + Sync + 'static>(
+    tasks: &mut JoinSet<()>,
+    queue: Arc<T>,
+    buffer_size: usize,
+    depth: usize,
+    tx: TunSender,
+) -> io::Result<()> {
+    let reader = Reader::new(queue.as_raw_fd(), false, depth, buffer_size);
+    let sink = TunSink { tx };
+    spawn(tasks, "vtrunkd-tun-ring".to_string(), reader, sink, queue)?;
+    Ok(())
+}
+
+/// Forwards datagrams from a link socket to `on_packet`, which returns `false` to stop.
+struct SocketSink<F> {
+    name: String,
+    on_packet: F,
+}
+
+impl<F: FnMut(&[u8], SocketAddr, u8) -> bool + Send + 'static> Sink for SocketSink<F> {
+    fn packet(&mut self, data: &[u8], src: Option<SocketAddr>, tos: u8) -> bool {
+        match src {
+            Some(src) => (self.on_packet)(data, src, tos),
+            None => true,
+        }
+    }
+
+    fn failed(&mut self, err: io::Error) {
+        error!("WireGuard socket recv error on {}: {}", self.name, err);
+    }
+}
+
+/// Receives on a link socket through io_uring with `depth` receives in flight; `on_packet`
+/// also gets the TOS byte when the socket asked for it.
+pub fn spawn_socket_reader<S, F>(
+    tasks: &mut JoinSet<()>,
+    name: &str,
+    socket: Arc<S>,
+    buffer_size: usize,
+    depth: usize,
+    on_packet: F,
+) -> io::Result<AbortHandle>
+where
+    S: AsRawFd + Send + Sync + 'static,
+    F: FnMut(&[u8], SocketAddr, u8) -> bool + Send + 'static,
+{
+    let reader = Reader::new(socket.as_raw_fd(), true, depth, buffer_size);
+    let sink = SocketSink {
+        name: name.to_string(),
+        on_packet,
+    };
+    spawn(
+        tasks,
+        format!("vtrunkd-{}-ring", name),
+        reader,
+        sink,
+        socket,
+    )
+}
+
+/// A queued write: a datagram with its destination and TOS byte, or a TUN packet.
+trait Outgoing: Send + 'static {
+    fn parts(&self) -> (Option<SocketAddr>, &[u8]);
+
+    fn tos(&self) -> Option<u8> {
+        None
+    }
+}
+
+impl Outgoing for Vec<u8> {
+    fn parts(&self) -> (Option<SocketAddr>, &[u8]) {
+        (None, self)
+    }
+}
+
+impl Outgoing for (SocketAddr, Vec<u8>, u8) {
+    fn parts(&self) -> (Option<SocketAddr>, &[u8]) {
+        (Some(self.0), &self.1)
+    }
+
+    fn tos(&self) -> Option<u8> {
+        Some(self.2)
+    }
+}
+
+/// One write of a batch; `iov` and `msg` point into the slot itself.
+struct WriteSlot {
+    /// Registered with the ring for TUN writes; datagrams are sent from the queue's buffer.
+    buf: Vec<u8>,
+    addr: libc::sockaddr_storage,
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+// SAFETY: the raw pointers in `iov` and `msg` only refer to the slot's own data, or to a
+// queued buffer the writer keeps until the write completes.
+unsafe impl Send for WriteSlot {}
+
+/// The fixed state of a writing ring thread, which takes up to one batch from its queue,
+/// waits for all of it to complete, then takes the next. A batch shares one TOS byte, so
+/// the socket is marked before it while nothing is in flight.
+struct Writer {
+    fd: RawFd,
+    slots: Vec<WriteSlot>,
+    /// Requests submitted and not yet completed.
+    in_flight: usize,
+}
+
+impl Writer {
+    /// `buffer_size` sizes the registered TUN buffers; sockets pass 0.
+    fn new(fd: RawFd, depth: usize, buffer_size: usize) -> Self {
+        let mut slots: Vec<WriteSlot> = (0..depth.max(1))
+            .map(|_| WriteSlot {
+                buf: vec![0u8; buffer_size],
+                // SAFETY: sockaddr_storage, iovec and msghdr are plain old data; zeroes are valid.
+                addr: unsafe { mem::zeroed() },
+                iov: unsafe { mem::zeroed() },
+                msg: unsafe { mem::zeroed() },
+            })
+            .collect();
+        for slot in &mut slots {
+            slot.msg.msg_name = (&mut slot.addr as *mut libc::sockaddr_storage).cast();
+            slot.msg.msg_iov = &mut slot.iov;
+            slot.msg.msg_iovlen = 1;
+        }
+        Writer {
+            fd,
+            slots,
+            in_flight: 0,
+        }
+    }
+
+    fn ring(&self) -> io::Result<IoUring> {
+        let entries = (2 * self.slots.len()).next_power_of_two() as u32;
+        let ring = IoUring::new(entries)?;
+        if self.slots[0].buf.is_empty() {
+            return Ok(ring);
+        }
+        let iovecs: Vec<libc::iovec> = self
+            .slots
+            .iter()
+            .map(|slot| libc::iovec {
+                iov_base: slot.buf.as_ptr() as *mut libc::c_void,
+                iov_len: slot.buf.len(),
+            })
+            .collect();
+        // SAFETY: the buffers live in `self.slots`, which `serve` frees only once nothing is
+        // in flight.
+        unsafe { ring.submitter().register_buffers(&iovecs)? };
+        Ok(ring)
+    }
+
+    /// Queues poll-then-write of `item` in slot `index`. TUN packets that fit go through
+    /// the slot's registered buffer with `WRITE_FIXED`, larger ones with a plain `WRITE`.
+    fn queue(&mut self, ring: &mut IoUring, index: usize, item: &impl Outgoing) -> io::Result<()> {
+        let fd = types::Fd(self.fd);
+        let slot = &mut self.slots[index];
+        let (to, data) = item.parts();
+        let poll = opcode::PollAdd::new(fd, libc::POLLOUT as u32)
+            .build()
+            .flags(squeue::Flags::IO_LINK)
+            .user_data(POLL | index as u64);
+        let write = match to {
+            Some(to) => {
+                let (addr, len) = to_storage(to);
+                slot.addr = addr;
+                slot.msg.msg_namelen = len;
+                slot.iov.iov_base = data.as_ptr() as *mut libc::c_void;
+                slot.iov.iov_len = data.len();
+                opcode::SendMsg::new(fd, &slot.msg).build()
+            }
+            None if data.len() <= slot.buf.len() => {
+                slot.buf[..data.len()].copy_from_slice(data);
+                opcode::WriteFixed::new(fd, slot.buf.as_ptr(), data.len() as u32, index as u16)
+                    .offset(u64::MAX)
+                    .build()
+            }
+            None => opcode::Write::new(fd, data.as_ptr(), data.len() as u32)
+                .offset(u64::MAX)
+                .build(),
+        }
+        .user_data(index as u64);
+        // SAFETY: both entries point into `self.slots` or `item`, which `serve` frees only
+        // once they have completed.
+        unsafe {
+            let mut submission = ring.submission();
+            submission
+                .push(&poll)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+            self.in_flight += 1;
+            submission
+                .push(&write)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+            self.in_flight += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes what `rx` delivers until every sender is gone, reporting each write and
+    /// handing each written batch to `recycle`.
+    fn serve<T: Outgoing>(
+        &mut self,
+        mut ring: IoUring,
+        rx: &mut mpsc::Receiver<T>,
+        mark: &mut impl FnMut(u8),
+        recycle: &mut impl FnMut(&mut Vec<T>),
+        written: &mut impl FnMut(io::Result<usize>),
+    ) -> io::Result<()> {
+        let mut batch = Vec::with_capacity(self.slots.len());
+        let result = self.write_batches(&mut ring, rx, &mut batch, mark, recycle, written);
+        if let Err(err) = cancel_all(&mut ring, self.slots.len(), &mut self.in_flight) {
+            // The kernel may still read the batch and the buffers; leak them rather than
+            // free them under it.
+            error!("io_uring writer could not cancel its writes: {}", err);
+            mem::forget(batch);
+            mem::forget(mem::take(&mut self.slots));
+            mem::forget(ring);
+        }
+        result
+    }
+
+    /// Serves batches until every sender is gone or the ring fails, possibly with writes
+    /// of `batch` still in flight.
+    fn write_batches<T: Outgoing>(
+        &mut self,
+        ring: &mut IoUring,
+        rx: &mut mpsc::Receiver<T>,
+        batch: &mut Vec<T>,
+        mark: &mut impl FnMut(u8),
+        recycle: &mut impl FnMut(&mut Vec<T>),
+        written: &mut impl FnMut(io::Result<usize>),
+    ) -> io::Result<()> {
+        let mut next = None;
+        while let Some(first) = next.take().or_else(|| rx.blocking_recv()) {
+            let tos = first.tos();
+            batch.push(first);
+            while batch.len() < self.slots.len() {
+                match rx.try_recv() {
+                    // A different marking waits for the next batch.
+                    Ok(item) if item.tos() != tos => {
+                        next = Some(item);
+                        break;
+                    }
+                    Ok(item) => batch.push(item),
+                    Err(_) => break,
+                }
+            }
+            if let Some(tos) = tos {
+                mark(tos);
+            }
+            for (index, item) in batch.iter().enumerate() {
+                self.queue(ring, index, item)?;
+            }
+            let mut pending = batch.len();
+            while pending > 0 {
+                ring.submit_and_wait(1)?;
+                let completed: Vec<(u64, i32)> = ring
+                    .completion()
+                    .map(|entry| (entry.user_data(), entry.result()))
+                    .collect();
+                self.in_flight -= completed.len();
+                for (user_data, result) in completed {
+                    // A failed poll cancels its write, which completes on its own.
+                    if user_data & POLL != 0 {
+                        continue;
+                    }
+                    let index = user_data as usize;
+                    if -result == libc::EAGAIN {
+                        self.queue(ring, index, &batch[index])?;
+                        continue;
+                    }
+                    pending -= 1;
+                    written(if result >= 0 {
+                        Ok(result as usize)
+                    } else {
+                        Err(io::Error::from_raw_os_error(-result))
+                    });
+                }
+            }
+            recycle(batch);
+            batch.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Runs `writer` on its own thread until every sender of `rx` is dropped.
+fn spawn_writer<T: Outgoing>(
+    name: String,
+    mut writer: Writer,
+    mut rx: mpsc::Receiver<T>,
+    mut mark: impl FnMut(u8) + Send + 'static,
+    mut recycle: impl FnMut(&mut Vec<T>) + Send + 'static,
+    mut written: impl FnMut(io::Result<usize>) + Send + 'static,
+    keep_alive: impl Send + 'static,
+) -> io::Result<()> {
+    // Set up here so a ring the kernel refuses fails the caller, not the thread.
+    let ring = writer.ring()?;
+    std::thread::Builder::new().name(name).spawn(move || {
+        let _keep_alive = keep_alive;
+        if let Err(err) = writer.serve(ring, &mut rx, &mut mark, &mut recycle, &mut written) {
+            written(Err(err));
+        }
+    })?;
+    Ok(())
+}
+
+/// Writes TUN packets through a ring thread. Writes complete after [`write`](Self::write)
+/// returns, so a failed one is returned by the next call.
+pub struct TunWriter {
+    tx: mpsc::Sender<Vec<u8>>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl TunWriter {
+    /// Queues `data`, waiting while the queue is full.
+    pub async fn write(&self, data: &[u8]) -> io::Result<()> {
+        self.write_batch(std::slice::from_ref(&data)).await
+    }
+
+    /// Queues `packets`, reserving room for as many as the queue holds at once so the
+    /// ring thread finds them together and submits them in one go.
+    pub async fn write_batch(&self, packets: &[impl AsRef<[u8]>]) -> io::Result<()> {
+        if let Some(err) = self
+            .error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            return Err(err);
+        }
+        for chunk in packets.chunks(self.tx.max_capacity()) {
+            let permits = self
+                .tx
+                .reserve_many(chunk.len())
+                .await
+                .map_err(|_| io::Error::other("io_uring TUN writer stopped"))?;
+            for (permit, packet) in permits.zip(chunk) {
+                permit.send(packet.as_ref().to_vec());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes one TUN queue through io_uring, up to `depth` packets at a time from registered
+/// buffers of `buffer_size` bytes, with `channel_depth` more waiting.
+pub fn spawn_tun_writer<T: AsRawFd + Send + Sync + 'static>(
+    queue: Arc<T>,
+    buffer_size: usize,
+    depth: usize,
+    channel_depth: usize,
+) -> io::Result<TunWriter> {
+    let (tx, rx) = mpsc::channel(channel_depth.max(1));
+    let error = Arc::new(Mutex::new(None));
+    let failed = Arc::clone(&error);
+    let writer = Writer::new(queue.as_raw_fd(), depth, buffer_size.max(1));
+    spawn_writer(
+        "vtrunkd-tun-wring".to_string(),
+        writer,
+        rx,
+        |_| {},
+        |_| {},
+        move |result| {
+            if let Err(err) = result {
+                *failed.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+            }
+        },
+        queue,
+    )?;
+    Ok(TunWriter { tx, error })
+}
+
+/// Sends the datagrams queued on `rx` from a link socket through io_uring, up to `depth`
+/// at a time. `mark` is called with the TOS byte of each batch before it is sent and
+/// `recycle` with the batch once it is; `written` gets each send's result.
+pub fn spawn_socket_writer<S, M, R, F>(
+    socket: Arc<S>,
+    depth: usize,
+    rx: mpsc::Receiver<(SocketAddr, Vec<u8>, u8)>,
+    mark: M,
+    recycle: R,
+    written: F,
+) -> io::Result<()>
+where
+    S: AsRawFd + Send + Sync + 'static,
+    M: FnMut(u8) + Send + 'static,
+    R: FnMut(&mut Vec<(SocketAddr, Vec<u8>, u8)>) + Send + 'static,
+    F: FnMut(io::Result<usize>) + Send + 'static,
+{
+    let writer = Writer::new(socket.as_raw_fd(), depth, 0);
+    spawn_writer(
+        "vtrunkd-send-ring".to_string(),
+        writer,
+        rx,
+        mark,
+        recycle,
+        written,
+        socket,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn socket_reader_forwards_datagrams_and_stops() {
+        let receiver = Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut tasks = JoinSet::new();
+        spawn_socket_reader(
+            &mut tasks,
+            "test",
+            Arc::clone(&receiver),
+            64,
+            4,
+            move |data, src, _| tx.blocking_send((data.to_vec(), src)).is_ok(),
+        )
+        .unwrap();
+
+        sender
+            .send_to(b"ping", receiver.local_addr().unwrap())
+            .unwrap();
+        let (data, src) = rx.recv().await.unwrap();
+        assert_eq!(data, b"ping");
+        assert_eq!(src, sender.local_addr().unwrap());
+
+        drop(tasks);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn socket_reader_cancels_its_reads_when_the_sink_stops() {
+        let receiver = Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut tasks = JoinSet::new();
+        // The other seven receives are still queued when the first packet stops the thread,
+        // which only exits, dropping `tx`, once they have been cancelled.
+        spawn_socket_reader(
+            &mut tasks,
+            "test",
+            Arc::clone(&receiver),
+            64,
+            8,
+            move |data, _, _| {
+                let _ = tx.blocking_send(data.to_vec());
+                false
+            },
+        )
+        .unwrap();
+
+        sender
+            .send_to(b"last", receiver.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap(), b"last");
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn socket_writer_sends_queued_datagrams() {
+        let sender = Arc::new(std::net::UdpSocket::bind("127.0.0.1:0").unwrap());
+        sender.set_nonblocking(true).unwrap();
+        let receiver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote = receiver.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel(8);
+        let (results_tx, mut results) = mpsc::unbounded_channel();
+        let (marks_tx, marks) = std::sync::mpsc::channel();
+        let mark = move |tos| marks_tx.send(tos).unwrap();
+        let recycle = |sent: &mut Vec<_>| sent.clear();
+        spawn_socket_writer(
+            sender,
+            4,
+            rx,
+            mark,
+            recycle,
+            move |result: io::Result<usize>| {
+                let _ = results_tx.send(result.map_err(|e| e.kind()));
+            },
+        )
+        .unwrap();
+
+        // Each batch is marked with its datagrams' TOS byte before it goes out.
+        for (packet, tos) in [(&b"one"[..], 0), (b"two", 0), (b"three", 0xb8)] {
+            tx.send((remote, packet.to_vec(), tos)).await.unwrap();
+        }
+        let mut buf = [0u8; 16];
+        for expected in [&b"one"[..], b"two", b"three"] {
+            let size = receiver.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], expected);
+            assert_eq!(results.recv().await.unwrap(), Ok(expected.len()));
+        }
+        drop(tx);
+        assert!(results.recv().await.is_none());
+        let mut marks: Vec<u8> = marks.try_iter().collect();
+        marks.dedup();
+        assert_eq!(marks, [0, 0xb8]);
+    }
+
+    #[tokio::test]
+    async fn tun_writer_writes_packets_of_any_size() {
+        let (queue, peer) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        queue.set_nonblocking(true).unwrap();
+        let writer = spawn_tun_writer(Arc::new(queue), 8, 2, 4).unwrap();
+
+        // The second packet does not fit the registered buffers.
+        writer.write(b"small").await.unwrap();
+        writer.write(b"larger than eight").await.unwrap();
+        let mut buf = [0u8; 32];
+        let size = peer.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"small");
+        let size = peer.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"larger than eight");
+    }
+}
@@ -0,0 +1,149 @@
+//! `wg-quick` rendering of a vtrunkd config, for checking keys with the standard tools or
+//! bringing up the same peer with plain WireGuard.
+
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::config::{self, Config};
+use crate::error::{VtrunkdError, VtrunkdResult};
+
+/// The rendered file and what it could not express.
+#[derive(Debug)]
+pub struct Export {
+    pub text: String,
+    pub warnings: Vec<String>,
+}
+
+/// Renders the interface and the first link as one `[Interface]`/`[Peer]` pair.
+pub fn export(config: &Config) -> VtrunkdResult<Export> {
+    let wg = &config.wireguard;
+    let link = wg
+        .links
+        .first()
+        .ok_or_else(|| VtrunkdError::InvalidConfig("No links to export".to_string()))?;
+    let mut warnings = Vec::new();
+    if wg.links.len() > 1 {
+        warnings.push(format!(
+            "wg-quick has no bonding; only link {} is exported and {} other link(s) are dropped",
+            config::link_name(link, 0),
+            wg.links.len() - 1
+        ));
+    }
+
+    let mut out = String::from("[Interface]\n");
+    let _ = writeln!(out, "PrivateKey = {}", *wg.load_private_key()?);
+    let mut addresses = Vec::new();
+    if let Some(address) = &config.network.address {
+        addresses.push(interface_address(
+            address,
+            config.network.netmask.as_deref(),
+        )?);
+    }
+    addresses.extend(config.network.addresses.iter().flatten().cloned());
+    if !addresses.is_empty() {
+        let _ = writeln!(out, "Address = {}", addresses.join(", "));
+    }
+    if let Some(bind) = &link.bind {
+        let bind: SocketAddr = bind.parse().map_err(|e| {
+            VtrunkdError::InvalidConfig(format!("Invalid bind address {}: {}", bind, e))
+        })?;
+        if bind.port() != 0 {
+            let _ = writeln!(out, "ListenPort = {}", bind.port());
+        }
+        if !bind.ip().is_unspecified() {
+            warnings.push(format!(
+                "WireGuard listens on every address; the bind to {} is not exported",
+                bind.ip()
+            ));
+        }
+    }
+    let _ = writeln!(out, "MTU = {}", config.network.mtu);
+    // vtrunkd installs no routes beyond the interface subnet, so neither should wg-quick.
+    out.push_str("Table = off\n");
+
+    out.push_str("\n[Peer]\n");
+    let _ = writeln!(out, "PublicKey = {}", wg.peer_public_key);
+    if let Some(preshared_key) = wg.load_preshared_key()? {
+        let _ = writeln!(out, "PresharedKey = {}", *preshared_key);
+    }
+    out.push_str("AllowedIPs = 0.0.0.0/0, ::/0\n");
+    if let Some(endpoint) = &link.endpoint {
+        let _ = writeln!(out, "Endpoint = {}", endpoint);
+    }
+    if let Some(keepalive) = wg.persistent_keepalive {
+        let _ = writeln!(out, "PersistentKeepalive = {}", keepalive);
+    }
+
+    Ok(Export {
+        text: out,
+        warnings,
+    })
+}
+
+/// `address/prefix`, with the prefix taken from an IPv4 `netmask`.
+fn interface_address(address: &str, netmask: Option<&str>) -> VtrunkdResult<String> {
+    let ip: IpAddr = address
+        .parse()
+        .map_err(|e| VtrunkdError::InvalidConfig(format!("Invalid address {}: {}", address, e)))?;
+    let prefix = match (ip, netmask) {
+        (IpAddr::V4(_), Some(netmask)) => {
+            let mask = u32::from(netmask.parse::<Ipv4Addr>().map_err(|e| {
+                VtrunkdError::InvalidConfig(format!("Invalid netmask {}: {}", netmask, e))
+            })?);
+            if mask.leading_ones() != mask.count_ones() {
+                return Err(VtrunkdError::InvalidConfig(format!(
+                    "Netmask {} is not a prefix",
+                    netmask
+                )));
+            }
+            mask.count_ones()
+        }
+        (IpAddr::V4(_), None) => 32,
+        (IpAddr::V6(_), _) => 128,
+    };
+    Ok(format!("{}/{}", ip, prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_the_first_link_and_warns_about_the_rest() {
+        let mut config = Config::default();
+        config.network.address = Some("10.10.0.2".to_string());
+        config.network.netmask = Some("255.255.255.0".to_string());
+        config.network.addresses = Some(vec!["fd00:10::2/64".to_string()]);
+        config.wireguard.preshared_key = Some("cHNr".to_string());
+        config.wireguard.links[0].bind = Some("0.0.0.0:51820".to_string());
+        config.wireguard.links[0].endpoint = Some("vps.example.com:51820".to_string());
+        let mut second = config.wireguard.links[0].clone();
+        second.name = Some("lte".to_string());
+        second.endpoint = Some("vps.example.com:51821".to_string());
+        config.wireguard.links.push(second);
+
+        let export = export(&config).unwrap();
+        let lines: Vec<&str> = export.text.lines().collect();
+        assert_eq!(lines[0], "[Interface]");
+        for expected in [
+            "Address = 10.10.0.2/24, fd00:10::2/64",
+            "ListenPort = 51820",
+            "[Peer]",
+            "PresharedKey = cHNr",
+            "Endpoint = vps.example.com:51820",
+        ] {
+            assert!(lines.contains(&expected), "missing {}", expected);
+        }
+        assert!(!export.text.contains("51821"));
+        assert_eq!(export.warnings.len(), 1);
+    }
+
+    #[test]
+    fn rejects_a_netmask_with_holes() {
+        assert_eq!(
+            interface_address("10.0.0.1", Some("255.0.255.0")).ok(),
+            None
+        );
+        assert_eq!(interface_address("fd00::1", None).unwrap(), "fd00::1/128");
+    }
+}
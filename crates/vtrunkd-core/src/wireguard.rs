@@ -0,0 +1,4923 @@
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::{engine::general_purpose, Engine as _};
+use boringtun::noise::rate_limiter::RateLimiter;
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+#[cfg(unix)]
+use nix::libc;
+#[cfg(unix)]
+use nix::sys::socket::{getsockopt, setsockopt, sockopt};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{AbortHandle, JoinSet};
+use tracing::{debug, error, info, warn};
+use zeroize::Zeroizing;
+
+use crate::activation;
+use crate::allowlist::AllowedSources;
+use crate::batch::RecvBatch;
+use crate::bond::{build_control_packet, parse_control_packet, BOND_BYE, BOND_PING, BOND_PONG};
+use crate::capture::{Capture, CaptureTarget};
+use crate::compress;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::config::IoBackend;
+use crate::config::{
+    link_name, parse_cidr, BondingMode, Config, LinksDownPolicy, NetworkConfig, PeerKind,
+    UnsupportedPolicy, WireGuardConfig, WireGuardLinkConfig, DEFAULT_BATCH_SIZE,
+    DEFAULT_CHANNEL_DEPTH, DEFAULT_ERROR_BACKOFF_SECS, DEFAULT_HANDSHAKE_RATE_LIMIT,
+    DEFAULT_HANDSHAKE_SOURCE_LIMIT, DEFAULT_HEALTH_INTERVAL_MS, DEFAULT_INTERFACE,
+    DEFAULT_LINKS_DOWN_BUFFER_PACKETS, DEFAULT_LINKS_DOWN_BUFFER_SECS, DEFAULT_SEND_QUEUE_DEPTH,
+    MAX_CRYPTO_WORKERS,
+};
+use crate::control::{self, ControlCommand, ControlRequest, LinkAdminState};
+use crate::ecn;
+use crate::error::{VtrunkdError, VtrunkdResult};
+use crate::events::{self, EventSender, TunnelEvent};
+#[cfg(unix)]
+use crate::handover;
+use crate::hooks::{self, Lifecycle};
+use crate::hotplug;
+use crate::icmp::{IcmpErrors, Refusal};
+use crate::inner::{self, Family};
+use crate::mss;
+use crate::network::TunnelDevice;
+use crate::outage::Outage;
+#[cfg(unix)]
+use crate::protect;
+use crate::quality::Quality;
+use crate::ratelimit::RateLimit;
+use crate::reorder::{self, Reorder};
+use crate::rotate::{self, KeyRotation, Message, OfferDue, FEATURE_LZ4};
+use crate::sandbox;
+use crate::state::{self, LinkState, SessionKeys, SessionState};
+use crate::stats::{
+    self, queue_packet, LinkCounters, StatsRegistry, StatsSnapshot, TunnelCounters,
+};
+use crate::throttle::{self, HandshakeThrottle};
+use crate::uapi;
+
+pub mod bench;
+mod lanes;
+#[cfg(all(test, feature = "netsim"))]
+mod netsim_tests;
+mod sender;
+
+use lanes::{LaneKeys, Lanes, Output, Received};
+use sender::{BufferPool, LinkSender, SendOutcome};
+
+const WG_KEEPALIVE_LEN: usize = 32;
+const WG_COOKIE_REPLY: u32 = 3;
+/// Points by which a link must outscore the current one for `failover_by_quality` to
+/// move to it.
+const QUALITY_HYSTERESIS: u64 = 10;
+/// boringtun counts handshakes until its limiter is reset, which it expects once a
+/// second; that makes `handshake_rate_limit` a per-second limit.
+const HANDSHAKE_COUNT_WINDOW: Duration = Duration::from_secs(1);
+
+struct Link {
+    name: String,
+    socket: Arc<UdpSocket>,
+    remote: Option<SocketAddr>,
+    weight: u32,
+    admin_state: LinkAdminState,
+    down_since: Option<Instant>,
+    last_rx: Option<Instant>,
+    last_ping_sent: Option<Instant>,
+    last_rtt_ms: Option<u64>,
+    quality: Quality,
+    degraded: bool,
+    /// Configured outer DSCP, for data whose own DSCP is not copied.
+    dscp: u8,
+    stats: Arc<LinkCounters>,
+    events: EventSender,
+    /// The tasks reading `socket` and its `receive_sockets`, stopped when the link is
+    /// rebound.
+    receivers: Vec<AbortHandle>,
+    /// The network device the link is pinned to, directly or through its address, and
+    /// the device's index when the socket was bound.
+    device: Option<String>,
+    device_index: Option<u32>,
+    /// Only `remote` may send datagrams that go further than a handshake.
+    strict_source: bool,
+    /// Writes to `socket` from the link's own queue.
+    sender: LinkSender,
+}
+
+struct LinkManager {
+    links: Vec<Link>,
+    mode: BondingMode,
+    error_backoff: Duration,
+    health_timeout: Option<Duration>,
+    next_index: usize,
+    remaining_weight: u32,
+    failover_index: Option<usize>,
+    /// Failover picks links by their quality score rather than their weight.
+    failover_by_quality: bool,
+    /// Off against a stock WireGuard peer, which would only drop the control packets.
+    bond_control: bool,
+    /// Tunnel MTU that TCP SYNs written to the TUN device are clamped to.
+    clamp_mss: Option<u32>,
+    /// Answers TUN packets that cannot be sent; `None` when `network.icmp_errors` is off.
+    icmp: Option<IcmpErrors>,
+    /// What becomes of TUN packets that are not IPv4 or IPv6, and when one was last logged.
+    unsupported: UnsupportedPolicy,
+    unsupported_logged: Option<Instant>,
+    /// What becomes of TUN packets while no link is available, and where `buffer` holds
+    /// them.
+    links_down: LinksDownPolicy,
+    outage: Option<Outage>,
+    /// `wireguard.send_limit_kbit` and `receive_limit_kbit`, when set.
+    send_limit: Option<RateLimit>,
+    receive_limit: Option<RateLimit>,
+    /// Mark data with the inner packets' DSCP rather than each link's own.
+    copy_dscp: bool,
+    /// RFC 6040 ECN propagation between the inner and outer headers.
+    ecn: bool,
+    /// Outer TOS byte of the next sends; a DSCP of 0 stands for each link's own.
+    tos: u8,
+    /// LZ4 compression is configured here; packets are only compressed once the peer's
+    /// `peer_features` include it.
+    compression: bool,
+    peer_features: u64,
+    /// Puts received data back in the peer's order; `None` unless `wireguard.reorder`.
+    reorder: Option<Reorder>,
+    /// Keys of the running session, for UAPI `get` and key rotation.
+    keys: uapi::Status,
+    rotation: KeyRotation,
+    /// Handshakes per second answered before cookies are demanded, the limiter the
+    /// session counts them in (`None` leaves it to the `Tunn`'s own) and when its count
+    /// was last reset.
+    handshake_rate_limit: u64,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    handshake_count_reset: Instant,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+    capture_dir: PathBuf,
+    capture: Option<Capture>,
+    /// Socket receive tasks; dropping the manager aborts them and releases the sockets.
+    receivers: JoinSet<()>,
+    /// Where a rebound link's new receiver queues its datagrams; weak so the loop still
+    /// notices when every receiver has stopped.
+    net_tx: Option<mpsc::WeakSender<NetPacket>>,
+    /// Buffers the link senders hand back once written, and those taken from it for the
+    /// next datagrams or TUN writes.
+    pool: Arc<BufferPool>,
+    spare: Vec<Vec<u8>>,
+    /// Decrypted packets held while a round of datagrams is handled, then written to the
+    /// TUN device together; `tun_batching` is set for the round.
+    tun_batch: Vec<Vec<u8>>,
+    tun_batching: bool,
+    /// The sessions past lane 0, each encrypting and decrypting on a thread of its own.
+    lanes: Lanes,
+}
+
+struct NetPacket {
+    link_index: usize,
+    src: SocketAddr,
+    /// ECN field of the outer header; only read when `ecn` is on.
+    ecn: u8,
+    data: Vec<u8>,
+}
+
+trait TunnelWriter: Sync {
+    fn write_packet<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>>;
+
+    fn write_packets<'a>(
+        &'a self,
+        packets: &'a [Vec<u8>],
+    ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+        Box::pin(async move {
+            for packet in packets {
+                self.write_packet(packet).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl TunnelWriter for TunnelDevice {
+    fn write_packet<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+        Box::pin(async move { TunnelDevice::write_packet(self, data).await })
+    }
+
+    fn write_packets<'a>(
+        &'a self,
+        packets: &'a [Vec<u8>],
+    ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+        Box::pin(async move { TunnelDevice::write_packets(self, packets).await })
+    }
+}
+
+/// Runs the tunnel until `shutdown_rx` fires (`Ok`) or something fails (`Err`).
+///
+/// The receivers are borrowed so the supervisor can call `run` again after a failure.
+pub async fn run(
+    config: &Config,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+    control_rx: &mut mpsc::Receiver<ControlRequest>,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+) -> VtrunkdResult<()> {
+    let interface = config
+        .network
+        .interface
+        .as_deref()
+        .unwrap_or(DEFAULT_INTERFACE);
+    let links_down: Vec<(String, &str)> = config
+        .wireguard
+        .links
+        .iter()
+        .enumerate()
+        .map(|(index, link)| (link_name(link, index), "down"))
+        .collect();
+    hooks::run_lifecycle(config, Lifecycle::PreUp, interface, &links_down).await?;
+
+    let mut handed_over = false;
+    let result = serve(
+        config,
+        stats,
+        events,
+        control_rx,
+        shutdown_rx,
+        &mut handed_over,
+    )
+    .await;
+    // The interface lives on in the process that took it over.
+    if !handed_over {
+        if let Err(e) =
+            hooks::run_lifecycle(config, Lifecycle::PostDown, interface, &links_down).await
+        {
+            warn!("{}", e);
+        }
+    }
+    result
+}
+
+async fn serve(
+    config: &Config,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+    control_rx: &mut mpsc::Receiver<ControlRequest>,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+    handed_over: &mut bool,
+) -> VtrunkdResult<()> {
+    let wg_config = &config.wireguard;
+    let bonding_mode = wg_config.bonding_mode.unwrap_or_default();
+    let error_backoff = Duration::from_secs(
+        wg_config
+            .error_backoff_secs
+            .unwrap_or(DEFAULT_ERROR_BACKOFF_SECS),
+    );
+    let health_interval = Duration::from_millis(
+        wg_config
+            .health_check_interval_ms
+            .unwrap_or(DEFAULT_HEALTH_INTERVAL_MS),
+    );
+    let health_timeout = wg_config.health_check_timeout_ms.map(Duration::from_millis);
+    let stats_interval = wg_config.stats_interval_secs.map(Duration::from_secs);
+    let batch_size = config.network.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+
+    let state_file = wg_config.state_file.as_deref().map(Path::new);
+    let saved = state_file.and_then(state::load);
+
+    // Wiped once the `Tunn` and the UAPI status hold their own copies.
+    let mut private_key =
+        Zeroizing::new(decode_key("private_key", &wg_config.load_private_key()?)?);
+    let mut peer_public_key = decode_key("peer_public_key", &wg_config.peer_public_key)?;
+    let preshared_key = Zeroizing::new(match wg_config.load_preshared_key()? {
+        Some(value) => Some(decode_key("preshared_key", &value)?),
+        None => None,
+    });
+    let mut rotation = KeyRotation {
+        state_file: state_file.map(Path::to_path_buf),
+        config_public_key: encode_key(&public_key_of(&private_key)),
+        config_peer_public_key: encode_key(&peer_public_key),
+        ..KeyRotation::default()
+    };
+    if let Some(rotated) = saved.as_ref().and_then(|saved| saved.keys.as_ref()) {
+        if rotated.config_public_key == rotation.config_public_key
+            && rotated.config_peer_public_key == rotation.config_peer_public_key
+        {
+            *private_key = decode_key("rotated private_key", &rotated.private_key)?;
+            peer_public_key = decode_key("rotated peer_public_key", &rotated.peer_public_key)?;
+            rotation.rotated = true;
+            info!("WireGuard using the rotated keys from the state file");
+        } else {
+            warn!("WireGuard ignoring rotated keys in the state file; the configured keys changed");
+        }
+    }
+
+    let keys = uapi::Status {
+        private_key: *private_key,
+        public_key: peer_public_key,
+        preshared_key: *preshared_key,
+        persistent_keepalive: wg_config.persistent_keepalive,
+        ..uapi::Status::default()
+    };
+    let index = lanes::session_index(0);
+    let handshake_rate_limit = wg_config
+        .handshake_rate_limit
+        .unwrap_or(DEFAULT_HANDSHAKE_RATE_LIMIT);
+    let rate_limiter = handshake_rate_limiter(&private_key, handshake_rate_limit);
+
+    let mut tunnel = Tunn::new(
+        StaticSecret::from(*private_key),
+        PublicKey::from(peer_public_key),
+        *preshared_key,
+        wg_config.persistent_keepalive,
+        index,
+        Some(Arc::clone(&rate_limiter)),
+    );
+    drop((private_key, preshared_key));
+
+    let device = TunnelDevice::new(&config.network)?;
+    info!(
+        "WireGuard TUN device {} ready ({} queues)",
+        device.name(),
+        device.queue_count()
+    );
+    info!(
+        "WireGuard bonding mode {:?}, error backoff {}s",
+        bonding_mode,
+        error_backoff.as_secs()
+    );
+    if let Some(timeout) = health_timeout {
+        info!(
+            "WireGuard health checks every {}ms (timeout {}ms)",
+            health_interval.as_millis(),
+            timeout.as_millis()
+        );
+    }
+
+    let (mut links, mut net_rx) = setup_links(
+        wg_config,
+        &config.network,
+        bonding_mode,
+        error_backoff,
+        health_timeout,
+        stats,
+        events,
+    )
+    .await?;
+    links.capture_dir = control::capture_dir(config.capture_dir());
+    links.bond_control = wg_config.peer_kind.unwrap_or_default() == PeerKind::Vtrunkd;
+    links.copy_dscp = wg_config.copy_dscp.unwrap_or(false);
+    links.ecn = wg_config.ecn.unwrap_or(false);
+    links.compression = wg_config.compression.unwrap_or(false);
+    links.failover_by_quality = wg_config.failover_by_quality.unwrap_or(false);
+    // One per TUN queue unless configured, so each queue's reader has a lane to feed.
+    let crypto_lanes = if links.bond_control {
+        wg_config
+            .crypto_workers
+            .unwrap_or_else(|| device.queue_count().min(MAX_CRYPTO_WORKERS))
+    } else {
+        1
+    };
+    links.reorder = wg_config.reorder.unwrap_or(false).then(|| {
+        Reorder::new(
+            wg_config.reorder_hold_ms.map(Duration::from_millis),
+            crypto_lanes,
+            &links.stats.tunnel,
+        )
+    });
+    links.keys = keys;
+    links.rotation = rotation;
+    links.handshake_rate_limit = handshake_rate_limit;
+    links.rate_limiter = Some(rate_limiter);
+    let (lanes_tx, mut lanes_rx) = mpsc::channel(lanes::OUTPUT_DEPTH);
+    links.lanes = Lanes::start(
+        crypto_lanes,
+        links.lane_keys(),
+        &links.pool,
+        &links.stats,
+        config.network.buffer_size,
+        lanes_tx,
+    )?;
+    if crypto_lanes > 1 {
+        info!(
+            "WireGuard running {} crypto lanes once the peer does",
+            crypto_lanes
+        );
+    }
+    if let Some(saved) = &saved {
+        links.restore_state(saved, SystemTime::now());
+    }
+    links.clamp_mss = config
+        .network
+        .clamp_mss
+        .unwrap_or(false)
+        .then_some(config.network.mtu);
+    links.unsupported = config.network.unsupported_packets.unwrap_or_default();
+    links.links_down = wg_config.links_down.unwrap_or_default();
+    links.outage = (links.links_down == LinksDownPolicy::Buffer).then(|| {
+        Outage::new(
+            wg_config
+                .links_down_buffer_packets
+                .unwrap_or(DEFAULT_LINKS_DOWN_BUFFER_PACKETS),
+            Duration::from_secs(
+                wg_config
+                    .links_down_buffer_secs
+                    .unwrap_or(DEFAULT_LINKS_DOWN_BUFFER_SECS),
+            ),
+        )
+    });
+    links.send_limit = wg_config
+        .send_limit_kbit
+        .map(|kbit| RateLimit::new(kbit, Instant::now()));
+    links.receive_limit = wg_config
+        .receive_limit_kbit
+        .map(|kbit| RateLimit::new(kbit, Instant::now()));
+    if config.network.icmp_errors.unwrap_or(true) {
+        let address = config
+            .network
+            .address
+            .as_deref()
+            .and_then(|a| a.parse().ok());
+        let addresses = config.network.addresses.iter().flatten();
+        let addresses = addresses.filter_map(|cidr| parse_cidr(cidr).ok());
+        links.icmp = Some(IcmpErrors::new(
+            config.network.mtu,
+            address
+                .into_iter()
+                .chain(addresses.map(|(address, _)| address)),
+            Instant::now(),
+        ));
+    }
+    if links.links.is_empty() {
+        return Err(VtrunkdError::InvalidConfig(
+            "WireGuard links must include at least one entry".to_string(),
+        ));
+    }
+    // Before the sandbox, so the commands can still change routes and firewall rules.
+    let states = links.link_states(Instant::now());
+    hooks::run_lifecycle(config, Lifecycle::PostUp, device.name(), &states).await?;
+
+    // Everything privileged (TUN, sockets) is set up; lock the process down.
+    sandbox::apply(config.sandbox.unwrap_or_default())?;
+
+    if links.has_endpoints() {
+        send_handshake(&mut tunnel, &mut links).await?;
+    }
+
+    let mut tun_packets = device.packets(&config.network, &links.stats)?;
+    let mut out_buf = vec![0u8; std::cmp::max(config.network.buffer_size + 32, 148)];
+    let mut wg_timer = tokio::time::interval(tokio::time::Duration::from_millis(250));
+    let mut health_timer = tokio::time::interval(health_interval);
+    // Only polled when stats_interval is set; the first tick is one full period out.
+    let stats_period = stats_interval.unwrap_or(Duration::from_secs(60));
+    let mut stats_timer =
+        tokio::time::interval_at(tokio::time::Instant::now() + stats_period, stats_period);
+    let mut stats_previous = links.stats.snapshot();
+    let mut stats_last = Instant::now();
+    let mut last_handshake: Option<Instant> = None;
+    let bond_epoch = Instant::now();
+    let (device_tx, mut device_rx) = mpsc::channel(16);
+    if wg_config.follow_devices.unwrap_or(true)
+        && links.links.iter().any(|link| link.device.is_some())
+    {
+        if let Err(e) = hotplug::spawn(&mut links.receivers, device_tx) {
+            warn!("WireGuard cannot follow network devices: {}", e);
+        }
+    }
+
+    loop {
+        let reorder_deadline = links.reorder.as_ref().and_then(Reorder::deadline);
+        tokio::select! {
+            received = tun_packets.recv() => {
+                match received {
+                    Some(received) => received?,
+                    None => {
+                        return Err(VtrunkdError::Network("TUN readers stopped".to_string()));
+                    }
+                }
+                // Drain what is already waiting so the link sends go out in batches.
+                links.replay_outage(&mut tunnel).await?;
+                let mut outgoing = Vec::new();
+                let mut outgoing_tos = 0;
+                let mut drained = 0usize;
+                let mut more = true;
+                while more {
+                    let packet = tun_packets.packet();
+                    drained += 1;
+                    if let Some(mtu) = links.clamp_mss {
+                        mss::clamp(packet, mtu);
+                    }
+                    if links.copy_dscp || links.ecn {
+                        // A batch shares one marking; send it when the marking changes.
+                        let tos = links.outer_tos(packet);
+                        if tos != outgoing_tos && !outgoing.is_empty() {
+                            links.mark_tos(outgoing_tos);
+                            links.send_packets(&mut outgoing).await?;
+                        }
+                        outgoing_tos = tos;
+                    }
+                    if !packet.is_empty() {
+                        links.stats.tunnel.record_tun_rx(packet.len());
+                        links.capture_tun(packet);
+                        if !links.accept_inner(packet, Instant::now()) {
+                            // Neither IPv4 nor IPv6, or reserved; counted, and logged if asked for.
+                        } else if !links.within_send_limit(packet, Instant::now()) {
+                            // Over `send_limit_kbit`; counted.
+                        } else if let Some(refusal) = links.refusal(packet, Instant::now()) {
+                            links.refuse(&device, packet, refusal).await?;
+                        } else if links.hold(packet, Instant::now()) {
+                            // No link to take it; sent once one comes back.
+                        } else if let Some(packet) = links.seal(&mut tunnel, packet, outgoing_tos)? {
+                            outgoing.push(packet);
+                        }
+                    }
+                    more = drained < batch_size && tun_packets.try_recv()?;
+                }
+                links.dispatch_lanes();
+                if links.copy_dscp || links.ecn {
+                    links.mark_tos(outgoing_tos);
+                }
+                links.send_packets(&mut outgoing).await?;
+            }
+
+            packet = net_rx.recv() => {
+                let mut next = match packet {
+                    Some(packet) => Some(packet),
+                    None => {
+                        return Err(VtrunkdError::Network(
+                            "WireGuard link receivers stopped".to_string(),
+                        ))
+                    }
+                };
+                let mut drained = 0usize;
+                links.batch_tun();
+                while let Some(packet) = next.take() {
+                    drained += 1;
+                    if links.admits(&packet) {
+                        links.record_rx(packet.link_index, Instant::now());
+                        links.capture_link(packet.link_index, packet.src, false, &packet.data);
+                        if let Some(packet) = links.lanes.route_inbound(packet) {
+                            handle_incoming(
+                                &mut tunnel,
+                                &device,
+                                &mut links,
+                                &mut out_buf,
+                                bond_epoch,
+                                packet,
+                            )
+                            .await?;
+                        }
+                    }
+                    if drained < batch_size {
+                        next = net_rx.try_recv().ok();
+                    }
+                }
+                links.dispatch_lanes();
+                links.flush_tun(&device).await?;
+            }
+
+            Some(output) = lanes_rx.recv() => {
+                links.batch_tun();
+                links.lane_output(&device, output, out_buf.len()).await?;
+                links.flush_tun(&device).await?;
+            }
+
+            _ = wg_timer.tick() => {
+                links.check_capture();
+                if handshake_completed(&tunnel, &mut last_handshake, Instant::now()) {
+                    info!(event = "handshake_complete", "WireGuard handshake completed");
+                    events::emit(&links.events, TunnelEvent::HandshakeComplete);
+                    links.send_features(&mut tunnel, &mut out_buf).await?;
+                }
+                links.resend_key_offer(&mut tunnel, &mut out_buf, Instant::now()).await?;
+                links.reset_handshake_count(Instant::now());
+                links.lanes.tick();
+                match tunnel.update_timers(&mut out_buf) {
+                    TunnResult::WriteToNetwork(packet) => {
+                        links.send_packet(packet.to_vec()).await?;
+                    }
+                    TunnResult::Done => {}
+                    TunnResult::Err(e) => {
+                        return Err(VtrunkdError::Network(format!("WireGuard timer error: {:?}", e)));
+                    }
+                    TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {}
+                }
+            }
+
+            _ = health_timer.tick() => {
+                links.replay_outage(&mut tunnel).await?;
+                if health_timeout.is_some() {
+                    links.check_degraded(Instant::now());
+                    links.record_loss();
+                    links.send_health_pings(bond_epoch).await?;
+                }
+                links.tune_reorder();
+            }
+
+            _ = tokio::time::sleep_until(reorder_deadline.unwrap_or_else(Instant::now).into()),
+                if reorder_deadline.is_some() =>
+            {
+                links.expire_reorder(&device, Instant::now()).await?;
+            }
+
+            _ = stats_timer.tick(), if stats_interval.is_some() => {
+                let now = Instant::now();
+                links.log_stats(&stats_previous, now.duration_since(stats_last), now);
+                stats_previous = links.stats.snapshot();
+                stats_last = now;
+            }
+
+            Some(device) = device_rx.recv() => {
+                links.device_changed(config, &device, bond_epoch).await;
+            }
+
+            Some(request) = control_rx.recv() => {
+                let result = match request.command {
+                    ControlCommand::Handover { path } => {
+                        let result = links.handover(&device, Path::new(&path));
+                        *handed_over |= result.is_ok();
+                        result
+                    }
+                    ControlCommand::Uapi => Ok(uapi::render(&links.uapi_status(&tunnel))),
+                    ControlCommand::RotateKey => {
+                        links.rotate_key(&mut tunnel, &mut out_buf, Instant::now()).await
+                    }
+                    ControlCommand::AcceptKey { public_key } => {
+                        links.accept_key(&mut tunnel, &mut out_buf, &public_key).await
+                    }
+                    ControlCommand::RebindLink { name } => {
+                        links.rebind(config, &name, bond_epoch).await
+                    }
+                    command => links.handle_command(command),
+                };
+                let _ = request.reply.send(result);
+            }
+
+            _ = &mut *shutdown_rx => {
+                info!("WireGuard shutting down");
+                let now = Instant::now();
+                links.log_stats(&stats_previous, now.duration_since(stats_last), now);
+                // After a handover the new process keeps the session; the peer must not fail over.
+                if !*handed_over {
+                    let states = links.link_states(Instant::now());
+                    if let Err(e) =
+                        hooks::run_lifecycle(config, Lifecycle::PreDown, device.name(), &states).await
+                    {
+                        warn!("{}", e);
+                    }
+                    links.send_bye(bond_epoch).await;
+                }
+                if let Some(path) = state_file {
+                    if let Err(e) = state::save(path, &links.session_state()) {
+                        warn!("Failed to save session state to {:?}: {}", path, e);
+                    }
+                }
+                if let Some(capture) = links.capture.take() {
+                    let _ = capture.finish();
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn handle_incoming(
+    tunnel: &mut Tunn,
+    device: &impl TunnelWriter,
+    links: &mut LinkManager,
+    out_buf: &mut [u8],
+    bond_epoch: Instant,
+    packet: NetPacket,
+) -> VtrunkdResult<()> {
+    if links
+        .handle_control_packet(packet.link_index, packet.src, &packet.data, bond_epoch)
+        .await?
+    {
+        return Ok(());
+    }
+
+    let max_len = out_buf.len();
+    let sequence = links
+        .reorder
+        .as_ref()
+        .and_then(|_| reorder::sequence(&packet.data));
+    let mut result = tunnel.decapsulate(Some(packet.src.ip()), &packet.data, out_buf);
+
+    loop {
+        match result {
+            TunnResult::WriteToNetwork(buffer)
+                if wg_packet_type(buffer) == Some(WG_COOKIE_REPLY) =>
+            {
+                // Over the handshake rate limit: only the source can use the cookie.
+                let _ = links
+                    .send_reply(packet.link_index, packet.src, buffer.to_vec())
+                    .await;
+                stats::add(&links.stats.tunnel.cookie_replies, 1);
+                return Ok(());
+            }
+            TunnResult::WriteToNetwork(buffer) => {
+                // A handshake that checked out, answered on the path it came in on.
+                links.roam(packet.link_index, packet.src);
+                // Pass slice directly to avoid allocation
+                links.send_packet(buffer.to_vec()).await?;
+                result = tunnel.decapsulate(None, &[], out_buf);
+            }
+            TunnResult::WriteToTunnelV4(buffer, _) | TunnResult::WriteToTunnelV6(buffer, _) => {
+                links.roam(packet.link_index, packet.src);
+                if links.bond_control {
+                    if let Some(message) = Message::parse(buffer) {
+                        links.write_tun(device, sequence, None).await?;
+                        return links.handle_session_message(tunnel, message, out_buf).await;
+                    }
+                }
+                return links
+                    .deliver(device, &packet, sequence, buffer, max_len)
+                    .await;
+            }
+            // A keepalive, for one, decrypts to nothing. Cookie replies end here too, but
+            // they are not proof enough to roam on.
+            TunnResult::Done => {
+                if wg_packet_type(&packet.data) != Some(WG_COOKIE_REPLY) {
+                    links.roam(packet.link_index, packet.src);
+                }
+                return links.write_tun(device, sequence, None).await;
+            }
+            TunnResult::Err(e) => {
+                if links.accept_rotated_peer(tunnel, &packet, out_buf).await? {
+                    return Ok(());
+                }
+                warn!("WireGuard decapsulate error: {:?}", e);
+                if let Some(link) = links.links.get(packet.link_index) {
+                    stats::add(&link.stats.rx_dropped, 1);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Reports whether a new session was established since the last call.
+fn handshake_completed(tunnel: &Tunn, last: &mut Option<Instant>, now: Instant) -> bool {
+    let established = match tunnel.time_since_last_handshake() {
+        Some(since) => now.checked_sub(since),
+        None => return false,
+    };
+    let Some(established) = established else {
+        return false;
+    };
+    // Tolerate timer jitter between polls of the same session.
+    let is_new = match *last {
+        Some(previous) => established > previous + Duration::from_secs(1),
+        None => true,
+    };
+    if is_new {
+        *last = Some(established);
+    }
+    is_new
+}
+
+async fn send_handshake(tunnel: &mut Tunn, links: &mut LinkManager) -> VtrunkdResult<()> {
+    let mut out_buf = vec![0u8; 2048];
+    match tunnel.format_handshake_initiation(&mut out_buf, true) {
+        TunnResult::WriteToNetwork(packet) => {
+            links.send_packet(packet.to_vec()).await?;
+        }
+        TunnResult::Done => {}
+        TunnResult::Err(e) => {
+            return Err(VtrunkdError::Network(format!(
+                "WireGuard handshake error: {:?}",
+                e
+            )))
+        }
+        TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {}
+    }
+    Ok(())
+}
+
+/// Reads datagrams from each of a link's sockets into `tx` until `receivers` is dropped.
+/// Those from outside the link's `allowed_sources` are counted as dropped, and handshakes
+/// over `handshake_source_limit` as throttled; neither goes further.
+fn spawn_link_receivers(
+    receivers: &mut JoinSet<()>,
+    network: &NetworkConfig,
+    index: usize,
+    wg_config: &WireGuardConfig,
+    sockets: &[Arc<UdpSocket>],
+    stats: &Arc<LinkCounters>,
+    tx: &mpsc::Sender<NetPacket>,
+) -> VtrunkdResult<Vec<AbortHandle>> {
+    let link_config = &wg_config.links[index];
+    let name = link_name(link_config, index);
+    let buffer_size = network.buffer_size;
+    let batch_size = network.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let source_limit = wg_config
+        .handshake_source_limit
+        .unwrap_or(DEFAULT_HANDSHAKE_SOURCE_LIMIT);
+    let mut handles = Vec::new();
+    // The kernel hands each source to one socket, so per-source limits still hold.
+    for socket in sockets {
+        let socket = Arc::clone(socket);
+        let stats = Arc::clone(stats);
+        let tx = tx.clone();
+        let name = name.clone();
+        let allowed = match &link_config.allowed_sources {
+            Some(sources) => Some(AllowedSources::parse(sources)?),
+            None => None,
+        };
+        let mut throttle = HandshakeThrottle::new(source_limit, Instant::now());
+        let mut admit = move |data: &[u8], src: SocketAddr, stats: &LinkCounters| {
+            if !allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.permits(src.ip()))
+            {
+                stats::add(&stats.rx_dropped, 1);
+                return false;
+            }
+            if throttle::is_handshake(data) && !throttle.allow(src.ip(), Instant::now()) {
+                stats::add(&stats.handshakes_throttled, 1);
+                return false;
+            }
+            true
+        };
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if network.io_backend == Some(IoBackend::IoUring) {
+            let receiver = crate::uring::spawn_socket_reader(
+                receivers,
+                &name,
+                socket,
+                buffer_size,
+                batch_size,
+                move |data, src, tos| {
+                    if !admit(data, src, &stats) {
+                        return true;
+                    }
+                    stats.record_rx(data.len());
+                    let packet = NetPacket {
+                        link_index: index,
+                        src,
+                        ecn: tos & 0b11,
+                        data: data.to_vec(),
+                    };
+                    queue_packet(&tx, packet, &stats.rx_overflow)
+                },
+            )?;
+            handles.push(receiver);
+            continue;
+        }
+
+        handles.push(receivers.spawn(async move {
+            let mut batch = RecvBatch::new(batch_size, buffer_size);
+            loop {
+                if let Err(err) = batch.recv(&socket).await {
+                    error!("WireGuard socket recv error on {}: {}", name, err);
+                    break;
+                }
+                for (data, src, tos) in batch.packets() {
+                    if !admit(data, src, &stats) {
+                        continue;
+                    }
+                    stats.record_rx(data.len());
+                    let packet = NetPacket {
+                        link_index: index,
+                        src,
+                        ecn: tos & 0b11,
+                        data: data.to_vec(),
+                    };
+                    if !queue_packet(&tx, packet, &stats.rx_overflow) {
+                        return;
+                    }
+                }
+            }
+        }));
+    }
+    Ok(handles)
+}
+
+/// Starts what writes a link's datagrams: a task, or a ring thread with io_uring. `dscp`
+/// is the link's own, which the socket is marked with already.
+fn spawn_link_sender(
+    network: &NetworkConfig,
+    socket: &Arc<UdpSocket>,
+    stats: &Arc<LinkCounters>,
+    pool: &Arc<BufferPool>,
+    dscp: u8,
+) -> VtrunkdResult<LinkSender> {
+    let depth = network.send_queue_depth.unwrap_or(DEFAULT_SEND_QUEUE_DEPTH);
+    let batch_size = network.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if network.io_backend == Some(IoBackend::IoUring) {
+        let sender = LinkSender::spawn_uring(
+            Arc::clone(socket),
+            Arc::clone(stats),
+            Arc::clone(pool),
+            depth,
+            batch_size,
+            dscp << 2,
+        )?;
+        return Ok(sender);
+    }
+    Ok(LinkSender::spawn(
+        Arc::clone(socket),
+        Arc::clone(stats),
+        Arc::clone(pool),
+        depth,
+        batch_size,
+        dscp << 2,
+    ))
+}
+
+async fn setup_links(
+    wg_config: &WireGuardConfig,
+    network: &NetworkConfig,
+    mode: BondingMode,
+    error_backoff: Duration,
+    health_timeout: Option<Duration>,
+    stats: Arc<StatsRegistry>,
+    events: EventSender,
+) -> VtrunkdResult<(LinkManager, mpsc::Receiver<NetPacket>)> {
+    let (tx, rx) = mpsc::channel(network.channel_depth.unwrap_or(DEFAULT_CHANNEL_DEPTH));
+    let mut links = Vec::new();
+    let mut receivers = JoinSet::new();
+    let pool = Arc::new(BufferPool::default());
+
+    for (index, link_config) in wg_config.links.iter().enumerate() {
+        let name = link_name(link_config, index);
+        let ecn = wg_config.ecn.unwrap_or(false);
+        let (socket, remote) = create_link_socket(link_config, &name, ecn, true, None).await?;
+        let device = link_device(link_config, &socket);
+        let socket = Arc::new(socket);
+        let link_stats = stats.link(index);
+        let sockets = receive_sockets(link_config, &name, ecn, &socket)?;
+        let link_receivers = spawn_link_receivers(
+            &mut receivers,
+            network,
+            index,
+            wg_config,
+            &sockets,
+            &link_stats,
+            &tx,
+        )?;
+
+        let dscp = link_config.dscp.unwrap_or(0);
+        let sender = spawn_link_sender(network, &socket, &link_stats, &pool, dscp)?;
+        links.push(Link {
+            name,
+            socket,
+            sender,
+            remote,
+            weight: link_config.weight.unwrap_or(1),
+            admin_state: LinkAdminState::Up,
+            down_since: None,
+            last_rx: None,
+            last_ping_sent: None,
+            last_rtt_ms: None,
+            quality: Quality::default(),
+            degraded: false,
+            dscp,
+            stats: link_stats,
+            events: events.clone(),
+            receivers: link_receivers,
+            device_index: device.as_deref().and_then(hotplug::device_index),
+            device,
+            strict_source: link_config.strict_source.unwrap_or(false),
+        });
+    }
+
+    Ok((
+        LinkManager {
+            links,
+            mode,
+            error_backoff,
+            health_timeout,
+            next_index: 0,
+            remaining_weight: 0,
+            failover_index: None,
+            failover_by_quality: false,
+            bond_control: true,
+            clamp_mss: None,
+            icmp: None,
+            unsupported: UnsupportedPolicy::default(),
+            unsupported_logged: None,
+            links_down: LinksDownPolicy::default(),
+            outage: None,
+            send_limit: None,
+            receive_limit: None,
+            copy_dscp: false,
+            ecn: false,
+            tos: 0,
+            compression: false,
+            peer_features: 0,
+            reorder: None,
+            keys: uapi::Status::default(),
+            rotation: KeyRotation::default(),
+            handshake_rate_limit: DEFAULT_HANDSHAKE_RATE_LIMIT,
+            rate_limiter: None,
+            handshake_count_reset: Instant::now(),
+            stats,
+            events,
+            capture_dir: control::capture_dir(None),
+            capture: None,
+            receivers,
+            net_tx: Some(tx.downgrade()),
+            pool,
+            spare: Vec::new(),
+            tun_batch: Vec::new(),
+            tun_batching: false,
+            lanes: Lanes::default(),
+        },
+        rx,
+    ))
+}
+
+/// Opens link `name`'s socket; `inherit` lets it come from a previous process or the
+/// service manager instead of a fresh bind, and `address` replaces the configured bind
+/// address, keeping its port.
+async fn create_link_socket(
+    link_config: &WireGuardLinkConfig,
+    name: &str,
+    ecn: bool,
+    inherit: bool,
+    address: Option<IpAddr>,
+) -> VtrunkdResult<(UdpSocket, Option<SocketAddr>)> {
+    let remote = match &link_config.endpoint {
+        Some(endpoint) => Some(resolve_endpoint(endpoint).await?),
+        None => None,
+    };
+
+    let mut bind_addr = match link_config.bind.as_deref() {
+        Some(value) => parse_bind_addr(value)?,
+        None => default_bind_addr(remote),
+    };
+    if let Some(address) = address {
+        bind_addr.set_ip(address);
+    }
+    let inherited = match inherit {
+        true => activation::socket_for(name, bind_addr)?,
+        false => None,
+    };
+    let socket = match inherited {
+        Some(socket) => UdpSocket::from_std(socket)?,
+        None if link_config.receive_sockets.unwrap_or(1) > 1 => {
+            UdpSocket::from_std(bind_reuse_port(bind_addr, name)?)?
+        }
+        None => UdpSocket::bind(bind_addr).await?,
+    };
+    configure_link_socket(&socket, link_config, name, ecn)?;
+
+    Ok((socket, remote))
+}
+
+/// `socket` and the further sockets the link's `receive_sockets` asks for, bound to the
+/// same address with `SO_REUSEPORT` so the kernel spreads sources across their receivers.
+fn receive_sockets(
+    link_config: &WireGuardLinkConfig,
+    name: &str,
+    ecn: bool,
+    socket: &Arc<UdpSocket>,
+) -> VtrunkdResult<Vec<Arc<UdpSocket>>> {
+    let local = socket.local_addr()?;
+    let mut sockets = vec![Arc::clone(socket)];
+    for _ in 1..link_config.receive_sockets.unwrap_or(1) {
+        let socket = UdpSocket::from_std(bind_reuse_port(local, name)?)?;
+        configure_link_socket(&socket, link_config, name, ecn)?;
+        sockets.push(Arc::new(socket));
+    }
+    Ok(sockets)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_reuse_port(addr: SocketAddr, name: &str) -> VtrunkdResult<std::net::UdpSocket> {
+    use nix::sys::socket::{bind, socket, AddressFamily, SockFlag, SockType, SockaddrStorage};
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let failed = |e: nix::Error| {
+        VtrunkdError::Network(format!(
+            "WireGuard {} cannot share {} between receive sockets: {}",
+            name, addr, e
+        ))
+    };
+    let fd = socket(
+        family,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+        None,
+    )
+    .map_err(failed)?;
+    // SAFETY: `socket` just returned this descriptor and nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    setsockopt(fd.as_raw_fd(), sockopt::ReusePort, &true).map_err(failed)?;
+    bind(fd.as_raw_fd(), &SockaddrStorage::from(addr)).map_err(failed)?;
+    Ok(std::net::UdpSocket::from(fd))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_reuse_port(_addr: SocketAddr, _name: &str) -> VtrunkdResult<std::net::UdpSocket> {
+    Err(VtrunkdError::InvalidConfig(
+        "WireGuard link receive_sockets is only supported on Linux".to_string(),
+    ))
+}
+
+/// Applies the link's socket options to one of its sockets.
+fn configure_link_socket(
+    socket: &UdpSocket,
+    link_config: &WireGuardLinkConfig,
+    name: &str,
+    ecn: bool,
+) -> VtrunkdResult<()> {
+    if let Some(device) = &link_config.bind_device {
+        bind_to_device(socket, name, device)?;
+    }
+    #[cfg(unix)]
+    protect::socket(name, socket.as_raw_fd())?;
+    if let Some(size) = link_config.socket_recv_buffer {
+        set_socket_buffer(socket, name, size, false)?;
+    }
+    if let Some(size) = link_config.socket_send_buffer {
+        set_socket_buffer(socket, name, size, true)?;
+    }
+    if let Some(dscp) = link_config.dscp.filter(|&dscp| dscp != 0) {
+        set_tos(socket, dscp << 2)?;
+    }
+    if let Some(ttl) = link_config.ttl {
+        set_ttl(socket, ttl)?;
+    }
+    if ecn {
+        receive_tos(socket)?;
+    }
+    Ok(())
+}
+
+/// The device a link rides: its `bind_device`, or the one holding the address it is bound
+/// to. Links on the wildcard address follow the routing table instead.
+fn link_device(link_config: &WireGuardLinkConfig, socket: &UdpSocket) -> Option<String> {
+    if let Some(device) = &link_config.bind_device {
+        return Some(device.clone());
+    }
+    let ip = socket.local_addr().ok()?.ip();
+    if ip.is_unspecified() {
+        return None;
+    }
+    hotplug::device_of(ip)
+}
+
+/// The address a link bound to a specific address should bind to on `device` now: the
+/// configured one while the device still has it, else the device's address of the same
+/// family. `None` when the link has no such address or the device has none to offer.
+fn device_bind_ip(link_config: &WireGuardLinkConfig, device: &str) -> Option<IpAddr> {
+    if link_config.bind_device.is_some() {
+        return None;
+    }
+    let configured = parse_bind_addr(link_config.bind.as_deref()?).ok()?.ip();
+    if configured.is_unspecified() {
+        return None;
+    }
+    let addresses = hotplug::device_addresses(device);
+    if addresses.contains(&configured) {
+        return Some(configured);
+    }
+    addresses.into_iter().find(|address| match address {
+        IpAddr::V4(_) => configured.is_ipv4(),
+        // Link-local addresses need a scope the configured bind does not carry.
+        IpAddr::V6(v6) => configured.is_ipv6() && !v6.is_unicast_link_local(),
+    })
+}
+
+/// Pins the socket to `device`, so it follows whatever address the device has.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_device(socket: &UdpSocket, name: &str, device: &str) -> VtrunkdResult<()> {
+    setsockopt(
+        socket.as_raw_fd(),
+        sockopt::BindToDevice,
+        &std::ffi::OsString::from(device),
+    )
+    .map_err(|e| {
+        VtrunkdError::Network(format!(
+            "WireGuard {} cannot bind to device {}: {}",
+            name, device, e
+        ))
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_to_device(_socket: &UdpSocket, _name: &str, _device: &str) -> VtrunkdResult<()> {
+    Err(VtrunkdError::InvalidConfig(
+        "WireGuard link bind_device is only supported on Linux".to_string(),
+    ))
+}
+
+/// Sets `SO_SNDBUF`/`SO_RCVBUF`, past `net.core.{w,r}mem_max` where `CAP_NET_ADMIN` allows.
+#[cfg(unix)]
+fn set_socket_buffer(socket: &UdpSocket, name: &str, size: usize, send: bool) -> VtrunkdResult<()> {
+    let fd = socket.as_raw_fd();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let forced = if send {
+        setsockopt(fd, sockopt::SndBufForce, &size)
+    } else {
+        setsockopt(fd, sockopt::RcvBufForce, &size)
+    }
+    .is_ok();
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let forced = false;
+    if !forced {
+        if send {
+            setsockopt(fd, sockopt::SndBuf, &size)?;
+        } else {
+            setsockopt(fd, sockopt::RcvBuf, &size)?;
+        }
+    }
+
+    let (kind, actual) = if send {
+        ("send", getsockopt(fd, sockopt::SndBuf)?)
+    } else {
+        ("receive", getsockopt(fd, sockopt::RcvBuf)?)
+    };
+    // Linux reports twice the requested size to account for bookkeeping overhead.
+    if actual < size {
+        warn!(
+            "WireGuard {} {} buffer is {} bytes, below the requested {}; raise net.core.{}mem_max",
+            name,
+            kind,
+            actual,
+            size,
+            if send { "w" } else { "r" }
+        );
+    } else {
+        debug!("WireGuard {} {} buffer set to {} bytes", name, kind, actual);
+    }
+    Ok(())
+}
+
+/// Sets `IP_TOS`, and `IPV6_TCLASS` on IPv6 sockets: DSCP and ECN of what is sent next.
+#[cfg(unix)]
+fn set_tos(socket: &UdpSocket, tos: u8) -> std::io::Result<()> {
+    set_socket_int(socket, libc::IP_TOS, libc::IPV6_TCLASS, tos.into())
+}
+
+#[cfg(unix)]
+fn set_ttl(socket: &UdpSocket, ttl: u8) -> std::io::Result<()> {
+    set_socket_int(socket, libc::IP_TTL, libc::IPV6_UNICAST_HOPS, ttl.into())
+}
+
+/// Asks for the TOS byte of each received datagram, which carries the path's ECN marks.
+#[cfg(unix)]
+fn receive_tos(socket: &UdpSocket) -> std::io::Result<()> {
+    set_socket_int(socket, libc::IP_RECVTOS, libc::IPV6_RECVTCLASS, 1)
+}
+
+#[cfg(unix)]
+fn set_socket_int(
+    socket: &UdpSocket,
+    ipv4: libc::c_int,
+    ipv6: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    let fd = socket.as_raw_fd();
+    let set = |level, name| {
+        // SAFETY: `value` is a valid c_int that outlives the call.
+        let result = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                (&value as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    };
+    if socket.local_addr()?.is_ipv6() {
+        set(libc::IPPROTO_IPV6, ipv6)?;
+        // Dual-stack sockets handle IPv4-mapped traffic with the IPv4 option; v6-only
+        // sockets may refuse it.
+        let _ = set(libc::IPPROTO_IP, ipv4);
+        Ok(())
+    } else {
+        set(libc::IPPROTO_IP, ipv4)
+    }
+}
+
+/// Socket options are set through the Unix socket API; elsewhere asking for them fails.
+#[cfg(not(unix))]
+fn set_socket_buffer(
+    _socket: &UdpSocket,
+    name: &str,
+    _size: usize,
+    _send: bool,
+) -> VtrunkdResult<()> {
+    Err(VtrunkdError::InvalidConfig(format!(
+        "WireGuard {} socket buffer sizes are only supported on Unix",
+        name
+    )))
+}
+
+#[cfg(not(unix))]
+fn set_tos(_socket: &UdpSocket, _tos: u8) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+fn set_ttl(_socket: &UdpSocket, _ttl: u8) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+fn receive_tos(_socket: &UdpSocket) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+fn default_bind_addr(remote: Option<SocketAddr>) -> SocketAddr {
+    match remote {
+        Some(SocketAddr::V6(_)) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        _ => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+    }
+}
+
+fn parse_bind_addr(value: &str) -> VtrunkdResult<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 0));
+    }
+
+    Err(VtrunkdError::InvalidConfig(format!(
+        "Invalid WireGuard bind address: {}",
+        value
+    )))
+}
+
+async fn resolve_endpoint(value: &str) -> VtrunkdResult<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    let mut resolved = lookup_host(value)
+        .await
+        .map_err(|e| VtrunkdError::InvalidConfig(format!("Failed to resolve {}: {}", value, e)))?;
+
+    resolved
+        .next()
+        .ok_or_else(|| VtrunkdError::InvalidConfig(format!("No addresses resolved for {}", value)))
+}
+
+fn encode_key(key: &[u8; 32]) -> String {
+    general_purpose::STANDARD.encode(key)
+}
+
+fn public_key_of(private_key: &[u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(*private_key)).to_bytes()
+}
+
+/// Handshakes carry a MAC keyed with the responder's public key, so each static key
+/// needs its own limiter.
+fn handshake_rate_limiter(private_key: &[u8; 32], limit: u64) -> Arc<RateLimiter> {
+    let public_key = PublicKey::from(public_key_of(private_key));
+    Arc::new(RateLimiter::new(&public_key, limit))
+}
+
+fn decode_key(label: &str, value: &str) -> VtrunkdResult<[u8; 32]> {
+    let decoded = general_purpose::STANDARD
+        .decode(value.trim())
+        .map(Zeroizing::new)
+        .map_err(|_| VtrunkdError::InvalidConfig(format!("Invalid base64 for {}", label)))?;
+    if decoded.len() != 32 {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Invalid {} length (expected 32 bytes, got {})",
+            label,
+            decoded.len()
+        )));
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&decoded);
+    Ok(key)
+}
+
+impl Link {
+    fn is_available(
+        &mut self,
+        now: Instant,
+        error_backoff: Duration,
+        health_timeout: Option<Duration>,
+    ) -> bool {
+        self.apply_send_outcome();
+        if self.remote.is_none() || !self.admin_state.carries_data() {
+            return false;
+        }
+
+        if let Some(timeout) = health_timeout {
+            match (self.last_rx, self.last_ping_sent) {
+                (Some(last_rx), _) => {
+                    if now.duration_since(last_rx) > timeout {
+                        self.mark_down(now, "no rx");
+                        return false;
+                    }
+                }
+                (None, Some(last_ping)) => {
+                    if now.duration_since(last_ping) > timeout {
+                        self.mark_down(now, "no pong");
+                        return false;
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+
+        if let Some(down_since) = self.down_since {
+            if now.duration_since(down_since) < error_backoff {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn state_label(
+        &mut self,
+        now: Instant,
+        error_backoff: Duration,
+        health_timeout: Option<Duration>,
+    ) -> &'static str {
+        match self.admin_state {
+            LinkAdminState::Down => "admin-down",
+            LinkAdminState::Drain => "drain",
+            LinkAdminState::Up => {
+                if self.remote.is_none() {
+                    "idle"
+                } else if self.is_available(now, error_backoff, health_timeout) {
+                    "up"
+                } else {
+                    "down"
+                }
+            }
+        }
+    }
+
+    fn mark_down(&mut self, now: Instant, reason: &str) {
+        if self.down_since.is_none() {
+            warn!(
+                link = %self.name,
+                event = "link_down",
+                "WireGuard {} marked down ({})",
+                self.name,
+                reason
+            );
+            self.quality.record_failure(now);
+            events::emit(
+                &self.events,
+                TunnelEvent::LinkDown {
+                    link: self.name.clone(),
+                    reason: reason.to_string(),
+                },
+            );
+        }
+        self.down_since = Some(now);
+    }
+
+    fn mark_recovered(&mut self, how: &str) {
+        if self.down_since.take().is_some() {
+            info!(
+                link = %self.name,
+                event = "link_up",
+                "WireGuard {} recovered{}",
+                self.name,
+                how
+            );
+            events::emit(
+                &self.events,
+                TunnelEvent::LinkUp {
+                    link: self.name.clone(),
+                },
+            );
+        }
+    }
+
+    /// Whether `src` is the peer's address on this link, as far as the link knows it.
+    fn is_remote(&self, src: SocketAddr) -> bool {
+        self.remote.is_some_and(|remote| {
+            remote.ip().to_canonical() == src.ip().to_canonical() && remote.port() == src.port()
+        })
+    }
+
+    fn record_rx(&mut self, now: Instant) {
+        self.last_rx = Some(now);
+        self.degraded = false;
+        self.mark_recovered(" (rx)");
+    }
+
+    fn record_ping(&mut self, now: Instant) {
+        self.last_ping_sent = Some(now);
+    }
+
+    fn record_rtt(&mut self, rtt_ms: u64) {
+        self.last_rtt_ms = Some(rtt_ms);
+        self.quality.record_rtt(rtt_ms);
+    }
+
+    /// Takes in what the link's sender saw: a failed send marks the link down, and one
+    /// that got through brings it back.
+    fn apply_send_outcome(&mut self) {
+        match self.sender.take_outcome() {
+            Some(SendOutcome::Failed { at, error }) => self.mark_down(at, &error),
+            Some(SendOutcome::Sent) => self.mark_recovered(""),
+            None => {}
+        }
+    }
+}
+
+impl LinkManager {
+    /// Takes back the peer addresses and RTTs of the last run. Configured endpoints win;
+    /// a saved address lets a listening side reach its peer before hearing from it.
+    fn restore_state(&mut self, saved: &SessionState, now: SystemTime) {
+        if !saved.is_fresh(now) {
+            return;
+        }
+        for link in &mut self.links {
+            let Some(state) = saved.links.iter().find(|state| state.name == link.name) else {
+                continue;
+            };
+            if link.remote.is_none() {
+                link.remote = state.remote;
+            }
+            link.last_rtt_ms = link.last_rtt_ms.or(state.rtt_ms);
+        }
+    }
+
+    fn session_state(&self) -> SessionState {
+        SessionState {
+            saved_at: state::unix_now(),
+            links: self
+                .links
+                .iter()
+                .map(|link| LinkState {
+                    name: link.name.clone(),
+                    remote: link.remote,
+                    rtt_ms: link.last_rtt_ms,
+                })
+                .collect(),
+            stats: self.stats.snapshot(),
+            keys: self.rotation.rotated.then(|| SessionKeys {
+                config_public_key: self.rotation.config_public_key.clone(),
+                config_peer_public_key: self.rotation.config_peer_public_key.clone(),
+                private_key: encode_key(&self.keys.private_key),
+                peer_public_key: encode_key(&self.keys.public_key),
+            }),
+        }
+    }
+
+    fn has_endpoints(&self) -> bool {
+        self.links.iter().any(|link| link.remote.is_some())
+    }
+
+    fn handle_command(&mut self, command: ControlCommand) -> Result<String, String> {
+        match command {
+            ControlCommand::SetLinkState { name, state } => self.set_admin_state(&name, state),
+            ControlCommand::Stats => serde_json::to_string(&self.live_stats(Instant::now()))
+                .map_err(|e| format!("Failed to encode stats: {}", e)),
+            // Subscriptions are served by the control socket itself.
+            ControlCommand::Events => Err("Unsupported command".to_string()),
+            ControlCommand::CaptureStart {
+                target,
+                seconds,
+                max_mb,
+            } => self.start_capture(target, seconds, max_mb),
+            ControlCommand::CaptureStop => match self.capture.take() {
+                Some(capture) => capture
+                    .finish()
+                    .map_err(|e| format!("Failed to finish capture: {}", e)),
+                None => Err("No capture running".to_string()),
+            },
+            // Need the TUN device or the session, so the run loop handles them.
+            ControlCommand::Handover { .. }
+            | ControlCommand::Uapi
+            | ControlCommand::RotateKey
+            | ControlCommand::AcceptKey { .. }
+            | ControlCommand::RebindLink { .. } => Err("Unsupported command".to_string()),
+        }
+    }
+
+    /// The session keys with their live fields filled in, for a UAPI `get`.
+    fn uapi_status(&self, tunnel: &Tunn) -> uapi::Status {
+        let active = self
+            .failover_index
+            .and_then(|index| self.links.get(index))
+            .filter(|link| link.remote.is_some())
+            .or_else(|| {
+                self.links.iter().find(|link| {
+                    link.remote.is_some()
+                        && link.down_since.is_none()
+                        && link.admin_state.carries_data()
+                })
+            })
+            .or_else(|| self.links.iter().find(|link| link.remote.is_some()));
+        let snapshot = self.stats.snapshot();
+        uapi::Status {
+            listen_port: self
+                .links
+                .first()
+                .and_then(|link| link.socket.local_addr().ok())
+                .map(|addr| addr.port()),
+            endpoint: active.and_then(|link| link.remote),
+            last_handshake: tunnel
+                .time_since_last_handshake()
+                .and_then(|since| SystemTime::now().checked_sub(since)),
+            tx_bytes: snapshot.links.iter().map(|link| link.tx_bytes).sum(),
+            rx_bytes: snapshot.links.iter().map(|link| link.rx_bytes).sum(),
+            private_key: self.keys.private_key,
+            public_key: self.keys.public_key,
+            preshared_key: self.keys.preshared_key,
+            persistent_keepalive: self.keys.persistent_keepalive,
+        }
+    }
+
+    /// Passes the TUN device and every link socket to an upgraded process.
+    #[cfg(unix)]
+    fn handover(&mut self, device: &TunnelDevice, path: &Path) -> Result<String, String> {
+        let sockets: Vec<(String, RawFd)> = self
+            .links
+            .iter()
+            .map(|link| (link.name.clone(), link.socket.as_raw_fd()))
+            .collect();
+        handover::send(path, &device.queue_fds(), &sockets)
+            .map_err(|e| format!("Handover failed: {}", e))?;
+        info!(
+            event = "handed_over",
+            "Handed the TUN device ({} queues) and {} link sockets to {:?}",
+            device.queue_count(),
+            sockets.len(),
+            path
+        );
+        events::emit(&self.events, TunnelEvent::HandedOver);
+        Ok(format!("handed over {} links", sockets.len()))
+    }
+
+    /// Handing over passes descriptors over a Unix domain socket.
+    #[cfg(not(unix))]
+    fn handover(&mut self, _device: &TunnelDevice, _path: &Path) -> Result<String, String> {
+        Err("Handover needs Unix domain sockets, which this platform lacks".to_string())
+    }
+
+    fn start_capture(
+        &mut self,
+        target: CaptureTarget,
+        seconds: u64,
+        max_mb: u64,
+    ) -> Result<String, String> {
+        if let Some(previous) = self.capture.take() {
+            let _ = previous.finish();
+        }
+        let capture = Capture::start(
+            &self.capture_dir,
+            target,
+            Duration::from_secs(seconds),
+            max_mb.saturating_mul(1024 * 1024),
+        )
+        .map_err(|e| format!("Failed to start capture in {:?}: {}", self.capture_dir, e))?;
+        let paths = capture.paths();
+        self.capture = Some(capture);
+        Ok(paths)
+    }
+
+    /// Ends the capture once it has used up its time or size budget.
+    fn check_capture(&mut self) {
+        if self.capture.as_ref().is_some_and(Capture::is_expired) {
+            if let Some(capture) = self.capture.take() {
+                if let Err(e) = capture.finish() {
+                    warn!("Failed to finish packet capture: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Writes a decrypted packet to the TUN device, through the reorder buffer when it is
+    /// numbered. `None` marks a numbered packet with nothing to write, so that its place
+    /// is not waited for.
+    async fn write_tun(
+        &mut self,
+        device: &impl TunnelWriter,
+        sequence: Option<(u32, u64)>,
+        packet: Option<&[u8]>,
+    ) -> VtrunkdResult<()> {
+        let (Some(reorder), Some(sequence)) = (&mut self.reorder, sequence) else {
+            if let Some(packet) = packet {
+                self.write_device(device, packet).await?;
+            }
+            return Ok(());
+        };
+        let ready = reorder.push(
+            lanes::lane_of_index(sequence.0),
+            sequence,
+            packet.map(<[u8]>::to_vec),
+            Instant::now(),
+            &self.stats.tunnel,
+        );
+        for packet in ready {
+            self.write_device(device, &packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether a packet read from the TUN device goes on to be tunnelled. One that is not
+    /// well-formed IPv4 or IPv6 is counted and handled by `network.unsupported_packets`;
+    /// one shaped like an envelope the peer unwraps is counted and dropped.
+    fn accept_inner(&mut self, packet: &[u8], now: Instant) -> bool {
+        // A vtrunkd peer would unwrap it as one of ours rather than deliver it.
+        if self.bond_control
+            && (rotate::is_envelope(packet) || self.compression && compress::is_compressed(packet))
+        {
+            stats::add(&self.stats.tunnel.tun_rx_reserved, 1);
+            return false;
+        }
+        let counter = match inner::family(packet) {
+            Family::Ipv4 | Family::Ipv6 => return true,
+            Family::Malformed => &self.stats.tunnel.tun_rx_malformed,
+            Family::NonIp => &self.stats.tunnel.tun_rx_non_ip,
+        };
+        stats::add(counter, 1);
+        match self.unsupported {
+            UnsupportedPolicy::Forward => true,
+            UnsupportedPolicy::Drop => false,
+            UnsupportedPolicy::Log => {
+                let quiet = self
+                    .unsupported_logged
+                    .is_some_and(|logged| now.duration_since(logged) < Duration::from_secs(1));
+                if !quiet {
+                    self.unsupported_logged = Some(now);
+                    warn!(
+                        "Dropping a {}-byte packet from the TUN device that is not IPv4 or IPv6: {:02x?}",
+                        packet.len(),
+                        &packet[..packet.len().min(16)]
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    /// Why a packet read from the TUN device cannot be sent. With ICMP errors off, it goes
+    /// out whatever its size, and unless `links_down` is `drop` it is dropped later if no
+    /// link takes it.
+    fn refusal(&mut self, packet: &[u8], now: Instant) -> Option<Refusal> {
+        if self.icmp.as_ref().is_some_and(|icmp| icmp.too_big(packet)) {
+            return Some(Refusal::TooBig);
+        }
+        let unreachable = match self.links_down {
+            LinksDownPolicy::BestEffort => {
+                self.icmp.is_some()
+                    && !self
+                        .links
+                        .iter()
+                        .any(|link| link.admin_state.carries_data() && link.remote.is_some())
+            }
+            LinksDownPolicy::Drop => !self.any_link_available(now),
+            LinksDownPolicy::Buffer => false,
+        };
+        unreachable.then_some(Refusal::Unreachable)
+    }
+
+    /// Takes a TUN packet off `send_limit_kbit`; one over it is counted and dropped.
+    fn within_send_limit(&mut self, packet: &[u8], now: Instant) -> bool {
+        let Some(limit) = &mut self.send_limit else {
+            return true;
+        };
+        let within = limit.allow(packet.len(), now);
+        if !within {
+            stats::add(&self.stats.tunnel.dropped_send_limit, 1);
+        }
+        within
+    }
+
+    /// Takes a packet received from the peer off `receive_limit_kbit`; one over it is
+    /// counted and dropped.
+    fn within_receive_limit(&mut self, packet: &[u8], now: Instant) -> bool {
+        let Some(limit) = &mut self.receive_limit else {
+            return true;
+        };
+        let within = limit.allow(packet.len(), now);
+        if !within {
+            stats::add(&self.stats.tunnel.dropped_receive_limit, 1);
+        }
+        within
+    }
+
+    fn any_link_available(&mut self, now: Instant) -> bool {
+        let (error_backoff, health_timeout) = (self.error_backoff, self.health_timeout);
+        self.links
+            .iter_mut()
+            .any(|link| link.is_available(now, error_backoff, health_timeout))
+    }
+
+    /// Holds `packet` for `links_down: buffer` when no link is available, or when earlier
+    /// packets still wait, so they keep their order.
+    fn hold(&mut self, packet: &[u8], now: Instant) -> bool {
+        if self.outage.is_none() {
+            return false;
+        }
+        let waiting = self
+            .outage
+            .as_ref()
+            .is_some_and(|outage| !outage.is_empty());
+        if !waiting && self.any_link_available(now) {
+            return false;
+        }
+        if let Some(outage) = &mut self.outage {
+            outage.push(packet, now, &self.stats.tunnel);
+        }
+        true
+    }
+
+    /// Sends the packets held for `links_down: buffer` once a link is available again,
+    /// and drops those held too long meanwhile.
+    async fn replay_outage(&mut self, tunnel: &mut Tunn) -> VtrunkdResult<()> {
+        let now = Instant::now();
+        if self.outage.as_ref().is_none_or(Outage::is_empty) {
+            return Ok(());
+        }
+        let available = self.any_link_available(now);
+        let Some(outage) = &mut self.outage else {
+            return Ok(());
+        };
+        if !available {
+            outage.expire(now, &self.stats.tunnel);
+            return Ok(());
+        }
+        let held = outage.take(now, &self.stats.tunnel);
+        debug!(
+            "WireGuard sending {} packets held while no link was available",
+            held.len()
+        );
+        let mut outgoing = Vec::with_capacity(held.len());
+        for packet in held {
+            let tos = self.outer_tos(&packet);
+            if let Some(packet) = self.seal(tunnel, &packet, tos)? {
+                outgoing.push(packet);
+            }
+        }
+        self.dispatch_lanes();
+        self.send_packets(&mut outgoing).await
+    }
+
+    /// Encrypts a TUN packet on its flow's crypto lane: here for lane 0, or once the
+    /// lane's worker has it, after [`dispatch_lanes`](Self::dispatch_lanes).
+    fn seal(
+        &mut self,
+        tunnel: &mut Tunn,
+        packet: &[u8],
+        tos: u8,
+    ) -> VtrunkdResult<Option<Vec<u8>>> {
+        let lane = self.lanes.flow_lane(packet);
+        if lane == 0 {
+            return self.encapsulate(tunnel, packet);
+        }
+        let mut buffer = self.take_buffer(packet.len());
+        buffer.copy_from_slice(packet);
+        self.lanes.queue_outbound(lane, buffer, tos);
+        Ok(None)
+    }
+
+    /// Hands the packets and datagrams gathered for the crypto lanes to their workers.
+    fn dispatch_lanes(&mut self) {
+        let compress = self.compression && self.peer_features & FEATURE_LZ4 != 0;
+        self.lanes
+            .dispatch(compress, &self.pool, &self.stats.tunnel);
+    }
+
+    /// What the crypto lanes' sessions are made from: the keys now in use.
+    fn lane_keys(&self) -> LaneKeys {
+        LaneKeys {
+            private_key: Zeroizing::new(self.keys.private_key),
+            peer_public_key: self.keys.public_key,
+            preshared_key: self.keys.preshared_key,
+            rate_limiter: self.rate_limiter.clone(),
+        }
+    }
+
+    /// Sends what a crypto lane encrypted, and delivers what it decrypted as
+    /// [`handle_incoming`] does lane 0's.
+    async fn lane_output(
+        &mut self,
+        device: &impl TunnelWriter,
+        output: Output,
+        max_len: usize,
+    ) -> VtrunkdResult<()> {
+        let received = match output {
+            Output::Sent(datagrams) => {
+                let marks = self.copy_dscp || self.ecn;
+                let mut outgoing = Vec::with_capacity(datagrams.len());
+                let mut outgoing_tos = 0;
+                for (datagram, tos) in datagrams {
+                    if marks && tos != outgoing_tos && !outgoing.is_empty() {
+                        self.mark_tos(outgoing_tos);
+                        self.send_packets(&mut outgoing).await?;
+                    }
+                    outgoing_tos = tos;
+                    outgoing.push(datagram);
+                }
+                if marks {
+                    self.mark_tos(outgoing_tos);
+                }
+                return self.send_packets(&mut outgoing).await;
+            }
+            Output::Received(received) => received,
+        };
+        for (packet, received) in received {
+            let sequence = self
+                .reorder
+                .as_ref()
+                .and_then(|_| reorder::sequence(&packet.data));
+            match received {
+                Received::Answered(datagrams) => {
+                    self.roam(packet.link_index, packet.src);
+                    for datagram in datagrams {
+                        self.send_packet(datagram).await?;
+                    }
+                }
+                Received::Cookie(reply) => {
+                    let _ = self.send_reply(packet.link_index, packet.src, reply).await;
+                    stats::add(&self.stats.tunnel.cookie_replies, 1);
+                }
+                Received::Data(mut data) => {
+                    self.roam(packet.link_index, packet.src);
+                    if self.bond_control && Message::parse(&data).is_some() {
+                        // Session messages only travel on lane 0.
+                        debug!("Dropping a session message from a crypto lane");
+                        self.write_tun(device, sequence, None).await?;
+                    } else {
+                        self.deliver(device, &packet, sequence, &mut data, max_len)
+                            .await?;
+                    }
+                    self.pool.put(std::iter::once(data));
+                }
+                Received::Empty => {
+                    if wg_packet_type(&packet.data) != Some(WG_COOKIE_REPLY) {
+                        self.roam(packet.link_index, packet.src);
+                    }
+                    self.write_tun(device, sequence, None).await?;
+                }
+                Received::Failed(e) => {
+                    warn!("WireGuard decapsulate error: {:?}", e);
+                    if let Some(link) = self.links.get(packet.link_index) {
+                        stats::add(&link.stats.rx_dropped, 1);
+                    }
+                }
+            }
+            self.pool.put(std::iter::once(packet.data));
+        }
+        Ok(())
+    }
+
+    /// Writes a packet decrypted from `packet` to the TUN device, inflated to `max_len`
+    /// at most when it came compressed, unless the receive limit or ECN drops it.
+    async fn deliver(
+        &mut self,
+        device: &impl TunnelWriter,
+        packet: &NetPacket,
+        sequence: Option<(u32, u64)>,
+        buffer: &mut [u8],
+        max_len: usize,
+    ) -> VtrunkdResult<()> {
+        let mut inflated;
+        // Only a vtrunkd peer that was told we take LZ4 sends these; for anyone else they
+        // are ordinary protocol 253 traffic.
+        let buffer = if self.bond_control && self.compression && compress::is_compressed(buffer) {
+            match compress::decompress(buffer, max_len) {
+                Some(packet) => {
+                    inflated = packet;
+                    &mut inflated[..]
+                }
+                None => {
+                    debug!("Dropping a compressed packet that does not decompress");
+                    if let Some(link) = self.links.get(packet.link_index) {
+                        stats::add(&link.stats.rx_dropped, 1);
+                    }
+                    return self.write_tun(device, sequence, None).await;
+                }
+            }
+        } else {
+            buffer
+        };
+        if !self.within_receive_limit(buffer, Instant::now()) {
+            return self.write_tun(device, sequence, None).await;
+        }
+        if self.ecn && !ecn::decapsulate(buffer, packet.ecn) {
+            if let Some(link) = self.links.get(packet.link_index) {
+                stats::add(&link.stats.rx_dropped, 1);
+            }
+            return self.write_tun(device, sequence, None).await;
+        }
+        if let Some(mtu) = self.clamp_mss {
+            mss::clamp(buffer, mtu);
+        }
+        self.write_tun(device, sequence, Some(buffer)).await
+    }
+
+    /// Encrypts a TUN packet, compressed when that pays, into a buffer from the pool;
+    /// `None` when the `Tunn` queued it behind a handshake.
+    fn encapsulate(&mut self, tunnel: &mut Tunn, packet: &[u8]) -> VtrunkdResult<Option<Vec<u8>>> {
+        let compressed = self.compress(packet);
+        let packet = compressed.as_deref().unwrap_or(packet);
+        // Room for the data header and tag, or for the handshake it may start instead.
+        let mut datagram = self.take_buffer(std::cmp::max(packet.len() + 32, 148));
+        let written = match tunnel.encapsulate(packet, &mut datagram) {
+            TunnResult::WriteToNetwork(packet) => Ok(Some(packet.len())),
+            TunnResult::Done => Ok(None),
+            TunnResult::Err(e) => Err(VtrunkdError::Network(format!(
+                "WireGuard encapsulate error: {:?}",
+                e
+            ))),
+            TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {
+                debug!("Unexpected tunnel write during encapsulate");
+                Ok(None)
+            }
+        };
+        match written {
+            Ok(Some(len)) => {
+                datagram.truncate(len);
+                Ok(Some(datagram))
+            }
+            other => {
+                self.spare.push(datagram);
+                other.map(|_| None)
+            }
+        }
+    }
+
+    /// A `len`-byte buffer, reused from the pool when one has come back.
+    fn take_buffer(&mut self, len: usize) -> Vec<u8> {
+        if self.spare.is_empty() {
+            self.pool.refill(&mut self.spare);
+        }
+        let mut buffer = self.spare.pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    /// Drops `packet`, writing the ICMP error for it back to the TUN device.
+    async fn refuse(
+        &mut self,
+        device: &impl TunnelWriter,
+        packet: &[u8],
+        refusal: Refusal,
+    ) -> VtrunkdResult<()> {
+        let dropped = match refusal {
+            Refusal::TooBig => &self.stats.tunnel.dropped_too_big,
+            Refusal::Unreachable => &self.stats.tunnel.dropped_no_link,
+        };
+        stats::add(dropped, 1);
+        let Some(icmp) = &mut self.icmp else {
+            return Ok(());
+        };
+        if let Some(error) = icmp.refuse(packet, refusal, Instant::now()) {
+            stats::add(&self.stats.tunnel.icmp_errors, 1);
+            self.write_device(device, &error).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the packets whose gap was held past the hold time by `now`.
+    async fn expire_reorder(
+        &mut self,
+        device: &impl TunnelWriter,
+        now: Instant,
+    ) -> VtrunkdResult<()> {
+        let Some(reorder) = &mut self.reorder else {
+            return Ok(());
+        };
+        for packet in reorder.expire(now, &self.stats.tunnel) {
+            self.write_device(device, &packet).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_device(
+        &mut self,
+        device: &impl TunnelWriter,
+        packet: &[u8],
+    ) -> VtrunkdResult<()> {
+        if self.tun_batching {
+            let mut buffer = self.take_buffer(packet.len());
+            buffer.copy_from_slice(packet);
+            self.tun_batch.push(buffer);
+        } else {
+            device.write_packet(packet).await?;
+        }
+        self.stats.tunnel.record_tun_tx(packet.len());
+        self.capture_tun(packet);
+        Ok(())
+    }
+
+    /// Holds the packets written to the TUN device until [`flush_tun`](Self::flush_tun).
+    fn batch_tun(&mut self) {
+        self.tun_batching = true;
+    }
+
+    /// Writes the held packets to the TUN device in one batch.
+    async fn flush_tun(&mut self, device: &impl TunnelWriter) -> VtrunkdResult<()> {
+        self.tun_batching = false;
+        if self.tun_batch.is_empty() {
+            return Ok(());
+        }
+        let written = device.write_packets(&self.tun_batch).await;
+        self.spare.append(&mut self.tun_batch);
+        written
+    }
+
+    /// Retunes the reorder hold time from the round trips of the links carrying data.
+    fn tune_reorder(&mut self) {
+        let rtts: Vec<Duration> = self
+            .links
+            .iter()
+            .filter(|link| link.admin_state.carries_data() && link.down_since.is_none())
+            .filter_map(|link| link.last_rtt_ms.map(Duration::from_millis))
+            .collect();
+        if let Some(reorder) = &mut self.reorder {
+            reorder.tune(rtts, &self.stats.tunnel);
+        }
+    }
+
+    fn capture_tun(&mut self, packet: &[u8]) {
+        if let Some(capture) = &mut self.capture {
+            if let Err(e) = capture.record_tun(packet) {
+                warn!("Packet capture stopped: {}", e);
+                self.capture = None;
+            }
+        }
+    }
+
+    fn capture_link(&mut self, index: usize, peer: SocketAddr, outbound: bool, payload: &[u8]) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        let Some(link) = self.links.get(index) else {
+            return;
+        };
+        let local = link
+            .socket
+            .local_addr()
+            .unwrap_or_else(|_| default_bind_addr(Some(peer)));
+        let (src, dst) = if outbound {
+            (local, peer)
+        } else {
+            (peer, local)
+        };
+        if let Err(e) = capture.record_link(src, dst, payload) {
+            warn!("Packet capture stopped: {}", e);
+            self.capture = None;
+        }
+    }
+
+    /// Replaces link `name`'s socket with a fresh one from its configuration, for when its
+    /// device or address went away and came back. A link bound to an address its device
+    /// no longer has moves to the device's new one. The endpoint is resolved again; a link
+    /// without one keeps the address it learned. The peer is pinged on the new socket
+    /// straight away, so the link recovers without sitting out the error backoff.
+    async fn rebind(
+        &mut self,
+        config: &Config,
+        name: &str,
+        epoch: Instant,
+    ) -> Result<String, String> {
+        let index = self
+            .links
+            .iter()
+            .position(|link| link.name == name)
+            .ok_or_else(|| format!("Unknown link: {}", name))?;
+        let tx = self
+            .net_tx
+            .as_ref()
+            .and_then(mpsc::WeakSender::upgrade)
+            .ok_or_else(|| "WireGuard link receivers stopped".to_string())?;
+        let wg_config = &config.wireguard;
+        let failed = |e: VtrunkdError| format!("Failed to rebind {}: {}", name, e);
+        let link_config = &wg_config.links[index];
+        let address = self.links[index]
+            .device
+            .as_deref()
+            .and_then(|device| device_bind_ip(link_config, device));
+        let (socket, remote) = create_link_socket(
+            link_config,
+            name,
+            wg_config.ecn.unwrap_or(false),
+            false,
+            address,
+        )
+        .await
+        .map_err(failed)?;
+        let device = link_device(link_config, &socket).or_else(|| self.links[index].device.clone());
+        let socket = Arc::new(socket);
+        let sockets = receive_sockets(link_config, name, wg_config.ecn.unwrap_or(false), &socket)
+            .map_err(failed)?;
+        // Reap the receivers of earlier rebinds.
+        while self.receivers.try_join_next().is_some() {}
+        let link = &mut self.links[index];
+        let receivers = spawn_link_receivers(
+            &mut self.receivers,
+            &config.network,
+            index,
+            wg_config,
+            &sockets,
+            &link.stats,
+            &tx,
+        )
+        .map_err(failed)?;
+        for previous in std::mem::replace(&mut link.receivers, receivers) {
+            previous.abort();
+        }
+        link.sender =
+            spawn_link_sender(&config.network, &socket, &link.stats, &self.pool, link.dscp)
+                .map_err(failed)?;
+        link.socket = socket;
+        link.device_index = device.as_deref().and_then(hotplug::device_index);
+        link.device = device;
+        if remote.is_some() {
+            link.remote = remote;
+        }
+        let local = link
+            .socket
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        info!(
+            link = %name,
+            event = "link_rebound",
+            "WireGuard {} rebound to {}",
+            name,
+            local
+        );
+        self.ping_link(index, epoch).await;
+        Ok(format!("{} rebound to {}", name, local))
+    }
+
+    /// Rebinds the links on `device` after rtnetlink reported it changed: a link pinned to
+    /// it when it was recreated or the link is down, and a link bound to one of its
+    /// addresses when that address moved.
+    async fn device_changed(&mut self, config: &Config, device: &str, epoch: Instant) {
+        for index in 0..self.links.len() {
+            let link = &self.links[index];
+            if link.device.as_deref() != Some(device) {
+                continue;
+            }
+            let link_config = &config.wireguard.links[index];
+            let stale = if link_config.bind_device.is_some() {
+                link.down_since.is_some() || link.device_index != hotplug::device_index(device)
+            } else {
+                let local = link.socket.local_addr().ok().map(|addr| addr.ip());
+                device_bind_ip(link_config, device).is_some_and(|ip| Some(ip) != local)
+            };
+            if !stale {
+                continue;
+            }
+            let name = link.name.clone();
+            if let Err(e) = self.rebind(config, &name, epoch).await {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    fn set_admin_state(&mut self, name: &str, state: LinkAdminState) -> Result<String, String> {
+        let link = self
+            .links
+            .iter_mut()
+            .find(|link| link.name == name)
+            .ok_or_else(|| format!("Unknown link: {}", name))?;
+        if link.admin_state != state {
+            info!(
+                link = %link.name,
+                event = "link_admin",
+                "WireGuard {} administratively {} (was {})",
+                link.name,
+                state,
+                link.admin_state
+            );
+            link.admin_state = state;
+            events::emit(
+                &self.events,
+                TunnelEvent::LinkAdmin {
+                    link: link.name.clone(),
+                    state: state.to_string(),
+                },
+            );
+        }
+        if !self
+            .links
+            .iter()
+            .any(|link| link.remote.is_some() && link.admin_state.carries_data())
+        {
+            warn!("WireGuard has no links left in service for data");
+        }
+        Ok(format!("{} {}", name, state))
+    }
+
+    /// Outer TOS byte for a plaintext packet; a DSCP of 0 stands for each link's own.
+    fn outer_tos(&self, packet: &[u8]) -> u8 {
+        let dscp = if self.copy_dscp {
+            inner_dscp(packet)
+        } else {
+            0
+        };
+        let ecn = if self.ecn {
+            ecn::encapsulate(ecn::inner(packet))
+        } else {
+            ecn::NOT_ECT
+        };
+        (dscp << 2) | ecn
+    }
+
+    /// Marks the next sends with `tos`, taking each link's own DSCP when its DSCP is 0.
+    /// The marking travels with each queued datagram, so sends already queued keep theirs.
+    fn mark_tos(&mut self, tos: u8) {
+        self.tos = tos;
+    }
+
+    /// The TOS byte link `index` sends with under the current marking.
+    fn link_tos(&self, index: usize) -> u8 {
+        let dscp = match self.tos >> 2 {
+            0 => self.links[index].dscp,
+            inner => inner,
+        };
+        (dscp << 2) | (self.tos & 0b11)
+    }
+
+    /// Each link's name and state, as the `links` command reports them.
+    fn link_states(&mut self, now: Instant) -> Vec<(String, &'static str)> {
+        let (error_backoff, health_timeout) = (self.error_backoff, self.health_timeout);
+        self.links
+            .iter_mut()
+            .map(|link| {
+                let state = link.state_label(now, error_backoff, health_timeout);
+                (link.name.clone(), state)
+            })
+            .collect()
+    }
+
+    /// Counters with each link's current state, RTT and quality score, as the `stats` command reports them.
+    fn live_stats(&mut self, now: Instant) -> StatsSnapshot {
+        let mut snapshot = self.stats.snapshot();
+        for (stats, link) in snapshot.links.iter_mut().zip(self.links.iter_mut()) {
+            let state = link.state_label(now, self.error_backoff, self.health_timeout);
+            stats.state = Some(state.to_string());
+            stats.rtt_ms = link.last_rtt_ms;
+            stats.quality = link.quality.score(now);
+        }
+        snapshot
+    }
+
+    /// Logs one line per link with rates and ping loss since `previous`.
+    fn log_stats(&mut self, previous: &StatsSnapshot, elapsed: Duration, now: Instant) {
+        let current = self.stats.snapshot();
+        for (index, link) in self.links.iter_mut().enumerate() {
+            let (Some(before), Some(after)) = (previous.links.get(index), current.links.get(index))
+            else {
+                continue;
+            };
+            let state = link.state_label(now, self.error_backoff, self.health_timeout);
+            let rtt = link
+                .last_rtt_ms
+                .map(|rtt| format!("{}ms", rtt))
+                .unwrap_or_else(|| "-".to_string());
+            let loss = stats::loss_percent(
+                after.pings_sent - before.pings_sent,
+                after.pongs_received - before.pongs_received,
+            )
+            .map(|loss| format!("{:.1}%", loss))
+            .unwrap_or_else(|| "-".to_string());
+            info!(
+                link = %link.name,
+                event = "link_stats",
+                rtt_ms = link.last_rtt_ms,
+                quality = link.quality.score(now),
+                rx_bytes = after.rx_bytes - before.rx_bytes,
+                tx_bytes = after.tx_bytes - before.tx_bytes,
+                "WireGuard {} {} in={} out={} rtt={} loss={}",
+                link.name,
+                state,
+                stats::format_rate(after.rx_bytes - before.rx_bytes, elapsed),
+                stats::format_rate(after.tx_bytes - before.tx_bytes, elapsed),
+                rtt,
+                loss
+            );
+        }
+        let dropped = current
+            .tun_rx_overflow
+            .saturating_sub(previous.tun_rx_overflow);
+        if dropped > 0 {
+            warn!(
+                event = "tun_overflow",
+                dropped,
+                queued = current.tun_queue_depth,
+                "WireGuard dropped {} TUN packets as the links fell behind ({} queued)",
+                dropped,
+                current.tun_queue_depth
+            );
+        }
+    }
+
+    /// Whether a datagram goes further than being counted. A `strict_source` link only
+    /// takes those from its peer once it knows one, and handshakes, which boringtun
+    /// checks cheaply and through which a peer that moved gets the link back.
+    fn admits(&self, packet: &NetPacket) -> bool {
+        let Some(link) = self.links.get(packet.link_index) else {
+            return true;
+        };
+        if !link.strict_source
+            || link.remote.is_none()
+            || link.is_remote(packet.src)
+            || throttle::is_handshake(&packet.data)
+        {
+            return true;
+        }
+        stats::add(&link.stats.rx_wrong_source, 1);
+        false
+    }
+
+    /// Counts a datagram read on link `index` as a sign of life, whoever sent it.
+    fn record_rx(&mut self, index: usize, now: Instant) {
+        if let Some(link) = self.links.get_mut(index) {
+            link.record_rx(now);
+        }
+    }
+
+    /// Moves link `index`'s return path to `src`. Only called for datagrams that
+    /// authenticated as the peer's, so a spoofed one cannot redirect the link.
+    fn roam(&mut self, index: usize, src: SocketAddr) {
+        if let Some(link) = self.links.get_mut(index) {
+            if link.remote != Some(src) {
+                debug!("WireGuard {} remote updated to {}", link.name, src);
+                events::emit(
+                    &self.events,
+                    TunnelEvent::EndpointChanged {
+                        link: link.name.clone(),
+                        endpoint: src.to_string(),
+                    },
+                );
+            }
+            link.remote = Some(src);
+        }
+    }
+
+    /// Flags links that have been silent for half the health timeout but are not down yet.
+    fn check_degraded(&mut self, now: Instant) {
+        let Some(timeout) = self.health_timeout else {
+            return;
+        };
+        for link in &mut self.links {
+            let Some(last_rx) = link.last_rx else {
+                continue;
+            };
+            let silent = now.duration_since(last_rx);
+            if link.degraded || link.down_since.is_some() || silent <= timeout / 2 {
+                continue;
+            }
+            link.degraded = true;
+            warn!(
+                link = %link.name,
+                event = "link_degraded",
+                "WireGuard {} degraded (no rx for {}ms)",
+                link.name,
+                silent.as_millis()
+            );
+            events::emit(
+                &self.events,
+                TunnelEvent::LinkDegraded {
+                    link: link.name.clone(),
+                    silent_ms: silent.as_millis() as u64,
+                },
+            );
+        }
+    }
+
+    /// Takes the pings each link left unanswered since the last round into its quality.
+    fn record_loss(&mut self) {
+        for link in &mut self.links {
+            link.quality.record_loss(
+                link.stats.pings_sent.load(Ordering::Relaxed),
+                link.stats.pongs_received.load(Ordering::Relaxed),
+            );
+        }
+    }
+
+    async fn send_health_pings(&mut self, epoch: Instant) -> VtrunkdResult<()> {
+        for index in 0..self.links.len() {
+            self.ping_link(index, epoch).await;
+        }
+        Ok(())
+    }
+
+    /// Pings link `index`, on the health check schedule or straight after a rebind.
+    async fn ping_link(&mut self, index: usize, epoch: Instant) {
+        let link = &self.links[index];
+        if !self.bond_control || !link.admin_state.carries_control() {
+            return;
+        }
+        let Some(remote) = link.remote else {
+            return;
+        };
+        let packet = build_control_packet(BOND_PING, epoch.elapsed().as_millis() as u64);
+        if self
+            .send_reply(index, remote, packet.to_vec())
+            .await
+            .is_ok()
+        {
+            let link = &mut self.links[index];
+            stats::add(&link.stats.control_tx, 1);
+            stats::add(&link.stats.pings_sent, 1);
+            link.record_ping(Instant::now());
+        }
+    }
+
+    /// Tells the peer on every link that this side is going away. Written straight to the
+    /// sockets rather than queued, so the goodbye leaves before the loop exits and the
+    /// senders with it.
+    async fn send_bye(&mut self, epoch: Instant) {
+        if !self.bond_control {
+            return;
+        }
+        let packet = build_control_packet(BOND_BYE, epoch.elapsed().as_millis() as u64);
+        for link in &self.links {
+            if !link.admin_state.carries_control() {
+                continue;
+            }
+            if let Some(remote) = link.remote {
+                if link.socket.send_to(&packet, remote).await.is_ok() {
+                    stats::add(&link.stats.control_tx, 1);
+                }
+            }
+        }
+    }
+
+    /// Tells the peer which optional features it may use towards this side. Sent inside
+    /// each new session, so a restarted peer learns about them and forgets old ones, and
+    /// nobody else can switch them.
+    async fn send_features(&mut self, tunnel: &mut Tunn, out_buf: &mut [u8]) -> VtrunkdResult<()> {
+        if !self.bond_control {
+            return Ok(());
+        }
+        let flags = if self.compression { FEATURE_LZ4 } else { 0 };
+        let lanes = self.lanes.count() as u8;
+        self.send_session_message(tunnel, Message::Features { flags, lanes }, out_buf)
+            .await
+    }
+
+    async fn handle_control_packet(
+        &mut self,
+        link_index: usize,
+        src: SocketAddr,
+        data: &[u8],
+        epoch: Instant,
+    ) -> VtrunkdResult<bool> {
+        let parsed = parse_control_packet(data).filter(|_| self.bond_control);
+        let (message_type, token) = match parsed {
+            Some(parsed) => parsed,
+            None => return Ok(false),
+        };
+
+        if let Some(link) = self.links.get(link_index) {
+            stats::add(&link.stats.control_rx, 1);
+        }
+
+        let now = Instant::now();
+        match message_type {
+            BOND_PING => {
+                let carries_control = self
+                    .links
+                    .get(link_index)
+                    .is_some_and(|link| link.admin_state.carries_control());
+                if carries_control {
+                    // Back to where the ping came from: control packets are not
+                    // authenticated, so they do not move the link's remote.
+                    let response = build_control_packet(BOND_PONG, token);
+                    if self
+                        .send_reply(link_index, src, response.to_vec())
+                        .await
+                        .is_ok()
+                    {
+                        stats::add(&self.links[link_index].stats.control_tx, 1);
+                    }
+                }
+            }
+            BOND_BYE => {
+                // Unauthenticated, so only the peer's address may take the link down.
+                match self.links.get_mut(link_index) {
+                    Some(link) if link.is_remote(src) => link.mark_down(now, "peer shut down"),
+                    Some(_) => debug!("Ignoring a bond goodbye from {}, not the peer", src),
+                    None => {}
+                }
+            }
+            BOND_PONG => {
+                if let Some(link) = self.links.get_mut(link_index) {
+                    stats::add(&link.stats.pongs_received, 1);
+                    let elapsed = epoch.elapsed().as_millis() as u64;
+                    if elapsed >= token {
+                        link.record_rtt(elapsed - token);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    /// The compressed form of a TUN packet, when both sides do LZ4 and it shrinks.
+    fn compress(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        if !self.compression || self.peer_features & FEATURE_LZ4 == 0 {
+            return None;
+        }
+        compress_counted(packet, &self.stats.tunnel)
+    }
+
+    /// Starts a new window of `handshake_rate_limit` once a second has passed.
+    fn reset_handshake_count(&mut self, now: Instant) {
+        if now.duration_since(self.handshake_count_reset) < HANDSHAKE_COUNT_WINDOW {
+            return;
+        }
+        self.handshake_count_reset = now;
+        self.rotation.trials = 0;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.reset_count();
+        }
+    }
+
+    /// Starts replacing this side's static key; the switch happens once the peer accepts.
+    async fn rotate_key(
+        &mut self,
+        tunnel: &mut Tunn,
+        out_buf: &mut [u8],
+        now: Instant,
+    ) -> Result<String, String> {
+        if !self.bond_control {
+            return Err("Key rotation needs a vtrunkd peer".to_string());
+        }
+        if self.rotation.state_file.is_none() {
+            return Err("Key rotation needs wireguard.state_file to keep the new keys".to_string());
+        }
+        if self.rotation.pending.is_some() {
+            return Err("A key rotation is already waiting for the peer".to_string());
+        }
+        if tunnel.time_since_last_handshake().is_none() {
+            return Err("No WireGuard session to announce the new key over".to_string());
+        }
+        let mut private_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut *private_key);
+        let public_key = public_key_of(&private_key);
+        self.rotation.pending = Some(rotate::Pending {
+            private_key,
+            public_key,
+            offered_at: now,
+            attempts: 1,
+        });
+        // Unanswered offers are repeated from the timer.
+        if let Err(e) = self
+            .send_session_message(tunnel, Message::Offer(public_key), out_buf)
+            .await
+        {
+            warn!("Failed to offer the new WireGuard key: {}", e);
+        }
+        let public_key = encode_key(&public_key);
+        info!(
+            "WireGuard offered the new public key {} to the peer",
+            public_key
+        );
+        Ok(format!("offered new public key {}", public_key))
+    }
+
+    /// Accepts the key the peer offered, as confirmed by the operator; the session switches
+    /// at the peer's next handshake.
+    async fn accept_key(
+        &mut self,
+        tunnel: &mut Tunn,
+        out_buf: &mut [u8],
+        public_key: &str,
+    ) -> Result<String, String> {
+        let key = decode_key("public key", public_key).map_err(|e| e.to_string())?;
+        if self.rotation.peer_offered != Some(key) {
+            return Err("The peer has not offered that key".to_string());
+        }
+        self.rotation.peer_offered = None;
+        self.rotation.peer_next = Some(key);
+        if let Err(e) = self
+            .send_session_message(tunnel, Message::Accept(key), out_buf)
+            .await
+        {
+            // The peer repeats its offer, which is answered then.
+            warn!("Failed to accept the new WireGuard key: {}", e);
+        }
+        let public_key = encode_key(&key);
+        info!(
+            "WireGuard accepted the peer's new public key {}; switching at its next handshake",
+            public_key
+        );
+        Ok(format!("accepted peer public key {}", public_key))
+    }
+
+    /// Repeats an unanswered key offer, and drops the new key when the peer never answers.
+    async fn resend_key_offer(
+        &mut self,
+        tunnel: &mut Tunn,
+        out_buf: &mut [u8],
+        now: Instant,
+    ) -> VtrunkdResult<()> {
+        match self.rotation.offer_due(now) {
+            OfferDue::Wait => Ok(()),
+            OfferDue::Resend(public_key) => {
+                self.send_session_message(tunnel, Message::Offer(public_key), out_buf)
+                    .await
+            }
+            OfferDue::Expired => {
+                warn!(
+                    "WireGuard peer never accepted the new key; keeping the current one \
+                     (does the peer set wireguard.state_file?)"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn send_session_message(
+        &mut self,
+        tunnel: &mut Tunn,
+        message: Message,
+        out_buf: &mut [u8],
+    ) -> VtrunkdResult<()> {
+        match tunnel.encapsulate(&message.encode(), out_buf) {
+            TunnResult::WriteToNetwork(packet) => self.send_packet(packet.to_vec()).await,
+            TunnResult::Err(e) => Err(VtrunkdError::Network(format!(
+                "WireGuard encapsulate error: {:?}",
+                e
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn handle_session_message(
+        &mut self,
+        tunnel: &mut Tunn,
+        message: Message,
+        out_buf: &mut [u8],
+    ) -> VtrunkdResult<()> {
+        match message {
+            Message::Offer(public_key) => {
+                if self.rotation.state_file.is_none() {
+                    warn!(
+                        "WireGuard peer offered a new key, which needs wireguard.state_file \
+                         to survive a restart; ignoring it"
+                    );
+                    return Ok(());
+                }
+                // Accepted already; the peer missed the answer.
+                if self.rotation.peer_next == Some(public_key) {
+                    return self
+                        .send_session_message(tunnel, Message::Accept(public_key), out_buf)
+                        .await;
+                }
+                if self.rotation.peer_offered != Some(public_key) {
+                    let peer_public_key = encode_key(&public_key);
+                    warn!(
+                        "WireGuard peer offered the new public key {}; run `vtrunkd accept-key {}` to accept it",
+                        peer_public_key, peer_public_key
+                    );
+                    self.rotation.peer_offered = Some(public_key);
+                    events::emit(&self.events, TunnelEvent::KeyOffered { peer_public_key });
+                }
+                Ok(())
+            }
+            Message::Features { flags, lanes } => {
+                let lz4 = flags & FEATURE_LZ4 != 0;
+                if self.compression && lz4 != (self.peer_features & FEATURE_LZ4 != 0) {
+                    info!(
+                        "WireGuard LZ4 compression {}",
+                        if lz4 {
+                            "enabled"
+                        } else {
+                            "not supported by the peer"
+                        }
+                    );
+                }
+                self.peer_features = flags;
+                if self.lanes.count() > 1 {
+                    let active = self.lanes.set_peer_lanes(lanes);
+                    info!("WireGuard spreading flows over {} crypto lanes", active);
+                }
+                Ok(())
+            }
+            Message::Accept(public_key) => {
+                let Some(pending) = self.rotation.pending.take() else {
+                    return Ok(());
+                };
+                if pending.public_key != public_key {
+                    self.rotation.pending = Some(pending);
+                    return Ok(());
+                }
+                // The peer keeps the old session until this one's handshake reaches it.
+                let rate_limiter =
+                    handshake_rate_limiter(&pending.private_key, self.handshake_rate_limit);
+                *tunnel = Tunn::new(
+                    StaticSecret::from(*pending.private_key),
+                    PublicKey::from(self.keys.public_key),
+                    self.keys.preshared_key,
+                    self.keys.persistent_keepalive,
+                    lanes::session_index(0),
+                    Some(Arc::clone(&rate_limiter)),
+                );
+                self.rate_limiter = Some(rate_limiter);
+                self.keys.private_key = *pending.private_key;
+                self.lanes.rekey(self.lane_keys());
+                self.key_rotated();
+                send_handshake(tunnel, self).await
+            }
+        }
+    }
+
+    /// Replaces the session when `packet` is the first handshake made with the key the
+    /// peer offered. Returns whether it was. Only handshake initiations are tried, and no
+    /// more of them a second than `handshake_rate_limit`.
+    async fn accept_rotated_peer(
+        &mut self,
+        tunnel: &mut Tunn,
+        packet: &NetPacket,
+        out_buf: &mut [u8],
+    ) -> VtrunkdResult<bool> {
+        let Some(peer_next) = self.rotation.peer_next else {
+            return Ok(false);
+        };
+        if !rotate::is_handshake_initiation(&packet.data)
+            || self.rotation.trials >= self.handshake_rate_limit
+        {
+            return Ok(false);
+        }
+        self.rotation.trials += 1;
+        let mut next = Tunn::new(
+            StaticSecret::from(self.keys.private_key),
+            PublicKey::from(peer_next),
+            self.keys.preshared_key,
+            self.keys.persistent_keepalive,
+            lanes::session_index(0),
+            self.rate_limiter.clone(),
+        );
+        match next.decapsulate(Some(packet.src.ip()), &packet.data, out_buf) {
+            TunnResult::WriteToNetwork(response)
+                if wg_packet_type(response) != Some(WG_COOKIE_REPLY) =>
+            {
+                self.roam(packet.link_index, packet.src);
+                self.send_packet(response.to_vec()).await?
+            }
+            _ => return Ok(false),
+        }
+        *tunnel = next;
+        self.keys.public_key = peer_next;
+        self.rotation.peer_next = None;
+        self.lanes.rekey(self.lane_keys());
+        self.key_rotated();
+        Ok(true)
+    }
+
+    /// Saves the keys now in use, which a restart must keep, and announces them.
+    fn key_rotated(&mut self) {
+        self.rotation.rotated = true;
+        let public_key = encode_key(&public_key_of(&self.keys.private_key));
+        let peer_public_key = encode_key(&self.keys.public_key);
+        info!(
+            event = "key_rotated",
+            "WireGuard switched keys: public key {}, peer {}", public_key, peer_public_key
+        );
+        if let Some(path) = &self.rotation.state_file {
+            if let Err(e) = state::save(path, &self.session_state()) {
+                error!("Failed to save the rotated keys to {:?}: {}", path, e);
+            }
+        }
+        events::emit(
+            &self.events,
+            TunnelEvent::KeyRotated {
+                public_key,
+                peer_public_key,
+            },
+        );
+    }
+
+    async fn send_packet(&mut self, packet: Vec<u8>) -> VtrunkdResult<()> {
+        let packet_type = wg_packet_type(&packet);
+        let is_keepalive = packet_type == Some(4) && packet.len() == WG_KEEPALIVE_LEN;
+        match packet_type {
+            Some(1..=3) => self.send_all(packet, false).await?,
+            Some(4) if is_keepalive => self.send_all(packet, false).await?,
+            _ => match self.mode {
+                BondingMode::Aggregate => self.send_round_robin(packet).await?,
+                BondingMode::Redundant => self.send_all(packet, true).await?,
+                BondingMode::Failover => self.send_failover(packet).await?,
+            },
+        }
+        Ok(())
+    }
+
+    /// Sends a run of packets, batching data packets per link in aggregate mode; leaves
+    /// `packets` empty.
+    async fn send_packets(&mut self, packets: &mut Vec<Vec<u8>>) -> VtrunkdResult<()> {
+        if packets.len() < 2 || self.mode != BondingMode::Aggregate {
+            for packet in packets.drain(..) {
+                self.send_packet(packet).await?;
+            }
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut per_link: Vec<Vec<Vec<u8>>> = vec![Vec::new(); self.links.len()];
+        for packet in packets.drain(..) {
+            if !is_bulk_data(&packet) {
+                self.send_packet(packet).await?;
+                continue;
+            }
+            match self.next_weighted_index(now) {
+                Some(index) if self.links[index].remote.is_some() => per_link[index].push(packet),
+                _ => self.send_round_robin(packet).await?,
+            }
+        }
+
+        for (index, packets) in per_link.into_iter().enumerate() {
+            if packets.is_empty() {
+                continue;
+            }
+            // Whatever the link did not take goes out one by one on the others.
+            for packet in self.send_batch_to_link(index, packets).await {
+                self.send_round_robin(packet).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues what fits of `packets` on link `index`, returning the rest.
+    async fn send_batch_to_link(&mut self, index: usize, packets: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let Some(remote) = self.links[index].remote else {
+            return packets;
+        };
+        let mut packets = packets.into_iter();
+        while let Some(packet) = packets.next() {
+            if let Err(packet) = self.send_reply(index, remote, packet).await {
+                return std::iter::once(packet).chain(packets).collect();
+            }
+        }
+        Vec::new()
+    }
+
+    async fn send_all(&mut self, packet: Vec<u8>, is_data: bool) -> VtrunkdResult<()> {
+        let mut sent = 0usize;
+        for index in 0..self.links.len() {
+            let admin_state = self.links[index].admin_state;
+            let allowed = if is_data {
+                admin_state.carries_data()
+            } else {
+                admin_state.carries_control()
+            };
+            if allowed && self.send_to_link(index, packet.clone()).await.is_ok() {
+                sent += 1;
+            }
+        }
+
+        if sent == 0 {
+            stats::add(&self.stats.tunnel.dropped_no_link, 1);
+            warn!("WireGuard has no remote endpoints to send to");
+        }
+        Ok(())
+    }
+
+    async fn send_round_robin(&mut self, mut packet: Vec<u8>) -> VtrunkdResult<()> {
+        let now = Instant::now();
+        let len = self.links.len();
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut attempts = 0usize;
+        while attempts < len {
+            let index = match self.next_weighted_index(now) {
+                Some(index) => index,
+                None => break,
+            };
+            packet = match self.send_to_link(index, packet).await {
+                Ok(()) => return Ok(()),
+                Err(packet) => packet,
+            };
+            attempts += 1;
+        }
+
+        if self.send_any(packet).await.is_err() {
+            stats::add(&self.stats.tunnel.dropped_no_link, 1);
+            warn!("WireGuard has no remote endpoints to send to");
+        }
+        Ok(())
+    }
+
+    async fn send_failover(&mut self, mut packet: Vec<u8>) -> VtrunkdResult<()> {
+        let now = Instant::now();
+        if let Some(index) = self.best_failover_index(now) {
+            self.record_failover_choice(index);
+            packet = match self.send_to_link(index, packet).await {
+                Ok(()) => return Ok(()),
+                Err(packet) => packet,
+            };
+        }
+
+        if self.send_any(packet).await.is_err() {
+            stats::add(&self.stats.tunnel.dropped_no_link, 1);
+            warn!("WireGuard has no remote endpoints to send to");
+        }
+        Ok(())
+    }
+
+    fn next_weighted_index(&mut self, now: Instant) -> Option<usize> {
+        if self.links.is_empty() {
+            return None;
+        }
+
+        let len = self.links.len();
+        let mut attempts = 0usize;
+        while attempts < len {
+            let index = self.next_index % len;
+            let link = &mut self.links[index];
+            if link.weight == 0 || !link.is_available(now, self.error_backoff, self.health_timeout)
+            {
+                self.advance_cursor(len);
+                attempts += 1;
+                continue;
+            }
+
+            if self.remaining_weight == 0 {
+                self.remaining_weight = link.weight;
+            }
+
+            if self.remaining_weight > 0 {
+                self.remaining_weight -= 1;
+                if self.remaining_weight == 0 {
+                    self.advance_cursor(len);
+                }
+                return Some(index);
+            }
+
+            self.advance_cursor(len);
+            attempts += 1;
+        }
+
+        None
+    }
+
+    fn best_failover_index(&mut self, now: Instant) -> Option<usize> {
+        if self.failover_by_quality {
+            return self.best_quality_index(now);
+        }
+        let mut best: Option<(usize, u32)> = None;
+        for (index, link) in self.links.iter_mut().enumerate() {
+            if !link.is_available(now, self.error_backoff, self.health_timeout) {
+                continue;
+            }
+            let weight = link.weight;
+            match best {
+                Some((_, best_weight)) if best_weight >= weight => {}
+                _ => best = Some((index, weight)),
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+
+    /// The available link with the best quality score, weight breaking ties; links not
+    /// scored yet come last. The current link is kept unless another scores
+    /// `QUALITY_HYSTERESIS` points better, so two close links do not take turns.
+    fn best_quality_index(&mut self, now: Instant) -> Option<usize> {
+        let mut best: Option<(usize, (Option<u64>, u32))> = None;
+        let mut current = None;
+        for (index, link) in self.links.iter_mut().enumerate() {
+            if !link.is_available(now, self.error_backoff, self.health_timeout) {
+                continue;
+            }
+            let rank = (link.quality.score(now), link.weight);
+            if self.failover_index == Some(index) {
+                current = Some((index, rank));
+            }
+            match best {
+                Some((_, best_rank)) if best_rank >= rank => {}
+                _ => best = Some((index, rank)),
+            }
+        }
+        match (current, best) {
+            (Some((index, (Some(score), _))), Some((_, (Some(best_score), _))))
+                if best_score < score + QUALITY_HYSTERESIS =>
+            {
+                Some(index)
+            }
+            _ => best.map(|(index, _)| index),
+        }
+    }
+
+    fn record_failover_choice(&mut self, index: usize) {
+        if let Some(previous) = self.failover_index {
+            if previous != index {
+                stats::add(&self.stats.tunnel.failovers, 1);
+                info!(
+                    link = %self.links[index].name,
+                    event = "failover",
+                    "WireGuard failover from {} to {}",
+                    self.links[previous].name,
+                    self.links[index].name
+                );
+                events::emit(
+                    &self.events,
+                    TunnelEvent::Failover {
+                        from: self.links[previous].name.clone(),
+                        to: self.links[index].name.clone(),
+                    },
+                );
+            }
+        }
+        self.failover_index = Some(index);
+    }
+
+    async fn send_any(&mut self, mut packet: Vec<u8>) -> Result<(), Vec<u8>> {
+        for index in 0..self.links.len() {
+            if !self.links[index].admin_state.carries_data() {
+                continue;
+            }
+            packet = match self.send_to_link(index, packet).await {
+                Ok(()) => return Ok(()),
+                Err(packet) => packet,
+            };
+        }
+        Err(packet)
+    }
+
+    async fn send_to_link(&mut self, index: usize, packet: Vec<u8>) -> Result<(), Vec<u8>> {
+        let remote = match self.links[index].remote {
+            Some(remote) => remote,
+            None => return Err(packet),
+        };
+        self.send_reply(index, remote, packet).await
+    }
+
+    /// Queues `packet` on the link at `index` for `remote`, whatever the link's peer;
+    /// hands it back when the link's send queue is full.
+    async fn send_reply(
+        &mut self,
+        index: usize,
+        remote: SocketAddr,
+        packet: Vec<u8>,
+    ) -> Result<(), Vec<u8>> {
+        let tos = self.link_tos(index);
+        // The queue takes the datagram itself, so a capture needs a copy of its own.
+        let captured = self.capture.is_some().then(|| packet.clone());
+        self.links[index].sender.send(remote, packet, tos)?;
+        if let Some(packet) = captured {
+            self.capture_link(index, remote, true, &packet);
+        }
+        Ok(())
+    }
+
+    fn advance_cursor(&mut self, len: usize) {
+        self.next_index = (self.next_index + 1) % len;
+        self.remaining_weight = 0;
+    }
+}
+
+/// A transport data packet that is not a keepalive, i.e. one the bonding mode schedules.
+/// The compressed form of a TUN packet when it shrinks, counted in the tunnel stats.
+fn compress_counted(packet: &[u8], tunnel: &TunnelCounters) -> Option<Vec<u8>> {
+    let compressed = compress::compress(packet)?;
+    stats::add(&tunnel.compressed_packets, 1);
+    stats::add(
+        &tunnel.compression_saved_bytes,
+        (packet.len() - compressed.len()) as u64,
+    );
+    Some(compressed)
+}
+
+fn is_bulk_data(packet: &[u8]) -> bool {
+    wg_packet_type(packet) == Some(4) && packet.len() != WG_KEEPALIVE_LEN
+}
+
+/// DSCP of a plaintext IPv4 or IPv6 packet read from the TUN device.
+fn inner_dscp(packet: &[u8]) -> u8 {
+    match packet {
+        [first, second, ..] if first >> 4 == 4 => second >> 2,
+        [first, second, ..] if first >> 4 == 6 => ((first & 0x0f) << 2) | (second >> 6),
+        _ => 0,
+    }
+}
+
+fn wg_packet_type(packet: &[u8]) -> Option<u32> {
+    if packet.len() < 4 {
+        return None;
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&packet[..4]);
+    Some(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    #[test]
+    fn decode_key_rejects_wrong_length() {
+        let result = decode_key("test", "AAAA");
+        assert!(matches!(result, Err(VtrunkdError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn decode_key_rejects_invalid_base64() {
+        let result = decode_key("test", "!!!");
+        assert!(matches!(
+            result,
+            Err(VtrunkdError::InvalidConfig(msg)) if msg.contains("Invalid base64")
+        ));
+    }
+
+    #[test]
+    fn wg_packet_type_reads_le() {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&3u32.to_le_bytes());
+        packet.extend_from_slice(&[0u8; 8]);
+        assert_eq!(wg_packet_type(&packet), Some(3));
+    }
+
+    #[test]
+    fn parse_bind_addr_accepts_ip_only() {
+        let addr = parse_bind_addr("127.0.0.1").expect("parse bind addr");
+        let expected = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        assert_eq!(addr, expected);
+    }
+
+    #[test]
+    fn default_bind_addr_prefers_ipv6_for_ipv6_remote() {
+        let remote = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 51820);
+        let bind_addr = default_bind_addr(Some(remote));
+        let expected = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0);
+        assert_eq!(bind_addr, expected);
+    }
+
+    #[tokio::test]
+    async fn link_marks_down_after_missed_pong() {
+        let now = Instant::now();
+        let last_ping = now
+            .checked_sub(Duration::from_secs(10))
+            .expect("instant subtraction");
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let stats = Arc::new(LinkCounters::default());
+        let mut link = Link {
+            name: "link-0".to_string(),
+            sender: LinkSender::spawn(
+                Arc::clone(&socket),
+                Arc::clone(&stats),
+                Arc::default(),
+                8,
+                8,
+                0,
+            ),
+            socket,
+            remote: Some(peer_addr()),
+            weight: 1,
+            admin_state: LinkAdminState::Up,
+            down_since: None,
+            last_rx: None,
+            last_ping_sent: Some(last_ping),
+            last_rtt_ms: None,
+            quality: Quality::default(),
+            degraded: false,
+            dscp: 0,
+            stats,
+            events: events::channel(),
+            receivers: Vec::new(),
+            device: None,
+            device_index: None,
+            strict_source: false,
+        };
+
+        let available =
+            link.is_available(now, Duration::from_secs(1), Some(Duration::from_secs(3)));
+        assert!(!available);
+        assert!(link.down_since.is_some());
+    }
+
+    fn peer_addr() -> SocketAddr {
+        "127.0.0.1:12345".parse().unwrap()
+    }
+
+    fn test_link(name: &str, socket: &Arc<UdpSocket>, weight: u32) -> Link {
+        let stats = Arc::new(LinkCounters::default());
+        Link {
+            name: name.to_string(),
+            socket: Arc::clone(socket),
+            sender: LinkSender::spawn(
+                Arc::clone(socket),
+                Arc::clone(&stats),
+                Arc::default(),
+                64,
+                8,
+                0,
+            ),
+            remote: Some(peer_addr()),
+            weight,
+            admin_state: LinkAdminState::Up,
+            down_since: None,
+            last_rx: None,
+            last_ping_sent: None,
+            last_rtt_ms: None,
+            quality: Quality::default(),
+            degraded: false,
+            dscp: 0,
+            stats,
+            events: events::channel(),
+            receivers: Vec::new(),
+            device: None,
+            device_index: None,
+            strict_source: false,
+        }
+    }
+
+    fn test_manager(links: Vec<Link>, mode: BondingMode) -> LinkManager {
+        LinkManager {
+            links,
+            mode,
+            error_backoff: Duration::from_secs(1),
+            health_timeout: None,
+            next_index: 0,
+            remaining_weight: 0,
+            failover_index: None,
+            failover_by_quality: false,
+            bond_control: true,
+            clamp_mss: None,
+            icmp: None,
+            unsupported: UnsupportedPolicy::default(),
+            unsupported_logged: None,
+            links_down: LinksDownPolicy::default(),
+            outage: None,
+            send_limit: None,
+            receive_limit: None,
+            copy_dscp: false,
+            ecn: false,
+            tos: 0,
+            compression: false,
+            peer_features: 0,
+            reorder: None,
+            keys: uapi::Status::default(),
+            rotation: KeyRotation::default(),
+            handshake_rate_limit: DEFAULT_HANDSHAKE_RATE_LIMIT,
+            rate_limiter: None,
+            handshake_count_reset: Instant::now(),
+            stats: Arc::new(StatsRegistry::new(&Config::default())),
+            events: events::channel(),
+            capture_dir: control::capture_dir(None),
+            capture: None,
+            receivers: JoinSet::new(),
+            net_tx: None,
+            pool: Arc::default(),
+            spare: Vec::new(),
+            tun_batch: Vec::new(),
+            tun_batching: false,
+            lanes: Lanes::default(),
+        }
+    }
+
+    /// Links on one throwaway socket with the given weights and admin states.
+    fn scheduler(links: &[(u32, LinkAdminState)], mode: BondingMode) -> LinkManager {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let socket = Arc::new(UdpSocket::from_std(socket).unwrap());
+        let links = links
+            .iter()
+            .enumerate()
+            .map(|(index, (weight, state))| {
+                let mut link = test_link(&format!("link-{}", index), &socket, *weight);
+                link.admin_state = *state;
+                link
+            })
+            .collect();
+        test_manager(links, mode)
+    }
+
+    fn admin_state() -> impl Strategy<Value = LinkAdminState> {
+        prop_oneof![
+            Just(LinkAdminState::Up),
+            Just(LinkAdminState::Down),
+            Just(LinkAdminState::Drain),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn weighted_round_robin_follows_weights(
+            weights in prop::collection::vec(0u32..6, 1..6),
+            rounds in 1u32..4,
+        ) {
+            let total: u32 = weights.iter().sum();
+            prop_assume!(total > 0);
+            let links: Vec<_> = weights.iter().map(|w| (*w, LinkAdminState::Up)).collect();
+            let mut manager = scheduler(&links, BondingMode::Aggregate);
+            let now = Instant::now();
+
+            let mut picks = vec![0u32; weights.len()];
+            for _ in 0..total * rounds {
+                picks[manager.next_weighted_index(now).unwrap()] += 1;
+            }
+            let expected: Vec<u32> = weights.iter().map(|w| w * rounds).collect();
+            prop_assert_eq!(picks, expected);
+        }
+
+        #[test]
+        fn scheduler_only_picks_links_that_carry_data(
+            links in prop::collection::vec((0u32..4, admin_state()), 1..6),
+        ) {
+            let mut manager = scheduler(&links, BondingMode::Aggregate);
+            let now = Instant::now();
+            let usable = |index: usize| links[index].1 == LinkAdminState::Up;
+
+            let any_weighted = (0..links.len()).any(|i| usable(i) && links[i].0 > 0);
+            for _ in 0..16 {
+                match manager.next_weighted_index(now) {
+                    Some(index) => prop_assert!(usable(index) && links[index].0 > 0),
+                    None => prop_assert!(!any_weighted),
+                }
+            }
+
+            let best_weight = (0..links.len()).filter(|i| usable(*i)).map(|i| links[i].0).max();
+            match manager.best_failover_index(now) {
+                Some(index) => {
+                    prop_assert!(usable(index));
+                    prop_assert_eq!(Some(links[index].0), best_weight);
+                }
+                None => prop_assert!(best_weight.is_none()),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn copied_dscp_marks_each_queued_datagram() {
+        // EF (46) in an IPv4 header and AF41 (34) in an IPv6 one.
+        assert_eq!(inner_dscp(&[0x45, 0xb8, 0, 20]), 46);
+        assert_eq!(inner_dscp(&[0x68, 0x80, 0, 0]), 34);
+        assert_eq!(inner_dscp(&[]), 0);
+
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        receive_tos(&peer).unwrap();
+        let mut link = test_link("lte", &socket, 1);
+        link.remote = Some(peer.local_addr().unwrap());
+        link.dscp = 10;
+        let mut links = test_manager(vec![link], BondingMode::Aggregate);
+
+        // Queued back to back: each keeps the marking it was queued with, ECN riding along
+        // with either DSCP.
+        links.ecn = true;
+        let ect0_ipv4 = [0x45, 0x02, 0, 20];
+        let outer = links.outer_tos(&ect0_ipv4);
+        assert_eq!(outer, ecn::ECT_0);
+        for (tos, packet) in [(46 << 2, b"ef"), (0, b"af"), (outer, b"ec")] {
+            links.mark_tos(tos);
+            assert!(links.send_to_link(0, packet.to_vec()).await.is_ok());
+        }
+        let mut received = Vec::new();
+        let mut batch = RecvBatch::new(4, 64);
+        while received.len() < 3 {
+            tokio::time::timeout(Duration::from_secs(1), batch.recv(&peer))
+                .await
+                .unwrap()
+                .unwrap();
+            received.extend(batch.packets().map(|(data, _, tos)| (data.to_vec(), tos)));
+        }
+        assert_eq!(
+            received,
+            [
+                (b"ef".to_vec(), 46 << 2),
+                (b"af".to_vec(), 10 << 2),
+                (b"ec".to_vec(), (10 << 2) | 0b10),
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn link_ttl_sets_the_outer_hop_limit() {
+        let mut wg_config = bench::bench_config(2, |_| None);
+        wg_config.links[1].ttl = Some(128);
+        let (links, _rx) = bench::bench_links(
+            &wg_config,
+            &Config::default().network,
+            BondingMode::Aggregate,
+            None,
+        )
+        .await
+        .unwrap();
+        let ttl = |index: usize| {
+            getsockopt(links.links[index].socket.as_raw_fd(), sockopt::Ipv4Ttl).unwrap()
+        };
+        assert_eq!(ttl(1), 128);
+    }
+
+    #[tokio::test]
+    async fn peer_bye_marks_link_down() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(
+            vec![test_link("wifi", &socket, 1), test_link("lte", &socket, 1)],
+            BondingMode::Failover,
+        );
+        let bye = build_control_packet(BOND_BYE, 0);
+        let handled = links
+            .handle_control_packet(0, peer_addr(), &bye, Instant::now())
+            .await
+            .unwrap();
+
+        assert!(handled);
+        assert!(links.links[0].down_since.is_some());
+        assert_eq!(links.best_failover_index(Instant::now()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn bye_from_a_foreign_address_is_ignored() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wifi", &socket, 1)], BondingMode::Failover);
+        let bye = build_control_packet(BOND_BYE, 0);
+        let stranger: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+        assert!(links
+            .handle_control_packet(0, stranger, &bye, Instant::now())
+            .await
+            .unwrap());
+        assert!(links.links[0].down_since.is_none());
+
+        links.links[0].remote = None;
+        links
+            .handle_control_packet(0, peer_addr(), &bye, Instant::now())
+            .await
+            .unwrap();
+        assert!(links.links[0].down_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn saved_state_fills_in_unknown_endpoints() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(
+            vec![test_link("wifi", &socket, 1), test_link("lte", &socket, 1)],
+            BondingMode::Aggregate,
+        );
+        let configured = links.links[0].remote;
+        links.links[1].remote = None;
+        let saved_remote: SocketAddr = "203.0.113.7:51820".parse().unwrap();
+        let mut saved = links.session_state();
+        for link in &mut saved.links {
+            link.remote = Some(saved_remote);
+            link.rtt_ms = Some(35);
+        }
+
+        links.restore_state(&saved, SystemTime::now() + state::MAX_AGE * 2);
+        assert_eq!(links.links[1].remote, None);
+        links.restore_state(&saved, SystemTime::now());
+        assert_eq!(links.links[0].remote, configured);
+        assert_eq!(links.links[1].remote, Some(saved_remote));
+        assert_eq!(links.links[1].last_rtt_ms, Some(35));
+    }
+
+    #[tokio::test]
+    async fn compression_waits_for_the_peer_to_announce_it() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("dsl", &socket, 1)], BondingMode::Failover);
+        links.compression = true;
+        let mut text = vec![0x45, 0, 0x05, 0xdc];
+        text.resize(1500, b'a');
+        assert_eq!(links.compress(&text), None);
+
+        let (mut peer, mut tunnel) = bench::tunnel_pair();
+        bench::handshake(&mut peer, &mut tunnel).unwrap();
+        let device = bench::MemoryDevice::default();
+        let mut out_buf = vec![0u8; 2048];
+        let now = Instant::now();
+        let receive = |data: Vec<u8>| NetPacket {
+            link_index: 0,
+            src: peer_addr(),
+            ecn: ecn::NOT_ECT,
+            data,
+        };
+        let seal = |peer: &mut Tunn, packet: &[u8]| {
+            let mut buf = vec![0u8; 2048];
+            match peer.encapsulate(packet, &mut buf) {
+                TunnResult::WriteToNetwork(datagram) => datagram.to_vec(),
+                _ => panic!("session is up"),
+            }
+        };
+
+        // The old plaintext announcement is anyone's to forge, so it no longer counts.
+        assert!(links
+            .handle_control_packet(0, peer_addr(), &build_control_packet(4, FEATURE_LZ4), now)
+            .await
+            .unwrap());
+        assert_eq!(links.compress(&text), None);
+        let announce = seal(
+            &mut peer,
+            &Message::Features {
+                flags: FEATURE_LZ4,
+                lanes: 1,
+            }
+            .encode(),
+        );
+        handle_incoming(
+            &mut tunnel,
+            &device,
+            &mut links,
+            &mut out_buf,
+            now,
+            receive(announce),
+        )
+        .await
+        .unwrap();
+        assert_eq!(device.packets.load(Ordering::Relaxed), 0);
+        let compressed = links.compress(&text).expect("peer accepts LZ4");
+        assert!(compress::is_compressed(&compressed));
+        assert_eq!(links.stats.snapshot().compressed_packets, 1);
+        // The same shape read from the TUN device would be unwrapped by the peer.
+        assert!(!links.accept_inner(&compressed, now));
+        assert_eq!(links.stats.snapshot().tun_rx_reserved, 1);
+
+        // A peer restarted without compression says so with its next session.
+        let announce = seal(
+            &mut peer,
+            &Message::Features { flags: 0, lanes: 1 }.encode(),
+        );
+        handle_incoming(
+            &mut tunnel,
+            &device,
+            &mut links,
+            &mut out_buf,
+            now,
+            receive(announce),
+        )
+        .await
+        .unwrap();
+        assert_eq!(links.compress(&text), None);
+
+        // To a stock WireGuard peer, protocol 253 is just traffic, both ways.
+        links.bond_control = false;
+        assert!(links.accept_inner(&compressed, now));
+        let datagram = seal(&mut peer, &compressed);
+        handle_incoming(
+            &mut tunnel,
+            &device,
+            &mut links,
+            &mut out_buf,
+            now,
+            receive(datagram),
+        )
+        .await
+        .unwrap();
+        assert_eq!(device.packets.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            device.bytes.load(Ordering::Relaxed),
+            compressed.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn a_round_of_datagrams_reaches_the_tun_device_in_one_batch() {
+        #[derive(Default)]
+        struct BatchDevice {
+            batches: std::sync::Mutex<Vec<usize>>,
+        }
+
+        impl TunnelWriter for BatchDevice {
+            fn write_packet<'a>(
+                &'a self,
+                _data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                panic!("written outside a batch");
+            }
+
+            fn write_packets<'a>(
+                &'a self,
+                packets: &'a [Vec<u8>],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                self.batches.lock().unwrap().push(packets.len());
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Failover);
+        let (mut peer, mut tunnel) = bench::tunnel_pair();
+        bench::handshake(&mut peer, &mut tunnel).unwrap();
+        let device = BatchDevice::default();
+        let mut out_buf = vec![0u8; 2048];
+        let mut inner = vec![0x45, 0, 0, 100];
+        inner.resize(100, 0);
+
+        links.batch_tun();
+        for _ in 0..3 {
+            let mut buf = vec![0u8; 2048];
+            let TunnResult::WriteToNetwork(datagram) = peer.encapsulate(&inner, &mut buf) else {
+                panic!("session is up");
+            };
+            let packet = NetPacket {
+                link_index: 0,
+                src: peer_addr(),
+                ecn: ecn::NOT_ECT,
+                data: datagram.to_vec(),
+            };
+            handle_incoming(
+                &mut tunnel,
+                &device,
+                &mut links,
+                &mut out_buf,
+                Instant::now(),
+                packet,
+            )
+            .await
+            .unwrap();
+        }
+        assert!(device.batches.lock().unwrap().is_empty());
+        links.flush_tun(&device).await.unwrap();
+        assert_eq!(*device.batches.lock().unwrap(), [3]);
+        assert_eq!(links.stats.snapshot().tun_tx_packets, 3);
+
+        // Their buffers are encrypted into next.
+        assert_eq!(links.spare.len(), 3);
+        let datagram = links.encapsulate(&mut tunnel, &inner).unwrap().unwrap();
+        assert_eq!(datagram.len(), inner.len() + 32);
+        assert_eq!(links.spare.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn datagrams_from_outside_allowed_sources_are_dropped() {
+        let mut wg_config = bench::bench_config(2, |_| None);
+        wg_config.links[0].allowed_sources = Some(vec!["192.0.2.0/24".to_string()]);
+        wg_config.links[1].allowed_sources = Some(vec!["127.0.0.0/8".to_string()]);
+        let network = Config::default().network;
+        let (links, mut rx) =
+            bench::bench_links(&wg_config, &network, BondingMode::Aggregate, None)
+                .await
+                .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for link in &links.links {
+            let addr = link.socket.local_addr().unwrap();
+            sender.send_to(&[1u8; 64], addr).await.unwrap();
+        }
+
+        let packet = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.link_index, 1);
+        assert!(tokio::time::timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .is_err());
+        let snapshot = links.stats.snapshot();
+        assert_eq!(snapshot.links[0].rx_dropped, 1);
+        assert_eq!(snapshot.links[0].rx_packets, 0);
+        assert_eq!(snapshot.links[1].rx_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn rebound_link_receives_on_its_new_socket_only() {
+        let config = Config {
+            wireguard: bench::bench_config(2, |_| None),
+            ..Config::default()
+        };
+        let (mut links, mut rx) = bench::bench_links(
+            &config.wireguard,
+            &config.network,
+            BondingMode::Aggregate,
+            None,
+        )
+        .await
+        .unwrap();
+        let old = links.links[1].socket.local_addr().unwrap();
+        let reply = links
+            .rebind(&config, "bench-1", Instant::now())
+            .await
+            .unwrap();
+        let new = links.links[1].socket.local_addr().unwrap();
+        assert_ne!(old, new);
+        assert!(reply.contains(&new.to_string()), "{}", reply);
+        assert!(links.rebind(&config, "wifi", Instant::now()).await.is_err());
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(&[1u8; 64], new).await.unwrap();
+        let packet = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.link_index, 1);
+        // The old socket is closed once its receiver stops.
+        tokio::task::yield_now().await;
+        sender.send_to(&[1u8; 64], old).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn receive_sockets_share_the_link_port() {
+        let mut wg_config = bench::bench_config(1, |_| None);
+        wg_config.links[0].receive_sockets = Some(4);
+        let (links, mut rx) = bench::bench_links(
+            &wg_config,
+            &Config::default().network,
+            BondingMode::Aggregate,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(links.links[0].receivers.len(), 4);
+        let local = links.links[0].socket.local_addr().unwrap();
+
+        // Whichever socket the kernel picks for a source, its datagrams reach the link.
+        let mut sources = Vec::new();
+        for _ in 0..16 {
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.send_to(&[1u8; 64], local).await.unwrap();
+            sources.push(sender.local_addr().unwrap());
+        }
+        for _ in 0..16 {
+            let packet = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(packet.link_index, 0);
+            sources.retain(|source| *source != packet.src);
+        }
+        assert!(sources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn link_follows_its_device_to_a_new_address() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = peer.local_addr().unwrap().to_string();
+        let mut config = Config {
+            wireguard: bench::bench_config(2, |_| Some(endpoint.clone())),
+            ..Config::default()
+        };
+        // Link 1 held 127.0.0.2 on the loopback device, which has since lost it.
+        config.wireguard.links[1].bind = Some("127.0.0.2:0".to_string());
+        let (mut links, _rx) = bench::bench_links(
+            &config.wireguard,
+            &config.network,
+            BondingMode::Aggregate,
+            None,
+        )
+        .await
+        .unwrap();
+        let Some(device) = links.links[0].device.clone() else {
+            // No loopback device in this sandbox.
+            return;
+        };
+        links.links[1].device = Some(device.clone());
+        links.links[1].down_since = Some(Instant::now());
+        let untouched = links.links[0].socket.local_addr().unwrap();
+
+        links.device_changed(&config, "eth9", Instant::now()).await;
+        assert_eq!(
+            links.links[1].socket.local_addr().unwrap().ip(),
+            IpAddr::from([127, 0, 0, 2])
+        );
+
+        links.device_changed(&config, &device, Instant::now()).await;
+        let rebound = links.links[1].socket.local_addr().unwrap();
+        assert_eq!(rebound.ip(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(links.links[0].socket.local_addr().unwrap(), untouched);
+        // The peer is pinged from the new address at once, which recovers the link.
+        let mut buf = [0u8; 64];
+        let (size, src) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(src, rebound);
+        assert_eq!(parse_control_packet(&buf[..size]).unwrap().0, BOND_PING);
+        links.links[1].apply_send_outcome();
+        assert!(links.links[1].down_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn key_rotation_switches_both_sides_once_the_peer_accepts() {
+        /// Hands the next datagram on `socket` to `links`; false once nothing arrives.
+        async fn deliver(
+            socket: &UdpSocket,
+            tunnel: &mut Tunn,
+            links: &mut LinkManager,
+            device: &impl TunnelWriter,
+        ) -> bool {
+            let mut buf = vec![0u8; 2048];
+            let received =
+                tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf)).await;
+            let Ok(Ok((size, src))) = received else {
+                return false;
+            };
+            let packet = NetPacket {
+                link_index: 0,
+                src,
+                ecn: ecn::NOT_ECT,
+                data: buf[..size].to_vec(),
+            };
+            let mut out_buf = vec![0u8; 2048];
+            handle_incoming(tunnel, device, links, &mut out_buf, Instant::now(), packet)
+                .await
+                .unwrap();
+            true
+        }
+
+        let dir = std::env::temp_dir().join(format!("vtrunkd-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let client_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut peers = Vec::new();
+        for (socket, remote, name) in [
+            (&client_socket, &server_socket, "client"),
+            (&server_socket, &client_socket, "server"),
+        ] {
+            let mut link = test_link("wan", socket, 1);
+            link.remote = Some(remote.local_addr().unwrap());
+            let mut links = test_manager(vec![link], BondingMode::Aggregate);
+            links.keys.private_key = rand::random::<[u8; 32]>();
+            links.rotation.state_file = Some(dir.join(format!("{}.state", name)));
+            peers.push(links);
+        }
+        let (mut server_links, mut client_links) = (peers.pop().unwrap(), peers.pop().unwrap());
+        client_links.keys.public_key = public_key_of(&server_links.keys.private_key);
+        server_links.keys.public_key = public_key_of(&client_links.keys.private_key);
+        let session = |links: &LinkManager| {
+            Tunn::new(
+                StaticSecret::from(links.keys.private_key),
+                PublicKey::from(links.keys.public_key),
+                None,
+                None,
+                rand::random::<u32>(),
+                None,
+            )
+        };
+        let (mut client, mut server) = (session(&client_links), session(&server_links));
+        bench::handshake(&mut client, &mut server).unwrap();
+        let device = bench::MemoryDevice::default();
+        let mut out_buf = vec![0u8; 2048];
+
+        // Offer, accept, the new handshake and its response.
+        let now = Instant::now();
+        let reply = client_links
+            .rotate_key(&mut client, &mut out_buf, now)
+            .await
+            .unwrap();
+        let new_public_key = client_links.rotation.pending.as_ref().unwrap().public_key;
+        assert!(reply.contains(&encode_key(&new_public_key)));
+        assert!(client_links
+            .rotate_key(&mut client, &mut out_buf, now)
+            .await
+            .is_err());
+        // An offer forged from the TUN device never reaches the peer.
+        assert!(!client_links.accept_inner(&Message::Offer([9; 32]).encode(), now));
+        assert_eq!(client_links.stats.snapshot().tun_rx_reserved, 1);
+        // The offer waits for the server's operator; a key it did not offer is refused.
+        assert!(deliver(&server_socket, &mut server, &mut server_links, &device).await);
+        assert_eq!(server_links.rotation.peer_offered, Some(new_public_key));
+        assert_eq!(server_links.rotation.peer_next, None);
+        assert!(!deliver(&client_socket, &mut client, &mut client_links, &device).await);
+        assert!(server_links
+            .accept_key(&mut server, &mut out_buf, &encode_key(&[9; 32]))
+            .await
+            .is_err());
+        server_links
+            .accept_key(&mut server, &mut out_buf, &encode_key(&new_public_key))
+            .await
+            .unwrap();
+        assert_eq!(server_links.rotation.peer_next, Some(new_public_key));
+        assert!(deliver(&client_socket, &mut client, &mut client_links, &device).await);
+        assert_eq!(
+            public_key_of(&client_links.keys.private_key),
+            new_public_key
+        );
+        assert!(deliver(&server_socket, &mut server, &mut server_links, &device).await);
+        assert_eq!(server_links.keys.public_key, new_public_key);
+        assert!(deliver(&client_socket, &mut client, &mut client_links, &device).await);
+
+        let packet = bench::ipv4_packet(200);
+        let encrypted = bench::encapsulate(&mut client, &packet, &mut out_buf)
+            .unwrap()
+            .to_vec();
+        client_links
+            .send_packets(&mut vec![encrypted])
+            .await
+            .unwrap();
+        while deliver(&server_socket, &mut server, &mut server_links, &device).await {}
+        assert_eq!(device.packets.load(Ordering::Relaxed), 1);
+
+        // Both sides come back with the new pair.
+        let saved = state::load(&dir.join("client.state"))
+            .unwrap()
+            .keys
+            .unwrap();
+        assert_eq!(
+            saved.private_key,
+            encode_key(&client_links.keys.private_key)
+        );
+        let saved = state::load(&dir.join("server.state"))
+            .unwrap()
+            .keys
+            .unwrap();
+        assert_eq!(saved.peer_public_key, encode_key(&new_public_key));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// What the server answers a fresh initiation from `client_key` with, read at `client`.
+    async fn reply_to_initiation(
+        server: &mut Tunn,
+        links: &mut LinkManager,
+        client_key: [u8; 32],
+        server_key: &[u8; 32],
+        client: &UdpSocket,
+    ) -> Option<u32> {
+        let mut initiator = Tunn::new(
+            StaticSecret::from(client_key),
+            PublicKey::from(public_key_of(server_key)),
+            None,
+            None,
+            2,
+            None,
+        );
+        let mut buf = vec![0u8; 2048];
+        let TunnResult::WriteToNetwork(initiation) =
+            initiator.format_handshake_initiation(&mut buf, true)
+        else {
+            panic!("no handshake initiation");
+        };
+        let packet = NetPacket {
+            link_index: 0,
+            src: client.local_addr().unwrap(),
+            ecn: ecn::NOT_ECT,
+            data: initiation.to_vec(),
+        };
+        let device = bench::MemoryDevice::default();
+        handle_incoming(
+            server,
+            &device,
+            links,
+            &mut vec![0u8; 2048],
+            Instant::now(),
+            packet,
+        )
+        .await
+        .unwrap();
+        let (size, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        wg_packet_type(&buf[..size])
+    }
+
+    #[tokio::test]
+    async fn handshake_rate_limit_counts_per_second() {
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut links = test_manager(
+            vec![test_link("wan", &server_socket, 1)],
+            BondingMode::Aggregate,
+        );
+        let server_key = rand::random::<[u8; 32]>();
+        let client_key = rand::random::<[u8; 32]>();
+        let rate_limiter = handshake_rate_limiter(&server_key, 2);
+        links.rate_limiter = Some(Arc::clone(&rate_limiter));
+        let mut server = Tunn::new(
+            StaticSecret::from(server_key),
+            PublicKey::from(public_key_of(&client_key)),
+            None,
+            None,
+            1,
+            Some(rate_limiter),
+        );
+        let start = Instant::now();
+        links.handshake_count_reset = start;
+
+        // Two handshakes a second; the wg timer ticking every 250ms does not add more.
+        assert_eq!(
+            reply_to_initiation(&mut server, &mut links, client_key, &server_key, &client).await,
+            Some(2)
+        );
+        assert_eq!(
+            reply_to_initiation(&mut server, &mut links, client_key, &server_key, &client).await,
+            Some(2)
+        );
+        assert_eq!(
+            reply_to_initiation(&mut server, &mut links, client_key, &server_key, &client).await,
+            Some(WG_COOKIE_REPLY)
+        );
+        for tick in 1..4 {
+            links.reset_handshake_count(start + Duration::from_millis(250 * tick));
+            assert_eq!(
+                reply_to_initiation(&mut server, &mut links, client_key, &server_key, &client)
+                    .await,
+                Some(WG_COOKIE_REPLY)
+            );
+        }
+        // boringtun also checks the time itself, so this needs a real second.
+        tokio::time::sleep(HANDSHAKE_COUNT_WINDOW).await;
+        links.reset_handshake_count(Instant::now());
+        assert_eq!(
+            reply_to_initiation(&mut server, &mut links, client_key, &server_key, &client).await,
+            Some(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn rotation_trials_share_the_handshake_rate_limit() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        let server_key = rand::random::<[u8; 32]>();
+        links.keys.private_key = server_key;
+        links.handshake_rate_limit = 2;
+        links.rotation.peer_next = Some(public_key_of(&rand::random::<[u8; 32]>()));
+        let mut server = Tunn::new(
+            StaticSecret::from(server_key),
+            PublicKey::from(public_key_of(&rand::random::<[u8; 32]>())),
+            None,
+            None,
+            1,
+            None,
+        );
+        // From a key that is neither the peer's nor the one it offered.
+        let mut stranger = Tunn::new(
+            StaticSecret::from(rand::random::<[u8; 32]>()),
+            PublicKey::from(public_key_of(&server_key)),
+            None,
+            None,
+            2,
+            None,
+        );
+        let mut buf = vec![0u8; 2048];
+        let TunnResult::WriteToNetwork(initiation) =
+            stranger.format_handshake_initiation(&mut buf, true)
+        else {
+            panic!("no handshake initiation");
+        };
+        let packet = NetPacket {
+            link_index: 0,
+            src: peer_addr(),
+            ecn: ecn::NOT_ECT,
+            data: initiation.to_vec(),
+        };
+
+        let mut out_buf = vec![0u8; 2048];
+        for _ in 0..3 {
+            assert!(!links
+                .accept_rotated_peer(&mut server, &packet, &mut out_buf)
+                .await
+                .unwrap());
+        }
+        assert_eq!(links.rotation.trials, 2);
+        links.reset_handshake_count(Instant::now() + HANDSHAKE_COUNT_WINDOW);
+        assert_eq!(links.rotation.trials, 0);
+        assert!(links.rotation.peer_next.is_some());
+    }
+
+    #[tokio::test]
+    async fn handshakes_over_the_rate_limit_need_a_cookie_from_their_source() {
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut link = test_link("wan", &server_socket, 1);
+        link.remote = Some(peer_socket.local_addr().unwrap());
+        let mut links = test_manager(vec![link], BondingMode::Aggregate);
+        let server_key = rand::random::<[u8; 32]>();
+        let client_key = rand::random::<[u8; 32]>();
+        let rate_limiter = handshake_rate_limiter(&server_key, 0);
+        links.rate_limiter = Some(Arc::clone(&rate_limiter));
+        let mut server = Tunn::new(
+            StaticSecret::from(server_key),
+            PublicKey::from(public_key_of(&client_key)),
+            None,
+            None,
+            1,
+            Some(rate_limiter),
+        );
+        let mut client = Tunn::new(
+            StaticSecret::from(client_key),
+            PublicKey::from(public_key_of(&server_key)),
+            None,
+            None,
+            2,
+            None,
+        );
+        let device = bench::MemoryDevice::default();
+        let mut out_buf = vec![0u8; 2048];
+        let mut buf = vec![0u8; 2048];
+
+        for expected_reply in [WG_COOKIE_REPLY, 2] {
+            let TunnResult::WriteToNetwork(initiation) =
+                client.format_handshake_initiation(&mut out_buf, true)
+            else {
+                panic!("no handshake initiation");
+            };
+            let packet = NetPacket {
+                link_index: 0,
+                src: client_socket.local_addr().unwrap(),
+                ecn: ecn::NOT_ECT,
+                data: initiation.to_vec(),
+            };
+            handle_incoming(
+                &mut server,
+                &device,
+                &mut links,
+                &mut vec![0u8; 2048],
+                Instant::now(),
+                packet,
+            )
+            .await
+            .unwrap();
+
+            // The cookie goes to the source without moving the link; the initiation that
+            // checks out moves the link's peer there, and the response follows.
+            let (size, _) =
+                tokio::time::timeout(Duration::from_secs(1), client_socket.recv_from(&mut buf))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(wg_packet_type(&buf[..size]), Some(expected_reply));
+            if expected_reply == WG_COOKIE_REPLY {
+                assert!(matches!(
+                    client.decapsulate(None, &buf[..size], &mut out_buf),
+                    TunnResult::Done
+                ));
+                assert_eq!(
+                    links.links[0].remote,
+                    Some(peer_socket.local_addr().unwrap())
+                );
+            }
+        }
+        assert_eq!(
+            links.links[0].remote,
+            Some(client_socket.local_addr().unwrap())
+        );
+        assert_eq!(links.stats.snapshot().cookie_replies, 1);
+    }
+
+    #[tokio::test]
+    async fn handshakes_over_the_source_limit_are_throttled() {
+        let mut wg_config = bench::bench_config(1, |_| None);
+        wg_config.handshake_source_limit = Some(2);
+        let network = Config::default().network;
+        let (links, mut rx) =
+            bench::bench_links(&wg_config, &network, BondingMode::Aggregate, None)
+                .await
+                .unwrap();
+        let addr = links.links[0].socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut initiation = [0u8; 148];
+        initiation[0] = 1;
+        for _ in 0..4 {
+            sender.send_to(&initiation, addr).await.unwrap();
+        }
+        sender.send_to(&[4u8; 64], addr).await.unwrap();
+
+        for expected in [148, 148, 64] {
+            let packet = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(packet.data.len(), expected);
+        }
+        let snapshot = links.stats.snapshot();
+        assert_eq!(snapshot.links[0].handshakes_throttled, 2);
+        assert_eq!(snapshot.links[0].rx_packets, 3);
+    }
+
+    #[tokio::test]
+    async fn stock_peer_only_ever_sees_wireguard_messages() {
+        struct TestDevice(std::sync::Mutex<Vec<Vec<u8>>>);
+
+        impl TunnelWriter for TestDevice {
+            fn write_packet<'a>(
+                &'a self,
+                data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                self.0.lock().unwrap().push(data.to_vec());
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        // Plain boringtun on a bare socket stands in for a kernel WireGuard server.
+        let stock_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut link = test_link("wan", &socket, 1);
+        link.remote = Some(stock_socket.local_addr().unwrap());
+        let mut links = test_manager(vec![link], BondingMode::Aggregate);
+        links.bond_control = false;
+        let (mut client, mut stock) = bench::tunnel_pair();
+        let device = TestDevice(std::sync::Mutex::new(Vec::new()));
+        let epoch = Instant::now();
+        let mut out_buf = vec![0u8; 2048];
+        let mut stock_buf = vec![0u8; 2048];
+        let mut recv_buf = vec![0u8; 2048];
+
+        // Everything this side sends must be a WireGuard message the stock peer accepts.
+        let mut stock_delivered = Vec::new();
+        send_handshake(&mut client, &mut links).await.unwrap();
+        links.send_health_pings(epoch).await.unwrap();
+        loop {
+            let received = tokio::time::timeout(
+                Duration::from_millis(200),
+                stock_socket.recv_from(&mut recv_buf),
+            )
+            .await;
+            let Ok(Ok((size, from))) = received else {
+                if stock_delivered.is_empty() && client.time_since_last_handshake().is_some() {
+                    let packet = bench::ipv4_packet(200);
+                    let encrypted = bench::encapsulate(&mut client, &packet, &mut out_buf)
+                        .unwrap()
+                        .to_vec();
+                    links.send_packets(&mut vec![encrypted]).await.unwrap();
+                    continue;
+                }
+                break;
+            };
+            let datagram = recv_buf[..size].to_vec();
+            assert!(parse_control_packet(&datagram).is_none());
+            let mut result = stock.decapsulate(Some(from.ip()), &datagram, &mut stock_buf);
+            loop {
+                match result {
+                    TunnResult::WriteToNetwork(reply) => {
+                        stock_socket.send_to(reply, from).await.unwrap();
+                        let (size, src) = socket.recv_from(&mut recv_buf).await.unwrap();
+                        let packet = NetPacket {
+                            link_index: 0,
+                            src,
+                            ecn: ecn::NOT_ECT,
+                            data: recv_buf[..size].to_vec(),
+                        };
+                        handle_incoming(
+                            &mut client,
+                            &device,
+                            &mut links,
+                            &mut out_buf,
+                            epoch,
+                            packet,
+                        )
+                        .await
+                        .unwrap();
+                        result = stock.decapsulate(None, &[], &mut stock_buf);
+                    }
+                    TunnResult::WriteToTunnelV4(packet, _) => {
+                        stock_delivered.push(packet.to_vec());
+                        break;
+                    }
+                    TunnResult::Done => break,
+                    other => panic!("stock peer rejected a datagram: {:?}", other),
+                }
+            }
+        }
+        assert_eq!(stock_delivered, vec![bench::ipv4_packet(200)]);
+
+        // Nothing extra on the way out either.
+        links.send_bye(epoch).await;
+        let quiet = tokio::time::timeout(
+            Duration::from_millis(100),
+            stock_socket.recv_from(&mut recv_buf),
+        )
+        .await;
+        assert!(quiet.is_err());
+        assert_eq!(
+            links.links[0]
+                .stats
+                .control_tx
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+
+        // And a stray bonding packet is handed to WireGuard, which drops it.
+        let ping = build_control_packet(BOND_PING, 1);
+        assert!(!links
+            .handle_control_packet(0, peer_addr(), &ping, epoch)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn drained_link_is_skipped_for_data() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(
+            vec![test_link("wifi", &socket, 1), test_link("lte", &socket, 1)],
+            BondingMode::Aggregate,
+        );
+
+        let reply = links.handle_command(ControlCommand::SetLinkState {
+            name: "wifi".to_string(),
+            state: LinkAdminState::Drain,
+        });
+        assert_eq!(reply, Ok("wifi drain".to_string()));
+
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(links.next_weighted_index(now), Some(1));
+        }
+        assert!(links
+            .handle_command(ControlCommand::SetLinkState {
+                name: "missing".to_string(),
+                state: LinkAdminState::Down,
+            })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn stats_reply_carries_link_state_and_rtt() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wifi", &socket, 1)], BondingMode::Aggregate);
+        let stats = |links: &mut LinkManager| {
+            let reply = links.handle_command(ControlCommand::Stats).unwrap();
+            serde_json::from_str::<StatsSnapshot>(&reply).unwrap().links[0].clone()
+        };
+        assert_eq!(stats(&mut links).state.as_deref(), Some("up"));
+        assert_eq!(stats(&mut links).rtt_ms, None);
+
+        links.links[0].record_rtt(42);
+        links.links[0].admin_state = LinkAdminState::Drain;
+        assert_eq!(stats(&mut links).state.as_deref(), Some("drain"));
+        assert_eq!(stats(&mut links).rtt_ms, Some(42));
+        // The counters saved across restarts stay free of it.
+        assert_eq!(links.stats.snapshot().links[0].state, None);
+    }
+
+    #[tokio::test]
+    async fn failover_switch_is_counted() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(
+            vec![test_link("wifi", &socket, 2), test_link("lte", &socket, 1)],
+            BondingMode::Failover,
+        );
+        let now = Instant::now();
+
+        let first = links.best_failover_index(now).expect("primary link");
+        assert_eq!(first, 0);
+        links.record_failover_choice(first);
+
+        links.links[0].admin_state = LinkAdminState::Down;
+        let second = links.best_failover_index(now).expect("standby link");
+        assert_eq!(second, 1);
+        links.record_failover_choice(second);
+
+        assert_eq!(links.stats.snapshot().failovers, 1);
+    }
+
+    #[tokio::test]
+    async fn failover_by_quality_prefers_the_better_link() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(
+            vec![test_link("wifi", &socket, 2), test_link("lte", &socket, 1)],
+            BondingMode::Failover,
+        );
+        links.failover_by_quality = true;
+        let now = Instant::now();
+        // Unscored links fall back to their weight.
+        assert_eq!(links.best_failover_index(now), Some(0));
+        links.record_failover_choice(0);
+
+        links.links[0].record_rtt(40);
+        links.links[1].record_rtt(20);
+        // Close enough that the current link is kept.
+        assert_eq!(links.best_failover_index(now), Some(0));
+
+        links.links[0].record_rtt(400);
+        assert_eq!(links.best_failover_index(now), Some(1));
+        assert_eq!(links.links[1].quality.score(now), Some(96));
+        let score = links.live_stats(now).links[0].quality;
+        assert!(score < Some(96), "score {:?}", score);
+    }
+
+    #[tokio::test]
+    async fn link_down_and_recovery_emit_events() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut link = test_link("wifi", &socket, 1);
+        let mut rx = link.events.subscribe();
+        let now = Instant::now();
+
+        link.mark_down(now, "no rx");
+        link.mark_down(now, "no rx");
+        link.record_rx(now);
+
+        assert_eq!(
+            rx.try_recv().expect("down event"),
+            TunnelEvent::LinkDown {
+                link: "wifi".to_string(),
+                reason: "no rx".to_string(),
+            }
+        );
+        assert_eq!(
+            rx.try_recv().expect("up event"),
+            TunnelEvent::LinkUp {
+                link: "wifi".to_string(),
+            }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn handle_incoming_drops_invalid_packet() {
+        struct TestDevice;
+
+        impl TunnelWriter for TestDevice {
+            fn write_packet<'a>(
+                &'a self,
+                _data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let mut tunnel = Tunn::new(
+            StaticSecret::from([1u8; 32]),
+            PublicKey::from([2u8; 32]),
+            None,
+            None,
+            1,
+            None,
+        );
+
+        let packet = NetPacket {
+            link_index: 0,
+            src: "127.0.0.1:12345".parse().unwrap(),
+            ecn: ecn::NOT_ECT,
+            data: vec![0u8; 1],
+        };
+
+        let mut links = LinkManager {
+            links: Vec::new(),
+            mode: BondingMode::Aggregate,
+            error_backoff: Duration::from_secs(1),
+            health_timeout: None,
+            next_index: 0,
+            remaining_weight: 0,
+            failover_index: None,
+            failover_by_quality: false,
+            bond_control: true,
+            clamp_mss: None,
+            icmp: None,
+            unsupported: UnsupportedPolicy::default(),
+            unsupported_logged: None,
+            links_down: LinksDownPolicy::default(),
+            outage: None,
+            send_limit: None,
+            receive_limit: None,
+            copy_dscp: false,
+            ecn: false,
+            tos: 0,
+            compression: false,
+            peer_features: 0,
+            reorder: None,
+            keys: uapi::Status::default(),
+            rotation: KeyRotation::default(),
+            handshake_rate_limit: DEFAULT_HANDSHAKE_RATE_LIMIT,
+            rate_limiter: None,
+            handshake_count_reset: Instant::now(),
+            stats: Arc::new(StatsRegistry::new(&Config::default())),
+            events: events::channel(),
+            capture_dir: control::capture_dir(None),
+            capture: None,
+            receivers: JoinSet::new(),
+            net_tx: None,
+            pool: Arc::default(),
+            spare: Vec::new(),
+            tun_batch: Vec::new(),
+            tun_batching: false,
+            lanes: Lanes::default(),
+        };
+
+        let mut out_buf = vec![0u8; 256];
+        let probe = tunnel.decapsulate(Some(packet.src.ip()), &packet.data, &mut out_buf);
+        assert!(matches!(probe, TunnResult::Err(_)));
+
+        let mut tunnel = Tunn::new(
+            StaticSecret::from([1u8; 32]),
+            PublicKey::from([2u8; 32]),
+            None,
+            None,
+            1,
+            None,
+        );
+        let result = handle_incoming(
+            &mut tunnel,
+            &TestDevice,
+            &mut links,
+            &mut out_buf,
+            Instant::now(),
+            packet,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reorder_writes_data_in_the_order_it_was_sent() {
+        struct TestDevice(std::sync::Mutex<Vec<u8>>);
+
+        impl TunnelWriter for TestDevice {
+            fn write_packet<'a>(
+                &'a self,
+                data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                self.0.lock().unwrap().push(data[data.len() - 1]);
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let (mut client, mut server) = bench::tunnel_pair();
+        bench::handshake(&mut client, &mut server).unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        links.reorder = Some(Reorder::new(
+            Some(Duration::from_millis(20)),
+            1,
+            &links.stats.tunnel,
+        ));
+        let mut out_buf = vec![0u8; 2048];
+        let datagrams: Vec<Vec<u8>> = (0..5u8)
+            .map(|id| {
+                let mut packet = bench::ipv4_packet(100);
+                packet[99] = id;
+                bench::encapsulate(&mut client, &packet, &mut out_buf)
+                    .unwrap()
+                    .to_vec()
+            })
+            .collect();
+        let device = TestDevice(std::sync::Mutex::new(Vec::new()));
+        let epoch = Instant::now();
+        for index in [0, 2, 3, 1, 4] {
+            let packet = NetPacket {
+                link_index: 0,
+                src: socket.local_addr().unwrap(),
+                ecn: ecn::NOT_ECT,
+                data: datagrams[index].clone(),
+            };
+            handle_incoming(
+                &mut server,
+                &device,
+                &mut links,
+                &mut out_buf,
+                epoch,
+                packet,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(*device.0.lock().unwrap(), [0, 1, 2, 3, 4]);
+        let snapshot = links.stats.snapshot();
+        assert_eq!(snapshot.reorder_depth, 0);
+        assert_eq!(snapshot.reorder_timeouts, 0);
+        assert_eq!(snapshot.tun_tx_packets, 5);
+    }
+
+    #[tokio::test]
+    async fn packets_that_cannot_be_sent_are_answered_with_icmp() {
+        struct TestDevice(std::sync::Mutex<Vec<Vec<u8>>>);
+
+        impl TunnelWriter for TestDevice {
+            fn write_packet<'a>(
+                &'a self,
+                data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                self.0.lock().unwrap().push(data.to_vec());
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        links.icmp = Some(IcmpErrors::new(1420, [], Instant::now()));
+        let device = TestDevice(std::sync::Mutex::new(Vec::new()));
+        let mut packet = bench::ipv4_packet(1500);
+        packet[6] = 0x40;
+        assert_eq!(
+            links.refusal(&bench::ipv4_packet(1420), Instant::now()),
+            None
+        );
+        assert_eq!(
+            links.refusal(&packet, Instant::now()),
+            Some(Refusal::TooBig)
+        );
+        links.links[0].remote = None;
+        assert_eq!(
+            links.refusal(&bench::ipv4_packet(100), Instant::now()),
+            Some(Refusal::Unreachable)
+        );
+
+        links
+            .refuse(&device, &packet, Refusal::TooBig)
+            .await
+            .unwrap();
+        let written = device.0.lock().unwrap();
+        assert_eq!(written.len(), 1);
+        // From the refused packet's destination back to its source.
+        assert_eq!(written[0][12..20], [10, 0, 0, 2, 10, 0, 0, 1]);
+        let snapshot = links.stats.snapshot();
+        assert_eq!(snapshot.dropped_too_big, 1);
+        assert_eq!(snapshot.icmp_errors, 1);
+
+        links.icmp = None;
+        assert_eq!(links.refusal(&packet, Instant::now()), None);
+    }
+
+    #[tokio::test]
+    async fn only_authenticated_packets_move_the_remote() {
+        struct TestDevice;
+
+        impl TunnelWriter for TestDevice {
+            fn write_packet<'a>(
+                &'a self,
+                _data: &'a [u8],
+            ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let (mut client, mut server) = bench::tunnel_pair();
+        bench::handshake(&mut client, &mut server).unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        let mut out_buf = vec![0u8; 2048];
+        let datagram = bench::encapsulate(&mut client, &bench::ipv4_packet(100), &mut out_buf)
+            .unwrap()
+            .to_vec();
+        let roamed: SocketAddr = "127.0.0.1:23456".parse().unwrap();
+        let receive = |src: SocketAddr, data: Vec<u8>| NetPacket {
+            link_index: 0,
+            src,
+            ecn: ecn::NOT_ECT,
+            data,
+        };
+        let epoch = Instant::now();
+
+        // A forged data packet and a bonding ping from elsewhere leave the return path be;
+        // the ping is still answered where it came from.
+        let mut forged = datagram.clone();
+        forged[40] ^= 1;
+        let spoofed = spoofer.local_addr().unwrap();
+        for data in [forged, build_control_packet(BOND_PING, 7).to_vec()] {
+            handle_incoming(
+                &mut server,
+                &TestDevice,
+                &mut links,
+                &mut out_buf,
+                epoch,
+                receive(spoofed, data),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(links.links[0].remote, Some(peer_addr()));
+        let mut buf = [0u8; 64];
+        let (size, _) = tokio::time::timeout(Duration::from_secs(1), spoofer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(parse_control_packet(&buf[..size]), Some((BOND_PONG, 7)));
+
+        handle_incoming(
+            &mut server,
+            &TestDevice,
+            &mut links,
+            &mut out_buf,
+            epoch,
+            receive(roamed, datagram),
+        )
+        .await
+        .unwrap();
+        assert_eq!(links.links[0].remote, Some(roamed));
+    }
+
+    #[tokio::test]
+    async fn strict_source_links_only_admit_their_peer() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        let receive = |src: &str, data: Vec<u8>| NetPacket {
+            link_index: 0,
+            src: src.parse().unwrap(),
+            ecn: ecn::NOT_ECT,
+            data,
+        };
+        let mut initiation = vec![0u8; 148];
+        initiation[0] = 1;
+        let stranger = "198.51.100.7:51820";
+
+        assert!(links.admits(&receive(stranger, vec![4, 0, 0, 0])));
+        links.links[0].strict_source = true;
+        assert!(!links.admits(&receive(stranger, vec![4, 0, 0, 0])));
+        assert!(!links.admits(&receive("127.0.0.1:12346", vec![4, 0, 0, 0])));
+        assert!(links.admits(&receive(stranger, initiation)));
+        assert!(links.admits(&receive("127.0.0.1:12345", vec![4, 0, 0, 0])));
+        assert!(links.admits(&receive("[::ffff:127.0.0.1]:12345", vec![4, 0, 0, 0])));
+        assert_eq!(
+            links.links[0].stats.rx_wrong_source.load(Ordering::Relaxed),
+            2
+        );
+
+        // Until it knows its peer, the link takes anyone.
+        links.links[0].remote = None;
+        assert!(links.admits(&receive(stranger, vec![4, 0, 0, 0])));
+    }
+
+    #[tokio::test]
+    async fn bandwidth_limits_drop_and_count_the_excess() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        let now = Instant::now();
+        let packet = bench::ipv4_packet(1000);
+        assert!((0..100).all(|_| links.within_send_limit(&packet, now)));
+
+        links.send_limit = Some(RateLimit::new(1_000, now));
+        links.receive_limit = Some(RateLimit::new(1_000, now));
+        let sent = (0..100)
+            .filter(|_| links.within_send_limit(&packet, now))
+            .count();
+        assert!(sent < 100);
+        assert!(links.within_receive_limit(&packet, now));
+        let tunnel = &links.stats.tunnel;
+        assert_eq!(
+            tunnel.dropped_send_limit.load(Ordering::Relaxed),
+            100 - sent as u64
+        );
+        assert_eq!(tunnel.dropped_receive_limit.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn links_down_policy_holds_or_refuses_packets() {
+        let (mut client, mut server) = bench::tunnel_pair();
+        bench::handshake(&mut client, &mut server).unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        links.links[0].remote = Some(peer.local_addr().unwrap());
+        links.links_down = LinksDownPolicy::Buffer;
+        links.outage = Some(Outage::new(8, Duration::from_secs(5)));
+        let mut out_buf = vec![0u8; 2048];
+        let packet = bench::ipv4_packet(100);
+        let now = Instant::now();
+        assert!(!links.hold(&packet, now));
+
+        links.links[0].mark_down(now, "no rx");
+        assert_eq!(links.refusal(&packet, now), None);
+        assert!(links.hold(&packet, now));
+        links.replay_outage(&mut client).await.unwrap();
+        assert_eq!(links.stats.snapshot().outage_depth, 1);
+
+        links.links[0].mark_recovered(" (rx)");
+        links.replay_outage(&mut client).await.unwrap();
+        let mut buf = [0u8; 2048];
+        let (size, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        match server.decapsulate(None, &buf[..size], &mut out_buf) {
+            TunnResult::WriteToTunnelV4(decrypted, _) => assert_eq!(decrypted, &packet[..]),
+            _ => panic!("held packet did not come through"),
+        }
+        let snapshot = links.stats.snapshot();
+        assert_eq!((snapshot.outage_depth, snapshot.outage_replayed), (0, 1));
+
+        links.links_down = LinksDownPolicy::Drop;
+        // Take in the replay's send before the link goes down again.
+        links.links[0].apply_send_outcome();
+        links.links[0].mark_down(now, "no rx");
+        assert_eq!(links.refusal(&packet, now), Some(Refusal::Unreachable));
+    }
+
+    #[tokio::test]
+    async fn unsupported_tun_packets_follow_the_policy() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let mut links = test_manager(vec![test_link("wan", &socket, 1)], BondingMode::Aggregate);
+        let arp = [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0x08, 0x06];
+        let now = Instant::now();
+        assert!(links.accept_inner(&bench::ipv4_packet(100), now));
+        assert!(!links.accept_inner(&arp, now));
+        assert!(!links.accept_inner(&bench::ipv4_packet(100)[..30], now));
+        links.unsupported = UnsupportedPolicy::Log;
+        assert!(!links.accept_inner(&arp, now));
+        assert_eq!(links.unsupported_logged, Some(now));
+        links.unsupported = UnsupportedPolicy::Forward;
+        assert!(links.accept_inner(&arp, now));
+        let snapshot = links.stats.snapshot();
+        assert_eq!(snapshot.tun_rx_non_ip, 3);
+        assert_eq!(snapshot.tun_rx_malformed, 1);
+    }
+}
@@ -0,0 +1,343 @@
+//! `vtrunkd bench`: an in-process client and server bonded over loopback sockets.
+//!
+//! The client encapsulates synthetic IPv4 packets and schedules them through the same
+//! `LinkManager` the daemon uses; the server decapsulates them into an in-memory device,
+//! so no TUN or privileges are needed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::*;
+
+/// How long the server waits for a straggler before counting the rest as lost.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+const IPV4_HEADER_LEN: usize = 20;
+/// Packets the client lets ahead of the server, so a pass measures sustained throughput
+/// instead of how much an unpaced sender overflows the receive queues.
+const WINDOW: u64 = 256;
+
+pub struct BenchOptions {
+    pub packets: usize,
+    /// Size of each inner IP packet in bytes.
+    pub size: usize,
+    pub links: usize,
+}
+
+/// One line of the report.
+pub struct BenchResult {
+    pub name: String,
+    pub sent: usize,
+    pub delivered: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Time spent in `LinkManager` scheduling and sending, zero for the crypto-only run.
+    pub send_time: Duration,
+}
+
+impl BenchResult {
+    pub fn packets_per_sec(&self) -> f64 {
+        self.delivered as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn mbit_per_sec(&self) -> f64 {
+        self.bytes as f64 * 8.0 / 1_000_000.0 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn send_ns_per_packet(&self) -> u64 {
+        (self.send_time.as_nanos() / self.sent.max(1) as u128) as u64
+    }
+}
+
+/// Discards decrypted packets, counting them.
+#[derive(Default)]
+pub(super) struct MemoryDevice {
+    pub(super) packets: AtomicU64,
+    pub(super) bytes: AtomicU64,
+}
+
+impl TunnelWriter for MemoryDevice {
+    fn write_packet<'a>(
+        &'a self,
+        data: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = VtrunkdResult<()>> + Send + 'a>> {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Runs the crypto-only pass, then one loopback pass per bonding mode.
+pub async fn run(options: &BenchOptions) -> VtrunkdResult<Vec<BenchResult>> {
+    if options.size < IPV4_HEADER_LEN || options.size > u16::MAX as usize {
+        return Err(VtrunkdError::InvalidConfig(format!(
+            "Bench packet size must be between {} and {}",
+            IPV4_HEADER_LEN,
+            u16::MAX
+        )));
+    }
+    if options.packets == 0 || options.links == 0 {
+        return Err(VtrunkdError::InvalidConfig(
+            "Bench needs at least one packet and one link".to_string(),
+        ));
+    }
+
+    let packet = ipv4_packet(options.size);
+    let mut results = vec![encapsulate_only(options, &packet)?];
+    for mode in [
+        BondingMode::Aggregate,
+        BondingMode::Redundant,
+        BondingMode::Failover,
+    ] {
+        results.push(loopback(options, mode, &packet).await?);
+    }
+    Ok(results)
+}
+
+fn encapsulate_only(options: &BenchOptions, packet: &[u8]) -> VtrunkdResult<BenchResult> {
+    let (mut client, mut server) = tunnel_pair();
+    handshake(&mut client, &mut server)?;
+    let mut out_buf = vec![0u8; packet.len() + 32];
+
+    let start = Instant::now();
+    for _ in 0..options.packets {
+        encapsulate(&mut client, packet, &mut out_buf)?;
+    }
+    Ok(BenchResult {
+        name: "encapsulate".to_string(),
+        sent: options.packets,
+        delivered: options.packets as u64,
+        bytes: (options.packets * packet.len()) as u64,
+        elapsed: start.elapsed(),
+        send_time: Duration::ZERO,
+    })
+}
+
+async fn loopback(
+    options: &BenchOptions,
+    mode: BondingMode,
+    packet: &[u8],
+) -> VtrunkdResult<BenchResult> {
+    let (mut client, mut server) = tunnel_pair();
+    handshake(&mut client, &mut server)?;
+
+    let network = NetworkConfig {
+        buffer_size: packet.len() + 64,
+        ..Config::default().network
+    };
+    let server_config = bench_config(options.links, |_| None);
+    let (mut server_links, mut net_rx) = bench_links(&server_config, &network, mode, None).await?;
+    let mut endpoints = Vec::new();
+    for link in &server_links.links {
+        endpoints.push(link.socket.local_addr()?.to_string());
+    }
+    let client_config = bench_config(options.links, |index| Some(endpoints[index].clone()));
+    let (mut client_links, _client_rx) = bench_links(&client_config, &network, mode, None).await?;
+
+    let batch_size = DEFAULT_BATCH_SIZE;
+    let device = MemoryDevice::default();
+    let start = Instant::now();
+    let sender = async {
+        let mut out_buf = vec![0u8; packet.len() + 32];
+        let mut send_time = Duration::ZERO;
+        let mut remaining = options.packets;
+        while remaining > 0 {
+            let sent = (options.packets - remaining) as u64;
+            let waiting = Instant::now();
+            // Lost packets never arrive; stop waiting for them after the drain timeout.
+            while sent > device.packets.load(Ordering::Relaxed) + WINDOW
+                && waiting.elapsed() < DRAIN_TIMEOUT
+            {
+                tokio::task::yield_now().await;
+            }
+            let count = remaining.min(batch_size);
+            let mut outgoing = Vec::with_capacity(count);
+            for _ in 0..count {
+                outgoing.push(encapsulate(&mut client, packet, &mut out_buf)?.to_vec());
+            }
+            let sending = Instant::now();
+            client_links.send_packets(&mut outgoing).await?;
+            send_time += sending.elapsed();
+            remaining -= count;
+        }
+        Ok::<_, VtrunkdError>(send_time)
+    };
+
+    let receiver = async {
+        let mut out_buf = vec![0u8; network.buffer_size];
+        let mut last = start;
+        while device.packets.load(Ordering::Relaxed) < options.packets as u64 {
+            let packet = match tokio::time::timeout(DRAIN_TIMEOUT, net_rx.recv()).await {
+                Ok(Some(packet)) => packet,
+                Ok(None) | Err(_) => break,
+            };
+            server_links.record_rx(packet.link_index, Instant::now());
+            handle_incoming(
+                &mut server,
+                &device,
+                &mut server_links,
+                &mut out_buf,
+                start,
+                packet,
+            )
+            .await?;
+            last = Instant::now();
+        }
+        Ok::<_, VtrunkdError>(last)
+    };
+
+    let (send_time, last) = tokio::join!(sender, receiver);
+    let (send_time, last) = (send_time?, last?);
+    Ok(BenchResult {
+        name: format!("{:?}", mode).to_lowercase(),
+        sent: options.packets,
+        delivered: device.packets.load(Ordering::Relaxed),
+        bytes: device.bytes.load(Ordering::Relaxed),
+        elapsed: last.duration_since(start),
+        send_time,
+    })
+}
+
+pub(super) fn bench_config(
+    links: usize,
+    endpoint: impl Fn(usize) -> Option<String>,
+) -> WireGuardConfig {
+    WireGuardConfig {
+        links: (0..links)
+            .map(|index| WireGuardLinkConfig {
+                name: Some(format!("bench-{}", index)),
+                bind: Some("127.0.0.1:0".to_string()),
+                bind_device: None,
+                endpoint: endpoint(index),
+                weight: Some(1),
+                socket_recv_buffer: None,
+                socket_send_buffer: None,
+                receive_sockets: None,
+                dscp: None,
+                ttl: None,
+                allowed_sources: None,
+                strict_source: None,
+            })
+            .collect(),
+        ..Config::default().wireguard
+    }
+}
+
+pub(super) async fn bench_links(
+    wg_config: &WireGuardConfig,
+    network: &NetworkConfig,
+    mode: BondingMode,
+    health_timeout: Option<Duration>,
+) -> VtrunkdResult<(LinkManager, mpsc::Receiver<NetPacket>)> {
+    let config = Config {
+        wireguard: wg_config.clone(),
+        ..Config::default()
+    };
+    setup_links(
+        wg_config,
+        network,
+        mode,
+        Duration::from_secs(DEFAULT_ERROR_BACKOFF_SECS),
+        health_timeout,
+        Arc::new(StatsRegistry::new(&config)),
+        events::channel(),
+    )
+    .await
+}
+
+pub(super) fn tunnel_pair() -> (Tunn, Tunn) {
+    let client_key = StaticSecret::from(rand::random::<[u8; 32]>());
+    let server_key = StaticSecret::from(rand::random::<[u8; 32]>());
+    let client_public = PublicKey::from(&client_key);
+    let server_public = PublicKey::from(&server_key);
+    (
+        Tunn::new(client_key, server_public, None, None, 1, None),
+        Tunn::new(server_key, client_public, None, None, 2, None),
+    )
+}
+
+/// Completes a handshake by passing messages between the two sessions in memory.
+pub(super) fn handshake(client: &mut Tunn, server: &mut Tunn) -> VtrunkdResult<()> {
+    let mut buf = vec![0u8; 2048];
+    let mut pending = match client.format_handshake_initiation(&mut buf, false) {
+        TunnResult::WriteToNetwork(packet) => vec![(true, packet.to_vec())],
+        _ => {
+            return Err(VtrunkdError::Network(
+                "Bench handshake initiation failed".to_string(),
+            ))
+        }
+    };
+    while let Some((to_server, packet)) = pending.pop() {
+        let peer = if to_server {
+            &mut *server
+        } else {
+            &mut *client
+        };
+        let mut result = peer.decapsulate(None, &packet, &mut buf);
+        loop {
+            match result {
+                TunnResult::WriteToNetwork(reply) => {
+                    pending.push((!to_server, reply.to_vec()));
+                    result = peer.decapsulate(None, &[], &mut buf);
+                }
+                TunnResult::Err(e) => {
+                    return Err(VtrunkdError::Network(format!(
+                        "Bench handshake error: {:?}",
+                        e
+                    )))
+                }
+                _ => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn encapsulate<'a>(
+    tunnel: &mut Tunn,
+    packet: &[u8],
+    out_buf: &'a mut [u8],
+) -> VtrunkdResult<&'a [u8]> {
+    match tunnel.encapsulate(packet, out_buf) {
+        TunnResult::WriteToNetwork(packet) => Ok(packet),
+        result => Err(VtrunkdError::Network(format!(
+            "Bench encapsulate returned {:?}",
+            result
+        ))),
+    }
+}
+
+/// A zero-filled UDP-less IPv4 packet from 10.0.0.1 to 10.0.0.2.
+pub(super) fn ipv4_packet(size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; size];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(size as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = 253; // reserved for experimentation
+    packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+    packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bench_delivers_packets_in_every_mode() {
+        let options = BenchOptions {
+            packets: 64,
+            size: 256,
+            links: 2,
+        };
+        let results = run(&options).await.unwrap();
+
+        let names: Vec<&str> = results.iter().map(|result| result.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["encapsulate", "aggregate", "redundant", "failover"]
+        );
+        for result in &results {
+            assert_eq!(result.delivered, 64, "{}", result.name);
+            assert_eq!(result.bytes, 64 * 256, "{}", result.name);
+        }
+    }
+}
@@ -0,0 +1,573 @@
+//! Crypto lanes (`wireguard.crypto_workers`): further WireGuard sessions with the peer,
+//! each encrypting and decrypting on a thread of its own, so the tunnel is not bound to
+//! the one core the tunnel loop runs on.
+//!
+//! Lane 0 is the loop's own session, which alone carries the session messages and key
+//! rotation. Every other lane is a `Tunn` owned by a worker thread. A lane's number is the
+//! low byte of the index its `Tunn` starts from, which boringtun shifts into the second
+//! byte of every session index on the wire, so each datagram is routed by the index it
+//! carries: ours for everything but handshake initiations, which carry the peer's. Both
+//! sides announce their lane count in their features and spread flows over the lower of
+//! the two, one flow to a lane so that each keeps its order.
+
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use boringtun::noise::errors::WireGuardError;
+use boringtun::noise::rate_limiter::RateLimiter;
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use tokio::sync::mpsc;
+use tracing::debug;
+use zeroize::Zeroizing;
+
+use super::sender::BufferPool;
+use super::{compress_counted, wg_packet_type, NetPacket, WG_COOKIE_REPLY};
+use crate::error::VtrunkdResult;
+use crate::queue;
+use crate::stats::{self, StatsRegistry, TunnelCounters};
+
+/// Jobs waiting for a lane before more are dropped; each is a round of the tunnel loop.
+const JOB_DEPTH: usize = 64;
+/// Results waiting for the tunnel loop before the lanes wait for it.
+pub(super) const OUTPUT_DEPTH: usize = 256;
+/// Room for a handshake message, the longest a timer sends.
+const HANDSHAKE_LEN: usize = 148;
+
+/// The index a lane's `Tunn` starts from: random, but for the lane in its low byte.
+pub(super) fn session_index(lane: usize) -> u32 {
+    (rand::random::<u32>() & 0x00ff_ff00) | lane as u32
+}
+
+/// The lane a session index on the wire belongs to.
+pub(super) fn lane_of_index(index: u32) -> usize {
+    usize::from((index >> 8) as u8)
+}
+
+/// The lane a datagram's session index names, and whether it is the peer's lane rather
+/// than ours; `None` for anything but a WireGuard message.
+fn lane_of(datagram: &[u8]) -> Option<(usize, bool)> {
+    let (at, peers) = match datagram.get(..4)? {
+        [1, 0, 0, 0] => (4, true),
+        [2, 0, 0, 0] => (8, false),
+        [3, 0, 0, 0] | [4, 0, 0, 0] => (4, false),
+        _ => return None,
+    };
+    let index = u32::from_le_bytes(datagram.get(at..at + 4)?.try_into().ok()?);
+    Some((lane_of_index(index), peers))
+}
+
+/// What the lanes' sessions are made from.
+#[derive(Clone)]
+pub(super) struct LaneKeys {
+    pub private_key: Zeroizing<[u8; 32]>,
+    pub peer_public_key: [u8; 32],
+    pub preshared_key: Option<[u8; 32]>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl LaneKeys {
+    /// A new session for `lane`. It keeps no keepalive of its own: lane 0's keeps the
+    /// path open.
+    fn tunnel(&self, lane: usize) -> Tunn {
+        Tunn::new(
+            StaticSecret::from(*self.private_key),
+            PublicKey::from(self.peer_public_key),
+            self.preshared_key,
+            None,
+            session_index(lane),
+            self.rate_limiter.clone(),
+        )
+    }
+}
+
+/// Keys the workers rebuild their sessions from whenever `generation` moves on.
+struct Shared {
+    generation: AtomicU64,
+    keys: Mutex<LaneKeys>,
+}
+
+enum Job {
+    /// TUN packets with the outer TOS byte they go out with, LZ4-compressed first when
+    /// `compress` is set.
+    Encrypt {
+        packets: Vec<(Vec<u8>, u8)>,
+        compress: bool,
+    },
+    Decrypt(Vec<NetPacket>),
+    /// Runs the session's timers; the tunnel loop ticks every lane with its own.
+    Tick,
+}
+
+/// What a lane hands back to the tunnel loop.
+pub(super) enum Output {
+    /// Datagrams to send with their outer TOS byte; those from the timers have 0.
+    Sent(Vec<(Vec<u8>, u8)>),
+    Received(Vec<(NetPacket, Received)>),
+}
+
+/// What became of a datagram a lane decrypted.
+pub(super) enum Received {
+    /// A handshake message, answered by the first datagram; the packets that waited for
+    /// the new session follow it.
+    Answered(Vec<Vec<u8>>),
+    /// Over the handshake rate limit: the cookie reply for its source.
+    Cookie(Vec<u8>),
+    /// A packet for the TUN device.
+    Data(Vec<u8>),
+    /// Nothing to write, as for a keepalive, a handshake response or a cookie reply.
+    Empty,
+    Failed(WireGuardError),
+}
+
+/// The lanes past lane 0, and what a round of the tunnel loop gathered for them.
+#[derive(Default)]
+pub(super) struct Lanes {
+    workers: Vec<SyncSender<Job>>,
+    shared: Option<Arc<Shared>>,
+    /// Lanes the peer runs too; TUN packets are spread over these.
+    active: usize,
+    outbound: Vec<Vec<(Vec<u8>, u8)>>,
+    inbound: Vec<Vec<NetPacket>>,
+}
+
+impl Lanes {
+    /// Starts a worker thread for every lane past lane 0 of `count`; what they produce
+    /// arrives on `output`. The workers stop once the `Lanes` are dropped.
+    pub(super) fn start(
+        count: usize,
+        keys: LaneKeys,
+        pool: &Arc<BufferPool>,
+        stats: &Arc<StatsRegistry>,
+        buffer_size: usize,
+        output: mpsc::Sender<Output>,
+    ) -> VtrunkdResult<Self> {
+        let shared = Arc::new(Shared {
+            generation: AtomicU64::new(0),
+            keys: Mutex::new(keys),
+        });
+        let mut workers = Vec::new();
+        for lane in 1..count {
+            let (tx, rx) = sync_channel(JOB_DEPTH);
+            let worker = Worker {
+                lane,
+                tunnel: shared
+                    .keys
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .tunnel(lane),
+                generation: 0,
+                shared: Arc::clone(&shared),
+                pool: Arc::clone(pool),
+                stats: Arc::clone(stats),
+                spare: Vec::new(),
+                buffer_len: std::cmp::max(buffer_size + 32, HANDSHAKE_LEN),
+                output: output.clone(),
+            };
+            std::thread::Builder::new()
+                .name(format!("vtrunkd-crypto-{}", lane))
+                .spawn(move || worker.run(rx))?;
+            workers.push(tx);
+        }
+        Ok(Lanes {
+            outbound: (1..count).map(|_| Vec::new()).collect(),
+            inbound: (1..count).map(|_| Vec::new()).collect(),
+            workers,
+            shared: Some(shared),
+            active: 1,
+        })
+    }
+
+    /// The lanes run here, lane 0 included.
+    pub(super) fn count(&self) -> usize {
+        self.workers.len() + 1
+    }
+
+    /// Takes the lane count the peer announced; returns the lanes now in use.
+    pub(super) fn set_peer_lanes(&mut self, lanes: u8) -> usize {
+        self.active = self.count().min(usize::from(lanes)).max(1);
+        self.active
+    }
+
+    /// Rebuilds every lane's session from new keys; each starts a handshake with its next
+    /// packet.
+    pub(super) fn rekey(&self, keys: LaneKeys) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        *shared.keys.lock().unwrap_or_else(PoisonError::into_inner) = keys;
+        shared.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// The lane for a TUN packet, the same for every packet of its flow.
+    pub(super) fn flow_lane(&self, packet: &[u8]) -> usize {
+        if self.active < 2 {
+            return 0;
+        }
+        (queue::flow_key(packet) % self.active as u64) as usize
+    }
+
+    /// Queues a TUN packet for `lane`, past lane 0, until [`dispatch`](Self::dispatch).
+    pub(super) fn queue_outbound(&mut self, lane: usize, packet: Vec<u8>, tos: u8) {
+        self.outbound[lane - 1].push((packet, tos));
+    }
+
+    /// Queues a datagram for the lane whose session it belongs to until
+    /// [`dispatch`](Self::dispatch); gives it back when that is lane 0.
+    pub(super) fn route_inbound(&mut self, packet: NetPacket) -> Option<NetPacket> {
+        let lane = match lane_of(&packet.data) {
+            // The peer only starts sessions on the lanes both sides use; before it has
+            // announced any, its indices are not numbered by lane at all.
+            Some((lane, true)) if lane < self.active => lane,
+            Some((lane, false)) if lane < self.count() => lane,
+            _ => 0,
+        };
+        if lane == 0 {
+            return Some(packet);
+        }
+        self.inbound[lane - 1].push(packet);
+        None
+    }
+
+    /// Hands what was queued to the workers. A lane that has fallen a full queue behind
+    /// loses it, counted in `crypto_dropped`.
+    pub(super) fn dispatch(&mut self, compress: bool, pool: &BufferPool, stats: &TunnelCounters) {
+        for (index, worker) in self.workers.iter().enumerate() {
+            if !self.outbound[index].is_empty() {
+                let packets = mem::take(&mut self.outbound[index]);
+                if let Err(TrySendError::Full(Job::Encrypt { packets, .. })) =
+                    worker.try_send(Job::Encrypt { packets, compress })
+                {
+                    stats::add(&stats.crypto_dropped, packets.len() as u64);
+                    pool.put(packets.into_iter().map(|(packet, _)| packet));
+                }
+            }
+            if !self.inbound[index].is_empty() {
+                let packets = mem::take(&mut self.inbound[index]);
+                if let Err(TrySendError::Full(Job::Decrypt(packets))) =
+                    worker.try_send(Job::Decrypt(packets))
+                {
+                    stats::add(&stats.crypto_dropped, packets.len() as u64);
+                }
+            }
+        }
+    }
+
+    /// Runs every worker's session timers; a lane that is behind skips a tick.
+    pub(super) fn tick(&self) {
+        for worker in &self.workers {
+            let _ = worker.try_send(Job::Tick);
+        }
+    }
+}
+
+struct Worker {
+    lane: usize,
+    tunnel: Tunn,
+    /// The key generation `tunnel` was made from.
+    generation: u64,
+    shared: Arc<Shared>,
+    pool: Arc<BufferPool>,
+    stats: Arc<StatsRegistry>,
+    spare: Vec<Vec<u8>>,
+    buffer_len: usize,
+    output: mpsc::Sender<Output>,
+}
+
+impl Worker {
+    fn run(mut self, jobs: Receiver<Job>) {
+        while let Ok(job) = jobs.recv() {
+            self.follow_keys();
+            let output = match job {
+                Job::Encrypt { packets, compress } => Output::Sent(self.encrypt(packets, compress)),
+                Job::Decrypt(packets) => Output::Received(
+                    packets
+                        .into_iter()
+                        .map(|packet| {
+                            let received = self.decrypt(&packet);
+                            (packet, received)
+                        })
+                        .collect(),
+                ),
+                Job::Tick => match self.tick() {
+                    Some(datagram) => Output::Sent(vec![(datagram, 0)]),
+                    None => continue,
+                },
+            };
+            if matches!(&output, Output::Sent(sent) if sent.is_empty()) {
+                continue;
+            }
+            // The tunnel loop has stopped.
+            if self.output.blocking_send(output).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn follow_keys(&mut self) {
+        let generation = self.shared.generation.load(Ordering::Acquire);
+        if generation == self.generation {
+            return;
+        }
+        let keys = self
+            .shared
+            .keys
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.tunnel = keys.tunnel(self.lane);
+        self.generation = generation;
+        debug!("WireGuard crypto lane {} switched keys", self.lane);
+    }
+
+    fn encrypt(&mut self, packets: Vec<(Vec<u8>, u8)>, compress: bool) -> Vec<(Vec<u8>, u8)> {
+        let mut sent = Vec::with_capacity(packets.len());
+        let mut used = Vec::with_capacity(packets.len());
+        for (packet, tos) in packets {
+            let compressed = if compress {
+                compress_counted(&packet, &self.stats.tunnel)
+            } else {
+                None
+            };
+            let plain = compressed.as_deref().unwrap_or(&packet);
+            let mut datagram = self.take_buffer(std::cmp::max(plain.len() + 32, HANDSHAKE_LEN));
+            let written = match self.tunnel.encapsulate(plain, &mut datagram) {
+                TunnResult::WriteToNetwork(datagram) => Some(datagram.len()),
+                TunnResult::Err(e) => {
+                    debug!(
+                        "WireGuard crypto lane {} encapsulate error: {:?}",
+                        self.lane, e
+                    );
+                    None
+                }
+                _ => None,
+            };
+            match written {
+                Some(len) => {
+                    datagram.truncate(len);
+                    sent.push((datagram, tos));
+                }
+                None => self.spare.push(datagram),
+            }
+            used.push(packet);
+        }
+        self.pool.put(used.into_iter());
+        sent
+    }
+
+    fn decrypt(&mut self, packet: &NetPacket) -> Received {
+        let mut buffer = self.take_buffer(self.buffer_len);
+        let (len, wrap): (usize, fn(Vec<u8>) -> Received) =
+            match self
+                .tunnel
+                .decapsulate(Some(packet.src.ip()), &packet.data, &mut buffer)
+            {
+                TunnResult::WriteToNetwork(reply)
+                    if wg_packet_type(reply) == Some(WG_COOKIE_REPLY) =>
+                {
+                    (reply.len(), Received::Cookie)
+                }
+                TunnResult::WriteToNetwork(reply) => {
+                    (reply.len(), |reply| Received::Answered(vec![reply]))
+                }
+                TunnResult::WriteToTunnelV4(data, _) | TunnResult::WriteToTunnelV6(data, _) => {
+                    (data.len(), Received::Data)
+                }
+                TunnResult::Done => {
+                    self.spare.push(buffer);
+                    return Received::Empty;
+                }
+                TunnResult::Err(e) => {
+                    self.spare.push(buffer);
+                    return Received::Failed(e);
+                }
+            };
+        buffer.truncate(len);
+        let mut received = wrap(buffer);
+        if let Received::Answered(answers) = &mut received {
+            // The packets boringtun held back until the session was up.
+            loop {
+                let mut queued = self.take_buffer(self.buffer_len);
+                let len = match self.tunnel.decapsulate(None, &[], &mut queued) {
+                    TunnResult::WriteToNetwork(datagram) => datagram.len(),
+                    _ => {
+                        self.spare.push(queued);
+                        break;
+                    }
+                };
+                queued.truncate(len);
+                answers.push(queued);
+            }
+        }
+        received
+    }
+
+    /// Runs the session's timers; the handshake or keepalive they call for, if any.
+    fn tick(&mut self) -> Option<Vec<u8>> {
+        let mut datagram = self.take_buffer(HANDSHAKE_LEN);
+        let len = match self.tunnel.update_timers(&mut datagram) {
+            TunnResult::WriteToNetwork(datagram) => Some(datagram.len()),
+            // An expired handshake or session: the lane starts a new one with its next
+            // packet, as an idle lane has nothing to keep up.
+            TunnResult::Err(e) => {
+                debug!("WireGuard crypto lane {} timer: {:?}", self.lane, e);
+                None
+            }
+            _ => None,
+        };
+        match len {
+            Some(len) => {
+                datagram.truncate(len);
+                Some(datagram)
+            }
+            None => {
+                self.spare.push(datagram);
+                None
+            }
+        }
+    }
+
+    /// A `len`-byte buffer, reused from the pool when one has come back.
+    fn take_buffer(&mut self, len: usize) -> Vec<u8> {
+        if self.spare.is_empty() {
+            self.pool.refill(&mut self.spare);
+        }
+        let mut buffer = self.spare.pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::config::Config;
+
+    fn keys(private: &StaticSecret, peer: &StaticSecret) -> LaneKeys {
+        LaneKeys {
+            private_key: Zeroizing::new(private.to_bytes()),
+            peer_public_key: PublicKey::from(peer).to_bytes(),
+            preshared_key: None,
+            rate_limiter: None,
+        }
+    }
+
+    fn datagram(data: Vec<u8>) -> NetPacket {
+        NetPacket {
+            link_index: 0,
+            src: SocketAddr::from((Ipv4Addr::LOCALHOST, 51820)),
+            ecn: 0,
+            data,
+        }
+    }
+
+    fn lanes(count: usize, keys: LaneKeys) -> (Lanes, mpsc::Receiver<Output>) {
+        let (tx, rx) = mpsc::channel(OUTPUT_DEPTH);
+        let lanes = Lanes::start(
+            count,
+            keys,
+            &Arc::default(),
+            &Arc::new(StatsRegistry::new(&Config::default())),
+            1500,
+            tx,
+        )
+        .unwrap();
+        (lanes, rx)
+    }
+
+    async fn sent(output: &mut mpsc::Receiver<Output>) -> Vec<Vec<u8>> {
+        match output.recv().await {
+            Some(Output::Sent(sent)) => sent.into_iter().map(|(datagram, _)| datagram).collect(),
+            _ => panic!("expected datagrams to send"),
+        }
+    }
+
+    async fn received(output: &mut mpsc::Receiver<Output>) -> Received {
+        match output.recv().await {
+            Some(Output::Received(mut received)) => received.remove(0).1,
+            _ => panic!("expected a decrypted datagram"),
+        }
+    }
+
+    #[test]
+    fn datagrams_are_routed_by_the_lane_in_their_index() {
+        for lane in [0, 1, 7, 255] {
+            assert_eq!(session_index(lane) & 0xff, lane as u32);
+            // boringtun numbers its sessions `index << 8` onwards.
+            assert_eq!(lane_of_index(session_index(lane) << 8 | 3), lane);
+        }
+        let mut message = vec![0u8; 32];
+        message[..4].copy_from_slice(&[4, 0, 0, 0]);
+        message[4..8].copy_from_slice(&(2u32 << 8).to_le_bytes());
+        assert_eq!(lane_of(&message), Some((2, false)));
+        message[0] = 1;
+        assert_eq!(lane_of(&message), Some((2, true)));
+        message[0] = 2;
+        message[8..12].copy_from_slice(&(5u32 << 8).to_le_bytes());
+        assert_eq!(lane_of(&message), Some((5, false)));
+        assert_eq!(lane_of(b"VTBD\x01"), None);
+
+        let (mut lanes, _output) = lanes(
+            4,
+            keys(
+                &StaticSecret::from(rand::random::<[u8; 32]>()),
+                &StaticSecret::from(rand::random::<[u8; 32]>()),
+            ),
+        );
+        // Until the peer announces its lanes, its initiations all go to lane 0.
+        message[0] = 1;
+        assert!(lanes.route_inbound(datagram(message.clone())).is_some());
+        assert_eq!(lanes.set_peer_lanes(8), 4);
+        assert!(lanes.route_inbound(datagram(message.clone())).is_none());
+        message[0] = 4;
+        message[4..8].copy_from_slice(&(9u32 << 8).to_le_bytes());
+        assert!(lanes.route_inbound(datagram(message)).is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lanes_carry_a_session_of_their_own() {
+        let (client_key, server_key) = (
+            StaticSecret::from(rand::random::<[u8; 32]>()),
+            StaticSecret::from(rand::random::<[u8; 32]>()),
+        );
+        let (mut client, mut client_output) = lanes(2, keys(&client_key, &server_key));
+        let (mut server, mut server_output) = lanes(2, keys(&server_key, &client_key));
+        client.set_peer_lanes(2);
+        server.set_peer_lanes(2);
+        let stats = TunnelCounters::default();
+        let pool = BufferPool::default();
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&40u16.to_be_bytes());
+        packet[9] = 17;
+
+        // The first packet waits for the lane's handshake.
+        client.queue_outbound(1, packet.clone(), 0);
+        client.dispatch(false, &pool, &stats);
+        let initiation = sent(&mut client_output).await.remove(0);
+        assert!(server.route_inbound(datagram(initiation)).is_none());
+        server.dispatch(false, &pool, &stats);
+        let Received::Answered(mut response) = received(&mut server_output).await else {
+            panic!("expected a handshake response");
+        };
+        assert!(client.route_inbound(datagram(response.remove(0))).is_none());
+        client.dispatch(false, &pool, &stats);
+        let Received::Answered(queued) = received(&mut client_output).await else {
+            panic!("expected the queued packet");
+        };
+        // A keepalive confirming the session, then the packet that waited for it.
+        let [_, data] = &queued[..] else {
+            panic!("expected a keepalive and one data packet");
+        };
+        assert!(server.route_inbound(datagram(data.clone())).is_none());
+        server.dispatch(false, &pool, &stats);
+        let Received::Data(delivered) = received(&mut server_output).await else {
+            panic!("expected the packet for the TUN device");
+        };
+        assert_eq!(delivered, packet);
+    }
+}
@@ -0,0 +1,238 @@
+//! Two bonded peers talking through impaired [`SimLink`] relays.
+
+use std::sync::atomic::Ordering;
+
+use super::bench::{
+    bench_config, bench_links, encapsulate, handshake, ipv4_packet, tunnel_pair, MemoryDevice,
+};
+use super::*;
+use crate::netsim::{Impairment, SimLink};
+
+const HEALTH_TIMEOUT: Duration = Duration::from_millis(300);
+const HEALTH_INTERVAL: Duration = Duration::from_millis(50);
+const PACKET_SIZE: usize = 1000;
+
+struct Peer {
+    tunnel: Tunn,
+    links: LinkManager,
+    net_rx: mpsc::Receiver<NetPacket>,
+    device: MemoryDevice,
+    out_buf: Vec<u8>,
+}
+
+impl Peer {
+    async fn receive(&mut self, packet: NetPacket, epoch: Instant) {
+        self.links.record_rx(packet.link_index, Instant::now());
+        handle_incoming(
+            &mut self.tunnel,
+            &self.device,
+            &mut self.links,
+            &mut self.out_buf,
+            epoch,
+            packet,
+        )
+        .await
+        .unwrap();
+    }
+
+    fn delivered(&self) -> u64 {
+        self.device.packets.load(Ordering::Relaxed)
+    }
+
+    fn link_rx(&self, index: usize) -> u64 {
+        self.links.links[index]
+            .stats
+            .rx_packets
+            .load(Ordering::Relaxed)
+    }
+}
+
+/// A client whose links reach the server through one relay each.
+async fn bonded_pair(
+    mode: BondingMode,
+    impairments: Vec<Impairment>,
+) -> (Peer, Peer, Vec<SimLink>) {
+    let (mut client_tunnel, mut server_tunnel) = tunnel_pair();
+    handshake(&mut client_tunnel, &mut server_tunnel).unwrap();
+    let network = NetworkConfig {
+        buffer_size: 2048,
+        ..Config::default().network
+    };
+
+    let count = impairments.len();
+    let (server_links, server_rx) =
+        bench_links(&bench_config(count, |_| None), &network, mode, None)
+            .await
+            .unwrap();
+    let mut relays = Vec::new();
+    for (link, impairment) in server_links.links.iter().zip(impairments) {
+        let upstream = link.socket.local_addr().unwrap();
+        relays.push(SimLink::spawn(upstream, impairment).await.unwrap());
+    }
+    let endpoints: Vec<String> = relays
+        .iter()
+        .map(|relay| relay.addr().to_string())
+        .collect();
+    let (client_links, client_rx) = bench_links(
+        &bench_config(count, |index| Some(endpoints[index].clone())),
+        &network,
+        mode,
+        Some(HEALTH_TIMEOUT),
+    )
+    .await
+    .unwrap();
+
+    let peer = |tunnel, links, net_rx| Peer {
+        tunnel,
+        links,
+        net_rx,
+        device: MemoryDevice::default(),
+        out_buf: vec![0u8; 2048],
+    };
+    (
+        peer(client_tunnel, client_links, client_rx),
+        peer(server_tunnel, server_links, server_rx),
+        relays,
+    )
+}
+
+/// Sends `count` packets `interval` apart while both peers handle what arrives and the
+/// client runs its health checks, then keeps going for `settle` so stragglers land.
+async fn exchange(
+    client: &mut Peer,
+    server: &mut Peer,
+    count: usize,
+    interval: Duration,
+    settle: Duration,
+    epoch: Instant,
+) {
+    let packet = ipv4_packet(PACKET_SIZE);
+    let mut out_buf = vec![0u8; 2048];
+    let mut send_timer = tokio::time::interval(interval);
+    let mut health_timer = tokio::time::interval(HEALTH_INTERVAL);
+    let mut sent = 0usize;
+    let mut deadline = None;
+
+    loop {
+        if sent == count && deadline.is_none() {
+            deadline = Some(tokio::time::Instant::now() + settle);
+        }
+        let finished = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = send_timer.tick(), if sent < count => {
+                let data = encapsulate(&mut client.tunnel, &packet, &mut out_buf).unwrap().to_vec();
+                client.links.send_packet(data).await.unwrap();
+                sent += 1;
+            }
+            _ = health_timer.tick() => {
+                client.links.check_degraded(Instant::now());
+                client.links.send_health_pings(epoch).await.unwrap();
+            }
+            Some(packet) = client.net_rx.recv() => client.receive(packet, epoch).await,
+            Some(packet) = server.net_rx.recv() => server.receive(packet, epoch).await,
+            _ = finished => return,
+        }
+    }
+}
+
+#[tokio::test]
+async fn aggregate_spreads_load_over_impaired_links() {
+    let impairment = Impairment {
+        loss: 0.02,
+        latency: Duration::from_millis(20),
+        jitter: Duration::from_millis(10),
+        reorder: 0.05,
+        rate_bps: Some(20_000_000),
+    };
+    let (mut client, mut server, _relays) =
+        bonded_pair(BondingMode::Aggregate, vec![impairment.clone(), impairment]).await;
+
+    let epoch = Instant::now();
+    exchange(
+        &mut client,
+        &mut server,
+        400,
+        Duration::from_millis(1),
+        Duration::from_millis(200),
+        epoch,
+    )
+    .await;
+
+    // 2% loss per link; reordered packets still pass the replay window.
+    assert!(
+        server.delivered() >= 360,
+        "delivered {}",
+        server.delivered()
+    );
+    for index in 0..2 {
+        assert!(
+            server.link_rx(index) >= 150,
+            "link {} rx {}",
+            index,
+            server.link_rx(index)
+        );
+    }
+    assert_eq!(client.links.stats.snapshot().failovers, 0);
+}
+
+#[tokio::test]
+async fn failover_moves_to_standby_when_primary_blackholes() {
+    let impairment = Impairment {
+        latency: Duration::from_millis(10),
+        jitter: Duration::from_millis(2),
+        ..Impairment::default()
+    };
+    let (mut client, mut server, relays) = bonded_pair(
+        BondingMode::Failover,
+        vec![impairment.clone(), impairment.clone()],
+    )
+    .await;
+    let epoch = Instant::now();
+    let settle = Duration::from_millis(100);
+
+    exchange(
+        &mut client,
+        &mut server,
+        100,
+        Duration::from_millis(2),
+        settle,
+        epoch,
+    )
+    .await;
+    assert_eq!(server.delivered(), 100);
+    let standby_before = server.link_rx(1);
+    // Only health pings reach the standby while the primary is healthy.
+    assert!(standby_before < 20, "standby rx {}", standby_before);
+
+    relays[0].set(Impairment {
+        loss: 1.0,
+        ..impairment
+    });
+    exchange(
+        &mut client,
+        &mut server,
+        300,
+        Duration::from_millis(2),
+        settle,
+        epoch,
+    )
+    .await;
+
+    // A send that leaves the socket clears `down_since`, so ask the way the scheduler does.
+    let (backoff, timeout) = (client.links.error_backoff, client.links.health_timeout);
+    assert!(!client.links.links[0].is_available(Instant::now(), backoff, timeout));
+    assert_eq!(client.links.stats.snapshot().failovers, 1);
+    // Packets sent before the health timeout expired are lost with the primary.
+    let moved = server.link_rx(1) - standby_before;
+    assert!(moved >= 100, "standby carried {}", moved);
+    assert!(
+        server.delivered() >= 200,
+        "delivered {}",
+        server.delivered()
+    );
+}
@@ -0,0 +1,296 @@
+//! Per-link send queues. Each link's datagrams are written by a task of its own, so a link
+//! whose socket buffer is full holds up only its own queue; once that queue is full the
+//! scheduler passes the link over and the other links carry on undelayed. With
+//! `network.io_backend: io_uring` that task is a ring thread instead.
+//!
+//! Each datagram is queued with the TOS byte it goes out with, and the socket is marked
+//! just before the run of datagrams that share it, so a marking never outruns the queue.
+//! Once written, its buffer goes back to a [`BufferPool`] for the tunnel loop to encrypt
+//! the next one into.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::batch;
+use crate::stats::{self, LinkCounters};
+
+/// Most buffers kept for reuse; more are freed.
+const POOL_LIMIT: usize = 4096;
+
+/// Datagram buffers the send queues have written, so steady traffic allocates none.
+#[derive(Default)]
+pub(super) struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Moves every buffer handed back so far into `spare`.
+    pub(super) fn refill(&self, spare: &mut Vec<Vec<u8>>) {
+        spare.append(&mut self.free.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    pub(super) fn put(&self, buffers: impl Iterator<Item = Vec<u8>>) {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        let room = POOL_LIMIT.saturating_sub(free.len());
+        free.extend(buffers.take(room));
+    }
+}
+
+/// The last send the tunnel loop has not picked up yet.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum SendOutcome {
+    Failed { at: Instant, error: String },
+    Sent,
+}
+
+pub(super) struct LinkSender {
+    tx: mpsc::Sender<(SocketAddr, Vec<u8>, u8)>,
+    outcome: Arc<Mutex<Option<SendOutcome>>>,
+    stats: Arc<LinkCounters>,
+    /// `None` for a ring thread, which stops once `tx` is dropped.
+    task: Option<JoinHandle<()>>,
+}
+
+impl LinkSender {
+    /// Starts the task writing to `socket`, taking up to `batch_size` queued datagrams per
+    /// `sendmmsg`. `tos` is the TOS byte the socket marks with already.
+    pub(super) fn spawn(
+        socket: Arc<UdpSocket>,
+        stats: Arc<LinkCounters>,
+        pool: Arc<BufferPool>,
+        depth: usize,
+        batch_size: usize,
+        tos: u8,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(depth.max(1));
+        let outcome = Arc::new(Mutex::new(None));
+        let marking = Marking {
+            socket: Arc::clone(&socket),
+            marked: tos,
+        };
+        let task = tokio::spawn(run(
+            socket,
+            marking,
+            rx,
+            Arc::clone(&stats),
+            Arc::clone(&outcome),
+            pool,
+            batch_size.max(1),
+        ));
+        LinkSender {
+            tx,
+            outcome,
+            stats,
+            task: Some(task),
+        }
+    }
+
+    /// Starts a ring thread writing to `socket`, with up to `batch_size` sends in flight.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub(super) fn spawn_uring(
+        socket: Arc<UdpSocket>,
+        stats: Arc<LinkCounters>,
+        pool: Arc<BufferPool>,
+        depth: usize,
+        batch_size: usize,
+        tos: u8,
+    ) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::channel(depth.max(1));
+        let outcome = Arc::new(Mutex::new(None));
+        let report = Arc::clone(&outcome);
+        let counters = Arc::clone(&stats);
+        let mut marking = Marking {
+            socket: Arc::clone(&socket),
+            marked: tos,
+        };
+        let mark = move |tos| marking.mark(tos);
+        let recycle = move |sent: &mut Vec<(SocketAddr, Vec<u8>, u8)>| {
+            pool.put(sent.drain(..).map(|(_, packet, _)| packet));
+        };
+        crate::uring::spawn_socket_writer(socket, batch_size, rx, mark, recycle, move |result| {
+            let change = match result {
+                Ok(size) => {
+                    counters.record_tx(size);
+                    SendOutcome::Sent
+                }
+                // The datagram the socket refused is dropped.
+                Err(err) => {
+                    stats::add(&counters.tx_errors, 1);
+                    SendOutcome::Failed {
+                        at: Instant::now(),
+                        error: err.to_string(),
+                    }
+                }
+            };
+            *report.lock().unwrap_or_else(PoisonError::into_inner) = Some(change);
+        })?;
+        Ok(LinkSender {
+            tx,
+            outcome,
+            stats,
+            task: None,
+        })
+    }
+
+    /// Queues `packet` for `remote`, marked with `tos`, without waiting. Hands `packet`
+    /// back when the queue is full, which is counted in `tx_queue_full`, or closed.
+    pub(super) fn send(&self, remote: SocketAddr, packet: Vec<u8>, tos: u8) -> Result<(), Vec<u8>> {
+        match self.tx.try_send((remote, packet, tos)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full((_, packet, _))) => {
+                stats::add(&self.stats.tx_queue_full, 1);
+                Err(packet)
+            }
+            Err(TrySendError::Closed((_, packet, _))) => Err(packet),
+        }
+    }
+
+    pub(super) fn take_outcome(&self) -> Option<SendOutcome> {
+        self.outcome
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+impl Drop for LinkSender {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// The TOS byte a link socket marks its sends with; the socket is only touched when it
+/// changes.
+struct Marking {
+    socket: Arc<UdpSocket>,
+    marked: u8,
+}
+
+impl Marking {
+    fn mark(&mut self, tos: u8) {
+        if tos == self.marked {
+            return;
+        }
+        match super::set_tos(&self.socket, tos) {
+            Ok(()) => self.marked = tos,
+            Err(err) => debug!("WireGuard cannot mark TOS {:#x}: {}", tos, err),
+        }
+    }
+}
+
+async fn run(
+    socket: Arc<UdpSocket>,
+    mut marking: Marking,
+    mut rx: mpsc::Receiver<(SocketAddr, Vec<u8>, u8)>,
+    stats: Arc<LinkCounters>,
+    outcome: Arc<Mutex<Option<SendOutcome>>>,
+    pool: Arc<BufferPool>,
+    batch_size: usize,
+) {
+    let report = |change| *outcome.lock().unwrap_or_else(PoisonError::into_inner) = Some(change);
+    let mut queued = Vec::with_capacity(batch_size);
+    while rx.recv_many(&mut queued, batch_size).await > 0 {
+        let mut rest = &queued[..];
+        while let Some((remote, _, tos)) = rest.first() {
+            // Runs to one destination with one marking go out in a single `sendmmsg`.
+            let run = rest
+                .iter()
+                .take_while(|(to, _, marked)| to == remote && marked == tos)
+                .count();
+            let packets: Vec<&[u8]> = rest[..run].iter().map(|(_, p, _)| p.as_slice()).collect();
+            marking.mark(*tos);
+            let sent = match batch::send_batch(&socket, *remote, &packets).await {
+                Ok(sent) => {
+                    for packet in &packets[..sent] {
+                        stats.record_tx(packet.len());
+                    }
+                    report(SendOutcome::Sent);
+                    sent
+                }
+                // The datagram the socket refused is dropped.
+                Err(err) => {
+                    stats::add(&stats.tx_errors, 1);
+                    report(SendOutcome::Failed {
+                        at: Instant::now(),
+                        error: err.to_string(),
+                    });
+                    1
+                }
+            };
+            rest = &rest[sent.max(1)..];
+        }
+        pool.put(queued.drain(..).map(|(_, packet, _)| packet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn full_queue_turns_datagrams_away_and_failures_are_reported() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let remote = peer.local_addr().unwrap();
+        let stats = Arc::new(LinkCounters::default());
+        let pool = Arc::new(BufferPool::default());
+        let sender = LinkSender::spawn(
+            Arc::clone(&socket),
+            Arc::clone(&stats),
+            Arc::clone(&pool),
+            2,
+            8,
+            0,
+        );
+
+        // Nothing runs the task until this one yields, so the third datagram finds the
+        // queue full.
+        assert!(sender.send(remote, b"one".to_vec(), 0).is_ok());
+        assert!(sender.send(remote, b"two".to_vec(), 0).is_ok());
+        assert_eq!(
+            sender.send(remote, b"three".to_vec(), 0),
+            Err(b"three".to_vec())
+        );
+        assert_eq!(stats.tx_queue_full.load(Ordering::Relaxed), 1);
+        let mut buf = [0u8; 16];
+        for expected in [&b"one"[..], b"two"] {
+            let (size, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..size], expected);
+        }
+        assert_eq!(stats.tx_packets.load(Ordering::Relaxed), 2);
+        assert_eq!(sender.take_outcome(), Some(SendOutcome::Sent));
+        // Their buffers come back for the next datagrams.
+        let mut spare = Vec::new();
+        pool.refill(&mut spare);
+        spare.sort();
+        assert_eq!(spare, [b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(sender.take_outcome(), None);
+
+        // An IPv6 destination on an IPv4 socket fails; the next good send recovers.
+        assert!(sender
+            .send("[::1]:9".parse().unwrap(), b"lost".to_vec(), 0)
+            .is_ok());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(
+            sender.take_outcome(),
+            Some(SendOutcome::Failed { .. })
+        ));
+        assert!(sender.send(remote, b"back".to_vec(), 0).is_ok());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sender.take_outcome(), Some(SendOutcome::Sent));
+        assert_eq!(stats.tx_errors.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,31 @@
+package com.vrayo.vtrunkd;
+
+import android.net.VpnService;
+
+/**
+ * The vtrunkd engine, from libvtrunkd.so built with the {@code android} feature.
+ *
+ * <p>Calls throw {@link IllegalStateException} on failure. Stats are JSON in the shape
+ * {@code vtrunkd stats} prints.
+ */
+public final class VtrunkdNative {
+    static {
+        System.loadLibrary("vtrunkd");
+    }
+
+    private VtrunkdNative() {}
+
+    /**
+     * Starts bonding on the descriptor from {@code VpnService.Builder.establish()}, which the
+     * caller keeps open until after {@link #stop}. Link sockets are passed to
+     * {@code service.protect()} as they open. Returns the handle the other calls take.
+     */
+    public static native long start(String configYaml, int tunFd, VpnService service);
+
+    public static native void stop(long handle);
+
+    public static native String stats(long handle);
+
+    /** Sets a link to "up", "down" or "drain". */
+    public static native void setLinkState(long handle, String link, String state);
+}
@@ -0,0 +1,71 @@
+/*
+ * C API for the vtrunkd bonding engine (libvtrunkd from crates/vtrunkd-ffi).
+ *
+ * Functions returning int give 0 on success and -1 on failure; vtrunkd_last_error()
+ * then describes the failure. A tunnel handle must not be used from two threads at once.
+ */
+#ifndef VTRUNKD_H
+#define VTRUNKD_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct VtrunkdTunnel vtrunkd_tunnel;
+
+/* Called with each tunnel event as a JSON object, on one of the tunnel's threads. The
+ * string is only valid during the call. */
+typedef void (*vtrunkd_event_cb)(const char *event_json, void *user_data);
+
+/* Called with each link socket before it sends; return nonzero once the socket bypasses
+ * the VPN (Android's VpnService.protect()), or 0 to fail the link. */
+typedef int (*vtrunkd_protect_cb)(int fd, void *user_data);
+
+/* Message for the last failed call on this thread, or NULL. Valid until the next call. */
+const char *vtrunkd_last_error(void);
+
+const char *vtrunkd_version(void);
+
+/* Validates a YAML configuration (the daemon's format) and creates a stopped tunnel with
+ * its own worker threads. runtime.pin_cpus is ignored. Returns NULL on failure. */
+vtrunkd_tunnel *vtrunkd_tunnel_new(const char *config_yaml);
+
+/* Opens the TUN device and link sockets; needs the same privileges as the daemon. */
+int vtrunkd_tunnel_start(vtrunkd_tunnel *tunnel);
+
+/* Says goodbye to the peer and waits for the tunnel to close. A stopped tunnel cannot be
+ * started again; free it and create a new one. */
+int vtrunkd_tunnel_stop(vtrunkd_tunnel *tunnel);
+
+/* 1 while the tunnel is running, 0 otherwise. */
+int vtrunkd_tunnel_is_running(vtrunkd_tunnel *tunnel);
+
+/* Link and tunnel counters as JSON, as `vtrunkd stats` prints them. Free with
+ * vtrunkd_string_free(). NULL on failure. */
+char *vtrunkd_tunnel_stats_json(vtrunkd_tunnel *tunnel);
+
+/* Sets a link's administrative state: "up", "down" or "drain". Fails unless running. */
+int vtrunkd_tunnel_set_link_state(vtrunkd_tunnel *tunnel, const char *link, const char *state);
+
+/* Registers a callback for tunnel events, replacing any earlier one; NULL unregisters.
+ * Must not be called from inside the callback. user_data must stay valid until the
+ * callback is replaced or the tunnel is freed. */
+int vtrunkd_tunnel_set_event_callback(vtrunkd_tunnel *tunnel, vtrunkd_event_cb callback,
+                                      void *user_data);
+
+/* Registers a callback for every link socket opened from now on, in any tunnel; NULL
+ * removes it. user_data is passed back from any thread and must stay valid until the
+ * callback is replaced. */
+void vtrunkd_set_protect_callback(vtrunkd_protect_cb callback, void *user_data);
+
+/* Stops the tunnel if needed and releases it. NULL is ignored. */
+void vtrunkd_tunnel_free(vtrunkd_tunnel *tunnel);
+
+/* Frees a string returned by this library. NULL is ignored. */
+void vtrunkd_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* VTRUNKD_H */
@@ -0,0 +1,142 @@
+//! JNI entry points for `com.vrayo.vtrunkd.VtrunkdNative` (see `android/`), for an app
+//! that bonds Wi-Fi and cellular under a `VpnService`.
+//!
+//! The service hands over the descriptor from `Builder.establish()` and itself, so every
+//! link socket goes through `protect()` before it sends. Failures throw
+//! `IllegalStateException`.
+
+use std::sync::Arc;
+
+use jni::objects::{GlobalRef, JClass, JObject, JString, JValue};
+use jni::sys::{jint, jlong, jstring};
+use jni::{JNIEnv, JavaVM};
+use vtrunkd_core::config;
+use vtrunkd_core::control::LinkAdminState;
+use vtrunkd_core::error::{VtrunkdError, VtrunkdResult};
+use vtrunkd_core::protect;
+
+use crate::{new_tunnel, VtrunkdTunnel};
+
+fn throw(env: &mut JNIEnv, err: VtrunkdError) {
+    let _ = env.throw_new("java/lang/IllegalStateException", err.to_string());
+}
+
+fn java_string(env: &mut JNIEnv, value: &JString) -> VtrunkdResult<String> {
+    env.get_string(value)
+        .map(String::from)
+        .map_err(|e| VtrunkdError::Config(e.to_string()))
+}
+
+fn jni_error(err: jni::errors::Error) -> VtrunkdError {
+    VtrunkdError::Control(format!("JNI: {}", err))
+}
+
+/// Calls `service.protect(fd)` from whichever runtime thread opens the socket.
+fn protector(vm: JavaVM, service: GlobalRef) -> protect::Protector {
+    Arc::new(move |fd| {
+        let Ok(mut env) = vm.attach_current_thread_as_daemon() else {
+            return false;
+        };
+        env.call_method(&service, "protect", "(I)Z", &[JValue::Int(fd)])
+            .and_then(|result| result.z())
+            .unwrap_or(false)
+    })
+}
+
+unsafe fn tunnel<'a>(handle: jlong) -> VtrunkdResult<&'a mut VtrunkdTunnel> {
+    crate::handle(handle as *mut VtrunkdTunnel)
+}
+
+fn start(
+    env: &mut JNIEnv,
+    config_yaml: &JString,
+    tun_fd: jint,
+    service: &JObject,
+) -> VtrunkdResult<jlong> {
+    let mut config = config::parse_config(&java_string(env, config_yaml)?)?;
+    config.network.tun_fd = Some(tun_fd);
+    let vm = env.get_java_vm().map_err(jni_error)?;
+    let service = env.new_global_ref(service).map_err(jni_error)?;
+    protect::set(Some(protector(vm, service)));
+
+    let mut handle = Box::new(new_tunnel(config)?);
+    let started = {
+        let _guard = handle.runtime.enter();
+        handle.tunnel.start()
+    };
+    started?;
+    Ok(Box::into_raw(handle) as jlong)
+}
+
+/// Starts a tunnel on `tunFd`, which the caller keeps open until after [`stop`]. Returns
+/// the handle the other calls take.
+#[no_mangle]
+pub extern "system" fn Java_com_vrayo_vtrunkd_VtrunkdNative_start(
+    mut env: JNIEnv,
+    _class: JClass,
+    config_yaml: JString,
+    tun_fd: jint,
+    service: JObject,
+) -> jlong {
+    start(&mut env, &config_yaml, tun_fd, &service).unwrap_or_else(|e| {
+        protect::set(None);
+        throw(&mut env, e);
+        0
+    })
+}
+
+/// Stops the tunnel and releases the handle and the reference to the service.
+#[no_mangle]
+pub extern "system" fn Java_com_vrayo_vtrunkd_VtrunkdNative_stop(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    unsafe { crate::vtrunkd_tunnel_free(handle as *mut VtrunkdTunnel) };
+    protect::set(None);
+}
+
+/// Link and tunnel counters as JSON, as `vtrunkd stats` prints them.
+#[no_mangle]
+pub extern "system" fn Java_com_vrayo_vtrunkd_VtrunkdNative_stats(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) -> jstring {
+    let json = unsafe { tunnel(handle) }.and_then(|handle| {
+        serde_json::to_string(&handle.tunnel.stats())
+            .map_err(|e| VtrunkdError::Control(e.to_string()))
+    });
+    match json.and_then(|json| env.new_string(json).map_err(jni_error)) {
+        Ok(json) => json.into_raw(),
+        Err(e) => {
+            throw(&mut env, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Sets a link to "up", "down" or "drain", e.g. to drain cellular while Wi-Fi is good.
+#[no_mangle]
+pub extern "system" fn Java_com_vrayo_vtrunkd_VtrunkdNative_setLinkState(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    link: JString,
+    state: JString,
+) {
+    let result = (|| {
+        let handle = unsafe { tunnel(handle) }?;
+        let link = java_string(&mut env, &link)?;
+        let state: LinkAdminState = java_string(&mut env, &state)?
+            .parse()
+            .map_err(VtrunkdError::Control)?;
+        handle
+            .runtime
+            .block_on(handle.tunnel.set_link_state(&link, state))
+            .map(|_| ())
+    })();
+    if let Err(e) = result {
+        throw(&mut env, e);
+    }
+}
@@ -0,0 +1,370 @@
+//! C API over [`vtrunkd_core::Tunnel`] for router firmware and non-Rust management planes.
+//!
+//! `include/vtrunkd.h` is the matching header; keep the two in step. Functions returning
+//! `int` give 0 on success and -1 on failure, with the reason in [`vtrunkd_last_error`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use vtrunkd_core::config::{self, Config, RuntimeConfig};
+use vtrunkd_core::control::LinkAdminState;
+use vtrunkd_core::error::{VtrunkdError, VtrunkdResult};
+use vtrunkd_core::protect;
+use vtrunkd_core::Tunnel;
+
+#[cfg(feature = "android")]
+mod android;
+
+/// Called with each tunnel event as a JSON object, on a runtime worker thread.
+pub type VtrunkdEventCallback = extern "C" fn(event_json: *const c_char, user_data: *mut c_void);
+/// Called with each link socket before it sends; returns nonzero once it bypasses the VPN.
+pub type VtrunkdProtectCallback = extern "C" fn(fd: c_int, user_data: *mut c_void) -> c_int;
+
+/// Opaque handle behind `vtrunkd_tunnel *`.
+pub struct VtrunkdTunnel {
+    tunnel: Tunnel,
+    event_task: Option<JoinHandle<()>>,
+    // Dropped last so the tunnel and event task are gone before its threads.
+    runtime: Runtime,
+}
+
+struct UserData(*mut c_void);
+
+impl UserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+// The caller promises the pointer may be used from any thread.
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn status(result: VtrunkdResult<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e.to_string());
+            -1
+        }
+    }
+}
+
+unsafe fn handle<'a>(tunnel: *mut VtrunkdTunnel) -> VtrunkdResult<&'a mut VtrunkdTunnel> {
+    tunnel
+        .as_mut()
+        .ok_or_else(|| VtrunkdError::Control("Null tunnel handle".to_string()))
+}
+
+unsafe fn string_arg<'a>(value: *const c_char, what: &str) -> VtrunkdResult<&'a str> {
+    if value.is_null() {
+        return Err(VtrunkdError::Config(format!("Missing {}", what)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| VtrunkdError::Config(format!("{} is not valid UTF-8", what)))
+}
+
+fn new_tunnel(config: Config) -> VtrunkdResult<VtrunkdTunnel> {
+    // Pinning would change the affinity of the caller's thread, so it is left to the host.
+    let settings = RuntimeConfig {
+        pin_cpus: None,
+        ..config.runtime.clone().unwrap_or_default()
+    };
+    let runtime = vtrunkd_core::runtime::build(&settings)?;
+    let tunnel = Tunnel::builder().config(config).build()?;
+    Ok(VtrunkdTunnel {
+        tunnel,
+        event_task: None,
+        runtime,
+    })
+}
+
+/// Waits for the event task to finish so no callback is running once this returns.
+fn stop_events(handle: &mut VtrunkdTunnel) {
+    if let Some(task) = handle.event_task.take() {
+        task.abort();
+        let _ = handle.runtime.block_on(task);
+    }
+}
+
+/// The message for the last failed call on this thread, or NULL. Valid until the next call.
+#[no_mangle]
+pub extern "C" fn vtrunkd_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+#[no_mangle]
+pub extern "C" fn vtrunkd_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// Validates a YAML configuration and creates a stopped tunnel; NULL on failure.
+///
+/// # Safety
+/// `config_yaml` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_new(config_yaml: *const c_char) -> *mut VtrunkdTunnel {
+    let config = string_arg(config_yaml, "config").and_then(config::parse_config);
+    match config.and_then(new_tunnel) {
+        Ok(tunnel) => Box::into_raw(Box::new(tunnel)),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Opens the TUN device and link sockets; needs the same privileges as the daemon.
+///
+/// # Safety
+/// `tunnel` must come from [`vtrunkd_tunnel_new`] and not be used concurrently.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_start(tunnel: *mut VtrunkdTunnel) -> c_int {
+    status(handle(tunnel).and_then(|handle| {
+        let _guard = handle.runtime.enter();
+        handle.tunnel.start()
+    }))
+}
+
+/// Says goodbye to the peer and waits for the tunnel to close. A stopped tunnel cannot be
+/// started again; free it and create a new one.
+///
+/// # Safety
+/// As for [`vtrunkd_tunnel_start`].
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_stop(tunnel: *mut VtrunkdTunnel) -> c_int {
+    status(handle(tunnel).and_then(|handle| handle.runtime.block_on(handle.tunnel.stop())))
+}
+
+/// Returns 1 while the tunnel task is alive, 0 otherwise.
+///
+/// # Safety
+/// As for [`vtrunkd_tunnel_start`].
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_is_running(tunnel: *mut VtrunkdTunnel) -> c_int {
+    handle(tunnel).map_or(0, |handle| handle.tunnel.is_running() as c_int)
+}
+
+/// Link and tunnel counters as JSON, in the shape `vtrunkd stats` prints. Free the
+/// result with [`vtrunkd_string_free`].
+///
+/// # Safety
+/// As for [`vtrunkd_tunnel_start`].
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_stats_json(tunnel: *mut VtrunkdTunnel) -> *mut c_char {
+    let json = handle(tunnel).and_then(|handle| {
+        let json = serde_json::to_string(&handle.tunnel.stats())
+            .map_err(|e| VtrunkdError::Control(e.to_string()))?;
+        CString::new(json).map_err(|e| VtrunkdError::Control(e.to_string()))
+    });
+    match json {
+        Ok(json) => json.into_raw(),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Sets a link to "up", "down" or "drain", as `vtrunkd link <state> <name>` does.
+///
+/// # Safety
+/// As for [`vtrunkd_tunnel_start`]; `link` and `state` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_set_link_state(
+    tunnel: *mut VtrunkdTunnel,
+    link: *const c_char,
+    state: *const c_char,
+) -> c_int {
+    status((|| {
+        let handle = handle(tunnel)?;
+        let link = string_arg(link, "link name")?;
+        let state: LinkAdminState = string_arg(state, "link state")?
+            .parse()
+            .map_err(VtrunkdError::Control)?;
+        handle
+            .runtime
+            .block_on(handle.tunnel.set_link_state(link, state))
+            .map(|_| ())
+    })())
+}
+
+/// Registers `callback` for tunnel events, replacing any earlier one; NULL unregisters.
+/// Events published while no callback is registered are not replayed.
+///
+/// # Safety
+/// As for [`vtrunkd_tunnel_start`], and not from inside the callback. `user_data` is passed
+/// back untouched and must stay valid until the callback is replaced or the tunnel is freed.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_set_event_callback(
+    tunnel: *mut VtrunkdTunnel,
+    callback: Option<VtrunkdEventCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    status(handle(tunnel).map(|handle| {
+        stop_events(handle);
+        let Some(callback) = callback else {
+            return;
+        };
+        let mut events = handle.tunnel.events();
+        let user_data = UserData(user_data);
+        handle.event_task = Some(handle.runtime.spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                // Events are plain strings and numbers, so neither step fails in practice.
+                if let Some(json) = serde_json::to_string(&event)
+                    .ok()
+                    .and_then(|json| CString::new(json).ok())
+                {
+                    callback(json.as_ptr(), user_data.get());
+                }
+            }
+        }));
+    }))
+}
+
+/// Registers `callback` for every link socket opened from now on, in any tunnel, as
+/// Android's `VpnService.protect()` needs; NULL removes it. A zero return fails the link.
+///
+/// # Safety
+/// `user_data` is passed back untouched, from any thread, and must stay valid until the
+/// callback is replaced.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_set_protect_callback(
+    callback: Option<VtrunkdProtectCallback>,
+    user_data: *mut c_void,
+) {
+    let user_data = UserData(user_data);
+    protect::set(callback.map(|callback| -> protect::Protector {
+        Arc::new(move |fd| callback(fd, user_data.get()) != 0)
+    }));
+}
+
+/// Stops the tunnel if it is running and releases the handle. NULL is ignored.
+///
+/// # Safety
+/// `tunnel` must come from [`vtrunkd_tunnel_new`] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_tunnel_free(tunnel: *mut VtrunkdTunnel) {
+    if tunnel.is_null() {
+        return;
+    }
+    let mut handle = Box::from_raw(tunnel);
+    stop_events(&mut handle);
+    let _ = handle.runtime.block_on(handle.tunnel.stop());
+}
+
+/// Frees a string returned by this library. NULL is ignored.
+///
+/// # Safety
+/// `value` must come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vtrunkd_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use vtrunkd_core::config::Config;
+    use vtrunkd_core::events::{self, TunnelEvent};
+
+    fn default_yaml() -> CString {
+        CString::new(serde_yaml::to_string(&Config::default()).unwrap()).unwrap()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(vtrunkd_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn invalid_config_reports_why() {
+        let yaml = CString::new("network: [").unwrap();
+        let tunnel = unsafe { vtrunkd_tunnel_new(yaml.as_ptr()) };
+        assert!(tunnel.is_null());
+        assert!(!last_error().is_empty());
+
+        assert_eq!(unsafe { vtrunkd_tunnel_start(ptr::null_mut()) }, -1);
+        assert!(last_error().contains("Null tunnel handle"));
+    }
+
+    #[test]
+    fn stopped_tunnel_reports_stats_and_rejects_link_control() {
+        let tunnel = unsafe { vtrunkd_tunnel_new(default_yaml().as_ptr()) };
+        assert!(!tunnel.is_null());
+        assert_eq!(unsafe { vtrunkd_tunnel_is_running(tunnel) }, 0);
+
+        let stats = unsafe { vtrunkd_tunnel_stats_json(tunnel) };
+        let json: serde_json::Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(stats) }.to_str().unwrap()).unwrap();
+        assert_eq!(json["links"].as_array().map(Vec::len), Some(1));
+        unsafe { vtrunkd_string_free(stats) };
+
+        let link = CString::new("link-0").unwrap();
+        let bogus = CString::new("sideways").unwrap();
+        let drain = CString::new("drain").unwrap();
+        let set = |state: &CString| unsafe {
+            vtrunkd_tunnel_set_link_state(tunnel, link.as_ptr(), state.as_ptr())
+        };
+        assert_eq!(set(&bogus), -1);
+        assert_eq!(set(&drain), -1);
+        assert!(last_error().contains("not running"));
+
+        assert_eq!(unsafe { vtrunkd_tunnel_stop(tunnel) }, 0);
+        unsafe { vtrunkd_tunnel_free(tunnel) };
+    }
+
+    extern "C" fn forward_event(event_json: *const c_char, user_data: *mut c_void) {
+        let tx = unsafe { &*(user_data as *const mpsc::Sender<String>) };
+        let json = unsafe { CStr::from_ptr(event_json) };
+        let _ = tx.send(json.to_string_lossy().into_owned());
+    }
+
+    #[test]
+    fn event_callback_receives_json() {
+        let tunnel = unsafe { vtrunkd_tunnel_new(default_yaml().as_ptr()) };
+        let (tx, rx) = mpsc::channel::<String>();
+        let user_data = &tx as *const mpsc::Sender<String> as *mut c_void;
+        assert_eq!(
+            unsafe { vtrunkd_tunnel_set_event_callback(tunnel, Some(forward_event), user_data) },
+            0
+        );
+
+        let sender = unsafe { &*tunnel }.tunnel.event_sender();
+        events::emit(
+            &sender,
+            TunnelEvent::LinkUp {
+                link: "link-0".to_string(),
+            },
+        );
+        let json = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(json.contains("link-0"), "{}", json);
+
+        unsafe { vtrunkd_tunnel_free(tunnel) };
+    }
+}
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vtrunkd_core::uci;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Anything that parses must serialize, as `showconf` does with it.
+    if let Ok(config) = uci::parse(text) {
+        let mut config = config.effective();
+        config.redact_secrets();
+        serde_yaml::to_string(&config).expect("serialize parsed config");
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vtrunkd_core::config::parse_config;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(yaml) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Anything that validates must also survive defaulting and re-serialization.
+    if let Ok(config) = parse_config(yaml) {
+        let mut config = config.effective();
+        config.redact_secrets();
+        serde_yaml::to_string(&config).expect("serialize validated config");
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vtrunkd_core::bond::{build_control_packet, parse_control_packet};
+
+// Control packets arrive unauthenticated on every link socket.
+fuzz_target!(|data: &[u8]| {
+    if let Some((message_type, token)) = parse_control_packet(data) {
+        assert_eq!(build_control_packet(message_type, token).as_slice(), data);
+    }
+});
@@ -0,0 +1,250 @@
+//! The address fields of the form. A tunnel address field takes one or more addresses,
+//! comma-separated, each with or without a `/prefix`; the first IPv4 one becomes the
+//! device's `address` and `netmask`, the rest (IPv6 among them) go under `addresses`.
+//! Server hosts and bind addresses may be IPv6, with or without brackets.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use vtrunkd_core::config as core_config;
+
+/// A tunnel address field, split the way the config takes it.
+#[derive(Default)]
+pub struct TunnelAddresses {
+    pub address: Option<String>,
+    pub netmask: Option<String>,
+    /// CIDR notation, set on the device after it is created (Linux only).
+    pub addresses: Vec<String>,
+}
+
+/// Parses the `label` field; `netmask` (dotted, `/24` or `24`) is the prefix of IPv4
+/// addresses written without one.
+pub fn tunnel_addresses(
+    label: &str,
+    input: &str,
+    netmask: &str,
+) -> Result<TunnelAddresses, String> {
+    let default_prefix = netmask_prefix(netmask)?;
+    let mut parsed = TunnelAddresses::default();
+    for entry in entries(input) {
+        let (ip, prefix) = if entry.contains('/') {
+            core_config::parse_cidr(entry).map_err(|e| format!("{}: {}", label, e))?
+        } else {
+            let ip: IpAddr = entry
+                .parse()
+                .map_err(|_| format!("{}: {} is not an IP address", label, entry))?;
+            let prefix = match ip {
+                IpAddr::V4(_) => default_prefix.ok_or_else(|| {
+                    format!("{}: give {} a /prefix or fill in the netmask", label, entry)
+                })?,
+                IpAddr::V6(_) => {
+                    return Err(format!(
+                        "{}: IPv6 addresses need a /prefix, such as {}/64",
+                        label, entry
+                    ))
+                }
+            };
+            (ip, prefix)
+        };
+        match ip {
+            IpAddr::V4(ip) if parsed.address.is_none() => {
+                parsed.address = Some(ip.to_string());
+                parsed.netmask = Some(prefix_netmask(prefix).to_string());
+            }
+            _ => parsed.addresses.push(format!("{}/{}", ip, prefix)),
+        }
+    }
+    if parsed.address.is_none() && parsed.addresses.is_empty() {
+        return Err(format!("{} is required", label));
+    }
+    Ok(parsed)
+}
+
+/// The first address of a tunnel address field, without its prefix.
+pub fn first_address(input: &str) -> Option<IpAddr> {
+    let entry = entries(input).next()?;
+    entry.split('/').next()?.parse().ok()
+}
+
+/// The config's tunnel addresses as the field shows them.
+pub fn field_of(network: &core_config::NetworkConfig) -> String {
+    network
+        .address
+        .iter()
+        .chain(network.addresses.iter().flatten())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A host name, an IPv4 address, or an IPv6 address with or without brackets.
+pub fn check_host(label: &str, host: &str) -> Result<Option<IpAddr>, String> {
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(Some(ip));
+    }
+    let valid = !bare.is_empty()
+        && !bare.contains(':')
+        && bare
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if valid {
+        Ok(None)
+    } else {
+        Err(format!("{} {} is not a host name or IP address", label, host))
+    }
+}
+
+pub fn check_bind(host: &str) -> Result<(), String> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map(|_| ())
+        .map_err(|_| {
+            format!(
+                "Server bind address {} must be an IP address, such as 0.0.0.0 or ::",
+                host
+            )
+        })
+}
+
+/// A link's bind address, which has to reach the server host when that is an address.
+pub fn check_link_bind(name: &str, bind: &str, server: Option<IpAddr>) -> Result<(), String> {
+    let bind: SocketAddr = bind.parse().map_err(|_| {
+        format!(
+            "Link {} bind must be ip:port, with IPv6 in brackets ([2001:db8::2]:0)",
+            name
+        )
+    })?;
+    match server {
+        Some(server) if !bind.ip().is_unspecified() && server.is_ipv4() != bind.is_ipv4() => {
+            Err(format!(
+                "Link {} binds to {} but the server host {} is the other IP version",
+                name,
+                bind.ip(),
+                server
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn entries(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+fn netmask_prefix(netmask: &str) -> Result<Option<u8>, String> {
+    let netmask = netmask.trim();
+    if netmask.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || format!("Netmask {} is not a dotted mask or a prefix length", netmask);
+    if let Ok(prefix) = netmask.trim_start_matches('/').parse::<u8>() {
+        return if prefix <= 32 {
+            Ok(Some(prefix))
+        } else {
+            Err(invalid())
+        };
+    }
+    let mask = u32::from(netmask.parse::<Ipv4Addr>().map_err(|_| invalid())?);
+    // Contiguous ones from the top: 255.255.0.255 is not a netmask.
+    if mask.leading_ones() + mask.trailing_zeros() != 32 {
+        return Err(invalid());
+    }
+    Ok(Some(mask.leading_ones() as u8))
+}
+
+fn prefix_netmask(prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(input: &str, netmask: &str) -> (Option<String>, Option<String>, Vec<String>) {
+        let parsed = tunnel_addresses("Client address", input, netmask).unwrap();
+        (parsed.address, parsed.netmask, parsed.addresses)
+    }
+
+    fn error(input: &str, netmask: &str) -> String {
+        match tunnel_addresses("Client address", input, netmask) {
+            Ok(_) => panic!("{} / {} was accepted", input, netmask),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn tunnel_addresses_take_cidr_and_ipv6() {
+        let address =
+            |address: &str, netmask: &str| (Some(address.to_string()), Some(netmask.to_string()));
+        let (first, mask, rest) = split("10.8.0.2", "255.255.255.0");
+        assert_eq!((first, mask), address("10.8.0.2", "255.255.255.0"));
+        assert!(rest.is_empty());
+        for netmask in ["/24", "24"] {
+            let (first, mask, _) = split("10.8.0.2", netmask);
+            assert_eq!((first, mask), address("10.8.0.2", "255.255.255.0"));
+        }
+
+        // A prefix of its own wins over the netmask field; later addresses go to `addresses`.
+        let (first, mask, rest) = split(" 10.8.0.2/30, fd00::2/64 ,10.9.0.2 ", "16");
+        assert_eq!((first, mask), address("10.8.0.2", "255.255.255.252"));
+        assert_eq!(rest, ["fd00::2/64", "10.9.0.2/16"]);
+
+        let (first, mask, rest) = split("fd00::2/64", "");
+        assert_eq!((first, mask), (None, None));
+        assert_eq!(rest, ["fd00::2/64"]);
+    }
+
+    #[test]
+    fn tunnel_addresses_reject_what_the_config_cannot_take() {
+        assert!(error("fd00::2", "24").contains("fd00::2/64"));
+        assert!(error("10.8.0.2", "").contains("netmask"));
+        assert!(error("10.8.0.2/33", "").contains("at most 32"));
+        assert!(error("10.8.0.300", "24").contains("not an IP address"));
+        assert!(error(" , ", "24").contains("is required"));
+        assert!(error("10.8.0.2", "255.255.0.255").contains("Netmask"));
+        assert!(error("10.8.0.2", "33").contains("Netmask"));
+        assert_eq!(prefix_netmask(0), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(prefix_netmask(32), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn fields_show_every_address() {
+        assert_eq!(
+            first_address("fd00::2/64, 10.8.0.2"),
+            "fd00::2".parse().ok()
+        );
+        assert_eq!(first_address(""), None);
+        let mut network = core_config::Config::default().network;
+        network.address = Some("10.8.0.2".to_string());
+        network.addresses = Some(vec!["fd00::2/64".to_string()]);
+        assert_eq!(field_of(&network), "10.8.0.2, fd00::2/64");
+    }
+
+    #[test]
+    fn hosts_and_binds_may_be_ipv6() {
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(check_host("Server host", "[2001:db8::1]"), Ok(Some(v6)));
+        assert_eq!(check_host("Server host", "2001:db8::1"), Ok(Some(v6)));
+        assert_eq!(check_host("Server host", "vps.example.com"), Ok(None));
+        assert!(check_host("Server host", "vps example").is_err());
+        assert!(check_host("Server host", "vps:51820").is_err());
+        assert!(check_host("Server host", "").is_err());
+
+        assert!(check_bind("::").is_ok());
+        assert!(check_bind("[::]").is_ok());
+        assert!(check_bind("0.0.0.0").is_ok());
+        assert!(check_bind("localhost").is_err());
+
+        let v4 = Some("203.0.113.1".parse().unwrap());
+        assert!(check_link_bind("wan", "[2001:db8::2]:0", Some(v6)).is_ok());
+        assert!(check_link_bind("wan", "[2001:db8::2]:0", v4)
+            .unwrap_err()
+            .contains("other IP version"));
+        assert!(check_link_bind("wan", "0.0.0.0:0", Some(v6)).is_ok());
+        assert!(check_link_bind("wan", "2001:db8::2:0", None).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Starting the Control Room at login: an XDG autostart entry on Linux, a LaunchAgent on
+//! macOS and a `Run` registry value on Windows, all for the current user. Which tunnel it
+//! then connects is the profiles' business (`profiles::reconnect_profile`).
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::fs;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::process::Command;
+
+use tauri::AppHandle;
+
+#[cfg(any(target_os = "linux", windows))]
+const ENTRY_NAME: &str = "vtrunkd Control Room";
+
+/// Whether the app is registered to start at login.
+#[tauri::command]
+pub fn autostart_enabled(app: AppHandle) -> Result<bool, String> {
+    registered(&app)
+}
+
+#[tauri::command]
+pub fn set_autostart(app: AppHandle, enabled: bool) -> Result<(), String> {
+    if enabled {
+        let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+        register(&app, &exe.to_string_lossy())
+    } else {
+        unregister(&app)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn entry_path(_app: &AppHandle) -> Result<PathBuf, String> {
+    let config = tauri::api::path::config_dir().ok_or("Unable to resolve ~/.config")?;
+    Ok(config.join("autostart").join("vtrunkd-control-room.desktop"))
+}
+
+#[cfg(target_os = "linux")]
+fn entry(_app: &AppHandle, exe: &str) -> String {
+    // The Exec key's quoting: inside double quotes, these four are escaped.
+    let mut quoted = String::new();
+    for c in exe.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    format!(
+        "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\"\nTerminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        ENTRY_NAME, quoted
+    )
+}
+
+/// launchd loads the agents in this directory at login.
+#[cfg(target_os = "macos")]
+fn entry_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let home = tauri::api::path::home_dir().ok_or("Unable to resolve the home directory")?;
+    Ok(home
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", agent_label(app))))
+}
+
+#[cfg(target_os = "macos")]
+fn entry(app: &AppHandle, exe: &str) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \x20 <key>Label</key>\n  <string>{}</string>\n\
+         \x20 <key>ProgramArguments</key>\n  <array>\n    <string>{}</string>\n  </array>\n\
+         \x20 <key>RunAtLoad</key>\n  <true/>\n\
+         </dict>\n</plist>\n",
+        escape(&agent_label(app)),
+        escape(exe)
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn agent_label(app: &AppHandle) -> String {
+    format!("{}.autostart", app.config().tauri.bundle.identifier)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn registered(app: &AppHandle) -> Result<bool, String> {
+    Ok(entry_path(app)?.exists())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn register(app: &AppHandle, exe: &str) -> Result<(), String> {
+    let path = entry_path(app)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, entry(app, exe))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn unregister(app: &AppHandle) -> Result<(), String> {
+    match fs::remove_file(entry_path(app)?) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove the login item: {}", e)),
+    }
+}
+
+#[cfg(windows)]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+#[cfg(windows)]
+fn registered(_app: &AppHandle) -> Result<bool, String> {
+    let output = Command::new("reg")
+        .args(["query", RUN_KEY, "/v", ENTRY_NAME])
+        .output()
+        .map_err(|e| format!("Failed to run reg: {}", e))?;
+    Ok(output.status.success())
+}
+
+#[cfg(windows)]
+fn register(_app: &AppHandle, exe: &str) -> Result<(), String> {
+    reg(&[
+        "add",
+        RUN_KEY,
+        "/v",
+        ENTRY_NAME,
+        "/t",
+        "REG_SZ",
+        "/d",
+        &format!("\"{}\"", exe),
+        "/f",
+    ])
+}
+
+#[cfg(windows)]
+fn unregister(app: &AppHandle) -> Result<(), String> {
+    if !registered(app)? {
+        return Ok(());
+    }
+    reg(&["delete", RUN_KEY, "/v", ENTRY_NAME, "/f"])
+}
+
+#[cfg(windows)]
+fn reg(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("reg")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run reg: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn registered(_app: &AppHandle) -> Result<bool, String> {
+    Ok(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn register(_app: &AppHandle, _exe: &str) -> Result<(), String> {
+    Err("Starting at login is not supported on this platform".to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn unregister(_app: &AppHandle) -> Result<(), String> {
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! "Test connection": the local daemon, each link's path to the server outside the tunnel,
+//! and the server's tunnel address through it, so a failure points at the hop that broke.
+
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+
+use serde::Serialize;
+use vtrunkd_core::stats::StatsSnapshot;
+use vtrunkd_core::{config as core_config, control};
+
+use crate::{addresses, query_stats, split_socket};
+
+const PING_COUNT: &str = "3";
+
+#[derive(Serialize)]
+pub struct Hop {
+    name: String,
+    target: String,
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Serialize)]
+pub struct ConnectionReport {
+    hops: Vec<Hop>,
+    /// Which part looks broken, in a sentence.
+    verdict: String,
+}
+
+/// A link as the test sees it: where it pings from and to.
+struct LinkTarget {
+    name: String,
+    source: Option<IpAddr>,
+    host: Option<String>,
+}
+
+#[tauri::command]
+pub async fn test_connection(
+    client_yaml: String,
+    server_address: String,
+) -> Result<ConnectionReport, String> {
+    let config = core_config::parse_config(&client_yaml)
+        .map_err(|e| format!("Invalid client config: {}", e))?;
+    // The field may list several addresses with prefixes; the first one is pinged.
+    let server_address = addresses::first_address(&server_address)
+        .ok_or_else(|| "Server tunnel address must be an IP address".to_string())?
+        .to_string();
+    tauri::async_runtime::spawn_blocking(move || check(&config, &server_address))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn check(config: &core_config::Config, server_address: &str) -> ConnectionReport {
+    let socket = control::socket_path(config.control_socket());
+    let snapshot = query_stats(&socket);
+    let links: Vec<LinkTarget> = config
+        .wireguard
+        .links
+        .iter()
+        .enumerate()
+        .map(|(index, link)| LinkTarget {
+            name: core_config::link_name(link, index),
+            source: link
+                .bind
+                .as_deref()
+                .and_then(|bind| bind.parse::<SocketAddr>().ok())
+                .map(|bind| bind.ip())
+                .filter(|ip| !ip.is_unspecified()),
+            host: link
+                .endpoint
+                .as_deref()
+                .and_then(split_socket)
+                .map(|(host, _)| host),
+        })
+        .collect();
+
+    // Each ping takes a few seconds, so they all run at once.
+    let (tunnel, link_pings) = std::thread::scope(|scope| {
+        let tunnel = scope.spawn(|| ping(server_address, None));
+        let pings: Vec<_> = links
+            .iter()
+            .map(|link| scope.spawn(move || link.host.as_deref().map(|h| ping(h, link.source))))
+            .collect();
+        let pings: Vec<Option<Result<f64, String>>> = pings
+            .into_iter()
+            .map(|ping| ping.join().unwrap_or(None))
+            .collect();
+        let tunnel = tunnel
+            .join()
+            .unwrap_or_else(|_| Err("ping failed".to_string()));
+        (tunnel, pings)
+    });
+
+    let state_of = |name: &str| {
+        snapshot.as_ref().ok().and_then(|snapshot| {
+            snapshot
+                .links
+                .iter()
+                .find(|link| link.name == name)
+                .and_then(|link| link.state.clone())
+        })
+    };
+    let mut hops = vec![daemon_hop(&snapshot, &socket.display().to_string())];
+    let mut links_up = 0;
+    let mut endpoints_answering = 0;
+    for (link, ping) in links.iter().zip(&link_pings) {
+        let state = state_of(&link.name);
+        let up = state.as_deref() == Some("up");
+        links_up += usize::from(up);
+        endpoints_answering += usize::from(matches!(ping, Some(Ok(_))));
+        let reach = match ping {
+            Some(Ok(ms)) => format!("ICMP {:.0} ms", ms),
+            Some(Err(e)) => format!("ICMP {}", e),
+            None => "no endpoint".to_string(),
+        };
+        hops.push(Hop {
+            name: format!("link {}", link.name),
+            target: link.host.clone().unwrap_or_else(|| "-".to_string()),
+            ok: up || (state.is_none() && matches!(ping, Some(Ok(_)))),
+            detail: match state {
+                Some(state) => format!("{}, {}", state, reach),
+                None => reach,
+            },
+        });
+    }
+    hops.push(Hop {
+        name: "tunnel".to_string(),
+        target: server_address.to_string(),
+        ok: tunnel.is_ok(),
+        detail: match &tunnel {
+            Ok(ms) => format!("{:.0} ms through the tunnel", ms),
+            Err(e) => e.clone(),
+        },
+    });
+
+    let verdict = if tunnel.is_ok() {
+        if links_up < links.len() && snapshot.is_ok() {
+            format!("The tunnel works, on {} of {} links.", links_up, links.len())
+        } else {
+            "The tunnel works end to end.".to_string()
+        }
+    } else if snapshot.is_err() {
+        "vtrunkd is not running here, or its control socket is not readable; start the tunnel."
+            .to_string()
+    } else if links_up == 0 && endpoints_answering == 0 {
+        "No link reaches the server: check each link's connection and the server host."
+            .to_string()
+    } else if links_up == 0 {
+        "The server answers but no link comes up: vtrunkd on the server is not running, its \
+         keys do not match this client, or its UDP ports are firewalled."
+            .to_string()
+    } else {
+        "Links are up but the server's tunnel address does not answer: check the server \
+         tunnel address and that the server allows ping."
+            .to_string()
+    };
+    ConnectionReport { hops, verdict }
+}
+
+fn daemon_hop(snapshot: &Result<StatsSnapshot, String>, socket: &str) -> Hop {
+    Hop {
+        name: "vtrunkd".to_string(),
+        target: socket.to_string(),
+        ok: snapshot.is_ok(),
+        detail: match snapshot {
+            Ok(snapshot) => format!(
+                "{} of {} links up",
+                snapshot
+                    .links
+                    .iter()
+                    .filter(|link| link.state.as_deref() == Some("up"))
+                    .count(),
+                snapshot.links.len()
+            ),
+            Err(e) => format!("no answer: {}", e),
+        },
+    }
+}
+
+/// Average round trip in ms of a few pings to `host`, sent from `source` when given.
+fn ping(host: &str, source: Option<IpAddr>) -> Result<f64, String> {
+    let v6 = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_ipv6());
+    let program = if v6 && cfg!(not(target_os = "linux")) {
+        "ping6"
+    } else {
+        "ping"
+    };
+    let mut command = Command::new(program);
+    command.args(["-c", PING_COUNT]);
+    #[cfg(target_os = "linux")]
+    command.args(["-W", "1"]);
+    #[cfg(not(target_os = "linux"))]
+    command.args(["-t", "5"]);
+    if let Some(source) = source {
+        let flag = if cfg!(target_os = "linux") { "-I" } else { "-S" };
+        command.args([flag, &source.to_string()]);
+    }
+    let output = command
+        .arg(host)
+        .output()
+        .map_err(|e| format!("{} failed: {}", program, e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let times: Vec<f64> = text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix("time="))
+        .filter_map(|time| time.trim_end_matches("ms").parse().ok())
+        .collect();
+    if !output.status.success() || times.is_empty() {
+        return Err("no reply".to_string());
+    }
+    Ok(times.iter().sum::<f64>() / times.len() as f64)
+}
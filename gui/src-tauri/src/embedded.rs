@@ -0,0 +1,191 @@
+//! The bonding engine built into the app (the `embedded` feature), so a desktop needs no
+//! separately installed `vtrunkd`. A start with no binary path and no elevation runs
+//! vtrunkd-core in this process; it serves the same control socket the daemon does, so the
+//! dashboard, events and connection test work unchanged. The app then needs the rights to
+//! create the TUN device itself, which is why elevated starts still run the binary.
+
+#[cfg(feature = "embedded")]
+pub use engine::Engine;
+pub use engine::start;
+
+/// Whether this build carries the engine, for the window to offer it.
+#[tauri::command]
+pub fn engine_built_in() -> bool {
+    cfg!(feature = "embedded")
+}
+
+#[cfg(not(feature = "embedded"))]
+mod engine {
+    use std::sync::Arc;
+
+    use tauri::AppHandle;
+
+    use crate::{Daemon, Launch, Tunnel};
+
+    pub fn start(
+        _app: &AppHandle,
+        _tunnel: &Arc<Tunnel>,
+        _launch: &Launch,
+    ) -> Result<Option<Daemon>, String> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "embedded")]
+mod engine {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, Once};
+
+    use tauri::{async_runtime, AppHandle};
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+    use vtrunkd_core::error::VtrunkdError;
+    use vtrunkd_core::{config as core_config, control, hooks};
+
+    use crate::{keychain, logs, watchdog, Daemon, Launch, Tunnel};
+
+    /// The tunnel the engine runs, whose name its log lines carry. The engine logs through
+    /// one global subscriber, so it runs one tunnel at a time.
+    static RUNNING: Mutex<Option<Arc<Tunnel>>> = Mutex::new(None);
+
+    pub struct Engine {
+        core: vtrunkd_core::Tunnel,
+        socket: PathBuf,
+    }
+
+    impl Engine {
+        /// The tunnel only ends on its own when the supervisor gives up on it.
+        pub fn try_wait(&mut self) -> Option<watchdog::Exit> {
+            if self.core.is_running() {
+                return None;
+            }
+            let status = match async_runtime::block_on(self.core.stop()) {
+                Ok(()) => "stopped on its own".to_string(),
+                Err(e) => format!("stopped: {}", e),
+            };
+            Some(watchdog::Exit {
+                status,
+                code: None,
+                success: false,
+            })
+        }
+
+        pub fn stop(&mut self) -> Result<(), String> {
+            // An error here is the run's own, already in the log.
+            let _ = async_runtime::block_on(self.core.stop());
+            Ok(())
+        }
+    }
+
+    impl Drop for Engine {
+        fn drop(&mut self) {
+            // The socket's listener outlives the run; without the file nothing reaches it.
+            let _ = fs::remove_file(&self.socket);
+            if let Ok(mut running) = RUNNING.lock() {
+                *running = None;
+            }
+        }
+    }
+
+    /// Runs the tunnel in the engine, or `None` when `launch` asks for the binary.
+    pub fn start(
+        app: &AppHandle,
+        tunnel: &Arc<Tunnel>,
+        launch: &Launch,
+    ) -> Result<Option<Daemon>, String> {
+        if !launch.binary_path.trim().is_empty() || launch.elevate {
+            return Ok(None);
+        }
+        let path = Path::new(&launch.config_path);
+        let mut config = core_config::load_config(path).map_err(|e| e.to_string())?;
+        keychain::unseal(path, &mut config)?;
+        init_logging(app);
+        {
+            let mut running = RUNNING.lock().map_err(|_| "State lock failed".to_string())?;
+            if let Some(other) = running.as_ref() {
+                return Err(format!(
+                    "The built-in engine already runs tunnel {}; give this one a vtrunkd binary",
+                    other.name
+                ));
+            }
+            *running = Some(tunnel.clone());
+        }
+
+        let socket = control::socket_path(config.control_socket());
+        let started = async_runtime::block_on(async {
+            let access = control::WriteAccess::from_config(config.control.as_ref())?;
+            let mut core = vtrunkd_core::Tunnel::builder().config(config).build()?;
+            hooks::spawn(core.config(), core.events());
+            match control::spawn_server(&socket, core.control_sender(), core.event_sender(), access)
+            {
+                Ok(()) => {}
+                Err(e @ VtrunkdError::AlreadyRunning(_)) => return Err(e),
+                Err(e) => {
+                    let message = format!("Control socket unavailable at {:?}: {}", socket, e);
+                    logs::record(app, &tunnel.name, &message);
+                }
+            }
+            core.start()?;
+            Ok::<_, VtrunkdError>(core)
+        });
+        match started {
+            Ok(core) => Ok(Some(Daemon::Embedded(Box::new(Engine { core, socket })))),
+            Err(e) => {
+                if let Ok(mut running) = RUNNING.lock() {
+                    *running = None;
+                }
+                Err(format!("Failed to start the built-in engine: {}", e))
+            }
+        }
+    }
+
+    /// Sends the engine's log lines where a daemon's output goes, tagged with its tunnel.
+    fn init_logging(app: &AppHandle) {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let app = app.clone();
+            let writer = move || EngineLog {
+                app: app.clone(),
+                text: Vec::new(),
+            };
+            let _ = tracing_subscriber::registry()
+                .with(Targets::new().with_target("vtrunkd_core", LevelFilter::INFO))
+                .with(
+                    tracing_subscriber::fmt::layer()
+                        .with_ansi(false)
+                        .with_writer(writer),
+                )
+                .try_init();
+        });
+    }
+
+    /// One event's output, recorded when the subscriber is done writing it.
+    struct EngineLog {
+        app: AppHandle,
+        text: Vec<u8>,
+    }
+
+    impl Write for EngineLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.text.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for EngineLog {
+        fn drop(&mut self) {
+            let Some(tunnel) = RUNNING.lock().ok().and_then(|running| running.clone()) else {
+                return;
+            };
+            for line in String::from_utf8_lossy(&self.text).lines() {
+                logs::record(&self.app, &tunnel.name, line);
+            }
+        }
+    }
+}
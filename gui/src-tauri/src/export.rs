@@ -0,0 +1,277 @@
+//! Moving a client config to another machine without copy-pasting keys: a QR code of a
+//! compact blob for a device with a camera, or a file sealed with a passphrase.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use qrcode::{Color, EcLevel, QrCode};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use vtrunkd_core::config as core_config;
+
+use crate::{imported_config, write_private_file, ImportedConfig};
+
+/// Starts the text in the QR code; the rest is the client YAML, deflated and in URL-safe
+/// base64 without padding.
+const QR_PREFIX: &str = "vtrunkd1:";
+/// Modules of white around the code, as scanners expect.
+const QR_QUIET_ZONE: usize = 4;
+const BUNDLE_FORMAT: &str = "vtrunkd-bundle";
+const BUNDLE_VERSION: u32 = 1;
+const KDF_ROUNDS: u32 = 600_000;
+const MIN_PASSPHRASE: usize = 8;
+
+/// An encrypted export, stored as JSON. The header goes into the AEAD as associated data,
+/// so it cannot be altered without failing decryption.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Bundle {
+    format: String,
+    version: u32,
+    /// PBKDF2-HMAC-SHA256 rounds deriving the key from the passphrase.
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// The client config as a QR code, in SVG. Anyone who sees the code has the keys.
+#[tauri::command]
+pub fn client_qr(client_yaml: String) -> Result<String, String> {
+    client_config(&client_yaml)?;
+    qr_svg(&qr_payload(&client_yaml)?)
+}
+
+/// Writes the client config to `path`, encrypted with `passphrase`.
+#[tauri::command]
+pub async fn export_bundle(
+    path: String,
+    client_yaml: String,
+    passphrase: String,
+) -> Result<(), String> {
+    client_config(&client_yaml)?;
+    if passphrase.chars().count() < MIN_PASSPHRASE {
+        return Err(format!(
+            "Use a passphrase of at least {} characters",
+            MIN_PASSPHRASE
+        ));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let bundle = seal(client_yaml.as_bytes(), &passphrase, KDF_ROUNDS)?;
+        let json = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+        write_private_file(Path::new(&path), &json)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Opens an export written by `export_bundle`, as if its config had been imported.
+#[tauri::command]
+pub async fn import_bundle(path: String, passphrase: String) -> Result<ImportedConfig, String> {
+    let json = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let bundle: Bundle =
+        serde_json::from_str(&json).map_err(|_| format!("{} is not a vtrunkd export", path))?;
+    let yaml = tauri::async_runtime::spawn_blocking(move || open(&bundle, &passphrase))
+        .await
+        .map_err(|e| e.to_string())??;
+    imported_config(yaml)
+}
+
+fn client_config(client_yaml: &str) -> Result<core_config::Config, String> {
+    let config = core_config::parse_config(client_yaml)
+        .map_err(|e| format!("Invalid client config: {}", e))?;
+    if config.wireguard.links.iter().all(|link| link.endpoint.is_none()) {
+        return Err("Only a client config, with link endpoints, can be exported".to_string());
+    }
+    Ok(config)
+}
+
+fn qr_payload(yaml: &str) -> Result<String, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(yaml.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let deflated = encoder.finish().map_err(|e| e.to_string())?;
+    Ok(format!(
+        "{}{}",
+        QR_PREFIX,
+        general_purpose::URL_SAFE_NO_PAD.encode(deflated)
+    ))
+}
+
+fn qr_svg(payload: &str) -> Result<String, String> {
+    // The lowest correction level fits the most; the code is shown on a screen, not printed.
+    let code = QrCode::with_error_correction_level(payload, EcLevel::L).map_err(|_| {
+        "The config is too large for a QR code; use an encrypted export instead".to_string()
+    })?;
+    let width = code.width();
+    let size = width + 2 * QR_QUIET_ZONE;
+    let mut modules = String::new();
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let _ = write!(
+                modules,
+                "M{},{}h1v1h-1z",
+                index % width + QR_QUIET_ZONE,
+                index / width + QR_QUIET_ZONE
+            );
+        }
+    }
+    Ok(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#fff"/><path d="{modules}" fill="#000"/></svg>"##
+    ))
+}
+
+fn seal(plaintext: &[u8], passphrase: &str, rounds: u32) -> Result<Bundle, String> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let mut bundle = Bundle {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        rounds,
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: String::new(),
+    };
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, rounds));
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: header(&bundle).as_bytes(),
+            },
+        )
+        .map_err(|_| "Encryption failed".to_string())?;
+    bundle.ciphertext = general_purpose::STANDARD.encode(ciphertext);
+    Ok(bundle)
+}
+
+fn open(bundle: &Bundle, passphrase: &str) -> Result<String, String> {
+    if bundle.format != BUNDLE_FORMAT || bundle.version != BUNDLE_VERSION {
+        return Err(format!(
+            "Unsupported export: {} version {}",
+            bundle.format, bundle.version
+        ));
+    }
+    let decode = |field: &str| {
+        general_purpose::STANDARD
+            .decode(field)
+            .map_err(|_| "The export is corrupt".to_string())
+    };
+    let salt = decode(&bundle.salt)?;
+    let nonce = decode(&bundle.nonce)?;
+    let ciphertext = decode(&bundle.ciphertext)?;
+    // A file could ask for any number of rounds; more than this is not one we wrote.
+    if nonce.len() != 12 || bundle.rounds == 0 || bundle.rounds > KDF_ROUNDS * 10 {
+        return Err("The export is corrupt".to_string());
+    }
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, bundle.rounds));
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: header(bundle).as_bytes(),
+            },
+        )
+        .map_err(|_| "Wrong passphrase, or the export was modified".to_string())?;
+    String::from_utf8(plaintext).map_err(|_| "The export is corrupt".to_string())
+}
+
+fn header(bundle: &Bundle) -> String {
+    format!(
+        "{} {} {} {} {}",
+        bundle.format, bundle.version, bundle.rounds, bundle.salt, bundle.nonce
+    )
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], rounds: u32) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    const CLIENT_YAML: &str = r#"
+network:
+  mtu: 1420
+  buffer_size: 65536
+wireguard:
+  private_key: "YNqHbfBQKaGvzefSSZlBVZMNsl6dDKvCXcILgDNQjUg="
+  peer_public_key: "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+  links:
+    - endpoint: "vps.example.com:51820"
+"#;
+
+    #[test]
+    fn only_client_configs_export() {
+        assert!(client_config(CLIENT_YAML).is_ok());
+        let server = CLIENT_YAML.replace(
+            "endpoint: \"vps.example.com:51820\"",
+            "bind: \"0.0.0.0:51820\"",
+        );
+        assert!(client_config(&server)
+            .unwrap_err()
+            .contains("Only a client config"));
+        assert!(client_config("wireguard: [").is_err());
+    }
+
+    #[test]
+    fn qr_payload_inflates_back_to_the_config() {
+        let payload = qr_payload(CLIENT_YAML).unwrap();
+        let encoded = payload.strip_prefix(QR_PREFIX).unwrap();
+        let deflated = general_purpose::URL_SAFE_NO_PAD.decode(encoded).unwrap();
+        let mut yaml = String::new();
+        DeflateDecoder::new(&deflated[..])
+            .read_to_string(&mut yaml)
+            .unwrap();
+        assert_eq!(yaml, CLIENT_YAML);
+
+        let svg = qr_svg(&payload).unwrap();
+        assert!(svg.starts_with("<svg") && svg.contains("h1v1h-1z"));
+        assert!(qr_svg(&"x".repeat(8000)).is_err());
+    }
+
+    #[test]
+    fn bundles_open_only_with_the_passphrase_and_header() {
+        // Few rounds keep the test quick; exports use KDF_ROUNDS.
+        let bundle = seal(CLIENT_YAML.as_bytes(), "correct horse", 1000).unwrap();
+        assert_eq!(open(&bundle, "correct horse").unwrap(), CLIENT_YAML);
+        assert!(open(&bundle, "wrong horse")
+            .unwrap_err()
+            .contains("Wrong passphrase"));
+
+        // The header is authenticated: fewer rounds no longer decrypt.
+        let json = serde_json::to_string(&bundle).unwrap();
+        let mut weakened: Bundle = serde_json::from_str(&json).unwrap();
+        weakened.rounds = 1;
+        assert!(open(&weakened, "correct horse").is_err());
+        let mut newer: Bundle = serde_json::from_str(&json).unwrap();
+        newer.version = BUNDLE_VERSION + 1;
+        assert!(open(&newer, "correct horse")
+            .unwrap_err()
+            .contains("Unsupported export"));
+        let mut huge: Bundle = serde_json::from_str(&json).unwrap();
+        huge.rounds = u32::MAX;
+        assert!(open(&huge, "correct horse")
+            .unwrap_err()
+            .contains("corrupt"));
+    }
+}
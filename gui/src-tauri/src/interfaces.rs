@@ -0,0 +1,227 @@
+//! What kind of uplink each local interface is, and where its default route goes, so link
+//! detection can skip tunnels and warn when two links leave through the same uplink, which
+//! quietly defeats bonding.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+
+use get_if_addrs::get_if_addrs;
+
+use crate::LinkInput;
+
+/// `wifi`, `ethernet`, `wwan`, `tether`, `vpn`, `virtual` or `unknown`.
+pub fn interface_kinds() -> HashMap<String, &'static str> {
+    let mut kinds = platform_kinds();
+    if let Ok(interfaces) = get_if_addrs() {
+        for iface in interfaces {
+            kinds
+                .entry(iface.name.clone())
+                .or_insert_with(|| kind_from_name(&iface.name));
+        }
+    }
+    kinds
+}
+
+/// The IPv4 gateway of each interface with a default route, in any routing table; `None`
+/// for a default route without one, such as a point-to-point link.
+pub fn default_gateways() -> HashMap<String, Option<String>> {
+    #[cfg(target_os = "linux")]
+    let (program, args) = ("ip", ["-4", "route", "show", "table", "all"].as_slice());
+    #[cfg(not(target_os = "linux"))]
+    let (program, args) = ("netstat", ["-rn", "-f", "inet"].as_slice());
+
+    let mut gateways = HashMap::new();
+    let Ok(output) = Command::new(program).args(args).output() else {
+        return gateways;
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.first() != Some(&"default") {
+            continue;
+        }
+        let (gateway, device) = if cfg!(target_os = "linux") {
+            let after = |key: &str| {
+                tokens
+                    .iter()
+                    .position(|token| *token == key)
+                    .and_then(|index| tokens.get(index + 1))
+            };
+            (after("via"), after("dev"))
+        } else {
+            // Destination, Gateway, Flags, Netif.
+            (tokens.get(1), tokens.get(3))
+        };
+        let Some(device) = device else {
+            continue;
+        };
+        let gateway = gateway
+            .filter(|gateway| gateway.parse::<IpAddr>().is_ok())
+            .map(|gateway| gateway.to_string());
+        let entry = gateways.entry(device.to_string()).or_insert(None);
+        if entry.is_none() {
+            *entry = gateway;
+        }
+    }
+    gateways
+}
+
+/// Warnings for links that will not bond as configured: bound to an address this machine
+/// does not have, several on one interface, or interfaces behind the same gateway.
+#[tauri::command]
+pub fn check_link_uplinks(links: Vec<LinkInput>) -> Result<Vec<String>, String> {
+    let interfaces = get_if_addrs().map_err(|e| e.to_string())?;
+    let gateways = default_gateways();
+    let mut warnings = Vec::new();
+    let mut by_interface: HashMap<String, Vec<&str>> = HashMap::new();
+    let mut unbound = Vec::new();
+    for link in &links {
+        let Ok(bind) = link.bind.trim().parse::<SocketAddr>() else {
+            continue;
+        };
+        if bind.ip().is_unspecified() {
+            unbound.push(link.name.as_str());
+            continue;
+        }
+        match interfaces.iter().find(|iface| iface.ip() == bind.ip()) {
+            Some(iface) => by_interface
+                .entry(iface.name.clone())
+                .or_default()
+                .push(link.name.as_str()),
+            None => warnings.push(format!(
+                "Link {} binds to {}, which no interface here has right now",
+                link.name,
+                bind.ip()
+            )),
+        }
+    }
+    if unbound.len() > 1 {
+        warnings.push(format!(
+            "Links {} bind to any address, so they all leave through the default route",
+            unbound.join(", ")
+        ));
+    }
+    let mut names: Vec<&String> = by_interface.keys().collect();
+    names.sort();
+    for name in &names {
+        let links = &by_interface[*name];
+        if links.len() > 1 {
+            warnings.push(format!(
+                "Links {} all bind to addresses on {}; they share one uplink",
+                links.join(", "),
+                name
+            ));
+        }
+    }
+    let mut by_gateway: HashMap<&str, Vec<&str>> = HashMap::new();
+    for name in &names {
+        if let Some(Some(gateway)) = gateways.get(name.as_str()) {
+            by_gateway.entry(gateway).or_default().push(name);
+        }
+    }
+    let mut shared: Vec<_> = by_gateway
+        .into_iter()
+        .filter(|(_, interfaces)| interfaces.len() > 1)
+        .collect();
+    shared.sort();
+    for (gateway, interfaces) in shared {
+        warnings.push(format!(
+            "Interfaces {} all route through gateway {}, likely the same uplink",
+            interfaces.join(", "),
+            gateway
+        ));
+    }
+    Ok(warnings)
+}
+
+fn kind_from_name(name: &str) -> &'static str {
+    let starts = |prefixes: &[&str]| prefixes.iter().any(|prefix| name.starts_with(prefix));
+    if starts(&["wwan", "ww", "rmnet", "ppp", "mbim"]) {
+        "wwan"
+    } else if starts(&["usb", "rndis"]) {
+        "tether"
+    } else if starts(&["wl", "wlan", "ath"]) {
+        "wifi"
+    } else if starts(&["utun", "tun", "tap", "wg", "ipsec", "gif", "stf"]) {
+        "vpn"
+    } else if starts(&["docker", "br", "veth", "virbr", "bridge", "awdl", "llw", "anpi", "vmnet"])
+    {
+        "virtual"
+    } else if starts(&["eth", "en"]) {
+        "ethernet"
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn platform_kinds() -> HashMap<String, &'static str> {
+    let mut kinds = HashMap::new();
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return kinds;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let uevent = std::fs::read_to_string(path.join("uevent")).unwrap_or_default();
+        let driver = std::fs::read_link(path.join("device/driver"))
+            .ok()
+            .and_then(|driver| driver.file_name().map(|d| d.to_string_lossy().to_string()));
+        let kind = if uevent.contains("DEVTYPE=wwan") {
+            "wwan"
+        } else if path.join("wireless").exists() || path.join("phy80211").exists() {
+            "wifi"
+        } else {
+            match driver.as_deref() {
+                Some("qmi_wwan" | "cdc_mbim" | "huawei_cdc_ncm" | "option") => "wwan",
+                Some("rndis_host" | "ipheth" | "cdc_ether" | "cdc_ncm") => "tether",
+                Some(_) => "ethernet",
+                // No device behind it: a tunnel, a PPP modem link, a bridge or similar.
+                None => match kind_from_name(&name) {
+                    kind @ ("vpn" | "wwan") => kind,
+                    _ => "virtual",
+                },
+            }
+        };
+        kinds.insert(name, kind);
+    }
+    kinds
+}
+
+/// From the hardware ports System Settings shows, such as "Wi-Fi" on en0 or "iPhone USB".
+#[cfg(target_os = "macos")]
+fn platform_kinds() -> HashMap<String, &'static str> {
+    let mut kinds = HashMap::new();
+    let Ok(output) = Command::new("networksetup")
+        .arg("-listallhardwareports")
+        .output()
+    else {
+        return kinds;
+    };
+    let mut port = String::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(name) = line.strip_prefix("Hardware Port: ") {
+            port = name.to_string();
+        } else if let Some(device) = line.strip_prefix("Device: ") {
+            let kind = if port.contains("Wi-Fi") || port.contains("AirPort") {
+                "wifi"
+            } else if port.contains("iPhone") || port.contains("iPad") || port.contains("Bluetooth PAN")
+            {
+                "tether"
+            } else if port.contains("Thunderbolt Bridge") {
+                "virtual"
+            } else if port.contains("Ethernet") || port.contains("LAN") {
+                "ethernet"
+            } else {
+                kind_from_name(device)
+            };
+            kinds.insert(device.to_string(), kind);
+        }
+    }
+    kinds
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn platform_kinds() -> HashMap<String, &'static str> {
+    HashMap::new()
+}
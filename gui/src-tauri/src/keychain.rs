@@ -0,0 +1,149 @@
+//! The OS keyring (Keychain, Secret Service or Credential Manager), holding the profiles'
+//! secrets and the keys of the configs the app writes to its config directory. Those files
+//! name credentials instead of holding keys; a daemon started from one gets the keys in a
+//! `$CREDENTIALS_DIRECTORY` only this user can read, removed again once it stops.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tauri::AppHandle;
+use vtrunkd_core::config as core_config;
+
+use crate::{app_config_dir, split_keys, write_private_file};
+use crate::{PRESHARED_KEY_CREDENTIAL, PRIVATE_KEY_CREDENTIAL};
+
+const KEYRING_SERVICE: &str = "vtrunkd-control-room";
+/// Under the runtime directory, one subdirectory per config being run.
+const CREDENTIALS_DIR: &str = "vtrunkd-credentials";
+
+/// Moves the inline keys of `yaml`, about to be written to `path`, into the keyring, and
+/// returns the config naming them as credentials instead.
+pub fn seal(path: &Path, yaml: &str) -> Result<String, String> {
+    let Some(split) = split_keys(yaml)? else {
+        return Ok(yaml.to_string());
+    };
+    let owner = config_owner(path);
+    store_secret(&owner, PRIVATE_KEY_CREDENTIAL, Some(&split.private_key))?;
+    store_secret(&owner, PRESHARED_KEY_CREDENTIAL, split.preshared_key.as_deref())?;
+    Ok(split.config_yaml)
+}
+
+/// Writes the credentials the config at `path` names from the keyring into a private
+/// directory for the daemon's `$CREDENTIALS_DIRECTORY`. `None` when the config holds its
+/// keys, or names one the keyring does not have; the daemon then says which.
+pub fn credentials_dir(app: &AppHandle, path: &Path) -> Result<Option<PathBuf>, String> {
+    let Ok(config) = core_config::load_config(path) else {
+        return Ok(None);
+    };
+    let wireguard = &config.wireguard;
+    let names: Vec<&String> = [
+        &wireguard.private_key_credential,
+        &wireguard.preshared_key_credential,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let owner = config_owner(path);
+    let mut credentials = Vec::new();
+    for name in names {
+        match load_secret(&owner, name)? {
+            Some(value) => credentials.push((name, value)),
+            None => return Ok(None),
+        }
+    }
+
+    let dir = runtime_dir(app)?.join(CREDENTIALS_DIR).join(file_stem(path));
+    create_private_dir(&dir)?;
+    for (name, value) in credentials {
+        write_private_file(&dir.join(name), &value)?;
+    }
+    Ok(Some(dir))
+}
+
+/// Puts the keys the config at `path` names from the keyring into `config` itself, for the
+/// engine built into the app. A credential the keyring lacks stays named, for it to report.
+#[cfg(feature = "embedded")]
+pub fn unseal(path: &Path, config: &mut core_config::Config) -> Result<(), String> {
+    let owner = config_owner(path);
+    let wireguard = &mut config.wireguard;
+    if let Some(name) = wireguard.private_key_credential.clone() {
+        if let Some(key) = load_secret(&owner, &name)? {
+            wireguard.private_key = key;
+            wireguard.private_key_credential = None;
+        }
+    }
+    if let Some(name) = wireguard.preshared_key_credential.clone() {
+        if let Some(key) = load_secret(&owner, &name)? {
+            wireguard.preshared_key = Some(key);
+            wireguard.preshared_key_credential = None;
+        }
+    }
+    Ok(())
+}
+
+/// Removes a directory `credentials_dir` made, once its daemon has stopped.
+pub fn remove_credentials(dir: &Path) {
+    let _ = fs::remove_dir_all(dir);
+}
+
+/// Stores `value` for the owner, or removes the entry when there is none.
+pub fn store_secret(owner: &str, key: &str, value: Option<&str>) -> Result<(), String> {
+    let entry = keyring_entry(owner, key)?;
+    match value.filter(|value| !value.is_empty()) {
+        Some(value) => entry
+            .set_password(value)
+            .map_err(|e| format!("Failed to store {} in the keyring: {}", key, e)),
+        None => match entry.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to remove {} from the keyring: {}", key, e)),
+        },
+    }
+}
+
+pub fn load_secret(owner: &str, key: &str) -> Result<Option<String>, String> {
+    match keyring_entry(owner, key)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Failed to read {} from the keyring: {}", key, e)),
+    }
+}
+
+/// `owner` is a profile name, or `config:` and a config file's name.
+fn keyring_entry(owner: &str, key: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, &format!("{}/{}", owner, key))
+        .map_err(|e| format!("Keyring unavailable: {}", e))
+}
+
+fn config_owner(path: &Path) -> String {
+    format!("config:{}", file_stem(path))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// `$XDG_RUNTIME_DIR` is memory-backed and cleared at logout; elsewhere the config dir.
+fn runtime_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => app_config_dir(app),
+    }
+}
+
+fn create_private_dir(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for dir in [dir, dir.parent().unwrap_or(dir)] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+//! The daemon's output, kept in a ring buffer so it can be filtered by level, searched,
+//! written to a file as it grows, and exported with the configs for a bug report.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use vtrunkd_core::config as core_config;
+
+use crate::write_private_file;
+
+/// Lines kept; older ones are dropped as new ones arrive.
+const LOG_CAPACITY: usize = 5000;
+/// Most severe first, the order a minimum level filters by.
+const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+#[derive(Clone, Serialize)]
+pub struct LogEntry {
+    seq: u64,
+    /// When the GUI received the line, in ms since the Unix epoch.
+    time_ms: u64,
+    level: &'static str,
+    /// The tunnel whose daemon wrote the line.
+    tunnel: String,
+    line: String,
+}
+
+impl LogEntry {
+    fn tagged(&self) -> String {
+        tagged(&self.tunnel, &self.line)
+    }
+}
+
+#[derive(Default)]
+pub struct LogState {
+    buffer: Mutex<LogBuffer>,
+}
+
+#[derive(Default)]
+struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+    next_seq: u64,
+    /// Where new lines are appended, once persisting is on.
+    file: Option<File>,
+}
+
+/// Keeps a line of `tunnel`'s daemon output and passes it on to the window.
+pub fn record(app: &AppHandle, tunnel: &str, line: &str) {
+    let line = strip_ansi(line.trim_end());
+    if let Ok(mut buffer) = app.state::<LogState>().buffer.lock() {
+        let entry = LogEntry {
+            seq: buffer.next_seq,
+            time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis() as u64),
+            level: level_of(&line),
+            tunnel: tunnel.to_string(),
+            line: line.clone(),
+        };
+        buffer.next_seq += 1;
+        if buffer.entries.len() == LOG_CAPACITY {
+            buffer.entries.pop_front();
+        }
+        buffer.entries.push_back(entry);
+        let failed = buffer
+            .file
+            .as_mut()
+            .is_some_and(|file| writeln!(file, "{}", tagged(tunnel, &line)).is_err());
+        if failed {
+            buffer.file = None;
+            let _ = app.emit_all("vtrunkd-log", "Writing the log file failed; stopped persisting.");
+        }
+    }
+    let _ = app.emit_all("vtrunkd-log", tagged(tunnel, &line));
+}
+
+/// A line as the activity log and the files show it, after the tunnel it came from.
+pub fn tagged(tunnel: &str, line: &str) -> String {
+    format!("[{}] {}", tunnel, line)
+}
+
+/// Kept lines at `level` or more severe that contain `query`, ignoring case; only
+/// `tunnel`'s when one is given.
+#[tauri::command]
+pub fn get_logs(
+    state: State<LogState>,
+    level: String,
+    query: String,
+    tunnel: Option<String>,
+) -> Result<Vec<LogEntry>, String> {
+    let max_rank = rank(&level).ok_or_else(|| format!("Unknown log level {}", level))?;
+    let query = query.trim().to_lowercase();
+    let buffer = state.buffer.lock().map_err(|_| "State lock failed".to_string())?;
+    Ok(buffer
+        .entries
+        .iter()
+        .filter(|entry| rank(entry.level).is_some_and(|rank| rank <= max_rank))
+        .filter(|entry| tunnel.as_ref().is_none_or(|tunnel| entry.tunnel == *tunnel))
+        .filter(|entry| query.is_empty() || entry.line.to_lowercase().contains(&query))
+        .cloned()
+        .collect())
+}
+
+#[tauri::command]
+pub fn clear_logs(state: State<LogState>) -> Result<(), String> {
+    let mut buffer = state.buffer.lock().map_err(|_| "State lock failed".to_string())?;
+    buffer.entries.clear();
+    Ok(())
+}
+
+/// Writes the kept lines to `path` and appends every new one there; no path stops.
+#[tauri::command]
+pub fn persist_logs(state: State<LogState>, path: Option<String>) -> Result<(), String> {
+    let mut buffer = state.buffer.lock().map_err(|_| "State lock failed".to_string())?;
+    let Some(path) = path else {
+        buffer.file = None;
+        return Ok(());
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    for entry in &buffer.entries {
+        writeln!(file, "{}", entry.tagged())
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    }
+    buffer.file = Some(file);
+    Ok(())
+}
+
+/// One text file for a bug report: versions, both configs with their secrets redacted,
+/// and the kept daemon log.
+#[tauri::command]
+pub fn export_diagnostics(
+    app: AppHandle,
+    state: State<LogState>,
+    path: String,
+    binary_path: String,
+    client_yaml: String,
+    server_yaml: String,
+) -> Result<(), String> {
+    let mut report = String::new();
+    let _ = writeln!(report, "== versions ==");
+    let _ = writeln!(report, "Control Room {}", app.package_info().version);
+    let _ = writeln!(report, "{}", daemon_version(&binary_path));
+    let _ = writeln!(
+        report,
+        "{} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    for (name, yaml) in [("client", &client_yaml), ("server", &server_yaml)] {
+        let _ = writeln!(report, "\n== {} config (secrets redacted) ==", name);
+        let _ = writeln!(report, "{}", redacted(yaml));
+    }
+    let _ = writeln!(report, "\n== daemon log ==");
+    let buffer = state.buffer.lock().map_err(|_| "State lock failed".to_string())?;
+    for entry in &buffer.entries {
+        let _ = writeln!(report, "{}", entry.tagged());
+    }
+    drop(buffer);
+    if let Some(dir) = Path::new(&path).parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    // Redacted, but still the addresses and layout of this setup.
+    write_private_file(Path::new(&path), &report)
+}
+
+fn daemon_version(binary_path: &str) -> String {
+    if binary_path.trim().is_empty() && cfg!(feature = "embedded") {
+        return "vtrunkd engine built into the Control Room".to_string();
+    }
+    let program = if binary_path.trim().is_empty() {
+        "vtrunkd"
+    } else {
+        binary_path.trim()
+    };
+    match Command::new(program).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        Ok(output) => format!(
+            "{} --version failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => format!("{} --version failed: {}", program, e),
+    }
+}
+
+/// The config as the daemon would run it, keys and tokens replaced. A config that does not
+/// parse is left out rather than risk its secrets.
+fn redacted(yaml: &str) -> String {
+    if yaml.trim().is_empty() {
+        return "(none)".to_string();
+    }
+    match core_config::parse_config(yaml) {
+        Ok(config) => {
+            let mut config = config.effective();
+            config.redact_secrets();
+            serde_yaml::to_string(&config).unwrap_or_else(|e| format!("(not shown: {})", e))
+        }
+        Err(e) => format!("(not shown, it does not parse: {})", e),
+    }
+}
+
+fn rank(level: &str) -> Option<usize> {
+    LEVELS.iter().position(|known| *known == level)
+}
+
+/// The level of a text (`... WARN vtrunkd::tunnel: ...`) or JSON (`"level":"WARN"`) log
+/// line; lines without one count as info.
+fn level_of(line: &str) -> &'static str {
+    let token = match line.split_once("\"level\":\"") {
+        Some((_, rest)) => rest.split('"').next(),
+        None => line
+            .split_whitespace()
+            .take(3)
+            .find(|word| LEVELS.iter().any(|level| word.eq_ignore_ascii_case(level))),
+    };
+    token
+        .and_then(|token| {
+            LEVELS
+                .iter()
+                .find(|level| token.eq_ignore_ascii_case(level))
+        })
+        .copied()
+        .unwrap_or("info")
+}
+
+/// The daemon colors its output when it writes to stdout.
+fn strip_ansi(line: &str) -> String {
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with a letter: ESC [ 1 ; 32 m.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
@@ -1,11 +1,33 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::collections::HashSet;
+mod addresses;
+mod autostart;
+mod connectivity;
+mod embedded;
+mod export;
+mod interfaces;
+mod keychain;
+mod logs;
+mod mtu;
+mod notifications;
+mod preflight;
+mod profiles;
+mod remote;
+mod routing;
+mod speedtest;
+mod ssh;
+mod watchdog;
+
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use base64::{engine::general_purpose, Engine as _};
 use boringtun::x25519::{PublicKey, StaticSecret};
@@ -14,26 +36,168 @@ use rand::rngs::OsRng;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager, State};
+use vtrunkd_core::stats::{self, StatsSnapshot};
+use vtrunkd_core::{config as core_config, control};
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Health pings go out about once a second, so loss is taken over a longer window.
+const LOSS_WINDOW: usize = 10;
+/// The tunnel started without a name; its files keep the names from before there were more.
+const DEFAULT_TUNNEL: &str = "default";
 
 #[derive(Default)]
 struct RunnerState {
-    child: Mutex<Option<Child>>,
+    /// Every tunnel started this session, by name. Stopped ones stay, so a restart the
+    /// watchdog has pending still finds its tunnel.
+    tunnels: Mutex<HashMap<String, Arc<Tunnel>>>,
+    /// Held while a tunnel starts, so two starting at once cannot both miss a conflict.
+    launching: Mutex<()>,
+}
+
+impl RunnerState {
+    /// The tunnel called `name`, created stopped the first time it is asked for.
+    fn tunnel(&self, name: &str) -> Result<Arc<Tunnel>, String> {
+        let name = tunnel_name(name);
+        let mut tunnels = self
+            .tunnels
+            .lock()
+            .map_err(|_| "State lock failed".to_string())?;
+        let tunnel = tunnels.entry(name.to_string()).or_insert_with(|| {
+            Arc::new(Tunnel {
+                name: name.to_string(),
+                ..Tunnel::default()
+            })
+        });
+        Ok(tunnel.clone())
+    }
+
+    fn running(&self) -> Vec<Arc<Tunnel>> {
+        self.tunnels
+            .lock()
+            .map(|tunnels| {
+                tunnels
+                    .values()
+                    .filter(|tunnel| tunnel.is_running())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// One daemon the window started, and the threads following it.
+#[derive(Default)]
+struct Tunnel {
+    name: String,
+    child: Mutex<Option<Daemon>>,
+    /// Set when the daemon was started as root through a password prompt. This user cannot
+    /// signal it, so stopping it goes through the same prompt.
+    elevation: Mutex<Option<Elevation>>,
+    /// Bumped on every start, so a poller left over from an earlier run stops.
+    run: AtomicU64,
+    /// Whether link events raise desktop notifications; the profile's setting.
+    notify: AtomicBool,
+    /// How the running daemon was started, to start it the same way after a crash.
+    launch: Mutex<Option<Launch>>,
+    /// The last lines the daemon wrote to stderr, shown when it exits.
+    stderr_tail: Mutex<VecDeque<String>>,
+    auto_restart: AtomicBool,
+    /// Restarts since the daemon last ran for a while.
+    restarts: AtomicU32,
+    /// The keys handed to the running daemon, removed once it stops.
+    credentials: Mutex<Option<PathBuf>>,
+    /// Set while waiting to restart, so stopping in the meantime cancels it.
+    restart_pending: AtomicBool,
+}
+
+impl Tunnel {
+    fn is_running(&self) -> bool {
+        self.child.lock().map(|child| child.is_some()).unwrap_or(false)
+    }
+
+    fn clear_credentials(&self) {
+        if let Some(dir) = self.credentials.lock().ok().and_then(|mut dir| dir.take()) {
+            keychain::remove_credentials(&dir);
+        }
+    }
+}
+
+/// What runs a tunnel: a `vtrunkd` process, or the engine built into the app.
+enum Daemon {
+    Process(Child),
+    #[cfg(feature = "embedded")]
+    Embedded(Box<embedded::Engine>),
+}
+
+impl Daemon {
+    /// How it ended, once it has.
+    fn try_wait(&mut self) -> Result<Option<watchdog::Exit>, String> {
+        match self {
+            Daemon::Process(child) => child
+                .try_wait()
+                .map(|status| status.as_ref().map(watchdog::Exit::of))
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "embedded")]
+            Daemon::Embedded(engine) => Ok(engine.try_wait()),
+        }
+    }
+
+    /// An elevated daemon is stopped through the same password prompt it was started with.
+    fn stop(&mut self, elevation: Option<&Elevation>) -> Result<(), String> {
+        match self {
+            Daemon::Process(child) => {
+                let exited = matches!(child.try_wait(), Ok(Some(_)));
+                match elevation {
+                    _ if exited => {}
+                    // A cancelled prompt leaves the daemon running and tracked.
+                    Some(elevation) => stop_elevated(elevation, child.id())?,
+                    None => child.kill().map_err(|e| e.to_string())?,
+                }
+                let _ = child.wait();
+                Ok(())
+            }
+            #[cfg(feature = "embedded")]
+            Daemon::Embedded(engine) => engine.stop(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Launch {
+    binary_path: String,
+    config_path: String,
+    elevate: bool,
+}
+
+enum Elevation {
+    /// pkexec execs vtrunkd in its own place, so the child is the daemon.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Pkexec,
+    /// osascript waits on a root shell running vtrunkd, which writes this pidfile.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Osascript { pidfile: PathBuf },
 }
 
 #[derive(Serialize)]
 struct LocalAddr {
     name: String,
     addr: String,
+    /// `wifi`, `ethernet`, `wwan`, `tether`, `vpn`, `virtual` or `unknown`, as far as the
+    /// interface's driver and name tell.
+    kind: String,
+    /// Whether the interface has a default route, and through which gateway.
+    default_route: bool,
+    gateway: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct LinkInput {
     name: String,
     bind: String,
     weight: u32,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct ConfigParams {
     client_interface: String,
     client_address: String,
@@ -47,10 +211,25 @@ struct ConfigParams {
     health_interval_ms: u64,
     health_timeout_ms: u64,
     health_enabled: bool,
+    preshared_key: bool,
     server_host: String,
     server_bind: String,
     server_port_base: u16,
     links: Vec<LinkInput>,
+    /// Route all of the client's traffic through the tunnel.
+    #[serde(default)]
+    full_tunnel: bool,
+    /// The client's control socket; tunnels running side by side each need their own.
+    #[serde(default)]
+    control_socket: String,
+    /// Keys of an already provisioned pair; only missing ones are generated, so changing a
+    /// setting does not lock the client out of its server.
+    #[serde(default)]
+    client_private_key: Option<String>,
+    #[serde(default)]
+    server_private_key: Option<String>,
+    #[serde(default)]
+    preshared_key_value: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -61,27 +240,110 @@ struct GeneratedConfigs {
     client_public_key: String,
     server_private_key: String,
     server_public_key: String,
+    preshared_key: Option<String>,
+    /// With a full tunnel, the traffic that still goes around it.
+    bypass: Vec<String>,
 }
 
-#[derive(Deserialize)]
+/// A config read back from disk. `params` leaves empty what the file cannot tell, such as
+/// the client's addresses in a server config.
+#[derive(Serialize)]
+struct ImportedConfig {
+    kind: String,
+    yaml: String,
+    params: ConfigParams,
+    public_key: Option<String>,
+    peer_public_key: String,
+}
+
+/// One link's row on the dashboard, pushed as part of `vtrunkd-stats` every second.
+#[derive(Serialize, Clone)]
+struct LinkDashboard {
+    name: String,
+    state: String,
+    rtt_ms: Option<u64>,
+    loss_percent: Option<f64>,
+    rx_bits_per_sec: u64,
+    tx_bits_per_sec: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct TunnelStats {
+    tunnel: String,
+    links: Vec<LinkDashboard>,
+}
+
+#[derive(Clone, Serialize)]
+struct TunnelEvent {
+    tunnel: String,
+    event: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct TunnelStatus {
+    name: String,
+    /// `running`, `restarting` or `stopped`.
+    state: &'static str,
+    config_path: Option<String>,
+    restarts: u32,
+}
+
+#[derive(Serialize, Deserialize)]
 struct SshConfig {
     host: String,
     user: String,
     port: u16,
     key_path: String,
     use_root: bool,
+    #[serde(default)]
+    auth: ssh::SshAuth,
+    /// The password for `password` auth, or the passphrase of the key.
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl SshConfig {
+    fn login_user(&self) -> String {
+        if self.use_root {
+            "root".to_string()
+        } else {
+            self.user.trim().to_string()
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct ProvisionOptions {
     install_vtrunkd: bool,
     install_service: bool,
+    /// Try the release binary for the VPS's architecture before building from source.
+    #[serde(default)]
+    prebuilt: bool,
 }
 
 #[derive(Serialize, Clone)]
 struct Config {
     network: NetworkConfig,
     wireguard: WireGuardConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hooks: Option<HooksConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    control: Option<ControlConfig>,
+}
+
+#[derive(Serialize, Clone)]
+struct ControlConfig {
+    socket: String,
+}
+
+#[derive(Serialize, Clone, Default)]
+struct HooksConfig {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pre_up: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    post_up: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    post_down: Vec<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -96,6 +358,8 @@ struct NetworkConfig {
     netmask: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     destination: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    addresses: Vec<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -134,32 +398,30 @@ fn list_local_addrs() -> Result<Vec<LocalAddr>, String> {
     let mut seen = HashSet::new();
     let mut addrs = Vec::new();
     let interfaces = get_if_addrs::get_if_addrs().map_err(|e| e.to_string())?;
+    let kinds = interfaces::interface_kinds();
+    let gateways = interfaces::default_gateways();
     for iface in interfaces {
         if iface.is_loopback() {
             continue;
         }
-        match iface.addr {
-            IfAddr::V4(addr) => {
-                let ip = addr.ip.to_string();
-                if seen.insert(ip.clone()) {
-                    addrs.push(LocalAddr {
-                        name: iface.name,
-                        addr: ip,
-                    });
-                }
-            }
+        let ip = match iface.addr {
+            IfAddr::V4(addr) => addr.ip.to_string(),
             IfAddr::V6(addr) => {
                 if addr.ip.is_unicast_link_local() {
                     continue;
                 }
-                let ip = addr.ip.to_string();
-                if seen.insert(ip.clone()) {
-                    addrs.push(LocalAddr {
-                        name: iface.name,
-                        addr: ip,
-                    });
-                }
+                addr.ip.to_string()
             }
+        };
+        if seen.insert(ip.clone()) {
+            let gateway = gateways.get(&iface.name);
+            addrs.push(LocalAddr {
+                kind: kinds.get(&iface.name).unwrap_or(&"unknown").to_string(),
+                default_route: gateway.is_some(),
+                gateway: gateway.cloned().flatten(),
+                name: iface.name,
+                addr: ip,
+            });
         }
     }
     Ok(addrs)
@@ -168,8 +430,23 @@ fn list_local_addrs() -> Result<Vec<LocalAddr>, String> {
 #[tauri::command]
 fn generate_configs(params: ConfigParams) -> Result<GeneratedConfigs, String> {
     validate_params(&params)?;
-    let (client_private_key, client_public_key) = generate_keypair();
-    let (server_private_key, server_public_key) = generate_keypair();
+    let (client_private_key, client_public_key) =
+        existing_or_new_keypair(params.client_private_key.as_deref(), "Client")?;
+    let (server_private_key, server_public_key) =
+        existing_or_new_keypair(params.server_private_key.as_deref(), "Server")?;
+    // A shared symmetric key keeps recorded traffic safe from a future quantum attack on
+    // the Curve25519 handshake.
+    let preshared_key = if params.preshared_key {
+        match existing_key(params.preshared_key_value.as_deref()) {
+            Some(key) => {
+                decode_key(key).ok_or("Preshared key is not a base64 32-byte key")?;
+                Some(key.to_string())
+            }
+            None => Some(generate_preshared_key()),
+        }
+    } else {
+        None
+    };
 
     let (health_interval, health_timeout) = if params.health_enabled {
         (Some(params.health_interval_ms), Some(params.health_timeout_ms))
@@ -183,8 +460,31 @@ fn generate_configs(params: ConfigParams) -> Result<GeneratedConfigs, String> {
     };
     let bonding_mode = params.bonding_mode.clone();
 
+    let client_addresses = addresses::tunnel_addresses(
+        "Client tunnel address",
+        &params.client_address,
+        &params.netmask,
+    )?;
+    // The client runs on this machine; only Linux adds addresses past the first.
+    if !cfg!(target_os = "linux") && !client_addresses.addresses.is_empty() {
+        return Err(
+            "This client takes a single IPv4 tunnel address; IPv6 and further addresses \
+             need Linux"
+                .to_string(),
+        );
+    }
+    let server_addresses = addresses::tunnel_addresses(
+        "Server tunnel address",
+        &params.server_address,
+        &params.netmask,
+    )?;
     let client_links = build_client_links(&params);
     let server_links = build_server_links(&params);
+    let full_tunnel = if params.full_tunnel {
+        Some(routing::full_tunnel(&params)?)
+    } else {
+        None
+    };
 
     let base_config = Config {
         network: NetworkConfig {
@@ -192,13 +492,14 @@ fn generate_configs(params: ConfigParams) -> Result<GeneratedConfigs, String> {
             buffer_size: params.buffer_size,
             interface: None,
             address: None,
-            netmask: Some(params.netmask),
+            netmask: None,
             destination: None,
+            addresses: Vec::new(),
         },
         wireguard: WireGuardConfig {
             private_key: String::new(),
             peer_public_key: String::new(),
-            preshared_key: None,
+            preshared_key: preshared_key.clone(),
             persistent_keepalive: keepalive,
             bonding_mode: Some(bonding_mode),
             error_backoff_secs: Some(params.error_backoff_secs),
@@ -206,23 +507,38 @@ fn generate_configs(params: ConfigParams) -> Result<GeneratedConfigs, String> {
             health_check_timeout_ms: health_timeout,
             links: Vec::new(),
         },
+        hooks: None,
+        control: None,
     };
 
     let mut client_config = base_config.clone();
     client_config.network.interface = Some(params.client_interface);
-    client_config.network.address = Some(params.client_address);
+    client_config.network.address = client_addresses.address;
+    client_config.network.netmask = client_addresses.netmask;
+    client_config.network.addresses = client_addresses.addresses;
     client_config.wireguard.private_key = client_private_key.clone();
     client_config.wireguard.peer_public_key = server_public_key.clone();
     client_config.wireguard.links = client_links;
+    client_config.hooks = full_tunnel.as_ref().map(|routes| routes.client.clone());
+    let control_socket = params.control_socket.trim();
+    client_config.control = (!control_socket.is_empty()).then(|| ControlConfig {
+        socket: control_socket.to_string(),
+    });
 
     let mut server_config = base_config;
-    server_config.network.address = Some(params.server_address);
+    server_config.network.address = server_addresses.address;
+    server_config.network.netmask = server_addresses.netmask;
+    server_config.network.addresses = server_addresses.addresses;
     server_config.wireguard.private_key = server_private_key.clone();
     server_config.wireguard.peer_public_key = client_public_key.clone();
     server_config.wireguard.links = server_links;
+    server_config.hooks = full_tunnel.as_ref().map(|routes| routes.server.clone());
 
     let client_yaml = serde_yaml::to_string(&client_config).map_err(|e| e.to_string())?;
     let server_yaml = serde_yaml::to_string(&server_config).map_err(|e| e.to_string())?;
+    // Catch anything the daemon would reject before it is written or provisioned.
+    core_config::parse_config(&client_yaml).map_err(|e| format!("Client config: {}", e))?;
+    core_config::parse_config(&server_yaml).map_err(|e| format!("Server config: {}", e))?;
 
     Ok(GeneratedConfigs {
         client_yaml,
@@ -231,228 +547,638 @@ fn generate_configs(params: ConfigParams) -> Result<GeneratedConfigs, String> {
         client_public_key,
         server_private_key,
         server_public_key,
+        preshared_key,
+        bypass: full_tunnel.map(|routes| routes.bypass).unwrap_or_default(),
     })
 }
 
 #[tauri::command]
-fn write_config(app: AppHandle, kind: String, yaml: String) -> Result<String, String> {
+fn write_config(
+    app: AppHandle,
+    kind: String,
+    yaml: String,
+    tunnel: Option<String>,
+) -> Result<String, String> {
     let config_dir = app_config_dir(&app)?;
     fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
-    let filename = match kind.as_str() {
-        "client" => "client.yaml",
-        "server" => "server.yaml",
+    let base = match kind.as_str() {
+        "client" => "client",
+        "server" => "server",
         _ => return Err("Unsupported config kind".to_string()),
     };
-    let path = config_dir.join(filename);
-    fs::write(&path, yaml).map_err(|e| e.to_string())?;
+    let tunnel = tunnel.as_deref().unwrap_or(DEFAULT_TUNNEL);
+    let path = config_dir.join(format!("{}.yaml", tunnel_file(tunnel, base)));
+    let yaml = keychain::seal(&path, &yaml)?;
+    write_private_file(&path, &yaml)?;
     Ok(path.to_string_lossy().to_string())
 }
 
 #[tauri::command]
+fn import_config(path: String) -> Result<ImportedConfig, String> {
+    let yaml = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    imported_config(yaml)
+}
+
+fn imported_config(yaml: String) -> Result<ImportedConfig, String> {
+    let config = core_config::parse_config(&yaml).map_err(|e| e.to_string())?;
+    // Only a client dials out; the server learns its peer's addresses.
+    let is_server = config.wireguard.links.iter().all(|link| link.endpoint.is_none());
+    let params = if is_server {
+        server_params(&config)
+    } else {
+        client_params(&config)
+    };
+    Ok(ImportedConfig {
+        kind: if is_server { "server" } else { "client" }.to_string(),
+        yaml,
+        params,
+        public_key: public_key_of(&config.wireguard.private_key),
+        peer_public_key: config.wireguard.peer_public_key.clone(),
+    })
+}
+
+#[tauri::command]
+fn save_config(path: String, yaml: String) -> Result<(), String> {
+    core_config::parse_config(&yaml).map_err(|e| e.to_string())?;
+    write_private_file(Path::new(&path), &yaml)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn start_vtrunkd(
     app: AppHandle,
     state: State<RunnerState>,
+    tunnel: String,
     binary_path: String,
     config_path: String,
+    elevate: bool,
+    notify: bool,
+    auto_restart: bool,
 ) -> Result<(), String> {
-    let mut guard = state.child.lock().map_err(|_| "State lock failed".to_string())?;
+    let tunnel = state.tunnel(&tunnel)?;
+    tunnel.notify.store(notify, Ordering::Relaxed);
+    tunnel.auto_restart.store(auto_restart, Ordering::Relaxed);
+    tunnel.restarts.store(0, Ordering::Relaxed);
+    tunnel.restart_pending.store(false, Ordering::Relaxed);
+    launch(
+        &app,
+        state.inner(),
+        &tunnel,
+        Launch {
+            binary_path,
+            config_path,
+            elevate,
+        },
+    )
+}
+
+/// Starts the daemon and the threads following it; also how the watchdog restarts it.
+fn launch(
+    app: &AppHandle,
+    state: &RunnerState,
+    tunnel: &Arc<Tunnel>,
+    launch: Launch,
+) -> Result<(), String> {
+    let _launching = state
+        .launching
+        .lock()
+        .map_err(|_| "State lock failed".to_string())?;
+    check_conflicts(state, tunnel, &launch.config_path)?;
+    let mut guard = tunnel.child.lock().map_err(|_| "State lock failed".to_string())?;
     if guard.is_some() {
-        return Err("vtrunkd is already running".to_string());
+        return Err(format!("Tunnel {} is already running", tunnel.name));
+    }
+
+    let config_path = launch.config_path.as_str();
+    let run = tunnel.run.fetch_add(1, Ordering::Relaxed) + 1;
+    if let Ok(mut tail) = tunnel.stderr_tail.lock() {
+        tail.clear();
     }
+    let (daemon, elevation, stderr_reader) = match embedded::start(app, tunnel, &launch)? {
+        Some(daemon) => (daemon, None, None),
+        None => spawn_daemon(app, tunnel, &launch, run)?,
+    };
+    #[cfg(unix)]
+    {
+        let config = core_config::load_config(Path::new(config_path)).ok();
+        let socket_path = control::socket_path(config.as_ref().and_then(|c| c.control_socket()));
+        let links = config.map_or(0, |config| config.wireguard.links.len());
+        watch_events(app.clone(), tunnel.clone(), socket_path.clone(), links);
+        poll_stats(app.clone(), tunnel.clone(), socket_path, run);
+    }
+    watchdog::watch_exit(app.clone(), tunnel.clone(), run, stderr_reader);
+
+    *tunnel
+        .elevation
+        .lock()
+        .map_err(|_| "State lock failed".to_string())? = elevation;
+    *guard = Some(daemon);
+    *tunnel.launch.lock().map_err(|_| "State lock failed".to_string())? = Some(launch);
+    Ok(())
+}
 
-    let mut command = Command::new(if binary_path.is_empty() {
+/// The daemon, how it was elevated, and the thread reading its stderr.
+type Spawned = (Daemon, Option<Elevation>, Option<JoinHandle<()>>);
+
+/// Runs the `vtrunkd` binary, following its output.
+fn spawn_daemon(
+    app: &AppHandle,
+    tunnel: &Arc<Tunnel>,
+    launch: &Launch,
+    run: u64,
+) -> Result<Spawned, String> {
+    let program = if launch.binary_path.is_empty() {
         "vtrunkd"
     } else {
-        binary_path.as_str()
-    });
-    let mut child = command
-        .arg("--config")
-        .arg(&config_path)
-        .arg("--foreground")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to start vtrunkd: {}", e))?;
+        launch.binary_path.as_str()
+    };
+    let config_path = launch.config_path.as_str();
+    let credentials = keychain::credentials_dir(app, Path::new(config_path))?;
+    let (mut command, elevation) = if launch.elevate {
+        elevated_command(app, &tunnel.name, program, config_path, credentials.as_deref())?
+    } else {
+        let mut command = Command::new(program);
+        command.arg("--config").arg(config_path).arg("--foreground");
+        if let Some(dir) = &credentials {
+            command.env("CREDENTIALS_DIRECTORY", dir);
+        }
+        (command, None)
+    };
+    let mut child = match command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            if let Some(dir) = &credentials {
+                keychain::remove_credentials(dir);
+            }
+            return Err(format!("Failed to start vtrunkd: {}", e));
+        }
+    };
+    *tunnel
+        .credentials
+        .lock()
+        .map_err(|_| "State lock failed".to_string())? = credentials;
 
     if let Some(stdout) = child.stdout.take() {
-        stream_logs(app.clone(), stdout, "vtrunkd-log");
+        stream_logs(app.clone(), tunnel.clone(), stdout, false);
     }
-    if let Some(stderr) = child.stderr.take() {
-        stream_logs(app.clone(), stderr, "vtrunkd-log");
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|stderr| stream_logs(app.clone(), tunnel.clone(), stderr, true));
+    if let Some(Elevation::Osascript { pidfile }) = &elevation {
+        follow_log(app.clone(), tunnel.clone(), pidfile.with_extension("log"), run);
     }
-
-    *guard = Some(child);
-    Ok(())
+    Ok((Daemon::Process(child), elevation, stderr_reader))
 }
 
-#[tauri::command]
-fn stop_vtrunkd(state: State<RunnerState>) -> Result<(), String> {
-    let mut guard = state.child.lock().map_err(|_| "State lock failed".to_string())?;
-    if let Some(mut child) = guard.take() {
-        child.kill().map_err(|e| e.to_string())?;
-        let _ = child.wait();
-        Ok(())
-    } else {
-        Err("vtrunkd is not running".to_string())
-    }
+/// What a running tunnel holds that another one started beside it must not share.
+struct Claims {
+    config_path: String,
+    interface: Option<String>,
+    socket: PathBuf,
+    binds: Vec<SocketAddr>,
+    full_tunnel: bool,
 }
 
-#[tauri::command]
-fn get_remote_fingerprint(host: String, port: u16) -> Result<String, String> {
-    if host.trim().is_empty() || host.starts_with('-') {
-        return Err("Invalid host".to_string());
-    }
-    let output = Command::new("ssh-keyscan")
-        .arg("-p")
-        .arg(port.to_string())
-        .arg(&host)
-        .output()
-        .map_err(|e| format!("ssh-keyscan failed: {}", e))?;
-
-    if !output.status.success() {
-        let err = String::from_utf8_lossy(&output.stderr);
-        return Err(if err.trim().is_empty() {
-            "ssh-keyscan failed".to_string()
-        } else {
-            err.to_string()
-        });
+impl Claims {
+    fn of(config_path: &str) -> Claims {
+        let config = core_config::load_config(Path::new(config_path)).ok();
+        let config = config.as_ref();
+        Claims {
+            config_path: config_path.to_string(),
+            interface: config.and_then(|config| config.network.interface.clone()),
+            socket: control::socket_path(config.and_then(|config| config.control_socket())),
+            // Port 0 picks a free port each time, so those never collide.
+            binds: config
+                .map(|config| {
+                    config
+                        .wireguard
+                        .links
+                        .iter()
+                        .filter_map(|link| link.bind.as_deref()?.parse().ok())
+                        .filter(|bind: &SocketAddr| bind.port() != 0)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            full_tunnel: config.is_some_and(routing::is_full_tunnel),
+        }
     }
 
-    if output.stdout.is_empty() {
-        return Err("No keys found for host. Ensure the host is reachable and SSH is running.".to_string());
+    /// What both claim, if anything, and how to resolve it.
+    fn clash(&self, other: &Claims) -> Option<String> {
+        if self.config_path == other.config_path {
+            return Some(format!("the config {}", self.config_path));
+        }
+        if let Some(interface) = &self.interface {
+            if other.interface.as_ref() == Some(interface) {
+                return Some(format!("interface {}; give each tunnel its own", interface));
+            }
+        }
+        if self.socket == other.socket {
+            return Some(format!(
+                "control socket {}; set a control socket for each tunnel",
+                self.socket.display()
+            ));
+        }
+        if let Some(bind) = self.binds.iter().find(|bind| other.binds.contains(bind)) {
+            return Some(format!("link address {}", bind));
+        }
+        if self.full_tunnel && other.full_tunnel {
+            return Some("the default route; only one tunnel can take all traffic".to_string());
+        }
+        None
     }
+}
 
-    let mut child = Command::new("ssh-keygen")
-        .arg("-lf")
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("ssh-keygen failed: {}", e))?;
-
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(&output.stdout)
-            .map_err(|e| format!("Failed to write to ssh-keygen: {}", e))?;
+/// Refuses to start `tunnel` from a config that collides with another running tunnel's,
+/// which would otherwise fail in the daemon or take over the other tunnel's device.
+fn check_conflicts(state: &RunnerState, tunnel: &Tunnel, config_path: &str) -> Result<(), String> {
+    let others: Vec<Arc<Tunnel>> = state
+        .running()
+        .into_iter()
+        .filter(|other| other.name != tunnel.name)
+        .collect();
+    if others.is_empty() {
+        return Ok(());
     }
-
-    let output = child
-        .wait_with_output()
-        .map_err(|e| format!("ssh-keygen wait failed: {}", e))?;
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+    let ours = Claims::of(config_path);
+    for other in others {
+        let Some(launch) = other.launch.lock().ok().and_then(|launch| launch.clone()) else {
+            continue;
+        };
+        if let Some(clash) = ours.clash(&Claims::of(&launch.config_path)) {
+            return Err(format!("Tunnel {} already uses {}", other.name, clash));
+        }
     }
+    Ok(())
+}
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+#[tauri::command]
+fn stop_vtrunkd(state: State<RunnerState>, tunnel: String) -> Result<(), String> {
+    let tunnel = state.tunnel(&tunnel)?;
+    let mut guard = tunnel.child.lock().map_err(|_| "State lock failed".to_string())?;
+    let mut elevation = tunnel
+        .elevation
+        .lock()
+        .map_err(|_| "State lock failed".to_string())?;
+    let restart_cancelled = tunnel.restart_pending.swap(false, Ordering::Relaxed);
+    let Some(daemon) = guard.as_mut() else {
+        if restart_cancelled {
+            return Ok(());
+        }
+        return Err(format!("Tunnel {} is not running", tunnel.name));
+    };
+    daemon.stop(elevation.as_ref())?;
+    *guard = None;
+    *elevation = None;
+    tunnel.clear_credentials();
+    Ok(())
 }
 
+/// The tunnels started this session, running or not, by name.
 #[tauri::command]
-fn trust_host(app: AppHandle, host: String, port: u16) -> Result<(), String> {
-    if host.trim().is_empty() || host.starts_with('-') {
-        return Err("Invalid host".to_string());
-    }
-    let config_dir = app_config_dir(&app)?;
-    fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
-    let known_hosts_path = config_dir.join("known_hosts");
+fn list_tunnels(state: State<RunnerState>) -> Result<Vec<TunnelStatus>, String> {
+    let tunnels = state
+        .tunnels
+        .lock()
+        .map_err(|_| "State lock failed".to_string())?;
+    let mut statuses: Vec<TunnelStatus> = tunnels
+        .values()
+        .map(|tunnel| TunnelStatus {
+            name: tunnel.name.clone(),
+            state: if tunnel.is_running() {
+                "running"
+            } else if tunnel.restart_pending.load(Ordering::Relaxed) {
+                "restarting"
+            } else {
+                "stopped"
+            },
+            config_path: tunnel
+                .launch
+                .lock()
+                .ok()
+                .and_then(|launch| launch.as_ref().map(|launch| launch.config_path.clone())),
+            restarts: tunnel.restarts.load(Ordering::Relaxed),
+        })
+        .collect();
+    statuses.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(statuses)
+}
 
-    let output = Command::new("ssh-keyscan")
-        .arg("-p")
-        .arg(port.to_string())
-        .arg(&host)
-        .output()
-        .map_err(|e| format!("ssh-keyscan failed: {}", e))?;
+/// Turns desktop notifications for a tunnel on or off.
+#[tauri::command]
+fn set_notifications(
+    state: State<RunnerState>,
+    tunnel: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state.tunnel(&tunnel)?.notify.store(enabled, Ordering::Relaxed);
+    Ok(())
+}
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+/// A blank name is the default tunnel.
+fn tunnel_name(name: &str) -> &str {
+    match name.trim() {
+        "" => DEFAULT_TUNNEL,
+        name => name,
     }
+}
 
-    if output.stdout.is_empty() {
-        return Err("No keys found to trust".to_string());
+/// The file name, without extension, of a tunnel's `base` file (`client`, `vtrunkd`).
+fn tunnel_file(tunnel: &str, base: &str) -> String {
+    match tunnel_name(tunnel) {
+        DEFAULT_TUNNEL => base.to_string(),
+        name => {
+            let name: String = name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("{}-{}", base, name)
+        }
     }
+}
 
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(known_hosts_path)
-        .map_err(|e| format!("Failed to open known_hosts: {}", e))?;
-
-    // Ensure there's a trailing newline in the output to avoid corrupting the file if it's missing one.
-    let mut keys = output.stdout;
-    if !keys.is_empty() && !keys.ends_with(b"\n") {
-        keys.push(b'\n');
+#[tauri::command]
+fn get_remote_fingerprint(host: String, port: u16) -> Result<String, String> {
+    if host.trim().is_empty() {
+        return Err("Invalid host".to_string());
     }
+    ssh::fingerprint(host.trim(), port)
+}
 
-    file.write_all(&keys)
-        .map_err(|e| format!("Failed to write to known_hosts: {}", e))?;
-    Ok(())
+#[tauri::command]
+fn trust_host(app: AppHandle, host: String, port: u16, fingerprint: String) -> Result<(), String> {
+    if host.trim().is_empty() {
+        return Err("Invalid host".to_string());
+    }
+    let known_hosts_path = app_config_dir(&app)?.join("known_hosts");
+    ssh::trust(host.trim(), port, &fingerprint, &known_hosts_path)
 }
 
 #[tauri::command]
-fn provision_vps(
+async fn provision_vps(
     app: AppHandle,
     ssh: SshConfig,
     options: ProvisionOptions,
     server_yaml: String,
 ) -> Result<String, String> {
-    let user = if ssh.use_root {
-        "root".to_string()
+    if server_yaml.trim().is_empty() {
+        return Err("Server config is empty".to_string());
+    }
+
+    let config_b64 = general_purpose::STANDARD.encode(server_yaml.as_bytes());
+    let credentials = if options.install_service {
+        split_credentials(&server_yaml)?
     } else {
-        ssh.user.trim().to_string()
+        None
     };
-    if ssh.host.trim().is_empty() {
-        return Err("SSH host is required".to_string());
+    let script = build_provision_script(
+        &config_b64,
+        credentials.as_ref(),
+        &options,
+        INSTALLED_BINARY,
+    );
+
+    let known_hosts_path = app_config_dir(&app)?.join("known_hosts");
+    // Run off the main thread so the progress events reach the window during the install.
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts_path)?;
+        // sh: Alpine, OpenWrt and FreeBSD come without bash.
+        let (_, status) = ssh::run_streaming(&session, "sh -s", Some(&script), |line| {
+            match line.strip_prefix(PROVISION_STEP) {
+                Some(step) => {
+                    let _ = app.emit_all("provision-step", step.to_string());
+                }
+                None => {
+                    let _ = app.emit_all("provision-log", line.to_string());
+                }
+            }
+        })?;
+        if status == 0 {
+            Ok("Provisioning complete.".to_string())
+        } else {
+            Err(format!("the script exited with status {}", status))
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn write_private_file(path: &Path, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| e.to_string())?;
+    // vtrunkd refuses to start from a config with keys that other users can read.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| e.to_string())?;
     }
-    if user.trim().is_empty() {
-        return Err("SSH user is required".to_string());
+    Ok(())
+}
+
+/// A command starting vtrunkd as root behind the system's password prompt, since creating
+/// the TUN device needs it.
+#[cfg(target_os = "linux")]
+fn elevated_command(
+    _app: &AppHandle,
+    _tunnel: &str,
+    program: &str,
+    config_path: &str,
+    credentials: Option<&Path>,
+) -> Result<(Command, Option<Elevation>), String> {
+    let mut command = Command::new("pkexec");
+    // pkexec clears the environment; `env` execs vtrunkd in its place with the variable.
+    if let Some(dir) = credentials {
+        command
+            .arg("env")
+            .arg(format!("CREDENTIALS_DIRECTORY={}", dir.display()));
     }
-    if server_yaml.trim().is_empty() {
-        return Err("Server config is empty".to_string());
+    command
+        .arg(program)
+        .arg("--config")
+        .arg(config_path)
+        .arg("--foreground");
+    Ok((command, Some(Elevation::Pkexec)))
+}
+
+#[cfg(target_os = "macos")]
+fn elevated_command(
+    app: &AppHandle,
+    tunnel: &str,
+    program: &str,
+    config_path: &str,
+    credentials: Option<&Path>,
+) -> Result<(Command, Option<Elevation>), String> {
+    // `do shell script` only returns output once the script ends, so the daemon logs to a
+    // file that `follow_log` streams, next to the pidfile used to stop it.
+    let config_dir = app_config_dir(app)?;
+    fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
+    let pidfile = config_dir.join(format!("{}.pid", tunnel_file(tunnel, "vtrunkd")));
+    let log = pidfile.with_extension("log");
+    fs::write(&log, "").map_err(|e| e.to_string())?;
+    let environment = credentials.map_or(String::new(), |dir| {
+        format!("CREDENTIALS_DIRECTORY={} ", shell_quote(&dir.to_string_lossy()))
+    });
+    let script = format!(
+        "{}{} --config {} --foreground --pidfile {} >> {} 2>&1",
+        environment,
+        shell_quote(program),
+        shell_quote(config_path),
+        shell_quote(&pidfile.to_string_lossy()),
+        shell_quote(&log.to_string_lossy())
+    );
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(administrator_script(&script));
+    Ok((command, Some(Elevation::Osascript { pidfile })))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn elevated_command(
+    _app: &AppHandle,
+    _tunnel: &str,
+    _program: &str,
+    _config_path: &str,
+    _credentials: Option<&Path>,
+) -> Result<(Command, Option<Elevation>), String> {
+    Err("Run the Control Room as Administrator to start the tunnel".to_string())
+}
+
+fn stop_elevated(elevation: &Elevation, child_pid: u32) -> Result<(), String> {
+    let output = match elevation {
+        Elevation::Pkexec => Command::new("pkexec")
+            .arg("kill")
+            .arg("-TERM")
+            .arg(child_pid.to_string())
+            .output(),
+        Elevation::Osascript { pidfile } => {
+            let pid: u32 = fs::read_to_string(pidfile)
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+                .ok_or_else(|| format!("No vtrunkd pid in {}", pidfile.display()))?;
+            Command::new("osascript")
+                .arg("-e")
+                .arg(administrator_script(&format!("kill -TERM {}", pid)))
+                .output()
+        }
+    }
+    .map_err(|e| format!("Failed to stop vtrunkd: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to stop vtrunkd: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
     }
+}
 
-    let config_b64 = general_purpose::STANDARD.encode(server_yaml.as_bytes());
-    let script = build_provision_script(&config_b64, &options);
+/// AppleScript running `script` in a root shell after asking for an admin password.
+fn administrator_script(script: &str) -> String {
+    format!(
+        "do shell script \"{}\" with administrator privileges",
+        script.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
 
-    let target = format!("{}@{}", user, ssh.host);
-    let config_dir = app_config_dir(&app)?;
-    let known_hosts_path = config_dir.join("known_hosts");
-
-    let mut cmd = Command::new("ssh");
-    cmd.arg("-p")
-        .arg(ssh.port.to_string())
-        .arg("-o")
-        .arg("BatchMode=yes")
-        .arg("-o")
-        .arg("StrictHostKeyChecking=yes")
-        .arg("-o")
-        .arg(format!("UserKnownHostsFile={}", known_hosts_path.to_string_lossy()))
-        .arg("-o")
-        .arg("ConnectTimeout=10");
-
-    if !ssh.key_path.trim().is_empty() {
-        cmd.arg("-i").arg(ssh.key_path.trim());
-    }
-
-    cmd.arg(target).arg("bash -s");
-    cmd.stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+#[cfg(target_os = "macos")]
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Streams lines appended to `path` until the tunnel is stopped or started again.
+fn follow_log(app: AppHandle, tunnel: Arc<Tunnel>, path: PathBuf, run: u64) {
+    std::thread::spawn(move || {
+        let Ok(file) = fs::File::open(&path) else {
+            return;
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) => {
+                    if tunnel.run.load(Ordering::Relaxed) != run || !tunnel.is_running() {
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(250));
+                }
+                Ok(_) => logs::record(&app, &tunnel.name, &line),
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+/// Installs the client config as `vtrunkd.service` on this machine, the way provisioning
+/// sets up the VPS, so the tunnel outlives the app and comes back after a reboot.
+#[tauri::command]
+fn install_local_service(binary_path: String, client_yaml: String) -> Result<String, String> {
+    let binary = binary_path.trim();
+    if !binary.starts_with('/') || binary.contains(char::is_whitespace) {
+        return Err("The service needs an absolute vtrunkd path without spaces".to_string());
+    }
+    core_config::parse_config(&client_yaml).map_err(|e| format!("Client config: {}", e))?;
+    let config_b64 = general_purpose::STANDARD.encode(client_yaml.as_bytes());
+    let credentials = split_credentials(&client_yaml)?;
+    let options = ProvisionOptions {
+        install_vtrunkd: false,
+        install_service: true,
+        prebuilt: false,
+    };
+    let script = build_provision_script(&config_b64, credentials.as_ref(), &options, binary);
+    run_as_root(&["bash", "-s"], Some(&script))
+}
 
-    let mut child = cmd.spawn().map_err(|e| format!("SSH spawn failed: {}", e))?;
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin
-            .write_all(script.as_bytes())
-            .map_err(|e| format!("SSH stdin failed: {}", e))?;
+/// `start`, `stop`, `restart`, `enable` or `disable` for the local `vtrunkd.service`.
+#[tauri::command]
+fn local_service(action: String) -> Result<String, String> {
+    if !["start", "stop", "restart", "enable", "disable"].contains(&action.as_str()) {
+        return Err("Unsupported service action".to_string());
     }
+    run_as_root(&["systemctl", &action, "vtrunkd"], None)
+}
+
+/// `active`/`inactive`/... and `enabled`/`disabled`, which need no privileges to read.
+#[tauri::command]
+fn local_service_status() -> Result<String, String> {
+    let query = |verb: &str| {
+        Command::new("systemctl")
+            .arg(verb)
+            .arg("vtrunkd")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .map_err(|e| format!("systemctl failed: {}", e))
+    };
+    Ok(format!("{}, {}", query("is-active")?, query("is-enabled")?))
+}
 
+/// Runs `args` through pkexec, feeding `stdin` if given, and returns its combined output.
+fn run_as_root(args: &[&str], stdin: Option<&str>) -> Result<String, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("The local service needs Linux with systemd".to_string());
+    }
+    let mut child = Command::new("pkexec")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("pkexec failed: {}", e))?;
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), stdin) {
+        pipe.write_all(input.as_bytes())
+            .map_err(|e| format!("pkexec stdin failed: {}", e))?;
+    }
     let output = child
         .wait_with_output()
-        .map_err(|e| format!("SSH failed: {}", e))?;
-
+        .map_err(|e| format!("pkexec failed: {}", e))?;
     let mut combined = String::new();
     combined.push_str(&String::from_utf8_lossy(&output.stdout));
     combined.push_str(&String::from_utf8_lossy(&output.stderr));
-
     if output.status.success() {
         Ok(combined.trim().to_string())
     } else {
@@ -466,16 +1192,187 @@ fn app_config_dir(app: &AppHandle) -> Result<PathBuf, String> {
         .ok_or_else(|| "Unable to resolve app config directory".to_string())
 }
 
-fn stream_logs<R: std::io::Read + Send + 'static>(app: AppHandle, reader: R, event: &str) {
-    let event_name = event.to_string();
+/// Records each line of the daemon's output; with `keep_tail`, also keeps the last few for
+/// the watchdog to show when the daemon exits.
+fn stream_logs<R: std::io::Read + Send + 'static>(
+    app: AppHandle,
+    tunnel: Arc<Tunnel>,
+    reader: R,
+    keep_tail: bool,
+) -> JoinHandle<()> {
     std::thread::spawn(move || {
         let reader = BufReader::new(reader);
-        for line in reader.lines().flatten() {
-            let _ = app.emit_all(&event_name, line);
+        for line in reader.lines().map_while(Result::ok) {
+            if keep_tail {
+                if let Ok(mut tail) = tunnel.stderr_tail.lock() {
+                    if tail.len() == watchdog::STDERR_TAIL {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line.trim_end().to_string());
+                }
+            }
+            logs::record(&app, &tunnel.name, &line);
+        }
+    })
+}
+
+fn watch_events(app: AppHandle, tunnel: Arc<Tunnel>, socket_path: PathBuf, links: usize) {
+    use std::os::unix::net::UnixStream;
+
+    std::thread::spawn(move || {
+        let mut stream = None;
+        for _ in 0..20 {
+            match UnixStream::connect(&socket_path) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(500)),
+            }
+        }
+        let Some(mut stream) = stream else {
+            let _ = app.emit_all(
+                "vtrunkd-log",
+                logs::tagged(
+                    &tunnel.name,
+                    &format!("Event stream unavailable at {}", socket_path.display()),
+                ),
+            );
+            return;
+        };
+        if stream.write_all(b"events\n").is_err() {
+            return;
+        }
+
+        let mut lines = BufReader::new(stream).lines();
+        match lines.next() {
+            Some(Ok(line)) if line.starts_with("ok") => {}
+            _ => return,
+        }
+        let mut watch = notifications::LinkWatch::new(links);
+        for line in lines.map_while(Result::ok) {
+            if let Ok(event) = serde_json::from_str::<serde_json::Value>(&line) {
+                // Tracked even with notifications off, so turning them on mid-run is right.
+                if let Some((title, body)) = watch.notification(&event) {
+                    if tunnel.notify.load(Ordering::Relaxed) {
+                        let title = format!("{}: {}", tunnel.name, title);
+                        notifications::show(&app, &title, &body);
+                    }
+                }
+                let _ = app.emit_all(
+                    "vtrunkd-event",
+                    TunnelEvent {
+                        tunnel: tunnel.name.clone(),
+                        event,
+                    },
+                );
+            }
+        }
+    });
+}
+
+/// Asks the daemon for its counters every second and pushes per-link state, RTT, loss and
+/// throughput to the dashboard, until the tunnel is stopped.
+fn poll_stats(app: AppHandle, tunnel: Arc<Tunnel>, socket_path: PathBuf, run: u64) {
+    std::thread::spawn(move || {
+        let mut history: VecDeque<StatsSnapshot> = VecDeque::with_capacity(LOSS_WINDOW);
+        loop {
+            std::thread::sleep(STATS_INTERVAL);
+            if tunnel.run.load(Ordering::Relaxed) != run {
+                return;
+            }
+            if !tunnel.is_running() {
+                let stats = TunnelStats {
+                    tunnel: tunnel.name.clone(),
+                    links: Vec::new(),
+                };
+                let _ = app.emit_all("vtrunkd-stats", stats);
+                return;
+            }
+            let snapshot = match query_stats(&socket_path) {
+                Ok(snapshot) => snapshot,
+                // Not listening yet, or restarting the tunnel; rates restart with it.
+                Err(_) => {
+                    history.clear();
+                    continue;
+                }
+            };
+            let stats = TunnelStats {
+                tunnel: tunnel.name.clone(),
+                links: dashboard_rows(&snapshot, history.back(), history.front()),
+            };
+            let _ = app.emit_all("vtrunkd-stats", stats);
+            if history.len() == LOSS_WINDOW {
+                history.pop_front();
+            }
+            history.push_back(snapshot);
         }
     });
 }
 
+fn query_stats(socket_path: &Path) -> Result<StatsSnapshot, String> {
+    let body = control_request(socket_path, &control::ControlCommand::Stats)?;
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
+
+/// Sends one command to the daemon's control socket and returns the reply after `ok`.
+fn control_request(socket_path: &Path, command: &control::ControlCommand) -> Result<String, String> {
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket_path).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(STATS_INTERVAL))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(format!("{}\n", command.to_line()).as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let body = line
+        .trim_end()
+        .strip_prefix("ok")
+        .ok_or_else(|| line.trim_end().to_string())?;
+    Ok(body.trim_start().to_string())
+}
+
+/// Rates against the poll a second ago (`previous`), loss against the oldest one kept.
+fn dashboard_rows(
+    current: &StatsSnapshot,
+    previous: Option<&StatsSnapshot>,
+    oldest: Option<&StatsSnapshot>,
+) -> Vec<LinkDashboard> {
+    let find = |snapshot: Option<&StatsSnapshot>, name: &str| {
+        snapshot.and_then(|s| s.links.iter().find(|link| link.name == name).cloned())
+    };
+    let interval = STATS_INTERVAL.as_secs().max(1);
+    current
+        .links
+        .iter()
+        .map(|link| {
+            let before = find(previous, &link.name);
+            let rate = |now: u64, then: Option<u64>| {
+                then.map_or(0, |then| now.saturating_sub(then) * 8 / interval)
+            };
+            let loss = find(oldest, &link.name).and_then(|start| {
+                stats::loss_percent(
+                    link.pings_sent.saturating_sub(start.pings_sent),
+                    link.pongs_received.saturating_sub(start.pongs_received),
+                )
+            });
+            LinkDashboard {
+                name: link.name.clone(),
+                state: link.state.clone().unwrap_or_else(|| "-".to_string()),
+                rtt_ms: link.rtt_ms,
+                loss_percent: loss,
+                rx_bits_per_sec: rate(link.rx_bytes, before.as_ref().map(|b| b.rx_bytes)),
+                tx_bits_per_sec: rate(link.tx_bytes, before.as_ref().map(|b| b.tx_bytes)),
+            }
+        })
+        .collect()
+}
+
 fn validate_params(params: &ConfigParams) -> Result<(), String> {
     if params.links.is_empty() {
         return Err("At least one link is required".to_string());
@@ -486,6 +1383,8 @@ fn validate_params(params: &ConfigParams) -> Result<(), String> {
     if params.server_bind.trim().is_empty() {
         return Err("Server bind address is required".to_string());
     }
+    let server = addresses::check_host("Server host", params.server_host.trim())?;
+    addresses::check_bind(params.server_bind.trim())?;
     if params.server_port_base == 0 {
         return Err("Server base port must be between 1 and 65535".to_string());
     }
@@ -510,6 +1409,7 @@ fn validate_params(params: &ConfigParams) -> Result<(), String> {
         if link.bind.trim().is_empty() {
             return Err("All links require a bind address".to_string());
         }
+        addresses::check_link_bind(&link.name, link.bind.trim(), server)?;
         if link.weight == 0 {
             return Err("Link weight must be greater than 0".to_string());
         }
@@ -517,6 +1417,28 @@ fn validate_params(params: &ConfigParams) -> Result<(), String> {
     Ok(())
 }
 
+fn generate_preshared_key() -> String {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    general_purpose::STANDARD.encode(key)
+}
+
+/// The given private key with its public key, or a fresh pair when none is given.
+fn existing_or_new_keypair(existing: Option<&str>, side: &str) -> Result<(String, String), String> {
+    match existing_key(existing) {
+        Some(private_key) => {
+            let public_key = public_key_of(private_key)
+                .ok_or_else(|| format!("{} private key is not a base64 32-byte key", side))?;
+            Ok((private_key.to_string(), public_key))
+        }
+        None => Ok(generate_keypair()),
+    }
+}
+
+fn existing_key(key: Option<&str>) -> Option<&str> {
+    key.map(str::trim).filter(|key| !key.is_empty())
+}
+
 fn generate_keypair() -> (String, String) {
     let mut private = [0u8; 32];
     OsRng.fill_bytes(&mut private);
@@ -557,6 +1479,127 @@ fn build_server_links(params: &ConfigParams) -> Vec<WireGuardLinkConfig> {
         .collect()
 }
 
+/// The settings both sides share, as `generate_configs` writes them into each config.
+fn shared_params(config: &core_config::Config) -> ConfigParams {
+    let wireguard = &config.wireguard;
+    let bonding_mode = match wireguard.bonding_mode.unwrap_or_default() {
+        core_config::BondingMode::Aggregate => "aggregate",
+        core_config::BondingMode::Redundant => "redundant",
+        core_config::BondingMode::Failover => "failover",
+    };
+    ConfigParams {
+        client_interface: String::new(),
+        client_address: String::new(),
+        server_address: String::new(),
+        netmask: config.network.netmask.clone().unwrap_or_default(),
+        mtu: config.network.mtu,
+        buffer_size: config.network.buffer_size,
+        bonding_mode: bonding_mode.to_string(),
+        keepalive: wireguard.persistent_keepalive.unwrap_or(0),
+        error_backoff_secs: wireguard
+            .error_backoff_secs
+            .unwrap_or(core_config::DEFAULT_ERROR_BACKOFF_SECS),
+        health_interval_ms: wireguard
+            .health_check_interval_ms
+            .unwrap_or(core_config::DEFAULT_HEALTH_INTERVAL_MS),
+        health_timeout_ms: wireguard.health_check_timeout_ms.unwrap_or(5000),
+        health_enabled: wireguard.health_check_timeout_ms.is_some(),
+        preshared_key: wireguard.preshared_key.is_some()
+            || wireguard.preshared_key_credential.is_some(),
+        server_host: String::new(),
+        server_bind: String::new(),
+        server_port_base: 0,
+        links: Vec::new(),
+        full_tunnel: routing::is_full_tunnel(config),
+        control_socket: String::new(),
+        client_private_key: None,
+        server_private_key: None,
+        preshared_key_value: wireguard.preshared_key.clone(),
+    }
+}
+
+fn client_params(config: &core_config::Config) -> ConfigParams {
+    let mut params = shared_params(config);
+    params.client_private_key = existing_key(Some(&config.wireguard.private_key)).map(String::from);
+    params.client_interface = config.network.interface.clone().unwrap_or_default();
+    params.control_socket = config.control_socket().unwrap_or_default().to_string();
+    params.client_address = addresses::field_of(&config.network);
+    params.server_address = config.network.destination.clone().unwrap_or_default();
+    if let Some((host, port)) = config
+        .wireguard
+        .links
+        .iter()
+        .find_map(|link| link.endpoint.as_deref().and_then(split_socket))
+    {
+        params.server_host = host;
+        params.server_port_base = port;
+    }
+    params.links = config
+        .wireguard
+        .links
+        .iter()
+        .enumerate()
+        .map(|(index, link)| LinkInput {
+            name: core_config::link_name(link, index),
+            bind: link.bind.clone().unwrap_or_default(),
+            weight: link.weight.unwrap_or(1),
+        })
+        .collect();
+    params
+}
+
+fn server_params(config: &core_config::Config) -> ConfigParams {
+    let mut params = shared_params(config);
+    params.server_private_key = existing_key(Some(&config.wireguard.private_key)).map(String::from);
+    params.server_address = addresses::field_of(&config.network);
+    if let Some((host, port)) = config
+        .wireguard
+        .links
+        .iter()
+        .find_map(|link| link.bind.as_deref().and_then(split_socket))
+    {
+        params.server_bind = host;
+        params.server_port_base = port;
+    }
+    params.links = config
+        .wireguard
+        .links
+        .iter()
+        .enumerate()
+        .map(|(index, link)| {
+            // Undo the `server-<index>-` prefix `build_server_links` adds.
+            let name = core_config::link_name(link, index);
+            let prefix = format!("server-{}-", index);
+            LinkInput {
+                name: name.strip_prefix(&prefix).unwrap_or(&name).to_string(),
+                bind: String::new(),
+                weight: link.weight.unwrap_or(1),
+            }
+        })
+        .collect();
+    params
+}
+
+/// Splits `host:port` or `[v6]:port` as written by `format_socket`.
+fn split_socket(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+fn decode_key(key: &str) -> Option<[u8; 32]> {
+    general_purpose::STANDARD
+        .decode(key.trim())
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+fn public_key_of(private_key: &str) -> Option<String> {
+    let public = PublicKey::from(&StaticSecret::from(decode_key(private_key)?));
+    Some(general_purpose::STANDARD.encode(public.as_bytes()))
+}
+
 fn format_socket(host: &str, port: u16) -> String {
     if host.contains(':') && !host.starts_with('[') {
         format!("[{}]:{}", host, port)
@@ -565,25 +1608,197 @@ fn format_socket(host: &str, port: u16) -> String {
     }
 }
 
-fn build_provision_script(config_b64: &str, options: &ProvisionOptions) -> String {
+/// Where provisioning installs vtrunkd on the VPS.
+const INSTALLED_BINARY: &str = "/usr/local/bin/vtrunkd";
+/// Release archives `vtrunkd-<target>.tar.gz`, each with a `.sha256` beside it.
+const RELEASE_URL: &str = "https://github.com/vzwjustin/vtrunkd/releases/latest/download";
+/// Prefix of the lines the provisioning script prints as it enters each step.
+const PROVISION_STEP: &str = "==> ";
+const PRIVATE_KEY_CREDENTIAL: &str = "vtrunkd.private_key";
+const PRESHARED_KEY_CREDENTIAL: &str = "vtrunkd.preshared_key";
+
+/// Server keys moved out of the config into service credentials, all base64 for the script.
+struct ServiceCredentials {
+    config_b64: String,
+    private_key_b64: String,
+    preshared_key_b64: Option<String>,
+}
+
+/// Splits the inline keys out of the server config so the service can hand them to vtrunkd
+/// (`LoadCredential=` under systemd, a credentials directory elsewhere); `None` when the
+/// config carries no private key.
+fn split_credentials(server_yaml: &str) -> Result<Option<ServiceCredentials>, String> {
+    let Some(split) = split_keys(server_yaml)? else {
+        return Ok(None);
+    };
+    Ok(Some(ServiceCredentials {
+        config_b64: general_purpose::STANDARD.encode(split.config_yaml.as_bytes()),
+        private_key_b64: general_purpose::STANDARD.encode(split.private_key.as_bytes()),
+        preshared_key_b64: split
+            .preshared_key
+            .map(|key| general_purpose::STANDARD.encode(key.as_bytes())),
+    }))
+}
+
+/// A config with its inline keys taken out and named as credentials instead.
+struct SplitKeys {
+    config_yaml: String,
+    private_key: String,
+    preshared_key: Option<String>,
+}
+
+/// `None` when the config carries no private key.
+fn split_keys(yaml: &str) -> Result<Option<SplitKeys>, String> {
+    let mut config: serde_yaml::Value =
+        serde_yaml::from_str(yaml).map_err(|e| format!("Invalid config: {}", e))?;
+    let Some(wireguard) = config
+        .get_mut("wireguard")
+        .and_then(serde_yaml::Value::as_mapping_mut)
+    else {
+        return Ok(None);
+    };
+    let private_key = match wireguard.remove("private_key") {
+        Some(serde_yaml::Value::String(key)) => key,
+        _ => return Ok(None),
+    };
+    let preshared_key = match wireguard.remove("preshared_key") {
+        Some(serde_yaml::Value::String(key)) => Some(key),
+        _ => None,
+    };
+    wireguard.insert("private_key_credential".into(), PRIVATE_KEY_CREDENTIAL.into());
+    if preshared_key.is_some() {
+        wireguard.insert("preshared_key_credential".into(), PRESHARED_KEY_CREDENTIAL.into());
+    }
+    let config_yaml =
+        serde_yaml::to_string(&config).map_err(|e| format!("Failed to encode config: {}", e))?;
+    Ok(Some(SplitKeys {
+        config_yaml,
+        private_key,
+        preshared_key,
+    }))
+}
+
+fn build_provision_script(
+    config_b64: &str,
+    credentials: Option<&ServiceCredentials>,
+    options: &ProvisionOptions,
+    binary: &str,
+) -> String {
     let install_flag = if options.install_vtrunkd { "1" } else { "0" };
     let service_flag = if options.install_service { "1" } else { "0" };
+    let prebuilt_flag = if options.prebuilt { "1" } else { "0" };
+    let credential_config_b64 = credentials.map_or("", |c| c.config_b64.as_str());
+    let private_key_b64 = credentials.map_or("", |c| c.private_key_b64.as_str());
+    let preshared_key_b64 = credentials
+        .and_then(|c| c.preshared_key_b64.as_deref())
+        .unwrap_or("");
+    let mut load_credentials = String::new();
+    if credentials.is_some() {
+        load_credentials.push_str(&format!(
+            "LoadCredential={}:/etc/vtrunkd/credentials/private.key\n",
+            PRIVATE_KEY_CREDENTIAL
+        ));
+    }
+    if !preshared_key_b64.is_empty() {
+        load_credentials.push_str(&format!(
+            "LoadCredential={}:/etc/vtrunkd/credentials/preshared.key\n",
+            PRESHARED_KEY_CREDENTIAL
+        ));
+    }
 
     format!(
-        "set -euo pipefail\n\
+        "set -eu\n\
+if (set -o pipefail) 2>/dev/null; then\n\
+  set -o pipefail\n\
+fi\n\
 CONFIG_B64='{config_b64}'\n\
 INSTALL_VTRUNKD='{install_flag}'\n\
 INSTALL_SERVICE='{service_flag}'\n\
+PREBUILT='{prebuilt_flag}'\n\
+RELEASE_URL='{RELEASE_URL}'\n\
+CREDENTIAL_CONFIG_B64='{credential_config_b64}'\n\
+PRIVATE_KEY_B64='{private_key_b64}'\n\
+PRESHARED_KEY_B64='{preshared_key_b64}'\n\
 SUDO=\"\"\n\
 if [ \"$(id -u)\" != \"0\" ]; then\n\
   SUDO=\"sudo\"\n\
 fi\n\
 \n\
+step() {{\n\
+  echo \"{PROVISION_STEP}$*\"\n\
+}}\n\
+\n\
+# systemd Linux, or one of the targets with their own package manager and init.\n\
+if [ \"$(uname -s)\" = 'FreeBSD' ]; then\n\
+  TARGET_OS='freebsd'\n\
+elif [ -f /etc/openwrt_release ]; then\n\
+  TARGET_OS='openwrt'\n\
+elif [ -f /etc/alpine-release ]; then\n\
+  TARGET_OS='alpine'\n\
+else\n\
+  TARGET_OS='linux'\n\
+fi\n\
+echo \"Target: $TARGET_OS\"\n\
+\n\
+decode() {{\n\
+  if command -v base64 >/dev/null 2>&1; then\n\
+    base64 -d\n\
+  elif command -v b64decode >/dev/null 2>&1; then\n\
+    b64decode -r\n\
+  else\n\
+    openssl base64 -d -A\n\
+  fi\n\
+}}\n\
+\n\
 write_config() {{\n\
-  printf '%s' \"$CONFIG_B64\" | base64 -d | $SUDO tee /etc/vtrunkd.yaml >/dev/null\n\
+  step 'Writing /etc/vtrunkd.yaml'\n\
+  printf '%s' \"$CONFIG_B64\" | decode | $SUDO tee /etc/vtrunkd.yaml >/dev/null\n\
+  $SUDO chmod 600 /etc/vtrunkd.yaml\n\
+}}\n\
+\n\
+write_credentials() {{\n\
+  if [ -z \"$CREDENTIAL_CONFIG_B64\" ]; then\n\
+    return\n\
+  fi\n\
+  $SUDO install -d -m 700 /etc/vtrunkd/credentials\n\
+  printf '%s' \"$PRIVATE_KEY_B64\" | decode | $SUDO tee /etc/vtrunkd/credentials/private.key >/dev/null\n\
+  $SUDO chmod 600 /etc/vtrunkd/credentials/private.key\n\
+  if [ -n \"$PRESHARED_KEY_B64\" ]; then\n\
+    printf '%s' \"$PRESHARED_KEY_B64\" | decode | $SUDO tee /etc/vtrunkd/credentials/preshared.key >/dev/null\n\
+    $SUDO chmod 600 /etc/vtrunkd/credentials/preshared.key\n\
+  fi\n\
+  if [ \"$TARGET_OS\" != 'linux' ]; then\n\
+    # Without systemd the daemon reads the directory itself, by credential name.\n\
+    $SUDO ln -sf private.key /etc/vtrunkd/credentials/{PRIVATE_KEY_CREDENTIAL}\n\
+    $SUDO ln -sf preshared.key /etc/vtrunkd/credentials/{PRESHARED_KEY_CREDENTIAL}\n\
+  fi\n\
+  printf '%s' \"$CREDENTIAL_CONFIG_B64\" | decode | $SUDO tee /etc/vtrunkd.yaml >/dev/null\n\
+}}\n\
+\n\
+prepare_target() {{\n\
+  if [ \"$TARGET_OS\" = 'openwrt' ]; then\n\
+    step 'Installing the TUN driver and certificates'\n\
+    $SUDO opkg update\n\
+    $SUDO opkg install kmod-tun ca-bundle curl\n\
+  fi\n\
 }}\n\
 \n\
 install_deps() {{\n\
+  step 'Installing build dependencies'\n\
+  case \"$TARGET_OS\" in\n\
+    alpine)\n\
+      $SUDO apk add --no-cache curl git build-base pkgconf openssl-dev linux-headers\n\
+      return\n\
+      ;;\n\
+    freebsd)\n\
+      $SUDO pkg install -y curl git rust pkgconf\n\
+      return\n\
+      ;;\n\
+    openwrt)\n\
+      echo 'vtrunkd does not build on OpenWrt itself; use a prebuilt release or the OpenWrt SDK' >&2\n\
+      exit 1\n\
+      ;;\n\
+  esac\n\
   if command -v apt-get >/dev/null 2>&1; then\n\
     $SUDO apt-get update -y\n\
     $SUDO apt-get install -y curl git build-essential pkg-config libssl-dev\n\
@@ -598,6 +1813,7 @@ install_deps() {{\n\
 }}\n\
 \n\
 install_rust() {{\n\
+  step 'Installing Rust'\n\
   if ! command -v cargo >/dev/null 2>&1; then\n\
     curl https://sh.rustup.rs -sSf | sh -s -- -y\n\
   fi\n\
@@ -607,10 +1823,52 @@ install_rust() {{\n\
   export PATH=\"$HOME/.cargo/bin:$PATH\"\n\
 }}\n\
 \n\
+install_prebuilt() {{\n\
+  step 'Downloading a prebuilt release'\n\
+  case \"$TARGET_OS\" in\n\
+    freebsd) echo 'No prebuilt vtrunkd for FreeBSD'; return 1 ;;\n\
+    alpine|openwrt) LIBC='musl' ;;\n\
+    *) LIBC='gnu' ;;\n\
+  esac\n\
+  case \"$(uname -m)\" in\n\
+    x86_64|amd64) TARGET=\"x86_64-unknown-linux-$LIBC\" ;;\n\
+    aarch64|arm64) TARGET=\"aarch64-unknown-linux-$LIBC\" ;;\n\
+    armv7l) TARGET=\"armv7-unknown-linux-${{LIBC}}eabihf\" ;;\n\
+    *) echo \"No prebuilt vtrunkd for $(uname -m)\"; return 1 ;;\n\
+  esac\n\
+  if ! command -v curl >/dev/null 2>&1 || ! command -v sha256sum >/dev/null 2>&1; then\n\
+    return 1\n\
+  fi\n\
+  ARCHIVE=\"vtrunkd-$TARGET.tar.gz\"\n\
+  TMP_DIR=\"$(mktemp -d)\"\n\
+  if ! curl -fsSL -o \"$TMP_DIR/$ARCHIVE\" \"$RELEASE_URL/$ARCHIVE\" \\\n\
+    || ! curl -fsSL -o \"$TMP_DIR/$ARCHIVE.sha256\" \"$RELEASE_URL/$ARCHIVE.sha256\"; then\n\
+    echo \"No prebuilt vtrunkd for $TARGET; building from source\"\n\
+    rm -rf \"$TMP_DIR\"\n\
+    return 1\n\
+  fi\n\
+  EXPECTED=\"$(cut -d ' ' -f 1 \"$TMP_DIR/$ARCHIVE.sha256\")\"\n\
+  ACTUAL=\"$(sha256sum \"$TMP_DIR/$ARCHIVE\" | cut -d ' ' -f 1)\"\n\
+  if [ -z \"$EXPECTED\" ] || [ \"$EXPECTED\" != \"$ACTUAL\" ]; then\n\
+    echo \"Checksum mismatch for $ARCHIVE; building from source\" >&2\n\
+    rm -rf \"$TMP_DIR\"\n\
+    return 1\n\
+  fi\n\
+  tar -xzf \"$TMP_DIR/$ARCHIVE\" -C \"$TMP_DIR\"\n\
+  $SUDO mkdir -p /usr/local/bin\n\
+  $SUDO install -m 755 \"$TMP_DIR/vtrunkd\" /usr/local/bin/vtrunkd\n\
+  rm -rf \"$TMP_DIR\"\n\
+  echo \"Installed prebuilt vtrunkd for $TARGET\"\n\
+}}\n\
+\n\
 install_vtrunkd() {{\n\
   if command -v vtrunkd >/dev/null 2>&1; then\n\
     return\n\
   fi\n\
+  prepare_target\n\
+  if [ \"$PREBUILT\" = \"1\" ] && install_prebuilt; then\n\
+    return\n\
+  fi\n\
   install_deps\n\
   install_rust\n\
   REPO_DIR=\"$HOME/.vtrunkd-build\"\n\
@@ -620,15 +1878,18 @@ install_vtrunkd() {{\n\
     git -C \"$REPO_DIR\" pull --rebase\n\
   fi\n\
   cd \"$REPO_DIR\"\n\
+  step 'Building vtrunkd (this can take several minutes)'\n\
   cargo build --release\n\
   $SUDO cp target/release/vtrunkd /usr/local/bin/vtrunkd\n\
 }}\n\
 \n\
-install_service() {{\n\
+install_systemd() {{\n\
   if ! command -v systemctl >/dev/null 2>&1; then\n\
     echo 'systemd not detected; skipping service install'\n\
     return\n\
   fi\n\
+  step 'Installing the systemd service'\n\
+  write_credentials\n\
   $SUDO tee /etc/systemd/system/vtrunkd.service >/dev/null <<'UNIT'\n\
 [Unit]\n\
 Description=vtrunkd bonding daemon\n\
@@ -637,7 +1898,8 @@ Wants=network-online.target\n\
 \n\
 [Service]\n\
 Type=simple\n\
-ExecStart=/usr/local/bin/vtrunkd --config /etc/vtrunkd.yaml --foreground\n\
+ExecStart={binary} --config /etc/vtrunkd.yaml --foreground\n\
+{load_credentials}\
 Restart=on-failure\n\
 RestartSec=2\n\
 \n\
@@ -645,9 +1907,97 @@ RestartSec=2\n\
 WantedBy=multi-user.target\n\
 UNIT\n\
   $SUDO systemctl daemon-reload\n\
+  step 'Starting vtrunkd'\n\
   $SUDO systemctl enable --now vtrunkd\n\
 }}\n\
 \n\
+install_openrc() {{\n\
+  step 'Installing the OpenRC service'\n\
+  write_credentials\n\
+  $SUDO tee /etc/init.d/vtrunkd >/dev/null <<'INIT'\n\
+#!/sbin/openrc-run\n\
+description='vtrunkd bonding daemon'\n\
+command='{binary}'\n\
+command_args='--config /etc/vtrunkd.yaml --foreground'\n\
+supervisor=supervise-daemon\n\
+respawn_delay=2\n\
+export CREDENTIALS_DIRECTORY=/etc/vtrunkd/credentials\n\
+\n\
+depend() {{\n\
+  need net\n\
+  after firewall\n\
+}}\n\
+INIT\n\
+  $SUDO chmod 755 /etc/init.d/vtrunkd\n\
+  $SUDO rc-update add vtrunkd default\n\
+  step 'Starting vtrunkd'\n\
+  $SUDO rc-service vtrunkd restart\n\
+}}\n\
+\n\
+install_procd() {{\n\
+  step 'Installing the procd service'\n\
+  write_credentials\n\
+  $SUDO tee /etc/init.d/vtrunkd >/dev/null <<'INIT'\n\
+#!/bin/sh /etc/rc.common\n\
+START=95\n\
+STOP=10\n\
+USE_PROCD=1\n\
+\n\
+start_service() {{\n\
+  procd_open_instance\n\
+  procd_set_param command {binary} --config /etc/vtrunkd.yaml --foreground\n\
+  procd_set_param env CREDENTIALS_DIRECTORY=/etc/vtrunkd/credentials\n\
+  procd_set_param respawn 3600 2 0\n\
+  procd_set_param stdout 1\n\
+  procd_set_param stderr 1\n\
+  procd_close_instance\n\
+}}\n\
+INIT\n\
+  $SUDO chmod 755 /etc/init.d/vtrunkd\n\
+  $SUDO /etc/init.d/vtrunkd enable\n\
+  step 'Starting vtrunkd'\n\
+  $SUDO /etc/init.d/vtrunkd restart\n\
+}}\n\
+\n\
+install_rcd() {{\n\
+  step 'Installing the rc.d service'\n\
+  write_credentials\n\
+  $SUDO mkdir -p /usr/local/etc/rc.d\n\
+  $SUDO tee /usr/local/etc/rc.d/vtrunkd >/dev/null <<'INIT'\n\
+#!/bin/sh\n\
+#\n\
+# PROVIDE: vtrunkd\n\
+# REQUIRE: NETWORKING\n\
+# KEYWORD: shutdown\n\
+\n\
+. /etc/rc.subr\n\
+\n\
+name=vtrunkd\n\
+rcvar=vtrunkd_enable\n\
+pidfile=/var/run/vtrunkd.pid\n\
+command=/usr/sbin/daemon\n\
+command_args=\"-R 2 -P $pidfile -o /var/log/vtrunkd.log {binary} --config /etc/vtrunkd.yaml --foreground\"\n\
+export CREDENTIALS_DIRECTORY=/etc/vtrunkd/credentials\n\
+\n\
+load_rc_config $name\n\
+: ${{vtrunkd_enable:=NO}}\n\
+run_rc_command \"$1\"\n\
+INIT\n\
+  $SUDO chmod 755 /usr/local/etc/rc.d/vtrunkd\n\
+  $SUDO sysrc vtrunkd_enable=YES\n\
+  step 'Starting vtrunkd'\n\
+  $SUDO service vtrunkd restart\n\
+}}\n\
+\n\
+install_service() {{\n\
+  case \"$TARGET_OS\" in\n\
+    alpine) install_openrc ;;\n\
+    openwrt) install_procd ;;\n\
+    freebsd) install_rcd ;;\n\
+    *) install_systemd ;;\n\
+  esac\n\
+}}\n\
+\n\
 if [ \"$INSTALL_VTRUNKD\" = \"1\" ]; then\n\
   install_vtrunkd\n\
 fi\n\
@@ -658,22 +2008,60 @@ fi\n\
 \n\
 if command -v vtrunkd >/dev/null 2>&1; then\n\
   vtrunkd --version || true\n\
-fi\n"
+fi\n\
+step 'Done'\n"
     )
 }
 
 fn main() {
     tauri::Builder::default()
         .manage(RunnerState::default())
+        .manage(remote::RemoteState::default())
+        .manage(logs::LogState::default())
         .invoke_handler(tauri::generate_handler![
             list_local_addrs,
+            interfaces::check_link_uplinks,
+            mtu::check_mtu,
+            preflight::preflight_client,
+            preflight::preflight_server,
             generate_configs,
             write_config,
+            import_config,
+            save_config,
+            export::client_qr,
+            export::export_bundle,
+            export::import_bundle,
             start_vtrunkd,
             stop_vtrunkd,
+            list_tunnels,
+            embedded::engine_built_in,
+            set_notifications,
+            logs::get_logs,
+            logs::clear_logs,
+            logs::persist_logs,
+            logs::export_diagnostics,
+            install_local_service,
+            local_service,
+            local_service_status,
             provision_vps,
+            connectivity::test_connection,
+            speedtest::speed_test,
+            speedtest::suggest_weights,
             get_remote_fingerprint,
-            trust_host
+            trust_host,
+            remote::server_status,
+            remote::follow_server_log,
+            remote::stop_server_log,
+            remote::compare_server_config,
+            remote::update_server,
+            remote::uninstall_server,
+            profiles::list_profiles,
+            profiles::save_profile,
+            profiles::load_profile,
+            profiles::delete_profile,
+            profiles::reconnect_profile,
+            autostart::autostart_enabled,
+            autostart::set_autostart
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
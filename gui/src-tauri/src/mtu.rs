@@ -0,0 +1,211 @@
+//! The largest tunnel MTU every link carries without fragmenting. Each tunnelled packet
+//! leaves a link as an outer IP header, UDP and a WireGuard data message around it;
+//! bonding adds nothing per packet (its control packets travel on their own, and
+//! compressed packets are never larger than the original).
+
+use std::net::{IpAddr, SocketAddr};
+#[cfg(not(target_os = "linux"))]
+use std::process::Command;
+
+use get_if_addrs::get_if_addrs;
+use serde::Serialize;
+
+use crate::{addresses, interfaces, LinkInput};
+
+const IPV4_HEADER: u32 = 20;
+const IPV6_HEADER: u32 = 40;
+const UDP_HEADER: u32 = 8;
+/// Type, receiver index and counter in front, the Poly1305 tag behind.
+const WIREGUARD_OVERHEAD: u32 = 32;
+/// Assumed for an interface whose MTU cannot be read.
+const ETHERNET_MTU: u32 = 1500;
+/// IPv6 inside the tunnel needs at least this much.
+const IPV6_MIN_MTU: u32 = 1280;
+
+#[derive(Serialize)]
+pub struct LinkMtu {
+    name: String,
+    interface: Option<String>,
+    mtu: u32,
+    /// Whether the outer packets are IPv6, which costs 20 bytes more.
+    ipv6: bool,
+    /// The largest tunnel MTU this link carries whole.
+    tunnel_mtu: u32,
+}
+
+#[derive(Serialize)]
+pub struct MtuAdvice {
+    /// The smallest of the links' tunnel MTUs.
+    suggested: u32,
+    links: Vec<LinkMtu>,
+    warnings: Vec<String>,
+}
+
+/// What `mtu` would do on each link, and the MTU that fits them all.
+#[tauri::command]
+pub fn check_mtu(
+    links: Vec<LinkInput>,
+    server_host: String,
+    mtu: u32,
+) -> Result<MtuAdvice, String> {
+    let interfaces = get_if_addrs().map_err(|e| e.to_string())?;
+    let server = addresses::check_host("Server host", server_host.trim())
+        .ok()
+        .flatten();
+    let mut advice = MtuAdvice {
+        suggested: 0,
+        links: Vec::new(),
+        warnings: Vec::new(),
+    };
+    for link in &links {
+        let bind = link.bind.trim().parse::<SocketAddr>().ok();
+        let interface = match bind.map(|bind| bind.ip()) {
+            Some(ip) if !ip.is_unspecified() => interfaces
+                .iter()
+                .find(|iface| iface.ip() == ip)
+                .map(|iface| iface.name.clone()),
+            // Unbound links leave through the default route.
+            _ => default_interface(),
+        };
+        let ipv6 = outer_ipv6(server, bind);
+        let link_mtu = interface
+            .as_deref()
+            .and_then(interface_mtu)
+            .unwrap_or(ETHERNET_MTU);
+        let tunnel_mtu = tunnel_mtu(link_mtu, ipv6);
+        if mtu > tunnel_mtu {
+            advice.warnings.push(format!(
+                "MTU {} fragments on link {} ({}MTU {}, {}): keep it at or below {}",
+                mtu,
+                link.name,
+                interface
+                    .as_deref()
+                    .map(|name| format!("{}, ", name))
+                    .unwrap_or_default(),
+                link_mtu,
+                if ipv6 { "IPv6" } else { "IPv4" },
+                tunnel_mtu
+            ));
+        }
+        advice.links.push(LinkMtu {
+            name: link.name.clone(),
+            interface,
+            mtu: link_mtu,
+            ipv6,
+            tunnel_mtu,
+        });
+    }
+    advice.suggested = advice
+        .links
+        .iter()
+        .map(|link| link.tunnel_mtu)
+        .min()
+        .unwrap_or_else(|| tunnel_mtu(ETHERNET_MTU, true));
+    if advice.suggested < IPV6_MIN_MTU {
+        advice.warnings.push(format!(
+            "The links leave room for a tunnel MTU of {}, too small for IPv6 inside the \
+             tunnel (at least {})",
+            advice.suggested, IPV6_MIN_MTU
+        ));
+    }
+    Ok(advice)
+}
+
+/// Whether a link's outer packets are IPv6, from the server's address or else the link's
+/// bind address. A server given by name may resolve to either family; unbound, the
+/// costlier one is assumed.
+fn outer_ipv6(server: Option<IpAddr>, bind: Option<SocketAddr>) -> bool {
+    match (server, bind) {
+        (Some(server), _) => server.is_ipv6(),
+        (None, Some(bind)) if !bind.ip().is_unspecified() => bind.is_ipv6(),
+        (None, _) => true,
+    }
+}
+
+/// The tunnel MTU a link of `link_mtu` carries whole.
+fn tunnel_mtu(link_mtu: u32, ipv6: bool) -> u32 {
+    let outer = if ipv6 { IPV6_HEADER } else { IPV4_HEADER };
+    link_mtu.saturating_sub(outer + UDP_HEADER + WIREGUARD_OVERHEAD)
+}
+
+fn default_interface() -> Option<String> {
+    let mut names: Vec<String> = interfaces::default_gateways().into_keys().collect();
+    names.sort();
+    // With several default routes, the smallest MTU is the safe guess.
+    names
+        .into_iter()
+        .min_by_key(|name| interface_mtu(name).unwrap_or(ETHERNET_MTU))
+}
+
+#[cfg(target_os = "linux")]
+fn interface_mtu(name: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// From the `mtu 1500` in the first line `ifconfig` prints.
+#[cfg(not(target_os = "linux"))]
+fn interface_mtu(name: &str) -> Option<u32> {
+    let output = Command::new("ifconfig").arg(name).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let tokens: Vec<&str> = stdout.lines().next()?.split_whitespace().collect();
+    let index = tokens.iter().position(|token| *token == "mtu")?;
+    tokens.get(index + 1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunnel_mtu_takes_off_the_outer_headers() {
+        assert_eq!(tunnel_mtu(1500, false), 1440);
+        assert_eq!(tunnel_mtu(1500, true), 1420);
+        // PPPoE
+        assert_eq!(tunnel_mtu(1492, false), 1432);
+        assert_eq!(tunnel_mtu(60, true), 0);
+    }
+
+    #[test]
+    fn check_mtu_follows_the_outer_family() {
+        let links = || {
+            vec![LinkInput {
+                name: "lo".to_string(),
+                bind: "127.0.0.1:0".to_string(),
+                weight: 1,
+            }]
+        };
+        let advice = check_mtu(links(), "127.0.0.1".to_string(), 1420).unwrap();
+        assert!(!advice.links[0].ipv6);
+        assert_eq!(
+            advice.links[0].tunnel_mtu,
+            tunnel_mtu(advice.links[0].mtu, false)
+        );
+        assert_eq!(advice.suggested, advice.links[0].tunnel_mtu);
+        assert!(advice.warnings.is_empty(), "{:?}", advice.warnings);
+
+        // Past what the link carries: warned, with the limit.
+        let too_big = advice.suggested + 1;
+        let advice = check_mtu(links(), "127.0.0.1".to_string(), too_big).unwrap();
+        assert_eq!(advice.warnings.len(), 1);
+        assert!(advice.warnings[0].contains(&format!("below {}", too_big - 1)));
+    }
+
+    #[test]
+    fn outer_family_falls_back_to_the_bind_address() {
+        let v4_bind = Some("192.0.2.10:0".parse().unwrap());
+        let v6_bind = Some("[2001:db8::10]:0".parse().unwrap());
+        let unbound = Some("0.0.0.0:0".parse().unwrap());
+        // The server's own address decides.
+        assert!(outer_ipv6(Some("2001:db8::1".parse().unwrap()), v4_bind));
+        assert!(!outer_ipv6(Some("198.51.100.1".parse().unwrap()), v6_bind));
+        // A server given by name takes the bound address's family; unbound, it might be IPv6.
+        assert!(!outer_ipv6(None, v4_bind));
+        assert!(outer_ipv6(None, v6_bind));
+        assert!(outer_ipv6(None, unbound));
+        assert!(outer_ipv6(None, None));
+    }
+}
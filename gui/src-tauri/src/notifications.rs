@@ -0,0 +1,74 @@
+//! Desktop notifications for the tunnel events worth noticing with the window in the
+//! background: a link going down, a failover, and the last link going down.
+
+use std::collections::HashSet;
+
+use tauri::api::notification::Notification;
+use tauri::AppHandle;
+
+/// Follows link states through the event stream, to tell when no link is left.
+pub struct LinkWatch {
+    links: usize,
+    down: HashSet<String>,
+}
+
+impl LinkWatch {
+    /// `links` is how many links the config has.
+    pub fn new(links: usize) -> Self {
+        LinkWatch {
+            links,
+            down: HashSet::new(),
+        }
+    }
+
+    /// The title and body of the notification `event` calls for, if any.
+    pub fn notification(&mut self, event: &serde_json::Value) -> Option<(String, String)> {
+        let field = |key: &str| event.get(key).and_then(|value| value.as_str()).unwrap_or("?");
+        match event.get("event")?.as_str()? {
+            "link_down" => {
+                let link = field("link");
+                if !self.down.insert(link.to_string()) {
+                    return None;
+                }
+                if self.all_down() {
+                    Some((
+                        "All links down".to_string(),
+                        format!(
+                            "The tunnel has no working link; {} was the last ({})",
+                            link,
+                            field("reason")
+                        ),
+                    ))
+                } else {
+                    Some((format!("Link {} down", link), field("reason").to_string()))
+                }
+            }
+            "link_up" => {
+                let was_all_down = self.all_down();
+                self.down.remove(field("link"));
+                was_all_down.then(|| {
+                    (
+                        "Tunnel back up".to_string(),
+                        format!("Link {} is up again", field("link")),
+                    )
+                })
+            }
+            "failover" => Some((
+                "Failover".to_string(),
+                format!("Traffic moved from {} to {}", field("from"), field("to")),
+            )),
+            _ => None,
+        }
+    }
+
+    fn all_down(&self) -> bool {
+        self.links > 0 && self.down.len() >= self.links
+    }
+}
+
+pub fn show(app: &AppHandle, title: &str, body: &str) {
+    let _ = Notification::new(&app.config().tauri.bundle.identifier)
+        .title(title)
+        .body(body)
+        .show();
+}
@@ -0,0 +1,217 @@
+//! Checks run before starting the tunnel or provisioning, so a missing address, a port
+//! another program holds or an unreachable server shows up as a clear message instead of
+//! a tunnel that never comes up.
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::AppHandle;
+use vtrunkd_core::config as core_config;
+
+use crate::{app_config_dir, ssh, SshConfig};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+pub struct Check {
+    name: String,
+    ok: bool,
+    /// Whether starting or provisioning should stop here rather than only warn.
+    blocking: bool,
+    detail: String,
+}
+
+impl Check {
+    fn pass(name: String, detail: String) -> Self {
+        Check {
+            name,
+            ok: true,
+            blocking: false,
+            detail,
+        }
+    }
+
+    fn fail(name: String, blocking: bool, detail: String) -> Self {
+        Check {
+            name,
+            ok: false,
+            blocking,
+            detail,
+        }
+    }
+}
+
+/// For each client link: its bind address is on an interface, its bind port is free, and
+/// a UDP probe to its endpoint is not refused.
+#[tauri::command]
+pub async fn preflight_client(client_yaml: String) -> Result<Vec<Check>, String> {
+    let config = core_config::parse_config(&client_yaml)
+        .map_err(|e| format!("Invalid client config: {}", e))?;
+    tauri::async_runtime::spawn_blocking(move || check_client(&config))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Whether the server's link ports are free on the VPS, or held by vtrunkd itself.
+#[tauri::command]
+pub async fn preflight_server(
+    app: AppHandle,
+    ssh: SshConfig,
+    server_yaml: String,
+) -> Result<Vec<Check>, String> {
+    let config = core_config::parse_config(&server_yaml)
+        .map_err(|e| format!("Invalid server config: {}", e))?;
+    let known_hosts = app_config_dir(&app)?.join("known_hosts");
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts)?;
+        let sudo = if ssh.login_user() == "root" { "" } else { "sudo " };
+        // With -p, ss names the owning process; that needs root for other users' sockets.
+        let (listening, _) = ssh::run(&session, &format!("{}ss -Hlunp", sudo), None)?;
+        Ok(check_server_ports(&config, &listening))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn check_client(config: &core_config::Config) -> Result<Vec<Check>, String> {
+    let interfaces = get_if_addrs::get_if_addrs().map_err(|e| e.to_string())?;
+    let mut checks = Vec::new();
+    let mut probes = Vec::new();
+    for (index, link) in config.wireguard.links.iter().enumerate() {
+        let name = format!("link {}", core_config::link_name(link, index));
+        let bind: Option<SocketAddr> = link.bind.as_deref().and_then(|bind| bind.parse().ok());
+        if let Some(bind) = bind {
+            if !bind.ip().is_unspecified()
+                && !interfaces.iter().any(|iface| iface.ip() == bind.ip())
+            {
+                checks.push(Check::fail(
+                    name,
+                    true,
+                    format!(
+                        "{} is not assigned to any interface; is that uplink connected?",
+                        bind.ip()
+                    ),
+                ));
+                continue;
+            }
+            if bind.port() != 0 {
+                if let Err(e) = UdpSocket::bind(bind) {
+                    let detail = if e.kind() == ErrorKind::AddrInUse {
+                        format!("UDP {} is already in use by another process", bind)
+                    } else {
+                        format!("cannot bind {}: {}", bind, e)
+                    };
+                    checks.push(Check::fail(name, true, detail));
+                    continue;
+                }
+            }
+        }
+        if let Some(endpoint) = link.endpoint.clone() {
+            probes.push((name, bind.map(|bind| bind.ip()), endpoint));
+        } else {
+            checks.push(Check::pass(name, "bind address ok".to_string()));
+        }
+    }
+    // Each probe waits for a reply, so they run at once.
+    let probed: Vec<Check> = std::thread::scope(|scope| {
+        let handles: Vec<_> = probes
+            .iter()
+            .map(|(name, source, endpoint)| {
+                scope.spawn(move || {
+                    let result = probe(*source, endpoint);
+                    (name.clone(), endpoint.clone(), result)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok())
+            .map(|(name, endpoint, result)| match result {
+                Ok(detail) => Check::pass(name, format!("{}: {}", endpoint, detail)),
+                Err(detail) => Check::fail(name, false, format!("{}: {}", endpoint, detail)),
+            })
+            .collect()
+    });
+    checks.extend(probed);
+    Ok(checks)
+}
+
+/// Sends one datagram from `source` and waits briefly. WireGuard never answers junk, so
+/// silence is the good outcome; a refusal means the host sent ICMP port unreachable.
+fn probe(source: Option<IpAddr>, endpoint: &str) -> Result<String, String> {
+    let target = endpoint
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve: {}", e))?
+        .find(|addr| source.is_none_or(|source| source.is_ipv4() == addr.is_ipv4()))
+        .ok_or_else(|| "no address of a matching family".to_string())?;
+    let local: SocketAddr = match source {
+        Some(source) => (source, 0).into(),
+        None if target.is_ipv4() => ([0, 0, 0, 0], 0).into(),
+        None => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).map_err(|e| format!("cannot bind {}: {}", local, e))?;
+    socket
+        .connect(target)
+        .map_err(|e| format!("no route from this link: {}", e))?;
+    socket
+        .set_read_timeout(Some(PROBE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    socket
+        .send(&[0])
+        .map_err(|e| format!("no route from this link: {}", e))?;
+    let mut reply = [0u8; 64];
+    match socket.recv(&mut reply) {
+        Ok(_) => Ok("reachable".to_string()),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            Ok("no refusal (the port is open, or filtered)".to_string())
+        }
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Err(
+            "refused: nothing listens on this port; is vtrunkd running on the server?".to_string(),
+        ),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// `listening` is `ss -Hlunp` output from the VPS.
+fn check_server_ports(config: &core_config::Config, listening: &str) -> Vec<Check> {
+    config
+        .wireguard
+        .links
+        .iter()
+        .enumerate()
+        .filter_map(|(index, link)| {
+            let bind: SocketAddr = link.bind.as_deref()?.parse().ok()?;
+            if bind.port() == 0 {
+                return None;
+            }
+            let name = format!("server port {}", bind.port());
+            let owner = listening.lines().find(|line| {
+                line.split_whitespace()
+                    .nth(3)
+                    .and_then(|local| local.rsplit_once(':'))
+                    .is_some_and(|(_, port)| port == bind.port().to_string())
+            });
+            Some(match owner {
+                None => Check::pass(name, "free".to_string()),
+                Some(line) if line.contains("\"vtrunkd\"") => {
+                    Check::pass(name, "in use by vtrunkd, already running there".to_string())
+                }
+                Some(line) => Check::fail(
+                    name,
+                    true,
+                    format!(
+                        "link {} cannot bind it; it is in use by {}",
+                        core_config::link_name(link, index),
+                        // users:(("dnsmasq",pid=612,fd=5))
+                        line.split("((\"")
+                            .nth(1)
+                            .and_then(|rest| rest.split('"').next())
+                            .unwrap_or("another process")
+                    ),
+                ),
+            })
+        })
+        .collect()
+}
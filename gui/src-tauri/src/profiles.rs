@@ -0,0 +1,142 @@
+//! Named connection profiles saved under the app config dir. Everything but the secrets
+//! goes to `profiles.json`; the keys and the SSH password go to the OS keyring (Keychain,
+//! Secret Service or Credential Manager), one entry per profile and secret.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::keychain::{load_secret, store_secret};
+use crate::{app_config_dir, ConfigParams, SshConfig};
+
+const PROFILES_FILE: &str = "profiles.json";
+/// The name of the profile loaded last, for reconnecting it when the app starts.
+const LAST_PROFILE_FILE: &str = "last_profile";
+
+#[derive(Serialize, Deserialize)]
+pub struct Profile {
+    name: String,
+    params: ConfigParams,
+    ssh: SshConfig,
+    binary_path: String,
+    /// Desktop notifications for link events while this profile's tunnel runs.
+    #[serde(default = "notifications_default")]
+    notifications: bool,
+    /// Start this profile's tunnel when the app starts, if it was the last one loaded.
+    #[serde(default)]
+    reconnect: bool,
+}
+
+fn notifications_default() -> bool {
+    true
+}
+
+#[tauri::command]
+pub fn list_profiles(app: AppHandle) -> Result<Vec<String>, String> {
+    let profiles = read_profiles(&profiles_path(&app)?)?;
+    Ok(profiles.into_iter().map(|profile| profile.name).collect())
+}
+
+#[tauri::command]
+pub fn save_profile(app: AppHandle, mut profile: Profile) -> Result<(), String> {
+    profile.name = profile.name.trim().to_string();
+    if profile.name.is_empty() {
+        return Err("Profile name is required".to_string());
+    }
+    let params = &mut profile.params;
+    for (key, value) in [
+        ("client_private_key", params.client_private_key.take()),
+        ("server_private_key", params.server_private_key.take()),
+        ("preshared_key", params.preshared_key_value.take()),
+        ("ssh_password", profile.ssh.password.take()),
+    ] {
+        store_secret(&profile.name, key, value.as_deref())?;
+    }
+
+    let path = profiles_path(&app)?;
+    let mut profiles = read_profiles(&path)?;
+    profiles.retain(|saved| saved.name != profile.name);
+    profiles.push(profile);
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    write_profiles(&path, &profiles)
+}
+
+#[tauri::command]
+pub fn load_profile(app: AppHandle, name: String) -> Result<Profile, String> {
+    let mut profile = read_profiles(&profiles_path(&app)?)?
+        .into_iter()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| format!("No profile named {}", name))?;
+    let params = &mut profile.params;
+    params.client_private_key = load_secret(&name, "client_private_key")?;
+    params.server_private_key = load_secret(&name, "server_private_key")?;
+    params.preshared_key_value = load_secret(&name, "preshared_key")?;
+    profile.ssh.password = load_secret(&name, "ssh_password")?;
+    // Only reconnecting on the next start depends on it, so a failure is not the load's.
+    let _ = fs::write(app_config_dir(&app)?.join(LAST_PROFILE_FILE), &name);
+    Ok(profile)
+}
+
+/// The last loaded profile, when it asks to be connected as the app starts.
+#[tauri::command]
+pub fn reconnect_profile(app: AppHandle) -> Result<Option<String>, String> {
+    let Some(name) = last_profile(&app)? else {
+        return Ok(None);
+    };
+    Ok(read_profiles(&profiles_path(&app)?)?
+        .into_iter()
+        .find(|profile| profile.name == name && profile.reconnect)
+        .map(|profile| profile.name))
+}
+
+#[tauri::command]
+pub fn delete_profile(app: AppHandle, name: String) -> Result<(), String> {
+    let path = profiles_path(&app)?;
+    let mut profiles = read_profiles(&path)?;
+    profiles.retain(|profile| profile.name != name);
+    write_profiles(&path, &profiles)?;
+    if last_profile(&app)?.as_deref() == Some(name.as_str()) {
+        let _ = fs::remove_file(app_config_dir(&app)?.join(LAST_PROFILE_FILE));
+    }
+    for key in [
+        "client_private_key",
+        "server_private_key",
+        "preshared_key",
+        "ssh_password",
+    ] {
+        store_secret(&name, key, None)?;
+    }
+    Ok(())
+}
+
+fn profiles_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_dir(app)?.join(PROFILES_FILE))
+}
+
+fn last_profile(app: &AppHandle) -> Result<Option<String>, String> {
+    let path = app_config_dir(app)?.join(LAST_PROFILE_FILE);
+    match fs::read_to_string(&path) {
+        Ok(name) => Ok(Some(name.trim().to_string()).filter(|name| !name.is_empty())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn read_profiles(path: &Path) -> Result<Vec<Profile>, String> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn write_profiles(path: &Path, profiles: &[Profile]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(profiles).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
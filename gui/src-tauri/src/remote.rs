@@ -0,0 +1,327 @@
+//! Looking after a provisioned VPS over SSH: the service's status and journal, streamed
+//! live on request, config updates shown as a diff before they are applied, and removal.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use base64::{engine::general_purpose, Engine as _};
+use tauri::{AppHandle, Manager, State};
+use vtrunkd_core::config as core_config;
+
+use crate::{
+    app_config_dir, split_credentials, ssh, SshConfig, INSTALLED_BINARY, PRESHARED_KEY_CREDENTIAL,
+};
+
+/// Journal lines fetched with the status.
+const JOURNAL_LINES: u32 = 100;
+
+#[derive(Default)]
+pub struct RemoteState {
+    /// Bumped to start following the server's journal anew or to stop following it.
+    follow: AtomicU64,
+}
+
+/// `systemctl status vtrunkd` followed by the unit's recent journal.
+#[tauri::command]
+pub async fn server_status(app: AppHandle, ssh: SshConfig) -> Result<String, String> {
+    let known_hosts = known_hosts_path(&app)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts)?;
+        let sudo = sudo(&ssh);
+        // `systemctl status` exits non-zero for a stopped unit, which is worth showing too.
+        let command = format!(
+            "{sudo}systemctl status vtrunkd --no-pager --lines=0; \
+             echo; \
+             {sudo}journalctl -u vtrunkd -n {JOURNAL_LINES} --no-pager"
+        );
+        let (output, _) = ssh::run(&session, &command, None)?;
+        Ok(output.trim_end().to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Streams new journal lines of the unit as `server-log` events until
+/// [`stop_server_log`], or until following starts again. `server-log-end` reports the
+/// stream ending on its own, with the error if there was one.
+#[tauri::command]
+pub fn follow_server_log(
+    app: AppHandle,
+    state: State<RemoteState>,
+    ssh: SshConfig,
+) -> Result<(), String> {
+    let known_hosts = known_hosts_path(&app)?;
+    let run = state.follow.fetch_add(1, Ordering::SeqCst) + 1;
+    std::thread::spawn(move || {
+        let remote = app.state::<RemoteState>();
+        let result = ssh::connect(&ssh, &known_hosts).and_then(|session| {
+            let command = format!("{}journalctl -u vtrunkd -f -n 0 --no-pager", sudo(&ssh));
+            ssh::follow(
+                &session,
+                &command,
+                || remote.follow.load(Ordering::SeqCst) != run,
+                |line| {
+                    let _ = app.emit_all("server-log", line.to_string());
+                },
+            )
+        });
+        if remote.follow.load(Ordering::SeqCst) == run {
+            let _ = app.emit_all("server-log-end", result.err());
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn stop_server_log(state: State<RemoteState>) {
+    state.follow.fetch_add(1, Ordering::SeqCst);
+}
+
+/// A unified diff of `/etc/vtrunkd.yaml` on the VPS against what [`update_server`] would
+/// write there, with key values hidden.
+#[tauri::command]
+pub async fn compare_server_config(
+    app: AppHandle,
+    ssh: SshConfig,
+    server_yaml: String,
+) -> Result<String, String> {
+    let script = build_update_script(&server_yaml, false)?;
+    let known_hosts = known_hosts_path(&app)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts)?;
+        let (output, status) = ssh::run(&session, "sh -s", Some(&script))?;
+        if status != 0 {
+            return Err(output.trim().to_string());
+        }
+        let diff = hide_keys(output.trim_end());
+        if diff.is_empty() {
+            Ok("No changes; the server already runs this config.".to_string())
+        } else {
+            Ok(diff)
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Replaces `/etc/vtrunkd.yaml` (and the key credentials, for a config provisioned with
+/// them) and restarts the service, leaving the binary and unit as they are.
+#[tauri::command]
+pub async fn update_server(
+    app: AppHandle,
+    ssh: SshConfig,
+    server_yaml: String,
+) -> Result<String, String> {
+    let script = build_update_script(&server_yaml, true)?;
+    let known_hosts = known_hosts_path(&app)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts)?;
+        let (output, status) = ssh::run(&session, "sh -s", Some(&script))?;
+        if status == 0 {
+            Ok(output.trim().to_string())
+        } else {
+            Err(output.trim().to_string())
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Stops and removes everything provisioning installed: the unit, the config and its
+/// credentials, the binary and the build checkout. Rust, if provisioning installed it, stays.
+#[tauri::command]
+pub async fn uninstall_server(app: AppHandle, ssh: SshConfig) -> Result<String, String> {
+    let known_hosts = known_hosts_path(&app)?;
+    let script = build_uninstall_script(INSTALLED_BINARY);
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = ssh::connect(&ssh, &known_hosts)?;
+        let (output, status) = ssh::run(&session, "sh -s", Some(&script))?;
+        if status == 0 {
+            Ok(output.trim().to_string())
+        } else {
+            Err(output.trim().to_string())
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn known_hosts_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_config_dir(app)?.join("known_hosts"))
+}
+
+/// The config goes where provisioning put it: with the keys as systemd credentials when
+/// the server already keeps them that way, inline otherwise.
+fn build_update_script(server_yaml: &str, apply: bool) -> Result<String, String> {
+    if server_yaml.trim().is_empty() {
+        return Err("Server config is empty".to_string());
+    }
+    core_config::parse_config(server_yaml).map_err(|e| format!("Invalid server config: {}", e))?;
+    let config_b64 = general_purpose::STANDARD.encode(server_yaml.as_bytes());
+    let credentials = split_credentials(server_yaml)?;
+    let credential_config_b64 = credentials.as_ref().map_or("", |c| c.config_b64.as_str());
+    let private_key_b64 = credentials.as_ref().map_or("", |c| c.private_key_b64.as_str());
+    let preshared_key_b64 = credentials
+        .as_ref()
+        .and_then(|c| c.preshared_key_b64.as_deref())
+        .unwrap_or("");
+    let apply_flag = if apply { "1" } else { "0" };
+
+    Ok(format!(
+        "set -eu\n\
+if (set -o pipefail) 2>/dev/null; then\n\
+  set -o pipefail\n\
+fi\n\
+CONFIG_B64='{config_b64}'\n\
+CREDENTIAL_CONFIG_B64='{credential_config_b64}'\n\
+PRIVATE_KEY_B64='{private_key_b64}'\n\
+PRESHARED_KEY_B64='{preshared_key_b64}'\n\
+APPLY='{apply_flag}'\n\
+SUDO=\"\"\n\
+if [ \"$(id -u)\" != \"0\" ]; then\n\
+  SUDO=\"sudo\"\n\
+fi\n\
+CREDENTIALS=/etc/vtrunkd/credentials\n\
+decode() {{\n\
+  if command -v base64 >/dev/null 2>&1; then\n\
+    base64 -d\n\
+  elif command -v b64decode >/dev/null 2>&1; then\n\
+    b64decode -r\n\
+  else\n\
+    openssl base64 -d -A\n\
+  fi\n\
+}}\n\
+NEW=\"$(mktemp)\"\n\
+trap 'rm -f \"$NEW\"' EXIT\n\
+USE_CREDENTIALS=0\n\
+if [ -n \"$CREDENTIAL_CONFIG_B64\" ] && $SUDO test -f \"$CREDENTIALS/private.key\"; then\n\
+  USE_CREDENTIALS=1\n\
+  printf '%s' \"$CREDENTIAL_CONFIG_B64\" | decode > \"$NEW\"\n\
+else\n\
+  printf '%s' \"$CONFIG_B64\" | decode > \"$NEW\"\n\
+fi\n\
+\n\
+key_changes() {{\n\
+  if ! printf '%s' \"$1\" | decode | $SUDO cmp -s - \"$2\"; then\n\
+    echo \"$3\"\n\
+  fi\n\
+}}\n\
+\n\
+write_key() {{\n\
+  printf '%s' \"$1\" | decode | $SUDO tee \"$2\" >/dev/null\n\
+  $SUDO chmod 600 \"$2\"\n\
+}}\n\
+\n\
+if [ \"$APPLY\" = \"0\" ]; then\n\
+  if $SUDO test -f /etc/vtrunkd.yaml; then\n\
+    $SUDO cat /etc/vtrunkd.yaml | diff -u -L /etc/vtrunkd.yaml -L 'new config' - \"$NEW\" || true\n\
+  else\n\
+    echo 'The server has no /etc/vtrunkd.yaml yet'\n\
+  fi\n\
+  if [ \"$USE_CREDENTIALS\" = \"1\" ]; then\n\
+    key_changes \"$PRIVATE_KEY_B64\" \"$CREDENTIALS/private.key\" 'The private key changes'\n\
+    if [ -n \"$PRESHARED_KEY_B64\" ]; then\n\
+      key_changes \"$PRESHARED_KEY_B64\" \"$CREDENTIALS/preshared.key\" 'The preshared key changes'\n\
+    fi\n\
+  fi\n\
+  exit 0\n\
+fi\n\
+\n\
+if [ \"$USE_CREDENTIALS\" = \"1\" ] && [ -n \"$PRESHARED_KEY_B64\" ] \\\n\
+  && ! $SUDO grep -qs '{PRESHARED_KEY_CREDENTIAL}' /etc/systemd/system/vtrunkd.service \\\n\
+  && ! $SUDO test -L \"$CREDENTIALS/{PRESHARED_KEY_CREDENTIAL}\"; then\n\
+  echo 'The service does not load a preshared key yet; provision the server again to add one' >&2\n\
+  exit 1\n\
+fi\n\
+$SUDO install -m 600 \"$NEW\" /etc/vtrunkd.yaml\n\
+if [ \"$USE_CREDENTIALS\" = \"1\" ]; then\n\
+  write_key \"$PRIVATE_KEY_B64\" \"$CREDENTIALS/private.key\"\n\
+  if [ -n \"$PRESHARED_KEY_B64\" ]; then\n\
+    write_key \"$PRESHARED_KEY_B64\" \"$CREDENTIALS/preshared.key\"\n\
+  fi\n\
+fi\n\
+echo 'Wrote /etc/vtrunkd.yaml'\n\
+if command -v systemctl >/dev/null 2>&1 && systemctl cat vtrunkd >/dev/null 2>&1; then\n\
+  $SUDO systemctl restart vtrunkd\n\
+  echo \"Restarted vtrunkd: $(systemctl is-active vtrunkd || true)\"\n\
+elif [ -x /etc/init.d/vtrunkd ]; then\n\
+  $SUDO /etc/init.d/vtrunkd restart && echo 'Restarted vtrunkd'\n\
+elif [ -x /usr/local/etc/rc.d/vtrunkd ]; then\n\
+  $SUDO service vtrunkd restart && echo 'Restarted vtrunkd'\n\
+else\n\
+  echo 'No vtrunkd service; restart vtrunkd on the server to use the new config'\n\
+fi\n"
+    ))
+}
+
+fn build_uninstall_script(binary: &str) -> String {
+    format!(
+        "set -u\n\
+if (set -o pipefail) 2>/dev/null; then\n\
+  set -o pipefail\n\
+fi\n\
+SUDO=\"\"\n\
+if [ \"$(id -u)\" != \"0\" ]; then\n\
+  SUDO=\"sudo\"\n\
+fi\n\
+\n\
+remove() {{\n\
+  if $SUDO test -e \"$1\"; then\n\
+    $SUDO rm -rf \"$1\" && echo \"Removed $1\"\n\
+  fi\n\
+}}\n\
+\n\
+if command -v systemctl >/dev/null 2>&1 && systemctl cat vtrunkd >/dev/null 2>&1; then\n\
+  $SUDO systemctl disable --now vtrunkd && echo 'Stopped and disabled vtrunkd.service'\n\
+  remove /etc/systemd/system/vtrunkd.service\n\
+  $SUDO systemctl daemon-reload\n\
+  $SUDO systemctl reset-failed vtrunkd 2>/dev/null || true\n\
+elif [ -x /etc/init.d/vtrunkd ]; then\n\
+  $SUDO /etc/init.d/vtrunkd stop\n\
+  if command -v rc-update >/dev/null 2>&1; then\n\
+    $SUDO rc-update del vtrunkd default\n\
+  else\n\
+    $SUDO /etc/init.d/vtrunkd disable\n\
+  fi\n\
+  echo 'Stopped and disabled the vtrunkd service'\n\
+  remove /etc/init.d/vtrunkd\n\
+elif [ -x /usr/local/etc/rc.d/vtrunkd ]; then\n\
+  $SUDO service vtrunkd onestop\n\
+  $SUDO sysrc -x vtrunkd_enable\n\
+  echo 'Stopped and disabled the vtrunkd service'\n\
+  remove /usr/local/etc/rc.d/vtrunkd\n\
+elif pgrep -x vtrunkd >/dev/null 2>&1; then\n\
+  echo 'vtrunkd is running outside a service; stop it yourself'\n\
+fi\n\
+remove /etc/vtrunkd.yaml\n\
+remove /etc/vtrunkd\n\
+remove '{binary}'\n\
+remove \"$HOME/.vtrunkd-build\"\n\
+echo 'vtrunkd is uninstalled'\n"
+    )
+}
+
+/// Blanks the values of inline keys in a diff of configs.
+fn hide_keys(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let body = line.trim_start_matches(['-', '+', ' ']).trim_start();
+            if body.starts_with("private_key:") || body.starts_with("preshared_key:") {
+                let end = line.find(':').map_or(line.len(), |colon| colon + 1);
+                format!("{} <hidden>", &line[..end])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The prefix for commands that need root, as provisioning requires passwordless sudo.
+fn sudo(ssh: &SshConfig) -> &'static str {
+    if ssh.login_user() == "root" {
+        ""
+    } else {
+        "sudo "
+    }
+}
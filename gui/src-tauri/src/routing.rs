@@ -0,0 +1,188 @@
+//! "Route all traffic through the VPN". vtrunkd installs no routes itself, so the client
+//! config gets `post_up` hooks sending the default route into the tunnel, and the server
+//! config hooks that forward and masquerade the client's traffic.
+//!
+//! The links' own datagrams must not follow the new default route back into the tunnel.
+//! On Linux a rule sends packets from each link's bind address to the main table first;
+//! everything else sees the main table without its default route, then the tunnel's
+//! table. On macOS the two halves of the address space go to the tunnel and a socket
+//! bound to an address keeps leaving through that address's interface.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use get_if_addrs::{get_if_addrs, IfAddr};
+use vtrunkd_core::config as core_config;
+
+use crate::{addresses, ConfigParams, HooksConfig};
+
+/// Routing table holding the client's default route through the tunnel (Linux).
+const TUNNEL_TABLE: u32 = 51820;
+/// Rule priorities: link sources, then the main table without defaults, then the tunnel.
+const RULE_PREF: u32 = 10500;
+const MASQUERADE: &str = "-j MASQUERADE";
+
+pub struct FullTunnel {
+    pub client: HooksConfig,
+    pub server: HooksConfig,
+    /// What keeps going around the tunnel, in sentences for the window.
+    pub bypass: Vec<String>,
+}
+
+pub fn full_tunnel(params: &ConfigParams) -> Result<FullTunnel, String> {
+    let client_address: Ipv4Addr = addresses::tunnel_addresses(
+        "Client tunnel address",
+        &params.client_address,
+        &params.netmask,
+    )?
+    .address
+    .and_then(|address| address.parse().ok())
+    .ok_or_else(|| "Full tunnel needs an IPv4 client tunnel address".to_string())?;
+    let mut sources: Vec<Ipv4Addr> = Vec::new();
+    for link in &params.links {
+        let bind: SocketAddr = link
+            .bind
+            .trim()
+            .parse()
+            .map_err(|_| format!("Link {} bind address is not ip:port", link.name))?;
+        match bind.ip() {
+            // The server is reached over IPv6 on this link; the IPv4 default is not in the way.
+            IpAddr::V6(ip) if !ip.is_unspecified() => {}
+            IpAddr::V4(ip) if !ip.is_unspecified() => {
+                if !sources.contains(&ip) {
+                    sources.push(ip);
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Full tunnel needs each link bound to its own address; link {} binds to \
+                     any address, so its packets would loop into the tunnel",
+                    link.name
+                ))
+            }
+        }
+    }
+
+    let client = client_hooks(&sources)?;
+    let rule = format!("POSTROUTING -s {} ! -o %i {}", client_address, MASQUERADE);
+    let cleanup = format!("iptables -t nat -D {} 2>/dev/null || true", rule);
+    let server = HooksConfig {
+        pre_up: vec![cleanup.clone()],
+        post_up: vec![
+            "sysctl -qw net.ipv4.ip_forward=1".to_string(),
+            format!("iptables -t nat -A {}", rule),
+        ],
+        post_down: vec![cleanup],
+    };
+
+    let mut bypass = vec![format!(
+        "The links' own packets to {}, from {}",
+        params.server_host.trim(),
+        params
+            .links
+            .iter()
+            .map(|link| link.bind.trim())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )];
+    for (name, network) in local_networks(&params.client_interface) {
+        bypass.push(format!("Local network {} on {}", network, name));
+    }
+    bypass.push(
+        "Any other route more specific than the default, such as another VPN's".to_string(),
+    );
+    bypass.push("IPv6 traffic; the tunnel carries IPv4 here".to_string());
+    Ok(FullTunnel {
+        client,
+        server,
+        bypass,
+    })
+}
+
+/// Whether a config has the hooks `full_tunnel` writes, on either side.
+pub fn is_full_tunnel(config: &core_config::Config) -> bool {
+    let markers = [
+        format!("lookup {}", TUNNEL_TABLE),
+        "0.0.0.0/1".to_string(),
+        MASQUERADE.to_string(),
+    ];
+    config
+        .hooks
+        .as_ref()
+        .and_then(|hooks| hooks.post_up.as_ref())
+        .is_some_and(|commands| {
+            commands
+                .iter()
+                .any(|command| markers.iter().any(|marker| command.contains(marker)))
+        })
+}
+
+#[cfg(target_os = "linux")]
+fn client_hooks(sources: &[Ipv4Addr]) -> Result<HooksConfig, String> {
+    let source_rule = |source: &Ipv4Addr| format!("from {} lookup main pref {}", source, RULE_PREF);
+    let main_rule = format!("lookup main suppress_prefixlength 0 pref {}", RULE_PREF + 1);
+    let tunnel_rule = format!("lookup {} pref {}", TUNNEL_TABLE, RULE_PREF + 2);
+
+    // Rules outlive the interface, so they go on a stop, and before a start in case a
+    // crash left them behind.
+    let mut cleanup = vec![
+        format!("ip rule del {} 2>/dev/null || true", tunnel_rule),
+        format!("ip rule del {} 2>/dev/null || true", main_rule),
+    ];
+    cleanup.extend(
+        sources
+            .iter()
+            .map(|source| format!("ip rule del {} 2>/dev/null || true", source_rule(source))),
+    );
+    let mut post_up: Vec<String> = sources
+        .iter()
+        .map(|source| format!("ip rule add {}", source_rule(source)))
+        .collect();
+    post_up.push(format!("ip rule add {}", main_rule));
+    post_up.push(format!("ip route replace default dev %i table {}", TUNNEL_TABLE));
+    post_up.push(format!("ip rule add {}", tunnel_rule));
+    Ok(HooksConfig {
+        pre_up: cleanup.clone(),
+        post_up,
+        post_down: cleanup,
+    })
+}
+
+/// The routes go away with the interface, so there is nothing to undo.
+#[cfg(target_os = "macos")]
+fn client_hooks(_sources: &[Ipv4Addr]) -> Result<HooksConfig, String> {
+    Ok(HooksConfig {
+        pre_up: Vec::new(),
+        post_up: ["0.0.0.0/1", "128.0.0.0/1"]
+            .iter()
+            .map(|half| format!("route -q -n add -inet {} -interface %i", half))
+            .collect(),
+        post_down: Vec::new(),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn client_hooks(_sources: &[Ipv4Addr]) -> Result<HooksConfig, String> {
+    Err("Full tunnel routing is only generated for Linux and macOS clients".to_string())
+}
+
+/// This machine's IPv4 networks, which stay reachable directly, except the tunnel's own.
+fn local_networks(tunnel_interface: &str) -> Vec<(String, String)> {
+    let Ok(interfaces) = get_if_addrs() else {
+        return Vec::new();
+    };
+    let mut networks: Vec<(String, String)> = interfaces
+        .into_iter()
+        .filter(|iface| !iface.is_loopback() && iface.name != tunnel_interface)
+        .filter_map(|iface| match iface.addr {
+            IfAddr::V4(addr) => {
+                let mask = u32::from(addr.netmask);
+                let network = Ipv4Addr::from(u32::from(addr.ip) & mask);
+                Some((iface.name, format!("{}/{}", network, mask.count_ones())))
+            }
+            IfAddr::V6(_) => None,
+        })
+        .collect();
+    networks.sort();
+    networks.dedup();
+    networks
+}
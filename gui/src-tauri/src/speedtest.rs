@@ -0,0 +1,344 @@
+//! Upload speed test through the running tunnel with iperf3: each link on its own, with the
+//! others drained over the control socket, then all of them bonded. Link weights can be
+//! suggested from the results.
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use vtrunkd_core::config as core_config;
+use vtrunkd_core::control::{self, ControlCommand, LinkAdminState};
+
+use crate::{app_config_dir, control_request, query_stats, split_socket, ssh, SshConfig};
+
+const IPERF_PORT: u16 = 5201;
+/// The iperf3 server started on the VPS for the test writes its pid here.
+const REMOTE_PIDFILE: &str = "/tmp/vtrunkd-iperf3.pid";
+/// Weights are packets in a row per link, so large ones make bursts; suggestions stay under
+/// this.
+const MAX_WEIGHT: u32 = 10;
+/// Round trip added to both sides when comparing links' RTTs, so a few ms of difference
+/// between fast links does not count.
+const RTT_SLACK_MS: f64 = 50.0;
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkSpeed {
+    name: String,
+    bits_per_sec: Option<u64>,
+    /// Health-check round trip when the test started.
+    rtt_ms: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WeightSuggestion {
+    name: String,
+    weight: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct SpeedReport {
+    links: Vec<LinkSpeed>,
+    bonded_bits_per_sec: Option<u64>,
+    bonded_error: Option<String>,
+    /// Bonded throughput over the best single link's, in percent; above 100 when bonding
+    /// pays off.
+    gain_percent: Option<f64>,
+}
+
+/// Tests against an iperf3 server at `target` (`host` or `host:port`, usually the server's
+/// tunnel address). With `ssh`, starts that server on the VPS for the test and stops it
+/// afterwards.
+#[tauri::command]
+pub async fn speed_test(
+    app: AppHandle,
+    client_yaml: String,
+    target: String,
+    seconds: u32,
+    ssh: Option<SshConfig>,
+) -> Result<SpeedReport, String> {
+    let config = core_config::parse_config(&client_yaml)
+        .map_err(|e| format!("Invalid client config: {}", e))?;
+    let socket = control::socket_path(config.control_socket());
+    let (host, port) = split_target(&target)?;
+    let known_hosts = app_config_dir(&app)?.join("known_hosts");
+    let seconds = seconds.clamp(1, 60);
+    tauri::async_runtime::spawn_blocking(move || {
+        let session = match &ssh {
+            Some(ssh) => Some(start_remote_server(ssh, &known_hosts, port)?),
+            None => None,
+        };
+        let report = run(&app, &socket, &host, port, seconds);
+        if let Some(session) = session {
+            let _ = ssh::run(
+                &session,
+                &format!("kill \"$(cat {0})\"; rm -f {0}", REMOTE_PIDFILE),
+                None,
+            );
+        }
+        report
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Weights proportional to each link's measured throughput, lowered for links much slower
+/// to respond than the fastest so they reorder less. Links without a result get none.
+#[tauri::command]
+pub fn suggest_weights(links: Vec<LinkSpeed>) -> Vec<WeightSuggestion> {
+    let best_rtt = links
+        .iter()
+        .filter(|link| link.bits_per_sec.is_some())
+        .filter_map(|link| link.rtt_ms)
+        .min()
+        .unwrap_or(0) as f64;
+    let scores: Vec<Option<f64>> = links
+        .iter()
+        .map(|link| {
+            let bits = link.bits_per_sec.filter(|bits| *bits > 0)? as f64;
+            let rtt = link.rtt_ms.map_or(best_rtt, |rtt| rtt as f64);
+            Some(bits * (best_rtt + RTT_SLACK_MS) / (rtt + RTT_SLACK_MS))
+        })
+        .collect();
+    let measured = scores.iter().flatten();
+    let lowest = measured.clone().copied().fold(f64::INFINITY, f64::min);
+    let highest = measured.copied().fold(0.0, f64::max);
+    // The slowest link gets 1, unless that would push the fastest past MAX_WEIGHT.
+    let unit = lowest.max(highest / MAX_WEIGHT as f64);
+    let weights: Vec<Option<u32>> = scores
+        .iter()
+        .map(|score| score.map(|score| ((score / unit).round() as u32).clamp(1, MAX_WEIGHT)))
+        .collect();
+    let divisor = weights.iter().flatten().copied().fold(0, gcd).max(1);
+    links
+        .into_iter()
+        .zip(weights)
+        .map(|(link, weight)| WeightSuggestion {
+            name: link.name,
+            weight: weight.map(|weight| weight / divisor),
+        })
+        .collect()
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn run(
+    app: &AppHandle,
+    socket: &Path,
+    host: &str,
+    port: u16,
+    seconds: u32,
+) -> Result<SpeedReport, String> {
+    let snapshot =
+        query_stats(socket).map_err(|e| format!("vtrunkd is not answering on its socket: {}", e))?;
+    // Links go back to how they were, not simply up, when the test ends.
+    let saved: Vec<(String, LinkAdminState, Option<u64>)> = snapshot
+        .links
+        .iter()
+        .map(|link| {
+            let state = match link.state.as_deref() {
+                Some("admin-down") => LinkAdminState::Down,
+                Some("drain") => LinkAdminState::Drain,
+                _ => LinkAdminState::Up,
+            };
+            (link.name.clone(), state, link.rtt_ms)
+        })
+        .collect();
+    let testable: Vec<&str> = saved
+        .iter()
+        .filter(|(_, state, _)| *state != LinkAdminState::Down)
+        .map(|(name, _, _)| name.as_str())
+        .collect();
+
+    let mut links = Vec::new();
+    for (name, state, rtt_ms) in &saved {
+        if *state == LinkAdminState::Down {
+            continue;
+        }
+        progress(app, format!("Testing link {}...", name));
+        let result = set_links(socket, &testable, |link| {
+            if link == name {
+                LinkAdminState::Up
+            } else {
+                LinkAdminState::Drain
+            }
+        })
+        .and_then(|()| iperf(host, port, seconds));
+        links.push(LinkSpeed {
+            name: name.clone(),
+            bits_per_sec: result.as_ref().ok().copied(),
+            rtt_ms: *rtt_ms,
+            error: result.err(),
+        });
+    }
+
+    progress(app, "Testing all links bonded...".to_string());
+    let bonded = set_links(socket, &testable, |_| LinkAdminState::Up)
+        .and_then(|()| iperf(host, port, seconds));
+    for (name, state, _) in &saved {
+        let _ = set_link(socket, name, *state);
+    }
+
+    let best = links.iter().filter_map(|link| link.bits_per_sec).max();
+    let gain_percent = match (&bonded, best) {
+        (Ok(bonded), Some(best)) if best > 0 => Some(*bonded as f64 * 100.0 / best as f64),
+        _ => None,
+    };
+    Ok(SpeedReport {
+        links,
+        bonded_bits_per_sec: bonded.as_ref().ok().copied(),
+        bonded_error: bonded.err(),
+        gain_percent,
+    })
+}
+
+fn set_links<F>(socket: &Path, links: &[&str], state_of: F) -> Result<(), String>
+where
+    F: Fn(&str) -> LinkAdminState,
+{
+    links
+        .iter()
+        .try_for_each(|link| set_link(socket, link, state_of(link)))
+}
+
+fn set_link(socket: &Path, name: &str, state: LinkAdminState) -> Result<(), String> {
+    let command = ControlCommand::SetLinkState {
+        name: name.to_string(),
+        state,
+    };
+    control_request(socket, &command)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to set link {}: {}", name, e))
+}
+
+/// Received throughput of an iperf3 upload to `host`.
+fn iperf(host: &str, port: u16, seconds: u32) -> Result<u64, String> {
+    let output = Command::new("iperf3")
+        .args(["-c", host, "-p", &port.to_string(), "-t", &seconds.to_string(), "-J"])
+        .output()
+        .map_err(|e| format!("iperf3 failed (is it installed?): {}", e))?;
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|_| String::from_utf8_lossy(&output.stderr).trim().to_string())?;
+    if let Some(error) = report.get("error").and_then(|error| error.as_str()) {
+        return Err(error.to_string());
+    }
+    report
+        .pointer("/end/sum_received/bits_per_second")
+        .and_then(|bits| bits.as_f64())
+        .map(|bits| bits as u64)
+        .ok_or_else(|| "iperf3 reported no throughput".to_string())
+}
+
+fn start_remote_server(
+    ssh: &SshConfig,
+    known_hosts: &Path,
+    port: u16,
+) -> Result<ssh2::Session, String> {
+    let session = ssh::connect(ssh, known_hosts)?;
+    let command = format!(
+        "command -v iperf3 >/dev/null 2>&1 || {{ echo 'iperf3 is not installed on the server'; exit 1; }}; \
+         iperf3 -s -D -p {port} --pidfile {REMOTE_PIDFILE}"
+    );
+    let (output, status) = ssh::run(&session, &command, None)?;
+    if status != 0 {
+        return Err(output.trim().to_string());
+    }
+    Ok(session)
+}
+
+fn progress(app: &AppHandle, message: String) {
+    let _ = app.emit_all("speedtest-progress", message);
+}
+
+fn split_target(target: &str) -> Result<(String, u16), String> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Err("Speed test target is required".to_string());
+    }
+    if target.parse::<IpAddr>().is_ok() {
+        return Ok((target.to_string(), IPERF_PORT));
+    }
+    match split_socket(target) {
+        Some(target) => Ok(target),
+        None => Ok((target.trim_matches(['[', ']']).to_string(), IPERF_PORT)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(name: &str, mbit: Option<u64>, rtt_ms: Option<u64>) -> LinkSpeed {
+        LinkSpeed {
+            name: name.to_string(),
+            bits_per_sec: mbit.map(|mbit| mbit * 1_000_000),
+            rtt_ms,
+            error: None,
+        }
+    }
+
+    fn weights(links: Vec<LinkSpeed>) -> Vec<Option<u32>> {
+        suggest_weights(links)
+            .into_iter()
+            .map(|suggestion| suggestion.weight)
+            .collect()
+    }
+
+    #[test]
+    fn weights_follow_throughput_and_round_trip() {
+        assert_eq!(
+            weights(vec![
+                link("fiber", Some(100), Some(20)),
+                link("lte", Some(50), Some(20)),
+                link("failed", None, Some(20)),
+            ]),
+            [Some(2), Some(1), None]
+        );
+        // As fast, but 200 ms further away.
+        assert_eq!(
+            weights(vec![
+                link("fiber", Some(100), Some(20)),
+                link("satellite", Some(100), Some(220)),
+            ]),
+            [Some(4), Some(1)]
+        );
+        // Capped at MAX_WEIGHT, then reduced: 10:2 is 5:1.
+        assert_eq!(
+            weights(vec![
+                link("fiber", Some(1000), None),
+                link("lte", Some(200), None),
+            ]),
+            [Some(5), Some(1)]
+        );
+        assert_eq!(
+            weights(vec![
+                link("fiber", Some(1000), None),
+                link("dsl", Some(1), None),
+            ]),
+            [Some(MAX_WEIGHT), Some(1)]
+        );
+    }
+
+    #[test]
+    fn split_target_defaults_the_iperf_port() {
+        let target = |host: &str, port: u16| Ok((host.to_string(), port));
+        assert_eq!(split_target("10.8.0.1"), target("10.8.0.1", IPERF_PORT));
+        assert_eq!(split_target(" 10.8.0.1:5300 "), target("10.8.0.1", 5300));
+        assert_eq!(split_target("fd00::1"), target("fd00::1", IPERF_PORT));
+        assert_eq!(split_target("[fd00::1]"), target("fd00::1", IPERF_PORT));
+        assert_eq!(split_target("[fd00::1]:5300"), target("fd00::1", 5300));
+        assert_eq!(
+            split_target("vps.example.com"),
+            target("vps.example.com", IPERF_PORT)
+        );
+        assert!(split_target("  ").is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! SSH sessions to the VPS over libssh2, with key, agent or password authentication.
+//!
+//! Host keys are checked against the app's own `known_hosts`. An unknown host is refused
+//! until the user has compared its fingerprint and trusted it in the UI, and a changed key
+//! is always refused.
+
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use ssh2::{
+    Channel, CheckResult, ExtendedData, HashType, HostKeyType, KnownHostFileKind, KnownHostKeyFormat,
+    Session,
+};
+
+use crate::SshConfig;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often [`follow`] checks whether it should stop while the remote side is quiet.
+const FOLLOW_POLL: Duration = Duration::from_millis(200);
+/// Keys tried in `key` mode with no key path, after the agent, as ssh does.
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SshAuth {
+    /// `key_path`, or the agent and the default keys in `~/.ssh` when it is empty.
+    #[default]
+    Key,
+    Agent,
+    Password,
+}
+
+/// Connects and authenticates, refusing hosts not trusted in `known_hosts`.
+pub fn connect(ssh: &SshConfig, known_hosts: &Path) -> Result<Session, String> {
+    let host = ssh.host.trim();
+    let user = ssh.login_user();
+    if host.is_empty() {
+        return Err("SSH host is required".to_string());
+    }
+    if user.is_empty() {
+        return Err("SSH user is required".to_string());
+    }
+    let session = handshake(host, ssh.port)?;
+    verify_host_key(&session, host, ssh.port, known_hosts)?;
+    authenticate(&session, ssh, &user)?;
+    // Provisioning may build vtrunkd from source; only the setup above is time-limited.
+    session.set_timeout(0);
+    Ok(session)
+}
+
+/// Runs `command`, feeding it `stdin`, and returns stdout and stderr together with the exit
+/// status.
+pub fn run(session: &Session, command: &str, stdin: Option<&str>) -> Result<(String, i32), String> {
+    run_streaming(session, command, stdin, |_| {})
+}
+
+/// [`run`], handing each line of output to `on_line` as it arrives.
+pub fn run_streaming<F>(
+    session: &Session,
+    command: &str,
+    stdin: Option<&str>,
+    mut on_line: F,
+) -> Result<(String, i32), String>
+where
+    F: FnMut(&str),
+{
+    let mut channel = exec(session, command)?;
+    if let Some(input) = stdin {
+        std::io::Write::write_all(&mut channel, input.as_bytes())
+            .map_err(|e| format!("SSH stdin failed: {}", e))?;
+    }
+    channel
+        .send_eof()
+        .map_err(|e| format!("SSH stdin failed: {}", e))?;
+    let mut output = String::new();
+    let mut reader = BufReader::new(&mut channel);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| format!("SSH failed: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        on_line(text.trim_end());
+        output.push_str(&text);
+    }
+    channel
+        .wait_close()
+        .map_err(|e| format!("SSH failed: {}", e))?;
+    let status = channel
+        .exit_status()
+        .map_err(|e| format!("SSH failed: {}", e))?;
+    Ok((output, status))
+}
+
+/// Runs `command` until it exits or `stop` returns true, handing each line of output to
+/// `on_line`. Meant for commands that never finish on their own, such as `journalctl -f`.
+pub fn follow<S, F>(session: &Session, command: &str, stop: S, mut on_line: F) -> Result<(), String>
+where
+    S: Fn() -> bool,
+    F: FnMut(&str),
+{
+    let mut channel = exec(session, command)?;
+    channel
+        .send_eof()
+        .map_err(|e| format!("SSH stdin failed: {}", e))?;
+    // Non-blocking reads, so a quiet log does not keep the stop flag from being seen.
+    session.set_blocking(false);
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 4096];
+    let result = loop {
+        if stop() {
+            break Ok(());
+        }
+        match channel.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                pending.extend_from_slice(&buf[..read]);
+                while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    on_line(String::from_utf8_lossy(&line).trim_end());
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(FOLLOW_POLL),
+            Err(e) => break Err(format!("SSH failed: {}", e)),
+        }
+    };
+    session.set_blocking(true);
+    let _ = channel.close();
+    result
+}
+
+/// The host's key fingerprint as `ssh-keygen -l` prints it, e.g. `SHA256:... (ED25519)`.
+pub fn fingerprint(host: &str, port: u16) -> Result<String, String> {
+    let session = handshake(host, port)?;
+    host_key_fingerprint(&session)
+}
+
+/// Adds the host's key to `known_hosts`, provided it still has the fingerprint the user
+/// checked.
+pub fn trust(host: &str, port: u16, expected: &str, known_hosts: &Path) -> Result<(), String> {
+    let session = handshake(host, port)?;
+    if host_key_fingerprint(&session)? != expected.trim() {
+        return Err("The host key changed since its fingerprint was fetched".to_string());
+    }
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| "The host sent no key".to_string())?;
+    let mut hosts = read_known_hosts(&session, known_hosts)?;
+    hosts
+        .add(&known_hosts_name(host, port), key, "", KnownHostKeyFormat::from(key_type))
+        .map_err(|e| format!("Failed to add the host key: {}", e))?;
+    if let Some(dir) = known_hosts.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    hosts
+        .write_file(known_hosts, KnownHostFileKind::OpenSSH)
+        .map_err(|e| format!("Failed to write known_hosts: {}", e))
+}
+
+fn exec(session: &Session, command: &str) -> Result<Channel, String> {
+    let mut channel = session
+        .channel_session()
+        .map_err(|e| format!("SSH channel failed: {}", e))?;
+    channel
+        .handle_extended_data(ExtendedData::Merge)
+        .map_err(|e| format!("SSH channel failed: {}", e))?;
+    channel
+        .exec(command)
+        .map_err(|e| format!("SSH exec failed: {}", e))?;
+    Ok(channel)
+}
+
+fn handshake(host: &str, port: u16) -> Result<Session, String> {
+    if host.starts_with('-') {
+        return Err("Invalid host".to_string());
+    }
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("No address for {}", host))?;
+    let tcp = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+    let mut session = Session::new().map_err(|e| e.to_string())?;
+    session.set_timeout(CONNECT_TIMEOUT.as_millis() as u32);
+    session.set_tcp_stream(tcp);
+    session
+        .handshake()
+        .map_err(|e| format!("SSH handshake with {} failed: {}", host, e))?;
+    Ok(session)
+}
+
+fn verify_host_key(
+    session: &Session,
+    host: &str,
+    port: u16,
+    known_hosts: &Path,
+) -> Result<(), String> {
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| "The host sent no key".to_string())?;
+    let hosts = read_known_hosts(session, known_hosts)?;
+    match hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(format!(
+            "{} is not trusted yet; fetch its fingerprint, compare it and trust the host first",
+            host
+        )),
+        CheckResult::Mismatch => Err(format!(
+            "The host key of {} changed ({}); refusing to connect",
+            host,
+            host_key_fingerprint(session)?
+        )),
+        CheckResult::Failure => Err("Failed to check the host key".to_string()),
+    }
+}
+
+fn authenticate(session: &Session, ssh: &SshConfig, user: &str) -> Result<(), String> {
+    let secret = ssh.password.as_deref().filter(|secret| !secret.is_empty());
+    let result = match ssh.auth {
+        SshAuth::Password => {
+            let password = secret.ok_or_else(|| "SSH password is required".to_string())?;
+            session.userauth_password(user, password)
+        }
+        SshAuth::Agent => session.userauth_agent(user),
+        SshAuth::Key if !ssh.key_path.trim().is_empty() => {
+            session.userauth_pubkey_file(user, None, Path::new(ssh.key_path.trim()), secret)
+        }
+        SshAuth::Key => {
+            if session.userauth_agent(user).is_err() {
+                for key in default_keys() {
+                    if session.userauth_pubkey_file(user, None, &key, secret).is_ok() {
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        return Err(format!("SSH authentication as {} failed: {}", user, e));
+    }
+    if session.authenticated() {
+        Ok(())
+    } else {
+        Err(format!("SSH authentication as {} failed", user))
+    }
+}
+
+fn default_keys() -> Vec<PathBuf> {
+    let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) else {
+        return Vec::new();
+    };
+    let dir = PathBuf::from(home).join(".ssh");
+    DEFAULT_KEYS
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+fn read_known_hosts(session: &Session, path: &Path) -> Result<ssh2::KnownHosts, String> {
+    let mut hosts = session.known_hosts().map_err(|e| e.to_string())?;
+    if path.exists() {
+        hosts
+            .read_file(path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    }
+    Ok(hosts)
+}
+
+/// `host`, or `[host]:port` off the default port, as OpenSSH writes it.
+fn known_hosts_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+fn host_key_fingerprint(session: &Session) -> Result<String, String> {
+    let hash = session
+        .host_key_hash(HashType::Sha256)
+        .ok_or_else(|| "The host sent no key".to_string())?;
+    let key_type = match session.host_key().map(|(_, key_type)| key_type) {
+        Some(HostKeyType::Rsa) => "RSA",
+        Some(HostKeyType::Dss) => "DSA",
+        Some(HostKeyType::Ecdsa256 | HostKeyType::Ecdsa384 | HostKeyType::Ecdsa521) => "ECDSA",
+        Some(HostKeyType::Ed25519) => "ED25519",
+        _ => "unknown",
+    };
+    Ok(format!(
+        "SHA256:{} ({})",
+        general_purpose::STANDARD_NO_PAD.encode(hash),
+        key_type
+    ))
+}
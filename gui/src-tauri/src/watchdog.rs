@@ -0,0 +1,187 @@
+//! Notices when the daemon exits on its own, which the window would otherwise keep showing
+//! as running, and starts it again with backoff when auto-restart is on.
+
+use std::process::ExitStatus;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{launch, logs, notifications, RunnerState, Tunnel};
+
+/// Lines of stderr shown with an exit.
+pub const STDERR_TAIL: usize = 20;
+const EXIT_POLL: Duration = Duration::from_millis(500);
+/// How long stderr gets to drain after the exit, so its last lines are in.
+const STDERR_DRAIN: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// Crashes in a row before giving up; a broken config would otherwise loop forever.
+const MAX_RESTARTS: u32 = 10;
+/// A run this long counts as recovered, so the next crash starts the backoff over.
+const STABLE_RUN: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Serialize)]
+struct Restarted {
+    tunnel: String,
+    attempt: u32,
+}
+
+/// How a tunnel's daemon ended.
+pub struct Exit {
+    /// `exited with status 1`, `killed by signal 9` or similar.
+    pub status: String,
+    pub code: Option<i32>,
+    pub success: bool,
+}
+
+impl Exit {
+    pub fn of(status: &ExitStatus) -> Exit {
+        Exit {
+            status: describe(status),
+            code: status.code(),
+            success: status.success(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct DaemonExit {
+    tunnel: String,
+    /// `exited with status 1`, `killed by signal 9` or similar.
+    status: String,
+    code: Option<i32>,
+    /// The last lines the daemon wrote to stderr.
+    stderr: Vec<String>,
+    /// Seconds until it is started again, when it will be.
+    restart_in_secs: Option<u64>,
+}
+
+/// Waits for run `run` of the tunnel's daemon to exit; a stop from the window is not an exit.
+pub fn watch_exit(
+    app: AppHandle,
+    tunnel: Arc<Tunnel>,
+    run: u64,
+    stderr: Option<JoinHandle<()>>,
+) {
+    let started = Instant::now();
+    thread::spawn(move || {
+        let exit = loop {
+            thread::sleep(EXIT_POLL);
+            if tunnel.run.load(Ordering::Relaxed) != run {
+                return;
+            }
+            let Ok(mut guard) = tunnel.child.lock() else {
+                return;
+            };
+            let Some(daemon) = guard.as_mut() else {
+                return;
+            };
+            match daemon.try_wait() {
+                Ok(Some(exit)) => {
+                    *guard = None;
+                    break exit;
+                }
+                Ok(None) => {}
+                Err(_) => return,
+            }
+        };
+        if let Ok(mut elevation) = tunnel.elevation.lock() {
+            *elevation = None;
+        }
+        tunnel.clear_credentials();
+        if let Some(stderr) = stderr {
+            let deadline = Instant::now() + STDERR_DRAIN;
+            while !stderr.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        if started.elapsed() >= STABLE_RUN {
+            tunnel.restarts.store(0, Ordering::Relaxed);
+        }
+        let attempt = tunnel.restarts.load(Ordering::Relaxed) + 1;
+        let auto_restart = tunnel.auto_restart.load(Ordering::Relaxed) && !exit.success;
+        let delay = backoff(attempt);
+        let exit = DaemonExit {
+            tunnel: tunnel.name.clone(),
+            status: exit.status,
+            code: exit.code,
+            stderr: tunnel
+                .stderr_tail
+                .lock()
+                .map(|tail| tail.iter().cloned().collect())
+                .unwrap_or_default(),
+            restart_in_secs: (auto_restart && attempt <= MAX_RESTARTS)
+                .then_some(delay.as_secs()),
+        };
+        if tunnel.notify.load(Ordering::Relaxed) {
+            let title = format!("{}: vtrunkd stopped", tunnel.name);
+            notifications::show(&app, &title, &exit.status);
+        }
+        let _ = app.emit_all("vtrunkd-exit", exit.clone());
+        if exit.restart_in_secs.is_none() {
+            if auto_restart {
+                let message =
+                    format!("Not restarting vtrunkd after {} crashes in a row.", MAX_RESTARTS);
+                let _ = app.emit_all("vtrunkd-log", logs::tagged(&tunnel.name, &message));
+            }
+            return;
+        }
+
+        tunnel.restarts.store(attempt, Ordering::Relaxed);
+        tunnel.restart_pending.store(true, Ordering::Relaxed);
+        thread::sleep(delay);
+        // Started or stopped from the window in the meantime.
+        if tunnel.run.load(Ordering::Relaxed) != run
+            || !tunnel.restart_pending.swap(false, Ordering::Relaxed)
+        {
+            return;
+        }
+        let Some(previous) = tunnel.launch.lock().ok().and_then(|launch| launch.clone()) else {
+            return;
+        };
+        match launch(&app, app.state::<RunnerState>().inner(), &tunnel, previous) {
+            Ok(()) => {
+                let restarted = Restarted {
+                    tunnel: tunnel.name.clone(),
+                    attempt,
+                };
+                let _ = app.emit_all("vtrunkd-restarted", restarted);
+            }
+            Err(e) => {
+                let _ = app.emit_all(
+                    "vtrunkd-exit",
+                    DaemonExit {
+                        tunnel: tunnel.name.clone(),
+                        status: format!("restart failed: {}", e),
+                        code: None,
+                        stderr: Vec::new(),
+                        restart_in_secs: None,
+                    },
+                );
+            }
+        }
+    });
+}
+
+/// 1 s before the first restart, doubling up to a minute.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.saturating_sub(1).min(6)).min(RESTART_BACKOFF_MAX)
+}
+
+fn describe(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exited with status {}", code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("killed by signal {}", signal);
+        }
+    }
+    status.to_string()
+}
@@ -1,5 +1,6 @@
 import { invoke } from '@tauri-apps/api/tauri';
 import { listen } from '@tauri-apps/api/event';
+import { ask, open, save } from '@tauri-apps/api/dialog';
 
 const linkTemplate = document.getElementById('link-template');
 const linksContainer = document.getElementById('links');
@@ -14,11 +15,37 @@ const linkCountEl = document.getElementById('link-count');
 const serverHostDisplayEl = document.getElementById('server-host-display');
 const sshFingerprintEl = document.getElementById('ssh-fingerprint');
 const trustHostBtn = document.getElementById('trust-host');
+const dashboardEl = document.getElementById('dashboard');
+const importedPathEl = document.getElementById('imported-path');
+const saveConfigBtn = document.getElementById('save-config');
+const provisionStepEl = document.getElementById('provision-step');
+const serverLogEl = document.getElementById('server-log');
+const followServerLogBtn = document.getElementById('follow-server-log');
+const stopServerLogBtn = document.getElementById('stop-server-log');
+const updateServerBtn = document.getElementById('update-server');
+const suggestWeightsBtn = document.getElementById('suggest-weights');
+const profileListEl = document.getElementById('profile-list');
+const clientQrEl = document.getElementById('client-qr');
+const notifyEl = document.getElementById('notify');
+const daemonLogEl = document.getElementById('daemon-log');
+const persistLogsBtn = document.getElementById('persist-logs');
+const tunnelsEl = document.getElementById('tunnels');
+const logTunnelEl = document.getElementById('log-tunnel');
+const profileReconnectEl = document.getElementById('profile-reconnect');
+const autostartEl = document.getElementById('autostart');
 
+let lastSpeedTest = null;
+let logRefresh = null;
+let persistingLogs = false;
+// Latest link rows of each running tunnel, merged into the dashboard.
+const tunnelStats = {};
 let links = [
   { name: 'wifi', bind: '', weight: 1 },
   { name: 'lte/5g', bind: '', weight: 1 }
 ];
+let imported = null;
+// Keys of the pair already generated or imported; kept until the user asks for new ones.
+let keys = {};
 
 function renderLinks() {
   linksContainer.innerHTML = '';
@@ -77,6 +104,10 @@ function readText(id) {
   return document.getElementById(id).value.trim();
 }
 
+function currentTunnel() {
+  return readText('tunnel-name') || 'default';
+}
+
 function buildParams() {
   const healthEnabled = document.getElementById('health-enabled').checked;
   return {
@@ -92,14 +123,20 @@ function buildParams() {
     health_interval_ms: readNumber('health-interval'),
     health_timeout_ms: readNumber('health-timeout'),
     health_enabled: healthEnabled,
+    preshared_key: document.getElementById('preshared-key').checked,
     server_host: readText('server-host'),
     server_bind: readText('server-bind'),
     server_port_base: readNumber('server-port'),
+    full_tunnel: document.getElementById('full-tunnel').checked,
+    control_socket: readText('control-socket'),
     links: links.map((link) => ({
       name: link.name,
       bind: link.bind,
       weight: link.weight
-    }))
+    })),
+    client_private_key: keys.client_private_key || null,
+    server_private_key: keys.server_private_key || null,
+    preshared_key_value: keys.preshared_key_value || null
   };
 }
 
@@ -111,14 +148,466 @@ async function generateConfigs() {
     const result = await invoke('generate_configs', { params });
     clientConfigEl.value = result.client_yaml;
     serverConfigEl.value = result.server_yaml;
+    updateServerBtn.disabled = true;
+    hideQr();
     clientPublicEl.textContent = result.client_public_key;
     serverPublicEl.textContent = result.server_public_key;
+    keys = {
+      client_private_key: result.client_private_key,
+      server_private_key: result.server_private_key,
+      preshared_key_value: result.preshared_key || keys.preshared_key_value
+    };
+    showBypass(result.bypass);
     appendLog('Configs generated.');
+    await checkUplinks(params.links);
+    await checkMtu(params);
   } catch (err) {
     appendLog(`Error: ${err}`);
   }
 }
 
+function showBypass(bypass) {
+  const bypassEl = document.getElementById('full-tunnel-bypass');
+  bypassEl.hidden = !bypass.length;
+  bypassEl.textContent = bypass.length
+    ? `Everything else goes through the tunnel. Bypassing it: ${bypass.join('; ')}.`
+    : '';
+  bypass.forEach((entry) => appendLog(`Bypasses the tunnel: ${entry}`));
+}
+
+async function checkUplinks(linkInputs) {
+  try {
+    const warnings = await invoke('check_link_uplinks', { links: linkInputs });
+    warnings.forEach((warning) => appendLog(`Warning: ${warning}`));
+  } catch (err) {
+    appendLog(`Uplink check failed: ${err}`);
+  }
+}
+
+async function adviseMtu(params) {
+  const advice = await invoke('check_mtu', {
+    links: params.links,
+    serverHost: params.server_host,
+    mtu: params.mtu
+  });
+  const adviceEl = document.getElementById('mtu-advice');
+  adviceEl.hidden = !advice.links.length;
+  adviceEl.textContent = advice.links
+    .map((link) => {
+      const via = link.interface ? ` via ${link.interface}` : '';
+      const outer = link.ipv6 ? 'IPv6' : 'IPv4';
+      return `${link.name}${via}: MTU ${link.mtu} over ${outer}, up to ${link.tunnel_mtu}`;
+    })
+    .join('; ');
+  advice.warnings.forEach((warning) => appendLog(`Warning: ${warning}`));
+  return advice;
+}
+
+async function checkMtu(params) {
+  try {
+    await adviseMtu(params);
+  } catch (err) {
+    appendLog(`MTU check failed: ${err}`);
+  }
+}
+
+async function fitMtu() {
+  try {
+    // Fitting replaces the MTU, so warnings about the current one do not apply.
+    const advice = await adviseMtu({ ...buildParams(), mtu: 0 });
+    document.getElementById('mtu').value = advice.suggested;
+    appendLog(`MTU set to ${advice.suggested}, the largest every link carries whole.`);
+  } catch (err) {
+    appendLog(`MTU check failed: ${err}`);
+  }
+}
+
+function writeText(id, value) {
+  if (value) {
+    document.getElementById(id).value = value;
+  }
+}
+
+function writeNumber(id, value) {
+  if (value) {
+    document.getElementById(id).value = value;
+  }
+}
+
+// Fills the form from an imported config, keeping what that side's file cannot tell.
+function applyParams(params) {
+  writeText('client-interface', params.client_interface);
+  writeText('client-address', params.client_address);
+  writeText('server-address', params.server_address);
+  writeText('netmask', params.netmask);
+  writeNumber('mtu', params.mtu);
+  writeNumber('buffer-size', params.buffer_size);
+  writeText('bonding-mode', params.bonding_mode);
+  document.getElementById('keepalive').value = params.keepalive;
+  writeNumber('error-backoff', params.error_backoff_secs);
+  writeNumber('health-interval', params.health_interval_ms);
+  writeNumber('health-timeout', params.health_timeout_ms);
+  document.getElementById('health-enabled').checked = params.health_enabled;
+  document.getElementById('preshared-key').checked = params.preshared_key;
+  document.getElementById('full-tunnel').checked = params.full_tunnel;
+  document.getElementById('control-socket').value = params.control_socket || '';
+  writeText('server-host', params.server_host);
+  writeText('server-bind', params.server_bind);
+  writeNumber('server-port', params.server_port_base);
+  links = params.links.map((link, index) => ({
+    name: link.name,
+    bind: link.bind || (links[index] ? links[index].bind : ''),
+    weight: link.weight
+  }));
+  renderLinks();
+  refreshMetrics();
+}
+
+async function importConfig() {
+  const path = await open({
+    multiple: false,
+    filters: [{ name: 'vtrunkd config', extensions: ['yaml', 'yml'] }]
+  });
+  if (!path) {
+    return;
+  }
+  try {
+    const result = await invoke('import_config', { path });
+    applyImported(result);
+    imported = { kind: result.kind, path };
+    importedPathEl.textContent = `Editing ${result.kind} config ${path}`;
+    saveConfigBtn.disabled = false;
+    appendLog(`Imported ${result.kind} config from ${path}.`);
+  } catch (err) {
+    appendLog(`Import failed: ${err}`);
+  }
+}
+
+function applyImported(result) {
+  applyParams(result.params);
+  keys = {
+      client_private_key: result.params.client_private_key || keys.client_private_key,
+      server_private_key: result.params.server_private_key || keys.server_private_key,
+    preshared_key_value: result.params.preshared_key_value || keys.preshared_key_value
+  };
+  if (result.kind === 'client') {
+    clientConfigEl.value = result.yaml;
+    hideQr();
+    clientPublicEl.textContent = result.public_key || '-';
+    serverPublicEl.textContent = result.peer_public_key;
+  } else {
+    serverConfigEl.value = result.yaml;
+    updateServerBtn.disabled = true;
+    serverPublicEl.textContent = result.public_key || '-';
+    clientPublicEl.textContent = result.peer_public_key;
+  }
+}
+
+async function importBundle() {
+  const passphrase = document.getElementById('bundle-passphrase').value;
+  if (!passphrase) {
+    appendLog('Enter the passphrase the config was exported with.');
+    return;
+  }
+  const path = await open({
+    multiple: false,
+    filters: [{ name: 'vtrunkd export', extensions: ['vtrunkd'] }]
+  });
+  if (!path) {
+    return;
+  }
+  appendLog('Decrypting the export...');
+  try {
+    const result = await invoke('import_bundle', { path, passphrase });
+    applyImported(result);
+    // Saving writes plain YAML, so it must not go over the encrypted file.
+    imported = null;
+    importedPathEl.textContent = `Loaded client config from ${path}`;
+    saveConfigBtn.disabled = true;
+    appendLog(`Imported client config from encrypted export ${path}.`);
+  } catch (err) {
+    appendLog(`Import failed: ${err}`);
+  }
+}
+
+async function refreshDaemonLog() {
+  try {
+    const entries = await invoke('get_logs', {
+      level: document.getElementById('log-level').value,
+      query: document.getElementById('log-query').value,
+      tunnel: logTunnelEl.value || null
+    });
+    daemonLogEl.textContent = entries.length
+      ? entries.map((entry) => `[${entry.tunnel}] ${entry.line}`).join('\n')
+      : 'No matching lines.';
+    daemonLogEl.scrollTop = daemonLogEl.scrollHeight;
+  } catch (err) {
+    appendLog(`Log filter failed: ${err}`);
+  }
+}
+
+async function persistLogs() {
+  try {
+    if (persistingLogs) {
+      await invoke('persist_logs', { path: null });
+      persistingLogs = false;
+      persistLogsBtn.textContent = 'Save to file';
+      appendLog('Stopped saving the daemon log.');
+      return;
+    }
+    const path = await save({
+      defaultPath: 'vtrunkd.log',
+      filters: [{ name: 'Log', extensions: ['log', 'txt'] }]
+    });
+    if (!path) {
+      return;
+    }
+    await invoke('persist_logs', { path });
+    persistingLogs = true;
+    persistLogsBtn.textContent = 'Stop saving';
+    appendLog(`Saving the daemon log to ${path}.`);
+  } catch (err) {
+    appendLog(`Saving the log failed: ${err}`);
+  }
+}
+
+async function clearLogs() {
+  await invoke('clear_logs');
+  await refreshDaemonLog();
+}
+
+async function exportDiagnostics() {
+  const path = await save({
+    defaultPath: 'vtrunkd-diagnostics.txt',
+    filters: [{ name: 'Text', extensions: ['txt'] }]
+  });
+  if (!path) {
+    return;
+  }
+  try {
+    await invoke('export_diagnostics', {
+      path,
+      binaryPath: readText('binary-path'),
+      clientYaml: clientConfigEl.value,
+      serverYaml: serverConfigEl.value
+    });
+    appendLog(`Diagnostics written to ${path}; keys and tokens are redacted.`);
+  } catch (err) {
+    appendLog(`Diagnostics export failed: ${err}`);
+  }
+}
+
+function hideQr() {
+  clientQrEl.hidden = true;
+  clientQrEl.innerHTML = '';
+}
+
+async function showQr() {
+  const clientYaml = clientConfigEl.value.trim();
+  if (!clientYaml) {
+    appendLog('Generate the client config first.');
+    return;
+  }
+  try {
+    // SVG built by the backend from the config, not markup from the user.
+    clientQrEl.innerHTML = await invoke('client_qr', { clientYaml });
+    clientQrEl.hidden = false;
+    appendLog('QR code shown; it holds the client private key.');
+  } catch (err) {
+    hideQr();
+    appendLog(`QR code failed: ${err}`);
+  }
+}
+
+async function exportBundle() {
+  const clientYaml = clientConfigEl.value.trim();
+  if (!clientYaml) {
+    appendLog('Generate the client config first.');
+    return;
+  }
+  const passphrase = document.getElementById('bundle-passphrase').value;
+  const path = await save({
+    defaultPath: 'client.vtrunkd',
+    filters: [{ name: 'vtrunkd export', extensions: ['vtrunkd'] }]
+  });
+  if (!path) {
+    return;
+  }
+  appendLog('Encrypting the client config...');
+  try {
+    await invoke('export_bundle', { path, clientYaml, passphrase });
+    appendLog(`Encrypted client config written to ${path}.`);
+  } catch (err) {
+    appendLog(`Export failed: ${err}`);
+  }
+}
+
+async function saveImportedConfig() {
+  if (!imported) {
+    return;
+  }
+  const yaml = imported.kind === 'client' ? clientConfigEl.value : serverConfigEl.value;
+  try {
+    await invoke('save_config', { path: imported.path, yaml });
+    appendLog(`Saved ${imported.path}.`);
+  } catch (err) {
+    appendLog(`Save failed: ${err}`);
+  }
+}
+
+async function refreshProfiles(selected) {
+  try {
+    const names = await invoke('list_profiles');
+    profileListEl.innerHTML = '';
+    names.forEach((name) => {
+      const option = document.createElement('option');
+      option.value = name;
+      option.textContent = name;
+      profileListEl.appendChild(option);
+    });
+    if (selected) {
+      profileListEl.value = selected;
+    }
+  } catch (err) {
+    appendLog(`Failed to list profiles: ${err}`);
+  }
+}
+
+async function saveProfile() {
+  const name = readText('profile-name') || profileListEl.value;
+  if (!name) {
+    appendLog('Name the profile first.');
+    return;
+  }
+  const profile = {
+    name,
+    params: buildParams(),
+    ssh: buildSsh(),
+    binary_path: readText('binary-path'),
+    notifications: notifyEl.checked,
+    reconnect: profileReconnectEl.checked
+  };
+  try {
+    await invoke('save_profile', { profile });
+    await refreshProfiles(name);
+    appendLog(`Profile ${name} saved.`);
+  } catch (err) {
+    appendLog(`Failed to save profile: ${err}`);
+  }
+}
+
+async function loadProfile() {
+  const name = profileListEl.value;
+  if (!name) {
+    return;
+  }
+  try {
+    const profile = await invoke('load_profile', { name });
+    links = [];
+    applyParams(profile.params);
+    keys = {
+      client_private_key: profile.params.client_private_key,
+      server_private_key: profile.params.server_private_key,
+      preshared_key_value: profile.params.preshared_key_value
+    };
+    document.getElementById('ssh-user').value = profile.ssh.user;
+    document.getElementById('ssh-port').value = profile.ssh.port;
+    document.getElementById('ssh-key').value = profile.ssh.key_path;
+    document.getElementById('ssh-root').checked = profile.ssh.use_root;
+    document.getElementById('ssh-auth').value = profile.ssh.auth;
+    document.getElementById('ssh-password').value = profile.ssh.password || '';
+    writeText('binary-path', profile.binary_path);
+    notifyEl.checked = profile.notifications;
+    profileReconnectEl.checked = profile.reconnect;
+    document.getElementById('profile-name').value = name;
+    document.getElementById('tunnel-name').value = name;
+    await invoke('set_notifications', { tunnel: name, enabled: notifyEl.checked });
+    clientConfigEl.value = '';
+    serverConfigEl.value = '';
+    updateServerBtn.disabled = true;
+    hideQr();
+    appendLog(`Profile ${name} loaded; generate configs to use it.`);
+  } catch (err) {
+    appendLog(`Failed to load profile: ${err}`);
+  }
+}
+
+// Connects the last loaded profile as the app starts, when that profile asks for it.
+async function reconnectLastProfile() {
+  let name;
+  try {
+    name = await invoke('reconnect_profile');
+  } catch (err) {
+    appendLog(`Failed to read the last profile: ${err}`);
+    return;
+  }
+  if (!name) {
+    return;
+  }
+  appendLog(`Reconnecting profile ${name}...`);
+  profileListEl.value = name;
+  await loadProfile();
+  await generateConfigs();
+  await startTunnel();
+}
+
+// A build with the engine built in runs tunnels itself when no binary is given.
+async function offerEngine() {
+  try {
+    if (!(await invoke('engine_built_in'))) {
+      return;
+    }
+    const binaryEl = document.getElementById('binary-path');
+    binaryEl.placeholder = 'Empty: the built-in engine';
+    if (binaryEl.value === binaryEl.defaultValue) {
+      binaryEl.value = '';
+    }
+  } catch (err) {
+    appendLog(`Failed to check for the built-in engine: ${err}`);
+  }
+}
+
+async function refreshAutostart() {
+  try {
+    autostartEl.checked = await invoke('autostart_enabled');
+  } catch (err) {
+    appendLog(`Failed to read the login item: ${err}`);
+  }
+}
+
+async function setAutostart() {
+  try {
+    await invoke('set_autostart', { enabled: autostartEl.checked });
+    appendLog(
+      autostartEl.checked
+        ? 'The Control Room starts at login.'
+        : 'The Control Room no longer starts at login.'
+    );
+  } catch (err) {
+    autostartEl.checked = !autostartEl.checked;
+    appendLog(`Failed to change the login item: ${err}`);
+  }
+}
+
+async function deleteProfile() {
+  const name = profileListEl.value;
+  if (!name) {
+    return;
+  }
+  try {
+    await invoke('delete_profile', { name });
+    await refreshProfiles();
+    appendLog(`Profile ${name} deleted.`);
+  } catch (err) {
+    appendLog(`Failed to delete profile: ${err}`);
+  }
+}
+
+function newKeys() {
+  keys = {};
+  appendLog('The next generated configs get new keys; provision the server again with them.');
+}
+
 async function verifyHost() {
   const host = readText('server-host');
   const port = readNumber('ssh-port') || 22;
@@ -145,7 +634,8 @@ async function trustHost() {
   const port = readNumber('ssh-port') || 22;
   appendLog(`Adding ${host} to known_hosts...`);
   try {
-    await invoke('trust_host', { host, port });
+    const fingerprint = sshFingerprintEl.textContent;
+    await invoke('trust_host', { host, port, fingerprint });
     appendLog(`${host} trusted.`);
     trustHostBtn.disabled = true;
     trustHostBtn.textContent = 'Trusted';
@@ -154,19 +644,48 @@ async function trustHost() {
   }
 }
 
-async function provisionVps() {
-  appendLog('Provisioning VPS...');
-  const ssh = {
+function buildSsh() {
+  return {
     host: readText('server-host'),
     user: readText('ssh-user'),
     port: readNumber('ssh-port'),
     key_path: readText('ssh-key'),
-    use_root: document.getElementById('ssh-root').checked
+    use_root: document.getElementById('ssh-root').checked,
+    auth: readText('ssh-auth'),
+    password: document.getElementById('ssh-password').value || null
   };
+}
+
+// Logs what the checks found; false when one of them should stop the action.
+async function preflight(command, args) {
+  try {
+    const checks = await invoke(command, args);
+    checks
+      .filter((check) => !check.ok)
+      .forEach((check) => {
+        appendLog(`${check.blocking ? 'Error' : 'Warning'}: ${check.name}: ${check.detail}`);
+      });
+    return !checks.some((check) => check.blocking);
+  } catch (err) {
+    appendLog(`Preflight check failed: ${err}`);
+    return false;
+  }
+}
+
+async function provisionVps() {
+  appendLog('Provisioning VPS...');
+  const ssh = buildSsh();
   const options = {
     install_vtrunkd: document.getElementById('install-vtrunkd').checked,
-    install_service: document.getElementById('install-service').checked
+    install_service: document.getElementById('install-service').checked,
+    prebuilt: document.getElementById('install-prebuilt').checked
   };
+  provisionStepEl.textContent = 'Checking the server ports...';
+  if (!(await preflight('preflight_server', { ssh, serverYaml: serverConfigEl.value }))) {
+    provisionStepEl.textContent = 'Not provisioned: fix the errors above first.';
+    return;
+  }
+  provisionStepEl.textContent = 'Connecting...';
   try {
     const output = await invoke('provision_vps', {
       ssh,
@@ -175,10 +694,90 @@ async function provisionVps() {
     });
     appendLog(output || 'Provisioning complete.');
   } catch (err) {
+    provisionStepEl.textContent = `Failed: ${provisionStepEl.textContent}`;
     appendLog(`Provisioning failed: ${err}`);
   }
 }
 
+function appendServerLog(line) {
+  const lines = serverLogEl.textContent.split('\n');
+  lines.push(line);
+  serverLogEl.textContent = lines.slice(-500).join('\n');
+  serverLogEl.scrollTop = serverLogEl.scrollHeight;
+}
+
+async function serverStatus() {
+  serverLogEl.textContent = 'Fetching status...';
+  try {
+    serverLogEl.textContent = await invoke('server_status', { ssh: buildSsh() });
+  } catch (err) {
+    serverLogEl.textContent = `Status failed: ${err}`;
+  }
+}
+
+function setFollowing(following) {
+  followServerLogBtn.disabled = following;
+  stopServerLogBtn.disabled = !following;
+}
+
+async function followServerLog() {
+  try {
+    await invoke('follow_server_log', { ssh: buildSsh() });
+    setFollowing(true);
+    appendServerLog('-- following the journal --');
+  } catch (err) {
+    appendServerLog(`Follow failed: ${err}`);
+  }
+}
+
+async function stopServerLog() {
+  await invoke('stop_server_log');
+  setFollowing(false);
+  appendServerLog('-- stopped following --');
+}
+
+async function compareServerConfig() {
+  updateServerBtn.disabled = true;
+  serverLogEl.textContent = 'Comparing with /etc/vtrunkd.yaml on the server...';
+  try {
+    serverLogEl.textContent = await invoke('compare_server_config', {
+      ssh: buildSsh(),
+      serverYaml: serverConfigEl.value
+    });
+    updateServerBtn.disabled = false;
+  } catch (err) {
+    serverLogEl.textContent = `Compare failed: ${err}`;
+  }
+}
+
+async function updateServer() {
+  appendServerLog('Uploading the server config...');
+  try {
+    appendServerLog(await invoke('update_server', {
+      ssh: buildSsh(),
+      serverYaml: serverConfigEl.value
+    }));
+  } catch (err) {
+    appendServerLog(`Update failed: ${err}`);
+  }
+}
+
+async function uninstallServer() {
+  const host = readText('server-host') || 'the server';
+  const confirmed = await ask(
+    `Stop vtrunkd on ${host} and remove its service, config, keys, binary and build directory?`,
+    { title: 'Uninstall vtrunkd', type: 'warning' }
+  );
+  if (!confirmed) return;
+  appendServerLog('Uninstalling vtrunkd...');
+  try {
+    appendServerLog(await invoke('uninstall_server', { ssh: buildSsh() }));
+    provisionStepEl.textContent = 'Not provisioned yet.';
+  } catch (err) {
+    appendServerLog(`Uninstall failed: ${err}`);
+  }
+}
+
 async function startTunnel() {
   appendLog('Starting tunnel...');
   try {
@@ -187,36 +786,231 @@ async function startTunnel() {
       appendLog('Generate the client config first.');
       return;
     }
+    if (!(await preflight('preflight_client', { clientYaml }))) {
+      appendLog('Not started: fix the errors above first.');
+      return;
+    }
+    const tunnel = currentTunnel();
     const configPath = await invoke('write_config', {
       kind: 'client',
-      yaml: clientYaml
+      yaml: clientYaml,
+      tunnel
+    });
+    const binaryPath = readText('binary-path');
+    const elevate = document.getElementById('elevate').checked;
+    const notify = notifyEl.checked;
+    const autoRestart = document.getElementById('auto-restart').checked;
+    await invoke('start_vtrunkd', {
+      tunnel,
+      binaryPath,
+      configPath,
+      elevate,
+      notify,
+      autoRestart
     });
-    const binaryPath = readText('binary-path') || 'vtrunkd';
-    await invoke('start_vtrunkd', { binaryPath, configPath });
-    runStatusEl.textContent = 'Status: running';
-    runStatusEl.classList.add('running');
-    appendLog(`Tunnel started using ${configPath}`);
+    appendLog(`Tunnel ${tunnel} started using ${configPath}`);
   } catch (err) {
     appendLog(`Start failed: ${err}`);
   }
+  await refreshTunnels();
 }
 
-async function stopTunnel() {
-  appendLog('Stopping tunnel...');
+async function stopTunnel(tunnel = currentTunnel()) {
+  appendLog(`Stopping tunnel ${tunnel}...`);
   try {
-    await invoke('stop_vtrunkd');
-    runStatusEl.textContent = 'Status: stopped';
-    runStatusEl.classList.remove('running');
-    appendLog('Tunnel stopped.');
+    await invoke('stop_vtrunkd', { tunnel });
+    appendLog(`Tunnel ${tunnel} stopped.`);
   } catch (err) {
     appendLog(`Stop failed: ${err}`);
   }
+  await refreshTunnels();
+}
+
+async function refreshTunnels() {
+  let tunnels;
+  try {
+    tunnels = await invoke('list_tunnels');
+  } catch (err) {
+    appendLog(`Failed to list tunnels: ${err}`);
+    return;
+  }
+  const running = tunnels.filter((tunnel) => tunnel.state === 'running').length;
+  runStatusEl.textContent = running ? `Status: ${running} running` : 'Status: stopped';
+  runStatusEl.classList.toggle('running', running > 0);
+
+  tunnelsEl.innerHTML = '';
+  if (!tunnels.length) {
+    const row = document.createElement('tr');
+    row.className = 'empty';
+    const cell = document.createElement('td');
+    cell.colSpan = 5;
+    cell.textContent = 'No tunnel started yet.';
+    row.appendChild(cell);
+    tunnelsEl.appendChild(row);
+  }
+  tunnels.forEach((tunnel) => {
+    const row = document.createElement('tr');
+    [tunnel.name, tunnel.state, String(tunnel.restarts), tunnel.config_path || '-'].forEach(
+      (value, index) => {
+        const cell = document.createElement('td');
+        cell.textContent = value;
+        if (index === 1) {
+          cell.className = `state-${tunnel.state}`;
+        }
+        row.appendChild(cell);
+      }
+    );
+    const actions = document.createElement('td');
+    if (tunnel.state !== 'stopped') {
+      const stopBtn = document.createElement('button');
+      stopBtn.className = 'ghost';
+      stopBtn.textContent = 'Stop';
+      stopBtn.addEventListener('click', () => stopTunnel(tunnel.name));
+      actions.appendChild(stopBtn);
+    }
+    row.appendChild(actions);
+    tunnelsEl.appendChild(row);
+  });
+
+  const selected = logTunnelEl.value;
+  logTunnelEl.innerHTML = '<option value="">All tunnels</option>';
+  tunnels.forEach((tunnel) => {
+    const option = document.createElement('option');
+    option.value = tunnel.name;
+    option.textContent = tunnel.name;
+    logTunnelEl.appendChild(option);
+  });
+  logTunnelEl.value = tunnels.some((tunnel) => tunnel.name === selected) ? selected : '';
+}
+
+async function testConnection() {
+  const clientYaml = clientConfigEl.value.trim();
+  if (!clientYaml) {
+    appendLog('Generate the client config first.');
+    return;
+  }
+  appendLog('Testing the connection...');
+  try {
+    const report = await invoke('test_connection', {
+      clientYaml,
+      serverAddress: readText('server-address')
+    });
+    report.hops.forEach((hop) => {
+      appendLog(`${hop.ok ? 'ok  ' : 'FAIL'} ${hop.name} (${hop.target}): ${hop.detail}`);
+    });
+    appendLog(report.verdict);
+  } catch (err) {
+    appendLog(`Connection test failed: ${err}`);
+  }
+}
+
+async function speedTest() {
+  const clientYaml = clientConfigEl.value.trim();
+  if (!clientYaml) {
+    appendLog('Generate the client config first.');
+    return;
+  }
+  appendLog('Running the speed test; traffic moves between links while it runs...');
+  try {
+    const report = await invoke('speed_test', {
+      clientYaml,
+      target: readText('speedtest-target') || readText('server-address'),
+      seconds: Number(document.getElementById('speedtest-seconds').value) || 5,
+      ssh: document.getElementById('speedtest-remote').checked ? buildSsh() : null
+    });
+    report.links.forEach((link) => {
+      const rtt = link.rtt_ms == null ? '' : `, RTT ${link.rtt_ms} ms`;
+      appendLog(
+        link.error
+          ? `Link ${link.name}: failed (${link.error})`
+          : `Link ${link.name}: ${formatRate(link.bits_per_sec)}${rtt}`
+      );
+    });
+    lastSpeedTest = report;
+    suggestWeightsBtn.disabled = !report.links.some((link) => link.bits_per_sec != null);
+    if (report.bonded_error) {
+      appendLog(`Bonded: failed (${report.bonded_error})`);
+    } else {
+      const gain =
+        report.gain_percent == null ? '' : ` (${report.gain_percent.toFixed(0)}% of the best link)`;
+      appendLog(`Bonded: ${formatRate(report.bonded_bits_per_sec)}${gain}`);
+    }
+  } catch (err) {
+    appendLog(`Speed test failed: ${err}`);
+  }
+}
+
+async function suggestWeights() {
+  if (!lastSpeedTest) return;
+  try {
+    const suggestions = await invoke('suggest_weights', { links: lastSpeedTest.links });
+    const known = suggestions.filter(
+      (entry) => entry.weight != null && links.some((link) => link.name === entry.name)
+    );
+    if (!known.length) {
+      appendLog('No measured link matches a link in the form.');
+      return;
+    }
+    const summary = known.map((entry) => `${entry.name}=${entry.weight}`).join(', ');
+    appendLog(`Suggested weights: ${summary}`);
+    const apply = await ask(`Set link weights ${summary} and generate the configs again?`, {
+      title: 'Apply link weights'
+    });
+    if (!apply) return;
+    known.forEach((entry) => {
+      links.find((link) => link.name === entry.name).weight = entry.weight;
+    });
+    renderLinks();
+    await generateConfigs();
+    appendLog('Weights applied; start the tunnel again (and update the server) to use them.');
+  } catch (err) {
+    appendLog(`Weight suggestion failed: ${err}`);
+  }
+}
+
+async function installLocalService() {
+  const clientYaml = clientConfigEl.value.trim();
+  if (!clientYaml) {
+    appendLog('Generate the client config first.');
+    return;
+  }
+  appendLog('Installing the local vtrunkd service...');
+  try {
+    const output = await invoke('install_local_service', {
+      binaryPath: readText('binary-path'),
+      clientYaml
+    });
+    appendLog(output || 'Local service installed and started.');
+  } catch (err) {
+    appendLog(`Service install failed: ${err}`);
+  }
+}
+
+async function localService(action) {
+  try {
+    const output = await invoke('local_service', { action });
+    appendLog(output || `Local service: ${action} done.`);
+  } catch (err) {
+    appendLog(`Service ${action} failed: ${err}`);
+  }
+}
+
+async function localServiceStatus() {
+  try {
+    const status = await invoke('local_service_status');
+    appendLog(`Local service: ${status}`);
+  } catch (err) {
+    appendLog(`Service status failed: ${err}`);
+  }
 }
 
 async function autoDetect() {
   appendLog('Detecting local IPs...');
   try {
-    const addresses = await invoke('list_local_addrs');
+    // Tunnels (including vtrunkd's own) and bridges are not uplinks.
+    const addresses = (await invoke('list_local_addrs')).filter(
+      (entry) => entry.kind !== 'vpn' && entry.kind !== 'virtual'
+    );
     if (!addresses.length) {
       appendLog('No suitable addresses detected.');
       return;
@@ -228,7 +1022,12 @@ async function autoDetect() {
     }));
     renderLinks();
     refreshMetrics();
+    addresses.forEach((entry) => {
+      const route = entry.default_route ? `, default route via ${entry.gateway || 'link'}` : '';
+      appendLog(`${entry.name} (${entry.kind}): ${entry.addr}${route}`);
+    });
     appendLog(`Detected ${addresses.length} addresses.`);
+    await checkUplinks(links);
   } catch (err) {
     appendLog(`Detection failed: ${err}`);
   }
@@ -246,15 +1045,141 @@ function setupAnimations() {
 renderLinks();
 refreshMetrics();
 setupAnimations();
+offerEngine().then(refreshProfiles).then(reconnectLastProfile);
+refreshTunnels();
+refreshAutostart();
 
 listen('vtrunkd-log', (event) => {
   appendLog(event.payload);
+  // Lines come in bursts; the filtered view is fetched again once they settle.
+  if (!logRefresh) {
+    logRefresh = setTimeout(() => {
+      logRefresh = null;
+      refreshDaemonLog();
+    }, 500);
+  }
+});
+
+listen('provision-step', (event) => {
+  provisionStepEl.textContent = event.payload;
+  appendLog(`==> ${event.payload}`);
+});
+
+listen('speedtest-progress', (event) => {
+  appendLog(event.payload);
+});
+
+listen('provision-log', (event) => {
+  appendLog(event.payload);
+});
+
+listen('server-log', (event) => {
+  appendServerLog(event.payload);
+});
+
+listen('server-log-end', (event) => {
+  setFollowing(false);
+  appendServerLog(event.payload ? `-- log stream failed: ${event.payload} --` : '-- log stream ended --');
+});
+
+function describeEvent(event) {
+  switch (event.event) {
+    case 'link_up':
+      return `Link ${event.link} is up.`;
+    case 'link_down':
+      return `Link ${event.link} is down (${event.reason}).`;
+    case 'link_degraded':
+      return `Link ${event.link} degraded (silent ${event.silent_ms} ms).`;
+    case 'link_admin':
+      return `Link ${event.link} set ${event.state}.`;
+    case 'endpoint_changed':
+      return `Link ${event.link} endpoint changed to ${event.endpoint}.`;
+    case 'handshake_complete':
+      return 'WireGuard handshake completed.';
+    case 'failover':
+      return `Failover from ${event.from} to ${event.to}.`;
+    default:
+      return `Event: ${JSON.stringify(event)}`;
+  }
+}
+
+listen('vtrunkd-event', (event) => {
+  appendLog(`[${event.payload.tunnel}] ${describeEvent(event.payload.event)}`);
+});
+
+function formatRate(bitsPerSec) {
+  if (bitsPerSec >= 1e9) return `${(bitsPerSec / 1e9).toFixed(1)} Gbit/s`;
+  if (bitsPerSec >= 1e6) return `${(bitsPerSec / 1e6).toFixed(1)} Mbit/s`;
+  if (bitsPerSec >= 1e3) return `${(bitsPerSec / 1e3).toFixed(1)} kbit/s`;
+  return `${bitsPerSec} bit/s`;
+}
+
+function renderDashboard() {
+  const rows = Object.keys(tunnelStats)
+    .sort()
+    .flatMap((tunnel) => tunnelStats[tunnel].map((link) => ({ tunnel, ...link })));
+  dashboardEl.innerHTML = '';
+  if (!rows.length) {
+    const row = document.createElement('tr');
+    row.className = 'empty';
+    const cell = document.createElement('td');
+    cell.colSpan = 7;
+    cell.textContent = 'Start the tunnel to see live link stats.';
+    row.appendChild(cell);
+    dashboardEl.appendChild(row);
+    return;
+  }
+  rows.forEach((link) => {
+    const row = document.createElement('tr');
+    const cells = [
+      link.tunnel,
+      link.name,
+      link.state,
+      link.rtt_ms == null ? '-' : `${link.rtt_ms} ms`,
+      link.loss_percent == null ? '-' : `${link.loss_percent.toFixed(1)}%`,
+      formatRate(link.rx_bits_per_sec),
+      formatRate(link.tx_bits_per_sec)
+    ];
+    cells.forEach((value, index) => {
+      const cell = document.createElement('td');
+      cell.textContent = value;
+      if (index === 2) {
+        cell.className = `state-${link.state}`;
+      }
+      row.appendChild(cell);
+    });
+    dashboardEl.appendChild(row);
+  });
+}
+
+listen('vtrunkd-stats', (event) => {
+  const { tunnel, links: rows } = event.payload;
+  if (rows.length) {
+    tunnelStats[tunnel] = rows;
+  } else {
+    delete tunnelStats[tunnel];
+  }
+  renderDashboard();
 });
 
 listen('vtrunkd-exit', (event) => {
-  runStatusEl.textContent = 'Status: stopped';
-  runStatusEl.classList.remove('running');
-  appendLog(`vtrunkd exited: ${event.payload}`);
+  const exit = event.payload;
+  delete tunnelStats[exit.tunnel];
+  renderDashboard();
+  appendLog(`[${exit.tunnel}] vtrunkd ${exit.status}.`);
+  exit.stderr.forEach((line) => appendLog(`  ${line}`));
+  if (exit.restart_in_secs != null) {
+    appendLog(
+      `[${exit.tunnel}] Restarting in ${exit.restart_in_secs} s; stop the tunnel to cancel.`
+    );
+  }
+  refreshTunnels();
+});
+
+listen('vtrunkd-restarted', (event) => {
+  const { tunnel, attempt } = event.payload;
+  appendLog(`[${tunnel}] vtrunkd restarted (attempt ${attempt}).`);
+  refreshTunnels();
 });
 
 ['bonding-mode', 'server-host'].forEach((id) => {
@@ -282,19 +1207,94 @@ document
 document
   .getElementById('provision')
   .addEventListener('click', () => withLoading('provision', provisionVps));
+document.getElementById('new-keys').addEventListener('click', newKeys);
+document
+  .getElementById('save-profile')
+  .addEventListener('click', () => withLoading('save-profile', saveProfile));
+document
+  .getElementById('load-profile')
+  .addEventListener('click', () => withLoading('load-profile', loadProfile));
+document
+  .getElementById('delete-profile')
+  .addEventListener('click', () => withLoading('delete-profile', deleteProfile));
+document
+  .getElementById('import-config')
+  .addEventListener('click', () => withLoading('import-config', importConfig));
+document
+  .getElementById('save-config')
+  .addEventListener('click', () => withLoading('save-config', saveImportedConfig));
 document.getElementById('verify-host').addEventListener('click', verifyHost);
 document.getElementById('trust-host').addEventListener('click', trustHost);
+document
+  .getElementById('server-status')
+  .addEventListener('click', () => withLoading('server-status', serverStatus));
+followServerLogBtn.addEventListener('click', followServerLog);
+document
+  .getElementById('compare-server')
+  .addEventListener('click', () => withLoading('compare-server', compareServerConfig));
+updateServerBtn.addEventListener('click', () =>
+  withLoading('update-server', updateServer).then(() => {
+    updateServerBtn.disabled = true;
+  })
+);
+document
+  .getElementById('uninstall-server')
+  .addEventListener('click', () => withLoading('uninstall-server', uninstallServer));
+// A compared diff no longer shows what would be uploaded once the config is edited.
+serverConfigEl.addEventListener('input', () => {
+  updateServerBtn.disabled = true;
+});
+stopServerLogBtn.addEventListener('click', stopServerLog);
+document
+  .getElementById('import-bundle')
+  .addEventListener('click', () => withLoading('import-bundle', importBundle));
+document.getElementById('show-qr').addEventListener('click', showQr);
+document
+  .getElementById('export-bundle')
+  .addEventListener('click', () => withLoading('export-bundle', exportBundle));
+document.getElementById('log-level').addEventListener('change', refreshDaemonLog);
+document.getElementById('log-query').addEventListener('input', refreshDaemonLog);
+logTunnelEl.addEventListener('change', refreshDaemonLog);
+persistLogsBtn.addEventListener('click', persistLogs);
+document.getElementById('clear-logs').addEventListener('click', clearLogs);
+document
+  .getElementById('export-diagnostics')
+  .addEventListener('click', () => withLoading('export-diagnostics', exportDiagnostics));
+autostartEl.addEventListener('change', setAutostart);
+notifyEl.addEventListener('change', () =>
+  invoke('set_notifications', { tunnel: currentTunnel(), enabled: notifyEl.checked })
+);
+// A code of an older config would import the wrong keys.
+clientConfigEl.addEventListener('input', hideQr);
 document
   .getElementById('start')
   .addEventListener('click', () => withLoading('start', startTunnel));
 document
   .getElementById('stop')
-  .addEventListener('click', () => withLoading('stop', stopTunnel));
+  .addEventListener('click', () => withLoading('stop', () => stopTunnel()));
+document
+  .getElementById('test-connection')
+  .addEventListener('click', () => withLoading('test-connection', testConnection));
+document
+  .getElementById('speed-test')
+  .addEventListener('click', () => withLoading('speed-test', speedTest));
+suggestWeightsBtn.addEventListener('click', () => withLoading('suggest-weights', suggestWeights));
+document
+  .getElementById('service-install')
+  .addEventListener('click', () => withLoading('service-install', installLocalService));
+document
+  .getElementById('service-start')
+  .addEventListener('click', () => withLoading('service-start', () => localService('start')));
+document
+  .getElementById('service-stop')
+  .addEventListener('click', () => withLoading('service-stop', () => localService('stop')));
+document.getElementById('service-status').addEventListener('click', localServiceStatus);
 document.getElementById('add-link').addEventListener('click', () => {
   links.push({ name: 'link', bind: '', weight: 1 });
   renderLinks();
   refreshMetrics();
 });
+document.getElementById('fit-mtu').addEventListener('click', () => withLoading('fit-mtu', fitMtu));
 document
   .getElementById('detect-links')
   .addEventListener('click', () => withLoading('detect-links', autoDetect));
@@ -1,15 +1,31 @@
 use clap::{Parser, Subcommand};
+use std::io::Write;
+#[cfg(unix)]
 use std::os::fd::AsRawFd;
-use std::path::PathBuf;
-use tokio::signal;
-use tracing::{error, info};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
 
-mod config;
-mod error;
-mod network;
-mod wireguard;
+#[cfg(windows)]
+mod service;
 
-use crate::error::VtrunkdResult;
+use vtrunkd_core::{
+    activation, api, audit, capture, config, container, control, error, health, hooks, logging,
+    pidfile, policy, runtime, uapi, uci, wgquick, wireguard, Tunnel,
+};
+#[cfg(unix)]
+use vtrunkd_core::{handover, tunnel};
+
+use vtrunkd_core::control::{ControlCommand, LinkAdminState};
+use vtrunkd_core::error::VtrunkdResult;
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/vtrunkd.yaml";
 
 #[derive(Parser)]
 #[command(name = "vtrunkd")]
@@ -28,6 +44,35 @@ struct Cli {
     #[arg(short, long)]
     foreground: bool,
 
+    /// Log output format (overrides logging.format in the config)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    log_format: Option<logging::LogFormat>,
+
+    /// PID file to write and lock (default when daemonized: /run/vtrunkd[-<interface>].pid)
+    #[arg(long, value_name = "FILE")]
+    pidfile: Option<PathBuf>,
+
+    /// Log destination (overrides logging.output in the config)
+    #[arg(long, value_enum, value_name = "OUTPUT")]
+    log_output: Option<logging::LogOutput>,
+
+    /// Use an already-open TUN file descriptor instead of creating the device
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i32).range(0..))]
+    tun_fd: Option<i32>,
+
+    /// Take over the TUN device and link sockets from the running instance, which then exits
+    #[arg(long)]
+    upgrade: bool,
+
+    /// Start even if other users can read the configuration file and its private keys
+    #[arg(long)]
+    insecure_permissions: bool,
+
+    /// Run as a container entrypoint: in the foreground, logging to stdout, with the
+    /// configuration from VTRUNKD_CONFIG_B64 or the individual VTRUNKD_* variables
+    #[arg(long, conflicts_with = "config")]
+    container: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -35,87 +80,616 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Generate configuration file
+    #[command(subcommand_negates_reqs = true)]
     Config {
         /// Output file path
+        #[arg(short, long, value_name = "FILE", required = true)]
+        output: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Print the effective configuration (defaults filled in, keys redacted) as YAML
+    Showconf,
+    /// Change the administrative state of a link on the running daemon, or rebind its socket
+    Link {
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
+        #[arg(short, long, value_name = "FILE")]
+        socket: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: LinkAction,
+    },
+    /// Print per-link counters from the running daemon as JSON
+    Stats {
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
+        #[arg(short, long, value_name = "FILE")]
+        socket: Option<PathBuf>,
+    },
+    /// Stream link and tunnel events from the running daemon as JSON lines
+    Events {
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
+        #[arg(short, long, value_name = "FILE")]
+        socket: Option<PathBuf>,
+    },
+    /// Record pcap files of TUN and/or link traffic on the running daemon
+    Capture {
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
+        #[arg(short, long, value_name = "FILE")]
+        socket: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: CaptureAction,
+    },
+    /// Replace this side's WireGuard key pair on the running daemon and hand the new public
+    /// key to the peer (needs wireguard.state_file on both sides)
+    RotateKey {
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
+        #[arg(short, long, value_name = "FILE")]
+        socket: Option<PathBuf>,
+    },
+    /// Accept the new WireGuard public key the peer offered with rotate-key
+    AcceptKey {
+        /// The peer's new public key, in base64, as logged when it was offered
+        #[arg(value_name = "KEY")]
+        public_key: String,
+
+        /// Control socket path (defaults to the one in --config, then /run/vtrunkd.sock)
         #[arg(short, long, value_name = "FILE")]
-        output: PathBuf,
+        socket: Option<PathBuf>,
+    },
+    /// Measure encapsulation and per-mode bonding throughput over loopback, without a TUN
+    Bench {
+        /// Packets to send in each pass
+        #[arg(long, default_value_t = 100_000)]
+        packets: usize,
+
+        /// Size of each inner IP packet in bytes
+        #[arg(long, default_value_t = 1400)]
+        size: usize,
+
+        /// Loopback links to bond
+        #[arg(long, default_value_t = 2)]
+        links: usize,
+    },
+    /// Install, remove or run vtrunkd as a Windows service
+    #[cfg(windows)]
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
     },
 }
 
-#[tokio::main]
-async fn main() -> VtrunkdResult<()> {
+#[cfg(windows)]
+#[derive(Subcommand)]
+enum ServiceAction {
+    /// Register an auto-start service that runs with the current --config
+    Install,
+    /// Stop and remove the service
+    Uninstall,
+    /// Entry point used by the service control manager
+    Run,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Convert --config for standard WireGuard tools (first link only)
+    Export {
+        /// Write a wg-quick file ([Interface] and [Peer])
+        #[arg(long, required = true)]
+        wg_quick: bool,
+
+        /// Output file path, created mode 0600 (default: stdout)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CaptureAction {
+    /// Start a capture; it stops by itself at the time or size limit
+    Start {
+        /// tun (inner packets), links (outer UDP per link) or all
+        #[arg(default_value = "all")]
+        target: capture::CaptureTarget,
+
+        /// Stop after this many seconds
+        #[arg(long, default_value_t = capture::DEFAULT_CAPTURE_SECS)]
+        seconds: u64,
+
+        /// Stop after writing this many megabytes
+        #[arg(long, default_value_t = capture::DEFAULT_CAPTURE_MAX_MB)]
+        max_mb: u64,
+    },
+    /// Stop the running capture and flush its files
+    Stop,
+}
+
+#[derive(Subcommand)]
+enum LinkAction {
+    /// Return a link to service
+    Up { name: String },
+    /// Take a link out of service entirely
+    Down { name: String },
+    /// Stop sending data on a link but keep it probed
+    Drain { name: String },
+    /// Open a fresh socket for a link after its device or address came back
+    Rebind { name: String },
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let result =
+        runtime::build(&runtime_settings(&cli)).and_then(|runtime| runtime.block_on(run(cli)));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!(code = e.code(), "{}", e);
+            eprintln!("vtrunkd: {} (error {})", e, e.code());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    // Initialize tracing
-    let filter = if cli.debug {
-        "vtrunkd=debug"
-    } else {
-        "vtrunkd=info"
+/// The config's `runtime` section when running the daemon. Config errors are left for
+/// `run_daemon` to report once logging is up.
+fn runtime_settings(cli: &Cli) -> config::RuntimeConfig {
+    let runs_daemon = match &cli.command {
+        None => true,
+        #[cfg(windows)]
+        Some(Commands::Service {
+            action: ServiceAction::Run,
+        }) => true,
+        Some(_) => false,
     };
+    if !runs_daemon {
+        return config::RuntimeConfig::default();
+    }
+    load_config(cli)
+        .ok()
+        .and_then(|(config, _)| config.runtime)
+        .unwrap_or_default()
+}
 
-    use tracing_subscriber::EnvFilter;
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::new(filter))
-        .init();
+/// The daemon's configuration and the file it came from; `--container` reads it from the
+/// environment instead.
+fn load_config(cli: &Cli) -> VtrunkdResult<(config::Config, Option<PathBuf>)> {
+    if cli.container {
+        let config = container::config_from_env(|name| std::env::var(name).ok())?;
+        return Ok((config, None));
+    }
+    let path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+    Ok((config::load_config(&path)?, Some(path)))
+}
 
-    info!("Starting vtrunkd {}", env!("CARGO_PKG_VERSION"));
+async fn run(mut cli: Cli) -> VtrunkdResult<()> {
+    // Subcommands talk to a running daemon or write files; they never read the log format from
+    // the config.
+    let runs_daemon = match &cli.command {
+        None => true,
+        #[cfg(windows)]
+        Some(Commands::Service {
+            action: ServiceAction::Run,
+        }) => true,
+        Some(_) => false,
+    };
+    // The bench prints its own report; redundant mode's duplicate drops would flood the log.
+    let quiet = matches!(cli.command, Some(Commands::Bench { .. }));
+    if !runs_daemon && !quiet {
+        logging::init(
+            cli.log_format.unwrap_or_default(),
+            cli.debug,
+            logging::LogSink::Stdout,
+        )?;
+    }
 
-    match cli.command {
-        Some(Commands::Config { output }) => {
+    match cli.command.take() {
+        Some(Commands::Config {
+            action: Some(ConfigAction::Export { output, .. }),
+            ..
+        }) => {
+            let config_path = cli
+                .config
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+            let export = wgquick::export(&config::load_config(&config_path)?)?;
+            // Logs go to stdout, which may be the exported file.
+            for warning in &export.warnings {
+                eprintln!("vtrunkd: warning: {}", warning);
+            }
+            match output {
+                Some(output) => {
+                    // The file carries the private key.
+                    let mut options = std::fs::OpenOptions::new();
+                    options.write(true).create(true).truncate(true);
+                    #[cfg(unix)]
+                    options.mode(0o600);
+                    let mut file = options.open(&output)?;
+                    file.write_all(export.text.as_bytes())?;
+                    info!("Exported wg-quick configuration to {:?}", output);
+                }
+                None => print!("{}", export.text),
+            }
+            return Ok(());
+        }
+        Some(Commands::Config { output, .. }) => {
+            let output = output.expect("clap requires --output without a subcommand");
             config::generate_default_config(&output)?;
             info!("Generated default configuration at {:?}", output);
             return Ok(());
         }
+        Some(Commands::Showconf) => {
+            let mut config = load_config(&cli)?.0.effective();
+            config.redact_secrets();
+            print!("{}", serde_yaml::to_string(&config)?);
+            return Ok(());
+        }
+        Some(Commands::Link { socket, action }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            let (name, state) = match action {
+                LinkAction::Up { name } => (name, LinkAdminState::Up),
+                LinkAction::Down { name } => (name, LinkAdminState::Down),
+                LinkAction::Drain { name } => (name, LinkAdminState::Drain),
+                LinkAction::Rebind { name } => {
+                    let command = ControlCommand::RebindLink { name };
+                    println!("{}", control::send_command(&socket, &command).await?);
+                    return Ok(());
+                }
+            };
+            let reply =
+                control::send_command(&socket, &ControlCommand::SetLinkState { name, state })
+                    .await?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Commands::Stats { socket }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            let reply = control::send_command(&socket, &ControlCommand::Stats).await?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Commands::Events { socket }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            control::watch_events(&socket, |line| println!("{}", line)).await?;
+            return Ok(());
+        }
+        Some(Commands::Capture { socket, action }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            let command = match action {
+                CaptureAction::Start {
+                    target,
+                    seconds,
+                    max_mb,
+                } => ControlCommand::CaptureStart {
+                    target,
+                    seconds,
+                    max_mb,
+                },
+                CaptureAction::Stop => ControlCommand::CaptureStop,
+            };
+            let reply = control::send_command(&socket, &command).await?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Commands::RotateKey { socket }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            let reply = control::send_command(&socket, &ControlCommand::RotateKey).await?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Commands::AcceptKey { public_key, socket }) => {
+            let socket = resolve_control_socket(socket, cli.config.as_deref())?;
+            let reply =
+                control::send_command(&socket, &ControlCommand::AcceptKey { public_key }).await?;
+            println!("{}", reply);
+            return Ok(());
+        }
+        Some(Commands::Bench {
+            packets,
+            size,
+            links,
+        }) => {
+            let options = wireguard::bench::BenchOptions {
+                packets,
+                size,
+                links,
+            };
+            println!(
+                "{} packets of {} bytes per pass over {} loopback links",
+                packets, size, links
+            );
+            for result in wireguard::bench::run(&options).await? {
+                let send = if result.send_time.is_zero() {
+                    String::new()
+                } else {
+                    format!("  send {} ns/pkt", result.send_ns_per_packet())
+                };
+                println!(
+                    "{:<12} {:>9}/{} delivered  {:>10.0} pkt/s  {:>9.1} Mbit/s{}",
+                    result.name,
+                    result.delivered,
+                    result.sent,
+                    result.packets_per_sec(),
+                    result.mbit_per_sec(),
+                    send
+                );
+            }
+            return Ok(());
+        }
+        #[cfg(windows)]
+        Some(Commands::Service { action }) => {
+            let config_path = cli
+                .config
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+            return match action {
+                ServiceAction::Install => service::install(&config_path),
+                ServiceAction::Uninstall => service::uninstall(),
+                ServiceAction::Run => tokio::task::block_in_place(service::run),
+            };
+        }
         None => {}
     }
 
-    let config_path = cli
-        .config
-        .unwrap_or_else(|| PathBuf::from("/etc/vtrunkd.yaml"));
-    let config = config::load_config(&config_path)?;
+    run_daemon(cli, shutdown_signal()?).await
+}
+
+/// Runs the daemon until `shutdown` resolves; shared by the CLI and the Windows service.
+async fn run_daemon<S>(cli: Cli, shutdown: S) -> VtrunkdResult<()>
+where
+    S: std::future::Future<Output = std::io::Result<()>> + Send,
+{
+    let (mut config, config_path) = load_config(&cli)?;
+    if let Some(fd) = cli.tun_fd {
+        config.network.tun_fd = Some(fd);
+    }
+    let foreground = cli.foreground || cli.container;
+
+    // Initialize tracing
+    let log_output = cli
+        .log_output
+        .or(cli.container.then_some(logging::LogOutput::Stdout));
+    let log_sink = logging::LogSink::from_config(log_output, config.logging.as_ref())?;
+    logging::init(
+        cli.log_format.or(config.log_format()).unwrap_or_default(),
+        cli.debug,
+        log_sink,
+    )?;
+    info!("Starting vtrunkd {}", env!("CARGO_PKG_VERSION"));
+    audit::init(
+        config
+            .logging
+            .as_ref()
+            .and_then(|logging| logging.audit.as_ref()),
+    )?;
+    audit::config_loaded(config_path.as_deref().unwrap_or(Path::new("environment")));
+    let permissions = match &config_path {
+        Some(path) if config.wireguard.has_inline_keys() => {
+            config::check_key_file_permissions(path)
+        }
+        _ => Ok(()),
+    };
+    if let Err(e) = permissions {
+        if !cli.insecure_permissions {
+            return Err(e);
+        }
+        warn!("{}; starting anyway (--insecure-permissions)", e);
+    }
+    if let Some(runtime) = &config.runtime {
+        if let Some(cpus) = &runtime.pin_cpus {
+            if cfg!(target_os = "linux") {
+                info!("Pinned to CPUs {:?}", cpus);
+            } else {
+                warn!("runtime.pin_cpus is only supported on Linux; ignoring");
+            }
+        }
+    }
+    activation::init(&config)?;
+
+    let control_path = control::socket_path(config.control_socket());
+    if cli.upgrade {
+        take_over(&control_path, &mut config).await?;
+    }
 
-    if !cli.foreground {
+    // Lock before forking so a duplicate instance fails on the terminal, not in /dev/null.
+    let pid_path = match cli.pidfile {
+        Some(path) => Some(path),
+        None if !foreground => Some(pidfile::default_path(&config)),
+        None => None,
+    };
+    let mut pid_file = match &pid_path {
+        Some(path) => Some(pidfile::PidFile::acquire(path)?),
+        None => None,
+    };
+
+    if !foreground {
         daemonize()?;
+        logging::reopen()?;
+        if let Some(pid_file) = &mut pid_file {
+            pid_file.write_pid()?;
+        }
     }
 
-    if let Err(e) = run_until_shutdown(wireguard::run(config), signal::ctrl_c()).await {
-        error!("WireGuard error: {}", e);
-        return Err(e);
+    if cli.container {
+        container::check_tun_access(&config.network)?;
     }
 
+    let mut tunnel = Tunnel::builder().config(config).build()?;
+    hooks::spawn(tunnel.config(), tunnel.events());
+    audit::spawn(tunnel.events());
+    policy::spawn(tunnel.config(), tunnel.control_sender(), tunnel.events());
+    let write_access = control::WriteAccess::from_config(tunnel.config().control.as_ref())?;
+    match control::spawn_server(
+        &control_path,
+        tunnel.control_sender(),
+        tunnel.event_sender(),
+        write_access,
+    ) {
+        Ok(()) => {}
+        Err(e @ error::VtrunkdError::AlreadyRunning(_)) => return Err(e),
+        Err(e) => warn!("Control socket unavailable at {:?}: {}", control_path, e),
+    }
+    let uapi_path = tunnel.config().uapi_enabled().then(|| {
+        let network = &tunnel.config().network;
+        uapi::socket_path(
+            network
+                .interface
+                .as_deref()
+                .unwrap_or(config::DEFAULT_INTERFACE),
+        )
+    });
+    if let Some(path) = &uapi_path {
+        if let Err(e) = uapi::spawn_server(path, tunnel.control_sender()) {
+            warn!("WireGuard UAPI socket unavailable at {:?}: {}", path, e);
+        }
+    }
+
+    if let Some(http) = tunnel.config().http_api() {
+        // `PUT /config` writes YAML, which must not replace a UCI file.
+        let config_path = config_path
+            .filter(|path| std::fs::read_to_string(path).is_ok_and(|text| !uci::is_uci(&text)));
+        if let Err(e) = api::spawn_server(http, tunnel.control_sender(), config_path) {
+            warn!("HTTP API unavailable on {}: {}", http.bind, e);
+        }
+    }
+    if let Some(bind) = tunnel.config().health_bind() {
+        if let Err(e) = health::spawn_server(bind, tunnel.control_sender()) {
+            warn!("Health checks unavailable on {}: {}", bind, e);
+        }
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt) = &tunnel.config().mqtt {
+        vtrunkd_core::mqtt::spawn(
+            mqtt,
+            tunnel.stats_stream(vtrunkd_core::mqtt::interval(mqtt)),
+            tunnel.events(),
+        );
+    }
+
+    // Notified by the D-Bus `Stop` method.
+    let stop = std::sync::Arc::new(tokio::sync::Notify::new());
+    #[cfg(feature = "dbus")]
+    if tunnel.config().dbus_enabled() {
+        if let Err(e) = vtrunkd_core::dbus::spawn_server(
+            tunnel.control_sender(),
+            tunnel.event_sender(),
+            stop.clone(),
+        )
+        .await
+        {
+            warn!("D-Bus service unavailable: {}", e);
+        }
+    }
+
+    spawn_reload_listener()?;
+    #[cfg(unix)]
+    let handed_over = handover::handed_over(tunnel.events());
+    #[cfg(not(unix))]
+    let handed_over = std::future::pending::<()>();
+    let shutdown = async move {
+        tokio::select! {
+            result = shutdown => result,
+            _ = handed_over => Ok(()),
+            _ = stop.notified() => Ok(()),
+        }
+    };
+    let result = tunnel.run_until(shutdown).await;
+    // Release the PID file and the UAPI socket before the control socket: an upgraded
+    // process waits for the control socket to disappear, then takes the lock and rebinds.
+    drop(pid_file);
+    if let Some(path) = &uapi_path {
+        let _ = std::fs::remove_file(path);
+    }
+    let _ = std::fs::remove_file(&control_path);
+    result?;
+
     info!("vtrunkd shutdown complete");
     Ok(())
 }
 
-async fn run_until_shutdown<R, S>(run_fut: R, shutdown: S) -> VtrunkdResult<()>
-where
-    R: std::future::Future<Output = VtrunkdResult<()>> + Send + 'static,
-    S: std::future::Future<Output = std::io::Result<()>> + Send,
-{
-    let mut run_handle = tokio::spawn(run_fut);
-    tokio::select! {
-        result = &mut run_handle => {
-            match result {
-                Ok(Ok(())) => Err(error::VtrunkdError::Network(
-                    "WireGuard task exited unexpectedly".to_string(),
-                )),
-                Ok(Err(e)) => Err(e),
-                Err(e) => Err(error::VtrunkdError::Network(format!(
-                    "WireGuard task join error: {}",
-                    e
-                ))),
-            }
+/// `--upgrade`: takes the TUN device and link sockets over from the running daemon, then
+/// waits for it to let go of the control socket.
+#[cfg(unix)]
+async fn take_over(control_path: &Path, config: &mut config::Config) -> VtrunkdResult<()> {
+    let inherited = handover::receive(control_path).await?;
+    // The device stays as the old daemon opened it: its queues, owner and persistence.
+    let network = &mut config.network;
+    network.tun_fd = inherited.tun_fds.first().copied();
+    network.tun_queues = None;
+    network.interface_mode = None;
+    network.tun_owner = None;
+    network.tun_group = None;
+    network.persistent = None;
+    activation::adopt_tun_queues(inherited.tun_fds);
+    activation::adopt(inherited.links);
+    handover::wait_for_exit(
+        control_path,
+        tunnel::SHUTDOWN_GRACE + Duration::from_secs(1),
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn take_over(_control_path: &Path, _config: &mut config::Config) -> VtrunkdResult<()> {
+    Err(error::VtrunkdError::Control(
+        "--upgrade passes descriptors over a Unix domain socket, which this platform lacks"
+            .to_string(),
+    ))
+}
+
+fn resolve_control_socket(
+    socket: Option<PathBuf>,
+    config: Option<&Path>,
+) -> VtrunkdResult<PathBuf> {
+    match (socket, config) {
+        (Some(socket), _) => Ok(socket),
+        (None, Some(path)) => Ok(control::socket_path(
+            config::load_config(path)?.control_socket(),
+        )),
+        (None, None) => Ok(control::socket_path(None)),
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM (how systemd and most init systems stop the daemon).
+#[cfg(unix)]
+fn shutdown_signal() -> std::io::Result<impl std::future::Future<Output = std::io::Result<()>>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = sigterm.recv() => Ok(()),
         }
-        shutdown_result = shutdown => {
-            shutdown_result?;
-            info!("Received shutdown signal");
-            run_handle.abort();
-            let _ = run_handle.await;
-            Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn shutdown_signal() -> std::io::Result<impl std::future::Future<Output = std::io::Result<()>>> {
+    Ok(tokio::signal::ctrl_c())
+}
+
+/// SIGHUP is reserved for configuration reload; until then it must not kill the daemon.
+#[cfg(unix)]
+fn spawn_reload_listener() -> std::io::Result<()> {
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            audit::reload_requested();
+            warn!("Received SIGHUP; configuration reload is not supported yet, keeping the current configuration");
         }
-    }
+    });
+    Ok(())
+}
+
+/// There is no SIGHUP to listen for.
+#[cfg(not(unix))]
+fn spawn_reload_listener() -> std::io::Result<()> {
+    Ok(())
 }
 
+#[cfg(unix)]
 fn daemonize() -> VtrunkdResult<()> {
     use nix::unistd::{chdir, close, fork, setsid, ForkResult};
     use std::fs::File;
@@ -149,31 +723,12 @@ fn daemonize() -> VtrunkdResult<()> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    async fn run_until_shutdown_errors_on_run_failure() {
-        let run_fut = async { Err(error::VtrunkdError::Network("boom".to_string())) };
-        let shutdown = std::future::pending::<std::io::Result<()>>();
-        let result = run_until_shutdown(run_fut, shutdown).await;
-        assert!(matches!(result, Err(error::VtrunkdError::Network(_))));
-    }
-
-    #[tokio::test]
-    async fn run_until_shutdown_errors_on_unexpected_exit() {
-        let run_fut = async { Ok(()) };
-        let shutdown = std::future::pending::<std::io::Result<()>>();
-        let result = run_until_shutdown(run_fut, shutdown).await;
-        assert!(matches!(result, Err(error::VtrunkdError::Network(_))));
-    }
-
-    #[tokio::test]
-    async fn run_until_shutdown_returns_ok_on_shutdown() {
-        let run_fut = std::future::pending::<VtrunkdResult<()>>();
-        let shutdown = async { Ok(()) };
-        let result = run_until_shutdown(run_fut, shutdown).await;
-        assert!(result.is_ok());
-    }
+/// Without fork there is nothing to detach from; the service manager runs the daemon instead.
+#[cfg(not(unix))]
+fn daemonize() -> VtrunkdResult<()> {
+    Err(error::VtrunkdError::InvalidConfig(
+        "Running in the background needs fork; use --foreground, or install vtrunkd as a \
+         service"
+            .to_string(),
+    ))
 }
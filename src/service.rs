@@ -0,0 +1,144 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::error;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use vtrunkd_core::error::{VtrunkdError, VtrunkdResult};
+
+use crate::Cli;
+
+const SERVICE_NAME: &str = "vtrunkd";
+const SERVICE_DISPLAY_NAME: &str = "vtrunkd bonding VPN";
+const SERVICE_DESCRIPTION: &str = "Bonds multiple network links into one WireGuard tunnel";
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_error(err: windows_service::Error) -> VtrunkdError {
+    VtrunkdError::SystemCall(format!("Windows service error: {}", err))
+}
+
+/// Registers an auto-start service running as LocalSystem with `config`.
+pub fn install(config: &Path) -> VtrunkdResult<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(service_error)?;
+    let config = std::path::absolute(config)?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            config.into_os_string(),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(service_error)?;
+    service
+        .set_description(SERVICE_DESCRIPTION)
+        .map_err(service_error)?;
+    println!("Installed service {}", SERVICE_NAME);
+    Ok(())
+}
+
+pub fn uninstall() -> VtrunkdResult<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(service_error)?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(service_error)?;
+    if service.query_status().map_err(service_error)?.current_state != ServiceState::Stopped {
+        let _ = service.stop();
+    }
+    service.delete().map_err(service_error)?;
+    println!("Removed service {}", SERVICE_NAME);
+    Ok(())
+}
+
+/// Hands the process to the service control manager; returns once the service stopped.
+pub fn run() -> VtrunkdResult<()> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(service_error)
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Service failed: {}", e);
+    }
+}
+
+fn status(state: ServiceState, exit_code: ServiceExitCode) -> ServiceStatus {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+/// Runs the daemon with service stop/shutdown requests wired to the graceful shutdown path.
+fn run_service() -> VtrunkdResult<()> {
+    let (stop_tx, mut stop_rx) = tokio::sync::mpsc::unbounded_channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop_tx.send(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, handler).map_err(service_error)?;
+    status_handle
+        .set_service_status(status(ServiceState::Running, ServiceExitCode::NO_ERROR))
+        .map_err(service_error)?;
+
+    // The SCM passes the launch arguments recorded by `install` on the command line.
+    let mut cli = Cli::parse();
+    cli.command = None;
+    cli.foreground = true;
+    let shutdown = async move {
+        stop_rx.recv().await;
+        Ok(())
+    };
+    let result = vtrunkd_core::runtime::build(&crate::runtime_settings(&cli))
+        .and_then(|runtime| runtime.block_on(crate::run_daemon(cli, shutdown)));
+
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(e) => ServiceExitCode::ServiceSpecific(u32::from(e.code())),
+    };
+    status_handle
+        .set_service_status(status(ServiceState::Stopped, exit_code))
+        .map_err(service_error)?;
+    result
+}